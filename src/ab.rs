@@ -0,0 +1,158 @@
+use crate::dsp::Processor;
+use crate::params::{Param, ParamInfo, ParamStore};
+use std::sync::Arc;
+
+/// Longest block worked on at once, longer callbacks are split to fit the
+/// second chain's buffer.
+const MAX_FRAMES: usize = 4096;
+/// Largest latency difference lined up between the chains.
+const MAX_ALIGN_SECONDS: f32 = 1.0;
+
+/// Output of one chain held back by the latency the other has on top, so
+/// both line up and the switch doesn't jump in time.
+#[derive(Default)]
+struct AlignLine {
+    samples: Vec<f32>,
+    write: usize,
+}
+
+impl AlignLine {
+    fn prepare(&mut self, frames: usize, channels: usize) {
+        let len = frames * channels;
+        if self.samples.len() != len {
+            self.samples = vec![0.0; len];
+        }
+        self.write = 0;
+    }
+
+    /// Pushes `sample` and returns the one `delay` samples before it.
+    fn process(&mut self, sample: f32, delay: usize) -> f32 {
+        let len = self.samples.len();
+        if delay == 0 || delay >= len {
+            return sample;
+        }
+        self.samples[self.write] = sample;
+        let out = self.samples[(self.write + len - delay) % len];
+        self.write = (self.write + 1) % len;
+        out
+    }
+
+    fn reset(&mut self) {
+        self.samples.fill(0.0);
+    }
+}
+
+/// Two complete DSP chains run side by side on the same input, for
+/// comparing settings on live material: `ab.select` picks the one heard,
+/// 0 for A and 1 for B, crossfading over `ab.fade` ms. Both run all the
+/// time, so the one switched to is already settled with its reverb tails
+/// and envelopes where they'd be, at the CPU cost of both. The chain with
+/// less latency is delayed to match the other's, up to a second.
+pub struct AbChain {
+    a: Box<dyn Processor>,
+    b: Box<dyn Processor>,
+    sample_rate: f32,
+    select: Arc<Param>,
+    fade_ms: Arc<Param>,
+    /// Share of B in the output, moving toward the selection.
+    mix: f32,
+    /// B's copy of the input.
+    scratch: Vec<f32>,
+    align_a: AlignLine,
+    align_b: AlignLine,
+    channels: usize,
+}
+
+impl AbChain {
+    /// Registers `ab.select` and `ab.fade` in `store`. A is heard first.
+    pub fn new(
+        a: Box<dyn Processor>,
+        b: Box<dyn Processor>,
+        sample_rate: f32,
+        store: &ParamStore,
+    ) -> Self {
+        let param = |name: &'static str, value: f32, min: f32, max: f32| {
+            let info = ParamInfo::new(name, value, min, max);
+            let param = Arc::new(Param::new(format!("ab.{}", name), &info));
+            store.add(param.clone());
+            param
+        };
+        AbChain {
+            a,
+            b,
+            sample_rate,
+            select: param("select", 0.0, 0.0, 1.0),
+            fade_ms: param("fade", 20.0, 1.0, 500.0),
+            mix: 0.0,
+            scratch: Vec::new(),
+            align_a: AlignLine::default(),
+            align_b: AlignLine::default(),
+            channels: 0,
+        }
+    }
+}
+
+impl Processor for AbChain {
+    fn name(&self) -> &'static str {
+        "ab"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.a.prepare(channels);
+        self.b.prepare(channels);
+        if self.channels != channels {
+            self.scratch = vec![0.0; MAX_FRAMES * channels];
+            let frames = (MAX_ALIGN_SECONDS * self.sample_rate) as usize;
+            self.align_a.prepare(frames, channels);
+            self.align_b.prepare(frames, channels);
+            self.channels = channels;
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if self.channels != channels {
+            // Not prepared for these channels, A alone
+            self.a.process(buffer, channels);
+            return;
+        }
+
+        let target = if self.select.get() >= 0.5 { 1.0 } else { 0.0 };
+        let step = 1000.0 / (self.fade_ms.get().max(1.0) * self.sample_rate);
+        for block in buffer.chunks_mut(MAX_FRAMES * channels) {
+            let other = &mut self.scratch[..block.len()];
+            other.copy_from_slice(block);
+            self.a.process(block, channels);
+            self.b.process(other, channels);
+
+            // Re-read every block, a reload can change either
+            let (latency_a, latency_b) = (self.a.latency(), self.b.latency());
+            let delay_a = latency_b.saturating_sub(latency_a) * channels;
+            let delay_b = latency_a.saturating_sub(latency_b) * channels;
+            for (frame, other) in block.chunks_mut(channels).zip(other.chunks(channels)) {
+                if self.mix != target {
+                    self.mix = if target > self.mix {
+                        (self.mix + step).min(target)
+                    } else {
+                        (self.mix - step).max(target)
+                    };
+                }
+                for (sample, &b) in frame.iter_mut().zip(other) {
+                    let a = self.align_a.process(*sample, delay_a);
+                    let b = self.align_b.process(b, delay_b);
+                    *sample = a + (b - a) * self.mix;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.a.reset();
+        self.b.reset();
+        self.align_a.reset();
+        self.align_b.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.a.latency().max(self.b.latency())
+    }
+}
@@ -0,0 +1,167 @@
+use crate::dsp::{db_to_gain, time_coefficient};
+use crate::params::{Param, ParamInfo, ParamStore};
+use anyhow::{Context, Result, bail};
+use std::sync::Arc;
+
+/// Smoothing of the auto-mix levels, fast enough to follow a talker
+/// turning to another mic without pumping between syllables.
+const AUTOMIX_RESPONSE_MS: f32 = 50.0;
+/// Power below which a mic counts as silent, -90 dBFS.
+const POWER_FLOOR: f32 = 1e-9;
+
+/// Parses a comma separated list of mixer channels, counted from 1 on the
+/// command line and from 0 in the result.
+pub fn parse_channels(text: &str) -> Result<Vec<usize>> {
+    let mut channels = Vec::new();
+    for part in text.split(',') {
+        let number: usize = part
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid channel '{}'", part.trim()))?;
+        if number == 0 {
+            bail!("Mixer channels start at 1");
+        }
+        if channels.contains(&(number - 1)) {
+            bail!("Channel {} is listed twice", number);
+        }
+        channels.push(number - 1);
+    }
+    Ok(channels)
+}
+
+/// Key channel and ducked channels of `--duck`, as `KEY:CH,CH`.
+pub fn parse_duck(text: &str) -> Result<(usize, Vec<usize>)> {
+    let (key, ducked) = text
+        .split_once(':')
+        .with_context(|| format!("Expected KEY:CHANNELS, e.g. 1:3,4, got '{}'", text))?;
+    let key = parse_channels(key)?;
+    let ducked = parse_channels(ducked)?;
+    if key.len() != 1 {
+        bail!("--duck takes one key channel");
+    }
+    if ducked.contains(&key[0]) {
+        bail!("Channel {} can't duck itself", key[0] + 1);
+    }
+    Ok((key[0], ducked))
+}
+
+fn check_channels(list: &[usize], count: usize) -> Result<()> {
+    if let Some(&channel) = list.iter().find(|&&channel| channel >= count) {
+        bail!(
+            "Channel {} isn't a mixer channel, there are {}",
+            channel + 1,
+            count
+        );
+    }
+    Ok(())
+}
+
+/// Turns channels down while a key channel is talking: music under a
+/// voice. Works on block levels, the mixer ramps the gain over each block.
+/// Settings are the `duck.` parameters.
+pub struct Ducker {
+    key: usize,
+    ducked: Vec<usize>,
+    threshold_db: Arc<Param>,
+    amount_db: Arc<Param>,
+    attack_ms: Arc<Param>,
+    release_ms: Arc<Param>,
+    bypass: Arc<Param>,
+    gain: f32,
+}
+
+impl Ducker {
+    /// `key` and `ducked` count from 0 and have to be below `channels`.
+    pub fn new(
+        key: usize,
+        ducked: Vec<usize>,
+        channels: usize,
+        params: &ParamStore,
+    ) -> Result<Self> {
+        check_channels(&[key], channels)?;
+        check_channels(&ducked, channels)?;
+        let param = |name, value, min, max| {
+            params.bind(
+                format!("duck.{}", name),
+                &ParamInfo::new(name, value, min, max),
+            )
+        };
+        Ok(Ducker {
+            key,
+            ducked,
+            threshold_db: param("threshold", -40.0, -80.0, 0.0),
+            amount_db: param("amount", -12.0, -60.0, 0.0),
+            attack_ms: param("attack", 20.0, 1.0, 1000.0),
+            release_ms: param("release", 500.0, 10.0, 5000.0),
+            bypass: param("bypass", 0.0, 0.0, 1.0),
+            gain: 1.0,
+        })
+    }
+
+    /// Follows the key's mean square `powers` of the last block and
+    /// applies the ducking to `gains`. `blocks` is blocks per second.
+    pub fn process(&mut self, powers: &[f32], gains: &mut [f32], blocks: f32) {
+        let level_db = 10.0 * powers[self.key].max(POWER_FLOOR).log10();
+        let target = if self.bypass.get() >= 0.5 || level_db < self.threshold_db.get() {
+            1.0
+        } else {
+            db_to_gain(self.amount_db.get())
+        };
+        let time = if target < self.gain {
+            self.attack_ms.get()
+        } else {
+            self.release_ms.get()
+        };
+        let coefficient = time_coefficient(time, blocks);
+        self.gain = target + (self.gain - target) * coefficient;
+        for &channel in &self.ducked {
+            gains[channel] *= self.gain;
+        }
+    }
+}
+
+/// Gain sharing across the mics of a panel, after Dugan: each mic gets its
+/// share of the total level, so the one being talked into stays up, the
+/// others come down, and the sum stays as loud as one open mic however
+/// many there are. Switched off with `automix.bypass`.
+pub struct AutoMix {
+    channels: Vec<usize>,
+    bypass: Arc<Param>,
+    /// Smoothed mean square of every mic, in `channels` order.
+    power: Vec<f32>,
+}
+
+impl AutoMix {
+    /// `channels` count from 0 and have to be below `count`.
+    pub fn new(channels: Vec<usize>, count: usize, params: &ParamStore) -> Result<Self> {
+        if channels.len() < 2 {
+            bail!("Auto-mix needs at least two channels");
+        }
+        check_channels(&channels, count)?;
+        Ok(AutoMix {
+            bypass: params.bind(
+                "automix.bypass".to_string(),
+                &ParamInfo::new("bypass", 0.0, 0.0, 1.0),
+            ),
+            power: vec![POWER_FLOOR; channels.len()],
+            channels,
+        })
+    }
+
+    /// Same as `Ducker::process`.
+    pub fn process(&mut self, powers: &[f32], gains: &mut [f32], blocks: f32) {
+        let coefficient = time_coefficient(AUTOMIX_RESPONSE_MS, blocks);
+        for (smoothed, &channel) in self.power.iter_mut().zip(&self.channels) {
+            let power = powers[channel].max(POWER_FLOOR);
+            *smoothed = power + (*smoothed - power) * coefficient;
+        }
+        if self.bypass.get() >= 0.5 {
+            return;
+        }
+        let total: f32 = self.power.iter().sum();
+        for (smoothed, &channel) in self.power.iter().zip(&self.channels) {
+            // Shares of the power, so the gains are its square root
+            gains[channel] *= (smoothed / total).sqrt();
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::dither::DitherMode;
+use crate::dsp::{DspChain, Limiter, Processor};
+use crate::fade::FadeControl;
+use crate::jitter::JitterStats;
+use crate::meter::{Meter, MeterLevels};
+use crate::mixer::{MasterBus, MasterControls};
+use crate::realtime::Realtime;
+use crate::rtlog::{Event, Logger, RtLogger};
+use crate::sample_convert;
+use crate::source::{self, Source, SourceSettings, SourceTap, StreamFeed};
+use crate::xrun::XrunStats;
+use anyhow::{Result, bail};
+use cpal::traits::DeviceTrait;
+use cpal::{Device, Stream, StreamConfig};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Where the broadcast bus plays.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BroadcastTarget {
+    /// An output device, found by part of its name.
+    Device(String),
+    /// The virtual microphone `--virtual-mic` creates, for OBS, Discord or
+    /// a browser to pick up.
+    VirtualMic,
+}
+
+impl BroadcastTarget {
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim() {
+            "" => bail!("--broadcast needs an output device name or mic"),
+            "mic" => Ok(BroadcastTarget::VirtualMic),
+            name => Ok(BroadcastTarget::Device(name.to_string())),
+        }
+    }
+}
+
+/// Console handles of the broadcast bus.
+pub struct BroadcastControls {
+    pub master: Arc<MasterControls>,
+    /// Levels after its limiter.
+    pub meter: Arc<MeterLevels>,
+}
+
+/// What the audience hears, next to what the performer monitors: the
+/// mixer's broadcast mix through a DSP chain, fader and limiter of its own,
+/// so the monitor can keep a reverb or a click the stream goes without.
+/// Runs in the output callback and hands its buffer to the broadcast
+/// device's stream through a ring.
+pub struct BroadcastBus {
+    chain: DspChain,
+    master: MasterBus,
+    limiter: Limiter,
+    meter: Meter,
+    feed: StreamFeed,
+}
+
+impl BroadcastBus {
+    /// `chain` should already be prepared for `channels`, `limiter` is
+    /// prepared here.
+    pub fn new(
+        chain: DspChain,
+        mut limiter: Limiter,
+        feed: StreamFeed,
+        sample_rate: f32,
+        channels: usize,
+    ) -> (Self, BroadcastControls) {
+        let (master, master_controls) = MasterBus::new();
+        limiter.prepare(channels);
+        let (meter, levels) = Meter::new(sample_rate, channels);
+        let bus = BroadcastBus {
+            chain,
+            master,
+            limiter,
+            meter,
+            feed,
+        };
+        let controls = BroadcastControls {
+            master: master_controls,
+            meter: levels,
+        };
+        (bus, controls)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.chain.names()
+    }
+
+    pub fn process(&mut self, data: &mut [f32], channels: usize) {
+        self.chain.process(data, channels);
+        self.master.process(data, channels);
+        self.limiter.process(data, channels);
+        self.meter.process(data, channels);
+        self.feed.push(data);
+    }
+}
+
+/// Device end of the broadcast bus. The device runs on a clock of its own,
+/// so the bus is read back out through a jitter buffer and resampled like
+/// an input. It outlives the stream, a rebuilt one carries on with it.
+pub struct BroadcastOutput {
+    config: StreamConfig,
+    dither: DitherMode,
+    tap: Arc<Mutex<SourceTap>>,
+    /// Callbacks of the device stream, watched for stalls.
+    xruns: Arc<XrunStats>,
+    log: Arc<Mutex<RtLogger>>,
+    realtime: Arc<Realtime>,
+}
+
+impl BroadcastOutput {
+    /// Builds a paused stream on `device`. A replacement device has to run
+    /// at the rate and channel count the first one had.
+    pub fn start(&self, device: &Device) -> Result<Stream> {
+        let format = device.default_output_config()?.sample_format();
+        let mut config = self.config.clone();
+        let channels = config.channels as usize;
+        let (tap, xruns) = (self.tap.clone(), self.xruns.clone());
+        let (err_xruns, log) = (self.xruns.clone(), self.log.clone());
+        let mut promoter = self
+            .realtime
+            .promoter("broadcast", config.sample_rate, device);
+        sample_convert::build_output_stream(
+            device,
+            &mut config,
+            format,
+            self.dither,
+            move |data: &mut [f32]| {
+                promoter.promote(data.len() / channels);
+                xruns.record_callback((data.len() / channels) as u64);
+                match tap.try_lock() {
+                    Ok(mut tap) => tap.read_into(data, channels),
+                    Err(_) => data.fill(0.0),
+                }
+            },
+            move |err| {
+                err_xruns.record_error(&err);
+                if let Ok(mut log) = log.try_lock() {
+                    log.log(Event::StreamError(err));
+                }
+            },
+        )
+    }
+
+    pub fn xruns(&self) -> Arc<XrunStats> {
+        self.xruns.clone()
+    }
+
+    /// Over- and underruns of the ring between the bus and the device.
+    pub fn ring_xruns(&self) -> Arc<XrunStats> {
+        self.tap.lock().unwrap().xruns()
+    }
+
+    pub fn jitter(&self) -> Arc<JitterStats> {
+        self.tap.lock().unwrap().jitter()
+    }
+
+    pub fn fade(&self) -> Arc<FadeControl> {
+        self.tap.lock().unwrap().fade()
+    }
+}
+
+/// Sets up the ring from a bus of `settings.output_rate` and
+/// `settings.output_channels` to `device`, at the device's own rate and
+/// channel count. Start the stream with `BroadcastOutput::start`, its
+/// errors go to `logger` and its thread is promoted through `realtime`.
+pub fn open(
+    device: &Device,
+    settings: &SourceSettings,
+    dither: DitherMode,
+    logger: &Logger,
+    realtime: &Arc<Realtime>,
+) -> Result<(StreamFeed, BroadcastOutput)> {
+    let name = device.description()?.to_string();
+    let default_config = device.default_output_config()?;
+    let rate = default_config.sample_rate();
+    let requested =
+        sample_convert::fixed_buffer_size(default_config.buffer_size(), settings.buffer_size);
+    let mut config: StreamConfig = default_config.into();
+    config.buffer_size = requested;
+    info!(
+        "Broadcast output {}: {} Hz, {} channels, buffer size {:?}",
+        name, config.sample_rate, config.channels, config.buffer_size
+    );
+
+    let device_settings = SourceSettings {
+        output_rate: rate,
+        output_channels: config.channels as usize,
+        target_latency: (settings.target_latency as u64 * rate as u64 / settings.output_rate as u64)
+            .max(1) as u32,
+        map: None,
+        ..*settings
+    };
+    let (feed, tap) = source::open_stream(
+        "broadcast bus".to_string(),
+        settings.output_rate,
+        settings.output_channels,
+        &device_settings,
+    )?;
+    let output = BroadcastOutput {
+        config,
+        dither,
+        tap: Arc::new(Mutex::new(tap)),
+        xruns: Arc::new(XrunStats::new("broadcast".to_string(), rate)),
+        log: Arc::new(Mutex::new(logger.channel("broadcast"))),
+        realtime: realtime.clone(),
+    };
+    Ok((feed, output))
+}
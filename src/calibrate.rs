@@ -0,0 +1,476 @@
+use crate::dither::DitherMode;
+use crate::dsp::fft::{Complex, Fft};
+use crate::dsp::graphic_eq::BANDS;
+use crate::dsp::{FirFilter, Processor};
+use crate::generator::{SignalGenerator, Waveform};
+use crate::params::ParamStore;
+use crate::realtime::{self, Realtime};
+use crate::resample::Quality;
+use crate::rtlog::{Event, Logger};
+use crate::sample_convert;
+use crate::source::{self, Source, SourceSettings};
+use anyhow::{Context, Result, bail};
+use cpal::Device;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use ringbuf::HeapRb;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use std::f32::consts::{LN_10, PI};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Let through before each part is recorded, for the streams to start and
+/// the room to fill.
+const SETTLE_SECONDS: f32 = 0.5;
+/// Silence recorded first, for the noise floor.
+const FLOOR_SECONDS: f32 = 2.0;
+const NOISE_SECONDS: f32 = 8.0;
+/// Welch FFT, 2.9 Hz bins at 48 kHz, fine enough to split the 20 Hz band.
+const FFT_SIZE: usize = 16384;
+/// Bands past these are left flat, few speakers or measurement mics are
+/// worth correcting beyond them.
+const LOW_HZ: f32 = 40.0;
+const HIGH_HZ: f32 = 16000.0;
+/// Bands whose average the response is levelled to.
+const REFERENCE_HZ: (f32, f32) = (200.0, 4000.0);
+const MAX_CUT_DB: f32 = 12.0;
+/// Boosts are held lower, filling a room null only burns headroom.
+const MAX_BOOST_DB: f32 = 6.0;
+/// Bands less this far over the noise floor are left flat.
+const MIN_SNR_DB: f32 = 10.0;
+/// Filter length, long enough to resolve the third-octave bands at 40 Hz.
+const FILTER_SECONDS: f32 = 0.2;
+const DEFAULT_FILE: &str = "room_correction.txt";
+
+/// One third-octave band of a measurement.
+struct Band {
+    centre: f32,
+    name: &'static str,
+    /// Against the average over `REFERENCE_HZ`, `None` when the band is out
+    /// of range or too close to the noise floor.
+    level_db: Option<f32>,
+    correction_db: f32,
+}
+
+/// Plays pink noise on the output, records it on the first selected input
+/// and works out a correction for the output bus from the third-octave
+/// response: cuts of up to 12 dB on the peaks and boosts of up to 6 dB in
+/// the dips, from 40 Hz to 16 kHz. The correction can be previewed, played
+/// through and measured again, before it is written as a minimum-phase
+/// filter for `--fir`, adding no latency of its own. Needs a mic at the
+/// listening position, ideally a flat measurement one.
+pub fn calibrate(
+    input_device: &Device,
+    output_device: &Device,
+    buffer_size: u32,
+    quality: Quality,
+) -> Result<()> {
+    let sample_rate = output_device.default_output_config()?.sample_rate() as f32;
+    println!(
+        "\nCalibrating the output at {} Hz. Pink noise will play at -21 dBFS RMS, set the \
+         speakers to a loud but comfortable level and keep the room quiet.",
+        sample_rate
+    );
+    let (floor, noise) = record(input_device, output_device, buffer_size, quality, None)?;
+    let bands = analyse(&floor, &noise, sample_rate)?;
+    print_bands(&bands, None);
+    let taps = design(&bands, sample_rate);
+
+    loop {
+        println!("\n[p] preview: play the noise through the correction and measure again");
+        println!("[a] apply: write the correction as a filter for --fir");
+        println!("[q] quit without writing");
+        print!("> ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(());
+        }
+        match answer.trim() {
+            "p" => {
+                let (floor, noise) = record(
+                    input_device,
+                    output_device,
+                    buffer_size,
+                    quality,
+                    Some(&taps),
+                )?;
+                match analyse(&floor, &noise, sample_rate) {
+                    Ok(corrected) => print_bands(&bands, Some(&corrected)),
+                    Err(e) => println!("{:#}", e),
+                }
+            }
+            "a" => return apply(&bands, &taps, sample_rate),
+            "q" | "" => return Ok(()),
+            other => println!("Unknown choice '{}'", other),
+        }
+    }
+}
+
+/// Plays silence and then pink noise, through `filter` when given, and
+/// returns the input recorded during each, summed to mono.
+fn record(
+    input_device: &Device,
+    output_device: &Device,
+    buffer_size: u32,
+    quality: Quality,
+    filter: Option<&[f32]>,
+) -> Result<(Vec<f32>, Vec<f32>)> {
+    let default_output_config = output_device.default_output_config()?;
+    let output_format = default_output_config.sample_format();
+    let requested =
+        sample_convert::fixed_buffer_size(default_output_config.buffer_size(), buffer_size);
+    let mut output_config: cpal::StreamConfig = default_output_config.into();
+    output_config.buffer_size = requested;
+    let output_rate = output_config.sample_rate;
+    let output_channels = output_config.channels as usize;
+
+    let settings = SourceSettings {
+        output_rate,
+        output_channels,
+        buffer_size,
+        target_latency: buffer_size,
+        drift_compensation: false,
+        map: None,
+        quality,
+        crossfade: Duration::ZERO,
+    };
+    let logger = Logger::spawn()?;
+    let realtime = Arc::new(Realtime::spawn(true, realtime::DEFAULT_PRIORITY)?);
+    let (mut feed, mut tap) = source::open_input(input_device, &settings, &logger, &realtime)?;
+    let input_stream = feed.start(input_device)?;
+
+    let seconds = |s: f32| (s * output_rate as f32) as usize;
+    let floor_start = seconds(SETTLE_SECONDS);
+    let floor_end = floor_start + seconds(FLOOR_SECONDS);
+    let noise_start = floor_end + seconds(SETTLE_SECONDS);
+    let total = noise_start + seconds(NOISE_SECONDS);
+
+    let store = ParamStore::new();
+    let mut generator = SignalGenerator::new(Waveform::PinkNoise, output_rate as f32, &store);
+    let corrected = filter.is_some();
+    let mut filter = filter.map(|taps| {
+        let mut filter = FirFilter::new(vec![taps.to_vec()]);
+        filter.prepare(1);
+        filter
+    });
+
+    // As in the latency measurement, the input is read on the output's
+    // clock so the recording lines up with what was played
+    let (mut producer, mut consumer) = HeapRb::<f32>::new(total).split();
+    let mut signal = vec![0.0f32; 8192];
+    let mut scratch = vec![0.0f32; 8192];
+    let mut played = 0usize;
+    let mut output_log = logger.channel("output");
+    let mut promoter = realtime.promoter("output", output_rate, output_device);
+    let output_stream = sample_convert::build_output_stream(
+        output_device,
+        &mut output_config,
+        output_format,
+        // The noise floor is measured, dither would be counted in it
+        DitherMode::Off,
+        move |data: &mut [f32]| {
+            let frames = data.len() / output_channels;
+            promoter.promote(frames);
+            let signal = &mut signal[..frames];
+            generator.read_into(signal, 1);
+            for (index, sample) in signal.iter_mut().enumerate() {
+                if played + index < floor_end {
+                    *sample = 0.0;
+                }
+            }
+            if let Some(filter) = filter.as_mut() {
+                filter.process(signal, 1);
+            }
+            for (frame, &sample) in data.chunks_mut(output_channels).zip(signal.iter()) {
+                frame.iter_mut().for_each(|s| *s = sample);
+            }
+            played += frames;
+
+            let captured = &mut scratch[..data.len()];
+            tap.read_into(captured, output_channels);
+            for frame in captured.chunks(output_channels) {
+                let mono = frame.iter().sum::<f32>() / output_channels as f32;
+                if producer.try_push(mono).is_err() {
+                    break;
+                }
+            }
+        },
+        move |err| output_log.log(Event::StreamError(err)),
+    )?;
+
+    println!(
+        "Measuring {}: {} s of silence, then {} s of noise...",
+        if corrected {
+            "through the correction"
+        } else {
+            "the room"
+        },
+        FLOOR_SECONDS,
+        NOISE_SECONDS
+    );
+    input_stream.play()?;
+    output_stream.play()?;
+    while consumer.occupied_len() < total {
+        thread::sleep(Duration::from_millis(50));
+    }
+    drop(output_stream);
+    drop(input_stream);
+    logger.stop();
+
+    let mut recorded = vec![0.0f32; total];
+    consumer.pop_slice(&mut recorded);
+    let noise = recorded.split_off(noise_start);
+    Ok((recorded[floor_start..floor_end].to_vec(), noise))
+}
+
+/// Third-octave response of `noise` over the noise floor, levelled to the
+/// average over `REFERENCE_HZ`, and the correction flattening it. Pink
+/// noise carries the same power in every third octave, so a flat system
+/// reads the same in each band.
+fn analyse(floor: &[f32], noise: &[f32], sample_rate: f32) -> Result<Vec<Band>> {
+    let floor = band_powers(floor, sample_rate);
+    let noise = band_powers(noise, sample_rate);
+    let edge = 2.0f32.powf(1.0 / 6.0);
+    let levels: Vec<Option<f32>> = BANDS
+        .iter()
+        .zip(floor.iter().zip(&noise))
+        .map(|(&(centre, _), (&floor, &noise))| {
+            let usable = (LOW_HZ..=HIGH_HZ).contains(&centre)
+                && centre * edge < sample_rate / 2.0
+                && noise > floor * 10.0f32.powf(MIN_SNR_DB / 10.0);
+            usable.then(|| 10.0 * (noise - floor).log10())
+        })
+        .collect();
+
+    let reference: Vec<f32> = BANDS
+        .iter()
+        .zip(&levels)
+        .filter(|((centre, _), _)| (REFERENCE_HZ.0..=REFERENCE_HZ.1).contains(centre))
+        .filter_map(|(_, &level)| level)
+        .collect();
+    if reference.is_empty() {
+        bail!(
+            "The noise barely shows over the input's noise floor. Raise the speakers or the \
+             mic gain, and check the mic is on the first selected input"
+        );
+    }
+    let reference = reference.iter().sum::<f32>() / reference.len() as f32;
+
+    Ok(BANDS
+        .iter()
+        .zip(levels)
+        .map(|(&(centre, name), level)| {
+            let level_db = level.map(|level| level - reference);
+            Band {
+                centre,
+                name,
+                level_db,
+                correction_db: level_db
+                    .map_or(0.0, |level| (-level).clamp(-MAX_CUT_DB, MAX_BOOST_DB)),
+            }
+        })
+        .collect())
+}
+
+/// Power in each of `BANDS`, averaged over half-overlapping Hann windows.
+fn band_powers(signal: &[f32], sample_rate: f32) -> Vec<f32> {
+    let fft = Fft::new(FFT_SIZE);
+    let window: Vec<f32> = (0..FFT_SIZE)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos())
+        .collect();
+    let mut power = vec![0.0f32; FFT_SIZE / 2];
+    let mut buffer = vec![Complex::ZERO; FFT_SIZE];
+    let mut frames = 0;
+    let mut start = 0;
+    while start + FFT_SIZE <= signal.len() {
+        for ((value, &s), &w) in buffer.iter_mut().zip(&signal[start..]).zip(&window) {
+            *value = Complex::new(s * w, 0.0);
+        }
+        fft.forward(&mut buffer);
+        for (power, value) in power.iter_mut().zip(&buffer) {
+            *power += value.norm_sqr();
+        }
+        frames += 1;
+        start += FFT_SIZE / 2;
+    }
+
+    let bin = |hz: f32| hz * FFT_SIZE as f32 / sample_rate;
+    let edge = 2.0f32.powf(1.0 / 6.0);
+    BANDS
+        .iter()
+        .map(|&(centre, _)| {
+            let low = bin(centre / edge).ceil() as usize;
+            let high = (bin(centre * edge).floor() as usize).min(power.len() - 1);
+            if low > high {
+                return 0.0;
+            }
+            power[low..=high].iter().sum::<f32>() / frames.max(1) as f32
+        })
+        .collect()
+}
+
+/// One line per band, with the response measured through the correction
+/// next to it when there is one.
+fn print_bands(bands: &[Band], corrected: Option<&[Band]>) {
+    let level = |band: &Band| match band.level_db {
+        Some(level) => format!("{:+.1} dB", level),
+        None => "-".to_string(),
+    };
+    println!(
+        "\n  {:>6}  {:>9}  {:>10}{}",
+        "Band",
+        "Response",
+        "Correction",
+        if corrected.is_some() {
+            "  Corrected"
+        } else {
+            ""
+        }
+    );
+    for (index, band) in bands.iter().enumerate() {
+        println!(
+            "  {:>6}  {:>9}  {:>10}{}",
+            band.name,
+            level(band),
+            format!("{:+.1} dB", band.correction_db),
+            corrected.map_or(String::new(), |c| format!("  {:>9}", level(&c[index])))
+        );
+    }
+    let spread = |bands: &[Band]| {
+        let levels = bands.iter().filter_map(|band| band.level_db);
+        let max = levels.clone().fold(f32::MIN, f32::max);
+        let min = levels.fold(f32::MAX, f32::min);
+        max - min
+    };
+    match corrected {
+        Some(corrected) => println!(
+            "Spread from {} Hz to {} Hz: {:.1} dB, {:.1} dB corrected",
+            LOW_HZ,
+            HIGH_HZ,
+            spread(bands),
+            spread(corrected)
+        ),
+        None => println!(
+            "Spread from {} Hz to {} Hz: {:.1} dB. Bands marked - are out of range or \
+             too close to the noise floor, and left flat",
+            LOW_HZ,
+            HIGH_HZ,
+            spread(bands)
+        ),
+    }
+}
+
+/// Minimum-phase filter following the band corrections, interpolated over
+/// log frequency. Designed through the folded cepstrum, so it has no delay
+/// of its own, unlike a linear-phase filter holding the sound back by half
+/// its length.
+fn design(bands: &[Band], sample_rate: f32) -> Vec<f32> {
+    let taps = (FILTER_SECONDS * sample_rate) as usize;
+    // Padded well past the filter, the cepstrum aliases otherwise
+    let size = (taps * 4).next_power_of_two();
+    let fft = Fft::new(size);
+
+    let points: Vec<(f32, f32)> = bands
+        .iter()
+        .map(|band| (band.centre.log2(), band.correction_db))
+        .collect();
+    let gain_db = |hz: f32| {
+        let x = hz.max(1.0).log2();
+        match points.iter().position(|&(p, _)| p >= x) {
+            Some(0) => points[0].1,
+            Some(i) => {
+                let ((x0, y0), (x1, y1)) = (points[i - 1], points[i]);
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            }
+            None => points[points.len() - 1].1,
+        }
+    };
+
+    // Log magnitude, real and even, so the cepstrum is real too
+    let mut spectrum = vec![Complex::ZERO; size];
+    for k in 0..=size / 2 {
+        let log = gain_db(k as f32 * sample_rate / size as f32) * LN_10 / 20.0;
+        spectrum[k] = Complex::new(log, 0.0);
+        spectrum[(size - k) % size] = Complex::new(log, 0.0);
+    }
+    fft.inverse(&mut spectrum);
+    for (n, value) in spectrum.iter_mut().enumerate() {
+        let fold = match n {
+            0 => 1.0,
+            n if n < size / 2 => 2.0,
+            n if n == size / 2 => 1.0,
+            _ => 0.0,
+        };
+        *value = Complex::new(value.re * fold, 0.0);
+    }
+    fft.forward(&mut spectrum);
+    for value in spectrum.iter_mut() {
+        *value = Complex::from_polar(value.re.exp(), value.im);
+    }
+    fft.inverse(&mut spectrum);
+
+    // What's left past the end is tiny, faded out so the cut doesn't ring
+    let fade = taps / 4;
+    (0..taps)
+        .map(|n| {
+            let tail = taps - n;
+            let envelope = if tail < fade {
+                0.5 - 0.5 * (PI * tail as f32 / fade as f32).cos()
+            } else {
+                1.0
+            };
+            spectrum[n].re * envelope
+        })
+        .collect()
+}
+
+/// Writes the filter as a text file of taps, which `--fir` reads.
+fn apply(bands: &[Band], taps: &[f32], sample_rate: f32) -> Result<()> {
+    print!("File to write the filter to [{}]: ", DEFAULT_FILE);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let path = match answer.trim() {
+        "" => PathBuf::from(DEFAULT_FILE),
+        path => PathBuf::from(path),
+    };
+
+    let mut text = format!(
+        "# Room correction from live_dsp calibrate, for --fir at {} Hz\n# {}\n",
+        sample_rate,
+        bands
+            .iter()
+            .filter(|band| band.correction_db != 0.0)
+            .map(|band| format!("{}={:.1}", band.name, band.correction_db))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    for tap in taps {
+        text.push_str(&format!("{}\n", tap));
+    }
+    fs::write(&path, text)
+        .with_context(|| format!("Failed to write the filter to {}", path.display()))?;
+
+    println!(
+        "Written {} taps to {}. Run with --fir {} on this output at {} Hz",
+        taps.len(),
+        path.display(),
+        path.display(),
+        sample_rate
+    );
+    let boost = bands
+        .iter()
+        .map(|band| band.correction_db)
+        .fold(0.0, f32::max);
+    if boost > 0.0 {
+        println!(
+            "It boosts by up to {:.1} dB, `set output.fir.gain -{:.1}` keeps the limiter out of it",
+            boost, boost
+        );
+    }
+    Ok(())
+}
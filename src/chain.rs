@@ -0,0 +1,686 @@
+use crate::control::{Command, Request};
+#[cfg(feature = "clap")]
+use crate::dsp::ClapHost;
+#[cfg(all(target_os = "linux", feature = "lv2"))]
+use crate::dsp::Lv2Host;
+use crate::dsp::amp;
+use crate::dsp::autotune;
+use crate::dsp::harmonizer::MAX_VOICES;
+use crate::dsp::{
+    AmpSim, AutoGain, AutoPan, AutoTune, Bitcrusher, Chorus, Compressor, ConvolutionReverb, Curve,
+    DeEsser, Delay, DelayTime, DspChain, FeedbackSuppressor, FeedbackView, FirFilter, Flanger,
+    GraphicEq, Harmonizer, Looper, MidSide, Multiband, NoiseGate, NoiseSuppressor, NoteDivision,
+    Phaser, PitchShifter, Processor, Reverb, RingModulator, Saturation, Scale, Tremolo, Vocoder,
+};
+use crate::mixer::ChannelKeys;
+use crate::params::{Param, ParamStore};
+use anyhow::{Context, Result, anyhow, bail};
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// Time between looks at the chain file's modification time.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Time the output gets to take a new chain and hand the old one back.
+const SWAP_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time the control loop gets to apply a reload.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One processor line of a chain file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub settings: Vec<(String, String)>,
+    pub line: usize,
+}
+
+impl Entry {
+    fn setting(&self, name: &str) -> Option<&str> {
+        self.settings
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn require(&self, name: &str) -> Result<&str> {
+        self.setting(name)
+            .with_context(|| format!("{} needs {}=", self.name, name))
+    }
+}
+
+/// Reads a chain file. The DSP chain is written one processor per line,
+/// in the order they run, with settings as `name=value`:
+///
+/// ```text
+/// denoise strength=0.6
+/// feedback threshold=12
+/// gate threshold=-50
+/// agc target=-20 max_gain=9
+/// compressor ratio=4 threshold=-18
+/// compressor sidechain=1 threshold=-30 ratio=3
+/// deesser frequency=7000 reduction=8
+/// midside width=1.4 side_cut=150
+/// geq 63=-3 2k5=-4.5 8k=2
+/// autotune key=F# scale=minor speed=0
+/// tremolo sync=1/8 depth=0.7
+/// chorus rate=0.6 depth=0.4 spread=1
+/// vocoder bands=20 carrier=2 formant=-3
+/// harmonizer voices=2 voice1.interval=4 voice2.pan=0.7
+/// amp stages=3 drive=36 bass=3 mid=-4 cabinet=cabs/greenback.wav
+/// saturation curve=tube drive=9 trim=-6
+/// bitcrusher bits=6 rate=8000 mix=0.5
+/// multiband bands=4 crossover1=150 band1.ratio=3 band3.solo=1
+/// convolution ir="rooms/small hall.wav" mix=0.3
+/// fir path=correction.wav gain=-6
+/// clap path=/usr/lib/clap/Dragonfly.clap id=com.example.reverb
+/// lv2 uri=http://calf.sourceforge.net/plugins/Equalizer5Band
+/// ```
+///
+/// Processors are `denoise`, `feedback`, `gate`, `agc`, `compressor`,
+/// `deesser`, `midside`, `geq`, `amp`, `saturation`, `multiband`, `reverb`,
+/// `convolution`, `fir`, `delay`, `tremolo`, `autopan`, `chorus`, `flanger`,
+/// `phaser`, `ringmod`, `bitcrusher`, `pitch`, `autotune`, `harmonizer`,
+/// `vocoder`, `looper`, `clap` and `lv2`, and any parameter `params` lists
+/// for them can be set, along with `bypass=1` and `wet=0.5` which every
+/// processor has. The saturation `curve` is named: tanh, cubic or tube, the
+/// amp has 1 to 4 `stages` and a `cabinet` IR, the multiband compressor has 3
+/// or 4 `bands`, the harmonizer 1 to 3 `voices`, the autotune `key` is a
+/// note, C to B with # or b, and its `scale` chromatic, major or minor, a
+/// delay, tremolo or autopan `sync` locks it to the engine tempo at a note
+/// length such as 1/4, 1/8. dotted or 1/8t triplet, a compressor's
+/// `sidechain` keys it from a mixer channel, counted from 1, and the `geq`
+/// bands are named by frequency, `1k25` for 1.25 kHz. Values holding spaces
+/// are quoted. Blank lines and lines starting with `#` are skipped.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the chain from {}", path.display()))?;
+    parse(&text).with_context(|| format!("Invalid chain in {}", path.display()))
+}
+
+pub fn parse(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = split(line).with_context(|| format!("Line {}", number + 1))?;
+        let name = words.remove(0);
+        let mut settings = Vec::new();
+        for word in words {
+            let Some((key, value)) = word.split_once('=') else {
+                bail!("Line {}: expected name=value, got '{}'", number + 1, word);
+            };
+            settings.push((key.to_string(), value.to_string()));
+        }
+        entries.push(Entry {
+            name,
+            settings,
+            line: number + 1,
+        });
+    }
+    Ok(entries)
+}
+
+/// Splits a line on whitespace, keeping quoted runs together without
+/// their quotes.
+fn split(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if quoted {
+        bail!("Unclosed quote");
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Makes the processors named in a chain file. Plugin files stay open
+/// between reloads.
+pub struct Builder {
+    sample_rate: f32,
+    feedback: Arc<FeedbackView>,
+    /// Engine tempo for the tempo-synced effects.
+    tempo: Arc<Param>,
+    /// Mixer channels a compressor can be keyed from.
+    keys: Arc<ChannelKeys>,
+    #[cfg(feature = "clap")]
+    clap: ClapHost,
+    #[cfg(all(target_os = "linux", feature = "lv2"))]
+    lv2: Option<Lv2Host>,
+}
+
+impl Builder {
+    pub fn new(
+        sample_rate: f32,
+        feedback: Arc<FeedbackView>,
+        tempo: Arc<Param>,
+        keys: Arc<ChannelKeys>,
+    ) -> Self {
+        Builder {
+            sample_rate,
+            feedback,
+            tempo,
+            keys,
+            #[cfg(feature = "clap")]
+            clap: ClapHost::new(),
+            #[cfg(all(target_os = "linux", feature = "lv2"))]
+            lv2: None,
+        }
+    }
+
+    /// Makes the chain for an output of `channels`.
+    pub fn build(&mut self, entries: &[Entry], channels: usize) -> Result<DspChain> {
+        let mut chain = DspChain::new();
+        for entry in entries {
+            self.push(&mut chain, entry, channels)
+                .with_context(|| format!("Line {}: {}", entry.line, entry.name))?;
+        }
+        Ok(chain)
+    }
+
+    fn push(&mut self, chain: &mut DspChain, entry: &Entry, channels: usize) -> Result<()> {
+        let sample_rate = self.sample_rate;
+        match entry.name.as_str() {
+            "denoise" => add(chain, NoiseSuppressor::new(sample_rate), entry, &[]),
+            "feedback" => add(
+                chain,
+                FeedbackSuppressor::new(sample_rate, self.feedback.clone()),
+                entry,
+                &[],
+            ),
+            "gate" => add(chain, NoiseGate::new(sample_rate), entry, &[]),
+            "agc" => add(chain, AutoGain::new(sample_rate), entry, &[]),
+            "compressor" => {
+                let mut compressor = Compressor::new(sample_rate);
+                if let Some(value) = entry.setting("sidechain") {
+                    let channel: usize = value
+                        .parse()
+                        .ok()
+                        .filter(|&channel| channel >= 1)
+                        .with_context(|| {
+                            format!("sidechain must be a mixer channel, got {}", value)
+                        })?;
+                    let key = self.keys.get(channel - 1).with_context(|| {
+                        format!(
+                            "sidechain {} isn't a mixer channel, there are {}",
+                            channel,
+                            self.keys.channels()
+                        )
+                    })?;
+                    compressor.set_sidechain(key);
+                }
+                add(chain, compressor, entry, &["sidechain"])
+            }
+            "deesser" => add(chain, DeEsser::new(sample_rate), entry, &[]),
+            "midside" => add(chain, MidSide::new(sample_rate), entry, &[]),
+            "geq" => add(chain, GraphicEq::new(sample_rate), entry, &[]),
+            "amp" => {
+                let stages = match entry.setting("stages") {
+                    Some(value) => value
+                        .parse()
+                        .ok()
+                        .filter(|stages| (1..=amp::MAX_STAGES).contains(stages))
+                        .with_context(|| {
+                            format!("stages must be 1 to {}, got {}", amp::MAX_STAGES, value)
+                        })?,
+                    None => 2,
+                };
+                let mut amp = AmpSim::new(stages, sample_rate);
+                if let Some(path) = entry.setting("cabinet") {
+                    amp.load_cabinet(Path::new(path))?;
+                }
+                add(chain, amp, entry, &["stages", "cabinet"])
+            }
+            "saturation" => {
+                let curve = match entry.setting("curve") {
+                    Some(name) => Curve::parse(name)?,
+                    None => Curve::Tanh,
+                };
+                add(
+                    chain,
+                    Saturation::new(curve, sample_rate),
+                    entry,
+                    &["curve"],
+                )
+            }
+            "multiband" => {
+                let bands = match entry.setting("bands") {
+                    Some(value) => value
+                        .parse()
+                        .ok()
+                        .filter(|bands| (3..=4).contains(bands))
+                        .with_context(|| format!("bands must be 3 or 4, got {}", value))?,
+                    None => 3,
+                };
+                add(chain, Multiband::new(bands, sample_rate), entry, &["bands"])
+            }
+            "reverb" => add(chain, Reverb::new(sample_rate), entry, &[]),
+            "delay" => {
+                let mut delay = Delay::new(sample_rate, self.tempo.clone());
+                if let Some(division) = sync(entry)? {
+                    delay.set_time(DelayTime::Note(division));
+                }
+                add(chain, delay, entry, &["sync"])
+            }
+            "tremolo" => {
+                let mut tremolo = Tremolo::new(sample_rate, self.tempo.clone());
+                tremolo.set_sync(sync(entry)?);
+                add(chain, tremolo, entry, &["sync"])
+            }
+            "autopan" => {
+                let mut autopan = AutoPan::new(sample_rate, self.tempo.clone());
+                autopan.set_sync(sync(entry)?);
+                add(chain, autopan, entry, &["sync"])
+            }
+            "chorus" => add(chain, Chorus::new(sample_rate), entry, &[]),
+            "flanger" => add(chain, Flanger::new(sample_rate), entry, &[]),
+            "phaser" => add(chain, Phaser::new(sample_rate), entry, &[]),
+            "ringmod" => add(chain, RingModulator::new(sample_rate), entry, &[]),
+            "bitcrusher" => add(chain, Bitcrusher::new(sample_rate), entry, &[]),
+            "pitch" => add(chain, PitchShifter::new(), entry, &[]),
+            "autotune" => {
+                let key = match entry.setting("key") {
+                    Some(name) => autotune::parse_key(name)?,
+                    None => 0,
+                };
+                let scale = match entry.setting("scale") {
+                    Some(name) => Scale::parse(name)?,
+                    None => Scale::Chromatic,
+                };
+                add(
+                    chain,
+                    AutoTune::new(key, scale, sample_rate),
+                    entry,
+                    &["key", "scale"],
+                )
+            }
+            "harmonizer" => {
+                let voices = match entry.setting("voices") {
+                    Some(value) => value
+                        .parse()
+                        .ok()
+                        .filter(|voices| (1..=MAX_VOICES).contains(voices))
+                        .with_context(|| {
+                            format!("voices must be 1 to {}, got {}", MAX_VOICES, value)
+                        })?,
+                    None => 2,
+                };
+                add(chain, Harmonizer::new(voices), entry, &["voices"])
+            }
+            "vocoder" => add(chain, Vocoder::new(sample_rate), entry, &[]),
+            "looper" => add(chain, Looper::new(sample_rate), entry, &[]),
+            "convolution" => {
+                let ir = Path::new(entry.require("ir")?);
+                add(
+                    chain,
+                    ConvolutionReverb::from_wav(ir, sample_rate)?,
+                    entry,
+                    &["ir"],
+                )
+            }
+            "fir" => {
+                let path = Path::new(entry.require("path")?);
+                add(chain, FirFilter::load(path, sample_rate)?, entry, &["path"])
+            }
+            "clap" => self.push_clap(chain, entry),
+            "lv2" => self.push_lv2(chain, entry, channels),
+            name => bail!(
+                "Unknown processor '{}', expected denoise, feedback, gate, agc, compressor, \
+                 deesser, midside, geq, amp, saturation, multiband, reverb, convolution, fir, \
+                 delay, tremolo, autopan, chorus, flanger, phaser, ringmod, bitcrusher, \
+                 pitch, autotune, harmonizer, vocoder, looper, clap or lv2",
+                name
+            ),
+        }
+    }
+
+    #[cfg(feature = "clap")]
+    fn push_clap(&mut self, chain: &mut DspChain, entry: &Entry) -> Result<()> {
+        let path = Path::new(entry.require("path")?);
+        let plugin = self
+            .clap
+            .load(path, entry.setting("id"), self.sample_rate)?;
+        add(chain, plugin, entry, &["path", "id"])
+    }
+
+    #[cfg(not(feature = "clap"))]
+    fn push_clap(&mut self, _chain: &mut DspChain, _entry: &Entry) -> Result<()> {
+        bail!("CLAP plugins need `--features clap`")
+    }
+
+    #[cfg(all(target_os = "linux", feature = "lv2"))]
+    fn push_lv2(&mut self, chain: &mut DspChain, entry: &Entry, channels: usize) -> Result<()> {
+        let uri = entry.require("uri")?;
+        // The world scans every installed plugin, only done when needed
+        if self.lv2.is_none() {
+            self.lv2 = Some(Lv2Host::new()?);
+        }
+        let host = self.lv2.as_ref().unwrap();
+        let plugin = host.load(uri, self.sample_rate, channels)?;
+        add(chain, plugin, entry, &["uri"])
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "lv2")))]
+    fn push_lv2(&mut self, _chain: &mut DspChain, _entry: &Entry, _channels: usize) -> Result<()> {
+        bail!("LV2 plugins need Linux and `--features lv2`")
+    }
+}
+
+/// The `sync` note length of a tempo-synced effect, `None` without one or
+/// with `sync=off`.
+fn sync(entry: &Entry) -> Result<Option<NoteDivision>> {
+    match entry.setting("sync") {
+        None | Some("off") => Ok(None),
+        Some(text) => NoteDivision::parse(text).map(Some).with_context(|| {
+            format!(
+                "sync must be off or a note like 1/4, 1/8. or 1/8t, got {}",
+                text
+            )
+        }),
+    }
+}
+
+/// Applies the entry's settings to `processor` and appends it. `fixed`
+/// settings were used to make it and aren't parameters.
+fn add<P: Processor + 'static>(
+    chain: &mut DspChain,
+    mut processor: P,
+    entry: &Entry,
+    fixed: &[&str],
+) -> Result<()> {
+    let params = processor.params();
+    let mut bypassed = false;
+    let mut wet = 1.0;
+    for (name, value) in &entry.settings {
+        if fixed.contains(&name.as_str()) {
+            continue;
+        }
+        match name.as_str() {
+            "bypass" => {
+                bypassed = parse_switch(value)?;
+                continue;
+            }
+            "wet" => {
+                wet = value
+                    .parse()
+                    .ok()
+                    .filter(|wet| (0.0..=1.0).contains(wet))
+                    .with_context(|| format!("wet must be 0 to 1, got {}", value))?;
+                continue;
+            }
+            _ => {}
+        }
+        let Some(info) = params.iter().find(|p| p.name == name) else {
+            let names: Vec<_> = fixed
+                .iter()
+                .copied()
+                .chain(["bypass", "wet"])
+                .chain(params.iter().map(|p| p.name))
+                .collect();
+            bail!(
+                "No setting '{}', {} has: {}",
+                name,
+                entry.name,
+                names.join(", ")
+            );
+        };
+        let value: f32 = value
+            .parse()
+            .with_context(|| format!("Invalid value for {}: {}", name, value))?;
+        if !(info.min..=info.max).contains(&value) {
+            bail!("{} must be {} to {}", name, info.min, info.max);
+        }
+        processor.set_param(info.name, value);
+    }
+    chain.push(processor);
+    chain.set_last(bypassed, wet);
+    Ok(())
+}
+
+fn parse_switch(text: &str) -> Result<bool> {
+    match text {
+        "1" | "on" | "true" => Ok(true),
+        "0" | "off" | "false" => Ok(false),
+        _ => bail!("bypass must be on or off, got {}", text),
+    }
+}
+
+/// The output's DSP chain. A rebuilt chain is picked up at the start of
+/// a callback, and the old one goes back to the `ChainReloader` to be
+/// dropped there, so nothing is freed on the audio thread.
+pub struct LiveChain {
+    chain: DspChain,
+    incoming: HeapCons<DspChain>,
+    retired: HeapProd<DspChain>,
+}
+
+impl LiveChain {
+    pub fn names(&self) -> Vec<&'static str> {
+        self.chain.names()
+    }
+}
+
+impl Processor for LiveChain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if let Some(chain) = self.incoming.try_pop() {
+            let old = std::mem::replace(&mut self.chain, chain);
+            // The reloader empties the ring before sending another chain,
+            // so there is always room
+            let _ = self.retired.try_push(old);
+        }
+        self.chain.process(buffer, channels);
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.chain.prepare(channels);
+    }
+
+    fn reset(&mut self) {
+        self.chain.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.chain.latency()
+    }
+}
+
+/// Rebuilds the output's DSP chain from its file and hands it to the
+/// `LiveChain`, with the streams left running. Parameter values come
+/// from the file, not from what was set since.
+pub struct ChainReloader {
+    path: PathBuf,
+    /// Put in front of the parameter keys, `b.` for an A/B chain's B.
+    prefix: String,
+    channels: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    builder: Builder,
+    /// Parameters of the chain last built, removed when a reload drops
+    /// their processor.
+    keys: Vec<String>,
+    incoming: HeapProd<DspChain>,
+    retired: HeapCons<DspChain>,
+}
+
+/// What every chain the reloader builds shares with the rest of the
+/// engine.
+pub struct ChainSettings<'a> {
+    pub sample_rate: f32,
+    /// Where the chain's parameters are bound.
+    pub params: &'a ParamStore,
+    /// Engine tempo for the tempo-synced effects.
+    pub tempo: Arc<Param>,
+    /// Mixer channels a compressor can be keyed from.
+    pub keys: Arc<ChannelKeys>,
+}
+
+impl ChainReloader {
+    /// Builds the chain in the file at `path` with its parameters under
+    /// `prefix`, returning the reloader and the chain to run. A feedback
+    /// suppressor reports its notches to `feedback`.
+    pub fn load(
+        path: &Path,
+        prefix: &str,
+        channels: usize,
+        settings: &ChainSettings,
+        feedback: Arc<FeedbackView>,
+    ) -> Result<(ChainReloader, LiveChain)> {
+        let mut builder = Builder::new(
+            settings.sample_rate,
+            feedback,
+            settings.tempo.clone(),
+            settings.keys.clone(),
+        );
+        let mut chain = builder.build(&load(path)?, channels)?;
+        chain.bind_params(prefix, settings.params);
+        chain.prepare(channels);
+        let keys = chain.param_keys();
+
+        let (incoming, taken) = HeapRb::new(1).split();
+        let (handed_back, retired) = HeapRb::new(2).split();
+        let reloader = ChainReloader {
+            path: path.to_path_buf(),
+            prefix: prefix.to_string(),
+            channels,
+            state: Mutex::new(State {
+                builder,
+                keys,
+                incoming,
+                retired,
+            }),
+        };
+        let live = LiveChain {
+            chain,
+            incoming: taken,
+            retired: handed_back,
+        };
+        Ok((reloader, live))
+    }
+
+    /// Reads the file again and swaps the chain it describes in. On an
+    /// error the running chain is kept.
+    pub fn reload(&self, params: &ParamStore) -> Result<String> {
+        let entries = load(&self.path)?;
+        let mut state = self.state.lock().unwrap();
+        state.retired.clear();
+        if state.incoming.is_full() {
+            bail!("The output hasn't taken the last chain yet, is it running?");
+        }
+
+        let mut chain = state.builder.build(&entries, self.channels)?;
+        chain.bind_params(&self.prefix, params);
+        chain.prepare(self.channels);
+        let keys = chain.param_keys();
+        for key in state.keys.iter().filter(|k| !keys.contains(k)) {
+            params.remove(key);
+        }
+        state.keys = keys;
+        let label = match self.prefix.trim_end_matches('.') {
+            "" => "DSP chain".to_string(),
+            prefix => format!("DSP chain {}", prefix.to_uppercase()),
+        };
+        let mut status = format!("{}: {}", label, chain.names().join(" -> "));
+        if chain.latency() > 0 {
+            status += &format!(", {} samples latency", chain.latency());
+        }
+        if state.incoming.try_push(chain).is_err() {
+            bail!("The output hasn't taken the last chain yet");
+        }
+
+        // Waits for the old chain so its memory and plugins are released
+        // now. A stopped output hands it back by the next reload
+        let start = Instant::now();
+        while state.retired.is_empty() && start.elapsed() < SWAP_TIMEOUT {
+            thread::sleep(Duration::from_millis(5));
+        }
+        state.retired.clear();
+        Ok(status)
+    }
+}
+
+/// Reloads the chain through the control loop when its file changes.
+pub struct ChainWatcher {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl ChainWatcher {
+    /// Watches the file at `path`, printing the outcome of every reload
+    /// when `report` is set.
+    pub fn spawn(path: &Path, remote: Sender<Request>, report: bool) -> Result<ChainWatcher> {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let path = path.to_path_buf();
+        let thread = thread::Builder::new()
+            .name("chain watch".to_string())
+            .spawn(move || {
+                let mut seen = modified(&path);
+                while flag.load(Ordering::Relaxed) {
+                    thread::sleep(POLL_INTERVAL);
+                    let current = modified(&path);
+                    // Missing while an editor replaces it
+                    if current.is_none() || current == seen {
+                        continue;
+                    }
+                    seen = current;
+                    let result = reload(&remote);
+                    if report {
+                        match result {
+                            Ok(status) => info!("{} changed. {}", path.display(), status),
+                            Err(err) => warn!("{} changed: {:#}", path.display(), err),
+                        }
+                    }
+                }
+            })?;
+        Ok(ChainWatcher { running, thread })
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn reload(remote: &Sender<Request>) -> Result<String> {
+    let (reply, result) = mpsc::channel();
+    remote
+        .send(Request {
+            command: Command::Reload,
+            reply: Some(reply),
+        })
+        .map_err(|_| anyhow!("The control loop has stopped"))?;
+    result
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| anyhow!("The control loop didn't answer"))?
+}
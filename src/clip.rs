@@ -0,0 +1,158 @@
+use crate::dsp::gain_to_db;
+use crate::params::AtomicF32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Samples this close to full scale count as clipped. A 16-bit full scale
+/// sample converts to just under 1.0, so a limit of 1.0 would miss it.
+const CLIP_LEVEL: f32 = 0.999;
+/// Channels the hold indicators tell apart, more share the last one.
+const MAX_CHANNELS: usize = 64;
+
+/// Clipped samples at one stage of the signal path: an input as it is
+/// converted, or the output of a processor. The audio thread adds to it
+/// once per block, the indicators hold until reset.
+#[derive(Debug)]
+pub struct ClipStats {
+    pub name: String,
+    samples: AtomicU64,
+    /// Blocks with at least one clipped sample.
+    blocks: AtomicU64,
+    /// Channels that clipped since the last reset, one bit each from 0.
+    held: AtomicU64,
+    /// Highest sample since the last reset.
+    peak: AtomicF32,
+}
+
+/// Totals at one point in time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClipSnapshot {
+    pub samples: u64,
+    pub blocks: u64,
+    /// Channels that clipped, counted from 0.
+    pub channels: Vec<usize>,
+    pub peak_db: f32,
+}
+
+impl ClipStats {
+    pub fn new(name: String) -> Self {
+        ClipStats {
+            name,
+            samples: AtomicU64::new(0),
+            blocks: AtomicU64::new(0),
+            held: AtomicU64::new(0),
+            peak: AtomicF32::new(0.0),
+        }
+    }
+
+    /// Counts the clipped samples in one interleaved block.
+    pub fn record(&self, buffer: &[f32], channels: usize) {
+        let channels = channels.max(1);
+        let mut clipped = 0;
+        let mut held = 0u64;
+        let mut peak = 0.0f32;
+        for frame in buffer.chunks(channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                let level = sample.abs();
+                peak = peak.max(level);
+                if level >= CLIP_LEVEL {
+                    clipped += 1;
+                    held |= 1 << channel.min(MAX_CHANNELS - 1);
+                }
+            }
+        }
+        if clipped == 0 {
+            return;
+        }
+        self.samples.fetch_add(clipped, Ordering::Relaxed);
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.held.fetch_or(held, Ordering::Relaxed);
+        if peak > self.peak.get() {
+            self.peak.set(peak);
+        }
+    }
+
+    /// Whether anything clipped since the last reset.
+    pub fn held(&self) -> bool {
+        self.held.load(Ordering::Relaxed) != 0
+    }
+
+    pub fn snapshot(&self) -> ClipSnapshot {
+        let held = self.held.load(Ordering::Relaxed);
+        ClipSnapshot {
+            samples: self.samples.load(Ordering::Relaxed),
+            blocks: self.blocks.load(Ordering::Relaxed),
+            channels: (0..MAX_CHANNELS).filter(|c| held & (1 << c) != 0).collect(),
+            peak_db: gain_to_db(self.peak.get()),
+        }
+    }
+
+    /// Clears the counters and the hold.
+    pub fn reset(&self) {
+        self.samples.store(0, Ordering::Relaxed);
+        self.blocks.store(0, Ordering::Relaxed);
+        self.held.store(0, Ordering::Relaxed);
+        self.peak.set(0.0);
+    }
+
+    pub fn describe(&self) -> String {
+        let snapshot = self.snapshot();
+        if snapshot.samples == 0 {
+            return format!("{}: no clipping", self.name);
+        }
+        let channels: Vec<String> = snapshot
+            .channels
+            .iter()
+            .map(|channel| (channel + 1).to_string())
+            .collect();
+        format!(
+            "{}: CLIP {} samples in {} blocks, channels {}, peak {:+.1} dBFS",
+            self.name,
+            snapshot.samples,
+            snapshot.blocks,
+            channels.join(" "),
+            snapshot.peak_db
+        )
+    }
+}
+
+/// Every stage watched for clipping, inputs and processors alike. Shared,
+/// so a reloaded chain picks its processors' counters back up.
+#[derive(Debug, Default)]
+pub struct ClipMeters {
+    stages: Mutex<Vec<Arc<ClipStats>>>,
+}
+
+impl ClipMeters {
+    /// Adds the stats of the inputs ahead of the processors they feed.
+    pub fn add_inputs(&self, inputs: Vec<Arc<ClipStats>>) {
+        self.stages.lock().unwrap().splice(0..0, inputs);
+    }
+
+    /// The counters under `name`, or new ones.
+    pub fn bind(&self, name: String) -> Arc<ClipStats> {
+        let mut stages = self.stages.lock().unwrap();
+        if let Some(stats) = stages.iter().find(|s| s.name == name) {
+            return stats.clone();
+        }
+        let stats = Arc::new(ClipStats::new(name));
+        stages.push(stats.clone());
+        stats
+    }
+
+    /// Every stage in the order added.
+    pub fn all(&self) -> Vec<Arc<ClipStats>> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    /// Stages holding a clip, the earliest in the signal path first.
+    pub fn held(&self) -> Vec<Arc<ClipStats>> {
+        self.all().into_iter().filter(|s| s.held()).collect()
+    }
+
+    pub fn reset(&self) {
+        for stats in self.all() {
+            stats.reset();
+        }
+    }
+}
@@ -0,0 +1,1310 @@
+use crate::broadcast::BroadcastControls;
+use crate::chain::ChainReloader;
+use crate::cues::CueList;
+use crate::dsp::FeedbackView;
+use crate::fade::FadeControl;
+use crate::headroom::HeadroomMeters;
+use crate::history::History;
+use crate::jitter::JitterStats;
+use crate::load::CallbackLoad;
+use crate::loudness::LoudnessLevels;
+use crate::meter::{self, MeterLevels};
+use crate::mixer::{AuxControls, ChannelControls, MasterControls, SEND_OFF_DB};
+use crate::params::{Param, ParamStore};
+use crate::phase::PhaseLevels;
+use crate::player::Transport;
+use crate::profile::{self, Profile};
+use crate::realtime::Realtime;
+use crate::scene::{Fades, Scenes};
+use crate::scope::ScopeView;
+use crate::service::Watchdog;
+use crate::shutdown;
+use crate::spectrogram::Spectrogram;
+use crate::spectrum::SpectrumView;
+use crate::supervisor::Supervisor;
+use crate::tempo::Tempo;
+use crate::timing::CallbackTimes;
+use crate::transfer::{self, TransferView};
+use crate::tuner::TunerView;
+use crate::xrun::XrunStats;
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::{self, BufRead};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Time the control loop gets to apply a change sent by another thread.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const HELP: &str = "Commands:
+  list                 Show every mixer channel
+  meters               Show input and output levels
+  loudness [reset]     Show output loudness (LUFS) and true peak, or restart it
+  spectrum             Show the strongest frequencies on the output
+  phase                Show the master bus phase correlation
+  tuner [ch|off]       Show the tuner's note, or move it to a mixer channel
+  scope [ch|off]       Show the scope's levels, or move it to a mixer channel
+  scope time <ms>      Set the time across the scope, 1 to 1000 ms
+  scope trigger <lvl>  Start sweeps on the left side rising through lvl, -1 to
+                       1, or off to run free
+  transfer             Show the transfer function per third octave
+  transfer <ref> <ch>  Measure from reference channel ref to mic channel ch,
+                       off to stop, find to look for the delay again
+  feedback [reset]     Show the notches set on ringing frequencies, or clear them
+  clips [reset]        Show clipping per input and processor, or clear the holds
+  headroom [reset]     Show the peaks at every stage and what to change about
+                       the gain staging, or restart them. Needs --headroom
+  cpu [reset]          Show the time each processor takes per callback, the
+                       costliest first, or restart the counts
+  realtime             Show whether each audio thread got real-time priority
+  gain <ch> <dB>       Set channel gain, e.g. gain 1 -3
+  pan <ch> <-1..1>     Pan a channel, -1 is hard left
+  mute <ch> [on|off]   Toggle or set mute
+  solo <ch> [on|off]   Toggle or set solo
+  send <ch> <aux> <dB> Set a channel's send level to an aux bus, -96 is off
+  prefader <ch> <aux>  Toggle a send between pre- and post-fader
+  return <aux> <dB>    Set an aux bus return level
+  master <dB>          Set master gain
+  dim [on|off]         Toggle or set the master dim
+  dimlevel <dB>        Set how far dim turns the master down, -20 at start
+  mono [on|off]        Toggle or set the master mono sum
+  onair <ch> [on|off]  Toggle or set a channel in the broadcast mix, or an aux
+                       return with onair aux1
+  broadcast <dB>       Set the broadcast bus gain
+  talk [on|off]        Toggle or set the talkback
+  looper <action>      Work the looper: rec to record, play and overdub, undo
+                       the last overdub or clear the loop
+  tempo [bpm]          Show or set the engine tempo synced effects follow
+  tap                  Tap the tempo in, from the second tap on
+  metronome [on|off]   Toggle or set the metronome, set its time signature with
+                       set metronome.beats and metronome.unit
+  xruns                Show overruns and underruns per stream
+  buffers              Show the input jitter buffers
+  target <ms>          Set the jitter buffer target latency of every input
+  undo                 Take back the last change to the mixer or an effect
+  redo                 Make the last change undone again
+  history              Show the latest changes undo takes back
+  params               Show every effect parameter
+  set <param> <value>  Change an effect parameter, e.g. set compressor.ratio 4,
+                       every effect has a bypass switch and a wet amount.
+                       channel.1.gain and channel.1.pan set a mixer channel
+  reload               Rebuild the DSP chain from its --chain file, and the
+                       --chain-b one
+  ab [a|b]             Toggle or pick the DSP chain heard, A or B
+  players              Show the file players
+  play [p]             Start file player p, 1 if omitted
+  pause [p]            Pause a file player
+  stop [p]             Pause and rewind a file player
+  seek [p] <time>      Jump to seconds or m:ss, e.g. seek 1:30
+  loop [p]             Toggle looping a file player
+  profiles             Show the loaded profiles
+  scenes               Show the stored scenes
+  scene <n> [fade]     Recall scene n, over fade seconds instead of its own
+  scene save <n>       Store every mixer and effect setting as scene n, a name
+                       can follow
+  scene fade <n> <s>   Set how long scene n fades, or one of its settings with
+                       scene fade <n> <key> <s>
+  fade <key> <v> <s>   Take a setting to a value over s seconds, keys as in a
+                       scene, e.g. fade channel.3.gain -60 10
+  cues                 Show the cue list and its clock
+  cue [name]           Fire the cue on standby, or the one called name
+  cues start [time]    Run the cue clock, from time in seconds or m:ss if given
+  cues stop            Stop the cue clock
+  cues reset           Stop the clock at 0 and put the first cue on standby
+  profile <name>       Switch to a profile's devices and settings
+  midi                 Show the MIDI controller mappings
+  learn <target>       Map the next MIDI control moved to a target, e.g. learn gain 1
+  quit                 Stop streaming and exit";
+
+/// Everything the console can adjust while streaming.
+pub struct Controls {
+    pub channels: Vec<Arc<ChannelControls>>,
+    pub aux: Vec<Arc<AuxControls>>,
+    pub master: Arc<MasterControls>,
+    /// Set with `--broadcast`.
+    pub broadcast: Option<BroadcastControls>,
+    /// Levels after the output limiter.
+    pub output: Arc<MeterLevels>,
+    pub loudness: Arc<LoudnessLevels>,
+    pub phase: Arc<PhaseLevels>,
+    pub spectrum: Arc<SpectrumView>,
+    /// Set with `--spectrogram`, written out on exit.
+    pub spectrogram: Option<Arc<Spectrogram>>,
+    /// Time the output callback spends rendering.
+    pub load: Arc<CallbackLoad>,
+    /// Where the output callback's time goes, stage by stage.
+    pub callback_times: Arc<CallbackTimes>,
+    /// How the device streams' threads are scheduled.
+    pub realtime: Arc<Realtime>,
+    pub tuner: Arc<TunerView>,
+    pub scope: Arc<ScopeView>,
+    pub transfer: Arc<TransferView>,
+    /// Notches set by a feedback suppressor in the chain.
+    pub feedback: Arc<FeedbackView>,
+    pub params: Arc<ParamStore>,
+    pub tempo: Arc<Tempo>,
+    pub players: Vec<Arc<Transport>>,
+    /// One per input stream plus the output.
+    pub xruns: Vec<Arc<XrunStats>>,
+    /// One per input stream.
+    pub buffers: Vec<Arc<JitterStats>>,
+    pub profiles: Vec<Profile>,
+    /// Name of the profile last switched to.
+    pub active_profile: Arc<Mutex<Option<String>>>,
+    pub scenes: Scenes,
+    pub fades: Fades,
+    /// Set with `--cues`.
+    pub cues: CueList,
+    pub history: History,
+    /// Output fade, closed while the output stream is rebuilt.
+    pub fade: Arc<FadeControl>,
+    /// Handed to threads that send commands: MIDI, OSC, HTTP and scripts.
+    pub remote: mpsc::Sender<Request>,
+    pub requests: mpsc::Receiver<Request>,
+    pub midi: Option<Arc<dyn Learn>>,
+    /// Set when the DSP chain comes from a `--chain` file.
+    pub chain: Option<ChainReloader>,
+    /// Set when `--chain-b` loads a second chain to compare with.
+    pub chain_b: Option<ChainReloader>,
+}
+
+/// Command sent from another thread, applied by the control loop. The
+/// result goes to `reply` when there is one.
+pub struct Request {
+    pub command: Command,
+    pub reply: Option<mpsc::Sender<Result<String>>>,
+}
+
+/// Controller that can map the next control moved to a target.
+pub trait Learn: Send + Sync {
+    /// Arms learning for `target`, a command without its value.
+    fn learn(&self, target: &str) -> Result<String>;
+
+    /// Every mapping, one per line.
+    fn describe(&self) -> String;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    List,
+    Meters,
+    Loudness,
+    ResetLoudness,
+    Spectrum,
+    Phase,
+    Tuner,
+    /// Turns the tuner off without a channel.
+    SetTuner(Option<usize>),
+    Scope,
+    /// Turns the scope off without a channel.
+    SetScope(Option<usize>),
+    ScopeTime(f32),
+    /// Runs free without a level.
+    ScopeTrigger(Option<f32>),
+    Transfer,
+    /// Reference and measurement channels, `None` to stop.
+    SetTransfer(Option<(usize, usize)>),
+    FindDelay,
+    Feedback,
+    ResetFeedback,
+    Clips,
+    ResetClips,
+    Headroom,
+    ResetHeadroom,
+    Cpu,
+    ResetCpu,
+    Realtime,
+    Xruns,
+    Buffers,
+    Target(f32),
+    Gain(usize, f32),
+    Pan(usize, f32),
+    /// Toggles without a state.
+    Mute(usize, Option<bool>),
+    Solo(usize, Option<bool>),
+    Send(usize, usize, f32),
+    PreFader(usize, usize),
+    Return(usize, f32),
+    Master(f32),
+    Dim(Option<bool>),
+    DimLevel(f32),
+    Mono(Option<bool>),
+    OnAir(usize, Option<bool>),
+    OnAirReturn(usize, Option<bool>),
+    Broadcast(f32),
+    Talk(Option<bool>),
+    /// The looper parameter to fire: record, undo or clear.
+    Looper(&'static str),
+    /// Shows the tempo without one.
+    Tempo(Option<f32>),
+    Tap,
+    Metronome(Option<bool>),
+    Undo,
+    Redo,
+    History,
+    Params,
+    Set(String, f32),
+    Reload,
+    /// Switches to B with `true`, toggles without a choice.
+    Ab(Option<bool>),
+    Players,
+    Play(usize),
+    Pause(usize),
+    Stop(usize),
+    Seek(usize, f32),
+    Loop(usize),
+    Profiles,
+    Profile(String),
+    Scenes,
+    /// Scene number from 1, and a fade replacing the scene's own.
+    Scene(usize, Option<f32>),
+    SaveScene(usize, Option<String>),
+    /// The whole scene's fade without a key.
+    SceneFade(usize, Option<String>, f32),
+    /// Setting key, value and seconds.
+    Fade(String, f32, f32),
+    Cues,
+    CueGo,
+    Cue(String),
+    /// Runs on from where it stopped without a time.
+    CueStart(Option<f32>),
+    CueStop,
+    CueReset,
+    Midi,
+    Learn(String),
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["list" | "ls"] => Command::List,
+            ["meters"] => Command::Meters,
+            ["loudness"] => Command::Loudness,
+            ["loudness", "reset"] => Command::ResetLoudness,
+            ["spectrum"] => Command::Spectrum,
+            ["phase"] => Command::Phase,
+            ["tuner"] => Command::Tuner,
+            ["tuner", "off"] => Command::SetTuner(None),
+            ["tuner", ch] => Command::SetTuner(Some(parse_channel(ch)?)),
+            ["scope"] => Command::Scope,
+            ["scope", "off"] => Command::SetScope(None),
+            ["scope", "time", ms] => Command::ScopeTime(parse_value(ms)?),
+            ["scope", "trigger", "off"] => Command::ScopeTrigger(None),
+            ["scope", "trigger", level] => Command::ScopeTrigger(Some(parse_value(level)?)),
+            ["scope", ch] => Command::SetScope(Some(parse_channel(ch)?)),
+            ["transfer"] => Command::Transfer,
+            ["transfer", "off"] => Command::SetTransfer(None),
+            ["transfer", "find"] => Command::FindDelay,
+            ["transfer", reference, ch] => {
+                let channels = (parse_channel(reference)?, parse_channel(ch)?);
+                if channels.0 == channels.1 {
+                    bail!("The reference and the measurement need different channels");
+                }
+                Command::SetTransfer(Some(channels))
+            }
+            ["feedback"] => Command::Feedback,
+            ["feedback", "reset"] => Command::ResetFeedback,
+            ["clips"] => Command::Clips,
+            ["clips", "reset"] => Command::ResetClips,
+            ["headroom"] => Command::Headroom,
+            ["headroom", "reset"] => Command::ResetHeadroom,
+            ["cpu"] => Command::Cpu,
+            ["cpu", "reset"] => Command::ResetCpu,
+            ["realtime"] => Command::Realtime,
+            ["xruns"] => Command::Xruns,
+            ["buffers"] => Command::Buffers,
+            ["target", ms] => Command::Target(parse_value(ms)?),
+            ["gain", ch, db] => Command::Gain(parse_channel(ch)?, parse_value(db)?),
+            ["pan", ch, pan] => Command::Pan(parse_channel(ch)?, parse_value(pan)?),
+            ["mute", ch] => Command::Mute(parse_channel(ch)?, None),
+            ["mute", ch, state] => Command::Mute(parse_channel(ch)?, Some(parse_state(state)?)),
+            ["solo", ch] => Command::Solo(parse_channel(ch)?, None),
+            ["solo", ch, state] => Command::Solo(parse_channel(ch)?, Some(parse_state(state)?)),
+            ["send", ch, aux, db] => {
+                Command::Send(parse_channel(ch)?, parse_aux(aux)?, parse_value(db)?)
+            }
+            ["prefader", ch, aux] => Command::PreFader(parse_channel(ch)?, parse_aux(aux)?),
+            ["return", aux, db] => Command::Return(parse_aux(aux)?, parse_value(db)?),
+            ["master", db] => Command::Master(parse_value(db)?),
+            ["dim"] => Command::Dim(None),
+            ["dim", state] => Command::Dim(Some(parse_state(state)?)),
+            ["dimlevel", db] => Command::DimLevel(parse_value(db)?),
+            ["mono"] => Command::Mono(None),
+            ["mono", state] => Command::Mono(Some(parse_state(state)?)),
+            ["onair", aux] if aux.starts_with("aux") => Command::OnAirReturn(parse_aux(aux)?, None),
+            ["onair", aux, state] if aux.starts_with("aux") => {
+                Command::OnAirReturn(parse_aux(aux)?, Some(parse_state(state)?))
+            }
+            ["onair", ch] => Command::OnAir(parse_channel(ch)?, None),
+            ["onair", ch, state] => Command::OnAir(parse_channel(ch)?, Some(parse_state(state)?)),
+            ["broadcast", db] => Command::Broadcast(parse_value(db)?),
+            ["looper", "rec" | "record"] => Command::Looper("record"),
+            ["looper", "undo"] => Command::Looper("undo"),
+            ["looper", "clear"] => Command::Looper("clear"),
+            ["tempo"] => Command::Tempo(None),
+            ["tempo", bpm] => Command::Tempo(Some(parse_value(bpm)?)),
+            ["tap"] => Command::Tap,
+            ["metronome"] => Command::Metronome(None),
+            ["metronome", state] => Command::Metronome(Some(parse_state(state)?)),
+            ["talk"] => Command::Talk(None),
+            ["talk", state] => Command::Talk(Some(parse_state(state)?)),
+            ["undo"] => Command::Undo,
+            ["redo"] => Command::Redo,
+            ["history"] => Command::History,
+            ["params"] => Command::Params,
+            ["set", key, value] => match key.split('.').collect::<Vec<_>>().as_slice() {
+                // Mixer settings by key, the way a daemon's clients address
+                // everything
+                ["channel", ch, "gain"] => Command::Gain(parse_channel(ch)?, parse_value(value)?),
+                ["channel", ch, "pan"] => Command::Pan(parse_channel(ch)?, parse_value(value)?),
+                _ => Command::Set(key.to_string(), parse_value(value)?),
+            },
+            ["reload"] => Command::Reload,
+            ["ab"] => Command::Ab(None),
+            ["ab", "a" | "A"] => Command::Ab(Some(false)),
+            ["ab", "b" | "B"] => Command::Ab(Some(true)),
+            ["players"] => Command::Players,
+            ["play"] => Command::Play(0),
+            ["play", p] => Command::Play(parse_player(p)?),
+            ["pause"] => Command::Pause(0),
+            ["pause", p] => Command::Pause(parse_player(p)?),
+            ["stop"] => Command::Stop(0),
+            ["stop", p] => Command::Stop(parse_player(p)?),
+            ["seek", time] => Command::Seek(0, parse_time(time)?),
+            ["seek", p, time] => Command::Seek(parse_player(p)?, parse_time(time)?),
+            ["loop"] => Command::Loop(0),
+            ["loop", p] => Command::Loop(parse_player(p)?),
+            ["profiles"] => Command::Profiles,
+            ["profile", name] => Command::Profile(name.to_string()),
+            ["scenes"] => Command::Scenes,
+            ["scene", "save", n] => Command::SaveScene(parse_scene(n)?, None),
+            ["scene", "save", n, name @ ..] => {
+                Command::SaveScene(parse_scene(n)?, Some(name.join(" ")))
+            }
+            ["scene", "fade", n, s] => Command::SceneFade(parse_scene(n)?, None, parse_value(s)?),
+            ["scene", "fade", n, key, s] => {
+                Command::SceneFade(parse_scene(n)?, Some(key.to_string()), parse_value(s)?)
+            }
+            ["scene", n] => Command::Scene(parse_scene(n)?, None),
+            ["scene", n, s] => Command::Scene(parse_scene(n)?, Some(parse_value(s)?)),
+            ["fade", key, value, s] => {
+                Command::Fade(key.to_string(), parse_value(value)?, parse_value(s)?)
+            }
+            ["cues"] => Command::Cues,
+            ["cue"] => Command::CueGo,
+            ["cue", name] => Command::Cue(name.to_string()),
+            ["cues", "start"] => Command::CueStart(None),
+            ["cues", "start", time] => Command::CueStart(Some(parse_time(time)?)),
+            ["cues", "stop"] => Command::CueStop,
+            ["cues", "reset"] => Command::CueReset,
+            ["midi"] => Command::Midi,
+            ["learn", target @ ..] if !target.is_empty() => Command::Learn(target.join(" ")),
+            ["help" | "?"] => Command::Help,
+            ["quit" | "q" | "exit"] => Command::Quit,
+            _ => bail!("Unknown command '{}', type 'help' for a list", line.trim()),
+        };
+        Ok(command)
+    }
+
+    /// Applies the command and returns a line describing the new state.
+    pub fn apply(&self, controls: &Controls) -> Result<String> {
+        let channels = &controls.channels;
+        let master = &controls.master;
+        let channel = |index: usize| {
+            channels
+                .get(index)
+                .with_context(|| format!("No mixer channel {}", index + 1))
+        };
+        let aux = |index: usize| {
+            controls
+                .aux
+                .get(index)
+                .with_context(|| format!("No aux bus {}", index + 1))
+        };
+        let player = |index: usize| {
+            controls
+                .players
+                .get(index)
+                .with_context(|| format!("No file player {}", index + 1))
+        };
+        let send = |index: usize, bus: usize| {
+            aux(bus)?;
+            Ok::<_, anyhow::Error>(&channel(index)?.sends[bus])
+        };
+
+        match *self {
+            Command::List => Ok(channels
+                .iter()
+                .enumerate()
+                .map(|(i, c)| describe(i, c))
+                .chain(
+                    controls
+                        .aux
+                        .iter()
+                        .enumerate()
+                        .map(|(i, a)| describe_aux(i, a)),
+                )
+                .chain(std::iter::once(describe_master(master)))
+                .chain(controls.broadcast.iter().map(describe_broadcast))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Meters => Ok(channels
+                .iter()
+                .map(|c| describe_meter(&c.name, &c.meter))
+                .chain(std::iter::once(describe_meter("Output", &controls.output)))
+                .chain(
+                    controls
+                        .broadcast
+                        .iter()
+                        .map(|b| describe_meter("Broadcast", &b.meter)),
+                )
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Loudness => Ok(describe_loudness(&controls.loudness)),
+            Command::ResetLoudness => {
+                controls.loudness.reset();
+                Ok("Loudness measurement restarted".to_string())
+            }
+            Command::Spectrum => Ok(controls
+                .spectrum
+                .peaks(8)
+                .iter()
+                .map(|(frequency, level_db)| format!("{:>8.1} Hz {:+6.1} dB", frequency, level_db))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Phase => Ok(describe_phase(&controls.phase)),
+            Command::Tuner => Ok(describe_tuner(controls)),
+            Command::SetTuner(index) => {
+                if let Some(index) = index {
+                    channel(index)?;
+                }
+                controls.tuner.set_channel(index);
+                Ok(describe_tuner(controls))
+            }
+            Command::Scope => Ok(describe_scope(controls)),
+            Command::SetScope(index) => {
+                if let Some(index) = index {
+                    channel(index)?;
+                }
+                controls.scope.set_channel(index);
+                Ok(describe_scope(controls))
+            }
+            Command::ScopeTime(ms) => {
+                controls.scope.set_time_ms(ms);
+                Ok(describe_scope(controls))
+            }
+            Command::ScopeTrigger(level) => {
+                controls.scope.set_trigger(level);
+                Ok(describe_scope(controls))
+            }
+            Command::Transfer => {
+                let mut lines = vec![describe_transfer(controls)];
+                if controls.transfer.delay_ms().is_some() {
+                    lines.extend(controls.transfer.third_octaves().into_iter().map(
+                        |(name, reading)| match reading {
+                            Some(reading) => format!(
+                                "{:>6} {:+6.1} dB {:+5.0} deg coherence {:.2}{}",
+                                name,
+                                reading.magnitude_db,
+                                reading.phase,
+                                reading.coherence,
+                                if reading.coherence < transfer::MIN_COHERENCE {
+                                    ", unreliable"
+                                } else {
+                                    ""
+                                }
+                            ),
+                            None => format!("{:>6} -", name),
+                        },
+                    ));
+                }
+                Ok(lines.join("\n"))
+            }
+            Command::SetTransfer(channels) => {
+                if let Some((reference, measurement)) = channels {
+                    channel(reference)?;
+                    channel(measurement)?;
+                }
+                controls.transfer.set_channels(channels);
+                Ok(describe_transfer(controls))
+            }
+            Command::FindDelay => {
+                if controls.transfer.channels().is_none() {
+                    bail!("The transfer function is off, start it with transfer <ref> <ch>");
+                }
+                controls.transfer.find_delay();
+                Ok(describe_transfer(controls))
+            }
+            Command::Feedback => {
+                let feedback = feedback(controls)?;
+                let notches = feedback.notches();
+                if notches.is_empty() {
+                    return Ok("No feedback notches".to_string());
+                }
+                Ok(notches
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (frequency, depth_db))| {
+                        format!("[notch{}] {:.0} Hz {:+.0} dB", i + 1, frequency, depth_db)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            Command::ResetFeedback => {
+                feedback(controls)?.reset();
+                Ok("Feedback notches cleared".to_string())
+            }
+            Command::Clips => Ok(describe_clips(controls)),
+            Command::ResetClips => {
+                controls.params.clips().reset();
+                Ok("Clip counters cleared".to_string())
+            }
+            Command::Headroom => Ok(headroom(controls)?.report()),
+            Command::ResetHeadroom => {
+                headroom(controls)?.reset();
+                Ok("Headroom peaks cleared".to_string())
+            }
+            Command::Cpu => Ok(controls.params.loads().report(controls.load.sample_rate)),
+            Command::ResetCpu => {
+                controls.params.loads().reset();
+                Ok("Processor timings cleared".to_string())
+            }
+            Command::Realtime => Ok(controls.realtime.report()),
+            Command::Xruns => Ok(controls
+                .xruns
+                .iter()
+                .map(|x| x.describe())
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Buffers => Ok(controls
+                .buffers
+                .iter()
+                .map(|b| b.describe())
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Target(ms) => {
+                for buffer in &controls.buffers {
+                    buffer.set_target_ms(ms);
+                }
+                Command::Buffers.apply(controls)
+            }
+            Command::Gain(index, gain_db) => {
+                channel(index)?.set_gain_db(gain_db);
+                Ok(describe(index, channel(index)?))
+            }
+            Command::Pan(index, pan) => {
+                channel(index)?.set_pan(pan);
+                Ok(describe(index, channel(index)?))
+            }
+            Command::Mute(index, state) => {
+                let c = channel(index)?;
+                c.set_mute(state.unwrap_or(!c.muted()));
+                Ok(describe(index, c))
+            }
+            Command::Solo(index, state) => {
+                let c = channel(index)?;
+                c.set_solo(state.unwrap_or(!c.soloed()));
+                Ok(describe(index, c))
+            }
+            Command::Send(index, bus, level_db) => {
+                send(index, bus)?.set_level_db(level_db);
+                Ok(describe(index, channel(index)?))
+            }
+            Command::PreFader(index, bus) => {
+                let s = send(index, bus)?;
+                s.set_pre_fader(!s.pre_fader());
+                Ok(describe(index, channel(index)?))
+            }
+            Command::Return(bus, return_db) => {
+                aux(bus)?.set_return_db(return_db);
+                Ok(describe_aux(bus, aux(bus)?))
+            }
+            Command::Master(gain_db) => {
+                master.set_gain_db(gain_db);
+                Ok(describe_master(master))
+            }
+            Command::Dim(state) => {
+                master.set_dim(state.unwrap_or(!master.dimmed()));
+                Ok(describe_master(master))
+            }
+            Command::DimLevel(dim_db) => {
+                master.set_dim_db(dim_db);
+                Ok(format!("Master dim: {:+.1} dB", master.dim_db()))
+            }
+            Command::Mono(state) => {
+                master.set_mono(state.unwrap_or(!master.mono()));
+                Ok(describe_master(master))
+            }
+            Command::OnAir(index, state) => {
+                let c = channel(index)?;
+                c.set_on_air(state.unwrap_or(!c.on_air()));
+                Ok(describe(index, c))
+            }
+            Command::OnAirReturn(bus, state) => {
+                let a = aux(bus)?;
+                a.set_on_air(state.unwrap_or(!a.on_air()));
+                Ok(describe_aux(bus, a))
+            }
+            Command::Broadcast(gain_db) => {
+                let broadcast = broadcast(controls)?;
+                broadcast.master.set_gain_db(gain_db);
+                Ok(describe_broadcast(broadcast))
+            }
+            Command::Talk(state) => {
+                let on = talkback(controls)?;
+                let talking = state.unwrap_or(on.get() < 0.5);
+                on.set(if talking { 1.0 } else { 0.0 });
+                Ok(format!("Talkback {}", if talking { "on" } else { "off" }))
+            }
+            Command::Looper(action) => {
+                looper(controls, action)?.set(1.0);
+                Ok(format!("Looper {}", action))
+            }
+            Command::Tempo(bpm) => {
+                if let Some(bpm) = bpm {
+                    controls.tempo.set(bpm);
+                }
+                Ok(format!("Tempo {:.1} BPM", controls.tempo.bpm()))
+            }
+            Command::Tap => Ok(match controls.tempo.tap() {
+                Some(bpm) => format!("Tempo {:.1} BPM", bpm),
+                None => "Tap again for the tempo".to_string(),
+            }),
+            Command::Metronome(state) => {
+                let on = metronome(controls)?;
+                let running = state.unwrap_or(on.get() < 0.5);
+                on.set(if running { 1.0 } else { 0.0 });
+                Ok(format!(
+                    "Metronome {} at {:.1} BPM",
+                    if running { "on" } else { "off" },
+                    controls.tempo.bpm()
+                ))
+            }
+            Command::Undo => controls.history.undo(controls),
+            Command::Redo => controls.history.redo(controls),
+            Command::History => Ok(controls.history.describe(controls)),
+            Command::Params => Ok(controls
+                .params
+                .all()
+                .iter()
+                .map(|p| describe_param(p))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Set(ref key, value) => {
+                let param = controls
+                    .params
+                    .get(key)
+                    .with_context(|| format!("No parameter '{}', type 'params' for a list", key))?;
+                param.set(value);
+                Ok(describe_param(&param))
+            }
+            Command::Players => Ok(controls
+                .players
+                .iter()
+                .enumerate()
+                .map(|(i, p)| describe_player(i, p))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            Command::Play(index) => {
+                player(index)?.play();
+                Ok(describe_player(index, player(index)?))
+            }
+            Command::Pause(index) => {
+                player(index)?.pause();
+                Ok(describe_player(index, player(index)?))
+            }
+            Command::Stop(index) => {
+                player(index)?.stop();
+                Ok(describe_player(index, player(index)?))
+            }
+            Command::Seek(index, seconds) => {
+                let p = player(index)?;
+                p.seek(seconds);
+                Ok(format!("{}: seek to {}", p.name, format_time(seconds)))
+            }
+            Command::Loop(index) => {
+                let p = player(index)?;
+                p.set_looping(!p.looping());
+                Ok(describe_player(index, p))
+            }
+            Command::Profiles => {
+                if controls.profiles.is_empty() {
+                    return Ok("No profiles loaded, pass --profiles <FILE>".to_string());
+                }
+                Ok(controls
+                    .profiles
+                    .iter()
+                    .map(describe_profile)
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }
+            // Devices are only switched through `execute`, which gets the
+            // supervisor
+            Command::Profile(ref name) => {
+                profile::switch(profile::find(&controls.profiles, name)?, controls, None)
+            }
+            Command::Scenes => Ok(controls.scenes.describe()),
+            Command::Scene(number, fade) => controls.scenes.recall(number, fade, controls),
+            Command::SaveScene(number, ref name) => {
+                controls.scenes.save(number, name.as_deref(), controls)
+            }
+            Command::SceneFade(number, ref key, seconds) => {
+                controls.scenes.set_fade(number, key.as_deref(), seconds)
+            }
+            Command::Fade(ref key, value, seconds) => {
+                if controls.fades.start(controls, key, value, seconds)? {
+                    Ok(format!("{} fading to {} over {:.1} s", key, value, seconds))
+                } else {
+                    Ok(format!("{} = {}", key, value))
+                }
+            }
+            Command::Cues => controls.cues.describe(),
+            Command::CueGo => Ok(run_cue(controls.cues.go()?, controls, None)),
+            Command::Cue(ref name) => Ok(run_cue(controls.cues.fire_named(name)?, controls, None)),
+            Command::CueStart(from) => controls.cues.start(from),
+            Command::CueStop => controls.cues.stop(),
+            Command::CueReset => controls.cues.reset(),
+            Command::Reload => {
+                let mut status = controls
+                    .chain
+                    .as_ref()
+                    .context("The DSP chain isn't from a file, start with --chain <FILE>")?
+                    .reload(&controls.params)?;
+                if let Some(chain_b) = &controls.chain_b {
+                    status = format!("{}\n{}", status, chain_b.reload(&controls.params)?);
+                }
+                Ok(status)
+            }
+            Command::Ab(choice) => {
+                let select = ab(controls)?;
+                let b = choice.unwrap_or(select.get() < 0.5);
+                select.set(if b { 1.0 } else { 0.0 });
+                Ok(format!("Hearing DSP chain {}", if b { "B" } else { "A" }))
+            }
+            Command::Midi => Ok(midi(controls)?.describe()),
+            Command::Learn(ref target) => midi(controls)?.learn(target),
+            Command::Help => Ok(HELP.to_string()),
+            Command::Quit => Ok(String::new()),
+        }
+    }
+}
+
+fn midi(controls: &Controls) -> Result<&Arc<dyn Learn>> {
+    controls
+        .midi
+        .as_ref()
+        .context("No MIDI controller, pass --midi <PORT>")
+}
+
+fn broadcast(controls: &Controls) -> Result<&BroadcastControls> {
+    controls
+        .broadcast
+        .as_ref()
+        .context("No broadcast bus, pass --broadcast <DEVICE|mic>")
+}
+
+/// The talkback switch, `talkback.on`.
+pub fn talkback(controls: &Controls) -> Result<Arc<Param>> {
+    controls
+        .params
+        .get("talkback.on")
+        .context("No talkback, pass --talkback <CH>")
+}
+
+pub fn metronome(controls: &Controls) -> Result<Arc<Param>> {
+    controls
+        .params
+        .get("metronome.on")
+        .context("No metronome, pass --metronome <SIG>")
+}
+
+/// The A/B switch, `ab.select`, 1 for B.
+pub fn ab(controls: &Controls) -> Result<Arc<Param>> {
+    controls
+        .params
+        .get("ab.select")
+        .context("No second DSP chain, pass --chain-b <FILE>")
+}
+
+/// The looper's `record`, `undo` or `clear` trigger.
+pub fn looper(controls: &Controls, action: &str) -> Result<Arc<Param>> {
+    controls
+        .params
+        .get(&format!("looper.{}", action))
+        .context("No looper, pass --looper <CH> or add one to the --chain file")
+}
+
+fn feedback(controls: &Controls) -> Result<&FeedbackView> {
+    if !controls.feedback.is_attached() {
+        bail!("No feedback suppressor, pass --feedback or add one to the --chain file");
+    }
+    Ok(&controls.feedback)
+}
+
+fn headroom(controls: &Controls) -> Result<&HeadroomMeters> {
+    let headroom = controls.params.headroom();
+    if !headroom.is_enabled() {
+        bail!("The gain-staging analysis is off, start with --headroom");
+    }
+    Ok(headroom)
+}
+
+fn describe_profile(profile: &Profile) -> String {
+    let mut devices: Vec<String> = profile
+        .inputs
+        .iter()
+        .map(|name| format!("in '{}'", name))
+        .collect();
+    if let Some(name) = &profile.output {
+        devices.push(format!("out '{}'", name));
+    }
+    format!(
+        "{}: {}, {} commands",
+        profile.name,
+        if devices.is_empty() {
+            "same devices".to_string()
+        } else {
+            devices.join(", ")
+        },
+        profile.commands.len()
+    )
+}
+
+fn describe(index: usize, channel: &ChannelControls) -> String {
+    let sends: String = channel
+        .sends
+        .iter()
+        .enumerate()
+        .filter(|(_, send)| send.level_db() > SEND_OFF_DB)
+        .map(|(bus, send)| {
+            format!(
+                ", send aux{} {:+.1} dB {}",
+                bus + 1,
+                send.level_db(),
+                if send.pre_fader() { "pre" } else { "post" }
+            )
+        })
+        .collect();
+    format!(
+        "[{}] {}: {:+.1} dB, pan {:+.2}{}{}{}{}",
+        index + 1,
+        channel.name,
+        channel.gain_db(),
+        channel.pan(),
+        if channel.muted() { ", muted" } else { "" },
+        if channel.soloed() { ", solo" } else { "" },
+        if channel.on_air() { "" } else { ", off air" },
+        sends
+    )
+}
+
+fn describe_aux(index: usize, aux: &AuxControls) -> String {
+    format!(
+        "[aux{}] {}: return {:+.1} dB{}",
+        index + 1,
+        aux.name,
+        aux.return_db(),
+        if aux.on_air() { "" } else { ", off air" }
+    )
+}
+
+fn describe_broadcast(broadcast: &BroadcastControls) -> String {
+    format!("Broadcast: {:+.1} dB", broadcast.master.gain_db())
+}
+
+fn describe_master(master: &MasterControls) -> String {
+    format!(
+        "Master: {:+.1} dB{}{}",
+        master.gain_db(),
+        if master.dimmed() {
+            format!(", dimmed {:+.1} dB", master.dim_db())
+        } else {
+            String::new()
+        },
+        if master.mono() { ", mono" } else { "" }
+    )
+}
+
+/// One line per audio channel: peak, RMS and hold in dBFS plus a bar.
+fn describe_meter(name: &str, levels: &MeterLevels) -> String {
+    levels
+        .levels()
+        .enumerate()
+        .map(|(channel, level)| {
+            format!(
+                "{:<16.16} {:>2} [{}] peak {:+6.1} rms {:+6.1} hold {:+6.1}",
+                if channel == 0 { name } else { "" },
+                channel + 1,
+                meter::bar(level, -60.0, 30),
+                level.peak_db,
+                level.rms_db,
+                level.hold_db
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn describe_loudness(loudness: &LoudnessLevels) -> String {
+    format!(
+        "M {:+.1} LUFS, S {:+.1} LUFS, I {:+.1} LUFS, TP {:+.1} dBTP",
+        loudness.momentary(),
+        loudness.short_term(),
+        loudness.integrated(),
+        loudness.true_peak_db()
+    )
+}
+
+/// The master bus correlation and the lowest it went lately.
+pub fn describe_phase(phase: &PhaseLevels) -> String {
+    if !phase.stereo() {
+        return "No phase on a mono output".to_string();
+    }
+    format!(
+        "Phase {:+.2}, lowest {:+.2}{}",
+        phase.correlation(),
+        phase.lowest(),
+        if phase.lowest() < 0.0 {
+            ", cancelling in mono"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Every stage that clipped since the counters were cleared, the earliest
+/// in the signal path first, since it may be what drives the later ones.
+pub fn describe_clips(controls: &Controls) -> String {
+    let clips = controls.params.clips();
+    let held = clips.held();
+    if held.is_empty() {
+        return format!("No clipping at {} stages", clips.all().len());
+    }
+    held.iter()
+        .map(|stats| stats.describe())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The tuner's channel and note, the TUI's tuner title.
+pub fn describe_tuner(controls: &Controls) -> String {
+    let tuner = &controls.tuner;
+    let Some(index) = tuner.channel() else {
+        return "Tuner off".to_string();
+    };
+    let name = controls
+        .channels
+        .get(index)
+        .map_or("", |channel| channel.name.as_str());
+    let note = match tuner.reading() {
+        Some(reading) => format!(
+            "{} {:.1} Hz {:+.0} cents",
+            reading.name(),
+            reading.frequency,
+            reading.cents
+        ),
+        None => "no pitch".to_string(),
+    };
+    format!(
+        "Tuner on channel {} ({}), A4 = {:.0} Hz: {}",
+        index + 1,
+        name,
+        tuner.reference(),
+        note
+    )
+}
+
+/// The scope's channel, sweep and levels, the TUI's scope title.
+pub fn describe_scope(controls: &Controls) -> String {
+    let view = &controls.scope;
+    let Some(index) = view.channel() else {
+        return "Scope off".to_string();
+    };
+    let name = controls
+        .channels
+        .get(index)
+        .map_or("", |channel| channel.name.as_str());
+    let trigger = match view.trigger() {
+        Some(level) => format!("trigger {:+.2} rising", level),
+        None => "free-running".to_string(),
+    };
+    let levels = match view.sweep() {
+        Some(sweep) => format!(
+            "peak {:.1} dBFS, DC {:+.2}%{}{}",
+            sweep.peak_db,
+            sweep.dc * 100.0,
+            if sweep.clipped > 0 {
+                format!(", {} samples clipped", sweep.clipped)
+            } else {
+                String::new()
+            },
+            if view.trigger().is_some() && !sweep.triggered {
+                ", not triggered"
+            } else {
+                ""
+            }
+        ),
+        None => "no signal yet".to_string(),
+    };
+    format!(
+        "Scope on channel {} ({}), {:.0} ms, {}: {}",
+        index + 1,
+        name,
+        view.time_ms(),
+        trigger,
+        levels
+    )
+}
+
+/// The channels the transfer function is measured on and their delay, the
+/// TUI's analyzer title while it's on.
+pub fn describe_transfer(controls: &Controls) -> String {
+    let transfer = &controls.transfer;
+    let Some((reference, measurement)) = transfer.channels() else {
+        return "Transfer function off".to_string();
+    };
+    let name = |index: usize| {
+        controls
+            .channels
+            .get(index)
+            .map_or("", |channel| channel.name.as_str())
+    };
+    format!(
+        "Transfer function from channel {} ({}) to channel {} ({}), {}",
+        reference + 1,
+        name(reference),
+        measurement + 1,
+        name(measurement),
+        match transfer.delay_ms() {
+            Some(delay) => format!("delay {:.2} ms", delay),
+            None => "finding the delay".to_string(),
+        }
+    )
+}
+
+pub fn describe_player(index: usize, player: &Transport) -> String {
+    format!(
+        "[play{}] {}: {} {} / {}{}",
+        index + 1,
+        player.name,
+        if player.playing() {
+            "playing"
+        } else {
+            "paused"
+        },
+        format_time(player.position_seconds()),
+        format_time(player.length_seconds()),
+        if player.looping() { ", loop" } else { "" }
+    )
+}
+
+/// `m:ss.s`
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0);
+    format!("{}:{:04.1}", (seconds / 60.0) as u32, seconds % 60.0)
+}
+
+fn describe_param(param: &Param) -> String {
+    format!(
+        "{} = {} ({}..{})",
+        param.key,
+        param.get(),
+        param.min,
+        param.max
+    )
+}
+
+/// Mixer channels are numbered from 1 on the console.
+fn parse_channel(text: &str) -> Result<usize> {
+    let number: usize = text
+        .parse()
+        .with_context(|| format!("Invalid channel '{}'", text))?;
+    if number == 0 {
+        bail!("Channel numbers start at 1");
+    }
+    Ok(number - 1)
+}
+
+fn parse_state(text: &str) -> Result<bool> {
+    match text {
+        "on" | "1" => Ok(true),
+        "off" | "0" => Ok(false),
+        _ => bail!("Expected on or off, got '{}'", text),
+    }
+}
+
+/// Aux buses are numbered from 1 too, with an optional `aux` prefix.
+fn parse_aux(text: &str) -> Result<usize> {
+    parse_channel(text.strip_prefix("aux").unwrap_or(text))
+        .with_context(|| format!("Invalid aux bus '{}'", text))
+}
+
+/// File players are numbered from 1, with an optional `play` prefix.
+fn parse_player(text: &str) -> Result<usize> {
+    parse_channel(text.strip_prefix("play").unwrap_or(text))
+        .with_context(|| format!("Invalid file player '{}'", text))
+}
+
+/// Scenes keep the number they're stored under, from 1.
+fn parse_scene(text: &str) -> Result<usize> {
+    text.parse()
+        .with_context(|| format!("Invalid scene '{}'", text))
+}
+
+/// Seconds, or minutes and seconds as `m:ss`.
+pub fn parse_time(text: &str) -> Result<f32> {
+    match text.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: u32 = minutes
+                .parse()
+                .with_context(|| format!("Invalid time '{}'", text))?;
+            Ok(minutes as f32 * 60.0 + parse_value(seconds)?)
+        }
+        None => parse_value(text),
+    }
+}
+
+fn parse_value(text: &str) -> Result<f32> {
+    text.parse()
+        .with_context(|| format!("Invalid value '{}'", text))
+}
+
+/// Applies `command`. Profiles switch devices when given the supervisor,
+/// which only the control loop holds.
+pub fn execute(
+    command: &Command,
+    controls: &Controls,
+    supervisor: Option<&mut Supervisor>,
+) -> Result<String> {
+    match command {
+        Command::Profile(name) => profile::switch(
+            profile::find(&controls.profiles, name)?,
+            controls,
+            supervisor,
+        ),
+        Command::CueGo => Ok(run_cue(controls.cues.go()?, controls, supervisor)),
+        Command::Cue(name) => Ok(run_cue(
+            controls.cues.fire_named(name)?,
+            controls,
+            supervisor,
+        )),
+        command => command.apply(controls),
+    }
+}
+
+/// Runs a fired cue's commands, carrying on past one that fails so the
+/// rest of the cue still happens. Returns a line per command.
+fn run_cue(
+    (label, commands): (String, Vec<Command>),
+    controls: &Controls,
+    mut supervisor: Option<&mut Supervisor>,
+) -> String {
+    let mut lines = vec![label];
+    for command in &commands {
+        match execute(command, controls, supervisor.as_deref_mut()) {
+            Ok(line) => lines.push(line),
+            Err(err) => lines.push(format!("{}", err)),
+        }
+    }
+    lines.join("\n")
+}
+
+/// Has the control loop apply `command` and waits for the result, for the
+/// threads that answer a client.
+pub fn send(remote: &mpsc::Sender<Request>, command: Command) -> Result<String> {
+    let (reply, result) = mpsc::channel();
+    remote
+        .send(Request {
+            command,
+            reply: Some(reply),
+        })
+        .map_err(|_| anyhow!("The control loop has stopped"))?;
+    result
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| anyhow!("The control loop didn't answer"))?
+}
+
+/// Applies the commands other threads sent since the last call, moves the
+/// fades along, fires the cues the cue clock passed and notes the changes
+/// for undo.
+pub fn serve_requests(controls: &Controls, mut supervisor: Option<&mut Supervisor>) {
+    controls.history.poll(controls);
+    controls.fades.step(controls);
+    for cue in controls.cues.due() {
+        println!("{}", run_cue(cue, controls, supervisor.as_deref_mut()));
+    }
+    while let Ok(request) = controls.requests.try_recv() {
+        // Quitting is left to the console and the TUI
+        let result = match request.command {
+            Command::Quit => Ok(String::new()),
+            ref command => execute(command, controls, supervisor.as_deref_mut()),
+        };
+        if let Some(reply) = request.reply {
+            let _ = reply.send(result);
+        }
+    }
+}
+
+/// Runs without a console for `daemon`: looks after the streams and
+/// applies the commands other threads send until a shutdown signal, or
+/// `quit` over the control socket. Pings systemd's watchdog on the way,
+/// while the output callback runs.
+pub fn run_headless(controls: &Controls, mut supervisor: Option<&mut Supervisor>) -> Result<()> {
+    let mut watchdog = Watchdog::from_env(controls.load.clone());
+    while !shutdown::requested() {
+        if let Some(supervisor) = supervisor.as_mut() {
+            supervisor.poll();
+        }
+        serve_requests(controls, supervisor.as_deref_mut());
+        watchdog.poll();
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Reads commands from stdin until `quit` or end of input, looking after
+/// the streams and the commands sent from other threads in between.
+pub fn run_console(controls: &Controls, mut supervisor: Option<&mut Supervisor>) -> Result<()> {
+    // Stdin is read on its own thread so a shutdown signal can end the
+    // loop while it waits for a line
+    let (sender, lines) = mpsc::channel();
+    thread::Builder::new()
+        .name("console".to_string())
+        .spawn(move || {
+            for line in io::stdin().lock().lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        })?;
+
+    while !shutdown::requested() {
+        if let Some(supervisor) = supervisor.as_mut() {
+            supervisor.poll();
+        }
+        serve_requests(controls, supervisor.as_deref_mut());
+        // Short enough that a fader on a controller doesn't lag
+        let line = match lines.recv_timeout(Duration::from_millis(20)) {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match Command::parse(&line) {
+            Ok(Command::Quit) => break,
+            Ok(command) => match execute(&command, controls, supervisor.as_deref_mut()) {
+                Ok(status) => println!("{}", status),
+                Err(err) => println!("{}", err),
+            },
+            Err(err) => println!("{}", err),
+        }
+    }
+    Ok(())
+}
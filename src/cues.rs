@@ -0,0 +1,295 @@
+use crate::control::{self, Command};
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A group of console commands fired together, by hand or at a time on
+/// the cue clock.
+///
+/// Cue lists live in a plain text file. A `[name]` line starts a cue fired
+/// by hand, `[m:ss name]` one the clock fires too, and every other line is
+/// a console command. `fade` takes a setting somewhere over a few seconds:
+///
+/// ```text
+/// [intro]
+/// play 1
+/// scene 1
+///
+/// [2:30 reverb]
+/// set reverb.bypass 0
+///
+/// [9:50 outro]
+/// fade channel.3.gain -60 10
+/// ```
+///
+/// `cue` fires the cue on standby, the first one not fired yet, and `cue
+/// <name>` any cue. `cues start` runs the clock, which fires each timed
+/// cue as it passes. Started from a later time, the cues before it are
+/// passed over. Blank lines and lines starting with `#` are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cue {
+    pub name: String,
+    /// Seconds on the clock, `None` for a cue fired by hand only.
+    pub at: Option<f32>,
+    pub commands: Vec<String>,
+}
+
+impl Cue {
+    /// Its time and name as written.
+    fn label(&self) -> String {
+        match self.at {
+            Some(at) => format!("{} {}", format_clock(at), self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Where the cue list is at.
+#[derive(Debug, Default)]
+struct Playback {
+    fired: Vec<bool>,
+    /// Clock reading when it was last stopped or started.
+    offset: f32,
+    /// When the clock was started, `None` while it's stopped.
+    started: Option<Instant>,
+    /// Clock reading the timed cues have been fired up to.
+    checked: f32,
+}
+
+impl Playback {
+    fn clock(&self) -> f32 {
+        self.offset
+            + self
+                .started
+                .map_or(0.0, |started| started.elapsed().as_secs_f32())
+    }
+
+    /// The first cue not fired yet.
+    fn standby(&self) -> Option<usize> {
+        self.fired.iter().position(|fired| !fired)
+    }
+}
+
+/// The cues from `--cues`, kept by the control loop.
+#[derive(Debug, Default)]
+pub struct CueList {
+    cues: Vec<Cue>,
+    playback: Mutex<Playback>,
+}
+
+impl CueList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cues from {}", path.display()))?;
+        let cues = parse(&text).with_context(|| format!("Invalid cues in {}", path.display()))?;
+        Ok(CueList {
+            playback: Mutex::new(Playback {
+                fired: vec![false; cues.len()],
+                ..Playback::default()
+            }),
+            cues,
+        })
+    }
+
+    /// One line per cue, the one on standby marked, then the clock.
+    pub fn describe(&self) -> Result<String> {
+        self.check()?;
+        let playback = self.playback.lock().unwrap();
+        let standby = playback.standby();
+        let mut lines: Vec<String> = self
+            .cues
+            .iter()
+            .enumerate()
+            .map(|(index, cue)| {
+                format!(
+                    "{}[{}] {}: {} commands{}",
+                    if standby == Some(index) { "> " } else { "  " },
+                    index + 1,
+                    cue.label(),
+                    cue.commands.len(),
+                    if playback.fired[index] { ", fired" } else { "" }
+                )
+            })
+            .collect();
+        lines.push(describe_clock(&playback));
+        Ok(lines.join("\n"))
+    }
+
+    /// Fires the cue on standby.
+    pub fn go(&self) -> Result<(String, Vec<Command>)> {
+        self.check()?;
+        let index = self
+            .playback
+            .lock()
+            .unwrap()
+            .standby()
+            .context("Every cue has been fired, 'cues reset' starts over")?;
+        Ok(self.fire(index))
+    }
+
+    /// Fires the cue called `name`, or the one numbered so from 1. Every
+    /// cue before it counts as fired, so the one after is on standby.
+    pub fn fire_named(&self, name: &str) -> Result<(String, Vec<Command>)> {
+        self.check()?;
+        let index = self
+            .cues
+            .iter()
+            .position(|cue| cue.name == name)
+            .or_else(|| {
+                let number: usize = name.parse().ok()?;
+                (1..=self.cues.len()).contains(&number).then(|| number - 1)
+            })
+            .with_context(|| format!("No cue '{}', type 'cues' for the list", name))?;
+        self.playback.lock().unwrap().fired[..index].fill(true);
+        Ok(self.fire(index))
+    }
+
+    /// Runs the clock from `from` seconds, or on from where it stopped.
+    pub fn start(&self, from: Option<f32>) -> Result<String> {
+        self.check()?;
+        let mut playback = self.playback.lock().unwrap();
+        let clock = from.unwrap_or_else(|| playback.clock()).max(0.0);
+        if let Some(from) = from {
+            // Passed over, not fired
+            for (fired, cue) in playback.fired.iter_mut().zip(&self.cues) {
+                *fired = cue.at.map_or(*fired, |at| at < from);
+            }
+        }
+        playback.offset = clock;
+        playback.checked = clock;
+        playback.started = Some(Instant::now());
+        Ok(describe_clock(&playback))
+    }
+
+    pub fn stop(&self) -> Result<String> {
+        self.check()?;
+        let mut playback = self.playback.lock().unwrap();
+        playback.offset = playback.clock();
+        playback.started = None;
+        Ok(describe_clock(&playback))
+    }
+
+    /// Stops the clock at 0 and puts the first cue back on standby.
+    pub fn reset(&self) -> Result<String> {
+        self.check()?;
+        let mut playback = self.playback.lock().unwrap();
+        *playback = Playback {
+            fired: vec![false; self.cues.len()],
+            ..Playback::default()
+        };
+        Ok(describe_clock(&playback))
+    }
+
+    /// The timed cues the clock passed since the last call, to be run by
+    /// the control loop.
+    pub fn due(&self) -> Vec<(String, Vec<Command>)> {
+        let mut due = Vec::new();
+        {
+            let mut playback = self.playback.lock().unwrap();
+            if playback.started.is_none() {
+                return Vec::new();
+            }
+            let clock = playback.clock();
+            for (index, cue) in self.cues.iter().enumerate() {
+                if let Some(at) = cue.at
+                    && at >= playback.checked
+                    && at < clock
+                    && !playback.fired[index]
+                {
+                    due.push(index);
+                }
+            }
+            playback.checked = clock;
+        }
+        due.into_iter().map(|index| self.fire(index)).collect()
+    }
+
+    /// Marks cue `index` fired and returns its label and commands.
+    fn fire(&self, index: usize) -> (String, Vec<Command>) {
+        self.playback.lock().unwrap().fired[index] = true;
+        let cue = &self.cues[index];
+        let commands = cue
+            .commands
+            .iter()
+            .filter_map(|line| Command::parse(line).ok())
+            .collect();
+        (format!("Cue {}", cue.label()), commands)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.cues.is_empty() {
+            bail!("No cues loaded, pass --cues <FILE>");
+        }
+        Ok(())
+    }
+}
+
+fn describe_clock(playback: &Playback) -> String {
+    format!(
+        "Cue clock {}{}",
+        format_clock(playback.clock()),
+        if playback.started.is_some() {
+            ", running"
+        } else {
+            ", stopped"
+        }
+    )
+}
+
+/// `m:ss`
+fn format_clock(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+pub fn parse(text: &str) -> Result<Vec<Cue>> {
+    let mut cues: Vec<Cue> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let words: Vec<&str> = header.split_whitespace().collect();
+            let time = |text: &str| {
+                control::parse_time(text).with_context(|| format!("Line {}", number + 1))
+            };
+            let (at, name) = match words.as_slice() {
+                [at, name] if at.contains(':') => (Some(time(at)?), *name),
+                [at] if at.contains(':') => (Some(time(at)?), *at),
+                [name] => (None, *name),
+                _ => bail!(
+                    "Line {}: expected [name] or [m:ss name], names can't hold spaces",
+                    number + 1
+                ),
+            };
+            if cues.iter().any(|c| c.name == name) {
+                bail!("Line {}: cue {} is defined twice", number + 1, name);
+            }
+            cues.push(Cue {
+                name: name.to_string(),
+                at,
+                commands: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(cue) = cues.last_mut() else {
+            bail!("Line {}: expected a [cue] line first", number + 1);
+        };
+        // Checked now so a typo shows up at startup, not mid-show
+        match Command::parse(line).with_context(|| format!("Line {}", number + 1))? {
+            Command::Cues
+            | Command::CueGo
+            | Command::Cue(_)
+            | Command::CueStart(_)
+            | Command::CueStop
+            | Command::CueReset
+            | Command::Quit => bail!("Line {}: '{}' can't be used in a cue", number + 1, line),
+            _ => cue.commands.push(line.to_string()),
+        }
+    }
+    Ok(cues)
+}
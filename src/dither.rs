@@ -0,0 +1,85 @@
+use anyhow::{Result, bail};
+
+/// Error feedback filter of the noise shaping, Wannamaker's 3-tap
+/// F-weighted curve. It moves the dither noise up out of the 2-5 kHz
+/// region the ear is most sensitive to.
+const SHAPING: [f32; 3] = [1.623, -0.982, 0.109];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMode {
+    /// Rounded to the output's resolution with nothing added.
+    Off,
+    /// Triangular dither of one step peak, which makes the rounding error
+    /// plain noise instead of distortion that follows the signal.
+    Tpdf,
+    /// TPDF with the rounding error fed back through `SHAPING`.
+    Shaped,
+}
+
+impl DitherMode {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mode = match text.trim() {
+            "off" => DitherMode::Off,
+            "tpdf" => DitherMode::Tpdf,
+            "shaped" => DitherMode::Shaped,
+            other => bail!("Unknown dither '{}', expected off, tpdf or shaped", other),
+        };
+        Ok(mode)
+    }
+}
+
+/// Prepares float samples for an integer output of `bits`, leaving them
+/// exactly on its steps so the conversion after it has nothing to
+/// truncate.
+pub struct Dither {
+    mode: DitherMode,
+    /// One step of the output, in full scale.
+    step: f32,
+    rng: u32,
+    /// Last rounding errors per channel, newest first.
+    errors: Vec<[f32; 3]>,
+}
+
+impl Dither {
+    pub fn new(mode: DitherMode, bits: u32, channels: usize) -> Self {
+        Dither {
+            mode,
+            step: 1.0 / (1u64 << (bits - 1)) as f32,
+            rng: 0x2545_f491,
+            errors: vec![[0.0; 3]; channels.max(1)],
+        }
+    }
+
+    pub fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let step = self.step;
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, errors) in frame.iter_mut().zip(self.errors.iter_mut()) {
+                let mut wanted = *sample;
+                if self.mode == DitherMode::Shaped {
+                    wanted -=
+                        SHAPING[0] * errors[0] + SHAPING[1] * errors[1] + SHAPING[2] * errors[2];
+                }
+                let noise = match self.mode {
+                    DitherMode::Off => 0.0,
+                    _ => (uniform(&mut self.rng) - uniform(&mut self.rng)) * step,
+                };
+                // Kept a step inside full scale so the conversion can't wrap
+                let out = (((wanted + noise) / step).round() * step).clamp(-1.0, 1.0 - step);
+                if self.mode == DitherMode::Shaped {
+                    // Clipping isn't fed back, it would only ring
+                    let error = (out - wanted).clamp(-2.0 * step, 2.0 * step);
+                    *errors = [error, errors[0], errors[1]];
+                }
+                *sample = out;
+            }
+        }
+    }
+}
+
+/// Uniform in `[0, 1)` from a xorshift state.
+fn uniform(rng: &mut u32) -> f32 {
+    *rng ^= *rng << 13;
+    *rng ^= *rng >> 17;
+    *rng ^= *rng << 5;
+    *rng as f32 / (u32::MAX as f32 + 1.0)
+}
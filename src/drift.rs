@@ -0,0 +1,49 @@
+/// Time the proportional term takes to pull the fill level back to target.
+const SETTLE_SECONDS: f64 = 4.0;
+/// Largest correction applied, as a fraction of the rate. Clocks further
+/// apart than this are left to the jitter buffer's drop and insert.
+const MAX_CORRECTION: f64 = 0.01;
+
+/// Estimates the rate mismatch between an input and the output clock from
+/// the fill level of the ring between them.
+///
+/// A PI controller turns the level's distance from target into a rate
+/// correction for the input resampler. The integral term converges on the
+/// actual clock drift, the proportional term works off what has piled up or
+/// drained in the meantime. The integral time is four times the settling
+/// time, which damps the loop critically.
+#[derive(Clone, Debug)]
+pub struct DriftEstimator {
+    /// Correction per frame of level error.
+    gain: f64,
+    /// Seconds between updates.
+    interval: f64,
+    drift: f64,
+}
+
+impl DriftEstimator {
+    /// `interval` is the time between calls to `update`.
+    pub fn new(sample_rate: u32, interval: f64) -> Self {
+        DriftEstimator {
+            gain: 1.0 / (SETTLE_SECONDS * sample_rate.max(1) as f64),
+            interval,
+            drift: 0.0,
+        }
+    }
+
+    /// Takes the level's distance from target in frames, positive when the
+    /// input is ahead, and returns the correction to apply: the fraction
+    /// the input resampler should speed up by.
+    pub fn update(&mut self, error: f64) -> f64 {
+        let integral_time = SETTLE_SECONDS * 4.0;
+        self.drift += self.gain * error * self.interval / integral_time;
+        self.drift = self.drift.clamp(-MAX_CORRECTION, MAX_CORRECTION);
+        (self.drift + self.gain * error).clamp(-MAX_CORRECTION, MAX_CORRECTION)
+    }
+
+    /// Estimated drift of the input against the output, as a fraction of
+    /// the rate.
+    pub fn drift(&self) -> f64 {
+        self.drift
+    }
+}
@@ -0,0 +1,109 @@
+use super::{Processor, db_to_gain, gain_to_db, time_coefficient};
+use crate::params::ParamInfo;
+
+/// Level is measured over about this long, so words, not syllables,
+/// move the gain.
+const DETECTOR_MS: f32 = 400.0;
+/// Gain slew. It comes down quicker than it goes up so a singer stepping
+/// up to the mic doesn't overload the compressor for long.
+const RISE_DB_PER_SECOND: f32 = 3.0;
+const FALL_DB_PER_SECOND: f32 = 10.0;
+/// Most the level is turned down.
+const MAX_CUT_DB: f32 = 24.0;
+
+/// Automatic gain control, a slow leveler for a mic whose distance
+/// changes. The RMS level is steered towards `target`, boosting by at
+/// most `max_gain` dB. Below `gate` the input is taken as a pause and the
+/// gain is held, so the room isn't pulled up between phrases. Fast peaks
+/// are left to the compressor after it.
+pub struct AutoGain {
+    sample_rate: f32,
+    target_db: f32,
+    max_gain_db: f32,
+    gate_db: f32,
+
+    detector_coeff: f32,
+    /// Smoothed mean square of the input, all channels together.
+    power: f32,
+    gain_db: f32,
+}
+
+impl AutoGain {
+    pub fn new(sample_rate: f32) -> Self {
+        AutoGain {
+            sample_rate,
+            target_db: -18.0,
+            max_gain_db: 12.0,
+            gate_db: -50.0,
+            detector_coeff: time_coefficient(DETECTOR_MS, sample_rate),
+            power: 0.0,
+            gain_db: 0.0,
+        }
+    }
+
+    /// RMS level aimed for, in dBFS.
+    pub fn set_target(&mut self, target_db: f32) {
+        self.target_db = target_db.clamp(-40.0, -6.0);
+    }
+
+    pub fn set_max_gain(&mut self, max_gain_db: f32) {
+        self.max_gain_db = max_gain_db.clamp(0.0, 30.0);
+    }
+
+    /// Level under which the gain is held, in dBFS.
+    pub fn set_gate(&mut self, gate_db: f32) {
+        self.gate_db = gate_db.clamp(-80.0, -30.0);
+    }
+}
+
+impl Processor for AutoGain {
+    fn name(&self) -> &'static str {
+        "agc"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let rise = RISE_DB_PER_SECOND / self.sample_rate;
+        let fall = FALL_DB_PER_SECOND / self.sample_rate;
+
+        for frame in buffer.chunks_mut(channels) {
+            let square = frame.iter().map(|s| s * s).sum::<f32>() / channels as f32;
+            self.power = self.detector_coeff * self.power + (1.0 - self.detector_coeff) * square;
+
+            let level_db = gain_to_db(self.power.sqrt());
+            if level_db > self.gate_db {
+                let wanted = (self.target_db - level_db).clamp(-MAX_CUT_DB, self.max_gain_db);
+                self.gain_db += (wanted - self.gain_db).clamp(-fall, rise);
+            } else {
+                // Held, but a lowered max gain still applies
+                self.gain_db = self.gain_db.min(self.max_gain_db);
+            }
+
+            let gain = db_to_gain(self.gain_db);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.power = 0.0;
+        self.gain_db = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("target", self.target_db, -40.0, -6.0),
+            ParamInfo::new("max_gain", self.max_gain_db, 0.0, 30.0),
+            ParamInfo::new("gate", self.gate_db, -80.0, -30.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "target" => self.set_target(value),
+            "max_gain" => self.set_max_gain(value),
+            "gate" => self.set_gate(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use super::Processor;
+use crate::params::ParamInfo;
+use anyhow::{Context, Result, bail};
+
+/// Longest alignment delay, a delay tower 170 m from the stage.
+pub const MAX_ALIGN_MS: f32 = 500.0;
+/// Speed of sound at 20 °C, for delays given as a distance.
+const SPEED_OF_SOUND: f32 = 343.0;
+
+/// Parses an alignment delay, `2.5ms`, `1.2m` or a bare number of
+/// milliseconds, into milliseconds.
+pub fn parse_delay(text: &str) -> Result<f32> {
+    let text = text.trim();
+    let ms = if let Some(ms) = text.strip_suffix("ms") {
+        ms.trim().parse::<f32>()
+    } else if let Some(meters) = text.strip_suffix('m') {
+        meters
+            .trim()
+            .parse::<f32>()
+            .map(|m| m / SPEED_OF_SOUND * 1000.0)
+    } else {
+        text.parse::<f32>()
+    }
+    .with_context(|| format!("Invalid delay '{}', expected e.g. 2.5ms or 1.2m", text))?;
+    if !(0.0..=MAX_ALIGN_MS).contains(&ms) {
+        bail!("Delay must be 0 to {} ms, got {:.2}", MAX_ALIGN_MS, ms);
+    }
+    Ok(ms)
+}
+
+/// Delay line read between samples with 3rd order Lagrange
+/// interpolation, so a delay lines up to a fraction of a sample instead
+/// of the nearest one, 7 mm at 48 kHz.
+pub struct FractionalDelay {
+    line: Vec<f32>,
+    position: usize,
+    delay: f32,
+}
+
+impl FractionalDelay {
+    pub fn new(max_samples: usize) -> Self {
+        FractionalDelay {
+            line: vec![0.0; max_samples + 4],
+            position: 0,
+            delay: 0.0,
+        }
+    }
+
+    pub fn set_delay(&mut self, samples: f32) {
+        self.delay = samples.clamp(0.0, (self.line.len() - 4) as f32);
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let len = self.line.len();
+        self.line[self.position] = x;
+        // Four taps around the delay, starting at the newest sample when
+        // it's under one
+        let first = (self.delay.floor() as usize).saturating_sub(1);
+        let t = self.delay - first as f32;
+        let tap = |k: usize| self.line[(self.position + len - first - k) % len];
+        let y = -(t - 1.0) * (t - 2.0) * (t - 3.0) / 6.0 * tap(0)
+            + t * (t - 2.0) * (t - 3.0) / 2.0 * tap(1)
+            - t * (t - 1.0) * (t - 3.0) / 2.0 * tap(2)
+            + t * (t - 1.0) * (t - 2.0) / 6.0 * tap(3);
+        self.position = (self.position + 1) % len;
+        y
+    }
+
+    pub fn reset(&mut self) {
+        self.line.fill(0.0);
+    }
+}
+
+struct ChannelDelay {
+    channel: usize,
+    ms: f32,
+    name: &'static str,
+    line: FractionalDelay,
+}
+
+/// Per output channel delay for time-aligning delay speakers or a sub to
+/// the mains, in the output stage just ahead of the limiter, which
+/// catches what the interpolation overshoots.
+pub struct Alignment {
+    sample_rate: f32,
+    channels: Vec<ChannelDelay>,
+}
+
+impl Alignment {
+    /// Takes `(channel, ms)` pairs with channels counted from 0.
+    pub fn new(delays: &[(usize, f32)], sample_rate: f32) -> Self {
+        let max_samples = (MAX_ALIGN_MS * sample_rate / 1000.0).ceil() as usize;
+        let channels = delays
+            .iter()
+            .map(|&(channel, ms)| {
+                let name: &'static str = Box::leak(format!("out{}", channel + 1).into_boxed_str());
+                let mut line = FractionalDelay::new(max_samples);
+                line.set_delay(ms * sample_rate / 1000.0);
+                ChannelDelay {
+                    channel,
+                    ms,
+                    name,
+                    line,
+                }
+            })
+            .collect();
+        Alignment {
+            sample_rate,
+            channels,
+        }
+    }
+}
+
+impl Processor for Alignment {
+    fn name(&self) -> &'static str {
+        "align"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        for frame in buffer.chunks_mut(channels) {
+            for delay in self.channels.iter_mut() {
+                if let Some(sample) = frame.get_mut(delay.channel) {
+                    *sample = delay.line.process(*sample);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for delay in self.channels.iter_mut() {
+            delay.line.reset();
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        self.channels
+            .iter()
+            .map(|delay| ParamInfo::new(delay.name, delay.ms, 0.0, MAX_ALIGN_MS))
+            .collect()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let sample_rate = self.sample_rate;
+        if let Some(delay) = self.channels.iter_mut().find(|d| d.name == name) {
+            delay.ms = value.clamp(0.0, MAX_ALIGN_MS);
+            delay.line.set_delay(delay.ms * sample_rate / 1000.0);
+        }
+    }
+}
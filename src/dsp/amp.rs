@@ -0,0 +1,264 @@
+use super::biquad::{BUTTERWORTH_Q, Biquad, Coefficients};
+use super::conditioning::DcBlocker;
+use super::convolution::{Convolver, read_wav_channels};
+use super::saturation::Curve;
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use anyhow::Result;
+use std::path::Path;
+use tracing::info;
+
+pub const MAX_STAGES: usize = 4;
+/// Cabinet responses are short, longer files are room tails that only
+/// cost CPU.
+const CABINET_MAX_SECONDS: f32 = 0.2;
+/// Cabinet convolver block, kept small for playing through it.
+const CABINET_BLOCK: usize = 64;
+/// Coupling high-pass in front of each stage, tightening the low end
+/// before it's clipped.
+const STAGE_HIGH_PASS: f32 = 120.0;
+/// Low-pass after each stage, taking the fizz off the harmonics.
+const STAGE_LOW_PASS: f32 = 6500.0;
+/// Tone stack corners: shelves for bass and treble, a peak for mid.
+const BASS_HZ: f32 = 100.0;
+const MID_HZ: f32 = 800.0;
+const MID_Q: f32 = 0.7;
+const TREBLE_HZ: f32 = 3200.0;
+/// Speaker band of the built-in cabinet, used without an IR.
+const SPEAKER_LOW: f32 = 80.0;
+const SPEAKER_HIGH: f32 = 4500.0;
+
+/// Filter states of one channel.
+#[derive(Clone, Copy, Default)]
+struct AmpChannel {
+    /// Coupling high-pass and fizz low-pass of each stage.
+    stages: [[Biquad; 2]; MAX_STAGES],
+    /// Bass, mid and treble.
+    tone: [Biquad; 3],
+    /// High-pass and two low-passes of the built-in cabinet.
+    speaker: [Biquad; 3],
+}
+
+/// Guitar amp channel strip for playing through an interface: `input`
+/// gain into 1 to 4 tube-curve distortion stages sharing `drive`, a
+/// `bass`/`mid`/`treble` tone stack and a cabinet, a loaded impulse
+/// response or a built-in speaker band when there's none. `trim` sets
+/// the level coming out. The cabinet IR adds 64 frames of latency, 1.3 ms
+/// at 48 kHz.
+pub struct AmpSim {
+    sample_rate: f32,
+    stage_count: usize,
+    input_db: f32,
+    drive_db: f32,
+    bass_db: f32,
+    mid_db: f32,
+    treble_db: f32,
+    trim_db: f32,
+    input: f32,
+    /// Gain into each stage.
+    stage_gain: f32,
+    trim: f32,
+
+    stage_coeffs: [Coefficients; 2],
+    tone_coeffs: [Coefficients; 3],
+    speaker_coeffs: [Coefficients; 3],
+    channels: Vec<AmpChannel>,
+    /// Takes out the offset the tube curve's asymmetry leaves.
+    dc: DcBlocker,
+    /// Mono cabinet response and its convolver per channel.
+    cabinet: Option<Vec<f32>>,
+    convolvers: Vec<Convolver>,
+}
+
+impl AmpSim {
+    pub fn new(stages: usize, sample_rate: f32) -> Self {
+        let mut amp = AmpSim {
+            sample_rate,
+            stage_count: stages.clamp(1, MAX_STAGES),
+            input_db: 0.0,
+            drive_db: 24.0,
+            bass_db: 0.0,
+            mid_db: 0.0,
+            treble_db: 0.0,
+            trim_db: -6.0,
+            input: 1.0,
+            stage_gain: 1.0,
+            trim: 1.0,
+            stage_coeffs: [
+                Coefficients::high_pass(STAGE_HIGH_PASS, BUTTERWORTH_Q, sample_rate),
+                Coefficients::low_pass(STAGE_LOW_PASS, BUTTERWORTH_Q, sample_rate),
+            ],
+            tone_coeffs: [Coefficients::default(); 3],
+            speaker_coeffs: [
+                Coefficients::high_pass(SPEAKER_LOW, BUTTERWORTH_Q, sample_rate),
+                Coefficients::low_pass(SPEAKER_HIGH, BUTTERWORTH_Q, sample_rate),
+                Coefficients::low_pass(SPEAKER_HIGH, BUTTERWORTH_Q, sample_rate),
+            ],
+            channels: Vec::new(),
+            dc: DcBlocker::new(sample_rate),
+            cabinet: None,
+            convolvers: Vec::new(),
+        };
+        amp.set_drive(amp.drive_db);
+        amp.set_trim(amp.trim_db);
+        amp.update_tone();
+        amp
+    }
+
+    /// Loads a cabinet impulse response, mixed down to mono and
+    /// normalised to unit energy like the reverb's.
+    pub fn load_cabinet(&mut self, path: &Path) -> Result<()> {
+        let channels = read_wav_channels(path, self.sample_rate)?;
+        let length = channels
+            .iter()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+            .min((CABINET_MAX_SECONDS * self.sample_rate) as usize)
+            .max(1);
+        let mut ir = vec![0.0; length];
+        for channel in &channels {
+            for (mixed, &sample) in ir.iter_mut().zip(channel) {
+                *mixed += sample / channels.len() as f32;
+            }
+        }
+        let energy: f32 = ir.iter().map(|s| s * s).sum();
+        if energy > 0.0 {
+            let scale = 1.0 / energy.sqrt();
+            ir.iter_mut().for_each(|s| *s *= scale);
+        }
+        info!(
+            "Loaded cabinet {}: {:.0} ms",
+            path.display(),
+            ir.len() as f32 * 1000.0 / self.sample_rate
+        );
+        self.cabinet = Some(ir);
+        self.convolvers.clear();
+        Ok(())
+    }
+
+    pub fn set_input(&mut self, input_db: f32) {
+        self.input_db = input_db.clamp(-24.0, 24.0);
+        self.input = db_to_gain(self.input_db);
+    }
+
+    /// Total gain into the stages, split evenly between them.
+    pub fn set_drive(&mut self, drive_db: f32) {
+        self.drive_db = drive_db.clamp(0.0, 48.0);
+        self.stage_gain = db_to_gain(self.drive_db / self.stage_count as f32);
+    }
+
+    pub fn set_trim(&mut self, trim_db: f32) {
+        self.trim_db = trim_db.clamp(-24.0, 12.0);
+        self.trim = db_to_gain(self.trim_db);
+    }
+
+    fn update_tone(&mut self) {
+        let rate = self.sample_rate;
+        self.tone_coeffs = [
+            Coefficients::low_shelf(BASS_HZ, self.bass_db, rate),
+            Coefficients::peaking(MID_HZ, MID_Q, self.mid_db, rate),
+            Coefficients::high_shelf(TREBLE_HZ, self.treble_db, rate),
+        ];
+    }
+}
+
+impl Processor for AmpSim {
+    fn name(&self) -> &'static str {
+        "amp"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels.len() < channels {
+            self.channels.resize(channels, AmpChannel::default());
+        }
+        self.dc.prepare(channels);
+        if let Some(ir) = &self.cabinet {
+            while self.convolvers.len() < channels {
+                self.convolvers.push(Convolver::new(ir, CABINET_BLOCK));
+            }
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let stages = self.stage_count;
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(&mut self.channels) {
+                let mut x = *sample * self.input;
+                for [high_pass, low_pass] in &mut state.stages[..stages] {
+                    x = high_pass.process(&self.stage_coeffs[0], x);
+                    x = Curve::Tube.shape(x * self.stage_gain);
+                    x = low_pass.process(&self.stage_coeffs[1], x);
+                }
+                *sample = x;
+            }
+        }
+        self.dc.process(buffer, channels);
+
+        for frame in buffer.chunks_mut(channels) {
+            for (channel, (sample, state)) in frame.iter_mut().zip(&mut self.channels).enumerate() {
+                let mut x = *sample;
+                for (filter, c) in state.tone.iter_mut().zip(&self.tone_coeffs) {
+                    x = filter.process(c, x);
+                }
+                x = match self.convolvers.get_mut(channel) {
+                    Some(convolver) => convolver.process_sample(x),
+                    None => state
+                        .speaker
+                        .iter_mut()
+                        .zip(&self.speaker_coeffs)
+                        .fold(x, |x, (filter, c)| filter.process(c, x)),
+                };
+                *sample = x * self.trim;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.channels.fill(AmpChannel::default());
+        self.dc.reset();
+        self.convolvers.iter_mut().for_each(Convolver::reset);
+    }
+
+    fn latency(&self) -> usize {
+        if self.cabinet.is_some() {
+            CABINET_BLOCK
+        } else {
+            0
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("input", self.input_db, -24.0, 24.0),
+            ParamInfo::new("drive", self.drive_db, 0.0, 48.0),
+            ParamInfo::new("bass", self.bass_db, -12.0, 12.0),
+            ParamInfo::new("mid", self.mid_db, -12.0, 12.0),
+            ParamInfo::new("treble", self.treble_db, -12.0, 12.0),
+            ParamInfo::new("trim", self.trim_db, -24.0, 12.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "input" => self.set_input(value),
+            "drive" => self.set_drive(value),
+            "bass" => {
+                self.bass_db = value.clamp(-12.0, 12.0);
+                self.update_tone();
+            }
+            "mid" => {
+                self.mid_db = value.clamp(-12.0, 12.0);
+                self.update_tone();
+            }
+            "treble" => {
+                self.treble_db = value.clamp(-12.0, 12.0);
+                self.update_tone();
+            }
+            "trim" => self.set_trim(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use super::{Processor, time_coefficient};
+use crate::params::ParamInfo;
+use anyhow::{Result, bail};
+use std::f32::consts::PI;
+
+/// Range of voices followed, a low bass to a high soprano.
+const MIN_FREQUENCY: f32 = 75.0;
+const MAX_FREQUENCY: f32 = 1000.0;
+/// Dip of YIN's normalised difference a period has to reach to count.
+const YIN_THRESHOLD: f32 = 0.2;
+/// Mean square below which nothing is taken as voiced, -50 dBFS.
+const SILENCE: f32 = 1e-5;
+/// Time between pitch estimates.
+const DETECT_MS: f32 = 10.0;
+/// Grain period while nothing is voiced, passed through unshifted.
+const UNVOICED_MS: f32 = 10.0;
+/// Least the summed grain windows are divided by, so the gaps left when
+/// lowering the pitch aren't pulled up by more than 6 dB.
+const WEIGHT_FLOOR: f32 = 0.5;
+
+/// Key of the scale, as a note name: C, F#, Bb... C is 0, B is 11.
+pub fn parse_key(text: &str) -> Result<usize> {
+    let text = text.trim();
+    let mut chars = text.chars();
+    let natural = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => bail!("Unknown key '{}', expected a note like C, F# or Bb", text),
+    };
+    let key = match chars.as_str() {
+        "" => natural,
+        "#" => natural + 1,
+        "b" => natural + 11,
+        _ => bail!("Unknown key '{}', expected a note like C, F# or Bb", text),
+    };
+    Ok(key % 12)
+}
+
+/// Notes the voice is pulled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    /// Natural minor.
+    Minor,
+}
+
+impl Scale {
+    const ALL: [Scale; 3] = [Scale::Chromatic, Scale::Major, Scale::Minor];
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let scale = match text.trim() {
+            "chromatic" => Scale::Chromatic,
+            "major" => Scale::Major,
+            "minor" => Scale::Minor,
+            other => bail!(
+                "Unknown scale '{}', expected chromatic, major or minor",
+                other
+            ),
+        };
+        Ok(scale)
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&s| s == self).unwrap_or(0)
+    }
+
+    /// Semitones above the key, with the octave so notes just below the
+    /// next key can round up to it.
+    fn notes(self) -> &'static [f32] {
+        match self {
+            Scale::Chromatic => &[
+                0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0,
+            ],
+            Scale::Major => &[0.0, 2.0, 4.0, 5.0, 7.0, 9.0, 11.0, 12.0],
+            Scale::Minor => &[0.0, 2.0, 3.0, 5.0, 7.0, 8.0, 10.0, 12.0],
+        }
+    }
+}
+
+/// Pitch correction for a live vocal. The pitch is estimated with YIN on
+/// the mono sum and pulled to the nearest note of `scale` in `key`, taking
+/// `speed` ms to get there: 0 snaps straight to the note, the hard-tuned
+/// effect, while 50 to 100 ms corrects and keeps slides and vibrato.
+///
+/// The voice is rebuilt with TD-PSOLA: grains two periods long are cut
+/// one input period apart and laid back down one corrected period apart,
+/// the same marks on every channel. Output lags the input by two and a
+/// half of the longest periods followed, about 35 ms.
+pub struct AutoTune {
+    sample_rate: f32,
+    key: usize,
+    scale: Scale,
+    speed_ms: f32,
+    /// Shift smoothing per estimate, from `speed_ms`.
+    speed_coeff: f32,
+
+    /// Periods followed, in frames.
+    min_period: usize,
+    max_period: usize,
+    /// Frames the output lags the input by, room for a grain either side
+    /// of the mark it's cut around.
+    delay: usize,
+    detect_interval: usize,
+    unvoiced_period: f32,
+
+    channels: usize,
+    /// Rings of `mask + 1` frames: the input, interleaved, its mono sum,
+    /// the overlap-added grains and the sum of their windows.
+    input: Vec<f32>,
+    mono: Vec<f32>,
+    output: Vec<f32>,
+    weight: Vec<f32>,
+    mask: usize,
+    /// Frames taken in since the last reset.
+    time: usize,
+    until_detect: usize,
+    /// Scratch for YIN: the latest frames in order and their difference.
+    frame: Vec<f32>,
+    difference: Vec<f32>,
+
+    /// Latest input period in frames, 0 while unvoiced, and the shift
+    /// applied, in semitones.
+    period: f32,
+    shift: f32,
+    /// Next analysis mark, in input frames, and synthesis mark, in output
+    /// frames.
+    mark: f64,
+    synth: f64,
+}
+
+impl AutoTune {
+    pub fn new(key: usize, scale: Scale, sample_rate: f32) -> Self {
+        let max_period = (sample_rate / MIN_FREQUENCY).ceil() as usize;
+        let delay = max_period * 5 / 2 + 2;
+        let mut autotune = AutoTune {
+            sample_rate,
+            key: key % 12,
+            scale,
+            speed_ms: 0.0,
+            speed_coeff: 0.0,
+            min_period: (sample_rate / MAX_FREQUENCY).floor().max(2.0) as usize,
+            max_period,
+            delay,
+            detect_interval: ((DETECT_MS * 0.001 * sample_rate) as usize).max(1),
+            unvoiced_period: UNVOICED_MS * 0.001 * sample_rate,
+            channels: 0,
+            input: Vec::new(),
+            mono: Vec::new(),
+            output: Vec::new(),
+            weight: Vec::new(),
+            mask: (delay + 2 * max_period + 2).next_power_of_two() - 1,
+            time: 0,
+            until_detect: 0,
+            frame: vec![0.0; 2 * max_period],
+            difference: vec![0.0; max_period + 1],
+            period: 0.0,
+            shift: 0.0,
+            mark: 0.0,
+            synth: 0.0,
+        };
+        autotune.set_speed(50.0);
+        autotune.reset();
+        autotune
+    }
+
+    /// Key of the scale, 0 for C to 11 for B.
+    pub fn set_key(&mut self, key: usize) {
+        self.key = key % 12;
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+
+    /// Time to pull a note to pitch, 0 for hard tuning.
+    pub fn set_speed(&mut self, speed_ms: f32) {
+        self.speed_ms = speed_ms.clamp(0.0, 1000.0);
+        let estimates = self.sample_rate / self.detect_interval as f32;
+        self.speed_coeff = time_coefficient(self.speed_ms, estimates);
+    }
+
+    /// Note of the scale closest to `note`, both as MIDI note numbers.
+    fn nearest(&self, note: f32) -> f32 {
+        let relative = note - self.key as f32;
+        let octave = (relative / 12.0).floor();
+        let within = relative - octave * 12.0;
+        let closest = self
+            .scale
+            .notes()
+            .iter()
+            .copied()
+            .min_by(|a, b| (within - a).abs().total_cmp(&(within - b).abs()))
+            .unwrap_or(0.0);
+        self.key as f32 + octave * 12.0 + closest
+    }
+
+    /// Estimates the period of the frames up to the current one with YIN
+    /// and moves the shift toward the nearest note.
+    fn detect(&mut self) {
+        let (min, max) = (self.min_period, self.max_period);
+        let start = (self.time + 1).wrapping_sub(2 * max);
+        for (i, sample) in self.frame.iter_mut().enumerate() {
+            *sample = self.mono[start.wrapping_add(i) & self.mask];
+        }
+
+        let window = &self.frame[..max];
+        let power = window.iter().map(|x| x * x).sum::<f32>() / max as f32;
+        if power < SILENCE {
+            self.period = 0.0;
+            self.shift = 0.0;
+            return;
+        }
+
+        // Cumulative mean normalised difference
+        let mut running = 0.0;
+        for tau in 1..=max {
+            let lagged = &self.frame[tau..tau + max];
+            let d: f32 = window
+                .iter()
+                .zip(lagged)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum();
+            running += d;
+            self.difference[tau] = if running > 0.0 {
+                d * tau as f32 / running
+            } else {
+                1.0
+            };
+        }
+
+        // First dip under the threshold, followed down to its bottom
+        let d = &self.difference;
+        let Some(mut tau) = (min..=max).find(|&tau| d[tau] < YIN_THRESHOLD) else {
+            self.period = 0.0;
+            self.shift = 0.0;
+            return;
+        };
+        while tau < max && d[tau + 1] < d[tau] {
+            tau += 1;
+        }
+        let mut period = tau as f32;
+        if tau < max {
+            let (a, b, c) = (d[tau - 1], d[tau], d[tau + 1]);
+            let curve = a - 2.0 * b + c;
+            if curve > 1e-9 {
+                period += 0.5 * (a - c) / curve;
+            }
+        }
+        self.period = period;
+
+        let note = 69.0 + 12.0 * (self.sample_rate / period / 440.0).log2();
+        let target = self.nearest(note) - note;
+        self.shift = target + (self.shift - target) * self.speed_coeff;
+    }
+
+    /// Cuts the grain for the next synthesis mark and adds it in.
+    fn place_grain(&mut self) {
+        let (period, ratio) = if self.period > 0.0 {
+            (self.period, 2f32.powf(self.shift / 12.0))
+        } else {
+            (self.unvoiced_period, 1.0)
+        };
+        let step = period as f64;
+
+        // Analysis mark closest to the input time of this synthesis mark
+        let target = self.synth - self.delay as f64;
+        while self.mark + step / 2.0 < target {
+            self.mark += step;
+        }
+
+        let half = (period.round() as usize).max(1);
+        let from = (self.mark.round() as usize).wrapping_sub(half);
+        let to = (self.synth.round() as usize).wrapping_sub(half);
+        let channels = self.channels;
+        for i in 1..2 * half {
+            let w = 0.5 - 0.5 * (PI * i as f32 / half as f32).cos();
+            let from = (from.wrapping_add(i) & self.mask) * channels;
+            let to = to.wrapping_add(i) & self.mask;
+            self.weight[to] += w;
+            let to = to * channels;
+            for c in 0..channels {
+                self.output[to + c] += self.input[from + c] * w;
+            }
+        }
+
+        self.synth += step / ratio as f64;
+    }
+}
+
+impl Processor for AutoTune {
+    fn name(&self) -> &'static str {
+        "autotune"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels != channels {
+            let frames = self.mask + 1;
+            self.input = vec![0.0; frames * channels];
+            self.mono = vec![0.0; frames];
+            self.output = vec![0.0; frames * channels];
+            self.weight = vec![0.0; frames];
+            self.channels = channels;
+            self.reset();
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let scale = 1.0 / channels as f32;
+        for frame in buffer.chunks_mut(channels) {
+            let position = self.time & self.mask;
+            let start = position * channels;
+            self.input[start..start + channels].copy_from_slice(frame);
+            self.mono[position] = frame.iter().sum::<f32>() * scale;
+
+            self.until_detect -= 1;
+            if self.until_detect == 0 {
+                self.until_detect = self.detect_interval;
+                self.detect();
+            }
+            // Every grain reaching this frame is in before it's read
+            while self.synth - self.max_period as f64 <= self.time as f64 {
+                self.place_grain();
+            }
+
+            let weight = self.weight[position].max(WEIGHT_FLOOR);
+            let output = &mut self.output[start..start + channels];
+            for (sample, out) in frame.iter_mut().zip(output.iter_mut()) {
+                *sample = *out / weight;
+                *out = 0.0;
+            }
+            self.weight[position] = 0.0;
+            self.time += 1;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.input.fill(0.0);
+        self.mono.fill(0.0);
+        self.output.fill(0.0);
+        self.weight.fill(0.0);
+        self.time = 0;
+        self.until_detect = self.detect_interval;
+        self.period = 0.0;
+        self.shift = 0.0;
+        self.mark = 0.0;
+        self.synth = self.delay as f64;
+    }
+
+    fn latency(&self) -> usize {
+        self.delay
+    }
+
+    /// `key` is 0 for C to 11 for B, `scale` counts chromatic, major,
+    /// minor from 0.
+    fn params(&self) -> Vec<ParamInfo> {
+        let last = (Scale::ALL.len() - 1) as f32;
+        vec![
+            ParamInfo::new("key", self.key as f32, 0.0, 11.0),
+            ParamInfo::new("scale", self.scale.index() as f32, 0.0, last),
+            ParamInfo::new("speed", self.speed_ms, 0.0, 1000.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "key" => self.set_key(value.round().clamp(0.0, 11.0) as usize),
+            "scale" => {
+                let index = value.round().clamp(0.0, (Scale::ALL.len() - 1) as f32);
+                self.set_scale(Scale::ALL[index as usize]);
+            }
+            "speed" => self.set_speed(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+
+/// Biquad coefficients with `a0` normalised to 1, from the RBJ audio EQ
+/// cookbook. Shelves use a slope of 1.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Coefficients {
+    b: [f32; 3],
+    a: [f32; 2],
+}
+
+/// Q of a Butterworth low- or high-pass.
+pub const BUTTERWORTH_Q: f32 = FRAC_1_SQRT_2;
+
+impl Coefficients {
+    pub fn low_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, q, sample_rate);
+        Self::normalised(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn high_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, q, sample_rate);
+        Self::normalised(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// 0 dB at `frequency`.
+    pub fn band_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, q, sample_rate);
+        Self::normalised([alpha, 0.0, -alpha], [1.0 + alpha, -2.0 * cos, 1.0 - alpha])
+    }
+
+    pub fn all_pass(frequency: f32, q: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, q, sample_rate);
+        Self::normalised(
+            [1.0 - alpha, -2.0 * cos, 1.0 + alpha],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn peaking(frequency: f32, q: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, q, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        Self::normalised(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(frequency: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, BUTTERWORTH_Q, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::normalised(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + k),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - k),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + k,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - k,
+            ],
+        )
+    }
+
+    pub fn high_shelf(frequency: f32, gain_db: f32, sample_rate: f32) -> Self {
+        let (cos, alpha) = angle(frequency, BUTTERWORTH_Q, sample_rate);
+        let a = 10f32.powf(gain_db / 40.0);
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::normalised(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + k),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - k),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + k,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - k,
+            ],
+        )
+    }
+
+    fn normalised(b: [f32; 3], a: [f32; 3]) -> Self {
+        Coefficients {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+}
+
+/// Cosine of the centre frequency and the cookbook's alpha. Kept under
+/// Nyquist so a high setting at a low sample rate stays stable.
+fn angle(frequency: f32, q: f32, sample_rate: f32) -> (f32, f32) {
+    let w0 = 2.0 * PI * frequency.min(sample_rate * 0.49) / sample_rate;
+    (w0.cos(), w0.sin() / (2.0 * q))
+}
+
+/// Direct form I history of one filter on one channel. Coefficients are
+/// passed in so channels can share them and a change applies at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Biquad {
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Biquad {
+    pub fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b[0] * x + c.b[1] * self.x[0] + c.b[2] * self.x[1]
+            - c.a[0] * self.y[0]
+            - c.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+
+    pub fn reset(&mut self) {
+        *self = Biquad::default();
+    }
+}
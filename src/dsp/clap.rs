@@ -0,0 +1,681 @@
+use super::Processor;
+use crate::params::ParamInfo;
+use anyhow::{Context, Result, bail};
+use libloading::Library;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+use tracing::info;
+
+/// Most frames handed to a plugin at once, callbacks are split to fit.
+const MAX_FRAMES: usize = 4096;
+
+/// Host of CLAP effect plugins. A plugin file is opened once, however
+/// many plugins are made from it, and closed after the last one is gone.
+#[derive(Default)]
+pub struct ClapHost {
+    modules: HashMap<PathBuf, Arc<Module>>,
+}
+
+impl ClapHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the plugin `id` from the `.clap` file at `path`, or its
+    /// first plugin when no id is given, running at `sample_rate`.
+    pub fn load(&mut self, path: &Path, id: Option<&str>, sample_rate: f32) -> Result<ClapPlugin> {
+        let module = match self.modules.get(path) {
+            Some(module) => module.clone(),
+            None => {
+                let module = Arc::new(Module::open(path)?);
+                self.modules.insert(path.to_path_buf(), module.clone());
+                module
+            }
+        };
+        ClapPlugin::new(module, id, sample_rate)
+            .with_context(|| format!("Failed to load a plugin from {}", path.display()))
+    }
+}
+
+/// Open plugin file with its entry point initialized.
+struct Module {
+    entry: *const ffi::PluginEntry,
+    factory: *const ffi::PluginFactory,
+    // Closed last, the pointers above point into it
+    _library: Library,
+}
+
+// The entry point and factory are safe to use from any thread
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl Module {
+    fn open(path: &Path) -> Result<Module> {
+        let library = unsafe { Library::new(path) }
+            .with_context(|| format!("Failed to open the plugin {}", path.display()))?;
+        let entry = unsafe { *library.get::<*const ffi::PluginEntry>(b"clap_entry\0")? };
+        if entry.is_null() {
+            bail!("{} has no clap_entry", path.display());
+        }
+        let entry_ref = unsafe { &*entry };
+        if entry_ref.clap_version.major < 1 {
+            bail!(
+                "{} was built against an unreleased CLAP version",
+                path.display()
+            );
+        }
+        let location = CString::new(path.to_string_lossy().as_bytes())?;
+        if !unsafe { (entry_ref.init)(location.as_ptr()) } {
+            bail!("{} failed to initialize", path.display());
+        }
+        let factory = unsafe { (entry_ref.get_factory)(ffi::PLUGIN_FACTORY_ID.as_ptr()) }
+            as *const ffi::PluginFactory;
+        if factory.is_null() {
+            unsafe { (entry_ref.deinit)() };
+            bail!("{} has no plugin factory", path.display());
+        }
+        Ok(Module {
+            entry,
+            factory,
+            _library: library,
+        })
+    }
+
+    /// Ids and names of the plugins in the file.
+    fn plugins(&self) -> Vec<(String, String)> {
+        let factory = unsafe { &*self.factory };
+        let count = unsafe { (factory.get_plugin_count)(self.factory) };
+        (0..count)
+            .filter_map(|index| {
+                let descriptor = unsafe { (factory.get_plugin_descriptor)(self.factory, index) };
+                if descriptor.is_null() {
+                    return None;
+                }
+                let descriptor = unsafe { &*descriptor };
+                Some((unsafe { text(descriptor.id) }, unsafe {
+                    text(descriptor.name)
+                }))
+            })
+            .collect()
+    }
+}
+
+impl Drop for Module {
+    fn drop(&mut self) {
+        unsafe { ((*self.entry).deinit)() };
+    }
+}
+
+/// Parameter change waiting for the next `process` call.
+#[derive(Clone, Copy)]
+struct PendingValue {
+    id: u32,
+    value: f64,
+}
+
+/// CLAP effect plugin as a chain processor. Its parameters show up in the
+/// store as `<plugin>.<parameter>`, both names in lower case with spaces
+/// as underscores, e.g. `tdr_nova.band_1_gain`. The plugin gets one audio
+/// port with as many channels as the chain, 32-bit floats.
+pub struct ClapPlugin {
+    plugin: *const ffi::Plugin,
+    name: &'static str,
+    /// Parameter names, as reported by `params`, with their CLAP ids and
+    /// ranges.
+    infos: Vec<(ParamInfo, u32)>,
+    pending: Vec<PendingValue>,
+    /// Converted to events for the next `process` call.
+    events: Vec<ffi::EventParamValue>,
+    processing: bool,
+
+    channels: usize,
+    inputs: Vec<Vec<f32>>,
+    outputs: Vec<Vec<f32>>,
+    input_pointers: Vec<*mut f32>,
+    output_pointers: Vec<*mut f32>,
+    steady_time: i64,
+    /// Frames of delay the plugin reports once activated.
+    latency: usize,
+
+    // Plugin before host before module when dropped, see `Drop`
+    _host: Box<ffi::Host>,
+    _module: Arc<Module>,
+}
+
+// The plugin is made on the main thread and only used from the audio
+// thread afterwards, which is what CLAP expects of a host
+unsafe impl Send for ClapPlugin {}
+
+impl ClapPlugin {
+    fn new(module: Arc<Module>, id: Option<&str>, sample_rate: f32) -> Result<ClapPlugin> {
+        let plugins = module.plugins();
+        let (id, plugin_name) = match id {
+            Some(id) => plugins
+                .iter()
+                .find(|(plugin_id, _)| plugin_id == id)
+                .cloned()
+                .with_context(|| {
+                    format!(
+                        "No plugin '{}', available: {}",
+                        id,
+                        plugins
+                            .iter()
+                            .map(|(id, name)| format!("{} ({})", id, name))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?,
+            None => plugins
+                .first()
+                .cloned()
+                .context("The file has no plugins")?,
+        };
+
+        let host = Box::new(ffi::Host {
+            clap_version: ffi::CLAP_VERSION,
+            host_data: ptr::null_mut(),
+            name: c"live_dsp".as_ptr(),
+            vendor: c"live_dsp".as_ptr(),
+            url: c"".as_ptr(),
+            version: c"0.1.0".as_ptr(),
+            get_extension: host_get_extension,
+            request_restart: host_request,
+            request_process: host_request,
+            request_callback: host_request,
+        });
+        let plugin_id = CString::new(id.as_str())?;
+        let factory = unsafe { &*module.factory };
+        let plugin = unsafe { (factory.create_plugin)(module.factory, &*host, plugin_id.as_ptr()) };
+        if plugin.is_null() {
+            bail!("Failed to create the plugin {}", id);
+        }
+        let plugin_ref = unsafe { &*plugin };
+        if !unsafe { (plugin_ref.init)(plugin) } {
+            unsafe { (plugin_ref.destroy)(plugin) };
+            bail!("The plugin {} failed to initialize", id);
+        }
+        if !unsafe { (plugin_ref.activate)(plugin, sample_rate as f64, 1, MAX_FRAMES as u32) } {
+            unsafe { (plugin_ref.destroy)(plugin) };
+            bail!("The plugin {} failed to activate", id);
+        }
+
+        let params = unsafe { (plugin_ref.get_extension)(plugin, ffi::EXT_PARAMS.as_ptr()) }
+            as *const ffi::PluginParams;
+        let mut infos: Vec<(ParamInfo, u32)> = Vec::new();
+        if !params.is_null() {
+            let extension = unsafe { &*params };
+            let count = unsafe { (extension.count)(plugin) };
+            for index in 0..count {
+                let mut info = ffi::ParamInfo::zeroed();
+                if !unsafe { (extension.get_info)(plugin, index, &mut info) }
+                    || info.flags & (ffi::PARAM_IS_HIDDEN | ffi::PARAM_IS_READONLY) != 0
+                {
+                    continue;
+                }
+                let name = unsafe { CStr::from_ptr(info.name.as_ptr()) }.to_string_lossy();
+                let mut name = key(&name);
+                // Keys have to be unique, a repeated name gets the id
+                if infos.iter().any(|(other, _)| other.name == name) {
+                    name = format!("{}_{}", name, info.id);
+                }
+                let mut value = info.default_value;
+                unsafe { (extension.get_value)(plugin, info.id, &mut value) };
+                infos.push((
+                    ParamInfo::new(
+                        Box::leak(name.into_boxed_str()),
+                        value as f32,
+                        info.min_value as f32,
+                        info.max_value as f32,
+                    ),
+                    info.id,
+                ));
+            }
+        }
+
+        let latency = unsafe { (plugin_ref.get_extension)(plugin, ffi::EXT_LATENCY.as_ptr()) }
+            as *const ffi::PluginLatency;
+        let latency = if latency.is_null() {
+            0
+        } else {
+            unsafe { ((*latency).get)(plugin) as usize }
+        };
+
+        info!(
+            "CLAP plugin: {} ({}), {} parameters",
+            plugin_name,
+            id,
+            infos.len()
+        );
+        Ok(ClapPlugin {
+            plugin,
+            name: Box::leak(key(&plugin_name).into_boxed_str()),
+            pending: Vec::with_capacity(infos.len()),
+            events: Vec::with_capacity(infos.len()),
+            infos,
+            processing: false,
+            channels: 0,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            input_pointers: Vec::new(),
+            output_pointers: Vec::new(),
+            steady_time: 0,
+            latency,
+            _host: host,
+            _module: module,
+        })
+    }
+
+    fn run(&mut self, frames: usize) {
+        self.events.clear();
+        for pending in self.pending.drain(..) {
+            self.events.push(ffi::EventParamValue {
+                header: ffi::EventHeader {
+                    size: size_of::<ffi::EventParamValue>() as u32,
+                    time: 0,
+                    space_id: ffi::CORE_EVENT_SPACE_ID,
+                    event_type: ffi::EVENT_PARAM_VALUE,
+                    flags: 0,
+                },
+                param_id: pending.id,
+                cookie: ptr::null_mut(),
+                note_id: -1,
+                port_index: -1,
+                channel: -1,
+                key: -1,
+                value: pending.value,
+            });
+        }
+        let in_events = ffi::InputEvents {
+            ctx: &self.events as *const Vec<ffi::EventParamValue> as *mut c_void,
+            size: input_events_size,
+            get: input_events_get,
+        };
+        let out_events = ffi::OutputEvents {
+            ctx: ptr::null_mut(),
+            try_push: output_events_push,
+        };
+
+        for (pointer, input) in self.input_pointers.iter_mut().zip(self.inputs.iter_mut()) {
+            *pointer = input.as_mut_ptr();
+        }
+        for (pointer, output) in self.output_pointers.iter_mut().zip(self.outputs.iter_mut()) {
+            *pointer = output.as_mut_ptr();
+        }
+        let input = ffi::AudioBuffer {
+            data32: self.input_pointers.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: self.channels as u32,
+            latency: 0,
+            constant_mask: 0,
+        };
+        let mut output = ffi::AudioBuffer {
+            data32: self.output_pointers.as_mut_ptr(),
+            data64: ptr::null_mut(),
+            channel_count: self.channels as u32,
+            latency: 0,
+            constant_mask: 0,
+        };
+        let process = ffi::Process {
+            steady_time: self.steady_time,
+            frames_count: frames as u32,
+            transport: ptr::null(),
+            audio_inputs: &input,
+            audio_outputs: &mut output,
+            audio_inputs_count: 1,
+            audio_outputs_count: 1,
+            in_events: &in_events,
+            out_events: &out_events,
+        };
+        let status = unsafe { ((*self.plugin).process)(self.plugin, &process) };
+        if status == ffi::PROCESS_ERROR {
+            // Nothing usable came out, the input goes through as is
+            for (output, input) in self.outputs.iter_mut().zip(&self.inputs) {
+                output[..frames].copy_from_slice(&input[..frames]);
+            }
+        }
+        self.steady_time += frames as i64;
+    }
+}
+
+impl Processor for ClapPlugin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if channels != self.channels {
+            // Only reached when `prepare` wasn't called for this layout
+            return;
+        }
+        if !self.processing {
+            // CLAP wants processing started on the audio thread
+            self.processing = unsafe { ((*self.plugin).start_processing)(self.plugin) };
+            if !self.processing {
+                return;
+            }
+        }
+        for block in buffer.chunks_mut(MAX_FRAMES * channels) {
+            let frames = block.len() / channels;
+            for (i, frame) in block.chunks_exact(channels).enumerate() {
+                for (input, &sample) in self.inputs.iter_mut().zip(frame) {
+                    input[i] = sample;
+                }
+            }
+            self.run(frames);
+            for (i, frame) in block.chunks_exact_mut(channels).enumerate() {
+                for (sample, output) in frame.iter_mut().zip(&self.outputs) {
+                    *sample = output[i];
+                }
+            }
+        }
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.channels = channels;
+        self.inputs = vec![vec![0.0; MAX_FRAMES]; channels];
+        self.outputs = vec![vec![0.0; MAX_FRAMES]; channels];
+        self.input_pointers = vec![ptr::null_mut(); channels];
+        self.output_pointers = vec![ptr::null_mut(); channels];
+    }
+
+    fn reset(&mut self) {
+        unsafe { ((*self.plugin).reset)(self.plugin) };
+    }
+
+    fn latency(&self) -> usize {
+        self.latency
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        self.infos.iter().map(|(info, _)| *info).collect()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let Some(&(_, id)) = self.infos.iter().find(|(info, _)| info.name == name) else {
+            return;
+        };
+        match self.pending.iter_mut().find(|p| p.id == id) {
+            Some(pending) => pending.value = value as f64,
+            // Capacity is one per parameter, so this never allocates
+            None => self.pending.push(PendingValue {
+                id,
+                value: value as f64,
+            }),
+        }
+    }
+}
+
+impl Drop for ClapPlugin {
+    fn drop(&mut self) {
+        let plugin = unsafe { &*self.plugin };
+        unsafe {
+            if self.processing {
+                (plugin.stop_processing)(self.plugin);
+            }
+            (plugin.deactivate)(self.plugin);
+            (plugin.destroy)(self.plugin);
+        }
+    }
+}
+
+/// Lower case with spaces as underscores. Leaked by the caller, since
+/// processor and parameter names are static; plugins are loaded once.
+fn key(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+unsafe fn text(pointer: *const c_char) -> String {
+    if pointer.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(pointer) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The host offers no extensions and ignores requests, plugins that need
+// a restart or a main thread callback get neither
+extern "C" fn host_get_extension(_host: *const ffi::Host, _id: *const c_char) -> *const c_void {
+    ptr::null()
+}
+
+extern "C" fn host_request(_host: *const ffi::Host) {}
+
+extern "C" fn input_events_size(list: *const ffi::InputEvents) -> u32 {
+    let events = unsafe { &*((*list).ctx as *const Vec<ffi::EventParamValue>) };
+    events.len() as u32
+}
+
+extern "C" fn input_events_get(
+    list: *const ffi::InputEvents,
+    index: u32,
+) -> *const ffi::EventHeader {
+    let events = unsafe { &*((*list).ctx as *const Vec<ffi::EventParamValue>) };
+    match events.get(index as usize) {
+        Some(event) => &event.header,
+        None => ptr::null(),
+    }
+}
+
+/// Events from the plugin, such as its own parameter changes, are dropped.
+extern "C" fn output_events_push(
+    _list: *const ffi::OutputEvents,
+    _event: *const ffi::EventHeader,
+) -> bool {
+    true
+}
+
+/// The parts of the CLAP 1.x C API the host uses, laid out as in the
+/// headers.
+mod ffi {
+    use std::ffi::{CStr, c_char, c_void};
+
+    pub const CLAP_VERSION: Version = Version {
+        major: 1,
+        minor: 2,
+        revision: 0,
+    };
+    pub const PLUGIN_FACTORY_ID: &CStr = c"clap.plugin-factory";
+    pub const EXT_PARAMS: &CStr = c"clap.params";
+    pub const EXT_LATENCY: &CStr = c"clap.latency";
+    pub const CORE_EVENT_SPACE_ID: u16 = 0;
+    pub const EVENT_PARAM_VALUE: u16 = 5;
+    pub const PROCESS_ERROR: i32 = 0;
+    pub const PARAM_IS_HIDDEN: u32 = 1 << 2;
+    pub const PARAM_IS_READONLY: u32 = 1 << 3;
+    const NAME_SIZE: usize = 256;
+    const PATH_SIZE: usize = 1024;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Version {
+        pub major: u32,
+        pub minor: u32,
+        pub revision: u32,
+    }
+
+    #[repr(C)]
+    pub struct PluginEntry {
+        pub clap_version: Version,
+        pub init: unsafe extern "C" fn(plugin_path: *const c_char) -> bool,
+        pub deinit: unsafe extern "C" fn(),
+        pub get_factory: unsafe extern "C" fn(factory_id: *const c_char) -> *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct PluginFactory {
+        pub get_plugin_count: unsafe extern "C" fn(factory: *const PluginFactory) -> u32,
+        pub get_plugin_descriptor: unsafe extern "C" fn(
+            factory: *const PluginFactory,
+            index: u32,
+        ) -> *const PluginDescriptor,
+        pub create_plugin: unsafe extern "C" fn(
+            factory: *const PluginFactory,
+            host: *const Host,
+            plugin_id: *const c_char,
+        ) -> *const Plugin,
+    }
+
+    #[repr(C)]
+    pub struct PluginDescriptor {
+        pub clap_version: Version,
+        pub id: *const c_char,
+        pub name: *const c_char,
+        pub vendor: *const c_char,
+        pub url: *const c_char,
+        pub manual_url: *const c_char,
+        pub support_url: *const c_char,
+        pub version: *const c_char,
+        pub description: *const c_char,
+        pub features: *const *const c_char,
+    }
+
+    #[repr(C)]
+    pub struct Host {
+        pub clap_version: Version,
+        pub host_data: *mut c_void,
+        pub name: *const c_char,
+        pub vendor: *const c_char,
+        pub url: *const c_char,
+        pub version: *const c_char,
+        pub get_extension:
+            extern "C" fn(host: *const Host, extension_id: *const c_char) -> *const c_void,
+        pub request_restart: extern "C" fn(host: *const Host),
+        pub request_process: extern "C" fn(host: *const Host),
+        pub request_callback: extern "C" fn(host: *const Host),
+    }
+
+    #[repr(C)]
+    pub struct Plugin {
+        pub desc: *const PluginDescriptor,
+        pub plugin_data: *mut c_void,
+        pub init: unsafe extern "C" fn(plugin: *const Plugin) -> bool,
+        pub destroy: unsafe extern "C" fn(plugin: *const Plugin),
+        pub activate: unsafe extern "C" fn(
+            plugin: *const Plugin,
+            sample_rate: f64,
+            min_frames_count: u32,
+            max_frames_count: u32,
+        ) -> bool,
+        pub deactivate: unsafe extern "C" fn(plugin: *const Plugin),
+        pub start_processing: unsafe extern "C" fn(plugin: *const Plugin) -> bool,
+        pub stop_processing: unsafe extern "C" fn(plugin: *const Plugin),
+        pub reset: unsafe extern "C" fn(plugin: *const Plugin),
+        pub process: unsafe extern "C" fn(plugin: *const Plugin, process: *const Process) -> i32,
+        pub get_extension:
+            unsafe extern "C" fn(plugin: *const Plugin, id: *const c_char) -> *const c_void,
+        pub on_main_thread: unsafe extern "C" fn(plugin: *const Plugin),
+    }
+
+    #[repr(C)]
+    pub struct Process {
+        pub steady_time: i64,
+        pub frames_count: u32,
+        pub transport: *const c_void,
+        pub audio_inputs: *const AudioBuffer,
+        pub audio_outputs: *mut AudioBuffer,
+        pub audio_inputs_count: u32,
+        pub audio_outputs_count: u32,
+        pub in_events: *const InputEvents,
+        pub out_events: *const OutputEvents,
+    }
+
+    #[repr(C)]
+    pub struct AudioBuffer {
+        pub data32: *mut *mut f32,
+        pub data64: *mut *mut f64,
+        pub channel_count: u32,
+        pub latency: u32,
+        pub constant_mask: u64,
+    }
+
+    #[repr(C)]
+    pub struct InputEvents {
+        pub ctx: *mut c_void,
+        pub size: extern "C" fn(list: *const InputEvents) -> u32,
+        pub get: extern "C" fn(list: *const InputEvents, index: u32) -> *const EventHeader,
+    }
+
+    #[repr(C)]
+    pub struct OutputEvents {
+        pub ctx: *mut c_void,
+        pub try_push: extern "C" fn(list: *const OutputEvents, event: *const EventHeader) -> bool,
+    }
+
+    #[repr(C)]
+    pub struct EventHeader {
+        pub size: u32,
+        pub time: u32,
+        pub space_id: u16,
+        pub event_type: u16,
+        pub flags: u32,
+    }
+
+    #[repr(C)]
+    pub struct EventParamValue {
+        pub header: EventHeader,
+        pub param_id: u32,
+        pub cookie: *mut c_void,
+        pub note_id: i32,
+        pub port_index: i16,
+        pub channel: i16,
+        pub key: i16,
+        pub value: f64,
+    }
+
+    #[repr(C)]
+    pub struct PluginParams {
+        pub count: unsafe extern "C" fn(plugin: *const Plugin) -> u32,
+        pub get_info:
+            unsafe extern "C" fn(plugin: *const Plugin, index: u32, info: *mut ParamInfo) -> bool,
+        pub get_value:
+            unsafe extern "C" fn(plugin: *const Plugin, id: u32, value: *mut f64) -> bool,
+        pub value_to_text: *const c_void,
+        pub text_to_value: *const c_void,
+        pub flush: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct PluginLatency {
+        pub get: unsafe extern "C" fn(plugin: *const Plugin) -> u32,
+    }
+
+    #[repr(C)]
+    pub struct ParamInfo {
+        pub id: u32,
+        pub flags: u32,
+        pub cookie: *mut c_void,
+        pub name: [c_char; NAME_SIZE],
+        pub module: [c_char; PATH_SIZE],
+        pub min_value: f64,
+        pub max_value: f64,
+        pub default_value: f64,
+    }
+
+    impl ParamInfo {
+        pub fn zeroed() -> Self {
+            ParamInfo {
+                id: 0,
+                flags: 0,
+                cookie: std::ptr::null_mut(),
+                name: [0; NAME_SIZE],
+                module: [0; PATH_SIZE],
+                min_value: 0.0,
+                max_value: 0.0,
+                default_value: 0.0,
+            }
+        }
+    }
+}
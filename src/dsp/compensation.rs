@@ -0,0 +1,52 @@
+use super::Processor;
+
+/// Delays a signal by a fixed number of frames, so it lines up with a
+/// parallel path through processors that add latency.
+pub struct DelayCompensation {
+    frames: usize,
+    line: Vec<f32>,
+    position: usize,
+}
+
+impl DelayCompensation {
+    pub fn new(frames: usize) -> Self {
+        DelayCompensation {
+            frames,
+            line: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Processor for DelayCompensation {
+    fn name(&self) -> &'static str {
+        "delay_compensation"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        let len = self.frames * channels;
+        if self.line.len() != len {
+            self.line = vec![0.0; len];
+            self.position = 0;
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        // Passed through until prepared, the line isn't grown here
+        if self.line.is_empty() || self.line.len() != self.frames * channels {
+            return;
+        }
+        for sample in buffer.iter_mut() {
+            std::mem::swap(sample, &mut self.line[self.position]);
+            self.position = (self.position + 1) % self.line.len();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.line.fill(0.0);
+    }
+
+    fn latency(&self) -> usize {
+        self.frames
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Processor, db_to_gain, gain_to_db, time_coefficient};
+use crate::params::{AtomicF32, ParamInfo};
+use std::sync::Arc;
+
+/// Feed-forward compressor with a soft knee. All channels share one gain
+/// envelope so the stereo image doesn't shift under gain reduction.
+pub struct Compressor {
+    sample_rate: f32,
+    threshold_db: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+    knee_db: f32,
+    makeup_db: f32,
+    /// Drive the detector from another mixer channel's block peak instead
+    /// of the loudest channel of the frame, e.g. to duck the music by a
+    /// vocal mic.
+    sidechain: Option<Arc<AtomicF32>>,
+
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Current (smoothed) gain reduction in dB, always <= 0.
+    envelope_db: f32,
+}
+
+impl Compressor {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut compressor = Compressor {
+            sample_rate,
+            threshold_db: -18.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 120.0,
+            knee_db: 6.0,
+            makeup_db: 0.0,
+            sidechain: None,
+            attack_coeff: 0.0,
+            release_coeff: 0.0,
+            envelope_db: 0.0,
+        };
+        compressor.update_coefficients();
+        compressor
+    }
+
+    pub fn set_threshold(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db;
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    pub fn set_attack(&mut self, attack_ms: f32) {
+        self.attack_ms = attack_ms.max(0.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_release(&mut self, release_ms: f32) {
+        self.release_ms = release_ms.max(0.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_knee(&mut self, knee_db: f32) {
+        self.knee_db = knee_db.max(0.0);
+    }
+
+    pub fn set_makeup(&mut self, makeup_db: f32) {
+        self.makeup_db = makeup_db;
+    }
+
+    /// Keys the compressor from `key`, one of `mixer::ChannelKeys`.
+    pub fn set_sidechain(&mut self, key: Arc<AtomicF32>) {
+        self.sidechain = Some(key);
+    }
+
+    fn update_coefficients(&mut self) {
+        self.attack_coeff = time_coefficient(self.attack_ms, self.sample_rate);
+        self.release_coeff = time_coefficient(self.release_ms, self.sample_rate);
+    }
+
+    /// Static curve: how many dB to reduce a signal at `level_db`.
+    fn compute_reduction(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold_db;
+        let slope = 1.0 / self.ratio - 1.0;
+
+        if 2.0 * over < -self.knee_db {
+            0.0
+        } else if 2.0 * over.abs() <= self.knee_db && self.knee_db > 0.0 {
+            let x = over + self.knee_db / 2.0;
+            slope * x * x / (2.0 * self.knee_db)
+        } else {
+            slope * over
+        }
+    }
+}
+
+impl Processor for Compressor {
+    fn name(&self) -> &'static str {
+        "compressor"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        // The sidechain key is read once a block
+        let sidechain = self.sidechain.as_ref().map(|key| key.get());
+
+        for frame in buffer.chunks_mut(channels) {
+            let key = match sidechain {
+                Some(peak) => peak,
+                None => frame.iter().fold(0.0f32, |acc, s| acc.max(s.abs())),
+            };
+
+            let target = self.compute_reduction(gain_to_db(key));
+            // More reduction means the attack phase
+            let coeff = if target < self.envelope_db {
+                self.attack_coeff
+            } else {
+                self.release_coeff
+            };
+            self.envelope_db = coeff * self.envelope_db + (1.0 - coeff) * target;
+
+            let gain = db_to_gain(self.envelope_db + self.makeup_db);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope_db = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("threshold", self.threshold_db, -60.0, 0.0),
+            ParamInfo::new("ratio", self.ratio, 1.0, 20.0),
+            ParamInfo::new("attack", self.attack_ms, 0.0, 500.0),
+            ParamInfo::new("release", self.release_ms, 0.0, 5000.0),
+            ParamInfo::new("knee", self.knee_db, 0.0, 24.0),
+            ParamInfo::new("makeup", self.makeup_db, -24.0, 24.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.set_threshold(value),
+            "ratio" => self.set_ratio(value),
+            "attack" => self.set_attack(value),
+            "release" => self.set_release(value),
+            "knee" => self.set_knee(value),
+            "makeup" => self.set_makeup(value),
+            _ => {}
+        }
+    }
+}
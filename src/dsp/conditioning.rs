@@ -0,0 +1,122 @@
+use super::Processor;
+use super::biquad::{Biquad, Coefficients};
+use crate::params::ParamInfo;
+use std::f32::consts::PI;
+
+/// Corner of the DC blocker, well under anything audible.
+const DC_CORNER_HZ: f32 = 5.0;
+/// Q of the two sections of a 4th order Butterworth.
+const BUTTERWORTH_4_Q: [f32; 2] = [0.541_196_1, 1.306_563];
+
+/// One-pole DC blocker for interfaces that deliver an offset, which eats
+/// headroom and thumps when a channel is muted or gated.
+pub struct DcBlocker {
+    /// Pole of the filter, just under 1.
+    pole: f32,
+    /// Previous input and output per channel.
+    last: Vec<(f32, f32)>,
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: f32) -> Self {
+        DcBlocker {
+            pole: (-2.0 * PI * DC_CORNER_HZ / sample_rate).exp(),
+            last: Vec::new(),
+        }
+    }
+}
+
+impl Processor for DcBlocker {
+    fn name(&self) -> &'static str {
+        "dc_block"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.last.len() < channels {
+            self.last.resize(channels, (0.0, 0.0));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, (x1, y1)) in frame.iter_mut().zip(self.last.iter_mut()) {
+                let y = *sample - *x1 + self.pole * *y1;
+                *x1 = *sample;
+                *y1 = y;
+                *sample = y;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last.fill((0.0, 0.0));
+    }
+}
+
+/// Subsonic high-pass, 24 dB per octave, for rumble from stands, stage
+/// floors and handling noise that only wastes headroom downstream.
+pub struct HighPass {
+    sample_rate: f32,
+    frequency: f32,
+    sections: [Coefficients; 2],
+    /// Both sections per channel.
+    filters: Vec<[Biquad; 2]>,
+}
+
+impl HighPass {
+    pub fn new(frequency: f32, sample_rate: f32) -> Self {
+        let mut high_pass = HighPass {
+            sample_rate,
+            frequency: 0.0,
+            sections: [Coefficients::default(); 2],
+            filters: Vec::new(),
+        };
+        high_pass.set_frequency(frequency);
+        high_pass
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.clamp(20.0, 120.0);
+        self.sections =
+            BUTTERWORTH_4_Q.map(|q| Coefficients::high_pass(self.frequency, q, self.sample_rate));
+    }
+}
+
+impl Processor for HighPass {
+    fn name(&self) -> &'static str {
+        "highpass"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.filters.len() < channels {
+            self.filters.resize(channels, [Biquad::default(); 2]);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                let x = filters[0].process(&self.sections[0], *sample);
+                *sample = filters[1].process(&self.sections[1], x);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filters in self.filters.iter_mut() {
+            filters.iter_mut().for_each(Biquad::reset);
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![ParamInfo::new("frequency", self.frequency, 20.0, 120.0)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if name == "frequency" {
+            self.set_frequency(value);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use super::Processor;
+use super::fft::{Complex, Fft};
+use crate::params::ParamInfo;
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::info;
+
+/// Impulse responses longer than this are truncated.
+const MAX_IR_SECONDS: f32 = 10.0;
+
+/// Uniformly partitioned overlap-save convolver for a single channel.
+/// Output lags the input by `block` samples.
+pub struct Convolver {
+    block: usize,
+    fft: Fft,
+    /// Spectra of the zero-padded IR partitions.
+    partitions: Vec<Vec<Complex>>,
+    /// Frequency-domain delay line of past input blocks, newest at `head`.
+    history: Vec<Vec<Complex>>,
+    head: usize,
+    /// Previous block followed by the block being filled.
+    input: Vec<f32>,
+    fill: usize,
+    output: Vec<f32>,
+    scratch: Vec<Complex>,
+    accum: Vec<Complex>,
+}
+
+impl Convolver {
+    pub fn new(ir: &[f32], block: usize) -> Self {
+        let block = block.next_power_of_two();
+        let size = block * 2;
+        let fft = Fft::new(size);
+
+        let partitions: Vec<Vec<Complex>> = ir
+            .chunks(block)
+            .map(|part| {
+                let mut spectrum = vec![Complex::ZERO; size];
+                for (bin, &h) in spectrum.iter_mut().zip(part) {
+                    bin.re = h;
+                }
+                fft.forward(&mut spectrum);
+                spectrum
+            })
+            .collect();
+        let count = partitions.len().max(1);
+
+        Convolver {
+            block,
+            fft,
+            partitions,
+            history: vec![vec![Complex::ZERO; size]; count],
+            head: 0,
+            input: vec![0.0; size],
+            fill: 0,
+            output: vec![0.0; block],
+            scratch: vec![Complex::ZERO; size],
+            accum: vec![Complex::ZERO; size],
+        }
+    }
+
+    pub fn process_sample(&mut self, sample: f32) -> f32 {
+        let out = self.output[self.fill];
+        self.input[self.block + self.fill] = sample;
+        self.fill += 1;
+        if self.fill == self.block {
+            self.fill = 0;
+            self.compute_block();
+        }
+        out
+    }
+
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|s| *s = 0.0);
+        self.output.iter_mut().for_each(|s| *s = 0.0);
+        for spectrum in self.history.iter_mut() {
+            spectrum.iter_mut().for_each(|c| *c = Complex::ZERO);
+        }
+        self.fill = 0;
+    }
+
+    fn compute_block(&mut self) {
+        for (bin, &x) in self.scratch.iter_mut().zip(self.input.iter()) {
+            *bin = Complex::new(x, 0.0);
+        }
+        self.fft.forward(&mut self.scratch);
+
+        let count = self.history.len();
+        self.head = (self.head + count - 1) % count;
+        self.history[self.head].copy_from_slice(&self.scratch);
+
+        self.accum.iter_mut().for_each(|c| *c = Complex::ZERO);
+        for (p, h) in self.partitions.iter().enumerate() {
+            let x = &self.history[(self.head + p) % count];
+            for ((acc, &x), &h) in self.accum.iter_mut().zip(x.iter()).zip(h.iter()) {
+                *acc += x * h;
+            }
+        }
+        self.fft.inverse(&mut self.accum);
+
+        // Only the second half is free of circular wrap-around
+        for (out, bin) in self.output.iter_mut().zip(self.accum[self.block..].iter()) {
+            *out = bin.re;
+        }
+        self.input.copy_within(self.block.., 0);
+    }
+}
+
+/// Reads a WAV file into one `Vec` per channel, resampled to `sample_rate`.
+pub fn read_wav_channels(path: &Path, sample_rate: f32) -> Result<Vec<Vec<f32>>> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open WAV file {}", path.display()))?;
+    let spec = reader.spec();
+
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mut data: Vec<Vec<f32>> = (0..channels)
+        .map(|ch| {
+            interleaved
+                .iter()
+                .skip(ch)
+                .step_by(channels)
+                .copied()
+                .collect()
+        })
+        .collect();
+
+    if spec.sample_rate as f32 != sample_rate {
+        info!(
+            "Resampling {} from {} Hz to {} Hz",
+            path.display(),
+            spec.sample_rate,
+            sample_rate
+        );
+        let ratio = spec.sample_rate as f64 / sample_rate as f64;
+        for channel in data.iter_mut() {
+            *channel = resample_linear(channel, ratio);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Offline linear interpolation, good enough for loading impulse responses.
+fn resample_linear(input: &[f32], ratio: f64) -> Vec<f32> {
+    let length = (input.len() as f64 / ratio).floor() as usize;
+    (0..length)
+        .map(|i| {
+            let pos = i as f64 * ratio;
+            let index = pos as usize;
+            let frac = (pos - index as f64) as f32;
+            let a = input[index];
+            let b = input.get(index + 1).copied().unwrap_or(0.0);
+            a + (b - a) * frac
+        })
+        .collect()
+}
+
+/// Reverb from a recorded room impulse response. A mono IR is used for every
+/// channel, otherwise IR channels are matched to buffer channels.
+pub struct ConvolutionReverb {
+    impulse: Vec<Vec<f32>>,
+    block: usize,
+    convolvers: Vec<Convolver>,
+    mix: f32,
+}
+
+impl ConvolutionReverb {
+    pub fn new(mut impulse: Vec<Vec<f32>>, block: usize) -> Self {
+        impulse.retain(|channel| !channel.is_empty());
+        if impulse.is_empty() {
+            impulse.push(vec![0.0]);
+        }
+
+        // Normalize to unit energy on the loudest channel
+        let energy = impulse
+            .iter()
+            .map(|ch| ch.iter().map(|s| s * s).sum::<f32>())
+            .fold(0.0f32, f32::max);
+        if energy > 0.0 {
+            let scale = 1.0 / energy.sqrt();
+            for sample in impulse.iter_mut().flatten() {
+                *sample *= scale;
+            }
+        }
+
+        let convolvers = impulse.iter().map(|ir| Convolver::new(ir, block)).collect();
+        ConvolutionReverb {
+            impulse,
+            block,
+            convolvers,
+            mix: 0.25,
+        }
+    }
+
+    pub fn from_wav(path: &Path, sample_rate: f32) -> Result<Self> {
+        let mut impulse = read_wav_channels(path, sample_rate)?;
+        let max_len = (MAX_IR_SECONDS * sample_rate) as usize;
+        for channel in impulse.iter_mut() {
+            channel.truncate(max_len);
+        }
+        info!(
+            "Loaded impulse response {}: {} channel(s), {:.2} s",
+            path.display(),
+            impulse.len(),
+            impulse[0].len() as f32 / sample_rate
+        );
+        Ok(ConvolutionReverb::new(impulse, 512))
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+impl Processor for ConvolutionReverb {
+    fn name(&self) -> &'static str {
+        "convolution_reverb"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        while self.convolvers.len() < channels {
+            let ir = &self.impulse[self.convolvers.len() % self.impulse.len()];
+            self.convolvers.push(Convolver::new(ir, self.block));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, convolver) in frame.iter_mut().zip(self.convolvers.iter_mut()) {
+                let reverb = convolver.process_sample(*sample);
+                *sample = *sample * dry + reverb * wet;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.convolvers.iter_mut().for_each(Convolver::reset);
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![ParamInfo::new("mix", self.mix, 0.0, 1.0)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if name == "mix" {
+            self.set_mix(value);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::biquad::{BUTTERWORTH_Q, Biquad, Coefficients};
+use super::{Processor, db_to_gain, gain_to_db, time_coefficient};
+use crate::params::ParamInfo;
+
+/// Sibilance is short, the detector follows it closely.
+const ATTACK_MS: f32 = 1.0;
+const RELEASE_MS: f32 = 60.0;
+/// Slope above the threshold, reduction is capped by `reduction` anyway.
+const RATIO: f32 = 4.0;
+
+/// Split-band de-esser for a vocal. The signal is split at `frequency`
+/// and only the band above it is turned down, while its level is over
+/// `threshold`, by at most `reduction` dB. Below the split the voice is
+/// left alone, and with nothing to reduce the bands sum back to the
+/// input. All channels share one detector, like the compressor.
+pub struct DeEsser {
+    sample_rate: f32,
+    frequency: f32,
+    threshold_db: f32,
+    reduction_db: f32,
+
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Smoothed level of the band above the split, in dB.
+    envelope_db: f32,
+    /// Low-pass splitting off the band below the split, per channel,
+    /// with the band above it from the last frame.
+    split: Coefficients,
+    filters: Vec<Biquad>,
+    highs: Vec<f32>,
+}
+
+impl DeEsser {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut deesser = DeEsser {
+            sample_rate,
+            frequency: 0.0,
+            threshold_db: -30.0,
+            reduction_db: 6.0,
+            attack_coeff: time_coefficient(ATTACK_MS, sample_rate),
+            release_coeff: time_coefficient(RELEASE_MS, sample_rate),
+            envelope_db: -120.0,
+            split: Coefficients::default(),
+            filters: Vec::new(),
+            highs: Vec::new(),
+        };
+        deesser.set_frequency(6000.0);
+        deesser
+    }
+
+    /// Lower edge of the band that's detected and reduced.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.clamp(2000.0, 12000.0);
+        self.split = Coefficients::low_pass(self.frequency, BUTTERWORTH_Q, self.sample_rate);
+    }
+
+    pub fn set_threshold(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db.clamp(-60.0, 0.0);
+    }
+
+    /// Most the band is turned down, in dB.
+    pub fn set_reduction(&mut self, reduction_db: f32) {
+        self.reduction_db = reduction_db.clamp(0.0, 24.0);
+    }
+}
+
+impl Processor for DeEsser {
+    fn name(&self) -> &'static str {
+        "deesser"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.filters.len() < channels {
+            self.filters.resize(channels, Biquad::default());
+            self.highs.resize(channels, 0.0);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        for frame in buffer.chunks_mut(channels) {
+            let mut key = 0.0f32;
+            for ((sample, filter), high) in frame
+                .iter()
+                .zip(self.filters.iter_mut())
+                .zip(self.highs.iter_mut())
+            {
+                *high = sample - filter.process(&self.split, *sample);
+                key = key.max(high.abs());
+            }
+
+            let level_db = gain_to_db(key);
+            let coeff = if level_db > self.envelope_db {
+                self.attack_coeff
+            } else {
+                self.release_coeff
+            };
+            self.envelope_db = coeff * self.envelope_db + (1.0 - coeff) * level_db;
+
+            let over = (self.envelope_db - self.threshold_db).max(0.0);
+            let reduction_db = (over * (1.0 - 1.0 / RATIO)).min(self.reduction_db);
+            let cut = 1.0 - db_to_gain(-reduction_db);
+            for (sample, high) in frame.iter_mut().zip(&self.highs) {
+                *sample -= high * cut;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.envelope_db = -120.0;
+        self.filters.iter_mut().for_each(Biquad::reset);
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("frequency", self.frequency, 2000.0, 12000.0),
+            ParamInfo::new("threshold", self.threshold_db, -60.0, 0.0),
+            ParamInfo::new("reduction", self.reduction_db, 0.0, 24.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "frequency" => self.set_frequency(value),
+            "threshold" => self.set_threshold(value),
+            "reduction" => self.set_reduction(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use super::Processor;
+use crate::params::{Param, ParamInfo};
+use std::sync::Arc;
+
+const MAX_DELAY_SECONDS: f32 = 4.0;
+
+/// Note length relative to the tempo, for tempo-synced delay times.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoteDivision {
+    Whole,
+    Half,
+    Quarter,
+    Eighth,
+    Sixteenth,
+    DottedQuarter,
+    DottedEighth,
+    TripletQuarter,
+    TripletEighth,
+}
+
+impl NoteDivision {
+    /// In the order of their index, e.g. the tremolo's `sync` parameter.
+    pub const ALL: [NoteDivision; 9] = [
+        NoteDivision::Whole,
+        NoteDivision::Half,
+        NoteDivision::Quarter,
+        NoteDivision::Eighth,
+        NoteDivision::Sixteenth,
+        NoteDivision::DottedQuarter,
+        NoteDivision::DottedEighth,
+        NoteDivision::TripletQuarter,
+        NoteDivision::TripletEighth,
+    ];
+
+    /// Length in quarter-note beats.
+    pub fn beats(self) -> f32 {
+        match self {
+            NoteDivision::Whole => 4.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::DottedQuarter => 1.5,
+            NoteDivision::DottedEighth => 0.75,
+            NoteDivision::TripletQuarter => 2.0 / 3.0,
+            NoteDivision::TripletEighth => 1.0 / 3.0,
+        }
+    }
+
+    /// Parses `1/4`, `1/8.` (dotted) or `1/8t` (triplet).
+    pub fn parse(text: &str) -> Option<Self> {
+        let division = match text.trim() {
+            "1/1" => NoteDivision::Whole,
+            "1/2" => NoteDivision::Half,
+            "1/4" => NoteDivision::Quarter,
+            "1/8" => NoteDivision::Eighth,
+            "1/16" => NoteDivision::Sixteenth,
+            "1/4." => NoteDivision::DottedQuarter,
+            "1/8." => NoteDivision::DottedEighth,
+            "1/4t" => NoteDivision::TripletQuarter,
+            "1/8t" => NoteDivision::TripletEighth,
+            _ => return None,
+        };
+        Some(division)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelayTime {
+    Millis(f32),
+    Note(NoteDivision),
+}
+
+struct DelayLine {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl DelayLine {
+    fn new(length: usize) -> Self {
+        DelayLine {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+        }
+    }
+
+    /// Sample written `delay` writes ago, counting the upcoming write.
+    fn read(&self, delay: usize) -> f32 {
+        let length = self.buffer.len();
+        let delay = delay.clamp(1, length - 1);
+        self.buffer[(self.index + 1 + length - delay) % length]
+    }
+
+    fn write(&mut self, sample: f32) {
+        let length = self.buffer.len();
+        self.index = (self.index + 1) % length;
+        self.buffer[self.index] = sample;
+    }
+}
+
+/// Echo with feedback. In ping-pong mode the input is summed to mono and
+/// the repeats bounce between the first two channels. A note length time
+/// follows the engine tempo, set with `tempo` or `tap`.
+pub struct Delay {
+    sample_rate: f32,
+    time: DelayTime,
+    tempo: Arc<Param>,
+    /// The tempo as last read, once a block.
+    bpm: f32,
+    feedback: f32,
+    mix: f32,
+    ping_pong: bool,
+
+    delay_samples: usize,
+    lines: Vec<DelayLine>,
+}
+
+impl Delay {
+    pub fn new(sample_rate: f32, tempo: Arc<Param>) -> Self {
+        let mut delay = Delay {
+            sample_rate,
+            time: DelayTime::Millis(120.0),
+            bpm: tempo.get(),
+            tempo,
+            feedback: 0.3,
+            mix: 0.25,
+            ping_pong: false,
+            delay_samples: 0,
+            lines: Vec::new(),
+        };
+        delay.update_delay();
+        delay
+    }
+
+    pub fn set_time(&mut self, time: DelayTime) {
+        self.time = time;
+        self.update_delay();
+    }
+
+    /// Feedback amount, capped below 1.0 so the repeats always decay.
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0.0, 0.95);
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.ping_pong = ping_pong;
+    }
+
+    /// Current delay time in milliseconds after tempo sync.
+    pub fn delay_ms(&self) -> f32 {
+        match self.time {
+            DelayTime::Millis(ms) => ms,
+            DelayTime::Note(division) => division.beats() * 60_000.0 / self.bpm,
+        }
+    }
+
+    /// 0 is in milliseconds, from 1 the note lengths of
+    /// `NoteDivision::ALL`.
+    fn sync_index(&self) -> f32 {
+        match self.time {
+            DelayTime::Millis(_) => 0.0,
+            DelayTime::Note(division) => NoteDivision::ALL
+                .iter()
+                .position(|&d| d == division)
+                .map_or(0.0, |index| (index + 1) as f32),
+        }
+    }
+
+    fn update_delay(&mut self) {
+        let max = (MAX_DELAY_SECONDS * self.sample_rate) as usize;
+        let samples = (self.delay_ms().max(0.0) * 0.001 * self.sample_rate) as usize;
+        self.delay_samples = samples.clamp(1, max);
+    }
+}
+
+impl Processor for Delay {
+    fn name(&self) -> &'static str {
+        "delay"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        let length = (MAX_DELAY_SECONDS * self.sample_rate) as usize + 1;
+        while self.lines.len() < channels {
+            self.lines.push(DelayLine::new(length));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        let bpm = self.tempo.get();
+        if bpm != self.bpm {
+            self.bpm = bpm;
+            self.update_delay();
+        }
+
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        let ping_pong = self.ping_pong && channels >= 2;
+
+        for frame in buffer.chunks_mut(channels) {
+            if ping_pong {
+                let left = self.lines[0].read(self.delay_samples);
+                let right = self.lines[1].read(self.delay_samples);
+                let mono = frame.iter().sum::<f32>() / channels as f32;
+                self.lines[0].write(mono + right * self.feedback);
+                self.lines[1].write(left * self.feedback);
+
+                frame[0] = frame[0] * dry + left * wet;
+                frame[1] = frame[1] * dry + right * wet;
+            } else {
+                for (sample, line) in frame.iter_mut().zip(self.lines.iter_mut()) {
+                    let echo = line.read(self.delay_samples);
+                    line.write(*sample + echo * self.feedback);
+                    *sample = *sample * dry + echo * wet;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in self.lines.iter_mut() {
+            line.buffer.iter_mut().for_each(|s| *s = 0.0);
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let last = NoteDivision::ALL.len() as f32;
+        vec![
+            ParamInfo::new("time", self.delay_ms(), 1.0, MAX_DELAY_SECONDS * 1000.0),
+            ParamInfo::new("sync", self.sync_index(), 0.0, last),
+            ParamInfo::new("feedback", self.feedback, 0.0, 0.95),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+            ParamInfo::new("ping_pong", self.ping_pong as u8 as f32, 0.0, 1.0),
+        ]
+    }
+
+    /// Setting `time` switches a tempo-synced delay to milliseconds, so
+    /// does `sync` 0, keeping the current time.
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "time" => self.set_time(DelayTime::Millis(value)),
+            "sync" => {
+                let index = value.round().clamp(0.0, NoteDivision::ALL.len() as f32) as usize;
+                let time = match index.checked_sub(1) {
+                    Some(index) => DelayTime::Note(NoteDivision::ALL[index]),
+                    None => DelayTime::Millis(self.delay_ms()),
+                };
+                self.set_time(time);
+            }
+            "feedback" => self.set_feedback(value),
+            "mix" => self.set_mix(value),
+            "ping_pong" => self.set_ping_pong(value >= 0.5),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use super::fft::{Complex, Fft};
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use std::f32::consts::PI;
+
+/// Analysis frame length, rounded up to a power of two.
+const FRAME_SECONDS: f32 = 0.01;
+/// Rise of the noise estimate while the signal stays above it, so a
+/// louder room is picked up within a few seconds.
+const NOISE_RISE_DB_PER_SECOND: f32 = 3.0;
+/// Power smoothing between frames, higher is slower.
+const POWER_SMOOTHING: f32 = 0.7;
+/// Gain smoothing between frames when the gain falls, keeps the leftover
+/// noise from warbling.
+const GAIN_RELEASE: f32 = 0.6;
+
+/// Per-channel analysis state.
+struct DenoiseChannel {
+    /// Last frame of input, the newest hop at the end.
+    input: Vec<f32>,
+    /// Overlap-added output, the first hop is ready to play.
+    output: Vec<f32>,
+    fill: usize,
+    /// Smoothed power, noise estimate and gain per bin.
+    power: Vec<f32>,
+    noise: Vec<f32>,
+    gains: Vec<f32>,
+}
+
+impl DenoiseChannel {
+    fn new(frame: usize) -> Self {
+        let bins = frame / 2 + 1;
+        DenoiseChannel {
+            input: vec![0.0; frame],
+            output: vec![0.0; frame],
+            fill: 0,
+            power: vec![0.0; bins],
+            noise: vec![f32::INFINITY; bins],
+            gains: vec![1.0; bins],
+        }
+    }
+}
+
+/// Spectral subtraction noise suppressor for a mic in a noisy room. The
+/// noise floor is tracked per frequency as the minimum of the smoothed
+/// spectrum, so fans and hum are learned without a noise-only sample and
+/// followed when they change. `strength` sets how much of the estimate
+/// is taken out, and `floor` how far down a bin can be pulled, higher
+/// sounds more natural.
+pub struct NoiseSuppressor {
+    strength: f32,
+    floor_db: f32,
+    floor: f32,
+    frame: usize,
+    hop: usize,
+    /// Per-frame multiplier on the noise estimate while it rises.
+    rise: f32,
+    fft: Fft,
+    /// Square-root Hann, applied before and after the transform so the
+    /// 50% overlap sums back to the input.
+    window: Vec<f32>,
+    spectrum: Vec<Complex>,
+    channels: Vec<DenoiseChannel>,
+}
+
+impl NoiseSuppressor {
+    pub fn new(sample_rate: f32) -> Self {
+        let frame = ((sample_rate * FRAME_SECONDS) as usize).next_power_of_two();
+        let hop = frame / 2;
+        let window = (0..frame)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / frame as f32).cos()).sqrt())
+            .collect();
+        let frames_per_second = sample_rate / hop as f32;
+        let mut suppressor = NoiseSuppressor {
+            strength: 0.5,
+            floor_db: -20.0,
+            floor: 0.0,
+            frame,
+            hop,
+            rise: 10f32.powf(NOISE_RISE_DB_PER_SECOND / frames_per_second / 10.0),
+            fft: Fft::new(frame),
+            window,
+            spectrum: vec![Complex::default(); frame],
+            channels: Vec::new(),
+        };
+        suppressor.set_floor(suppressor.floor_db);
+        suppressor
+    }
+
+    /// 0.0 leaves the signal alone, 1.0 takes out twice the estimated
+    /// noise.
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(0.0, 1.0);
+    }
+
+    /// Most a frequency is turned down by, in dB.
+    pub fn set_floor(&mut self, floor_db: f32) {
+        self.floor_db = floor_db.clamp(-60.0, 0.0);
+        self.floor = db_to_gain(self.floor_db);
+    }
+
+    /// Runs the last frame of `channel` through the suppression,
+    /// overlap-adding the result.
+    fn process_frame(&mut self, channel: usize) {
+        let state = &mut self.channels[channel];
+        for ((bin, &sample), &window) in
+            self.spectrum.iter_mut().zip(&state.input).zip(&self.window)
+        {
+            *bin = Complex::new(sample * window, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        let over = 2.0 * self.strength;
+        let bins = self.frame / 2 + 1;
+        for bin in 0..bins {
+            let power = self.spectrum[bin].norm_sqr();
+            let smoothed = POWER_SMOOTHING * state.power[bin] + (1.0 - POWER_SMOOTHING) * power;
+            state.power[bin] = smoothed;
+            state.noise[bin] = smoothed.min(state.noise[bin] * self.rise);
+
+            let gain = if smoothed > 0.0 {
+                (1.0 - over * state.noise[bin] / smoothed).max(0.0).sqrt()
+            } else {
+                1.0
+            }
+            .max(self.floor);
+            // Opens at once, closes smoothed
+            let previous = state.gains[bin];
+            state.gains[bin] = if gain >= previous {
+                gain
+            } else {
+                GAIN_RELEASE * previous + (1.0 - GAIN_RELEASE) * gain
+            };
+        }
+        for bin in 0..bins {
+            let gain = state.gains[bin];
+            self.spectrum[bin] = self.spectrum[bin].scale(gain);
+            // Mirrored bins keep the output real
+            if bin > 0 && bin < self.frame / 2 {
+                let mirror = self.frame - bin;
+                self.spectrum[mirror] = self.spectrum[mirror].scale(gain);
+            }
+        }
+        self.fft.inverse(&mut self.spectrum);
+
+        state.output.copy_within(self.hop.., 0);
+        state.output[self.frame - self.hop..].fill(0.0);
+        for ((out, bin), &window) in state
+            .output
+            .iter_mut()
+            .zip(&self.spectrum)
+            .zip(&self.window)
+        {
+            *out += bin.re * window;
+        }
+        state.input.copy_within(self.hop.., 0);
+    }
+}
+
+impl Processor for NoiseSuppressor {
+    fn name(&self) -> &'static str {
+        "denoise"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        while self.channels.len() < channels {
+            self.channels.push(DenoiseChannel::new(self.frame));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let start = self.frame - self.hop;
+        for frame in buffer.chunks_mut(channels) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let state = &mut self.channels[channel];
+                state.input[start + state.fill] = *sample;
+                *sample = state.output[state.fill];
+                state.fill += 1;
+                if state.fill == self.hop {
+                    state.fill = 0;
+                    self.process_frame(channel);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for state in self.channels.iter_mut() {
+            state.input.fill(0.0);
+            state.output.fill(0.0);
+            state.fill = 0;
+            state.power.fill(0.0);
+            state.noise.fill(f32::INFINITY);
+            state.gains.fill(1.0);
+        }
+    }
+
+    /// A full frame, half to fill it and half for the overlap.
+    fn latency(&self) -> usize {
+        self.frame
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("strength", self.strength, 0.0, 1.0),
+            ParamInfo::new("floor", self.floor_db, -60.0, 0.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "strength" => self.set_strength(value),
+            "floor" => self.set_floor(value),
+            _ => {}
+        }
+    }
+}
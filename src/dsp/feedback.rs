@@ -0,0 +1,354 @@
+use super::biquad::{Biquad, Coefficients};
+use super::fft::{Complex, Fft};
+use super::{Processor, gain_to_db};
+use crate::params::{AtomicF32, ParamInfo};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Most notches engaged at once, the oldest is reused after that.
+pub const MAX_NOTCHES: usize = 8;
+const FFT_SIZE: usize = 4096;
+const HOP: usize = FFT_SIZE / 2;
+/// Bins either side of a peak its prominence is measured against, the
+/// closest few left out as part of the peak.
+const NEIGHBOURHOOD: usize = 24;
+const PEAK_WIDTH: usize = 3;
+/// Quieter peaks are never feedback worth notching.
+const MIN_LEVEL_DB: f32 = -50.0;
+/// Peaks followed at once.
+const MAX_CANDIDATES: usize = 16;
+/// Narrow enough to leave the voice around a notch alone.
+const NOTCH_Q: f32 = 30.0;
+const FIRST_DEPTH_DB: f32 = -6.0;
+/// A notch that rings again is made deeper by this much.
+const DEPTH_STEP_DB: f32 = -3.0;
+/// Detections closer than this to a notch, about a semitone, deepen it
+/// instead of adding another.
+const SAME_NOTCH_RATIO: f32 = 1.06;
+
+/// Notches the suppressor engaged, readable from any thread.
+#[derive(Debug, Default)]
+pub struct FeedbackView {
+    /// Frequency and depth in dB of every slot, frequency 0 when free.
+    notches: [(AtomicF32, AtomicF32); MAX_NOTCHES],
+    /// Suppressors alive, two for a moment while a reloaded chain is
+    /// swapped in.
+    attached: AtomicUsize,
+    reset: AtomicBool,
+}
+
+impl FeedbackView {
+    /// Whether a suppressor is running in the chain.
+    pub fn is_attached(&self) -> bool {
+        self.attached.load(Ordering::Relaxed) > 0
+    }
+
+    /// Engaged notches as `(frequency, depth_db)`, lowest first.
+    pub fn notches(&self) -> Vec<(f32, f32)> {
+        let mut notches: Vec<(f32, f32)> = self
+            .notches
+            .iter()
+            .map(|(frequency, depth)| (frequency.get(), depth.get()))
+            .filter(|&(frequency, _)| frequency > 0.0)
+            .collect();
+        notches.sort_by(|a, b| a.0.total_cmp(&b.0));
+        notches
+    }
+
+    /// Asks the suppressor to drop every notch.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Notch {
+    frequency: f32,
+    depth_db: f32,
+    /// When it was engaged or last deepened, the lowest is reused first.
+    order: u64,
+    coefficients: Coefficients,
+}
+
+/// Peak seen in consecutive analyses, hits 0 when the slot is free.
+#[derive(Clone, Copy, Default)]
+struct Candidate {
+    bin: usize,
+    hits: u32,
+    seen: bool,
+}
+
+/// Feedback destroyer. Watches the spectrum for narrow peaks standing
+/// well above their surroundings for a while, the ringing of a mic
+/// feeding back through the speakers, and drops a narrow notch on each.
+/// A notch that rings again gets deeper. `feedback reset` clears them.
+pub struct FeedbackSuppressor {
+    sample_rate: f32,
+    view: Arc<FeedbackView>,
+    /// Rise over the neighbouring bins that counts as ringing, in dB.
+    threshold_db: f32,
+    /// Time a peak has to last before it's notched.
+    hold_ms: f32,
+    /// Deepest a notch goes, in dB.
+    depth_db: f32,
+
+    fft: Fft,
+    window: Vec<f32>,
+    scale: f32,
+    /// The newest `FFT_SIZE` samples of the mono sum.
+    frame: Vec<f32>,
+    fill: usize,
+    spectrum: Vec<Complex>,
+    magnitudes: Vec<f32>,
+    candidates: [Candidate; MAX_CANDIDATES],
+    notches: [Option<Notch>; MAX_NOTCHES],
+    engaged: u64,
+    /// Filter history per channel, one per notch slot.
+    filters: Vec<[Biquad; MAX_NOTCHES]>,
+    /// Whether the view shows this suppressor's notches yet, rather than
+    /// those of the chain it replaced.
+    published: bool,
+}
+
+impl FeedbackSuppressor {
+    pub fn new(sample_rate: f32, view: Arc<FeedbackView>) -> Self {
+        view.attached.fetch_add(1, Ordering::Relaxed);
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos())
+            .collect();
+        let scale = 2.0 / window.iter().sum::<f32>();
+        FeedbackSuppressor {
+            sample_rate,
+            view,
+            threshold_db: 15.0,
+            hold_ms: 250.0,
+            depth_db: -24.0,
+            fft: Fft::new(FFT_SIZE),
+            window,
+            scale,
+            frame: vec![0.0; FFT_SIZE],
+            fill: 0,
+            spectrum: vec![Complex::ZERO; FFT_SIZE],
+            magnitudes: vec![0.0; FFT_SIZE / 2 + 1],
+            candidates: [Candidate::default(); MAX_CANDIDATES],
+            notches: [None; MAX_NOTCHES],
+            engaged: 0,
+            filters: Vec::new(),
+            published: false,
+        }
+    }
+
+    /// Analyses a peak has to be found in a row to be notched.
+    fn hold_frames(&self) -> u32 {
+        ((self.hold_ms / 1000.0 * self.sample_rate / HOP as f32).ceil() as u32).max(2)
+    }
+
+    fn analyze(&mut self) {
+        for ((bin, &sample), &w) in self.spectrum.iter_mut().zip(&self.frame).zip(&self.window) {
+            *bin = Complex::new(sample * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(&self.spectrum) {
+            *magnitude = gain_to_db(bin.norm() * self.scale);
+        }
+
+        self.candidates.iter_mut().for_each(|c| c.seen = false);
+        let m = &self.magnitudes;
+        for bin in NEIGHBOURHOOD..m.len() - NEIGHBOURHOOD {
+            if m[bin] < MIN_LEVEL_DB || m[bin] <= m[bin - 1] || m[bin] < m[bin + 1] {
+                continue;
+            }
+            let around = (bin - NEIGHBOURHOOD..=bin + NEIGHBOURHOOD)
+                .filter(|&b| b.abs_diff(bin) > PEAK_WIDTH)
+                .map(|b| m[b])
+                .sum::<f32>()
+                / (2 * (NEIGHBOURHOOD - PEAK_WIDTH)) as f32;
+            if m[bin] - around < self.threshold_db {
+                continue;
+            }
+            let tracked = self
+                .candidates
+                .iter_mut()
+                .find(|c| c.hits > 0 && c.bin.abs_diff(bin) <= 1);
+            match tracked {
+                Some(candidate) => {
+                    candidate.bin = bin;
+                    candidate.hits += 1;
+                    candidate.seen = true;
+                }
+                None => {
+                    if let Some(free) = self.candidates.iter_mut().find(|c| c.hits == 0) {
+                        *free = Candidate {
+                            bin,
+                            hits: 1,
+                            seen: true,
+                        };
+                    }
+                }
+            }
+        }
+
+        let hold = self.hold_frames();
+        for index in 0..MAX_CANDIDATES {
+            let candidate = self.candidates[index];
+            if !candidate.seen {
+                self.candidates[index].hits = 0;
+            } else if candidate.hits >= hold {
+                self.candidates[index].hits = 0;
+                let frequency = self.peak_frequency(candidate.bin);
+                self.engage(frequency);
+            }
+        }
+    }
+
+    /// Frequency of the peak at `bin`, refined between bins with a
+    /// parabola through it and its neighbours.
+    fn peak_frequency(&self, bin: usize) -> f32 {
+        let (left, centre, right) = (
+            self.magnitudes[bin - 1],
+            self.magnitudes[bin],
+            self.magnitudes[bin + 1],
+        );
+        let curve = left - 2.0 * centre + right;
+        let offset = if curve.abs() > f32::EPSILON {
+            (0.5 * (left - right) / curve).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        (bin as f32 + offset) * self.sample_rate / FFT_SIZE as f32
+    }
+
+    fn engage(&mut self, frequency: f32) {
+        self.engaged += 1;
+        let existing = self.notches.iter().position(|n| {
+            n.is_some_and(|n| {
+                let ratio = frequency.max(n.frequency) / frequency.min(n.frequency);
+                ratio < SAME_NOTCH_RATIO
+            })
+        });
+        let (slot, depth_db) = match existing {
+            Some(slot) => {
+                let notch = self.notches[slot].unwrap();
+                (slot, (notch.depth_db + DEPTH_STEP_DB).max(self.depth_db))
+            }
+            None => {
+                let slot = self
+                    .notches
+                    .iter()
+                    .position(Option::is_none)
+                    .unwrap_or_else(|| {
+                        (0..MAX_NOTCHES)
+                            .min_by_key(|&i| self.notches[i].map_or(0, |n| n.order))
+                            .unwrap()
+                    });
+                self.filters
+                    .iter_mut()
+                    .for_each(|f| f[slot] = Biquad::default());
+                (slot, FIRST_DEPTH_DB.max(self.depth_db))
+            }
+        };
+        self.notches[slot] = Some(Notch {
+            frequency,
+            depth_db,
+            order: self.engaged,
+            coefficients: Coefficients::peaking(frequency, NOTCH_Q, depth_db, self.sample_rate),
+        });
+        self.publish();
+    }
+
+    fn clear(&mut self) {
+        self.notches = [None; MAX_NOTCHES];
+        self.candidates = [Candidate::default(); MAX_CANDIDATES];
+        for filters in self.filters.iter_mut() {
+            *filters = [Biquad::default(); MAX_NOTCHES];
+        }
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        self.published = true;
+        for (notch, (frequency, depth)) in self.notches.iter().zip(&self.view.notches) {
+            let (f, d) = notch.map_or((0.0, 0.0), |n| (n.frequency, n.depth_db));
+            frequency.set(f);
+            depth.set(d);
+        }
+    }
+}
+
+impl Processor for FeedbackSuppressor {
+    fn name(&self) -> &'static str {
+        "feedback"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.filters.len() < channels {
+            self.filters
+                .resize(channels, [Biquad::default(); MAX_NOTCHES]);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        if self.view.reset.swap(false, Ordering::Relaxed) {
+            self.clear();
+        } else if !self.published {
+            self.publish();
+        }
+
+        for frame in buffer.chunks_mut(channels) {
+            self.frame[FFT_SIZE - HOP + self.fill] = frame.iter().sum::<f32>() / channels as f32;
+            self.fill += 1;
+            if self.fill == HOP {
+                self.fill = 0;
+                self.analyze();
+                self.frame.copy_within(HOP.., 0);
+            }
+
+            for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                for (notch, state) in self.notches.iter().zip(filters.iter_mut()) {
+                    if let Some(notch) = notch {
+                        *sample = state.process(&notch.coefficients, *sample);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps the notches, they're what stops the ringing.
+    fn reset(&mut self) {
+        self.candidates = [Candidate::default(); MAX_CANDIDATES];
+        for filters in self.filters.iter_mut() {
+            *filters = [Biquad::default(); MAX_NOTCHES];
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("threshold", self.threshold_db, 6.0, 30.0),
+            ParamInfo::new("hold", self.hold_ms, 50.0, 2000.0),
+            ParamInfo::new("depth", self.depth_db, -48.0, -6.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.threshold_db = value.clamp(6.0, 30.0),
+            "hold" => self.hold_ms = value.clamp(50.0, 2000.0),
+            "depth" => self.depth_db = value.clamp(-48.0, -6.0),
+            _ => {}
+        }
+    }
+}
+
+impl Drop for FeedbackSuppressor {
+    fn drop(&mut self) {
+        if self.view.attached.fetch_sub(1, Ordering::Relaxed) > 1 {
+            return;
+        }
+        for (frequency, depth) in &self.view.notches {
+            frequency.set(0.0);
+            depth.set(0.0);
+        }
+    }
+}
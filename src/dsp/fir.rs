@@ -0,0 +1,144 @@
+use super::convolution::{Convolver, read_wav_channels};
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// Filters longer than this are truncated, 1.4 s at 48 kHz is far past
+/// what room correction needs.
+const MAX_TAPS: usize = 65536;
+/// Convolver block, the processor's latency on top of the filter's own.
+const BLOCK: usize = 256;
+
+/// Reads filter taps from a text file, the way REW and rePhase export
+/// them: one number per line or several split by commas, semicolons or
+/// spaces. Lines starting with `#`, `*`, `;` or `%` are comments.
+pub fn read_text_taps(path: &Path) -> Result<Vec<f32>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read filter {}", path.display()))?;
+    let mut taps = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', '*', ';', '%']) {
+            continue;
+        }
+        for value in line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+        {
+            let tap: f32 = value.parse().with_context(|| {
+                format!("Invalid coefficient '{}' on line {}", value, index + 1)
+            })?;
+            taps.push(tap);
+        }
+    }
+    if taps.is_empty() {
+        bail!("No coefficients in {}", path.display());
+    }
+    Ok(taps)
+}
+
+/// Measured room or speaker correction: FIR filters loaded from a WAV
+/// file or a text file of coefficients. A mono filter is used for every
+/// channel, otherwise filter channels are matched to buffer channels. The
+/// taps are used as given, unlike the reverb's impulse response which is
+/// normalised, so `gain` is there to make room for the filter's boosts.
+pub struct FirFilter {
+    taps: Vec<Vec<f32>>,
+    convolvers: Vec<Convolver>,
+    gain_db: f32,
+    gain: f32,
+}
+
+impl FirFilter {
+    pub fn new(mut taps: Vec<Vec<f32>>) -> Self {
+        taps.retain(|channel| !channel.is_empty());
+        if taps.is_empty() {
+            taps.push(vec![1.0]);
+        }
+        for channel in taps.iter_mut() {
+            channel.truncate(MAX_TAPS);
+        }
+        let convolvers = taps.iter().map(|t| Convolver::new(t, BLOCK)).collect();
+        FirFilter {
+            taps,
+            convolvers,
+            gain_db: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Loads a `.wav` file, resampled to `sample_rate`, or a text file.
+    /// Text files carry no rate, so they have to be designed for the
+    /// output's.
+    pub fn load(path: &Path, sample_rate: f32) -> Result<Self> {
+        let wav = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"));
+        let taps = if wav {
+            read_wav_channels(path, sample_rate)?
+        } else {
+            vec![read_text_taps(path)?]
+        };
+        let filter = FirFilter::new(taps);
+        info!(
+            "Loaded FIR filter {}: {} channel(s), {} taps{}",
+            path.display(),
+            filter.taps.len(),
+            filter.taps[0].len(),
+            if wav {
+                String::new()
+            } else {
+                format!(", taken to be at {} Hz", sample_rate)
+            }
+        );
+        Ok(filter)
+    }
+
+    pub fn set_gain(&mut self, gain_db: f32) {
+        self.gain_db = gain_db.clamp(-24.0, 12.0);
+        self.gain = db_to_gain(self.gain_db);
+    }
+}
+
+impl Processor for FirFilter {
+    fn name(&self) -> &'static str {
+        "fir"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        while self.convolvers.len() < channels {
+            let taps = &self.taps[self.convolvers.len() % self.taps.len()];
+            self.convolvers.push(Convolver::new(taps, BLOCK));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, convolver) in frame.iter_mut().zip(self.convolvers.iter_mut()) {
+                *sample = convolver.process_sample(*sample) * self.gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.convolvers.iter_mut().for_each(Convolver::reset);
+    }
+
+    fn latency(&self) -> usize {
+        BLOCK
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![ParamInfo::new("gain", self.gain_db, -24.0, 12.0)]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if name == "gain" {
+            self.set_gain(value);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use super::{Processor, db_to_gain, time_coefficient};
+use crate::params::ParamInfo;
+
+/// Per-channel state, channels open and close independently.
+#[derive(Clone, Default)]
+struct GateChannel {
+    envelope: f32,
+    gain: f32,
+    open: bool,
+    hold_left: usize,
+}
+
+/// Mutes a channel while its level stays below the threshold. The gate
+/// opens at `threshold_db` and only closes again once the level falls below
+/// `threshold_db - hysteresis_db`, so it doesn't chatter around the edge.
+pub struct NoiseGate {
+    sample_rate: f32,
+    threshold_db: f32,
+    hysteresis_db: f32,
+    attack_ms: f32,
+    hold_ms: f32,
+    release_ms: f32,
+
+    open_threshold: f32,
+    close_threshold: f32,
+    attack_step: f32,
+    release_step: f32,
+    hold_samples: usize,
+    detector_coeff: f32,
+    channels: Vec<GateChannel>,
+}
+
+impl NoiseGate {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut gate = NoiseGate {
+            sample_rate,
+            threshold_db: -50.0,
+            hysteresis_db: 6.0,
+            attack_ms: 1.0,
+            hold_ms: 80.0,
+            release_ms: 150.0,
+            open_threshold: 0.0,
+            close_threshold: 0.0,
+            attack_step: 0.0,
+            release_step: 0.0,
+            hold_samples: 0,
+            detector_coeff: time_coefficient(10.0, sample_rate),
+            channels: Vec::new(),
+        };
+        gate.update_coefficients();
+        gate
+    }
+
+    pub fn set_threshold(&mut self, threshold_db: f32) {
+        self.threshold_db = threshold_db;
+        self.update_coefficients();
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis_db: f32) {
+        self.hysteresis_db = hysteresis_db.max(0.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_attack(&mut self, attack_ms: f32) {
+        self.attack_ms = attack_ms.max(0.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_hold(&mut self, hold_ms: f32) {
+        self.hold_ms = hold_ms.max(0.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_release(&mut self, release_ms: f32) {
+        self.release_ms = release_ms.max(0.0);
+        self.update_coefficients();
+    }
+
+    fn update_coefficients(&mut self) {
+        self.open_threshold = db_to_gain(self.threshold_db);
+        self.close_threshold = db_to_gain(self.threshold_db - self.hysteresis_db);
+        // Linear gain ramps, a 0 ms time jumps straight to the target
+        self.attack_step = ramp_step(self.attack_ms, self.sample_rate);
+        self.release_step = ramp_step(self.release_ms, self.sample_rate);
+        self.hold_samples = (self.hold_ms * 0.001 * self.sample_rate) as usize;
+    }
+}
+
+fn ramp_step(ms: f32, sample_rate: f32) -> f32 {
+    let samples = ms * 0.001 * sample_rate;
+    if samples < 1.0 { 1.0 } else { 1.0 / samples }
+}
+
+impl Processor for NoiseGate {
+    fn name(&self) -> &'static str {
+        "gate"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels.len() != channels {
+            self.channels.resize(channels, GateChannel::default());
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(self.channels.iter_mut()) {
+                // Peak detector: instant rise, smoothed fall
+                let level = sample.abs();
+                state.envelope = if level > state.envelope {
+                    level
+                } else {
+                    self.detector_coeff * state.envelope + (1.0 - self.detector_coeff) * level
+                };
+
+                if state.envelope >= self.open_threshold {
+                    state.open = true;
+                    state.hold_left = self.hold_samples;
+                } else if state.open && state.envelope < self.close_threshold {
+                    if state.hold_left > 0 {
+                        state.hold_left -= 1;
+                    } else {
+                        state.open = false;
+                    }
+                }
+
+                state.gain = if state.open {
+                    (state.gain + self.attack_step).min(1.0)
+                } else {
+                    (state.gain - self.release_step).max(0.0)
+                };
+
+                *sample *= state.gain;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.channels.clear();
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("threshold", self.threshold_db, -96.0, 0.0),
+            ParamInfo::new("hysteresis", self.hysteresis_db, 0.0, 24.0),
+            ParamInfo::new("attack", self.attack_ms, 0.0, 500.0),
+            ParamInfo::new("hold", self.hold_ms, 0.0, 2000.0),
+            ParamInfo::new("release", self.release_ms, 0.0, 5000.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "threshold" => self.set_threshold(value),
+            "hysteresis" => self.set_hysteresis(value),
+            "attack" => self.set_attack(value),
+            "hold" => self.set_hold(value),
+            "release" => self.set_release(value),
+            _ => {}
+        }
+    }
+}
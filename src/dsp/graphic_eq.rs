@@ -0,0 +1,125 @@
+use super::Processor;
+use super::biquad::{Biquad, Coefficients};
+use crate::params::ParamInfo;
+
+/// ISO 266 third-octave centres and their parameter names, written the
+/// way they're printed on a graphic EQ's faders. `1k25` rather than
+/// `1.25k` keeps the dot free for the parameter key.
+pub const BANDS: [(f32, &str); 31] = [
+    (20.0, "20"),
+    (25.0, "25"),
+    (31.5, "31"),
+    (40.0, "40"),
+    (50.0, "50"),
+    (63.0, "63"),
+    (80.0, "80"),
+    (100.0, "100"),
+    (125.0, "125"),
+    (160.0, "160"),
+    (200.0, "200"),
+    (250.0, "250"),
+    (315.0, "315"),
+    (400.0, "400"),
+    (500.0, "500"),
+    (630.0, "630"),
+    (800.0, "800"),
+    (1000.0, "1k"),
+    (1250.0, "1k25"),
+    (1600.0, "1k6"),
+    (2000.0, "2k"),
+    (2500.0, "2k5"),
+    (3150.0, "3k15"),
+    (4000.0, "4k"),
+    (5000.0, "5k"),
+    (6300.0, "6k3"),
+    (8000.0, "8k"),
+    (10000.0, "10k"),
+    (12500.0, "12k5"),
+    (16000.0, "16k"),
+    (20000.0, "20k"),
+];
+
+/// Q of a third-octave band.
+const BAND_Q: f32 = 4.32;
+const MAX_GAIN_DB: f32 = 12.0;
+
+/// 31-band graphic EQ, a peaking filter on each ISO third-octave centre
+/// with ±12 dB. Bands are parameters named by frequency, `geq.1k25`, so a
+/// MIDI fader or an OSC address can be mapped to each. Flat bands are
+/// skipped.
+pub struct GraphicEq {
+    sample_rate: f32,
+    gains: [f32; 31],
+    coefficients: [Coefficients; 31],
+    /// Every band per channel.
+    filters: Vec<[Biquad; 31]>,
+}
+
+impl GraphicEq {
+    pub fn new(sample_rate: f32) -> Self {
+        GraphicEq {
+            sample_rate,
+            gains: [0.0; 31],
+            coefficients: [Coefficients::default(); 31],
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn set_band(&mut self, band: usize, gain_db: f32) {
+        let gain_db = gain_db.clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+        self.gains[band] = gain_db;
+        self.coefficients[band] =
+            Coefficients::peaking(BANDS[band].0, BAND_Q, gain_db, self.sample_rate);
+        if gain_db == 0.0 {
+            // Skipped while flat, so it starts clean when moved again
+            for filters in self.filters.iter_mut() {
+                filters[band].reset();
+            }
+        }
+    }
+}
+
+impl Processor for GraphicEq {
+    fn name(&self) -> &'static str {
+        "geq"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.filters.len() < channels {
+            self.filters.resize(channels, [Biquad::default(); 31]);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                for (band, filter) in filters.iter_mut().enumerate() {
+                    if self.gains[band] != 0.0 {
+                        *sample = filter.process(&self.coefficients[band], *sample);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filters in self.filters.iter_mut() {
+            filters.iter_mut().for_each(Biquad::reset);
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        BANDS
+            .iter()
+            .zip(self.gains)
+            .map(|(&(_, name), gain)| ParamInfo::new(name, gain, -MAX_GAIN_DB, MAX_GAIN_DB))
+            .collect()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if let Some(band) = BANDS.iter().position(|&(_, n)| n == name) {
+            self.set_band(band, value);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::pitch::PhaseVocoder;
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use std::f32::consts::FRAC_PI_4;
+
+pub const MAX_VOICES: usize = 3;
+
+/// Interval, level and pan parameter names of each voice.
+const VOICE_PARAMS: [[&str; 3]; MAX_VOICES] = [
+    ["voice1.interval", "voice1.level", "voice1.pan"],
+    ["voice2.interval", "voice2.level", "voice2.pan"],
+    ["voice3.interval", "voice3.level", "voice3.pan"],
+];
+
+/// Interval, level in dB and pan each voice starts at: a minor third
+/// left, a fifth right, an octave down in the middle.
+const DEFAULTS: [(f32, f32, f32); MAX_VOICES] =
+    [(3.0, -6.0, -0.5), (7.0, -6.0, 0.5), (-12.0, -9.0, 0.0)];
+
+struct Voice {
+    shifter: PhaseVocoder,
+    interval: f32,
+    level_db: f32,
+    pan: f32,
+    gain: f32,
+    /// Gains into the first two channels, from the level and pan.
+    gains: (f32, f32),
+}
+
+impl Voice {
+    fn new((interval, level_db, pan): (f32, f32, f32)) -> Self {
+        let mut voice = Voice {
+            shifter: PhaseVocoder::new(),
+            interval: 0.0,
+            level_db,
+            pan,
+            gain: 0.0,
+            gains: (0.0, 0.0),
+        };
+        voice.set_interval(interval);
+        voice.update_gains();
+        voice
+    }
+
+    fn set_interval(&mut self, semitones: f32) {
+        self.interval = semitones.clamp(-24.0, 24.0);
+        self.shifter.set_ratio(2f32.powf(self.interval / 12.0));
+    }
+
+    /// Constant power, so a voice keeps its loudness wherever it's panned.
+    fn update_gains(&mut self) {
+        self.gain = db_to_gain(self.level_db);
+        let angle = (self.pan + 1.0) * FRAC_PI_4;
+        self.gains = (self.gain * angle.cos(), self.gain * angle.sin());
+    }
+}
+
+/// Backing vocals from the lead: up to three pitch-shifted copies of the
+/// input, each at its own interval in semitones, level and pan. A fraction
+/// of a semitone with no other voices doubles the lead instead.
+///
+/// The voices come from the mono sum of the input and go to the first two
+/// channels, a mono chain gets them unpanned. The lead passes straight
+/// through, the voices run a vocoder frame behind it, about 30 ms, which
+/// sounds like a second singer rather than a delay.
+pub struct Harmonizer {
+    voices: Vec<Voice>,
+}
+
+impl Harmonizer {
+    /// `voices` is clamped to 1..=MAX_VOICES.
+    pub fn new(voices: usize) -> Self {
+        Harmonizer {
+            voices: DEFAULTS[..voices.clamp(1, MAX_VOICES)]
+                .iter()
+                .map(|&defaults| Voice::new(defaults))
+                .collect(),
+        }
+    }
+
+    pub fn set_interval(&mut self, voice: usize, semitones: f32) {
+        if let Some(voice) = self.voices.get_mut(voice) {
+            voice.set_interval(semitones);
+        }
+    }
+
+    pub fn set_level_db(&mut self, voice: usize, level_db: f32) {
+        if let Some(voice) = self.voices.get_mut(voice) {
+            voice.level_db = level_db.clamp(-60.0, 6.0);
+            voice.update_gains();
+        }
+    }
+
+    pub fn set_pan(&mut self, voice: usize, pan: f32) {
+        if let Some(voice) = self.voices.get_mut(voice) {
+            voice.pan = pan.clamp(-1.0, 1.0);
+            voice.update_gains();
+        }
+    }
+}
+
+impl Processor for Harmonizer {
+    fn name(&self) -> &'static str {
+        "harmonizer"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let scale = 1.0 / channels as f32;
+        for frame in buffer.chunks_mut(channels) {
+            let input = frame.iter().sum::<f32>() * scale;
+            let (mut mono, mut left, mut right) = (0.0, 0.0, 0.0);
+            for voice in &mut self.voices {
+                let shifted = voice.shifter.process_sample(input);
+                mono += shifted * voice.gain;
+                left += shifted * voice.gains.0;
+                right += shifted * voice.gains.1;
+            }
+            match frame {
+                [sample] => *sample += mono,
+                [l, r, ..] => {
+                    *l += left;
+                    *r += right;
+                }
+                [] => {}
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for voice in &mut self.voices {
+            voice.shifter.reset();
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params = Vec::new();
+        for (voice, names) in self.voices.iter().zip(VOICE_PARAMS) {
+            params.push(ParamInfo::new(names[0], voice.interval, -24.0, 24.0));
+            params.push(ParamInfo::new(names[1], voice.level_db, -60.0, 6.0));
+            params.push(ParamInfo::new(names[2], voice.pan, -1.0, 1.0));
+        }
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let Some(voice) = VOICE_PARAMS.iter().position(|names| names.contains(&name)) else {
+            return;
+        };
+        match name.split_once('.') {
+            Some((_, "interval")) => self.set_interval(voice, value),
+            Some((_, "level")) => self.set_level_db(voice, value),
+            Some((_, "pan")) => self.set_pan(voice, value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::{Processor, db_to_gain, time_coefficient};
+use std::collections::VecDeque;
+
+/// Brickwall peak limiter. The signal is delayed by the lookahead time so
+/// the gain can ramp down *before* a transient arrives instead of clipping
+/// its leading edge.
+///
+/// The required gain per frame goes through a sliding minimum over the
+/// lookahead window and then a box filter of the same length; the box filter
+/// output at the delayed sample is an average of values that are all below
+/// that sample's required gain, so the ceiling is never exceeded.
+pub struct Limiter {
+    ceiling: f32,
+    release_coeff: f32,
+    lookahead: usize,
+
+    channels: usize,
+    /// Interleaved delay line holding `lookahead` frames.
+    delay: Vec<f32>,
+    /// Recent (index, required gain) pairs, increasing gains front to back.
+    window_min: VecDeque<(usize, f32)>,
+    /// Last `lookahead + 1` envelope values for the box filter.
+    smooth: Vec<f32>,
+    smooth_sum: f64,
+    envelope: f32,
+    position: usize,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: f32, ceiling_db: f32, lookahead_ms: f32) -> Self {
+        let lookahead = (lookahead_ms.max(0.0) * 0.001 * sample_rate).round() as usize;
+        Limiter {
+            ceiling: db_to_gain(ceiling_db.min(0.0)),
+            release_coeff: time_coefficient(50.0, sample_rate),
+            lookahead,
+            channels: 0,
+            delay: Vec::new(),
+            window_min: VecDeque::with_capacity(lookahead + 1),
+            smooth: vec![1.0; lookahead + 1],
+            smooth_sum: (lookahead + 1) as f64,
+            envelope: 1.0,
+            position: 0,
+        }
+    }
+
+    fn required_gain(&self, frame: &[f32]) -> f32 {
+        let peak = frame.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+        if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Processor for Limiter {
+    fn name(&self) -> &'static str {
+        "limiter"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels != channels {
+            self.channels = channels;
+            self.delay = vec![0.0; self.lookahead * channels];
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        let window = self.lookahead + 1;
+
+        for frame in buffer.chunks_mut(channels) {
+            let index = self.position;
+            self.position = self.position.wrapping_add(1);
+
+            // Sliding minimum of the required gain over the lookahead window
+            let required = self.required_gain(frame);
+            while self.window_min.back().is_some_and(|&(_, g)| g >= required) {
+                self.window_min.pop_back();
+            }
+            self.window_min.push_back((index, required));
+            while self
+                .window_min
+                .front()
+                .is_some_and(|&(i, _)| index.wrapping_sub(i) >= window)
+            {
+                self.window_min.pop_front();
+            }
+            let target = self.window_min.front().map_or(1.0, |&(_, g)| g);
+
+            // Instant attack (the box filter provides the ramp), smooth release
+            self.envelope = if target < self.envelope {
+                target
+            } else {
+                target + self.release_coeff * (self.envelope - target)
+            };
+
+            let slot = index % window;
+            self.smooth_sum += (self.envelope - self.smooth[slot]) as f64;
+            self.smooth[slot] = self.envelope;
+            let gain = (self.smooth_sum / window as f64) as f32;
+
+            // Swap the incoming frame with the one leaving the delay line
+            if self.lookahead > 0 {
+                let offset = (index % self.lookahead) * channels;
+                let delayed = &mut self.delay[offset..offset + channels];
+                for (sample, stored) in frame.iter_mut().zip(delayed.iter_mut()) {
+                    std::mem::swap(sample, stored);
+                }
+            }
+
+            for sample in frame.iter_mut() {
+                // Clamp guards against float rounding in the running sum
+                *sample = (*sample * gain).clamp(-self.ceiling, self.ceiling);
+            }
+        }
+    }
+
+    /// The lookahead.
+    fn latency(&self) -> usize {
+        self.lookahead
+    }
+
+    fn reset(&mut self) {
+        self.delay.iter_mut().for_each(|s| *s = 0.0);
+        self.window_min.clear();
+        self.smooth.iter_mut().for_each(|s| *s = 1.0);
+        self.smooth_sum = self.smooth.len() as f64;
+        self.envelope = 1.0;
+    }
+}
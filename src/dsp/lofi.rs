@@ -0,0 +1,195 @@
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use std::f32::consts::PI;
+
+/// Ring modulator: the input multiplied by a sine at `frequency`, leaving
+/// the sum and difference of every partial with it for the robot and
+/// bell voices. `mix` blends it with the dry signal and `trim` sets the
+/// level coming out.
+pub struct RingModulator {
+    sample_rate: f32,
+    frequency: f32,
+    mix: f32,
+    trim_db: f32,
+    trim: f32,
+    /// 0 to 1.
+    phase: f32,
+}
+
+impl RingModulator {
+    pub fn new(sample_rate: f32) -> Self {
+        RingModulator {
+            sample_rate,
+            frequency: 30.0,
+            mix: 1.0,
+            trim_db: 0.0,
+            trim: 1.0,
+            phase: 0.0,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.clamp(1.0, 5000.0);
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_trim(&mut self, trim_db: f32) {
+        self.trim_db = trim_db.clamp(-24.0, 12.0);
+        self.trim = db_to_gain(self.trim_db);
+    }
+}
+
+impl Processor for RingModulator {
+    fn name(&self) -> &'static str {
+        "ringmod"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let increment = self.frequency / self.sample_rate;
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        for frame in buffer.chunks_mut(channels) {
+            let carrier = (2.0 * PI * self.phase).sin();
+            for sample in frame.iter_mut() {
+                *sample = (*sample * dry + *sample * carrier * wet) * self.trim;
+            }
+            self.phase += increment;
+            if self.phase >= 1.0 {
+                self.phase -= 1.0;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("frequency", self.frequency, 1.0, 5000.0),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+            ParamInfo::new("trim", self.trim_db, -24.0, 12.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "frequency" => self.set_frequency(value),
+            "mix" => self.set_mix(value),
+            "trim" => self.set_trim(value),
+            _ => {}
+        }
+    }
+}
+
+/// Bitcrusher: the input held at `rate` Hz and rounded to `bits`, with
+/// the aliasing and grit that brings. Both can be fractional for a
+/// smooth sweep. `mix` and `trim` as for the ring modulator.
+pub struct Bitcrusher {
+    sample_rate: f32,
+    bits: f32,
+    rate: f32,
+    mix: f32,
+    trim_db: f32,
+    trim: f32,
+
+    /// Held frame per channel and the time until the next one, in
+    /// held-sample periods.
+    held: Vec<f32>,
+    countdown: f32,
+}
+
+impl Bitcrusher {
+    pub fn new(sample_rate: f32) -> Self {
+        Bitcrusher {
+            sample_rate,
+            bits: 8.0,
+            rate: (sample_rate / 4.0).min(22050.0),
+            mix: 1.0,
+            trim_db: 0.0,
+            trim: 1.0,
+            held: Vec::new(),
+            countdown: 0.0,
+        }
+    }
+
+    pub fn set_bits(&mut self, bits: f32) {
+        self.bits = bits.clamp(1.0, 16.0);
+    }
+
+    /// Rate the input is held at, up to the stream's own.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.clamp(200.0, self.sample_rate);
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_trim(&mut self, trim_db: f32) {
+        self.trim_db = trim_db.clamp(-24.0, 12.0);
+        self.trim = db_to_gain(self.trim_db);
+    }
+}
+
+impl Processor for Bitcrusher {
+    fn name(&self) -> &'static str {
+        "bitcrusher"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.held.len() < channels {
+            self.held.resize(channels, 0.0);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        // Half the levels above zero, half below
+        let levels = 2f32.powf(self.bits - 1.0);
+        let step = self.rate / self.sample_rate;
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        for frame in buffer.chunks_mut(channels) {
+            self.countdown -= step;
+            let sample_now = self.countdown <= 0.0;
+            if sample_now {
+                self.countdown += 1.0;
+            }
+            for (sample, held) in frame.iter_mut().zip(self.held.iter_mut()) {
+                if sample_now {
+                    *held = (*sample * levels).round() / levels;
+                }
+                *sample = (*sample * dry + *held * wet) * self.trim;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.held.fill(0.0);
+        self.countdown = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("bits", self.bits, 1.0, 16.0),
+            ParamInfo::new("rate", self.rate, 200.0, self.sample_rate),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+            ParamInfo::new("trim", self.trim_db, -24.0, 12.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "bits" => self.set_bits(value),
+            "rate" => self.set_rate(value),
+            "mix" => self.set_mix(value),
+            "trim" => self.set_trim(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+
+/// Longest loop, the buffers are this long from the start so recording
+/// never allocates.
+const MAX_LOOP_SECONDS: f32 = 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Empty,
+    /// First pass, which sets the loop length.
+    Recording,
+    Playing,
+    /// Playing and adding the input as a new layer.
+    Overdubbing,
+}
+
+/// Live looper for a one-person rig. The first `record` starts the loop,
+/// the next closes it and plays it back, later ones start and stop
+/// overdubs. `undo` takes off the last overdub and `clear` empties the
+/// loop. With a `length` in seconds the first pass closes by itself. The
+/// input always passes through, the loop is added to it.
+///
+/// The last overdub is kept apart so it can be undone, it's folded into
+/// the loop when the next one starts, a pass over the loop on the audio
+/// thread at that moment.
+pub struct Looper {
+    sample_rate: f32,
+    state: State,
+    /// Loop length in seconds, 0 to close it by hand.
+    length: f32,
+    level: f32,
+    level_db: f32,
+    /// Everything but the last overdub, and the last overdub.
+    base: Vec<f32>,
+    layer: Vec<f32>,
+    /// Whether `layer` holds anything to undo.
+    layered: bool,
+    /// Loop length and play position, in frames.
+    frames: usize,
+    position: usize,
+    channels: usize,
+}
+
+impl Looper {
+    pub fn new(sample_rate: f32) -> Self {
+        Looper {
+            sample_rate,
+            state: State::Empty,
+            length: 0.0,
+            level: 1.0,
+            level_db: 0.0,
+            base: Vec::new(),
+            layer: Vec::new(),
+            layered: false,
+            frames: 0,
+            position: 0,
+            channels: 0,
+        }
+    }
+
+    /// The looper's one button: record, play, overdub, play...
+    pub fn record(&mut self) {
+        self.state = match self.state {
+            State::Empty => {
+                self.position = 0;
+                self.layered = false;
+                State::Recording
+            }
+            State::Recording => self.close(),
+            State::Playing => {
+                if self.layered {
+                    let len = self.frames * self.channels;
+                    for (base, layer) in self.base[..len].iter_mut().zip(&mut self.layer[..len]) {
+                        *base += *layer;
+                        *layer = 0.0;
+                    }
+                }
+                self.layered = true;
+                State::Overdubbing
+            }
+            State::Overdubbing => State::Playing,
+        };
+    }
+
+    /// Takes off the last overdub, stopping it if it's still going.
+    pub fn undo(&mut self) {
+        if self.state == State::Overdubbing {
+            self.state = State::Playing;
+        }
+        if self.layered {
+            let len = self.frames * self.channels;
+            self.layer[..len].fill(0.0);
+            self.layered = false;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.state = State::Empty;
+        self.frames = 0;
+        self.position = 0;
+        self.layered = false;
+    }
+
+    /// Loop length for the first pass, 0 to close it with `record`.
+    pub fn set_length(&mut self, seconds: f32) {
+        self.length = seconds.clamp(0.0, MAX_LOOP_SECONDS);
+    }
+
+    pub fn set_level_db(&mut self, level_db: f32) {
+        self.level_db = level_db.clamp(-60.0, 6.0);
+        self.level = db_to_gain(self.level_db);
+    }
+
+    /// Ends the first pass where it got to, nothing recorded empties it.
+    fn close(&mut self) -> State {
+        self.frames = self.position;
+        self.position = 0;
+        if self.frames == 0 {
+            State::Empty
+        } else {
+            State::Playing
+        }
+    }
+
+    fn max_frames(&self) -> usize {
+        (MAX_LOOP_SECONDS * self.sample_rate) as usize
+    }
+}
+
+impl Processor for Looper {
+    fn name(&self) -> &'static str {
+        "looper"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels != channels {
+            let len = self.max_frames() * channels;
+            self.base = vec![0.0; len];
+            self.layer = vec![0.0; len];
+            self.channels = channels;
+            self.clear();
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if self.channels != channels {
+            return;
+        }
+        let limit = if self.length > 0.0 {
+            ((self.length * self.sample_rate) as usize).clamp(1, self.max_frames())
+        } else {
+            self.max_frames()
+        };
+
+        for frame in buffer.chunks_mut(channels) {
+            let start = self.position * channels;
+            match self.state {
+                State::Empty => continue,
+                State::Recording => {
+                    self.base[start..start + channels].copy_from_slice(frame);
+                    self.layer[start..start + channels].fill(0.0);
+                    self.position += 1;
+                    if self.position >= limit {
+                        self.state = self.close();
+                    }
+                    continue;
+                }
+                State::Playing | State::Overdubbing => {}
+            }
+            let base = &self.base[start..start + channels];
+            let layer = &mut self.layer[start..start + channels];
+            for ((sample, base), layer) in frame.iter_mut().zip(base).zip(layer.iter_mut()) {
+                let input = *sample;
+                *sample += (base + *layer) * self.level;
+                if self.state == State::Overdubbing {
+                    *layer += input;
+                }
+            }
+            self.position = (self.position + 1) % self.frames;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.clear();
+    }
+
+    /// `record`, `undo` and `clear` act each time they're set to 1.
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("record", 0.0, 0.0, 1.0),
+            ParamInfo::new("undo", 0.0, 0.0, 1.0),
+            ParamInfo::new("clear", 0.0, 0.0, 1.0),
+            ParamInfo::new("length", self.length, 0.0, MAX_LOOP_SECONDS),
+            ParamInfo::new("level", self.level_db, -60.0, 6.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "record" if value >= 0.5 => self.record(),
+            "undo" if value >= 0.5 => self.undo(),
+            "clear" if value >= 0.5 => self.clear(),
+            "length" => self.set_length(value),
+            "level" => self.set_level_db(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use super::Processor;
+use crate::params::ParamInfo;
+use anyhow::{Context, Result, bail};
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// Most frames handed to a plugin at once, callbacks are split to fit.
+const MAX_FRAMES: usize = 4096;
+
+const AUDIO_PORT: &CStr = c"http://lv2plug.in/ns/lv2core#AudioPort";
+const CONTROL_PORT: &CStr = c"http://lv2plug.in/ns/lv2core#ControlPort";
+const INPUT_PORT: &CStr = c"http://lv2plug.in/ns/lv2core#InputPort";
+const OUTPUT_PORT: &CStr = c"http://lv2plug.in/ns/lv2core#OutputPort";
+const CONNECTION_OPTIONAL: &CStr = c"http://lv2plug.in/ns/lv2core#connectionOptional";
+const URID_MAP: &CStr = c"http://lv2plug.in/ns/ext/urid#map";
+const URID_UNMAP: &CStr = c"http://lv2plug.in/ns/ext/urid#unmap";
+
+/// LV2 host on top of lilv. The installed plugins are scanned once, when
+/// the host is made, from the usual LV2_PATH locations.
+pub struct Lv2Host {
+    world: Arc<World>,
+}
+
+/// The lilv world and the features handed to every plugin.
+struct World {
+    world: *mut ffi::LilvWorld,
+    features: Box<[ffi::Feature; 2]>,
+    /// URIs mapped for plugins, the URID is the index plus one. It and
+    /// the two below are what the features point to.
+    _uris: Box<Mutex<Vec<CString>>>,
+    _map: Box<ffi::UridMap>,
+    _unmap: Box<ffi::UridUnmap>,
+}
+
+// lilv is only called while loading, on one thread
+unsafe impl Send for World {}
+unsafe impl Sync for World {}
+
+impl Lv2Host {
+    pub fn new() -> Result<Self> {
+        let world = unsafe { ffi::lilv_world_new() };
+        if world.is_null() {
+            bail!("Failed to start lilv");
+        }
+        unsafe { ffi::lilv_world_load_all(world) };
+
+        let uris = Box::new(Mutex::new(Vec::new()));
+        let handle = &*uris as *const Mutex<Vec<CString>> as *mut c_void;
+        let map = Box::new(ffi::UridMap {
+            handle,
+            map: urid_map,
+        });
+        let unmap = Box::new(ffi::UridUnmap {
+            handle,
+            unmap: urid_unmap,
+        });
+        let features = Box::new([
+            ffi::Feature {
+                uri: URID_MAP.as_ptr(),
+                data: &*map as *const ffi::UridMap as *mut c_void,
+            },
+            ffi::Feature {
+                uri: URID_UNMAP.as_ptr(),
+                data: &*unmap as *const ffi::UridUnmap as *mut c_void,
+            },
+        ]);
+        Ok(Lv2Host {
+            world: Arc::new(World {
+                world,
+                features,
+                _uris: uris,
+                _map: map,
+                _unmap: unmap,
+            }),
+        })
+    }
+
+    /// Instantiates the plugin with `uri` for `channels` channels at
+    /// `sample_rate`. A mono plugin on a stereo chain runs once per
+    /// channel with the same settings.
+    pub fn load(&self, uri: &str, sample_rate: f32, channels: usize) -> Result<Lv2Plugin> {
+        Lv2Plugin::new(self.world.clone(), uri, sample_rate, channels)
+            .with_context(|| format!("Failed to load the LV2 plugin {}", uri))
+    }
+}
+
+impl Drop for World {
+    fn drop(&mut self) {
+        unsafe { ffi::lilv_world_free(self.world) };
+    }
+}
+
+/// Owned lilv node, freed when dropped.
+struct Node(*mut ffi::LilvNode);
+
+impl Node {
+    fn uri(world: &World, uri: &CStr) -> Node {
+        Node(unsafe { ffi::lilv_new_uri(world.world, uri.as_ptr()) })
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        unsafe { ffi::lilv_node_free(self.0) };
+    }
+}
+
+enum Port {
+    AudioIn(u32),
+    AudioOut(u32),
+    ControlIn(u32),
+    /// Meters and the like, written by the plugin and not read.
+    ControlOut(u32),
+}
+
+struct Instance {
+    instance: *mut ffi::LilvInstance,
+    /// One value per port, control ports point into it.
+    controls: Box<[f32]>,
+}
+
+impl Instance {
+    fn descriptor(&self) -> &ffi::Descriptor {
+        unsafe { &*(*self.instance).lv2_descriptor }
+    }
+
+    fn handle(&self) -> *mut c_void {
+        unsafe { (*self.instance).lv2_handle }
+    }
+}
+
+/// LV2 effect plugin as a chain processor. Input control ports become
+/// parameters named by their port symbol, under the plugin's name in
+/// lower case with spaces as underscores, e.g. `calf_reverb.decay_time`.
+pub struct Lv2Plugin {
+    name: &'static str,
+    instances: Vec<Instance>,
+    /// Index of every input control port with its parameter.
+    params: Vec<(usize, ParamInfo)>,
+    channels: usize,
+    /// Planar buffers, one per chain channel. An instance per channel
+    /// uses its own pair.
+    inputs: Vec<Vec<f32>>,
+    outputs: Vec<Vec<f32>>,
+    /// Control output the plugin reports its latency on, in frames.
+    latency_port: Option<usize>,
+    _world: Arc<World>,
+}
+
+// Instances are made on the main thread and only run on the audio thread
+// afterwards, as LV2 allows
+unsafe impl Send for Lv2Plugin {}
+
+impl Lv2Plugin {
+    fn new(world: Arc<World>, uri: &str, sample_rate: f32, channels: usize) -> Result<Lv2Plugin> {
+        let uri_node = Node::uri(&world, &CString::new(uri)?);
+        let plugins = unsafe { ffi::lilv_world_get_all_plugins(world.world) };
+        let plugin = unsafe { ffi::lilv_plugins_get_by_uri(plugins, uri_node.0) };
+        if plugin.is_null() {
+            bail!("No LV2 plugin installed with that URI, see `lv2ls`");
+        }
+
+        let audio = Node::uri(&world, AUDIO_PORT);
+        let control = Node::uri(&world, CONTROL_PORT);
+        let input = Node::uri(&world, INPUT_PORT);
+        let output = Node::uri(&world, OUTPUT_PORT);
+        let optional = Node::uri(&world, CONNECTION_OPTIONAL);
+
+        let count = unsafe { ffi::lilv_plugin_get_num_ports(plugin) } as usize;
+        let mut mins = vec![0.0f32; count];
+        let mut maxes = vec![0.0f32; count];
+        let mut defaults = vec![0.0f32; count];
+        unsafe {
+            ffi::lilv_plugin_get_port_ranges_float(
+                plugin,
+                mins.as_mut_ptr(),
+                maxes.as_mut_ptr(),
+                defaults.as_mut_ptr(),
+            )
+        };
+
+        let mut ports = Vec::new();
+        let mut params = Vec::new();
+        for index in 0..count {
+            let port = unsafe { ffi::lilv_plugin_get_port_by_index(plugin, index as u32) };
+            let is = |class: &Node| unsafe { ffi::lilv_port_is_a(plugin, port, class.0) };
+            let symbol = unsafe {
+                CStr::from_ptr(ffi::lilv_node_as_string(ffi::lilv_port_get_symbol(
+                    plugin, port,
+                )))
+            }
+            .to_string_lossy()
+            .into_owned();
+            let index = index as u32;
+            let is_optional = unsafe { ffi::lilv_port_has_property(plugin, port, optional.0) };
+            let kind = match (is(&audio), is(&control), is(&input), is(&output)) {
+                // Sidechains and the like are left unconnected
+                (true, ..) if is_optional => continue,
+                (true, _, true, _) => Port::AudioIn(index),
+                (true, _, _, true) => Port::AudioOut(index),
+                (_, true, true, _) => Port::ControlIn(index),
+                (_, true, _, true) => Port::ControlOut(index),
+                _ if is_optional => continue,
+                _ => bail!("Port '{}' is of a type the host doesn't support", symbol),
+            };
+            if let Port::ControlIn(index) = kind {
+                let i = index as usize;
+                // Ports without a range get 0 to 1
+                let (min, max) = if mins[i].is_nan() || maxes[i].is_nan() || mins[i] >= maxes[i] {
+                    (0.0, 1.0)
+                } else {
+                    (mins[i], maxes[i])
+                };
+                let default = if defaults[i].is_nan() {
+                    min
+                } else {
+                    defaults[i].clamp(min, max)
+                };
+                defaults[i] = default;
+                let name: &'static str = Box::leak(symbol.into_boxed_str());
+                params.push((i, ParamInfo::new(name, default, min, max)));
+            }
+            ports.push(kind);
+        }
+
+        let audio_ins = ports
+            .iter()
+            .filter(|p| matches!(p, Port::AudioIn(_)))
+            .count();
+        let audio_outs = ports
+            .iter()
+            .filter(|p| matches!(p, Port::AudioOut(_)))
+            .count();
+        let copies = match (audio_ins, audio_outs) {
+            (ins, outs) if ins == channels && outs == channels => 1,
+            (1, 1) => channels,
+            (ins, outs) => bail!(
+                "{} audio inputs and {} outputs don't fit {} channels",
+                ins,
+                outs,
+                channels
+            ),
+        };
+
+        let features: Vec<*const ffi::Feature> = world
+            .features
+            .iter()
+            .map(|f| f as *const ffi::Feature)
+            .chain(std::iter::once(ptr::null()))
+            .collect();
+        let mut instances = Vec::new();
+        for _ in 0..copies {
+            let instance = unsafe {
+                ffi::lilv_plugin_instantiate(plugin, sample_rate as f64, features.as_ptr())
+            };
+            if instance.is_null() {
+                bail!("The plugin failed to instantiate, it may need host features");
+            }
+            instances.push(Instance {
+                instance,
+                controls: defaults.clone().into_boxed_slice(),
+            });
+        }
+
+        let name_node = unsafe { ffi::lilv_plugin_get_name(plugin) };
+        let name = unsafe { CStr::from_ptr(ffi::lilv_node_as_string(name_node)) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { ffi::lilv_node_free(name_node) };
+        info!(
+            "LV2 plugin: {}{}, {} parameters",
+            name,
+            if copies > 1 { " per channel" } else { "" },
+            params.len()
+        );
+
+        let latency_port = unsafe { ffi::lilv_plugin_has_latency(plugin) }
+            .then(|| unsafe { ffi::lilv_plugin_get_latency_port_index(plugin) } as usize);
+        let mut lv2 = Lv2Plugin {
+            name: Box::leak(key(&name).into_boxed_str()),
+            instances,
+            params,
+            channels,
+            inputs: vec![vec![0.0; MAX_FRAMES]; channels],
+            outputs: vec![vec![0.0; MAX_FRAMES]; channels],
+            latency_port,
+            _world: world,
+        };
+        lv2.connect(&ports);
+        for instance in &lv2.instances {
+            if let Some(activate) = instance.descriptor().activate {
+                unsafe { activate(instance.handle()) };
+            }
+            // An empty run has the plugin write its latency port
+            if lv2.latency_port.is_some() {
+                unsafe { (instance.descriptor().run)(instance.handle(), 0) };
+            }
+        }
+        Ok(lv2)
+    }
+
+    /// Points every port at its buffer. The buffers never move afterwards.
+    fn connect(&mut self, ports: &[Port]) {
+        let per_instance = self.channels / self.instances.len();
+        for (copy, instance) in self.instances.iter_mut().enumerate() {
+            let descriptor = unsafe { &*(*instance.instance).lv2_descriptor };
+            let handle = unsafe { (*instance.instance).lv2_handle };
+            let (mut audio_in, mut audio_out) = (copy * per_instance, copy * per_instance);
+            for port in ports {
+                let (index, data) = match *port {
+                    Port::AudioIn(index) => {
+                        audio_in += 1;
+                        (index, self.inputs[audio_in - 1].as_mut_ptr())
+                    }
+                    Port::AudioOut(index) => {
+                        audio_out += 1;
+                        (index, self.outputs[audio_out - 1].as_mut_ptr())
+                    }
+                    Port::ControlIn(index) | Port::ControlOut(index) => {
+                        (index, &mut instance.controls[index as usize] as *mut f32)
+                    }
+                };
+                unsafe { (descriptor.connect_port)(handle, index, data as *mut c_void) };
+            }
+        }
+    }
+}
+
+impl Processor for Lv2Plugin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if channels != self.channels {
+            // Ports are wired for the channel count given at load
+            return;
+        }
+        for block in buffer.chunks_mut(MAX_FRAMES * channels) {
+            let frames = block.len() / channels;
+            for (i, frame) in block.chunks_exact(channels).enumerate() {
+                for (input, &sample) in self.inputs.iter_mut().zip(frame) {
+                    input[i] = sample;
+                }
+            }
+            for instance in &self.instances {
+                unsafe { (instance.descriptor().run)(instance.handle(), frames as u32) };
+            }
+            for (i, frame) in block.chunks_exact_mut(channels).enumerate() {
+                for (sample, output) in frame.iter_mut().zip(&self.outputs) {
+                    *sample = output[i];
+                }
+            }
+        }
+    }
+
+    fn prepare(&mut self, _channels: usize) {
+        // Buffers are made at load, when the ports are connected
+    }
+
+    fn reset(&mut self) {
+        // LV2 has no reset, deactivating and activating again clears state
+        for instance in &self.instances {
+            let descriptor = instance.descriptor();
+            if let (Some(deactivate), Some(activate)) = (descriptor.deactivate, descriptor.activate)
+            {
+                unsafe {
+                    deactivate(instance.handle());
+                    activate(instance.handle());
+                }
+            }
+        }
+    }
+
+    fn latency(&self) -> usize {
+        match (self.latency_port, self.instances.first()) {
+            (Some(port), Some(instance)) => instance.controls[port].max(0.0) as usize,
+            _ => 0,
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        self.params.iter().map(|&(_, info)| info).collect()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let Some(&(port, _)) = self.params.iter().find(|(_, info)| info.name == name) else {
+            return;
+        };
+        for instance in self.instances.iter_mut() {
+            instance.controls[port] = value;
+        }
+    }
+}
+
+impl Drop for Lv2Plugin {
+    fn drop(&mut self) {
+        for instance in &self.instances {
+            if let Some(deactivate) = instance.descriptor().deactivate {
+                unsafe { deactivate(instance.handle()) };
+            }
+            unsafe { ffi::lilv_instance_free(instance.instance) };
+        }
+    }
+}
+
+/// Lower case with spaces as underscores.
+fn key(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+extern "C" fn urid_map(handle: *mut c_void, uri: *const c_char) -> u32 {
+    let uris = unsafe { &*(handle as *const Mutex<Vec<CString>>) };
+    let uri = unsafe { CStr::from_ptr(uri) };
+    let mut uris = uris.lock().unwrap();
+    match uris.iter().position(|known| known.as_c_str() == uri) {
+        Some(index) => index as u32 + 1,
+        None => {
+            uris.push(uri.to_owned());
+            uris.len() as u32
+        }
+    }
+}
+
+extern "C" fn urid_unmap(handle: *mut c_void, urid: u32) -> *const c_char {
+    let uris = unsafe { &*(handle as *const Mutex<Vec<CString>>) };
+    let uris = uris.lock().unwrap();
+    match urid.checked_sub(1).and_then(|i| uris.get(i as usize)) {
+        // The strings are never dropped before the world, so the pointer
+        // stays valid
+        Some(uri) => uri.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The parts of lilv and the LV2 headers the host uses. The `lilv_instance`
+/// calls are inline functions in lilv.h, so they're made through the
+/// descriptor here.
+mod ffi {
+    use std::ffi::{c_char, c_void};
+
+    #[repr(C)]
+    pub struct LilvWorld {
+        _private: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct LilvPlugins {
+        _private: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct LilvPlugin {
+        _private: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct LilvPort {
+        _private: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct LilvNode {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    pub struct LilvInstance {
+        pub lv2_descriptor: *const Descriptor,
+        pub lv2_handle: *mut c_void,
+        pub pimpl: *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct Descriptor {
+        pub uri: *const c_char,
+        pub instantiate: *const c_void,
+        pub connect_port: unsafe extern "C" fn(instance: *mut c_void, port: u32, data: *mut c_void),
+        pub activate: Option<unsafe extern "C" fn(instance: *mut c_void)>,
+        pub run: unsafe extern "C" fn(instance: *mut c_void, sample_count: u32),
+        pub deactivate: Option<unsafe extern "C" fn(instance: *mut c_void)>,
+        pub cleanup: *const c_void,
+        pub extension_data: *const c_void,
+    }
+
+    #[repr(C)]
+    pub struct Feature {
+        pub uri: *const c_char,
+        pub data: *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct UridMap {
+        pub handle: *mut c_void,
+        pub map: extern "C" fn(handle: *mut c_void, uri: *const c_char) -> u32,
+    }
+
+    #[repr(C)]
+    pub struct UridUnmap {
+        pub handle: *mut c_void,
+        pub unmap: extern "C" fn(handle: *mut c_void, urid: u32) -> *const c_char,
+    }
+
+    #[link(name = "lilv-0")]
+    unsafe extern "C" {
+        pub fn lilv_world_new() -> *mut LilvWorld;
+        pub fn lilv_world_load_all(world: *mut LilvWorld);
+        pub fn lilv_world_free(world: *mut LilvWorld);
+        pub fn lilv_world_get_all_plugins(world: *const LilvWorld) -> *const LilvPlugins;
+        pub fn lilv_new_uri(world: *mut LilvWorld, uri: *const c_char) -> *mut LilvNode;
+        pub fn lilv_node_free(node: *mut LilvNode);
+        pub fn lilv_node_as_string(node: *const LilvNode) -> *const c_char;
+        pub fn lilv_plugins_get_by_uri(
+            plugins: *const LilvPlugins,
+            uri: *const LilvNode,
+        ) -> *const LilvPlugin;
+        pub fn lilv_plugin_get_name(plugin: *const LilvPlugin) -> *mut LilvNode;
+        pub fn lilv_plugin_get_num_ports(plugin: *const LilvPlugin) -> u32;
+        pub fn lilv_plugin_get_port_ranges_float(
+            plugin: *const LilvPlugin,
+            min_values: *mut f32,
+            max_values: *mut f32,
+            def_values: *mut f32,
+        );
+        pub fn lilv_plugin_has_latency(plugin: *const LilvPlugin) -> bool;
+        pub fn lilv_plugin_get_latency_port_index(plugin: *const LilvPlugin) -> u32;
+        pub fn lilv_plugin_get_port_by_index(
+            plugin: *const LilvPlugin,
+            index: u32,
+        ) -> *const LilvPort;
+        pub fn lilv_port_is_a(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+            port_class: *const LilvNode,
+        ) -> bool;
+        pub fn lilv_port_has_property(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+            property: *const LilvNode,
+        ) -> bool;
+        pub fn lilv_port_get_symbol(
+            plugin: *const LilvPlugin,
+            port: *const LilvPort,
+        ) -> *const LilvNode;
+        pub fn lilv_plugin_instantiate(
+            plugin: *const LilvPlugin,
+            sample_rate: f64,
+            features: *const *const Feature,
+        ) -> *mut LilvInstance;
+        pub fn lilv_instance_free(instance: *mut LilvInstance);
+    }
+}
@@ -0,0 +1,156 @@
+use super::biquad::{BUTTERWORTH_Q, Biquad, Coefficients};
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+
+/// Corners of the shelves on each path.
+const LOW_SHELF_HZ: f32 = 200.0;
+const HIGH_SHELF_HZ: f32 = 4000.0;
+
+/// Gain and a two-shelf EQ on the mid or side signal.
+struct MsPath {
+    gain_db: f32,
+    low_db: f32,
+    high_db: f32,
+    gain: f32,
+    low: Coefficients,
+    high: Coefficients,
+    filters: [Biquad; 2],
+}
+
+impl MsPath {
+    fn new(sample_rate: f32) -> Self {
+        let mut path = MsPath {
+            gain_db: 0.0,
+            low_db: 0.0,
+            high_db: 0.0,
+            gain: 1.0,
+            low: Coefficients::default(),
+            high: Coefficients::default(),
+            filters: [Biquad::default(); 2],
+        };
+        path.update(sample_rate);
+        path
+    }
+
+    fn update(&mut self, sample_rate: f32) {
+        self.gain = db_to_gain(self.gain_db);
+        self.low = Coefficients::low_shelf(LOW_SHELF_HZ, self.low_db, sample_rate);
+        self.high = Coefficients::high_shelf(HIGH_SHELF_HZ, self.high_db, sample_rate);
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let x = self.filters[0].process(&self.low, x);
+        self.filters[1].process(&self.high, x) * self.gain
+    }
+}
+
+/// Mid/side matrix on the first two channels. The mid, what the two have
+/// in common, and the side, their difference, each get a gain and low and
+/// high shelves; `width` scales the side on top of that, 0 folds to mono
+/// and 2 doubles it. A centred mono vocal lives in the mid only, so the
+/// backing track's width changes without moving it. `side_cut` keeps the
+/// bass mono. Other channels pass through.
+pub struct MidSide {
+    sample_rate: f32,
+    width: f32,
+    side_cut_hz: f32,
+    mid: MsPath,
+    side: MsPath,
+    cut: Coefficients,
+    cut_filter: Biquad,
+}
+
+impl MidSide {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut midside = MidSide {
+            sample_rate,
+            width: 1.0,
+            side_cut_hz: 20.0,
+            mid: MsPath::new(sample_rate),
+            side: MsPath::new(sample_rate),
+            cut: Coefficients::default(),
+            cut_filter: Biquad::default(),
+        };
+        midside.set_side_cut(midside.side_cut_hz);
+        midside
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0.0, 2.0);
+    }
+
+    /// High-pass on the side, below it both channels carry the same bass.
+    pub fn set_side_cut(&mut self, frequency: f32) {
+        self.side_cut_hz = frequency.clamp(20.0, 500.0);
+        self.cut = Coefficients::high_pass(self.side_cut_hz, BUTTERWORTH_Q, self.sample_rate);
+    }
+}
+
+impl Processor for MidSide {
+    fn name(&self) -> &'static str {
+        "midside"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if channels < 2 {
+            return;
+        }
+        for frame in buffer.chunks_mut(channels) {
+            let mid = (frame[0] + frame[1]) * 0.5;
+            let side = (frame[0] - frame[1]) * 0.5;
+
+            let mid = self.mid.process(mid);
+            let side = self.cut_filter.process(&self.cut, side);
+            let side = self.side.process(side) * self.width;
+
+            frame[0] = mid + side;
+            frame[1] = mid - side;
+        }
+    }
+
+    fn reset(&mut self) {
+        for path in [&mut self.mid, &mut self.side] {
+            path.filters.iter_mut().for_each(Biquad::reset);
+        }
+        self.cut_filter.reset();
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("width", self.width, 0.0, 2.0),
+            ParamInfo::new("mid_gain", self.mid.gain_db, -12.0, 12.0),
+            ParamInfo::new("mid_low", self.mid.low_db, -12.0, 12.0),
+            ParamInfo::new("mid_high", self.mid.high_db, -12.0, 12.0),
+            ParamInfo::new("side_gain", self.side.gain_db, -12.0, 12.0),
+            ParamInfo::new("side_low", self.side.low_db, -12.0, 12.0),
+            ParamInfo::new("side_high", self.side.high_db, -12.0, 12.0),
+            ParamInfo::new("side_cut", self.side_cut_hz, 20.0, 500.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "width" => self.set_width(value),
+            "side_cut" => self.set_side_cut(value),
+            _ => {
+                // `mid_gain`, `side_low`...
+                let Some((path, setting)) = name.split_once('_') else {
+                    return;
+                };
+                let path = match path {
+                    "mid" => &mut self.mid,
+                    "side" => &mut self.side,
+                    _ => return,
+                };
+                let value = value.clamp(-12.0, 12.0);
+                match setting {
+                    "gain" => path.gain_db = value,
+                    "low" => path.low_db = value,
+                    "high" => path.high_db = value,
+                    _ => return,
+                }
+                path.update(self.sample_rate);
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+pub mod agc;
+pub mod alignment;
+pub mod amp;
+pub mod autotune;
+pub mod biquad;
+#[cfg(feature = "clap")]
+pub mod clap;
+pub mod compensation;
+pub mod compressor;
+pub mod conditioning;
+pub mod convolution;
+pub mod deesser;
+pub mod delay;
+pub mod denoise;
+pub mod feedback;
+pub mod fft;
+pub mod fir;
+pub mod gate;
+pub mod graphic_eq;
+pub mod harmonizer;
+pub mod limiter;
+pub mod lofi;
+pub mod looper;
+#[cfg(all(target_os = "linux", feature = "lv2"))]
+pub mod lv2;
+pub mod midside;
+pub mod modulation;
+pub mod multiband;
+pub mod pitch;
+pub mod reverb;
+pub mod saturation;
+pub mod speakers;
+pub mod tremolo;
+pub mod vocoder;
+
+pub use agc::AutoGain;
+pub use alignment::Alignment;
+pub use amp::AmpSim;
+pub use autotune::{AutoTune, Scale};
+#[cfg(feature = "clap")]
+pub use clap::ClapHost;
+pub use compensation::DelayCompensation;
+pub use compressor::Compressor;
+pub use conditioning::{DcBlocker, HighPass};
+pub use convolution::ConvolutionReverb;
+pub use deesser::DeEsser;
+pub use delay::{Delay, DelayTime, NoteDivision};
+pub use denoise::NoiseSuppressor;
+pub use feedback::{FeedbackSuppressor, FeedbackView};
+pub use fir::FirFilter;
+pub use gate::NoiseGate;
+pub use graphic_eq::GraphicEq;
+pub use harmonizer::Harmonizer;
+pub use limiter::Limiter;
+pub use lofi::{Bitcrusher, RingModulator};
+pub use looper::Looper;
+#[cfg(all(target_os = "linux", feature = "lv2"))]
+pub use lv2::Lv2Host;
+pub use midside::MidSide;
+pub use modulation::{Chorus, Flanger, Phaser};
+pub use multiband::Multiband;
+pub use pitch::PitchShifter;
+pub use reverb::Reverb;
+pub use saturation::{Curve, Saturation};
+pub use speakers::{SpeakerOutput, Speakers};
+pub use tremolo::{AutoPan, Tremolo};
+pub use vocoder::Vocoder;
+
+use crate::clip::ClipStats;
+use crate::headroom::StageLevels;
+use crate::load::ProcessorLoad;
+use crate::params::{Param, ParamInfo, ParamStore};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A block-based audio effect. Buffers are interleaved `[L, R, L, R...]`
+/// with `channels` samples per frame.
+pub trait Processor: Send {
+    fn name(&self) -> &'static str;
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize);
+
+    /// Allocates the per-channel state for `channels` ahead of streaming so
+    /// `process` doesn't have to on the audio thread.
+    fn prepare(&mut self, _channels: usize) {}
+
+    /// Clear any internal state (envelopes, delay lines...).
+    fn reset(&mut self) {}
+
+    /// Frames the output lags the input by, lined up with by the chain
+    /// when mixing in the dry signal.
+    fn latency(&self) -> usize {
+        0
+    }
+
+    /// Parameters that can be changed while streaming, with their current
+    /// values. Called once when the chain is bound to a `ParamStore`.
+    fn params(&self) -> Vec<ParamInfo> {
+        Vec::new()
+    }
+
+    /// Applies a parameter reported by `params`. Runs on the audio thread,
+    /// so it must not allocate or block.
+    fn set_param(&mut self, _name: &str, _value: f32) {}
+}
+
+/// Links a store entry to the processor it controls.
+struct Binding {
+    processor: usize,
+    name: &'static str,
+    param: Arc<Param>,
+    seen: u32,
+}
+
+/// Chain-level parameters every processor gets, handled by the chain
+/// itself.
+const BYPASS: &str = "bypass";
+const WET: &str = "wet";
+
+/// Longest block mixed with its dry signal at once, longer callbacks are
+/// split to fit the dry lines.
+const MAX_FRAMES: usize = 4096;
+
+/// A processor with the switches the chain puts around it.
+struct Slot {
+    processor: Box<dyn Processor>,
+    bypassed: bool,
+    /// Wet amount asked for, and the one reached, ramped over a block so
+    /// moving it doesn't click.
+    wet: f32,
+    current_wet: f32,
+    dry: DryLine,
+    /// Counts what clips on the way out, set when the chain is bound.
+    clips: Option<Arc<ClipStats>>,
+    /// Peaks in and out, set when bound with the headroom analysis on.
+    levels: Option<Arc<StageLevels>>,
+    /// Time taken per callback, set when the chain is bound.
+    load: Option<Arc<ProcessorLoad>>,
+}
+
+/// Input of a processor delayed by its latency, so the dry signal lines
+/// up with the processed one when mixed, and a bypassed processor keeps
+/// the timing of the chain.
+#[derive(Default)]
+struct DryLine {
+    samples: Vec<f32>,
+    write: usize,
+    /// In samples, latency frames times channels.
+    delay: usize,
+    channels: usize,
+}
+
+impl DryLine {
+    fn prepare(&mut self, latency: usize, channels: usize) {
+        let len = (MAX_FRAMES + latency) * channels;
+        if self.samples.len() != len {
+            self.samples = vec![0.0; len];
+        }
+        self.write = 0;
+        self.delay = latency * channels;
+        self.channels = channels;
+    }
+
+    fn is_ready(&self, channels: usize) -> bool {
+        !self.samples.is_empty() && self.channels == channels
+    }
+
+    fn push(&mut self, block: &[f32]) {
+        let len = self.samples.len();
+        for &sample in block {
+            self.samples[self.write] = sample;
+            self.write = (self.write + 1) % len;
+        }
+    }
+
+    /// Dry sample lined up with sample `index` of the last `count` pushed.
+    fn get(&self, count: usize, index: usize) -> f32 {
+        let len = self.samples.len();
+        self.samples[(self.write + 2 * len - count + index - self.delay) % len]
+    }
+
+    fn reset(&mut self) {
+        self.samples.fill(0.0);
+    }
+}
+
+/// Processors run in insertion order on the same buffer. Each can be
+/// bypassed or mixed with its own input, which is delayed to match when
+/// the processor adds latency.
+#[derive(Default)]
+pub struct DspChain {
+    slots: Vec<Slot>,
+    bindings: Vec<Binding>,
+}
+
+impl DspChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, processor: impl Processor + 'static) {
+        self.slots.push(Slot {
+            processor: Box::new(processor),
+            bypassed: false,
+            wet: 1.0,
+            current_wet: 1.0,
+            dry: DryLine::default(),
+            clips: None,
+            levels: None,
+            load: None,
+        });
+    }
+
+    /// Bypasses the processor pushed last, or sets how much of it is
+    /// heard, before the chain is bound.
+    pub fn set_last(&mut self, bypassed: bool, wet: f32) {
+        if let Some(slot) = self.slots.last_mut() {
+            slot.bypassed = bypassed;
+            slot.wet = wet.clamp(0.0, 1.0);
+            slot.current_wet = slot.wet;
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.slots.iter().map(|s| s.processor.name()).collect()
+    }
+
+    /// Registers every processor parameter in `store` under `prefix`, plus
+    /// a `bypass` switch and a `wet` amount per processor, and watches each
+    /// processor's output for clipping, its time per callback, and its
+    /// peaks when the headroom analysis is on. Repeated processors get a number, e.g.
+    /// `compressor2.ratio`. Keys already in the store are reused.
+    pub fn bind_params(&mut self, prefix: &str, store: &ParamStore) {
+        for index in 0..self.slots.len() {
+            let slot = &self.slots[index];
+            let name = slot.processor.name();
+            let count = self.slots[..=index]
+                .iter()
+                .filter(|s| s.processor.name() == name)
+                .count();
+            let label = if count > 1 {
+                format!("{}{}", name, count)
+            } else {
+                name.to_string()
+            };
+
+            let bypassed = if slot.bypassed { 1.0 } else { 0.0 };
+            let bypass = ParamInfo::new(BYPASS, bypassed, 0.0, 1.0);
+            let wet = ParamInfo::new(WET, slot.wet, 0.0, 1.0);
+            for info in [bypass, wet].into_iter().chain(slot.processor.params()) {
+                let key = format!("{}{}.{}", prefix, label, info.name);
+                let param = store.bind(key, &info);
+                self.bindings.push(Binding {
+                    processor: index,
+                    name: info.name,
+                    seen: param.version(),
+                    param,
+                });
+            }
+            let stage = format!("{}{}", prefix, label);
+            self.slots[index].clips = Some(store.clips().bind(stage.clone()));
+            self.slots[index].levels = store.headroom().bind(stage.clone());
+            self.slots[index].load = Some(store.loads().bind(stage));
+        }
+    }
+
+    /// Keys of the parameters bound by `bind_params`.
+    pub fn param_keys(&self) -> Vec<String> {
+        self.bindings.iter().map(|b| b.param.key.clone()).collect()
+    }
+
+    /// Pushes parameters changed since the last block into the processors.
+    fn apply_params(&mut self) {
+        for binding in self.bindings.iter_mut() {
+            let version = binding.param.version();
+            if version != binding.seen {
+                binding.seen = version;
+                let value = binding.param.get();
+                let slot = &mut self.slots[binding.processor];
+                match binding.name {
+                    BYPASS => {
+                        let bypassed = value >= 0.5;
+                        // Drop stale tails so re-enabling doesn't replay old audio
+                        if slot.bypassed && !bypassed {
+                            slot.processor.reset();
+                        }
+                        slot.bypassed = bypassed;
+                    }
+                    WET => slot.wet = value,
+                    name => slot.processor.set_param(name, value),
+                }
+            }
+        }
+    }
+}
+
+impl Slot {
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if !self.bypassed
+            && let Some(levels) = &self.levels
+        {
+            levels.record_input(buffer);
+        }
+        let started = Instant::now();
+        self.run(buffer, channels);
+        if self.bypassed {
+            return;
+        }
+        if let Some(load) = &self.load {
+            load.record(started.elapsed(), (buffer.len() / channels.max(1)) as u64);
+        }
+        if let Some(clips) = &self.clips {
+            clips.record(buffer, channels);
+        }
+        if let Some(levels) = &self.levels {
+            levels.record_output(buffer);
+        }
+    }
+
+    fn run(&mut self, buffer: &mut [f32], channels: usize) {
+        if !self.dry.is_ready(channels) {
+            // Not prepared for these channels, no dry signal to mix
+            if !self.bypassed {
+                self.processor.process(buffer, channels);
+            }
+            return;
+        }
+
+        for block in buffer.chunks_mut(MAX_FRAMES * channels) {
+            self.dry.push(block);
+            let count = block.len();
+            if self.bypassed {
+                if self.dry.delay > 0 {
+                    for (index, sample) in block.iter_mut().enumerate() {
+                        *sample = self.dry.get(count, index);
+                    }
+                }
+                continue;
+            }
+
+            self.processor.process(block, channels);
+            if self.wet >= 1.0 && self.current_wet >= 1.0 {
+                continue;
+            }
+            let frames = (count / channels).max(1);
+            let step = (self.wet - self.current_wet) / frames as f32;
+            for (frame, samples) in block.chunks_mut(channels).enumerate() {
+                let wet = self.current_wet + step * (frame + 1) as f32;
+                for (channel, sample) in samples.iter_mut().enumerate() {
+                    let dry = self.dry.get(count, frame * channels + channel);
+                    *sample = dry * (1.0 - wet) + *sample * wet;
+                }
+            }
+            self.current_wet = self.wet;
+        }
+    }
+}
+
+impl Processor for DspChain {
+    fn name(&self) -> &'static str {
+        "chain"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.apply_params();
+        for slot in self.slots.iter_mut() {
+            slot.process(buffer, channels);
+        }
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        for slot in self.slots.iter_mut() {
+            slot.processor.prepare(channels);
+            let latency = slot.processor.latency();
+            slot.dry.prepare(latency, channels);
+        }
+    }
+
+    fn reset(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.processor.reset();
+            slot.dry.reset();
+        }
+    }
+
+    /// Bypassed processors keep their delay, so this doesn't change while
+    /// streaming.
+    fn latency(&self) -> usize {
+        self.slots.iter().map(|s| s.processor.latency()).sum()
+    }
+}
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-9).log10()
+}
+
+/// One-pole smoothing coefficient reaching ~63% of a step after `ms`.
+pub fn time_coefficient(ms: f32, sample_rate: f32) -> f32 {
+    if ms <= 0.0 {
+        return 0.0;
+    }
+    (-1.0 / (ms * 0.001 * sample_rate)).exp()
+}
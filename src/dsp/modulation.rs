@@ -0,0 +1,320 @@
+use super::Processor;
+use crate::params::ParamInfo;
+use std::f32::consts::PI;
+
+/// All-pass stages of the phaser, three notches.
+const PHASER_STAGES: usize = 6;
+/// Bottom of the phaser sweep, `depth` 1 takes it four octaves up.
+const PHASER_LOWEST: f32 = 200.0;
+const PHASER_OCTAVES: f32 = 4.0;
+
+/// Sine LFO from 0 to 1 at `phase` cycles. Odd channels are offset by
+/// half of `spread` cycles, so 1 puts the two sides in opposite phase.
+fn lfo(phase: f32, channel: usize, spread: f32) -> f32 {
+    let offset = (channel % 2) as f32 * spread * 0.5;
+    0.5 + 0.5 * (2.0 * PI * (phase + offset)).sin()
+}
+
+fn advance(phase: &mut f32, rate: f32, sample_rate: f32) {
+    *phase += rate / sample_rate;
+    if *phase >= 1.0 {
+        *phase -= 1.0;
+    }
+}
+
+/// Delay line read between samples.
+struct Line {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Line {
+    fn new(length: usize) -> Self {
+        Line {
+            buffer: vec![0.0; length.max(2)],
+            index: 0,
+        }
+    }
+
+    /// Sample written `delay` writes ago, counting the upcoming write,
+    /// interpolated.
+    fn read(&self, delay: f32) -> f32 {
+        let length = self.buffer.len();
+        let delay = delay.clamp(1.0, (length - 2) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let at = |delay: usize| self.buffer[(self.index + 1 + length - delay) % length];
+        at(whole) + (at(whole + 1) - at(whole)) * fraction
+    }
+
+    fn write(&mut self, sample: f32) {
+        let length = self.buffer.len();
+        self.index = (self.index + 1) % length;
+        self.buffer[self.index] = sample;
+    }
+}
+
+/// Core of the chorus and flanger: a delay line per channel whose length
+/// swings with the LFO between `base_ms` and `base_ms + depth * swing_ms`.
+struct SweptDelay {
+    sample_rate: f32,
+    base_ms: f32,
+    swing_ms: f32,
+    /// Feedback range, the flanger's goes negative.
+    min_feedback: f32,
+
+    rate: f32,
+    depth: f32,
+    feedback: f32,
+    spread: f32,
+    mix: f32,
+    phase: f32,
+    lines: Vec<Line>,
+}
+
+impl SweptDelay {
+    fn new(sample_rate: f32, base_ms: f32, swing_ms: f32, min_feedback: f32) -> Self {
+        SweptDelay {
+            sample_rate,
+            base_ms,
+            swing_ms,
+            min_feedback,
+            rate: 0.5,
+            depth: 0.5,
+            feedback: 0.0,
+            spread: 0.5,
+            mix: 0.5,
+            phase: 0.0,
+            lines: Vec::new(),
+        }
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        let length = ((self.base_ms + self.swing_ms) * 0.001 * self.sample_rate) as usize + 3;
+        while self.lines.len() < channels {
+            self.lines.push(Line::new(length));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        let to_samples = 0.001 * self.sample_rate;
+        for frame in buffer.chunks_mut(channels) {
+            for (channel, (sample, line)) in frame.iter_mut().zip(&mut self.lines).enumerate() {
+                let swing = self.depth * self.swing_ms * lfo(self.phase, channel, self.spread);
+                let delayed = line.read((self.base_ms + swing) * to_samples);
+                line.write(*sample + delayed * self.feedback);
+                *sample = *sample * dry + delayed * wet;
+            }
+            advance(&mut self.phase, self.rate, self.sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.buffer.fill(0.0);
+        }
+        self.phase = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("rate", self.rate, 0.01, 10.0),
+            ParamInfo::new("depth", self.depth, 0.0, 1.0),
+            ParamInfo::new("feedback", self.feedback, self.min_feedback, 0.95),
+            ParamInfo::new("spread", self.spread, 0.0, 1.0),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "rate" => self.rate = value.clamp(0.01, 10.0),
+            "depth" => self.depth = value.clamp(0.0, 1.0),
+            "feedback" => self.feedback = value.clamp(self.min_feedback, 0.95),
+            "spread" => self.spread = value.clamp(0.0, 1.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+}
+
+/// Chorus: copies delayed by 8 to 20 ms and swept slowly, thickening a
+/// voice or a guitar. `spread` sets the two sides' LFOs apart for width.
+pub struct Chorus(SweptDelay);
+
+impl Chorus {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut core = SweptDelay::new(sample_rate, 8.0, 12.0, 0.0);
+        core.rate = 0.8;
+        Chorus(core)
+    }
+}
+
+impl Processor for Chorus {
+    fn name(&self) -> &'static str {
+        "chorus"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.0.prepare(channels);
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.0.process(buffer, channels);
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        self.0.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        self.0.set_param(name, value);
+    }
+}
+
+/// Flanger: a copy delayed by 0.5 to 5.5 ms swept against the dry signal,
+/// the comb of notches moving through the sound. Negative `feedback`
+/// gives the hollower sound.
+pub struct Flanger(SweptDelay);
+
+impl Flanger {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut core = SweptDelay::new(sample_rate, 0.5, 5.0, -0.95);
+        core.rate = 0.25;
+        core.feedback = 0.5;
+        Flanger(core)
+    }
+}
+
+impl Processor for Flanger {
+    fn name(&self) -> &'static str {
+        "flanger"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.0.prepare(channels);
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.0.process(buffer, channels);
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        self.0.params()
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        self.0.set_param(name, value);
+    }
+}
+
+/// One channel of the phaser: the all-pass states and the last output,
+/// fed back.
+#[derive(Clone, Copy, Default)]
+struct PhaserChannel {
+    states: [f32; PHASER_STAGES],
+    last: f32,
+}
+
+/// Phaser: six first-order all-passes swept together from 200 Hz, up to
+/// four octaves at full `depth`, mixed with the dry signal for three
+/// moving notches.
+pub struct Phaser {
+    sample_rate: f32,
+    rate: f32,
+    depth: f32,
+    feedback: f32,
+    spread: f32,
+    mix: f32,
+    phase: f32,
+    channels: Vec<PhaserChannel>,
+}
+
+impl Phaser {
+    pub fn new(sample_rate: f32) -> Self {
+        Phaser {
+            sample_rate,
+            rate: 0.3,
+            depth: 0.7,
+            feedback: 0.3,
+            spread: 0.5,
+            mix: 0.5,
+            phase: 0.0,
+            channels: Vec::new(),
+        }
+    }
+}
+
+impl Processor for Phaser {
+    fn name(&self) -> &'static str {
+        "phaser"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        if self.channels.len() < channels {
+            self.channels.resize(channels, PhaserChannel::default());
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        let highest = self.sample_rate * 0.45;
+        for frame in buffer.chunks_mut(channels) {
+            for (channel, (sample, state)) in frame.iter_mut().zip(&mut self.channels).enumerate() {
+                let octaves = self.depth * PHASER_OCTAVES * lfo(self.phase, channel, self.spread);
+                let frequency = (PHASER_LOWEST * octaves.exp2()).min(highest);
+                let t = (PI * frequency / self.sample_rate).tan();
+                let a = (t - 1.0) / (t + 1.0);
+
+                let mut x = *sample + state.last * self.feedback;
+                for s in &mut state.states {
+                    let y = a * x + *s;
+                    *s = x - a * y;
+                    x = y;
+                }
+                state.last = x;
+                *sample = *sample * dry + x * wet;
+            }
+            advance(&mut self.phase, self.rate, self.sample_rate);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.channels.fill(PhaserChannel::default());
+        self.phase = 0.0;
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("rate", self.rate, 0.01, 10.0),
+            ParamInfo::new("depth", self.depth, 0.0, 1.0),
+            ParamInfo::new("feedback", self.feedback, -0.9, 0.9),
+            ParamInfo::new("spread", self.spread, 0.0, 1.0),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "rate" => self.rate = value.clamp(0.01, 10.0),
+            "depth" => self.depth = value.clamp(0.0, 1.0),
+            "feedback" => self.feedback = value.clamp(-0.9, 0.9),
+            "spread" => self.spread = value.clamp(0.0, 1.0),
+            "mix" => self.mix = value.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use super::Processor;
+use super::biquad::{BUTTERWORTH_Q, Biquad, Coefficients};
+use super::compressor::Compressor;
+use crate::params::ParamInfo;
+
+const MAX_BANDS: usize = 4;
+
+/// Band settings, passed through to each band's compressor apart from
+/// `solo`.
+const SETTINGS: [&str; 7] = [
+    "threshold",
+    "ratio",
+    "attack",
+    "release",
+    "knee",
+    "makeup",
+    "solo",
+];
+
+/// `SETTINGS` under each band's prefix, for the parameter names.
+const BAND_PARAMS: [[&str; 7]; MAX_BANDS] = [
+    [
+        "band1.threshold",
+        "band1.ratio",
+        "band1.attack",
+        "band1.release",
+        "band1.knee",
+        "band1.makeup",
+        "band1.solo",
+    ],
+    [
+        "band2.threshold",
+        "band2.ratio",
+        "band2.attack",
+        "band2.release",
+        "band2.knee",
+        "band2.makeup",
+        "band2.solo",
+    ],
+    [
+        "band3.threshold",
+        "band3.ratio",
+        "band3.attack",
+        "band3.release",
+        "band3.knee",
+        "band3.makeup",
+        "band3.solo",
+    ],
+    [
+        "band4.threshold",
+        "band4.ratio",
+        "band4.attack",
+        "band4.release",
+        "band4.knee",
+        "band4.makeup",
+        "band4.solo",
+    ],
+];
+
+const CROSSOVER_PARAMS: [&str; MAX_BANDS - 1] = ["crossover1", "crossover2", "crossover3"];
+
+/// Default crossover and its range for each split, by band count. The
+/// ranges don't overlap much so the bands stay in order.
+const CROSSOVERS_3: [(f32, f32, f32); 2] = [(200.0, 40.0, 1000.0), (2500.0, 500.0, 12000.0)];
+const CROSSOVERS_4: [(f32, f32, f32); 3] = [
+    (120.0, 40.0, 500.0),
+    (1000.0, 250.0, 5000.0),
+    (6000.0, 2000.0, 16000.0),
+];
+
+/// Attack and release of each band's compressor, lows slower so they
+/// don't distort by following single cycles.
+const TIMES_3: [(f32, f32); 3] = [(20.0, 200.0), (10.0, 120.0), (5.0, 80.0)];
+const TIMES_4: [(f32, f32); 4] = [(20.0, 200.0), (10.0, 150.0), (5.0, 100.0), (3.0, 60.0)];
+
+/// Crossover and phase-matching filters of one channel.
+#[derive(Clone)]
+struct ChannelFilters {
+    /// Two low-pass then two high-pass sections per crossover.
+    split: Vec<[Biquad; 4]>,
+    /// Band `b`'s all-pass for crossover `c` at `b * crossovers + c`.
+    all_pass: Vec<Biquad>,
+}
+
+struct Band {
+    compressor: Compressor,
+    solo: bool,
+}
+
+/// Mastering-style multiband compressor of 3 or 4 bands. Linkwitz-Riley
+/// 24 dB/oct crossovers split the signal and each band gets its own
+/// compressor before they're summed back; the lower bands go through the
+/// all-pass of every crossover above them so the sum is flat with the
+/// compressors idle. Soloing bands plays only those, for finding the
+/// crossovers and settings by ear.
+pub struct Multiband {
+    sample_rate: f32,
+    crossovers: Vec<f32>,
+    low: Vec<Coefficients>,
+    high: Vec<Coefficients>,
+    all_pass: Vec<Coefficients>,
+    bands: Vec<Band>,
+    filters: Vec<ChannelFilters>,
+    /// One frame of every band, band after band.
+    frame: Vec<f32>,
+}
+
+impl Multiband {
+    pub fn new(bands: usize, sample_rate: f32) -> Self {
+        let bands = bands.clamp(3, MAX_BANDS);
+        let times: &[(f32, f32)] = if bands == 3 { &TIMES_3 } else { &TIMES_4 };
+        let bands = times
+            .iter()
+            .map(|&(attack, release)| {
+                let mut compressor = Compressor::new(sample_rate);
+                compressor.set_threshold(-18.0);
+                compressor.set_ratio(2.0);
+                compressor.set_attack(attack);
+                compressor.set_release(release);
+                Band {
+                    compressor,
+                    solo: false,
+                }
+            })
+            .collect();
+        let mut multiband = Multiband {
+            sample_rate,
+            crossovers: Vec::new(),
+            low: Vec::new(),
+            high: Vec::new(),
+            all_pass: Vec::new(),
+            bands,
+            filters: Vec::new(),
+            frame: Vec::new(),
+        };
+        for (index, &(frequency, _, _)) in multiband.ranges().iter().enumerate() {
+            multiband.crossovers.push(frequency);
+            multiband.low.push(Coefficients::default());
+            multiband.high.push(Coefficients::default());
+            multiband.all_pass.push(Coefficients::default());
+            multiband.set_crossover(index, frequency);
+        }
+        multiband
+    }
+
+    /// Moves crossover `index`, counted from the lowest.
+    pub fn set_crossover(&mut self, index: usize, frequency: f32) {
+        let Some(&(_, min, max)) = self.ranges().get(index) else {
+            return;
+        };
+        let frequency = frequency.clamp(min, max);
+        self.crossovers[index] = frequency;
+        self.low[index] = Coefficients::low_pass(frequency, BUTTERWORTH_Q, self.sample_rate);
+        self.high[index] = Coefficients::high_pass(frequency, BUTTERWORTH_Q, self.sample_rate);
+        // What a Linkwitz-Riley pair sums to
+        self.all_pass[index] = Coefficients::all_pass(frequency, BUTTERWORTH_Q, self.sample_rate);
+    }
+
+    pub fn set_solo(&mut self, band: usize, solo: bool) {
+        if let Some(band) = self.bands.get_mut(band) {
+            band.solo = solo;
+        }
+    }
+
+    fn ranges(&self) -> &'static [(f32, f32, f32)] {
+        if self.bands.len() == 3 {
+            &CROSSOVERS_3
+        } else {
+            &CROSSOVERS_4
+        }
+    }
+}
+
+impl Processor for Multiband {
+    fn name(&self) -> &'static str {
+        "multiband"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        let crossovers = self.crossovers.len();
+        if self.filters.len() < channels {
+            let filters = ChannelFilters {
+                split: vec![[Biquad::default(); 4]; crossovers],
+                all_pass: vec![Biquad::default(); self.bands.len() * crossovers],
+            };
+            self.filters.resize(channels, filters);
+        }
+        self.frame.resize(self.bands.len() * channels, 0.0);
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        let crossovers = self.crossovers.len();
+        let soloed = self.bands.iter().any(|band| band.solo);
+
+        for frame in buffer.chunks_mut(channels) {
+            for (channel, (&x, filters)) in frame.iter().zip(self.filters.iter_mut()).enumerate() {
+                let mut rest = x;
+                for split in 0..crossovers {
+                    let [low_a, low_b, high_a, high_b] = &mut filters.split[split];
+                    let low =
+                        low_b.process(&self.low[split], low_a.process(&self.low[split], rest));
+                    rest =
+                        high_b.process(&self.high[split], high_a.process(&self.high[split], rest));
+                    let mut band = low;
+                    for above in split + 1..crossovers {
+                        band = filters.all_pass[split * crossovers + above]
+                            .process(&self.all_pass[above], band);
+                    }
+                    self.frame[split * channels + channel] = band;
+                }
+                self.frame[crossovers * channels + channel] = rest;
+            }
+
+            for (band, samples) in self.bands.iter_mut().zip(self.frame.chunks_mut(channels)) {
+                band.compressor.process(samples, channels);
+            }
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self
+                    .bands
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, band)| !soloed || band.solo)
+                    .map(|(index, _)| self.frame[index * channels + channel])
+                    .sum();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filters in self.filters.iter_mut() {
+            for split in filters.split.iter_mut() {
+                split.iter_mut().for_each(Biquad::reset);
+            }
+            filters.all_pass.iter_mut().for_each(Biquad::reset);
+        }
+        for band in self.bands.iter_mut() {
+            band.compressor.reset();
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params: Vec<_> = self
+            .ranges()
+            .iter()
+            .zip(&self.crossovers)
+            .zip(CROSSOVER_PARAMS)
+            .map(|((&(_, min, max), &frequency), name)| ParamInfo::new(name, frequency, min, max))
+            .collect();
+        for (band, names) in self.bands.iter().zip(BAND_PARAMS) {
+            for info in band.compressor.params() {
+                if let Some(index) = SETTINGS.iter().position(|&s| s == info.name) {
+                    params.push(ParamInfo::new(names[index], info.value, info.min, info.max));
+                }
+            }
+            params.push(ParamInfo::new(names[6], band.solo as u8 as f32, 0.0, 1.0));
+        }
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if let Some(index) = CROSSOVER_PARAMS.iter().position(|&p| p == name) {
+            self.set_crossover(index, value);
+            return;
+        }
+        let Some(band) = BAND_PARAMS.iter().position(|names| names.contains(&name)) else {
+            return;
+        };
+        match name.split_once('.') {
+            Some((_, "solo")) => self.set_solo(band, value >= 0.5),
+            Some((_, setting)) => {
+                if let Some(band) = self.bands.get_mut(band) {
+                    band.compressor.set_param(setting, value);
+                }
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use super::Processor;
+use super::fft::{Complex, Fft};
+use crate::params::ParamInfo;
+use std::f32::consts::PI;
+
+const FRAME_SIZE: usize = 2048;
+const OVERSAMPLING: usize = 4;
+const HOP: usize = FRAME_SIZE / OVERSAMPLING;
+
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * (phase / (2.0 * PI)).round()
+}
+
+/// Streaming single-channel phase vocoder pitch shifter. Output lags the
+/// input by `FRAME_SIZE - HOP` samples.
+pub struct PhaseVocoder {
+    fft: Fft,
+    window: Vec<f32>,
+    ratio: f32,
+
+    input: Vec<f32>,
+    output: Vec<f32>,
+    accum: Vec<f32>,
+    rover: usize,
+    spectrum: Vec<Complex>,
+    last_phase: Vec<f32>,
+    sum_phase: Vec<f32>,
+    magnitudes: Vec<f32>,
+    frequencies: Vec<f32>,
+    synth_magnitudes: Vec<f32>,
+    synth_frequencies: Vec<f32>,
+}
+
+impl Default for PhaseVocoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PhaseVocoder {
+    pub fn new() -> Self {
+        let bins = FRAME_SIZE / 2 + 1;
+        PhaseVocoder {
+            fft: Fft::new(FRAME_SIZE),
+            window: (0..FRAME_SIZE)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / FRAME_SIZE as f32).cos())
+                .collect(),
+            ratio: 1.0,
+            input: vec![0.0; FRAME_SIZE],
+            output: vec![0.0; FRAME_SIZE],
+            accum: vec![0.0; FRAME_SIZE * 2],
+            rover: FRAME_SIZE - HOP,
+            spectrum: vec![Complex::ZERO; FRAME_SIZE],
+            last_phase: vec![0.0; bins],
+            sum_phase: vec![0.0; bins],
+            magnitudes: vec![0.0; bins],
+            frequencies: vec![0.0; bins],
+            synth_magnitudes: vec![0.0; bins],
+            synth_frequencies: vec![0.0; bins],
+        }
+    }
+
+    pub fn latency() -> usize {
+        FRAME_SIZE - HOP
+    }
+
+    /// Frequency multiplier, 2.0 is an octave up.
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.clamp(0.25, 4.0);
+    }
+
+    pub fn process_sample(&mut self, sample: f32) -> f32 {
+        let latency = FRAME_SIZE - HOP;
+        self.input[self.rover] = sample;
+        let out = self.output[self.rover - latency];
+        self.rover += 1;
+
+        if self.rover >= FRAME_SIZE {
+            self.rover = latency;
+            self.process_frame();
+        }
+        out
+    }
+
+    pub fn reset(&mut self) {
+        self.input.iter_mut().for_each(|s| *s = 0.0);
+        self.output.iter_mut().for_each(|s| *s = 0.0);
+        self.accum.iter_mut().for_each(|s| *s = 0.0);
+        self.last_phase.iter_mut().for_each(|s| *s = 0.0);
+        self.sum_phase.iter_mut().for_each(|s| *s = 0.0);
+        self.rover = FRAME_SIZE - HOP;
+    }
+
+    fn process_frame(&mut self) {
+        let bins = FRAME_SIZE / 2 + 1;
+        // Phase advance of bin 1 over one hop
+        let expected = 2.0 * PI * HOP as f32 / FRAME_SIZE as f32;
+
+        for ((bin, &x), &w) in self.spectrum.iter_mut().zip(&self.input).zip(&self.window) {
+            *bin = Complex::new(x * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        // Analysis: estimate the true frequency of each bin, in bins
+        for k in 0..bins {
+            let value = self.spectrum[k];
+            let phase = value.arg();
+            let delta = wrap_phase(phase - self.last_phase[k] - k as f32 * expected);
+            self.last_phase[k] = phase;
+
+            self.magnitudes[k] = value.norm();
+            self.frequencies[k] = k as f32 + delta / expected;
+        }
+
+        // Move every bin to its shifted position
+        self.synth_magnitudes.iter_mut().for_each(|m| *m = 0.0);
+        self.synth_frequencies.iter_mut().for_each(|f| *f = 0.0);
+        for k in 0..bins {
+            let target = (k as f32 * self.ratio).round() as usize;
+            if target < bins {
+                self.synth_magnitudes[target] += self.magnitudes[k];
+                self.synth_frequencies[target] = self.frequencies[k] * self.ratio;
+            }
+        }
+
+        // Synthesis: accumulate phase at the shifted frequencies
+        for k in 0..bins {
+            self.sum_phase[k] =
+                wrap_phase(self.sum_phase[k] + self.synth_frequencies[k] * expected);
+            self.spectrum[k] = Complex::from_polar(self.synth_magnitudes[k], self.sum_phase[k]);
+        }
+        for k in bins..FRAME_SIZE {
+            self.spectrum[k] = self.spectrum[FRAME_SIZE - k].conj();
+        }
+        self.fft.inverse(&mut self.spectrum);
+
+        // Hann analysis and synthesis windows at 4x overlap sum to 1.5
+        let scale = 1.0 / 1.5;
+        for ((acc, bin), &w) in self.accum.iter_mut().zip(&self.spectrum).zip(&self.window) {
+            *acc += bin.re * w * scale;
+        }
+
+        self.output[..HOP].copy_from_slice(&self.accum[..HOP]);
+        self.accum.copy_within(HOP.., 0);
+        let len = self.accum.len();
+        self.accum[len - HOP..].iter_mut().for_each(|s| *s = 0.0);
+        self.input.copy_within(HOP.., 0);
+    }
+}
+
+/// Transposes the input by semitones and cents, one vocoder per channel.
+pub struct PitchShifter {
+    semitones: f32,
+    cents: f32,
+    mix: f32,
+    voices: Vec<PhaseVocoder>,
+}
+
+impl Default for PitchShifter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PitchShifter {
+    pub fn new() -> Self {
+        PitchShifter {
+            semitones: 0.0,
+            cents: 0.0,
+            mix: 1.0,
+            voices: Vec::new(),
+        }
+    }
+
+    pub fn set_semitones(&mut self, semitones: f32) {
+        self.semitones = semitones.clamp(-24.0, 24.0);
+        self.update_ratio();
+    }
+
+    pub fn set_cents(&mut self, cents: f32) {
+        self.cents = cents.clamp(-100.0, 100.0);
+        self.update_ratio();
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn ratio(&self) -> f32 {
+        2f32.powf((self.semitones + self.cents / 100.0) / 12.0)
+    }
+
+    fn update_ratio(&mut self) {
+        let ratio = self.ratio();
+        self.voices.iter_mut().for_each(|v| v.set_ratio(ratio));
+    }
+}
+
+impl Processor for PitchShifter {
+    fn name(&self) -> &'static str {
+        "pitch_shift"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        while self.voices.len() < channels {
+            let mut voice = PhaseVocoder::new();
+            voice.set_ratio(self.ratio());
+            self.voices.push(voice);
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let (dry, wet) = (1.0 - self.mix, self.mix);
+        for frame in buffer.chunks_mut(channels) {
+            for (sample, voice) in frame.iter_mut().zip(self.voices.iter_mut()) {
+                *sample = *sample * dry + voice.process_sample(*sample) * wet;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.voices.iter_mut().for_each(PhaseVocoder::reset);
+    }
+
+    fn latency(&self) -> usize {
+        PhaseVocoder::latency()
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("semitones", self.semitones, -24.0, 24.0),
+            ParamInfo::new("cents", self.cents, -100.0, 100.0),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "semitones" => self.set_semitones(value),
+            "cents" => self.set_cents(value),
+            "mix" => self.set_mix(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use super::Processor;
+use crate::params::ParamInfo;
+
+// Freeverb tunings, in samples at 44.1 kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const INPUT_GAIN: f32 = 0.015;
+const WET_SCALE: f32 = 3.0;
+const MAX_PRE_DELAY_MS: f32 = 500.0;
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_store: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Comb {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+            filter_store: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_store = output * (1.0 - damp) + self.filter_store * damp;
+        self.buffer[self.index] = input + self.filter_store * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|s| *s = 0.0);
+        self.filter_store = 0.0;
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Allpass {
+            buffer: vec![0.0; length.max(1)],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        self.buffer[self.index] = input + buffered * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        buffered - input
+    }
+
+    fn clear(&mut self) {
+        self.buffer.iter_mut().for_each(|s| *s = 0.0);
+    }
+}
+
+/// One comb/allpass network, odd channels get the stereo spread offset.
+struct Tank {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Tank {
+    fn new(sample_rate: f32, spread: usize) -> Self {
+        let scale = |length: usize| ((length + spread) as f32 * sample_rate / 44100.0) as usize;
+        Tank {
+            combs: COMB_TUNINGS.iter().map(|&l| Comb::new(scale(l))).collect(),
+            allpasses: ALLPASS_TUNINGS
+                .iter()
+                .map(|&l| Allpass::new(scale(l)))
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damp: f32) -> f32 {
+        let mut out = 0.0;
+        for comb in self.combs.iter_mut() {
+            out += comb.process(input, feedback, damp);
+        }
+        for allpass in self.allpasses.iter_mut() {
+            out = allpass.process(out);
+        }
+        out
+    }
+
+    fn clear(&mut self) {
+        self.combs.iter_mut().for_each(Comb::clear);
+        self.allpasses.iter_mut().for_each(Allpass::clear);
+    }
+}
+
+/// Freeverb-style algorithmic reverb, a cheap alternative to
+/// `ConvolutionReverb`. The input is summed to mono and fed to one tank per
+/// output channel.
+pub struct Reverb {
+    sample_rate: f32,
+    room_size: f32,
+    damping: f32,
+    mix: f32,
+
+    feedback: f32,
+    damp: f32,
+    pre_delay: Vec<f32>,
+    pre_delay_samples: usize,
+    pre_delay_index: usize,
+    tanks: Vec<Tank>,
+}
+
+impl Reverb {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut reverb = Reverb {
+            sample_rate,
+            room_size: 0.5,
+            damping: 0.5,
+            mix: 0.25,
+            feedback: 0.0,
+            damp: 0.0,
+            pre_delay: vec![0.0; (MAX_PRE_DELAY_MS * 0.001 * sample_rate) as usize + 1],
+            pre_delay_samples: 0,
+            pre_delay_index: 0,
+            tanks: Vec::new(),
+        };
+        reverb.update_coefficients();
+        reverb
+    }
+
+    /// Room size from 0.0 (small) to 1.0 (large).
+    pub fn set_room_size(&mut self, room_size: f32) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+        self.update_coefficients();
+    }
+
+    /// High frequency damping from 0.0 (bright) to 1.0 (dark).
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0.0, 1.0);
+        self.update_coefficients();
+    }
+
+    pub fn set_pre_delay(&mut self, pre_delay_ms: f32) {
+        let samples =
+            (pre_delay_ms.clamp(0.0, MAX_PRE_DELAY_MS) * 0.001 * self.sample_rate) as usize;
+        self.pre_delay_samples = samples.min(self.pre_delay.len() - 1);
+    }
+
+    /// Wet amount, 0.0 is fully dry and 1.0 fully wet.
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    fn update_coefficients(&mut self) {
+        self.feedback = self.room_size * 0.28 + 0.7;
+        self.damp = self.damping * 0.4;
+    }
+}
+
+impl Processor for Reverb {
+    fn name(&self) -> &'static str {
+        "reverb"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        while self.tanks.len() < channels {
+            let spread = if self.tanks.len() % 2 == 1 {
+                STEREO_SPREAD
+            } else {
+                0
+            };
+            self.tanks.push(Tank::new(self.sample_rate, spread));
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+
+        let (dry, wet) = (1.0 - self.mix, self.mix * WET_SCALE);
+        let length = self.pre_delay.len();
+        for frame in buffer.chunks_mut(channels) {
+            let mono = frame.iter().sum::<f32>() * INPUT_GAIN;
+
+            self.pre_delay[self.pre_delay_index] = mono;
+            let read = (self.pre_delay_index + length - self.pre_delay_samples) % length;
+            let input = self.pre_delay[read];
+            self.pre_delay_index = (self.pre_delay_index + 1) % length;
+
+            for (sample, tank) in frame.iter_mut().zip(self.tanks.iter_mut()) {
+                let reverb = tank.process(input, self.feedback, self.damp);
+                *sample = *sample * dry + reverb * wet;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pre_delay.iter_mut().for_each(|s| *s = 0.0);
+        self.tanks.iter_mut().for_each(Tank::clear);
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let pre_delay_ms = self.pre_delay_samples as f32 * 1000.0 / self.sample_rate;
+        vec![
+            ParamInfo::new("room_size", self.room_size, 0.0, 1.0),
+            ParamInfo::new("damping", self.damping, 0.0, 1.0),
+            ParamInfo::new("pre_delay", pre_delay_ms, 0.0, MAX_PRE_DELAY_MS),
+            ParamInfo::new("mix", self.mix, 0.0, 1.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "room_size" => self.set_room_size(value),
+            "damping" => self.set_damping(value),
+            "pre_delay" => self.set_pre_delay(value),
+            "mix" => self.set_mix(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::conditioning::DcBlocker;
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use anyhow::{Result, bail};
+
+/// Offset of the tube curve, the asymmetry that adds even harmonics.
+const TUBE_BIAS: f32 = 0.2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    /// Smooth all the way, the warmest.
+    Tanh,
+    /// Clean below the knee and flat at full scale by 1.5, the best as a
+    /// safety stage.
+    Cubic,
+    /// Asymmetric tanh, pushed harder on one side like a triode.
+    Tube,
+}
+
+impl Curve {
+    const ALL: [Curve; 3] = [Curve::Tanh, Curve::Cubic, Curve::Tube];
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let curve = match text.trim() {
+            "tanh" => Curve::Tanh,
+            "cubic" => Curve::Cubic,
+            "tube" => Curve::Tube,
+            other => bail!("Unknown curve '{}', expected tanh, cubic or tube", other),
+        };
+        Ok(curve)
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&c| c == self).unwrap_or(0)
+    }
+
+    /// Each curve has a slope of 1 at zero and tops out at full scale.
+    pub(super) fn shape(self, x: f32) -> f32 {
+        match self {
+            Curve::Tanh => x.tanh(),
+            Curve::Cubic => {
+                let x = x.clamp(-1.5, 1.5);
+                x - 4.0 / 27.0 * x * x * x
+            }
+            Curve::Tube => {
+                let slope = 1.0 - TUBE_BIAS.tanh().powi(2);
+                ((x + TUBE_BIAS).tanh() - TUBE_BIAS.tanh()) / slope
+            }
+        }
+    }
+}
+
+/// Waveshaper for saturation as an effect, or as a soft clipper that
+/// rounds off overs instead of squaring them. `drive` pushes the signal
+/// into the curve and `trim` sets the level coming out.
+pub struct Saturation {
+    curve: Curve,
+    drive_db: f32,
+    trim_db: f32,
+    drive: f32,
+    trim: f32,
+    /// Takes out the offset the tube curve's asymmetry leaves.
+    dc: DcBlocker,
+}
+
+impl Saturation {
+    pub fn new(curve: Curve, sample_rate: f32) -> Self {
+        Saturation {
+            curve,
+            drive_db: 0.0,
+            trim_db: 0.0,
+            drive: 1.0,
+            trim: 1.0,
+            dc: DcBlocker::new(sample_rate),
+        }
+    }
+
+    pub fn set_drive(&mut self, drive_db: f32) {
+        self.drive_db = drive_db.clamp(0.0, 24.0);
+        self.drive = db_to_gain(self.drive_db);
+    }
+
+    pub fn set_trim(&mut self, trim_db: f32) {
+        self.trim_db = trim_db.clamp(-24.0, 6.0);
+        self.trim = db_to_gain(self.trim_db);
+    }
+}
+
+impl Processor for Saturation {
+    fn name(&self) -> &'static str {
+        "saturation"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.dc.prepare(channels);
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        for sample in buffer.iter_mut() {
+            *sample = self.curve.shape(*sample * self.drive) * self.trim;
+        }
+        if self.curve == Curve::Tube {
+            self.dc.process(buffer, channels);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.dc.reset();
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let last = (Curve::ALL.len() - 1) as f32;
+        vec![
+            ParamInfo::new("curve", self.curve.index() as f32, 0.0, last),
+            ParamInfo::new("drive", self.drive_db, 0.0, 24.0),
+            ParamInfo::new("trim", self.trim_db, -24.0, 6.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "curve" => {
+                let index = value.round().clamp(0.0, (Curve::ALL.len() - 1) as f32);
+                self.curve = Curve::ALL[index as usize];
+            }
+            "drive" => self.set_drive(value),
+            "trim" => self.set_trim(value),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use super::alignment::FractionalDelay;
+use super::biquad::{BUTTERWORTH_Q, Biquad, Coefficients};
+use super::{Processor, db_to_gain};
+use crate::params::ParamInfo;
+use anyhow::{Context, Result, bail};
+
+/// Longest alignment delay, enough for 30 m of distance between boxes.
+const MAX_DELAY_MS: f32 = 100.0;
+const MIN_CROSSOVER_HZ: f32 = 20.0;
+const MAX_CROSSOVER_HZ: f32 = 20000.0;
+
+/// One output channel of the speaker processor as given on the command
+/// line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpeakerOutput {
+    pub output: usize,
+    /// Bus channels averaged into the output.
+    pub sources: Vec<usize>,
+    pub high_pass: Option<f32>,
+    pub low_pass: Option<f32>,
+    pub delay_ms: f32,
+    pub gain_db: f32,
+}
+
+impl SpeakerOutput {
+    /// Parses `out1=L:hp=100,out2=R:hp=100,out3=L+R:lp=100:delay=2.5:gain=-3`.
+    /// Channels are numbered from 1, `L` and `R` are aliases for 1 and 2.
+    /// Outputs left out are silent.
+    pub fn parse_list(spec: &str, channels: usize) -> Result<Vec<Self>> {
+        let mut outputs: Vec<SpeakerOutput> = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.split(':');
+            let (output, sources) = parts
+                .next()
+                .and_then(|head| head.split_once('='))
+                .with_context(|| format!("Invalid speaker output '{}', expected outN=L", entry))?;
+            let output = parse_channel(output.trim().strip_prefix("out").unwrap_or(""), channels)
+                .with_context(|| format!("Invalid output in '{}'", entry))?;
+            if outputs.iter().any(|o| o.output == output) {
+                bail!("Output {} is given twice", output + 1);
+            }
+            let sources = sources
+                .split('+')
+                .map(|source| parse_channel(source, channels))
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Invalid source in '{}'", entry))?;
+
+            let mut speaker = SpeakerOutput {
+                output,
+                sources,
+                high_pass: None,
+                low_pass: None,
+                delay_ms: 0.0,
+                gain_db: 0.0,
+            };
+            for setting in parts {
+                let (name, value) = setting.split_once('=').with_context(|| {
+                    format!("Invalid setting '{}', expected name=value", setting)
+                })?;
+                let value: f32 = value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid value in '{}'", setting))?;
+                match name.trim() {
+                    "hp" => speaker.high_pass = Some(crossover(value)?),
+                    "lp" => speaker.low_pass = Some(crossover(value)?),
+                    "delay" if (0.0..=MAX_DELAY_MS).contains(&value) => speaker.delay_ms = value,
+                    "delay" => bail!("delay must be 0 to {} ms, got {}", MAX_DELAY_MS, value),
+                    "gain" => speaker.gain_db = value.clamp(-24.0, 12.0),
+                    other => bail!(
+                        "Unknown setting '{}', expected hp, lp, delay or gain",
+                        other
+                    ),
+                }
+            }
+            if let (Some(high_pass), Some(low_pass)) = (speaker.high_pass, speaker.low_pass)
+                && high_pass >= low_pass
+            {
+                bail!("'{}' has its high-pass above its low-pass", entry);
+            }
+            outputs.push(speaker);
+        }
+        if outputs.is_empty() {
+            bail!("Speaker layout '{}' has no outputs", spec);
+        }
+        Ok(outputs)
+    }
+
+    pub fn describe(&self) -> String {
+        let sources: Vec<_> = self.sources.iter().map(|s| (s + 1).to_string()).collect();
+        let mut text = format!("out{} = {}", self.output + 1, sources.join("+"));
+        match (self.high_pass, self.low_pass) {
+            (Some(high), Some(low)) => text += &format!(", {:.0}-{:.0} Hz", high, low),
+            (Some(high), None) => text += &format!(", above {:.0} Hz", high),
+            (None, Some(low)) => text += &format!(", below {:.0} Hz", low),
+            (None, None) => {}
+        }
+        if self.delay_ms > 0.0 {
+            text += &format!(", {:.2} ms", self.delay_ms);
+        }
+        if self.gain_db != 0.0 {
+            text += &format!(", {:+.1} dB", self.gain_db);
+        }
+        text
+    }
+}
+
+fn parse_channel(text: &str, channels: usize) -> Result<usize> {
+    let number = match text.trim() {
+        "L" | "l" => 1,
+        "R" | "r" => 2,
+        other => other
+            .parse::<usize>()
+            .with_context(|| format!("Invalid channel '{}'", other))?,
+    };
+    if number == 0 || number > channels {
+        bail!(
+            "Channel {} isn't one of the {} output channels",
+            number,
+            channels
+        );
+    }
+    Ok(number - 1)
+}
+
+fn crossover(frequency: f32) -> Result<f32> {
+    if !(MIN_CROSSOVER_HZ..=MAX_CROSSOVER_HZ).contains(&frequency) {
+        bail!(
+            "Crossover must be {} to {} Hz, got {}",
+            MIN_CROSSOVER_HZ,
+            MAX_CROSSOVER_HZ,
+            frequency
+        );
+    }
+    Ok(frequency)
+}
+
+/// A Linkwitz-Riley 24 dB/oct filter, two Butterworth sections in a row.
+struct Crossover {
+    frequency: f32,
+    coefficients: Coefficients,
+    sections: [Biquad; 2],
+    high: bool,
+}
+
+impl Crossover {
+    fn new(frequency: f32, high: bool, sample_rate: f32) -> Self {
+        let mut crossover = Crossover {
+            frequency: 0.0,
+            coefficients: Coefficients::default(),
+            sections: [Biquad::default(); 2],
+            high,
+        };
+        crossover.set_frequency(frequency, sample_rate);
+        crossover
+    }
+
+    fn set_frequency(&mut self, frequency: f32, sample_rate: f32) {
+        self.frequency = frequency.clamp(MIN_CROSSOVER_HZ, MAX_CROSSOVER_HZ);
+        self.coefficients = if self.high {
+            Coefficients::high_pass(self.frequency, BUTTERWORTH_Q, sample_rate)
+        } else {
+            Coefficients::low_pass(self.frequency, BUTTERWORTH_Q, sample_rate)
+        };
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let x = self.sections[0].process(&self.coefficients, x);
+        self.sections[1].process(&self.coefficients, x)
+    }
+}
+
+/// The filters, delay and gain of one output and its parameter names.
+struct Output {
+    output: usize,
+    sources: Vec<usize>,
+    high_pass: Option<Crossover>,
+    low_pass: Option<Crossover>,
+    delay_ms: f32,
+    gain_db: f32,
+    gain: f32,
+    delay: FractionalDelay,
+    names: [&'static str; 4],
+}
+
+impl Output {
+    fn process(&mut self, input: &[f32]) -> f32 {
+        let mut x = self.sources.iter().map(|&s| input[s]).sum::<f32>() / self.sources.len() as f32;
+        if let Some(filter) = self.high_pass.as_mut() {
+            x = filter.process(x);
+        }
+        if let Some(filter) = self.low_pass.as_mut() {
+            x = filter.process(x);
+        }
+        self.delay.process(x) * self.gain
+    }
+}
+
+/// Loudspeaker management on the master bus: each output channel takes
+/// one or more bus channels through its own Linkwitz-Riley high- and
+/// low-pass, delay and gain, e.g. full-range tops on 1 and 2 and a summed
+/// sub on 3. Delay time-aligns boxes at different distances. Channels with
+/// no output set are silenced.
+pub struct Speakers {
+    sample_rate: f32,
+    outputs: Vec<Output>,
+    /// The bus frame, read before the outputs overwrite it.
+    input: Vec<f32>,
+}
+
+impl Speakers {
+    pub fn new(layout: &[SpeakerOutput], sample_rate: f32) -> Self {
+        let max_samples = (MAX_DELAY_MS * sample_rate / 1000.0).ceil() as usize;
+        let outputs = layout
+            .iter()
+            .map(|speaker| {
+                let key = |setting: &str| -> &'static str {
+                    Box::leak(format!("out{}.{}", speaker.output + 1, setting).into_boxed_str())
+                };
+                let mut delay = FractionalDelay::new(max_samples);
+                delay.set_delay(speaker.delay_ms * sample_rate / 1000.0);
+                Output {
+                    output: speaker.output,
+                    sources: speaker.sources.clone(),
+                    high_pass: speaker
+                        .high_pass
+                        .map(|frequency| Crossover::new(frequency, true, sample_rate)),
+                    low_pass: speaker
+                        .low_pass
+                        .map(|frequency| Crossover::new(frequency, false, sample_rate)),
+                    delay_ms: speaker.delay_ms,
+                    gain_db: speaker.gain_db,
+                    gain: db_to_gain(speaker.gain_db),
+                    delay,
+                    names: [key("high_pass"), key("low_pass"), key("delay"), key("gain")],
+                }
+            })
+            .collect();
+        Speakers {
+            sample_rate,
+            outputs,
+            input: Vec::new(),
+        }
+    }
+}
+
+impl Processor for Speakers {
+    fn name(&self) -> &'static str {
+        "speakers"
+    }
+
+    fn prepare(&mut self, channels: usize) {
+        self.input.resize(channels, 0.0);
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        self.prepare(channels);
+        for frame in buffer.chunks_mut(channels) {
+            self.input.copy_from_slice(frame);
+            frame.fill(0.0);
+            for output in self.outputs.iter_mut() {
+                if output.output < channels {
+                    frame[output.output] = output.process(&self.input);
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for output in self.outputs.iter_mut() {
+            for filter in [&mut output.high_pass, &mut output.low_pass]
+                .into_iter()
+                .flatten()
+            {
+                filter.sections.iter_mut().for_each(Biquad::reset);
+            }
+            output.delay.reset();
+        }
+    }
+
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params = Vec::new();
+        for output in self.outputs.iter() {
+            let [high_pass, low_pass, delay, gain] = output.names;
+            if let Some(filter) = &output.high_pass {
+                params.push(ParamInfo::new(
+                    high_pass,
+                    filter.frequency,
+                    MIN_CROSSOVER_HZ,
+                    MAX_CROSSOVER_HZ,
+                ));
+            }
+            if let Some(filter) = &output.low_pass {
+                params.push(ParamInfo::new(
+                    low_pass,
+                    filter.frequency,
+                    MIN_CROSSOVER_HZ,
+                    MAX_CROSSOVER_HZ,
+                ));
+            }
+            params.push(ParamInfo::new(delay, output.delay_ms, 0.0, MAX_DELAY_MS));
+            params.push(ParamInfo::new(gain, output.gain_db, -24.0, 12.0));
+        }
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        let sample_rate = self.sample_rate;
+        let Some((output, index)) = self.outputs.iter_mut().find_map(|output| {
+            let index = output.names.iter().position(|&n| n == name)?;
+            Some((output, index))
+        }) else {
+            return;
+        };
+        match index {
+            0 => {
+                if let Some(filter) = output.high_pass.as_mut() {
+                    filter.set_frequency(value, sample_rate);
+                }
+            }
+            1 => {
+                if let Some(filter) = output.low_pass.as_mut() {
+                    filter.set_frequency(value, sample_rate);
+                }
+            }
+            2 => {
+                output.delay_ms = value.clamp(0.0, MAX_DELAY_MS);
+                output
+                    .delay
+                    .set_delay(output.delay_ms * sample_rate / 1000.0);
+            }
+            _ => {
+                output.gain_db = value.clamp(-24.0, 12.0);
+                output.gain = db_to_gain(output.gain_db);
+            }
+        }
+    }
+}
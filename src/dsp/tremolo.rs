@@ -0,0 +1,203 @@
+use super::Processor;
+use super::delay::NoteDivision;
+use crate::params::{Param, ParamInfo};
+use std::f32::consts::{FRAC_PI_4, PI, SQRT_2};
+use std::sync::Arc;
+
+/// LFO of the tremolo and auto-pan, free running at `rate` Hz or one
+/// cycle per note length of the engine tempo.
+struct SyncedLfo {
+    sample_rate: f32,
+    rate: f32,
+    /// Note length of a cycle, `None` to run at `rate`.
+    sync: Option<NoteDivision>,
+    tempo: Arc<Param>,
+    /// 0 to 1.
+    phase: f32,
+}
+
+impl SyncedLfo {
+    fn new(sample_rate: f32, rate: f32, tempo: Arc<Param>) -> Self {
+        SyncedLfo {
+            sample_rate,
+            rate,
+            sync: None,
+            tempo,
+            phase: 0.0,
+        }
+    }
+
+    /// Phase step per frame, the tempo read once a block.
+    fn increment(&self) -> f32 {
+        let frequency = match self.sync {
+            Some(division) => self.tempo.get() / 60.0 / division.beats(),
+            None => self.rate,
+        };
+        frequency / self.sample_rate
+    }
+
+    fn advance(&mut self, increment: f32) {
+        self.phase += increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+    }
+
+    /// 0 runs free, from 1 the note lengths of `NoteDivision::ALL`.
+    fn sync_index(&self) -> f32 {
+        self.sync
+            .and_then(|sync| NoteDivision::ALL.iter().position(|&d| d == sync))
+            .map_or(0.0, |index| (index + 1) as f32)
+    }
+
+    fn params(&self) -> [ParamInfo; 2] {
+        let last = NoteDivision::ALL.len() as f32;
+        [
+            ParamInfo::new("rate", self.rate, 0.05, 20.0),
+            ParamInfo::new("sync", self.sync_index(), 0.0, last),
+        ]
+    }
+
+    /// Returns whether `name` was one of the LFO's.
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match name {
+            "rate" => self.rate = value.clamp(0.05, 20.0),
+            "sync" => {
+                let index = value.round().clamp(0.0, NoteDivision::ALL.len() as f32) as usize;
+                self.sync = index.checked_sub(1).map(|index| NoteDivision::ALL[index]);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Tremolo: the level swept by the LFO, down by up to `depth` at the
+/// bottom of each cycle. `shape` goes from a sine at 0 to close to a
+/// square at 1, the choppy effect. `sync` locks it to the engine tempo,
+/// set with `tempo` or `tap`.
+pub struct Tremolo {
+    lfo: SyncedLfo,
+    depth: f32,
+    shape: f32,
+}
+
+impl Tremolo {
+    pub fn new(sample_rate: f32, tempo: Arc<Param>) -> Self {
+        Tremolo {
+            lfo: SyncedLfo::new(sample_rate, 5.0, tempo),
+            depth: 0.5,
+            shape: 0.0,
+        }
+    }
+
+    pub fn set_sync(&mut self, sync: Option<NoteDivision>) {
+        self.lfo.sync = sync;
+    }
+}
+
+impl Processor for Tremolo {
+    fn name(&self) -> &'static str {
+        "tremolo"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let increment = self.lfo.increment();
+        // Steeper sines are squarer, scaled back to reach +-1
+        let steepness = 1.0 + self.shape * 9.0;
+        let scale = 1.0 / steepness.tanh();
+        for frame in buffer.chunks_mut(channels) {
+            let wave = (steepness * (2.0 * PI * self.lfo.phase).sin()).tanh() * scale;
+            let gain = 1.0 - self.depth * (0.5 - 0.5 * wave);
+            frame.iter_mut().for_each(|sample| *sample *= gain);
+            self.lfo.advance(increment);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.lfo.phase = 0.0;
+    }
+
+    /// `sync` is 0 to run at `rate` Hz, or from 1 a note length: 1/1, 1/2,
+    /// 1/4, 1/8, 1/16, 1/4., 1/8., 1/4t, 1/8t.
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params = Vec::from(self.lfo.params());
+        params.push(ParamInfo::new("depth", self.depth, 0.0, 1.0));
+        params.push(ParamInfo::new("shape", self.shape, 0.0, 1.0));
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if self.lfo.set_param(name, value) {
+            return;
+        }
+        match name {
+            "depth" => self.depth = value.clamp(0.0, 1.0),
+            "shape" => self.shape = value.clamp(0.0, 1.0),
+            _ => {}
+        }
+    }
+}
+
+/// Auto-pan: the first two channels swept between the sides by the LFO,
+/// as far as `depth`, 1 for hard left to hard right. Constant power, so
+/// the level holds across the sweep. Mono passes through. Synced like
+/// the tremolo.
+pub struct AutoPan {
+    lfo: SyncedLfo,
+    depth: f32,
+}
+
+impl AutoPan {
+    pub fn new(sample_rate: f32, tempo: Arc<Param>) -> Self {
+        AutoPan {
+            lfo: SyncedLfo::new(sample_rate, 0.5, tempo),
+            depth: 1.0,
+        }
+    }
+
+    pub fn set_sync(&mut self, sync: Option<NoteDivision>) {
+        self.lfo.sync = sync;
+    }
+}
+
+impl Processor for AutoPan {
+    fn name(&self) -> &'static str {
+        "autopan"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        if channels < 2 {
+            return;
+        }
+        let increment = self.lfo.increment();
+        for frame in buffer.chunks_mut(channels) {
+            let pan = self.depth * (2.0 * PI * self.lfo.phase).sin();
+            // Unity in the middle, +3 dB on one side at the ends
+            let angle = (pan + 1.0) * FRAC_PI_4;
+            frame[0] *= SQRT_2 * angle.cos();
+            frame[1] *= SQRT_2 * angle.sin();
+            self.lfo.advance(increment);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.lfo.phase = 0.0;
+    }
+
+    /// `sync` as for the tremolo.
+    fn params(&self) -> Vec<ParamInfo> {
+        let mut params = Vec::from(self.lfo.params());
+        params.push(ParamInfo::new("depth", self.depth, 0.0, 1.0));
+        params
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        if self.lfo.set_param(name, value) {
+            return;
+        }
+        if name == "depth" {
+            self.depth = value.clamp(0.0, 1.0);
+        }
+    }
+}
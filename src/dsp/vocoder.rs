@@ -0,0 +1,223 @@
+use super::biquad::{Biquad, Coefficients};
+use super::{Processor, db_to_gain, time_coefficient};
+use crate::params::ParamInfo;
+use std::f32::consts::FRAC_PI_2;
+
+const MAX_BANDS: usize = 32;
+/// Frame channels the carrier can be taken from.
+const MAX_CARRIER: usize = 8;
+/// Span of the band centres.
+const LOWEST: f32 = 100.0;
+const HIGHEST: f32 = 8000.0;
+/// The band envelopes follow syllables without buzzing at the pitch.
+const ATTACK_MS: f32 = 2.0;
+
+/// One band: two band-passes in series on each side and the modulator's
+/// level in it.
+#[derive(Clone, Copy, Default)]
+struct Band {
+    modulator: [Biquad; 2],
+    carrier: [Biquad; 2],
+    envelope: f32,
+}
+
+/// Classic channel vocoder: the mic's level in each of `bands` bands
+/// shapes the same band of a carrier, so a synth pad or the built-in saw
+/// speaks with the voice. `carrier` picks the carrier, 0 for the saw at
+/// `frequency` Hz, or a channel of the frame from 1, e.g. a synth on the
+/// second channel of an interface with the mic on the first. The other
+/// channels are the modulator, and every channel gets the result.
+///
+/// `formant` moves the carrier bands by semitones against the mic's, for
+/// a bigger or smaller voice than the singer's.
+pub struct Vocoder {
+    sample_rate: f32,
+    band_count: usize,
+    formant: f32,
+    carrier: usize,
+    frequency: f32,
+    release_ms: f32,
+    gain_db: f32,
+
+    modulator_coeffs: [Coefficients; MAX_BANDS],
+    carrier_coeffs: [Coefficients; MAX_BANDS],
+    bands: [Band; MAX_BANDS],
+    attack_coeff: f32,
+    release_coeff: f32,
+    /// Output gain, with the make-up for splitting the carrier into bands.
+    gain: f32,
+    /// Saw phase, 0 to 1.
+    phase: f32,
+}
+
+impl Vocoder {
+    pub fn new(sample_rate: f32) -> Self {
+        let mut vocoder = Vocoder {
+            sample_rate,
+            band_count: 16,
+            formant: 0.0,
+            carrier: 0,
+            frequency: 110.0,
+            release_ms: 30.0,
+            gain_db: 0.0,
+            modulator_coeffs: [Coefficients::default(); MAX_BANDS],
+            carrier_coeffs: [Coefficients::default(); MAX_BANDS],
+            bands: [Band::default(); MAX_BANDS],
+            attack_coeff: time_coefficient(ATTACK_MS, sample_rate),
+            release_coeff: 0.0,
+            gain: 1.0,
+            phase: 0.0,
+        };
+        vocoder.set_release(30.0);
+        vocoder.update_bands();
+        vocoder
+    }
+
+    pub fn set_bands(&mut self, bands: usize) {
+        self.band_count = bands.clamp(4, MAX_BANDS);
+        self.update_bands();
+    }
+
+    /// Carrier band shift, in semitones.
+    pub fn set_formant(&mut self, semitones: f32) {
+        self.formant = semitones.clamp(-12.0, 12.0);
+        self.update_bands();
+    }
+
+    /// 0 for the built-in saw, otherwise a channel of the frame from 1.
+    pub fn set_carrier(&mut self, carrier: usize) {
+        self.carrier = carrier.min(MAX_CARRIER);
+    }
+
+    /// Pitch of the built-in saw.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency.clamp(20.0, 2000.0);
+    }
+
+    /// How fast a band closes after the voice leaves it.
+    pub fn set_release(&mut self, release_ms: f32) {
+        self.release_ms = release_ms.clamp(5.0, 500.0);
+        self.release_coeff = time_coefficient(self.release_ms, self.sample_rate);
+    }
+
+    pub fn set_gain(&mut self, gain_db: f32) {
+        self.gain_db = gain_db.clamp(-24.0, 24.0);
+        self.update_gain();
+    }
+
+    /// Band centres evenly spaced in pitch, each as wide as the step to
+    /// the next so neighbours cross over.
+    fn update_bands(&mut self) {
+        let highest = HIGHEST.min(self.sample_rate * 0.45);
+        let count = self.band_count;
+        let step = (highest / LOWEST).powf(1.0 / (count - 1) as f32);
+        let q = step.sqrt() / (step - 1.0);
+        let shift = 2f32.powf(self.formant / 12.0);
+        for band in 0..count {
+            let centre = LOWEST * step.powi(band as i32);
+            self.modulator_coeffs[band] = Coefficients::band_pass(centre, q, self.sample_rate);
+            self.carrier_coeffs[band] =
+                Coefficients::band_pass(centre * shift, q, self.sample_rate);
+        }
+        self.update_gain();
+    }
+
+    /// A broadband carrier spreads its level over the bands and the
+    /// envelopes read the rectified mean, both made up here.
+    fn update_gain(&mut self) {
+        self.gain = db_to_gain(self.gain_db) * (self.band_count as f32).sqrt() * FRAC_PI_2;
+    }
+
+    /// Band-limited saw, the steps smoothed with PolyBLEP.
+    fn saw(&mut self) -> f32 {
+        let increment = self.frequency / self.sample_rate;
+        let t = self.phase;
+        let mut value = 2.0 * t - 1.0;
+        if t < increment {
+            let x = t / increment;
+            value -= x + x - x * x - 1.0;
+        } else if t > 1.0 - increment {
+            let x = (t - 1.0) / increment;
+            value -= x * x + x + x + 1.0;
+        }
+        self.phase += increment;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        value
+    }
+}
+
+impl Processor for Vocoder {
+    fn name(&self) -> &'static str {
+        "vocoder"
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        // A carrier channel past the frame, or the only channel, falls back
+        // to the saw
+        let carrier =
+            (channels > 1 && (1..=channels).contains(&self.carrier)).then(|| self.carrier - 1);
+        let others = (channels - carrier.is_some() as usize) as f32;
+
+        for frame in buffer.chunks_mut(channels) {
+            let (modulator, carrier) = match carrier {
+                Some(index) => {
+                    let sum: f32 = frame.iter().sum();
+                    ((sum - frame[index]) / others, frame[index])
+                }
+                None => (frame.iter().sum::<f32>() / others, self.saw()),
+            };
+
+            let mut out = 0.0;
+            let count = self.band_count;
+            let bands = self.bands[..count]
+                .iter_mut()
+                .zip(&self.modulator_coeffs[..count])
+                .zip(&self.carrier_coeffs[..count]);
+            for ((band, m), c) in bands {
+                let filtered = band.modulator[0].process(m, modulator);
+                let level = band.modulator[1].process(m, filtered).abs();
+                let coeff = if level > band.envelope {
+                    self.attack_coeff
+                } else {
+                    self.release_coeff
+                };
+                band.envelope = level + (band.envelope - level) * coeff;
+                let filtered = band.carrier[0].process(c, carrier);
+                out += band.carrier[1].process(c, filtered) * band.envelope;
+            }
+
+            frame.fill(out * self.gain);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bands = [Band::default(); MAX_BANDS];
+        self.phase = 0.0;
+    }
+
+    /// `carrier` is 0 for the built-in saw or a channel from 1.
+    fn params(&self) -> Vec<ParamInfo> {
+        vec![
+            ParamInfo::new("bands", self.band_count as f32, 4.0, MAX_BANDS as f32),
+            ParamInfo::new("formant", self.formant, -12.0, 12.0),
+            ParamInfo::new("carrier", self.carrier as f32, 0.0, MAX_CARRIER as f32),
+            ParamInfo::new("frequency", self.frequency, 20.0, 2000.0),
+            ParamInfo::new("release", self.release_ms, 5.0, 500.0),
+            ParamInfo::new("gain", self.gain_db, -24.0, 24.0),
+        ]
+    }
+
+    fn set_param(&mut self, name: &str, value: f32) {
+        match name {
+            "bands" => self.set_bands(value.round() as usize),
+            "formant" => self.set_formant(value),
+            "carrier" => self.set_carrier(value.round() as usize),
+            "frequency" => self.set_frequency(value),
+            "release" => self.set_release(value),
+            "gain" => self.set_gain(value),
+            _ => {}
+        }
+    }
+}
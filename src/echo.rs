@@ -0,0 +1,201 @@
+use crate::source::Source;
+use anyhow::{Result, bail};
+use ringbuf::traits::{Consumer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::ffi::{c_int, c_void};
+use tracing::info;
+
+/// Block speex cancels at once, 10 ms.
+const FRAME_SECONDS: f32 = 0.01;
+/// Longest echo path cancelled, speaker to mic with the room's reflections.
+const TAIL_SECONDS: f32 = 0.25;
+/// Output kept for the canceller, more than one callback ever needs.
+const REFERENCE_SECONDS: f32 = 1.0;
+
+/// Wraps the mic `source` with a speex echo canceller. The returned tap
+/// goes at the very end of the output processing, so the canceller
+/// learns what the speakers play and takes it out of the mic.
+pub fn cancel(source: Box<dyn Source>, sample_rate: u32) -> Result<(EchoCanceller, EchoReference)> {
+    let frame = (sample_rate as f32 * FRAME_SECONDS) as usize;
+    let tail = (sample_rate as f32 * TAIL_SECONDS) as usize;
+    let (producer, consumer) =
+        HeapRb::<f32>::new((sample_rate as f32 * REFERENCE_SECONDS) as usize).split();
+
+    let echo = unsafe { ffi::speex_echo_state_init(frame as c_int, tail as c_int) };
+    if echo.is_null() {
+        bail!("Failed to start the echo canceller");
+    }
+    let preprocess =
+        unsafe { ffi::speex_preprocess_state_init(frame as c_int, sample_rate as c_int) };
+    if preprocess.is_null() {
+        unsafe { ffi::speex_echo_state_destroy(echo) };
+        bail!("Failed to start the echo suppressor");
+    }
+    let mut rate = sample_rate as c_int;
+    // Only the residual echo suppression, noise is left to the chain
+    let mut denoise: c_int = 0;
+    unsafe {
+        ffi::speex_echo_ctl(
+            echo,
+            ffi::SPEEX_ECHO_SET_SAMPLING_RATE,
+            &mut rate as *mut c_int as *mut c_void,
+        );
+        ffi::speex_preprocess_ctl(
+            preprocess,
+            ffi::SPEEX_PREPROCESS_SET_ECHO_STATE,
+            echo as *mut c_void,
+        );
+        ffi::speex_preprocess_ctl(
+            preprocess,
+            ffi::SPEEX_PREPROCESS_SET_DENOISE,
+            &mut denoise as *mut c_int as *mut c_void,
+        );
+    }
+    info!(
+        "Echo cancellation on {}: {} ms tail",
+        source.name(),
+        (TAIL_SECONDS * 1000.0) as u32
+    );
+
+    let canceller = EchoCanceller {
+        source,
+        echo,
+        preprocess,
+        reference: consumer,
+        mic: vec![0; frame],
+        played: vec![0; frame],
+        cancelled: vec![0; frame],
+        position: 0,
+    };
+    Ok((canceller, EchoReference { producer }))
+}
+
+/// The mic source with the speakers taken out. Works on a mono mix of
+/// the mic, written back to every channel, and delays it by one frame.
+pub struct EchoCanceller {
+    source: Box<dyn Source>,
+    echo: *mut ffi::SpeexEchoState,
+    preprocess: *mut ffi::SpeexPreprocessState,
+    reference: HeapCons<f32>,
+    /// Frame being collected, the speaker signal alongside it, and the
+    /// last frame cancelled, played out while the next one fills.
+    mic: Vec<i16>,
+    played: Vec<i16>,
+    cancelled: Vec<i16>,
+    position: usize,
+}
+
+// Made on the main thread, only used by the output callback afterwards
+unsafe impl Send for EchoCanceller {}
+
+impl Source for EchoCanceller {
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        self.source.read_into(data, channels);
+
+        for frame in data.chunks_mut(channels) {
+            let mic = frame.iter().sum::<f32>() / channels as f32;
+            // Silence before the output has run once
+            let played = self.reference.try_pop().unwrap_or(0.0);
+            self.mic[self.position] = to_i16(mic);
+            self.played[self.position] = to_i16(played);
+            let out = self.cancelled[self.position] as f32 / 32768.0;
+            frame.iter_mut().for_each(|s| *s = out);
+
+            self.position += 1;
+            if self.position == self.mic.len() {
+                self.position = 0;
+                unsafe {
+                    ffi::speex_echo_cancellation(
+                        self.echo,
+                        self.mic.as_ptr(),
+                        self.played.as_ptr(),
+                        self.cancelled.as_mut_ptr(),
+                    );
+                    ffi::speex_preprocess_run(self.preprocess, self.cancelled.as_mut_ptr());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for EchoCanceller {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::speex_preprocess_state_destroy(self.preprocess);
+            ffi::speex_echo_state_destroy(self.echo);
+        }
+    }
+}
+
+/// Copies what the speakers play, as mono, to the canceller. Never
+/// blocks, output the canceller hasn't taken yet is dropped.
+pub struct EchoReference {
+    producer: HeapProd<f32>,
+}
+
+impl EchoReference {
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        for frame in buffer.chunks(channels) {
+            let _ = self
+                .producer
+                .try_push(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample * 32767.0).clamp(-32768.0, 32767.0) as i16
+}
+
+/// The parts of speexdsp's echo canceller and preprocessor used.
+mod ffi {
+    use std::ffi::{c_int, c_void};
+
+    #[repr(C)]
+    pub struct SpeexEchoState {
+        _private: [u8; 0],
+    }
+    #[repr(C)]
+    pub struct SpeexPreprocessState {
+        _private: [u8; 0],
+    }
+
+    pub const SPEEX_ECHO_SET_SAMPLING_RATE: c_int = 24;
+    pub const SPEEX_PREPROCESS_SET_DENOISE: c_int = 0;
+    pub const SPEEX_PREPROCESS_SET_ECHO_STATE: c_int = 24;
+
+    #[link(name = "speexdsp")]
+    unsafe extern "C" {
+        pub fn speex_echo_state_init(
+            frame_size: c_int,
+            filter_length: c_int,
+        ) -> *mut SpeexEchoState;
+        pub fn speex_echo_state_destroy(state: *mut SpeexEchoState);
+        pub fn speex_echo_cancellation(
+            state: *mut SpeexEchoState,
+            rec: *const i16,
+            play: *const i16,
+            out: *mut i16,
+        );
+        pub fn speex_echo_ctl(
+            state: *mut SpeexEchoState,
+            request: c_int,
+            ptr: *mut c_void,
+        ) -> c_int;
+        pub fn speex_preprocess_state_init(
+            frame_size: c_int,
+            sampling_rate: c_int,
+        ) -> *mut SpeexPreprocessState;
+        pub fn speex_preprocess_state_destroy(state: *mut SpeexPreprocessState);
+        pub fn speex_preprocess_run(state: *mut SpeexPreprocessState, x: *mut i16) -> c_int;
+        pub fn speex_preprocess_ctl(
+            state: *mut SpeexPreprocessState,
+            request: c_int,
+            ptr: *mut c_void,
+        ) -> c_int;
+    }
+}
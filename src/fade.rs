@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Default length of the fades around a stream switch.
+pub const DEFAULT_CROSSFADE: Duration = Duration::from_millis(20);
+/// Time on top of the fade for the callback to get around to it.
+const CALLBACK_SLACK: Duration = Duration::from_millis(20);
+
+/// Control side of a fade. When a stream is switched the old path fades out
+/// and the new one fades in, so the switch dips briefly instead of clicking.
+#[derive(Debug)]
+pub struct FadeControl {
+    pub length: Duration,
+    open: AtomicBool,
+    /// Drop to silence without a ramp on the next callback.
+    mute: AtomicBool,
+}
+
+impl FadeControl {
+    /// Fades to silence and returns once it got there, so the stream can be
+    /// torn down right after.
+    pub fn fade_out(&self) {
+        self.open.store(false, Ordering::Relaxed);
+        thread::sleep(self.length + CALLBACK_SLACK);
+    }
+
+    pub fn fade_in(&self) {
+        self.open.store(true, Ordering::Relaxed);
+    }
+
+    /// Silences at once, for a stream that's gone already and has nothing
+    /// left to fade. `fade_in` brings it back.
+    pub fn mute(&self) {
+        self.open.store(false, Ordering::Relaxed);
+        self.mute.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Audio side of a fade, ramping its gain linearly between 0 and 1.
+pub struct Fade {
+    control: Arc<FadeControl>,
+    gain: f32,
+    target: f32,
+    /// Gain change per frame.
+    step: f32,
+}
+
+impl Fade {
+    /// A zero `length` switches within one frame.
+    pub fn new(sample_rate: f32, length: Duration) -> (Self, Arc<FadeControl>) {
+        let control = Arc::new(FadeControl {
+            length,
+            open: AtomicBool::new(true),
+            mute: AtomicBool::new(false),
+        });
+        let frames = (length.as_secs_f32() * sample_rate).max(1.0);
+        let fade = Fade {
+            control: control.clone(),
+            gain: 1.0,
+            target: 1.0,
+            step: 1.0 / frames,
+        };
+        (fade, control)
+    }
+
+    /// Picks up the control's state. Call once per callback before `apply`.
+    pub fn begin(&mut self) {
+        if self.control.mute.swap(false, Ordering::Relaxed) {
+            self.gain = 0.0;
+        }
+        self.target = if self.control.open.load(Ordering::Relaxed) {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    /// Drops the gain to 0, it ramps back in on the next frames applied.
+    pub fn silence(&mut self) {
+        self.gain = 0.0;
+    }
+
+    /// Applies the gain to one frame and moves it one step along.
+    pub fn apply(&mut self, frame: &mut [f32]) {
+        if self.gain == self.target {
+            if self.gain == 0.0 {
+                frame.fill(0.0);
+            }
+            return;
+        }
+        self.gain = if self.target > self.gain {
+            (self.gain + self.step).min(self.target)
+        } else {
+            (self.gain - self.step).max(self.target)
+        };
+        frame.iter_mut().for_each(|s| *s *= self.gain);
+    }
+
+    pub fn process(&mut self, data: &mut [f32], channels: usize) {
+        self.begin();
+        if self.gain == self.target && self.gain == 1.0 {
+            return;
+        }
+        for frame in data.chunks_mut(channels) {
+            self.apply(frame);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+
+/// Creates a ring that moves whole interleaved frames of `channels` samples.
+///
+/// Every channel shares one ring, and a frame is only pushed when there is
+/// room for all of it and only popped when all of it is there. A full or
+/// empty ring therefore drops or repeats whole frames, it can never shift one
+/// channel against the others.
+pub fn frame_ring(capacity_frames: usize, channels: usize) -> (FrameProducer, FrameConsumer) {
+    let channels = channels.max(1);
+    let (producer, consumer) = HeapRb::<f32>::new(capacity_frames.max(1) * channels).split();
+    (
+        FrameProducer {
+            inner: producer,
+            channels,
+        },
+        FrameConsumer {
+            inner: consumer,
+            channels,
+        },
+    )
+}
+
+pub struct FrameProducer {
+    inner: HeapProd<f32>,
+    channels: usize,
+}
+
+impl FrameProducer {
+    /// Pushes one frame, or nothing if the ring has no room for all of it.
+    /// `frame` must be `channels` samples long.
+    pub fn push_frame(&mut self, frame: &[f32]) -> bool {
+        debug_assert_eq!(frame.len(), self.channels);
+        if self.inner.vacant_len() < self.channels {
+            return false;
+        }
+        self.inner.push_slice(frame);
+        true
+    }
+}
+
+pub struct FrameConsumer {
+    inner: HeapCons<f32>,
+    channels: usize,
+}
+
+impl FrameConsumer {
+    /// Frames that can be popped right now. The producer can only add to
+    /// this.
+    pub fn occupied_frames(&self) -> usize {
+        self.inner.occupied_len() / self.channels
+    }
+
+    /// Pops one frame into `frame`, or leaves it untouched if the ring does
+    /// not hold a whole one. `frame` must be `channels` samples long.
+    pub fn pop_frame(&mut self, frame: &mut [f32]) -> bool {
+        debug_assert_eq!(frame.len(), self.channels);
+        if self.inner.occupied_len() < self.channels {
+            return false;
+        }
+        self.inner.pop_slice(frame);
+        true
+    }
+
+    /// Throws away up to `frames` of the oldest frames and returns how many
+    /// were dropped.
+    pub fn skip_frames(&mut self, frames: usize) -> usize {
+        let frames = frames.min(self.occupied_frames());
+        self.inner.skip(frames * self.channels);
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Frame `n` of channel `c` carries `n * 8 + c`, so a frame read back
+    /// shows both where it came from and whether its channels still belong
+    /// together.
+    fn make_frame(n: usize, channels: usize) -> Vec<f32> {
+        (0..channels).map(|c| (n * 8 + c) as f32).collect()
+    }
+
+    fn check_frame(frame: &[f32]) -> usize {
+        let n = frame[0] as usize / 8;
+        for (c, &sample) in frame.iter().enumerate() {
+            assert_eq!(
+                sample,
+                (n * 8 + c) as f32,
+                "channels out of step in {:?}",
+                frame
+            );
+        }
+        n
+    }
+
+    #[test]
+    fn overflow_drops_whole_frames() {
+        let (mut producer, mut consumer) = frame_ring(4, 2);
+        let pushed: Vec<bool> = (0..10)
+            .map(|n| producer.push_frame(&make_frame(n, 2)))
+            .collect();
+        assert_eq!(pushed.iter().filter(|&&p| p).count(), 4);
+        assert!(pushed[..4].iter().all(|&p| p));
+
+        let mut frame = [0.0; 2];
+        for n in 0..4 {
+            assert!(consumer.pop_frame(&mut frame));
+            assert_eq!(check_frame(&frame), n);
+        }
+        assert!(!consumer.pop_frame(&mut frame));
+    }
+
+    #[test]
+    fn underflow_leaves_frame_untouched() {
+        let (mut producer, mut consumer) = frame_ring(4, 3);
+        let mut frame = [-1.0; 3];
+        assert!(!consumer.pop_frame(&mut frame));
+        assert_eq!(frame, [-1.0; 3]);
+
+        assert!(producer.push_frame(&make_frame(5, 3)));
+        assert!(consumer.pop_frame(&mut frame));
+        assert_eq!(check_frame(&frame), 5);
+    }
+
+    #[test]
+    fn push_after_partial_drain_stays_aligned() {
+        // An odd channel count doesn't divide the ring's wrap point evenly
+        // unless the capacity is counted in frames
+        let (mut producer, mut consumer) = frame_ring(3, 5);
+        let mut frame = [0.0; 5];
+        let mut next_push = 0;
+        let mut next_pop = 0;
+        for round in 0..50 {
+            while producer.push_frame(&make_frame(next_push, 5)) {
+                next_push += 1;
+            }
+            for _ in 0..(round % 3) + 1 {
+                if consumer.pop_frame(&mut frame) {
+                    assert_eq!(check_frame(&frame), next_pop);
+                    next_pop += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skip_frames_keeps_alignment() {
+        let (mut producer, mut consumer) = frame_ring(8, 2);
+        for n in 0..6 {
+            assert!(producer.push_frame(&make_frame(n, 2)));
+        }
+        assert_eq!(consumer.skip_frames(4), 4);
+        let mut frame = [0.0; 2];
+        assert!(consumer.pop_frame(&mut frame));
+        assert_eq!(check_frame(&frame), 4);
+        assert_eq!(consumer.skip_frames(10), 1);
+        assert_eq!(consumer.occupied_frames(), 0);
+    }
+
+    #[test]
+    fn concurrent_overflow_keeps_frames_intact() {
+        // A producer much faster than the consumer keeps the ring full, so
+        // most pushes fail. Every frame read must still be whole, and frame
+        // numbers must only go up.
+        let (mut producer, mut consumer) = frame_ring(16, 2);
+        let writer = thread::spawn(move || {
+            let mut dropped = 0;
+            for n in 0..200_000 {
+                if !producer.push_frame(&make_frame(n, 2)) {
+                    dropped += 1;
+                }
+            }
+            dropped
+        });
+
+        let mut frame = [0.0; 2];
+        let mut last = None;
+        let mut popped = 0;
+        while !writer.is_finished() || consumer.occupied_frames() > 0 {
+            if consumer.pop_frame(&mut frame) {
+                let n = check_frame(&frame);
+                assert!(last.is_none_or(|last| n > last));
+                last = Some(n);
+                popped += 1;
+            }
+        }
+        let dropped = writer.join().unwrap();
+        assert_eq!(popped + dropped, 200_000);
+    }
+}
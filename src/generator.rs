@@ -0,0 +1,178 @@
+use crate::dsp::db_to_gain;
+use crate::params::{Param, ParamInfo, ParamStore};
+use crate::source::Source;
+use anyhow::{Result, bail};
+use std::f64::consts::TAU;
+use std::sync::Arc;
+
+/// Ends of the log sweep.
+const SWEEP_START_HZ: f64 = 20.0;
+const SWEEP_END_HZ: f64 = 20_000.0;
+/// Seconds between impulses.
+const IMPULSE_PERIOD: f64 = 1.0;
+/// Brings the pink filter's output to the same RMS as the white noise.
+const PINK_SCALE: f32 = 0.33;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    WhiteNoise,
+    PinkNoise,
+    Sweep,
+    Impulse,
+}
+
+impl Waveform {
+    const ALL: [Waveform; 5] = [
+        Waveform::Sine,
+        Waveform::WhiteNoise,
+        Waveform::PinkNoise,
+        Waveform::Sweep,
+        Waveform::Impulse,
+    ];
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let waveform = match text.trim() {
+            "sine" => Waveform::Sine,
+            "white" => Waveform::WhiteNoise,
+            "pink" => Waveform::PinkNoise,
+            "sweep" => Waveform::Sweep,
+            "impulse" => Waveform::Impulse,
+            other => bail!(
+                "Unknown waveform '{}', expected sine, white, pink, sweep or impulse",
+                other
+            ),
+        };
+        Ok(waveform)
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&w| w == self).unwrap_or(0)
+    }
+}
+
+/// Test signal source for calibrating levels and measuring frequency
+/// response. The same mono signal goes to every output channel.
+///
+/// Level is the peak in dBFS for sine, sweep and impulse. The noises are
+/// scaled to the RMS of a sine at the same level, so both read the same on
+/// an RMS meter.
+pub struct SignalGenerator {
+    sample_rate: f64,
+    waveform: Arc<Param>,
+    frequency: Arc<Param>,
+    level_db: Arc<Param>,
+    sweep_seconds: Arc<Param>,
+
+    phase: f64,
+    /// Time into the current sweep or impulse period, in samples.
+    elapsed: u64,
+    rng: u32,
+    /// Paul Kellet's pink noise filter state.
+    pink: [f32; 7],
+}
+
+impl SignalGenerator {
+    /// Registers the generator's controls in `store` as `generator.*` so
+    /// they can be changed like any effect parameter.
+    pub fn new(waveform: Waveform, sample_rate: f32, store: &ParamStore) -> Self {
+        let param = |name: &str, info: ParamInfo| {
+            let param = Arc::new(Param::new(format!("generator.{}", name), &info));
+            store.add(param.clone());
+            param
+        };
+        let last = (Waveform::ALL.len() - 1) as f32;
+        SignalGenerator {
+            sample_rate: sample_rate as f64,
+            waveform: param(
+                "waveform",
+                ParamInfo::new("waveform", waveform.index() as f32, 0.0, last),
+            ),
+            frequency: param(
+                "frequency",
+                ParamInfo::new("frequency", 1000.0, 10.0, sample_rate / 2.0),
+            ),
+            level_db: param("level", ParamInfo::new("level", -18.0, -96.0, 0.0)),
+            sweep_seconds: param("sweep", ParamInfo::new("sweep", 10.0, 0.5, 60.0)),
+            phase: 0.0,
+            elapsed: 0,
+            rng: 0x2545_f491,
+            pink: [0.0; 7],
+        }
+    }
+
+    fn waveform(&self) -> Waveform {
+        let index = self.waveform.get().round() as usize;
+        Waveform::ALL[index.min(Waveform::ALL.len() - 1)]
+    }
+
+    /// Uniform in `[-1, 1)`.
+    fn white(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    fn pink(&mut self) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + white * 0.0555179;
+        b[1] = 0.99332 * b[1] + white * 0.0750759;
+        b[2] = 0.96900 * b[2] + white * 0.153852;
+        b[3] = 0.86650 * b[3] + white * 0.3104856;
+        b[4] = 0.55000 * b[4] + white * 0.5329522;
+        b[5] = -0.7616 * b[5] - white * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+        b[6] = white * 0.115926;
+        pink * PINK_SCALE
+    }
+
+    fn next_sample(&mut self, waveform: Waveform, gain: f32) -> f32 {
+        // Uniform noise has an RMS of 1/sqrt(3), a sine 1/sqrt(2)
+        let noise_gain = gain * (3.0f32 / 2.0).sqrt();
+        match waveform {
+            Waveform::Sine => {
+                self.phase = (self.phase + self.frequency.get() as f64 / self.sample_rate) % 1.0;
+                (self.phase * TAU).sin() as f32 * gain
+            }
+            Waveform::WhiteNoise => self.white() * noise_gain,
+            Waveform::PinkNoise => self.pink() * noise_gain,
+            Waveform::Sweep => {
+                let length = (self.sweep_seconds.get() as f64 * self.sample_rate) as u64;
+                if self.elapsed >= length {
+                    self.elapsed = 0;
+                    self.phase = 0.0;
+                }
+                let end = SWEEP_END_HZ.min(self.sample_rate * 0.45);
+                let t = self.elapsed as f64 / length.max(1) as f64;
+                let frequency = SWEEP_START_HZ * (end / SWEEP_START_HZ).powf(t);
+                self.elapsed += 1;
+                self.phase = (self.phase + frequency / self.sample_rate) % 1.0;
+                (self.phase * TAU).sin() as f32 * gain
+            }
+            Waveform::Impulse => {
+                let period = (IMPULSE_PERIOD * self.sample_rate) as u64;
+                let sample = if self.elapsed == 0 { gain } else { 0.0 };
+                self.elapsed = (self.elapsed + 1) % period.max(1);
+                sample
+            }
+        }
+    }
+}
+
+impl Source for SignalGenerator {
+    fn name(&self) -> &str {
+        "generator"
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        let waveform = self.waveform();
+        let gain = db_to_gain(self.level_db.get());
+        for frame in data.chunks_mut(channels) {
+            let sample = self.next_sample(waveform, gain);
+            frame.iter_mut().for_each(|s| *s = sample);
+        }
+    }
+}
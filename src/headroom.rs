@@ -0,0 +1,216 @@
+use crate::dsp::gain_to_db;
+use crate::params::AtomicF32;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Peaks above this leave too little room for the next transient.
+const HOT_DB: f32 = -3.0;
+/// Peaks at or above this are as good as clipped.
+const FULL_SCALE_DB: f32 = -0.5;
+/// An input peaking below this wastes its converter's resolution.
+const QUIET_DB: f32 = -40.0;
+/// Below this the stage never saw a signal.
+const SILENT_DB: f32 = -90.0;
+/// A processor adding more than this makes the ones after it work hot.
+const BOOST_DB: f32 = 12.0;
+
+/// Highest samples at one stage of the signal path over the session: an
+/// input as it is converted, or a processor on its way in and out.
+#[derive(Debug)]
+pub struct StageLevels {
+    pub name: String,
+    /// Processors only, inputs have no level ahead of them.
+    input: Option<AtomicF32>,
+    output: AtomicF32,
+}
+
+/// Peaks at one point in time, in dBFS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StageSnapshot {
+    pub input_db: Option<f32>,
+    pub output_db: f32,
+}
+
+impl StageLevels {
+    pub fn input(name: String) -> Self {
+        StageLevels {
+            name,
+            input: None,
+            output: AtomicF32::new(0.0),
+        }
+    }
+
+    pub fn processor(name: String) -> Self {
+        StageLevels {
+            name,
+            input: Some(AtomicF32::new(0.0)),
+            output: AtomicF32::new(0.0),
+        }
+    }
+
+    /// Takes the peak of a block going into the processor.
+    pub fn record_input(&self, buffer: &[f32]) {
+        if let Some(input) = &self.input {
+            raise(input, buffer);
+        }
+    }
+
+    /// Takes the peak of a block as the stage hands it on.
+    pub fn record_output(&self, buffer: &[f32]) {
+        raise(&self.output, buffer);
+    }
+
+    pub fn snapshot(&self) -> StageSnapshot {
+        StageSnapshot {
+            input_db: self.input.as_ref().map(|input| gain_to_db(input.get())),
+            output_db: gain_to_db(self.output.get()),
+        }
+    }
+
+    pub fn reset(&self) {
+        if let Some(input) = &self.input {
+            input.set(0.0);
+        }
+        self.output.set(0.0);
+    }
+
+    pub fn describe(&self) -> String {
+        let snapshot = self.snapshot();
+        match snapshot.input_db {
+            Some(input_db) => format!(
+                "{}: in {}, out {}",
+                self.name,
+                format_peak(input_db),
+                format_peak(snapshot.output_db)
+            ),
+            None => format!("{}: {}", self.name, format_peak(snapshot.output_db)),
+        }
+    }
+
+    /// What to change about the gain around this stage, if anything.
+    pub fn advice(&self) -> Option<String> {
+        let snapshot = self.snapshot();
+        let output_db = snapshot.output_db;
+        let Some(input_db) = snapshot.input_db else {
+            return if output_db >= FULL_SCALE_DB {
+                Some(format!(
+                    "{} peaks at {:+.1} dBFS, lower the gain at the source",
+                    self.name, output_db
+                ))
+            } else if output_db > SILENT_DB && output_db < QUIET_DB {
+                Some(format!(
+                    "{} peaks at only {:+.1} dBFS, raise the gain at the source",
+                    self.name, output_db
+                ))
+            } else {
+                None
+            };
+        };
+        if input_db <= SILENT_DB {
+            return None;
+        }
+        if input_db >= HOT_DB {
+            Some(format!(
+                "{} input peaks at {:+.1} dBFS, consider lowering the gain ahead of it",
+                self.name, input_db
+            ))
+        } else if output_db >= FULL_SCALE_DB {
+            Some(format!(
+                "{} output peaks at {:+.1} dBFS, consider lowering its output or makeup gain",
+                self.name, output_db
+            ))
+        } else if output_db - input_db > BOOST_DB {
+            Some(format!(
+                "{} adds {:.1} dB, the processors after it run at {:+.1} dBFS",
+                self.name,
+                output_db - input_db,
+                output_db
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+fn raise(peak: &AtomicF32, buffer: &[f32]) {
+    let level = buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if level > peak.get() {
+        peak.set(level);
+    }
+}
+
+fn format_peak(db: f32) -> String {
+    if db <= SILENT_DB {
+        "no signal".to_string()
+    } else {
+        format!("{:+.1} dBFS", db)
+    }
+}
+
+/// Every stage the gain-staging analysis watches. Processors are only
+/// bound once it is enabled with `--headroom`, inputs are always watched
+/// since they are scanned for clipping anyway.
+#[derive(Debug, Default)]
+pub struct HeadroomMeters {
+    enabled: AtomicBool,
+    stages: Mutex<Vec<Arc<StageLevels>>>,
+}
+
+impl HeadroomMeters {
+    /// Starts watching processors bound from now on.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Adds the inputs ahead of the processors they feed.
+    pub fn add_inputs(&self, inputs: Vec<Arc<StageLevels>>) {
+        self.stages.lock().unwrap().splice(0..0, inputs);
+    }
+
+    /// The levels of the processor under `name`, or new ones, while
+    /// enabled.
+    pub fn bind(&self, name: String) -> Option<Arc<StageLevels>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let mut stages = self.stages.lock().unwrap();
+        if let Some(levels) = stages.iter().find(|s| s.name == name) {
+            return Some(levels.clone());
+        }
+        let levels = Arc::new(StageLevels::processor(name));
+        stages.push(levels.clone());
+        Some(levels)
+    }
+
+    /// Every stage in the order added.
+    pub fn all(&self) -> Vec<Arc<StageLevels>> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        for levels in self.all() {
+            levels.reset();
+        }
+    }
+
+    /// The peaks of every stage, then what to change, the earliest stage
+    /// first since fixing it moves the ones after it.
+    pub fn report(&self) -> String {
+        let stages = self.all();
+        if stages.is_empty() {
+            return "No stages watched".to_string();
+        }
+        let mut lines: Vec<String> = stages.iter().map(|s| s.describe()).collect();
+        let advice: Vec<String> = stages.iter().filter_map(|s| s.advice()).collect();
+        if advice.is_empty() {
+            lines.push("Gain staging looks fine".to_string());
+        } else {
+            lines.extend(advice);
+        }
+        lines.join("\n")
+    }
+}
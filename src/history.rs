@@ -0,0 +1,160 @@
+use crate::control::Controls;
+use crate::scene;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Changes kept to undo, the oldest are dropped past this.
+const DEPTH: usize = 100;
+/// Changes to the same settings closer together than this are one step,
+/// so a fader moved on a controller undoes in one go.
+const COALESCE: Duration = Duration::from_secs(1);
+/// Steps `history` lists.
+const SHOWN: usize = 10;
+
+/// One undo step: every setting it changed, from and to.
+struct Change {
+    values: Vec<(String, f32, f32)>,
+    /// Time of its latest change.
+    last: Instant,
+    /// Taken back or redone since, later changes don't join it.
+    closed: bool,
+}
+
+impl Change {
+    fn describe(&self) -> String {
+        match self.values.as_slice() {
+            [(key, before, after)] => format!("{} {} -> {}", key, before, after),
+            values => format!(
+                "{} settings: {}",
+                values.len(),
+                values
+                    .iter()
+                    .map(|(key, ..)| key.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Every setting as last looked at.
+    seen: HashMap<String, f32>,
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+/// Undo and redo over every setting a scene stores, the mixer and the
+/// effect parameters. The control loop looks for changes between commands,
+/// so whatever made them counts, the console, the TUI, MIDI or OSC. A
+/// scene recall or a fade is one step.
+#[derive(Default)]
+pub struct History {
+    state: Mutex<State>,
+}
+
+impl History {
+    /// Records what changed since the last look.
+    pub fn poll(&self, controls: &Controls) {
+        let now = scene::snapshot(controls);
+        let mut state = self.state.lock().unwrap();
+        let changed: Vec<(String, f32, f32)> = now
+            .iter()
+            .filter_map(|(key, value)| {
+                // Settings that are new since, e.g. after a reload, have
+                // nothing to go back to
+                let before = *state.seen.get(key)?;
+                (before != *value).then(|| (key.clone(), before, *value))
+            })
+            .collect();
+        state.seen = now.into_iter().collect();
+        if changed.is_empty() {
+            return;
+        }
+
+        state.redo.clear();
+        let time = Instant::now();
+        if let Some(top) = state.undo.last_mut()
+            && !top.closed
+            && time.duration_since(top.last) < COALESCE
+            && changed
+                .iter()
+                .all(|(key, ..)| top.values.iter().any(|(k, ..)| k == key))
+        {
+            for (key, _, after) in changed {
+                if let Some(value) = top.values.iter_mut().find(|(k, ..)| *k == key) {
+                    value.2 = after;
+                }
+            }
+            top.last = time;
+            return;
+        }
+        if state.undo.len() == DEPTH {
+            state.undo.remove(0);
+        }
+        state.undo.push(Change {
+            values: changed,
+            last: time,
+            closed: false,
+        });
+    }
+
+    /// Puts the settings of the last step back.
+    pub fn undo(&self, controls: &Controls) -> Result<String> {
+        self.poll(controls);
+        let mut state = self.state.lock().unwrap();
+        let Some(change) = state.undo.pop() else {
+            bail!("Nothing to undo");
+        };
+        for (key, before, _) in &change.values {
+            if scene::set(controls, key, *before) {
+                state.seen.insert(key.clone(), *before);
+            }
+        }
+        let line = format!("Undone: {}", change.describe());
+        state.redo.push(change);
+        if let Some(top) = state.undo.last_mut() {
+            top.closed = true;
+        }
+        Ok(line)
+    }
+
+    /// Makes the last step undone again.
+    pub fn redo(&self, controls: &Controls) -> Result<String> {
+        self.poll(controls);
+        let mut state = self.state.lock().unwrap();
+        let Some(mut change) = state.redo.pop() else {
+            bail!("Nothing to redo");
+        };
+        for (key, _, after) in &change.values {
+            if scene::set(controls, key, *after) {
+                state.seen.insert(key.clone(), *after);
+            }
+        }
+        let line = format!("Redone: {}", change.describe());
+        change.closed = true;
+        state.undo.push(change);
+        Ok(line)
+    }
+
+    /// The latest steps, newest first.
+    pub fn describe(&self, controls: &Controls) -> String {
+        self.poll(controls);
+        let state = self.state.lock().unwrap();
+        if state.undo.is_empty() {
+            return "Nothing to undo".to_string();
+        }
+        state
+            .undo
+            .iter()
+            .rev()
+            .take(SHOWN)
+            .enumerate()
+            .map(|(index, change)| format!("[{}] {}", index + 1, change.describe()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
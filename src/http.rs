@@ -0,0 +1,1186 @@
+use crate::control::{self, Command, Controls, Request};
+use crate::dsp::FeedbackView;
+use crate::jitter::JitterStats;
+use crate::json::Json;
+use crate::load::{self, CallbackLoad};
+use crate::loudness::LoudnessLevels;
+use crate::meter::MeterLevels;
+use crate::metrics::Exposition;
+use crate::mixer::{AuxControls, ChannelControls, MasterControls};
+use crate::params::{Param, ParamStore};
+use crate::phase::PhaseLevels;
+use crate::player::Transport;
+use crate::websocket::WebSocket;
+use crate::xrun::{XrunSnapshot, XrunStats};
+use anyhow::{Context, Result, bail};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Longest request body taken.
+const MAX_BODY: usize = 64 * 1024;
+/// Time a client gets to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// Mixer page served at `/`, working through the JSON API.
+const INDEX: &str = include_str!("web/index.html");
+/// Time between rounds of events on `/api/events`.
+const EVENT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Read side of the engine for the network threads, every handle cloned
+/// out of the `Controls`. Changes go through the control loop instead.
+#[derive(Clone)]
+pub struct View {
+    channels: Vec<Arc<ChannelControls>>,
+    aux: Vec<Arc<AuxControls>>,
+    master: Arc<MasterControls>,
+    output: Arc<MeterLevels>,
+    loudness: Arc<LoudnessLevels>,
+    phase: Arc<PhaseLevels>,
+    params: Arc<ParamStore>,
+    feedback: Arc<FeedbackView>,
+    players: Vec<Arc<Transport>>,
+    xruns: Vec<Arc<XrunStats>>,
+    buffers: Vec<Arc<JitterStats>>,
+    load: Arc<CallbackLoad>,
+    profiles: Vec<String>,
+}
+
+impl View {
+    pub fn new(controls: &Controls) -> Self {
+        View {
+            channels: controls.channels.clone(),
+            aux: controls.aux.clone(),
+            master: controls.master.clone(),
+            output: controls.output.clone(),
+            loudness: controls.loudness.clone(),
+            phase: controls.phase.clone(),
+            params: controls.params.clone(),
+            feedback: controls.feedback.clone(),
+            players: controls.players.clone(),
+            xruns: controls.xruns.clone(),
+            buffers: controls.buffers.clone(),
+            load: controls.load.clone(),
+            profiles: controls.profiles.iter().map(|p| p.name.clone()).collect(),
+        }
+    }
+
+    pub fn state(&self) -> Json {
+        Json::object([
+            ("channels", self.channels()),
+            ("aux", self.aux()),
+            ("master", self.master()),
+            ("levels", self.levels()),
+            ("streams", self.streams()),
+            ("params", self.params()),
+            ("feedback", self.feedback()),
+            ("clips", self.clips()),
+            ("cpu", self.cpu()),
+            ("players", self.players()),
+            ("profiles", self.profiles()),
+        ])
+    }
+
+    pub fn channels(&self) -> Json {
+        Json::Array(
+            self.channels
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    let sends = c
+                        .sends
+                        .iter()
+                        .enumerate()
+                        .map(|(bus, send)| {
+                            Json::object([
+                                ("aux", (bus as u64 + 1).into()),
+                                ("level_db", send.level_db().into()),
+                                ("pre_fader", send.pre_fader().into()),
+                            ])
+                        })
+                        .collect();
+                    Json::object([
+                        ("channel", (i as u64 + 1).into()),
+                        ("name", c.name.as_str().into()),
+                        ("gain_db", c.gain_db().into()),
+                        ("pan", c.pan().into()),
+                        ("mute", c.muted().into()),
+                        ("solo", c.soloed().into()),
+                        ("sends", Json::Array(sends)),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub fn aux(&self) -> Json {
+        Json::Array(
+            self.aux
+                .iter()
+                .enumerate()
+                .map(|(i, a)| {
+                    Json::object([
+                        ("aux", (i as u64 + 1).into()),
+                        ("name", a.name.as_str().into()),
+                        ("return_db", a.return_db().into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub fn master(&self) -> Json {
+        Json::object([
+            ("gain_db", self.master.gain_db().into()),
+            ("dim", self.master.dimmed().into()),
+            ("dim_db", self.master.dim_db().into()),
+            ("mono", self.master.mono().into()),
+        ])
+    }
+
+    /// Meter readings of every channel and the output, the loudness and the
+    /// phase correlation.
+    pub fn levels(&self) -> Json {
+        Json::object([
+            (
+                "channels",
+                Json::Array(self.channels.iter().map(|c| meter(&c.meter)).collect()),
+            ),
+            ("output", meter(&self.output)),
+            (
+                "loudness",
+                Json::object([
+                    ("momentary", self.loudness.momentary().into()),
+                    ("short_term", self.loudness.short_term().into()),
+                    ("integrated", self.loudness.integrated().into()),
+                    ("true_peak_db", self.loudness.true_peak_db().into()),
+                ]),
+            ),
+            (
+                "phase",
+                Json::object([
+                    ("correlation", self.phase.correlation().into()),
+                    ("lowest", self.phase.lowest().into()),
+                ]),
+            ),
+        ])
+    }
+
+    /// Every stream with its xruns, and the input jitter buffers.
+    pub fn streams(&self) -> Json {
+        let streams = self
+            .xruns
+            .iter()
+            .map(|x| {
+                let snapshot = x.snapshot();
+                let (min, max) = x.callback_range().unwrap_or((0, 0));
+                Json::object([
+                    ("name", x.name.as_str().into()),
+                    ("sample_rate", (x.sample_rate as u64).into()),
+                    ("callbacks", x.callbacks().into()),
+                    ("callback_min", min.into()),
+                    ("callback_max", max.into()),
+                    ("overruns", snapshot.overruns.into()),
+                    ("underruns", snapshot.underruns.into()),
+                    ("longest_gap", snapshot.longest_gap.into()),
+                    ("errors", snapshot.errors.into()),
+                ])
+            })
+            .collect();
+        let buffers = self
+            .buffers
+            .iter()
+            .map(|b| {
+                Json::object([
+                    ("name", b.name.as_str().into()),
+                    ("target", b.target().into()),
+                    ("margin", b.margin().into()),
+                    ("drift_ppm", b.drift_ppm().into()),
+                ])
+            })
+            .collect();
+        Json::object([
+            ("streams", Json::Array(streams)),
+            ("buffers", Json::Array(buffers)),
+        ])
+    }
+
+    pub fn params(&self) -> Json {
+        Json::Array(self.params.all().iter().map(|p| param(p)).collect())
+    }
+
+    /// Every input and processor watched for clipping, with its counts and
+    /// hold.
+    pub fn clips(&self) -> Json {
+        Json::Array(
+            self.params
+                .clips()
+                .all()
+                .iter()
+                .map(|stats| {
+                    let snapshot = stats.snapshot();
+                    Json::object([
+                        ("name", stats.name.as_str().into()),
+                        ("held", stats.held().into()),
+                        ("samples", snapshot.samples.into()),
+                        ("blocks", snapshot.blocks.into()),
+                        (
+                            "channels",
+                            Json::Array(
+                                snapshot
+                                    .channels
+                                    .iter()
+                                    .map(|&channel| (channel as u64 + 1).into())
+                                    .collect(),
+                            ),
+                        ),
+                        ("peak_db", snapshot.peak_db.into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Time each processor takes per callback in microseconds, and its
+    /// share of the callback budget.
+    pub fn cpu(&self) -> Json {
+        let sample_rate = self.load.sample_rate;
+        Json::Array(
+            self.params
+                .loads()
+                .all()
+                .iter()
+                .map(|load| {
+                    let s = load.snapshot();
+                    Json::object([
+                        ("name", load.name.as_str().into()),
+                        ("calls", s.calls.into()),
+                        ("min_us", (s.shortest.as_secs_f32() * 1e6).into()),
+                        ("avg_us", (s.average().as_secs_f32() * 1e6).into()),
+                        ("max_us", (s.longest.as_secs_f32() * 1e6).into()),
+                        ("load", s.load(sample_rate).into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    /// Whether a feedback suppressor runs and the notches it set.
+    pub fn feedback(&self) -> Json {
+        Json::object([
+            ("attached", self.feedback.is_attached().into()),
+            (
+                "notches",
+                Json::Array(
+                    self.feedback
+                        .notches()
+                        .iter()
+                        .map(|&(frequency, depth_db)| {
+                            Json::object([
+                                ("frequency", frequency.into()),
+                                ("depth_db", depth_db.into()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+
+    pub fn players(&self) -> Json {
+        Json::Array(
+            self.players
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    Json::object([
+                        ("player", (i as u64 + 1).into()),
+                        ("name", p.name.as_str().into()),
+                        ("playing", p.playing().into()),
+                        ("looping", p.looping().into()),
+                        ("position", p.position_seconds().into()),
+                        ("length", p.length_seconds().into()),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    pub fn profiles(&self) -> Json {
+        Json::Array(self.profiles.iter().map(|p| p.as_str().into()).collect())
+    }
+}
+
+fn meter(levels: &MeterLevels) -> Json {
+    Json::Array(
+        levels
+            .levels()
+            .map(|level| {
+                Json::object([
+                    ("peak_db", level.peak_db.into()),
+                    ("rms_db", level.rms_db.into()),
+                    ("hold_db", level.hold_db.into()),
+                ])
+            })
+            .collect(),
+    )
+}
+
+impl View {
+    /// Engine health for Prometheus: xruns, render time, jitter buffers,
+    /// clipping and processor time, as served on `GET /metrics`.
+    pub fn metrics(&self) -> String {
+        let mut page = Exposition::new();
+
+        page.family(
+            "xruns_total",
+            "counter",
+            "Callbacks that dropped samples (overrun), played silence (underrun) or reported a backend error",
+        );
+        for stats in &self.xruns {
+            let s = stats.snapshot();
+            for (kind, count) in [
+                ("overrun", s.overruns),
+                ("underrun", s.underruns),
+                ("error", s.errors),
+            ] {
+                page.sample(
+                    "xruns_total",
+                    &[("stream", stats.name.as_str()), ("kind", kind)],
+                    count as f64,
+                );
+            }
+        }
+        page.family(
+            "xrun_samples_total",
+            "counter",
+            "Samples dropped or missing in xruns",
+        );
+        for stats in &self.xruns {
+            let s = stats.snapshot();
+            for (kind, count) in [
+                ("overrun", s.overrun_samples),
+                ("underrun", s.underrun_samples),
+            ] {
+                page.sample(
+                    "xrun_samples_total",
+                    &[("stream", stats.name.as_str()), ("kind", kind)],
+                    count as f64,
+                );
+            }
+        }
+        page.family(
+            "stream_callbacks_total",
+            "counter",
+            "Audio callbacks per stream",
+        );
+        for stats in &self.xruns {
+            page.sample(
+                "stream_callbacks_total",
+                &[("stream", stats.name.as_str())],
+                stats.callbacks() as f64,
+            );
+        }
+
+        let render = self.load.snapshot();
+        page.family(
+            "callback_duration_seconds",
+            "histogram",
+            "Time the output callback spends rendering",
+        );
+        let mut cumulative = 0;
+        for (bound, count) in load::BUCKETS_US.iter().zip(self.load.buckets()) {
+            cumulative += count;
+            let le = (*bound as f64 / 1e6).to_string();
+            page.sample(
+                "callback_duration_seconds_bucket",
+                &[("le", le.as_str())],
+                cumulative as f64,
+            );
+        }
+        page.sample(
+            "callback_duration_seconds_bucket",
+            &[("le", "+Inf")],
+            render.callbacks as f64,
+        );
+        page.sample(
+            "callback_duration_seconds_sum",
+            &[],
+            render.busy.as_secs_f64(),
+        );
+        page.sample(
+            "callback_duration_seconds_count",
+            &[],
+            render.callbacks as f64,
+        );
+        page.family(
+            "callback_longest_seconds",
+            "gauge",
+            "Longest output callback so far",
+        );
+        page.sample(
+            "callback_longest_seconds",
+            &[],
+            render.longest.as_secs_f64(),
+        );
+        page.family(
+            "rendered_audio_seconds_total",
+            "counter",
+            "Length of the audio rendered, divide the duration sum's rate by its rate for the load",
+        );
+        page.sample(
+            "rendered_audio_seconds_total",
+            &[],
+            render.audio.as_secs_f64(),
+        );
+        page.family(
+            "callback_load_ratio",
+            "gauge",
+            "Share of the audio's duration spent rendering it over the session",
+        );
+        page.sample("callback_load_ratio", &[], render.load() as f64);
+
+        page.family(
+            "buffer_target_frames",
+            "gauge",
+            "Frames an input's jitter buffer aims to keep queued",
+        );
+        for stats in &self.buffers {
+            page.sample(
+                "buffer_target_frames",
+                &[("input", stats.name.as_str())],
+                stats.target() as f64,
+            );
+        }
+        page.family(
+            "buffer_margin_frames",
+            "gauge",
+            "Lowest fill level of an input's jitter buffer over the last window",
+        );
+        for stats in &self.buffers {
+            page.sample(
+                "buffer_margin_frames",
+                &[("input", stats.name.as_str())],
+                stats.margin() as f64,
+            );
+        }
+        page.family(
+            "buffer_drift_ppm",
+            "gauge",
+            "Estimated clock drift of an input",
+        );
+        for stats in &self.buffers {
+            page.sample(
+                "buffer_drift_ppm",
+                &[("input", stats.name.as_str())],
+                stats.drift_ppm() as f64,
+            );
+        }
+        page.family(
+            "buffer_corrections_total",
+            "counter",
+            "Frames dropped or repeated to hold the target, and refills after running dry",
+        );
+        for stats in &self.buffers {
+            let (dropped, inserted, prefills) = stats.corrections();
+            for (kind, count) in [
+                ("dropped", dropped),
+                ("inserted", inserted),
+                ("prefill", prefills),
+            ] {
+                page.sample(
+                    "buffer_corrections_total",
+                    &[("input", stats.name.as_str()), ("kind", kind)],
+                    count as f64,
+                );
+            }
+        }
+
+        let clips = self.params.clips().all();
+        page.family(
+            "clipped_samples_total",
+            "counter",
+            "Samples at full scale per input and processor since the counters were cleared",
+        );
+        for stats in &clips {
+            page.sample(
+                "clipped_samples_total",
+                &[("stage", stats.name.as_str())],
+                stats.snapshot().samples as f64,
+            );
+        }
+        page.family(
+            "clip_held",
+            "gauge",
+            "1 while a stage holds a clip indicator",
+        );
+        for stats in &clips {
+            page.sample(
+                "clip_held",
+                &[("stage", stats.name.as_str())],
+                if stats.held() { 1.0 } else { 0.0 },
+            );
+        }
+
+        let processors = self.params.loads().all();
+        page.family(
+            "processor_busy_seconds_total",
+            "counter",
+            "Time spent in each DSP processor since its timings were cleared",
+        );
+        for load in &processors {
+            page.sample(
+                "processor_busy_seconds_total",
+                &[("processor", load.name.as_str())],
+                load.snapshot().busy.as_secs_f64(),
+            );
+        }
+        page.family(
+            "processor_longest_seconds",
+            "gauge",
+            "Longest call of each DSP processor since its timings were cleared",
+        );
+        for load in &processors {
+            page.sample(
+                "processor_longest_seconds",
+                &[("processor", load.name.as_str())],
+                load.snapshot().longest.as_secs_f64(),
+            );
+        }
+        page.family(
+            "processor_load_ratio",
+            "gauge",
+            "Share of the callback budget each DSP processor takes",
+        );
+        for load in &processors {
+            page.sample(
+                "processor_load_ratio",
+                &[("processor", load.name.as_str())],
+                load.snapshot().load(self.load.sample_rate) as f64,
+            );
+        }
+        page.finish()
+    }
+}
+
+fn param(param: &Param) -> Json {
+    Json::object([
+        ("key", param.key.as_str().into()),
+        ("value", param.get().into()),
+        ("min", param.min.into()),
+        ("max", param.max.into()),
+    ])
+}
+
+/// HTTP server for the JSON control API. Reads are answered from the
+/// `View`, changes are sent to the control loop and answered once it
+/// applied them:
+///
+/// ```text
+/// GET  /api/state                  everything below in one object
+/// GET  /api/channels | aux | master | levels | streams | params | feedback | clips | cpu | players
+/// GET  /api/profiles
+/// PUT  /api/channels/1             {"gain_db": -6, "pan": 0, "mute": false, "solo": false}
+/// PUT  /api/channels/1/sends/1     {"level_db": -10}
+/// PUT  /api/aux/1                  {"return_db": -12}
+/// PUT  /api/master                 {"gain_db": -3, "dim": true, "dim_db": -20, "mono": false}
+/// PUT  /api/params/reverb.mix      {"value": 0.3}
+/// POST /api/feedback/reset         clears the feedback notches
+/// POST /api/clips/reset            clears the clip counters and holds
+/// POST /api/cpu/reset              clears the processor timings
+/// POST /api/players/1/play         also pause, stop and loop
+/// POST /api/profiles/podcast       switches to the profile
+/// POST /api/command                {"command": "gain 1 -6"}, any console command
+/// ```
+///
+/// `/api/events` is a WebSocket pushing JSON messages, each with an
+/// `event` field: `levels` with the meters, as `GET /api/levels`, about
+/// 20 times a second; `xrun` with a stream's totals whenever they grow; and
+/// `channels`, `aux`, `master`, `params`, `feedback`, `players` or
+/// `profiles` with the whole section whenever anything in it changed, from
+/// wherever.
+///
+/// `/` is a mixer page for a phone or tablet built on the two: faders,
+/// meters, mutes and solos, effect bypasses, feedback notches and the
+/// players.
+///
+/// `/metrics` serves xruns, render time per callback and per processor,
+/// jitter buffers and clip counts in the Prometheus text format, for
+/// monitoring long-running installations.
+///
+/// Browsers only get in from the page itself and the origins in
+/// `HttpAccess`, and changes have to come as `application/json`, so a page
+/// from elsewhere can't send commands. With a token, every request but the
+/// page needs it.
+pub struct HttpServer {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// Who may use the API besides the mixer page served with it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpAccess {
+    /// Origins of pages served elsewhere that may call the API, e.g.
+    /// `http://dashboard.local:8080`.
+    pub origins: Vec<String>,
+    /// Required on every request but the page when set, as
+    /// `Authorization: Bearer <token>` or a `token` query parameter, the
+    /// one a WebSocket can send.
+    pub token: Option<String>,
+}
+
+impl HttpServer {
+    pub fn spawn(
+        addr: SocketAddr,
+        access: HttpAccess,
+        view: View,
+        remote: Sender<Request>,
+    ) -> Result<HttpServer> {
+        let open = !addr.ip().is_loopback() && access.token.is_none();
+        let listener = TcpListener::bind(addr)
+            .with_context(|| format!("Failed to listen for HTTP on {}", addr))?;
+        // Polled so the thread can notice it should stop
+        listener.set_nonblocking(true)?;
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let thread = thread::Builder::new()
+            .name("http".to_string())
+            .spawn(move || {
+                let mut streams: Vec<JoinHandle<()>> = Vec::new();
+                while flag.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            if let Ok(Some(socket)) = serve(stream, &access, &view, &remote) {
+                                let (view, flag) = (view.clone(), flag.clone());
+                                let events = thread::Builder::new()
+                                    .name("http events".to_string())
+                                    .spawn(move || stream_events(socket, &view, &flag));
+                                streams.extend(events);
+                            }
+                            streams.retain(|s| !s.is_finished());
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(50));
+                        }
+                        Err(_) => thread::sleep(Duration::from_millis(50)),
+                    }
+                }
+                for stream in streams {
+                    let _ = stream.join();
+                }
+            })?;
+        info!("HTTP: control API on http://{}/api/state", addr);
+        if open {
+            warn!("HTTP: anyone on the network can control the mix, set --http-token");
+        }
+        Ok(HttpServer { running, thread })
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+type Section = (&'static str, fn(&View) -> Json);
+
+/// State sent on `/api/events` when it changes.
+const SECTIONS: [Section; 7] = [
+    ("channels", View::channels),
+    ("aux", View::aux),
+    ("master", View::master),
+    ("params", View::params),
+    ("feedback", View::feedback),
+    ("players", View::players),
+    ("profiles", View::profiles),
+];
+
+/// Sends events until the client goes away or the server stops.
+fn stream_events(mut socket: WebSocket, view: &View, running: &AtomicBool) {
+    // Nothing sent yet, so the first round carries the whole state
+    let mut sent: Vec<Option<Json>> = vec![None; SECTIONS.len()];
+    let mut xruns: Vec<XrunSnapshot> = view.xruns.iter().map(|x| x.snapshot()).collect();
+
+    while running.load(Ordering::Relaxed) {
+        let mut messages = vec![Json::object([
+            ("event", "levels".into()),
+            ("data", view.levels()),
+        ])];
+        for ((name, section), sent) in SECTIONS.iter().zip(sent.iter_mut()) {
+            let current = section(view);
+            if sent.as_ref() != Some(&current) {
+                messages.push(Json::object([
+                    ("event", (*name).into()),
+                    ("data", current.clone()),
+                ]));
+                *sent = Some(current);
+            }
+        }
+        for (stats, reported) in view.xruns.iter().zip(xruns.iter_mut()) {
+            let snapshot = stats.snapshot();
+            if snapshot.events() > reported.events() {
+                messages.push(Json::object([
+                    ("event", "xrun".into()),
+                    ("stream", stats.name.as_str().into()),
+                    ("overruns", snapshot.overruns.into()),
+                    ("underruns", snapshot.underruns.into()),
+                    ("errors", snapshot.errors.into()),
+                    ("longest_gap", snapshot.longest_gap.into()),
+                ]));
+            }
+            *reported = snapshot;
+        }
+
+        for message in messages {
+            if socket.send_text(&message.to_string()).is_err() {
+                return;
+            }
+        }
+        if !matches!(socket.poll(), Ok(true)) {
+            return;
+        }
+        thread::sleep(EVENT_INTERVAL);
+    }
+}
+
+/// `PORT` listens on localhost only, `ADDR:PORT` on that address.
+pub fn parse_addr(text: &str) -> Result<SocketAddr> {
+    if let Ok(port) = text.parse::<u16>() {
+        return Ok(SocketAddr::from(([127, 0, 0, 1], port)));
+    }
+    text.parse()
+        .with_context(|| format!("Invalid address '{}', expected PORT or ADDR:PORT", text))
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// What follows the `?`, still percent-encoded.
+    query: String,
+    /// Names in lower case.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether `origin` is the server itself, as for the mixer page.
+    fn same_origin(&self, origin: &str) -> bool {
+        let host = origin
+            .strip_prefix("http://")
+            .or_else(|| origin.strip_prefix("https://"));
+        host.zip(self.header("host"))
+            .is_some_and(|(origin, host)| origin.eq_ignore_ascii_case(host))
+    }
+
+    /// Whether the request carries `token`, in a header or the query.
+    fn has_token(&self, token: &str) -> bool {
+        let bearer = self
+            .header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let query = self
+            .query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .map(percent_decode);
+        bearer.is_some_and(|given| same_token(given, token))
+            || query.is_some_and(|given| same_token(&given, token))
+    }
+}
+
+/// Compares every byte whatever the first mismatch, so the time taken
+/// doesn't give the token away.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Answers one request and closes the connection, or hands back the
+/// socket when the request was for the event stream.
+fn serve(
+    stream: TcpStream,
+    access: &HttpAccess,
+    view: &View,
+    remote: &Sender<Request>,
+) -> Result<Option<WebSocket>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(err) => return refuse(reader.get_mut(), 400, &format!("{:#}", err), None),
+    };
+
+    // Browsers say where the page calling comes from, anything else
+    // doesn't get the cross-origin headers and is turned away
+    let origin = request.header("origin");
+    let allowed = origin.filter(|origin| access.origins.iter().any(|o| o == origin));
+    if origin.is_some_and(|origin| allowed.is_none() && !request.same_origin(origin)) {
+        return refuse(reader.get_mut(), 403, "Origin not allowed", None);
+    }
+    if request.method == "OPTIONS" {
+        respond(reader.get_mut(), 204, "application/json", "", allowed)?;
+        return Ok(None);
+    }
+    if request.method == "GET" && matches!(request.path.as_str(), "/" | "/index.html") {
+        respond(
+            reader.get_mut(),
+            200,
+            "text/html; charset=utf-8",
+            INDEX,
+            None,
+        )?;
+        return Ok(None);
+    }
+    if let Some(token) = &access.token
+        && !request.has_token(token)
+    {
+        return refuse(reader.get_mut(), 401, "Missing or wrong token", allowed);
+    }
+
+    if request.method == "GET" && request.path == "/metrics" {
+        let page = view.metrics();
+        respond(
+            reader.get_mut(),
+            200,
+            "text/plain; version=0.0.4",
+            &page,
+            allowed,
+        )?;
+        return Ok(None);
+    }
+    if request.method == "GET"
+        && request.path.trim_end_matches('/') == "/api/events"
+        && request
+            .header("upgrade")
+            .is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+    {
+        let key = request
+            .header("sec-websocket-key")
+            .context("WebSocket upgrade without a key")?
+            .to_string();
+        return WebSocket::accept(reader.into_inner(), &key).map(Some);
+    }
+    let (status, body) = match route(&request, view, remote) {
+        Ok(json) => (200, json),
+        Err(err) => (
+            if err.is::<NotFound>() {
+                404
+            } else if err.is::<NotJson>() {
+                415
+            } else {
+                400
+            },
+            Json::object([("error", format!("{:#}", err).into())]),
+        ),
+    };
+    respond(
+        reader.get_mut(),
+        status,
+        "application/json",
+        &body.to_string(),
+        allowed,
+    )?;
+    Ok(None)
+}
+
+/// Answers with `message` as the error and no more.
+fn refuse(
+    stream: &mut TcpStream,
+    status: u16,
+    message: &str,
+    origin: Option<&str>,
+) -> Result<Option<WebSocket>> {
+    let body = Json::object([("error", message.into())]).to_string();
+    respond(stream, status, "application/json", &body, origin)?;
+    Ok(None)
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (Some(method), Some(target)) = (words.next(), words.next()) else {
+        bail!("Malformed request line");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+    let method = method.to_string();
+
+    let mut headers = Vec::new();
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "content-length" {
+                length = value.trim().parse().context("Invalid Content-Length")?;
+            }
+            headers.push((name, value.trim().to_string()));
+        }
+    }
+    if length > MAX_BODY {
+        bail!("Request body over {} bytes", MAX_BODY);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+/// Writes the response, with the cross-origin headers for an `origin`
+/// that's allowed.
+fn respond(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+    origin: Option<&str>,
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        415 => "Unsupported Media Type",
+        _ => "Bad Request",
+    };
+    let cors = origin
+        .map(|origin| {
+            format!(
+                "Access-Control-Allow-Origin: {}\r\n\
+                 Access-Control-Allow-Methods: GET, PUT, POST, OPTIONS\r\n\
+                 Access-Control-Allow-Headers: Content-Type, Authorization\r\n\
+                 Vary: Origin\r\n",
+                origin
+            )
+        })
+        .unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         {}\
+         Connection: close\r\n\r\n{}",
+        status,
+        reason,
+        content_type,
+        body.len(),
+        cors,
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[derive(Debug)]
+struct NotFound;
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No such endpoint")
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// A body sent as anything but JSON, as a form or `text/plain` can be
+/// from any page without asking first.
+#[derive(Debug)]
+struct NotJson;
+
+impl std::fmt::Display for NotJson {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected Content-Type: application/json")
+    }
+}
+
+impl std::error::Error for NotJson {}
+
+fn route(request: &HttpRequest, view: &View, remote: &Sender<Request>) -> Result<Json> {
+    let parts: Vec<&str> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .filter(|p| !p.is_empty())
+        .collect();
+    let body = || -> Result<Json> {
+        let json = request.header("content-type").is_some_and(|kind| {
+            let kind = kind.split(';').next().unwrap_or(kind);
+            kind.trim().eq_ignore_ascii_case("application/json")
+        });
+        if !json {
+            return Err(NotJson.into());
+        }
+        let text = std::str::from_utf8(&request.body)?;
+        Json::parse(text).context("Invalid JSON body")
+    };
+
+    // Changes are console lines, so they're checked the same way
+    let lines: Vec<String> = match (request.method.as_str(), parts.as_slice()) {
+        ("GET", ["api", "state"]) => return Ok(view.state()),
+        ("GET", ["api", "channels"]) => return Ok(view.channels()),
+        ("GET", ["api", "aux"]) => return Ok(view.aux()),
+        ("GET", ["api", "master"]) => return Ok(view.master()),
+        ("GET", ["api", "levels"]) => return Ok(view.levels()),
+        ("GET", ["api", "streams"]) => return Ok(view.streams()),
+        ("GET", ["api", "params"]) => return Ok(view.params()),
+        ("GET", ["api", "feedback"]) => return Ok(view.feedback()),
+        ("GET", ["api", "clips"]) => return Ok(view.clips()),
+        ("GET", ["api", "cpu"]) => return Ok(view.cpu()),
+        ("GET", ["api", "players"]) => return Ok(view.players()),
+        ("GET", ["api", "profiles"]) => return Ok(view.profiles()),
+        ("PUT", ["api", "channels", ch]) => {
+            let body = body()?;
+            fields(
+                &body,
+                &[
+                    ("gain_db", &|v| Ok(format!("gain {} {}", ch, number(v)?))),
+                    ("pan", &|v| Ok(format!("pan {} {}", ch, number(v)?))),
+                    ("mute", &|v| Ok(format!("mute {} {}", ch, state(v)?))),
+                    ("solo", &|v| Ok(format!("solo {} {}", ch, state(v)?))),
+                ],
+            )?
+        }
+        ("PUT", ["api", "channels", ch, "sends", aux]) => fields(
+            &body()?,
+            &[("level_db", &|v| {
+                Ok(format!("send {} {} {}", ch, aux, number(v)?))
+            })],
+        )?,
+        ("PUT", ["api", "aux", aux]) => fields(
+            &body()?,
+            &[("return_db", &|v| {
+                Ok(format!("return {} {}", aux, number(v)?))
+            })],
+        )?,
+        ("PUT", ["api", "master"]) => fields(
+            &body()?,
+            &[
+                ("gain_db", &|v| Ok(format!("master {}", number(v)?))),
+                ("dim", &|v| Ok(format!("dim {}", state(v)?))),
+                ("dim_db", &|v| Ok(format!("dimlevel {}", number(v)?))),
+                ("mono", &|v| Ok(format!("mono {}", state(v)?))),
+            ],
+        )?,
+        ("PUT", ["api", "params", key]) => fields(
+            &body()?,
+            &[("value", &|v| Ok(format!("set {} {}", key, number(v)?)))],
+        )?,
+        (
+            "POST",
+            [
+                "api",
+                "players",
+                p,
+                action @ ("play" | "pause" | "stop" | "loop"),
+            ],
+        ) => {
+            vec![format!("{} {}", action, p)]
+        }
+        ("POST", ["api", "feedback", "reset"]) => vec!["feedback reset".to_string()],
+        ("POST", ["api", "clips", "reset"]) => vec!["clips reset".to_string()],
+        ("POST", ["api", "cpu", "reset"]) => vec!["cpu reset".to_string()],
+        ("POST", ["api", "profiles", name]) => vec![format!("profile {}", name)],
+        ("POST", ["api", "command"]) => {
+            let body = body()?;
+            let line = body
+                .get("command")
+                .and_then(Json::as_str)
+                .context("Expected {\"command\": \"...\"}")?;
+            vec![line.to_string()]
+        }
+        _ => return Err(NotFound.into()),
+    };
+
+    let mut results = Vec::new();
+    for line in lines {
+        let command = Command::parse(&line)?;
+        if command == Command::Quit {
+            bail!("Quitting isn't available over HTTP");
+        }
+        results.push(Json::from(control::send(remote, command)?));
+    }
+    Ok(Json::object([("result", Json::Array(results))]))
+}
+
+type Field<'a> = (&'a str, &'a dyn Fn(&Json) -> Result<String>);
+
+/// Console lines for the fields present in `body`, in the order listed.
+fn fields(body: &Json, known: &[Field]) -> Result<Vec<String>> {
+    let Json::Object(present) = body else {
+        bail!("Expected a JSON object");
+    };
+    if let Some((key, _)) = present
+        .iter()
+        .find(|(key, _)| !known.iter().any(|(k, _)| k == key))
+    {
+        bail!("Unknown field '{}'", key);
+    }
+    known
+        .iter()
+        .filter_map(|(key, line)| body.get(key).map(line))
+        .collect()
+}
+
+fn number(value: &Json) -> Result<f64> {
+    value.as_f64().context("Expected a number")
+}
+
+fn state(value: &Json) -> Result<&'static str> {
+    match value.as_bool().context("Expected true or false")? {
+        true => Ok("on"),
+        false => Ok("off"),
+    }
+}
@@ -0,0 +1,135 @@
+use crate::dither::{Dither, DitherMode};
+use crate::recorder::Encoder;
+use crate::resample::{Quality, Resampler};
+use anyhow::{Result, anyhow, bail};
+use mp3lame_encoder::{Bitrate, Builder, FlushNoGap, InterleavedPcm};
+use std::io::Write;
+
+/// Highest rate MP3 carries, faster outputs are resampled down to it.
+const MAX_RATE: u32 = 48_000;
+
+/// LAME's constant bitrates, the closest at or below the one asked for
+/// is used.
+const BITRATES: [(u32, Bitrate); 16] = [
+    (8, Bitrate::Kbps8),
+    (16, Bitrate::Kbps16),
+    (24, Bitrate::Kbps24),
+    (32, Bitrate::Kbps32),
+    (40, Bitrate::Kbps40),
+    (48, Bitrate::Kbps48),
+    (64, Bitrate::Kbps64),
+    (80, Bitrate::Kbps80),
+    (96, Bitrate::Kbps96),
+    (112, Bitrate::Kbps112),
+    (128, Bitrate::Kbps128),
+    (160, Bitrate::Kbps160),
+    (192, Bitrate::Kbps192),
+    (224, Bitrate::Kbps224),
+    (256, Bitrate::Kbps256),
+    (320, Bitrate::Kbps320),
+];
+
+/// Constant bitrate MP3 through LAME, written straight to the connection.
+pub struct Mp3Encoder<W: Write> {
+    encoder: mp3lame_encoder::Encoder,
+    writer: W,
+    channels: usize,
+    resampler: Option<Resampler>,
+    dither: Dither,
+    samples: Vec<f32>,
+    pcm: Vec<i16>,
+    output: Vec<u8>,
+}
+
+impl<W: Write> Mp3Encoder<W> {
+    pub fn new(
+        writer: W,
+        bitrate_kbps: u32,
+        dither: DitherMode,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Self> {
+        if !(1..=2).contains(&channels) {
+            bail!("MP3 supports 1 or 2 channels, got {}", channels);
+        }
+        let rate = sample_rate.min(MAX_RATE);
+        let bitrate = BITRATES
+            .into_iter()
+            .rev()
+            .find(|(kbps, _)| *kbps <= bitrate_kbps)
+            .map_or(Bitrate::Kbps8, |(_, bitrate)| bitrate);
+
+        let mut builder = Builder::new().ok_or_else(|| anyhow!("Failed to set up LAME"))?;
+        builder
+            .set_num_channels(channels as u8)
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+        builder
+            .set_sample_rate(rate)
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+        builder
+            .set_brate(bitrate)
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+        let encoder = builder
+            .build()
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+
+        Ok(Mp3Encoder {
+            encoder,
+            writer,
+            channels,
+            resampler: (rate != sample_rate)
+                .then(|| Resampler::new(sample_rate, rate, channels, Quality::High)),
+            dither: Dither::new(dither, 16, channels),
+            samples: Vec::new(),
+            pcm: Vec::new(),
+            output: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write + Send> Encoder for Mp3Encoder<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        self.samples.clear();
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let resampled = &mut self.samples;
+                resampler.process(samples, |frame| resampled.extend_from_slice(frame));
+            }
+            None => self.samples.extend_from_slice(samples),
+        }
+        self.dither.process(&mut self.samples, self.channels);
+        self.pcm.clear();
+        self.pcm.extend(
+            self.samples
+                .iter()
+                .map(|&sample| (sample * 32768.0).clamp(-32768.0, 32767.0) as i16),
+        );
+
+        self.output.clear();
+        self.output
+            .reserve(mp3lame_encoder::max_required_buffer_size(self.pcm.len()));
+        let size = self
+            .encoder
+            .encode(InterleavedPcm(&self.pcm), self.output.spare_capacity_mut())
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+        // SAFETY: LAME initialised the first `size` bytes of the spare
+        // capacity
+        unsafe { self.output.set_len(size) };
+        self.writer.write_all(&self.output)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.output.clear();
+        self.output.reserve(7200);
+        let size = self
+            .encoder
+            .flush::<FlushNoGap>(self.output.spare_capacity_mut())
+            .map_err(|error| anyhow!("LAME: {:?}", error))?;
+        // SAFETY: as in `write`
+        unsafe { self.output.set_len(size) };
+        self.writer.write_all(&self.output)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
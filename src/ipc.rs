@@ -0,0 +1,357 @@
+use crate::control::{self, Command, Request};
+use crate::shutdown;
+use anyhow::{Result, bail};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+/// Socket name in the runtime directory.
+const SOCKET_NAME: &str = "live_dsp.sock";
+
+/// Where the daemon listens without `--socket`: the user's runtime
+/// directory, or the temporary one when there isn't one.
+pub fn default_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(SOCKET_NAME)
+}
+
+/// Runs a console line for a client and formats the answer as sent back:
+/// `ok` or `error` on the first line, the text after it. `quit` stops the
+/// daemon.
+fn answer(line: &str, remote: &Sender<Request>) -> String {
+    let result = match Command::parse(line) {
+        Ok(Command::Quit) => {
+            shutdown::request();
+            Ok("Shutting down".to_string())
+        }
+        Ok(command) => control::send(remote, command),
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(text) => format!("ok\n{}", text),
+        Err(err) => format!("error\n{}", err),
+    }
+}
+
+/// Splits an answer back into the text or the error.
+fn parse_answer(answer: &str) -> Result<String> {
+    match answer.split_once('\n') {
+        Some(("ok", text)) => Ok(text.to_string()),
+        Some(("error", text)) => bail!("{}", text),
+        _ => bail!("Unexpected answer from the daemon: '{}'", answer.trim()),
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{IpcServer, send_line};
+
+#[cfg(unix)]
+mod unix {
+    use super::{answer, parse_answer};
+    use crate::control::Request;
+    use anyhow::{Context, Result, bail};
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::Sender;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+    use tracing::info;
+
+    /// Time a client gets to send its line.
+    const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Unix socket taking console commands for `daemon`, one line per
+    /// connection, as sent by `live_dsp ctl`.
+    pub struct IpcServer {
+        running: Arc<AtomicBool>,
+        thread: JoinHandle<()>,
+        path: PathBuf,
+    }
+
+    impl IpcServer {
+        pub fn spawn(path: &Path, remote: Sender<Request>) -> Result<IpcServer> {
+            if path.exists() {
+                // Left behind by a daemon that didn't get to clean up,
+                // unless one is still answering on it
+                if UnixStream::connect(path).is_ok() {
+                    bail!("Another live_dsp is listening on {}", path.display());
+                }
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale {}", path.display()))?;
+            }
+            let listener = UnixListener::bind(path)
+                .with_context(|| format!("Failed to listen on {}", path.display()))?;
+            // Polled so the thread can notice it should stop
+            listener.set_nonblocking(true)?;
+            let running = Arc::new(AtomicBool::new(true));
+            let flag = running.clone();
+            let thread = thread::Builder::new()
+                .name("ipc".to_string())
+                .spawn(move || {
+                    while flag.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                let _ = serve(stream, &remote);
+                            }
+                            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                                thread::sleep(Duration::from_millis(50));
+                            }
+                            Err(_) => thread::sleep(Duration::from_millis(50)),
+                        }
+                    }
+                })?;
+            info!("Control socket: {}", path.display());
+            Ok(IpcServer {
+                running,
+                thread,
+                path: path.to_path_buf(),
+            })
+        }
+
+        pub fn stop(self) {
+            self.running.store(false, Ordering::Relaxed);
+            let _ = self.thread.join();
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn serve(stream: UnixStream, remote: &Sender<Request>) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        (&stream).write_all(answer(&line, remote).as_bytes())?;
+        Ok(())
+    }
+
+    /// Sends one console line to the daemon on `path` and returns its
+    /// answer.
+    pub fn send_line(path: &Path, line: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(path)
+            .with_context(|| format!("No live_dsp daemon is listening on {}", path.display()))?;
+        writeln!(stream, "{}", line)?;
+        stream.shutdown(Shutdown::Write)?;
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer)?;
+        parse_answer(&answer)
+    }
+}
+
+#[cfg(windows)]
+pub use windows::{IpcServer, send_line};
+
+/// The same protocol on a named pipe, `\\.\pipe\` and the file name of the
+/// socket path, e.g. `\\.\pipe\live_dsp.sock`. The standard library opens
+/// one as a client, the server side is two kernel32 calls.
+#[cfg(windows)]
+mod windows {
+    use super::{answer, parse_answer};
+    use crate::control::Request;
+    use anyhow::{Context, Result, bail};
+    use std::ffi::c_void;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::Sender;
+    use std::thread::{self, JoinHandle};
+    use tracing::info;
+
+    const PIPE_ACCESS_DUPLEX: u32 = 0x3;
+    const FILE_FLAG_FIRST_PIPE_INSTANCE: u32 = 0x0008_0000;
+    /// Refuses clients on other machines.
+    const PIPE_REJECT_REMOTE_CLIENTS: u32 = 0x8;
+    const PIPE_UNLIMITED_INSTANCES: u32 = 255;
+    const BUFFER_SIZE: u32 = 4096;
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+    const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security_attributes: *mut c_void,
+        ) -> *mut c_void;
+        fn ConnectNamedPipe(pipe: *mut c_void, overlapped: *mut c_void) -> i32;
+    }
+
+    fn pipe_name(path: &Path) -> String {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| super::SOCKET_NAME.to_string());
+        format!(r"\\.\pipe\{}", name)
+    }
+
+    /// Opens another instance of the pipe for the next client. The default
+    /// security lets only this user and administrators write to it.
+    fn create(name: &str, first: bool) -> io::Result<File> {
+        let wide: Vec<u16> = std::ffi::OsStr::new(name)
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+        let open_mode = if first {
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE
+        } else {
+            PIPE_ACCESS_DUPLEX
+        };
+        // Byte mode and blocking, the defaults. SAFETY: `wide` is
+        // nul-terminated and outlives the call, no security attributes are
+        // passed
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                open_mode,
+                PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the handle is open and owned by nothing else, the file
+        // closes it
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+
+    /// Blocks until a client opens `pipe`.
+    fn connect(pipe: &File) -> io::Result<()> {
+        // SAFETY: the handle stays open for the call, which is synchronous
+        if unsafe { ConnectNamedPipe(pipe.as_raw_handle(), std::ptr::null_mut()) } != 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        // Connected between creating the instance and waiting on it
+        match err.raw_os_error() {
+            Some(ERROR_PIPE_CONNECTED) => Ok(()),
+            _ => Err(err),
+        }
+    }
+
+    /// Named pipe taking console commands for `daemon`, one line per
+    /// connection, as sent by `live_dsp ctl`.
+    pub struct IpcServer {
+        running: Arc<AtomicBool>,
+        thread: JoinHandle<()>,
+        name: String,
+    }
+
+    impl IpcServer {
+        pub fn spawn(path: &Path, remote: Sender<Request>) -> Result<IpcServer> {
+            let name = pipe_name(path);
+            let first = create(&name, true).map_err(|err| {
+                if err.raw_os_error() == Some(ERROR_ACCESS_DENIED) {
+                    anyhow::anyhow!("Another live_dsp is listening on {}", name)
+                } else {
+                    anyhow::Error::new(err).context(format!("Failed to listen on {}", name))
+                }
+            })?;
+            let running = Arc::new(AtomicBool::new(true));
+            let flag = running.clone();
+            let pipe_name = name.clone();
+            let thread = thread::Builder::new()
+                .name("ipc".to_string())
+                .spawn(move || {
+                    let mut pipe = Some(first);
+                    while flag.load(Ordering::Relaxed) {
+                        let Some(current) = pipe.take().or_else(|| create(&pipe_name, false).ok())
+                        else {
+                            thread::sleep(std::time::Duration::from_millis(50));
+                            continue;
+                        };
+                        // `stop` connects too, to wake this up
+                        if connect(&current).is_err() || !flag.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        // A client that never sends its line only holds up
+                        // its own thread
+                        let remote = remote.clone();
+                        let _ = thread::Builder::new()
+                            .name("ipc client".to_string())
+                            .spawn(move || serve(current, &remote));
+                    }
+                })?;
+            info!("Control pipe: {}", name);
+            Ok(IpcServer {
+                running,
+                thread,
+                name,
+            })
+        }
+
+        pub fn stop(self) {
+            self.running.store(false, Ordering::Relaxed);
+            let _ = OpenOptions::new().read(true).write(true).open(&self.name);
+            let _ = self.thread.join();
+        }
+    }
+
+    fn serve(pipe: File, remote: &Sender<Request>) -> Result<()> {
+        let mut line = String::new();
+        BufReader::new(&pipe).read_line(&mut line)?;
+        (&pipe).write_all(answer(&line, remote).as_bytes())?;
+        // Waits for the client to read it all before the pipe closes
+        pipe.sync_all()?;
+        Ok(())
+    }
+
+    /// Sends one console line to the daemon on the pipe for `path` and
+    /// returns its answer.
+    pub fn send_line(path: &Path, line: &str) -> Result<String> {
+        let name = pipe_name(path);
+        let mut pipe = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&name)
+            .with_context(|| format!("No live_dsp daemon is listening on {}", name))?;
+        writeln!(pipe, "{}", line)?;
+        let mut answer = String::new();
+        // The daemon closing its end reads as the end of the answer
+        pipe.read_to_string(&mut answer)?;
+        if answer.is_empty() {
+            bail!("The daemon on {} closed without answering", name);
+        }
+        parse_answer(&answer)
+    }
+}
+
+/// Other platforms go without the control socket.
+#[cfg(not(any(unix, windows)))]
+pub struct IpcServer;
+
+#[cfg(not(any(unix, windows)))]
+impl IpcServer {
+    pub fn spawn(
+        _path: &std::path::Path,
+        _remote: std::sync::mpsc::Sender<crate::control::Request>,
+    ) -> anyhow::Result<IpcServer> {
+        anyhow::bail!("The daemon's control socket needs Unix or Windows")
+    }
+
+    pub fn stop(self) {}
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn send_line(_path: &std::path::Path, _line: &str) -> anyhow::Result<String> {
+    anyhow::bail!("The daemon's control socket needs Unix or Windows")
+}
@@ -0,0 +1,226 @@
+use crate::Render;
+use crate::frame_ring::{self, FrameConsumer, FrameProducer};
+use crate::rtlog::{Event, Logger, RtLogger};
+use crate::source::Source;
+use crate::xrun::XrunStats;
+use anyhow::{Context, Result};
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, Frames, NotificationHandler,
+    Port, ProcessHandler, ProcessScope,
+};
+use std::sync::Arc;
+use tracing::error;
+
+/// Frames rendered per pass. Longer JACK periods are rendered in several
+/// passes, so a change of buffer size never has to grow anything.
+pub const CHUNK_FRAMES: usize = 1024;
+
+/// Mixer channel fed by one group of JACK input ports.
+pub struct JackInput {
+    name: String,
+    consumer: FrameConsumer,
+}
+
+impl Source for JackInput {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        // The process callback pushed exactly these frames just before
+        for frame in data.chunks_mut(channels) {
+            if !self.consumer.pop_frame(frame) {
+                frame.fill(0.0);
+            }
+        }
+    }
+}
+
+/// JACK client with its ports registered, not yet running.
+pub struct JackSetup {
+    client: Client,
+    inputs: Vec<(Vec<Port<AudioIn>>, FrameProducer)>,
+    outputs: Vec<Port<AudioOut>>,
+    channels: usize,
+    pub sample_rate: u32,
+    pub buffer_size: u32,
+}
+
+/// Registers as the JACK client `name` with `inputs` mixer channels of
+/// `channels` input ports each, and `channels` output ports. Ports are
+/// named `in1_L`, `in1_R`... and `out_L`, `out_R`, numbered from 1 when
+/// there are more than two channels.
+pub fn connect(name: &str, inputs: usize, channels: usize) -> Result<(JackSetup, Vec<JackInput>)> {
+    let (client, _status) = Client::new(name, ClientOptions::NO_START_SERVER)
+        .context("Failed to connect to the JACK server, is it running?")?;
+
+    let mut groups = Vec::new();
+    let mut sources = Vec::new();
+    for index in 1..=inputs {
+        let ports = (0..channels)
+            .map(|channel| {
+                let port = format!("in{}_{}", index, port_suffix(channel, channels));
+                client
+                    .register_port(&port, AudioIn::default())
+                    .with_context(|| format!("Failed to register JACK port {}", port))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (producer, consumer) = frame_ring::frame_ring(CHUNK_FRAMES, channels);
+        groups.push((ports, producer));
+        sources.push(JackInput {
+            name: format!("jack in{}", index),
+            consumer,
+        });
+    }
+    let outputs = (0..channels)
+        .map(|channel| {
+            let port = format!("out_{}", port_suffix(channel, channels));
+            client
+                .register_port(&port, AudioOut::default())
+                .with_context(|| format!("Failed to register JACK port {}", port))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let setup = JackSetup {
+        sample_rate: client.sample_rate() as u32,
+        buffer_size: client.buffer_size(),
+        client,
+        inputs: groups,
+        outputs,
+        channels,
+    };
+    Ok((setup, sources))
+}
+
+fn port_suffix(channel: usize, channels: usize) -> String {
+    match (channels, channel) {
+        (2, 0) => "L".to_string(),
+        (2, _) => "R".to_string(),
+        _ => (channel + 1).to_string(),
+    }
+}
+
+impl JackSetup {
+    /// Starts the client. JACK calls `render` from then on with the input
+    /// ports already pushed to the `JackInput`s, and plays what it renders
+    /// on the output ports. Period and rate changes go to `logger`.
+    pub fn activate(
+        self,
+        render: Render,
+        xruns: Arc<XrunStats>,
+        logger: &Logger,
+    ) -> Result<JackSession> {
+        let process = Process {
+            inputs: self.inputs,
+            outputs: self.outputs,
+            channels: self.channels,
+            render,
+            scratch: vec![0.0; CHUNK_FRAMES * self.channels],
+            xruns: xruns.clone(),
+            log: logger.channel("jack"),
+        };
+        let notifications = Notifications {
+            engine_rate: self.sample_rate,
+            xruns,
+            log: logger.channel("jack"),
+        };
+        let client = self
+            .client
+            .activate_async(notifications, process)
+            .context("Failed to activate the JACK client")?;
+        Ok(JackSession { client })
+    }
+}
+
+/// Running JACK client.
+pub struct JackSession {
+    client: AsyncClient<Notifications, Process>,
+}
+
+impl JackSession {
+    /// Disconnects from the server. The process callback is done once this
+    /// returns.
+    pub fn stop(self) {
+        if let Err(err) = self.client.deactivate() {
+            error!("Failed to deactivate the JACK client: {}", err);
+        }
+    }
+}
+
+struct Process {
+    inputs: Vec<(Vec<Port<AudioIn>>, FrameProducer)>,
+    outputs: Vec<Port<AudioOut>>,
+    channels: usize,
+    render: Render,
+    /// Interleaved buffer of one chunk, for the inputs and then the output.
+    scratch: Vec<f32>,
+    xruns: Arc<XrunStats>,
+    log: RtLogger,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        let frames = ps.n_frames() as usize;
+        self.xruns.record_callback(frames as u64);
+
+        let channels = self.channels;
+        let mut start = 0;
+        while start < frames {
+            let len = (frames - start).min(CHUNK_FRAMES);
+            let data = &mut self.scratch[..len * channels];
+
+            // Interleave every port group and queue it for its mixer channel
+            for (ports, producer) in &mut self.inputs {
+                for (channel, port) in ports.iter().enumerate() {
+                    let samples = &port.as_slice(ps)[start..start + len];
+                    for (frame, &sample) in samples.iter().enumerate() {
+                        data[frame * channels + channel] = sample;
+                    }
+                }
+                for frame in data.chunks(channels) {
+                    producer.push_frame(frame);
+                }
+            }
+
+            (self.render)(data);
+            for (channel, port) in self.outputs.iter_mut().enumerate() {
+                let samples = &mut port.as_mut_slice(ps)[start..start + len];
+                for (frame, sample) in samples.iter_mut().enumerate() {
+                    *sample = data[frame * channels + channel];
+                }
+            }
+            start += len;
+        }
+        Control::Continue
+    }
+
+    fn buffer_size(&mut self, _: &Client, size: Frames) -> Control {
+        // Any period is rendered in chunks, there is nothing to resize
+        self.log.log(Event::BufferSize(size));
+        Control::Continue
+    }
+}
+
+struct Notifications {
+    /// Rate the mixer and effects were set up for.
+    engine_rate: u32,
+    xruns: Arc<XrunStats>,
+    log: RtLogger,
+}
+
+impl NotificationHandler for Notifications {
+    fn sample_rate(&mut self, _: &Client, rate: Frames) -> Control {
+        if rate != self.engine_rate {
+            self.log.log(Event::SampleRate {
+                rate,
+                engine: self.engine_rate,
+            });
+        }
+        Control::Continue
+    }
+
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.xruns.record_xrun();
+        Control::Continue
+    }
+}
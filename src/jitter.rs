@@ -0,0 +1,276 @@
+use crate::drift::DriftEstimator;
+use crate::frame_ring::FrameConsumer;
+use crate::params::AtomicF32;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Length of the window the lowest fill level is taken over.
+const WINDOW_MS: usize = 500;
+
+/// Fill level and corrections of one jitter buffer, shared with the control
+/// side. The target can be changed while streaming.
+#[derive(Debug)]
+pub struct JitterStats {
+    pub name: String,
+    pub sample_rate: u32,
+    /// Largest target the ring has room for, in frames.
+    pub max_target: u64,
+    target: AtomicU64,
+    /// Lowest margin of the last window in frames.
+    margin: AtomicU64,
+    dropped: AtomicU64,
+    inserted: AtomicU64,
+    prefills: AtomicU64,
+    /// Rate correction the input resampler applies, read by the input
+    /// callback.
+    correction: AtomicF32,
+    /// Estimated clock drift of the input in parts per million.
+    drift_ppm: AtomicF32,
+}
+
+impl JitterStats {
+    pub fn new(name: String, sample_rate: u32, target: u64, max_target: u64) -> Self {
+        JitterStats {
+            name,
+            sample_rate,
+            max_target,
+            target: AtomicU64::new(target.min(max_target)),
+            margin: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            inserted: AtomicU64::new(0),
+            prefills: AtomicU64::new(0),
+            correction: AtomicF32::new(0.0),
+            drift_ppm: AtomicF32::new(0.0),
+        }
+    }
+
+    pub fn target(&self) -> u64 {
+        self.target.load(Ordering::Relaxed)
+    }
+
+    /// Sets the target latency in frames, clamped to what the ring holds.
+    pub fn set_target(&self, frames: u64) {
+        self.target
+            .store(frames.clamp(1, self.max_target), Ordering::Relaxed);
+    }
+
+    pub fn set_target_ms(&self, ms: f32) {
+        self.set_target((ms.max(0.0) * self.sample_rate as f32 / 1000.0) as u64);
+    }
+
+    pub fn margin(&self) -> u64 {
+        self.margin.load(Ordering::Relaxed)
+    }
+
+    /// Fraction the input should be sped up by to hold the target.
+    pub fn correction(&self) -> f32 {
+        self.correction.get()
+    }
+
+    pub fn drift_ppm(&self) -> f32 {
+        self.drift_ppm.get()
+    }
+
+    /// Frames dropped and repeated to hold the target, and the times the
+    /// buffer ran dry and filled up again.
+    pub fn corrections(&self) -> (u64, u64, u64) {
+        (
+            self.dropped.load(Ordering::Relaxed),
+            self.inserted.load(Ordering::Relaxed),
+            self.prefills.load(Ordering::Relaxed),
+        )
+    }
+
+    fn to_ms(&self, frames: u64) -> f32 {
+        frames as f32 * 1000.0 / self.sample_rate.max(1) as f32
+    }
+
+    pub fn describe(&self) -> String {
+        let (dropped, inserted, prefills) = self.corrections();
+        format!(
+            "{}: target {} frames ({:.1} ms), margin {} frames ({:.1} ms), drift {:+.1} ppm, {} dropped, {} inserted, {} prefills",
+            self.name,
+            self.target(),
+            self.to_ms(self.target()),
+            self.margin(),
+            self.to_ms(self.margin()),
+            self.drift_ppm(),
+            dropped,
+            inserted,
+            prefills
+        )
+    }
+}
+
+/// Keeps the frames waiting in a frame ring close to a target latency.
+///
+/// The target is the margin left in the ring at its lowest point, right
+/// before a callback takes its frames, so a late input callback has that
+/// long to arrive. The buffer plays silence until the ring holds the target
+/// plus one callback, then watches the lowest fill level over a window of
+/// callbacks. When the input runs faster than the output the level creeps
+/// up and frames are dropped, when it runs slower frames are repeated, at
+/// most one per callback. Running dry starts another prefill, so a hiccup
+/// costs one gap instead of a stream of short ones.
+///
+/// With a drift estimator the level is instead held by speeding up or
+/// slowing down the input resampler, and dropping frames is only the
+/// fallback for a ring that has filled far past its target.
+pub struct JitterBuffer {
+    consumer: FrameConsumer,
+    stats: Arc<JitterStats>,
+    prefilling: bool,
+    /// Callbacks per measuring window.
+    window: usize,
+    callbacks: usize,
+    /// Lowest margin seen so far in this window, as it would have been
+    /// without the corrections made since the window started.
+    low: isize,
+    /// Frames inserted minus frames dropped this window.
+    shift: isize,
+    /// Lowest margin of the last window, moved along with the corrections
+    /// made since.
+    level: f32,
+    correction: Correction,
+    /// Last frame played, repeated to insert one.
+    last: Vec<f32>,
+    /// Repeat the last frame on the next pop.
+    insert: bool,
+    drift: Option<DriftEstimator>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Correction {
+    None,
+    Drop,
+    Insert,
+}
+
+impl JitterBuffer {
+    /// `buffer_size` is the expected frames per callback, used to size the
+    /// measuring window. With `drift_compensation` the level is held by
+    /// publishing a rate correction in `stats` for the input side.
+    pub fn new(
+        consumer: FrameConsumer,
+        channels: usize,
+        buffer_size: usize,
+        drift_compensation: bool,
+        stats: Arc<JitterStats>,
+    ) -> Self {
+        let window = (stats.sample_rate as usize * WINDOW_MS / 1000 / buffer_size.max(1)).max(1);
+        let interval = (window * buffer_size) as f64 / stats.sample_rate.max(1) as f64;
+        let drift = drift_compensation.then(|| DriftEstimator::new(stats.sample_rate, interval));
+        JitterBuffer {
+            consumer,
+            stats,
+            prefilling: true,
+            window,
+            callbacks: 0,
+            low: isize::MAX,
+            shift: 0,
+            level: 0.0,
+            correction: Correction::None,
+            last: vec![0.0; channels],
+            insert: false,
+            drift,
+        }
+    }
+
+    /// Measures the fill level and decides on this callback's correction.
+    /// Call once per callback with the frames it is about to pop.
+    pub fn begin(&mut self, frames: usize) {
+        let target = self.stats.target() as f32;
+        let fill = self.consumer.occupied_frames();
+        let margin = fill.saturating_sub(frames);
+        if self.prefilling {
+            if (margin as f32) < target {
+                return;
+            }
+            self.prefilling = false;
+            self.callbacks = 0;
+            self.low = isize::MAX;
+            self.shift = 0;
+            self.level = margin as f32;
+            self.correction = Correction::None;
+        }
+
+        self.low = self.low.min(margin as isize - self.shift);
+        self.callbacks += 1;
+        if self.callbacks >= self.window {
+            let low = (self.low + self.shift).max(0);
+            self.level = low as f32;
+            self.stats.margin.store(low as u64, Ordering::Relaxed);
+            self.callbacks = 0;
+            self.low = isize::MAX;
+            self.shift = 0;
+
+            if let Some(drift) = &mut self.drift {
+                let correction = drift.update((self.level - target) as f64);
+                self.stats.correction.set(correction as f32);
+                self.stats.drift_ppm.set((drift.drift() * 1e6) as f32);
+            }
+        }
+
+        // Start correcting once the level is a quarter of the target off
+        // and keep going until it is back on target. The resampler holds
+        // the level when compensating for drift, so only a ring twice as
+        // full as it should be is trimmed then
+        let tolerance = if self.drift.is_some() {
+            target
+        } else {
+            (target / 4.0).max(1.0)
+        };
+        let error = self.level - target;
+        if error > tolerance {
+            self.correction = Correction::Drop;
+        } else if error < -tolerance {
+            self.correction = Correction::Insert;
+        }
+        match self.correction {
+            Correction::Drop if error <= 0.0 => self.correction = Correction::None,
+            Correction::Insert if error >= 0.0 => self.correction = Correction::None,
+            Correction::Drop => {
+                if self.consumer.skip_frames(1) == 1 {
+                    self.level -= 1.0;
+                    self.shift -= 1;
+                    self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Correction::Insert => self.insert = true,
+            Correction::None => {}
+        }
+    }
+
+    /// Whether the ring is filling up again, playing silence meanwhile.
+    pub fn prefilling(&self) -> bool {
+        self.prefilling
+    }
+
+    /// Fills `frame` with the next frame to play. Returns false when the
+    /// ring ran dry, in which case `frame` is silence. Prefill silence is
+    /// on purpose and returns true.
+    pub fn pop_frame(&mut self, frame: &mut [f32]) -> bool {
+        if self.prefilling {
+            frame.fill(0.0);
+            return true;
+        }
+        if self.insert {
+            self.insert = false;
+            self.level += 1.0;
+            self.shift += 1;
+            self.stats.inserted.fetch_add(1, Ordering::Relaxed);
+            frame.copy_from_slice(&self.last);
+            return true;
+        }
+        if self.consumer.pop_frame(frame) {
+            self.last.copy_from_slice(frame);
+            return true;
+        }
+
+        frame.fill(0.0);
+        self.last.fill(0.0);
+        self.prefilling = true;
+        self.stats.prefills.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+}
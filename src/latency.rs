@@ -0,0 +1,213 @@
+use crate::dither::DitherMode;
+use crate::dsp::fft::{Complex, Fft};
+use crate::realtime::{self, Realtime};
+use crate::resample::Quality;
+use crate::rtlog::{Event, Logger};
+use crate::sample_convert;
+use crate::source::{self, Source, SourceSettings};
+use anyhow::{Result, bail};
+use cpal::Device;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use ringbuf::HeapRb;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Number of chirps played, one per period.
+const REPEATS: usize = 5;
+const PERIOD_SECONDS: f32 = 1.0;
+const CHIRP_SECONDS: f32 = 0.1;
+const CHIRP_LEVEL: f32 = 0.25;
+/// Echoes later than this are not searched for.
+const MAX_LATENCY_SECONDS: f32 = 0.9;
+/// Correlation peak over the window's RMS needed to trust a measurement.
+const MIN_CONFIDENCE: f32 = 8.0;
+
+/// Plays a train of chirps on the output, records the input through the
+/// same ring buffer path the loopback uses, and reports the round trip
+/// found by cross-correlation. Needs a cable or mic from output to input.
+pub fn measure(
+    input_device: &Device,
+    output_device: &Device,
+    buffer_size: u32,
+    quality: Quality,
+) -> Result<()> {
+    let default_output_config = output_device.default_output_config()?;
+    let output_format = default_output_config.sample_format();
+    let requested =
+        sample_convert::fixed_buffer_size(default_output_config.buffer_size(), buffer_size);
+    let mut output_config: cpal::StreamConfig = default_output_config.into();
+    output_config.buffer_size = requested;
+    let output_rate = output_config.sample_rate;
+    let output_channels = output_config.channels as usize;
+
+    let settings = SourceSettings {
+        output_rate,
+        output_channels,
+        buffer_size,
+        target_latency: buffer_size,
+        drift_compensation: false,
+        map: None,
+        quality,
+        // A fade would bend the chirps that arrive right after a gap
+        crossfade: Duration::ZERO,
+    };
+    let logger = Logger::spawn()?;
+    // Measured on the same scheduling the mixer runs its streams with
+    let realtime = Arc::new(Realtime::spawn(true, realtime::DEFAULT_PRIORITY)?);
+    let (mut feed, mut tap) = source::open_input(input_device, &settings, &logger, &realtime)?;
+    let input_stream = feed.start(input_device)?;
+
+    let chirp = chirp(output_rate as f32);
+    let period = (PERIOD_SECONDS * output_rate as f32) as usize;
+    let total = period * (REPEATS + 1);
+
+    // The callback plays the chirps and hands the input, summed to mono,
+    // back to this thread. Both run on the output clock, so an input frame's
+    // index is directly comparable to the output frame it was played with.
+    let (mut producer, mut consumer) = HeapRb::<f32>::new(total).split();
+    let mut scratch = vec![0.0f32; 8192];
+    let mut played = 0usize;
+    let probe = chirp.clone();
+    let mut output_log = logger.channel("output");
+    let mut promoter = realtime.promoter("output", output_rate, output_device);
+    let output_stream = sample_convert::build_output_stream(
+        output_device,
+        &mut output_config,
+        output_format,
+        // The chirps are found by correlation, noise would only blur them
+        DitherMode::Off,
+        move |data: &mut [f32]| {
+            promoter.promote(data.len() / output_channels);
+            for frame in data.chunks_mut(output_channels) {
+                let position = played % period;
+                let sample = if played < period * REPEATS && position < probe.len() {
+                    probe[position]
+                } else {
+                    0.0
+                };
+                frame.iter_mut().for_each(|s| *s = sample);
+                played += 1;
+            }
+
+            // Callbacks come in pieces no bigger than the scratch
+            let captured = &mut scratch[..data.len()];
+            tap.read_into(captured, output_channels);
+            for frame in captured.chunks(output_channels) {
+                let mono = frame.iter().sum::<f32>() / output_channels as f32;
+                if producer.try_push(mono).is_err() {
+                    break;
+                }
+            }
+        },
+        move |err| output_log.log(Event::StreamError(err)),
+    )?;
+
+    println!(
+        "\nMeasuring round-trip latency with {} chirps, {} Hz, buffer size {}...",
+        REPEATS, output_rate, buffer_size
+    );
+    input_stream.play()?;
+    output_stream.play()?;
+    while consumer.occupied_len() < total {
+        thread::sleep(Duration::from_millis(50));
+    }
+    drop(output_stream);
+    drop(input_stream);
+    logger.stop();
+
+    let mut recorded = vec![0.0f32; total];
+    consumer.pop_slice(&mut recorded);
+
+    let max_lag = ((MAX_LATENCY_SECONDS * output_rate as f32) as usize).min(period);
+    let correlation = cross_correlate(&recorded, &chirp);
+    let mut lags = Vec::new();
+    for repeat in 0..REPEATS {
+        let window = &correlation[repeat * period..repeat * period + max_lag];
+        let (lag, peak) = window
+            .iter()
+            .map(|c| c.abs())
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0));
+        let rms = (window.iter().map(|c| c * c).sum::<f32>() / window.len() as f32).sqrt();
+        let confidence = if rms > 0.0 { peak / rms } else { 0.0 };
+
+        if confidence >= MIN_CONFIDENCE {
+            println!(
+                "  chirp {}: {} samples ({:.2} ms)",
+                repeat + 1,
+                lag,
+                lag as f32 * 1000.0 / output_rate as f32
+            );
+            lags.push(lag);
+        } else {
+            println!("  chirp {}: no clear echo", repeat + 1);
+        }
+    }
+
+    if lags.is_empty() {
+        bail!(
+            "No echo detected. Connect the output to the input (cable or speaker and mic) \
+             and raise the input level"
+        );
+    }
+    lags.sort_unstable();
+    let median = lags[lags.len() / 2];
+    let spread = lags[lags.len() - 1] - lags[0];
+    println!(
+        "Round trip: {} samples, {:.2} ms at {} Hz (spread {} samples over {} chirps)",
+        median,
+        median as f32 * 1000.0 / output_rate as f32,
+        output_rate,
+        spread,
+        lags.len()
+    );
+    Ok(())
+}
+
+/// Linear chirp from 200 Hz to 0.4 × the sample rate with raised-cosine
+/// fades, which correlates to a single sharp peak.
+fn chirp(sample_rate: f32) -> Vec<f32> {
+    let length = (CHIRP_SECONDS * sample_rate) as usize;
+    let (start, end) = (200.0, 0.4 * sample_rate);
+    let duration = length as f32 / sample_rate;
+    let fade = length / 10;
+    (0..length)
+        .map(|n| {
+            let t = n as f32 / sample_rate;
+            let phase = 2.0 * PI * (start * t + (end - start) * t * t / (2.0 * duration));
+            let edge = n.min(length - 1 - n);
+            let envelope = if edge < fade {
+                0.5 - 0.5 * (PI * edge as f32 / fade as f32).cos()
+            } else {
+                1.0
+            };
+            phase.sin() * envelope * CHIRP_LEVEL
+        })
+        .collect()
+}
+
+/// `result[lag] = sum(signal[lag + n] * probe[n])`, via FFT.
+fn cross_correlate(signal: &[f32], probe: &[f32]) -> Vec<f32> {
+    let size = (signal.len() + probe.len()).next_power_of_two();
+    let fft = Fft::new(size);
+
+    let mut a = vec![Complex::ZERO; size];
+    for (value, &s) in a.iter_mut().zip(signal) {
+        *value = Complex::new(s, 0.0);
+    }
+    let mut b = vec![Complex::ZERO; size];
+    for (value, &p) in b.iter_mut().zip(probe) {
+        *value = Complex::new(p, 0.0);
+    }
+    fft.forward(&mut a);
+    fft.forward(&mut b);
+    for (x, y) in a.iter_mut().zip(&b) {
+        *x = *x * y.conj();
+    }
+    fft.inverse(&mut a);
+    a.iter().take(signal.len()).map(|c| c.re).collect()
+}
@@ -0,0 +1,255 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the callback duration histogram in microseconds, the
+/// last bucket takes everything longer.
+pub const BUCKETS_US: [u64; 10] = [100, 250, 500, 1000, 2000, 3000, 5000, 10000, 20000, 50000];
+
+/// Time the output callback spends rendering, against the time the audio
+/// it renders lasts. Recorded once per callback on the audio thread.
+#[derive(Debug)]
+pub struct CallbackLoad {
+    pub sample_rate: u32,
+    callbacks: AtomicU64,
+    /// Time spent rendering, in nanoseconds.
+    busy_ns: AtomicU64,
+    /// Length of the audio rendered, in frames.
+    frames: AtomicU64,
+    longest_ns: AtomicU64,
+    /// Callbacks per duration bucket, not cumulative.
+    buckets: [AtomicU64; BUCKETS_US.len() + 1],
+}
+
+/// Totals at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoadSnapshot {
+    pub callbacks: u64,
+    pub busy: Duration,
+    /// Time the rendered audio lasts.
+    pub audio: Duration,
+    pub longest: Duration,
+}
+
+impl LoadSnapshot {
+    /// Share of the audio's duration spent rendering it, 1 is the whole
+    /// budget.
+    pub fn load(&self) -> f32 {
+        if self.audio.is_zero() {
+            return 0.0;
+        }
+        self.busy.as_secs_f32() / self.audio.as_secs_f32()
+    }
+}
+
+impl CallbackLoad {
+    pub fn new(sample_rate: u32) -> Self {
+        CallbackLoad {
+            sample_rate,
+            callbacks: AtomicU64::new(0),
+            busy_ns: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            longest_ns: AtomicU64::new(0),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Records one callback that took `busy` to render `frames`.
+    pub fn record(&self, busy: Duration, frames: u64) {
+        let ns = busy.as_nanos() as u64;
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+        self.busy_ns.fetch_add(ns, Ordering::Relaxed);
+        self.frames.fetch_add(frames, Ordering::Relaxed);
+        self.longest_ns.fetch_max(ns, Ordering::Relaxed);
+        let us = ns / 1000;
+        let bucket = BUCKETS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap_or(BUCKETS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LoadSnapshot {
+        let frames = self.frames.load(Ordering::Relaxed);
+        LoadSnapshot {
+            callbacks: self.callbacks.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(self.busy_ns.load(Ordering::Relaxed)),
+            audio: Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64),
+            longest: Duration::from_nanos(self.longest_ns.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Callbacks per bucket of `BUCKETS_US`, then the ones longer than
+    /// the last bound.
+    pub fn buckets(&self) -> Vec<u64> {
+        self.buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect()
+    }
+
+    pub fn describe(&self) -> String {
+        let s = self.snapshot();
+        let average = if s.callbacks > 0 {
+            s.busy.div_f64(s.callbacks as f64)
+        } else {
+            Duration::ZERO
+        };
+        format!(
+            "render: {} callbacks, {:.0}% load, {:.2} ms average, {:.2} ms longest",
+            s.callbacks,
+            s.load() * 100.0,
+            average.as_secs_f32() * 1000.0,
+            s.longest.as_secs_f32() * 1000.0
+        )
+    }
+}
+
+/// Time one processor of a DSP chain takes per callback, recorded on the
+/// audio thread while it isn't bypassed.
+#[derive(Debug)]
+pub struct ProcessorLoad {
+    /// Named like the processor's parameter keys, e.g. `aux1.reverb`.
+    pub name: String,
+    calls: AtomicU64,
+    busy_ns: AtomicU64,
+    frames: AtomicU64,
+    /// `u64::MAX` until the first call.
+    shortest_ns: AtomicU64,
+    longest_ns: AtomicU64,
+}
+
+/// Totals of one processor at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProcessorSnapshot {
+    pub calls: u64,
+    pub busy: Duration,
+    pub frames: u64,
+    pub shortest: Duration,
+    pub longest: Duration,
+}
+
+impl ProcessorSnapshot {
+    pub fn average(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+        self.busy.div_f64(self.calls as f64)
+    }
+
+    /// Share of the audio's duration the processor takes, 1 is the whole
+    /// callback budget at `sample_rate`.
+    pub fn load(&self, sample_rate: u32) -> f32 {
+        if self.frames == 0 {
+            return 0.0;
+        }
+        self.busy.as_secs_f32() * sample_rate as f32 / self.frames as f32
+    }
+}
+
+impl ProcessorLoad {
+    pub fn new(name: String) -> Self {
+        ProcessorLoad {
+            name,
+            calls: AtomicU64::new(0),
+            busy_ns: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            shortest_ns: AtomicU64::new(u64::MAX),
+            longest_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one call that took `busy` to process `frames`.
+    pub fn record(&self, busy: Duration, frames: u64) {
+        let ns = busy.as_nanos() as u64;
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.busy_ns.fetch_add(ns, Ordering::Relaxed);
+        self.frames.fetch_add(frames, Ordering::Relaxed);
+        self.shortest_ns.fetch_min(ns, Ordering::Relaxed);
+        self.longest_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ProcessorSnapshot {
+        let calls = self.calls.load(Ordering::Relaxed);
+        let shortest = if calls > 0 {
+            self.shortest_ns.load(Ordering::Relaxed)
+        } else {
+            0
+        };
+        ProcessorSnapshot {
+            calls,
+            busy: Duration::from_nanos(self.busy_ns.load(Ordering::Relaxed)),
+            frames: self.frames.load(Ordering::Relaxed),
+            shortest: Duration::from_nanos(shortest),
+            longest: Duration::from_nanos(self.longest_ns.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.calls.store(0, Ordering::Relaxed);
+        self.busy_ns.store(0, Ordering::Relaxed);
+        self.frames.store(0, Ordering::Relaxed);
+        self.shortest_ns.store(u64::MAX, Ordering::Relaxed);
+        self.longest_ns.store(0, Ordering::Relaxed);
+    }
+
+    pub fn describe(&self, sample_rate: u32) -> String {
+        let s = self.snapshot();
+        if s.calls == 0 {
+            return format!("{}: not run", self.name);
+        }
+        format!(
+            "{}: {:.0} us min, {:.0} us average, {:.0} us max, {:.1}% of the budget",
+            self.name,
+            s.shortest.as_secs_f32() * 1e6,
+            s.average().as_secs_f32() * 1e6,
+            s.longest.as_secs_f32() * 1e6,
+            s.load(sample_rate) * 100.0
+        )
+    }
+}
+
+/// Every processor timed, across the chains. Shared, so a reloaded chain
+/// picks its processors' totals back up.
+#[derive(Debug, Default)]
+pub struct ProcessorLoads {
+    processors: Mutex<Vec<Arc<ProcessorLoad>>>,
+}
+
+impl ProcessorLoads {
+    /// The totals under `name`, or new ones.
+    pub fn bind(&self, name: String) -> Arc<ProcessorLoad> {
+        let mut processors = self.processors.lock().unwrap();
+        if let Some(load) = processors.iter().find(|p| p.name == name) {
+            return load.clone();
+        }
+        let load = Arc::new(ProcessorLoad::new(name));
+        processors.push(load.clone());
+        load
+    }
+
+    /// Every processor in the order bound.
+    pub fn all(&self) -> Vec<Arc<ProcessorLoad>> {
+        self.processors.lock().unwrap().clone()
+    }
+
+    pub fn reset(&self) {
+        for load in self.all() {
+            load.reset();
+        }
+    }
+
+    /// One line per processor, the costliest first.
+    pub fn report(&self, sample_rate: u32) -> String {
+        let mut processors = self.all();
+        if processors.is_empty() {
+            return "No processors in the chain".to_string();
+        }
+        processors.sort_by_key(|p| std::cmp::Reverse(p.snapshot().busy));
+        processors
+            .iter()
+            .map(|p| p.describe(sample_rate))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
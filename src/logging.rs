@@ -0,0 +1,46 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+
+/// Used when neither `--log` nor `RUST_LOG` is set.
+const DEFAULT_FILTER: &str = "info";
+
+/// Installs the subscriber, printing to stderr or appending to `file`.
+///
+/// Status, warnings and errors are events with their module path as the
+/// target, so `--log` or `RUST_LOG` pick levels per subsystem. What a mode
+/// exists to print, such as prompts, console replies and the reports of
+/// `measure-latency` and `calibrate`, still goes to stdout. Audio threads
+/// never log themselves: their events go through `rtlog` and their
+/// timings through `timing`.
+pub fn init(filter: Option<&str>, file: Option<&Path>) -> Result<()> {
+    let filter = match filter {
+        Some(directives) => EnvFilter::try_new(directives)
+            .with_context(|| format!("Invalid log filter '{}'", directives))?,
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+        }
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open the log file {}", path.display()))?;
+            builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .try_init()
+        }
+        // The console shows events as they happen, so no timestamps
+        None => builder
+            .without_time()
+            .with_writer(std::io::stderr)
+            .try_init(),
+    }
+    .map_err(|err| anyhow!("Failed to set up logging: {}", err))
+}
@@ -0,0 +1,331 @@
+use crate::dsp::gain_to_db;
+use crate::params::AtomicF32;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Reported for windows that are silent or not yet filled.
+pub const SILENCE_LUFS: f32 = -70.0;
+/// Gating blocks are 400 ms long and start every 100 ms.
+const SUB_BLOCK_MS: f32 = 100.0;
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+/// Integrated loudness histogram, 0.1 LU bins from the absolute gate up.
+const HISTOGRAM_MIN_LUFS: f64 = ABSOLUTE_GATE_LUFS;
+const HISTOGRAM_BINS: usize = 800;
+const HISTOGRAM_STEP: f64 = 0.1;
+/// True peak oversampling factor and taps per polyphase branch.
+const OVERSAMPLE: usize = 4;
+const TRUE_PEAK_TAPS: usize = 12;
+
+/// Loudness readings published by the audio thread.
+#[derive(Debug)]
+pub struct LoudnessLevels {
+    momentary: AtomicF32,
+    short_term: AtomicF32,
+    integrated: AtomicF32,
+    true_peak_db: AtomicF32,
+    reset: AtomicBool,
+}
+
+impl LoudnessLevels {
+    /// Loudness over the last 400 ms, in LUFS.
+    pub fn momentary(&self) -> f32 {
+        self.momentary.get()
+    }
+
+    /// Loudness over the last 3 s, in LUFS.
+    pub fn short_term(&self) -> f32 {
+        self.short_term.get()
+    }
+
+    /// Gated programme loudness since the last reset, in LUFS.
+    pub fn integrated(&self) -> f32 {
+        self.integrated.get()
+    }
+
+    /// Highest inter-sample peak since the last reset, in dBTP.
+    pub fn true_peak_db(&self) -> f32 {
+        self.true_peak_db.get()
+    }
+
+    /// Asks the audio thread to restart the integrated and true-peak
+    /// measurement.
+    pub fn reset(&self) {
+        self.reset.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Direct form I biquad with `a0` normalised to 1.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            ..Default::default()
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// The two-stage K-weighting filter from ITU-R BS.1770, with coefficients
+/// derived for any sample rate.
+fn k_weighting(sample_rate: f64) -> (Biquad, Biquad) {
+    // High shelf modelling the head
+    let f0 = 1681.974450955533;
+    let gain_db = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // RLB high-pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    (shelf, high_pass)
+}
+
+/// BS.1770 channel weights: surrounds count 1.41, the LFE of a 5.1 layout
+/// is ignored.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn loudness(weighted_power: f64) -> f64 {
+    if weighted_power <= 0.0 {
+        f64::NEG_INFINITY
+    } else {
+        -0.691 + 10.0 * weighted_power.log10()
+    }
+}
+
+fn publish(value: f64) -> f32 {
+    value.max(SILENCE_LUFS as f64) as f32
+}
+
+struct ChannelState {
+    shelf: Biquad,
+    high_pass: Biquad,
+    /// Most recent input samples for the true-peak interpolator.
+    history: [f32; TRUE_PEAK_TAPS],
+}
+
+/// EBU R128 loudness meter: momentary, short-term and gated integrated
+/// loudness plus a 4x oversampled true peak. Runs on the audio thread
+/// without allocating.
+pub struct LoudnessMeter {
+    levels: Arc<LoudnessLevels>,
+    channels: Vec<ChannelState>,
+    weights: Vec<f64>,
+
+    sub_block_len: usize,
+    sub_block_fill: usize,
+    /// Per-channel sum of squares of the current 100 ms sub-block.
+    sub_block_sum: Vec<f64>,
+    /// Weighted power of the last 30 sub-blocks, newest at `ring_head`.
+    ring: [f64; SHORT_TERM_SUB_BLOCKS],
+    ring_head: usize,
+    ring_filled: usize,
+
+    /// Count and summed power of gating blocks per loudness bin.
+    histogram_count: Vec<u64>,
+    histogram_power: Vec<f64>,
+    true_peak: f32,
+    /// Polyphase interpolation filter, `OVERSAMPLE` rows of taps.
+    true_peak_filter: Vec<f32>,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: f32, channels: usize) -> (Self, Arc<LoudnessLevels>) {
+        let levels = Arc::new(LoudnessLevels {
+            momentary: AtomicF32::new(SILENCE_LUFS),
+            short_term: AtomicF32::new(SILENCE_LUFS),
+            integrated: AtomicF32::new(SILENCE_LUFS),
+            true_peak_db: AtomicF32::new(SILENCE_LUFS),
+            reset: AtomicBool::new(false),
+        });
+
+        let (shelf, high_pass) = k_weighting(sample_rate as f64);
+        let meter = LoudnessMeter {
+            levels: levels.clone(),
+            channels: (0..channels)
+                .map(|_| ChannelState {
+                    shelf,
+                    high_pass,
+                    history: [0.0; TRUE_PEAK_TAPS],
+                })
+                .collect(),
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            sub_block_len: ((SUB_BLOCK_MS * 0.001 * sample_rate) as usize).max(1),
+            sub_block_fill: 0,
+            sub_block_sum: vec![0.0; channels],
+            ring: [0.0; SHORT_TERM_SUB_BLOCKS],
+            ring_head: 0,
+            ring_filled: 0,
+            histogram_count: vec![0; HISTOGRAM_BINS],
+            histogram_power: vec![0.0; HISTOGRAM_BINS],
+            true_peak: 0.0,
+            true_peak_filter: true_peak_filter(),
+        };
+        (meter, levels)
+    }
+
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        if self.levels.reset.swap(false, Ordering::Relaxed) {
+            self.histogram_count.iter_mut().for_each(|c| *c = 0);
+            self.histogram_power.iter_mut().for_each(|p| *p = 0.0);
+            self.true_peak = 0.0;
+        }
+
+        for frame in buffer.chunks(channels) {
+            for (channel, (&sample, state)) in
+                frame.iter().zip(self.channels.iter_mut()).enumerate()
+            {
+                let weighted = state.high_pass.process(state.shelf.process(sample as f64));
+                self.sub_block_sum[channel] += weighted * weighted;
+
+                state.history.copy_within(1.., 0);
+                state.history[TRUE_PEAK_TAPS - 1] = sample;
+                for phase in self.true_peak_filter.chunks(TRUE_PEAK_TAPS) {
+                    let interpolated: f32 =
+                        phase.iter().zip(&state.history).map(|(c, x)| c * x).sum();
+                    self.true_peak = self.true_peak.max(interpolated.abs());
+                }
+            }
+
+            self.sub_block_fill += 1;
+            if self.sub_block_fill == self.sub_block_len {
+                self.finish_sub_block();
+            }
+        }
+
+        self.levels
+            .true_peak_db
+            .set(publish(gain_to_db(self.true_peak) as f64));
+    }
+
+    fn finish_sub_block(&mut self) {
+        let power: f64 = self
+            .sub_block_sum
+            .iter()
+            .zip(&self.weights)
+            .map(|(sum, weight)| weight * sum / self.sub_block_len as f64)
+            .sum();
+        self.sub_block_sum.iter_mut().for_each(|s| *s = 0.0);
+        self.sub_block_fill = 0;
+
+        self.ring_head = (self.ring_head + 1) % SHORT_TERM_SUB_BLOCKS;
+        self.ring[self.ring_head] = power;
+        self.ring_filled = (self.ring_filled + 1).min(SHORT_TERM_SUB_BLOCKS);
+
+        let momentary = self.window_power(MOMENTARY_SUB_BLOCKS);
+        let short_term = self.window_power(SHORT_TERM_SUB_BLOCKS);
+        self.levels.momentary.set(publish(loudness(momentary)));
+        self.levels.short_term.set(publish(loudness(short_term)));
+
+        // Every completed 400 ms window is one gating block
+        if self.ring_filled >= MOMENTARY_SUB_BLOCKS {
+            let block_loudness = loudness(momentary);
+            if block_loudness >= ABSOLUTE_GATE_LUFS {
+                let bin = ((block_loudness - HISTOGRAM_MIN_LUFS) / HISTOGRAM_STEP) as usize;
+                let bin = bin.min(HISTOGRAM_BINS - 1);
+                self.histogram_count[bin] += 1;
+                self.histogram_power[bin] += momentary;
+            }
+            self.levels.integrated.set(publish(self.integrated()));
+        }
+    }
+
+    /// Mean power of the newest `sub_blocks` sub-blocks, zero until that
+    /// many have been measured.
+    fn window_power(&self, sub_blocks: usize) -> f64 {
+        if self.ring_filled < sub_blocks {
+            return 0.0;
+        }
+        (0..sub_blocks)
+            .map(|i| {
+                self.ring[(self.ring_head + SHORT_TERM_SUB_BLOCKS - i) % SHORT_TERM_SUB_BLOCKS]
+            })
+            .sum::<f64>()
+            / sub_blocks as f64
+    }
+
+    /// Two-pass gated loudness over the histogram.
+    fn integrated(&self) -> f64 {
+        let mean = |from_bin: usize| {
+            let count: u64 = self.histogram_count[from_bin..].iter().sum();
+            if count == 0 {
+                return 0.0;
+            }
+            self.histogram_power[from_bin..].iter().sum::<f64>() / count as f64
+        };
+
+        let ungated = loudness(mean(0));
+        if ungated == f64::NEG_INFINITY {
+            return ungated;
+        }
+        let relative_gate = ungated + RELATIVE_GATE_LU;
+        let bin = ((relative_gate - HISTOGRAM_MIN_LUFS) / HISTOGRAM_STEP).max(0.0) as usize;
+        loudness(mean(bin.min(HISTOGRAM_BINS - 1)))
+    }
+}
+
+/// Windowed-sinc interpolator for the oversampled true peak, one row of
+/// taps per sub-sample phase.
+fn true_peak_filter() -> Vec<f32> {
+    let half = TRUE_PEAK_TAPS as f64 / 2.0;
+    let mut filter = Vec::with_capacity(OVERSAMPLE * TRUE_PEAK_TAPS);
+    for phase in 0..OVERSAMPLE {
+        let offset = phase as f64 / OVERSAMPLE as f64;
+        for tap in 0..TRUE_PEAK_TAPS {
+            // Interpolate between the two middle samples of the history
+            let x = tap as f64 - (half - 1.0) - offset;
+            let sinc = if x.abs() < 1e-9 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let window = 0.5 + 0.5 * (PI * x / (half + 1.0)).cos();
+            filter.push((sinc * window) as f32);
+        }
+    }
+    filter
+}
@@ -1,17 +1,164 @@
-use anyhow::{Context, Result, anyhow};
-use cpal::{Device, SupportedBufferSize};
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use ringbuf::HeapRb;
-use ringbuf::traits::{Consumer, Producer, Split};
-use std::cmp::max;
+mod ab;
+mod automix;
+mod broadcast;
+mod calibrate;
+mod chain;
+mod cli;
+mod clip;
+mod control;
+mod cues;
+mod dither;
+mod drift;
+mod dsp;
+#[cfg(feature = "aec")]
+mod echo;
+mod fade;
+mod frame_ring;
+mod generator;
+mod headroom;
+mod history;
+mod http;
+mod icecast;
+mod ipc;
+#[cfg(feature = "jack")]
+mod jack_client;
+mod jitter;
+mod json;
+mod latency;
+mod load;
+mod logging;
+mod loudness;
+mod meter;
+mod metrics;
+mod metronome;
+#[cfg(feature = "midi")]
+mod midi;
+mod mixer;
+mod osc;
+mod output_tap;
+mod params;
+mod phase;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+mod pipewire_node;
+mod player;
+mod profile;
+mod realtime;
+mod recorder;
+mod resample;
+mod routing;
+mod rtlog;
+mod rtp;
+mod sample_convert;
+mod scene;
+mod scope;
+#[cfg(feature = "script")]
+mod script;
+mod service;
+mod shutdown;
+mod snapcast;
+mod source;
+mod spectrogram;
+mod spectrum;
+mod supervisor;
+mod tempo;
+mod timing;
+mod transfer;
+mod tui;
+mod tuner;
+mod virtual_mic;
+mod websocket;
+mod xrun;
+
+use ab::AbChain;
+use anyhow::{Context, Result, anyhow, bail};
+use broadcast::{BroadcastBus, BroadcastTarget};
+use chain::{ChainReloader, ChainSettings};
+use cli::{Args, AuxEffect, Mode};
+use control::Controls;
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host, SupportedBufferSize, SupportedStreamConfig};
+use cues::CueList;
+use dsp::{
+    Alignment, AutoGain, Compressor, ConvolutionReverb, Curve, DcBlocker, DeEsser, Delay, DspChain,
+    FeedbackSuppressor, FeedbackView, FirFilter, GraphicEq, HighPass, Limiter, Looper, MidSide,
+    Multiband, NoiseGate, NoiseSuppressor, Processor, Reverb, Saturation, SpeakerOutput, Speakers,
+};
+use fade::Fade;
+use generator::SignalGenerator;
+use history::History;
+use icecast::IcecastStream;
+use load::CallbackLoad;
+use loudness::LoudnessMeter;
+use meter::Meter;
+use metronome::Metronome;
+use mixer::{MasterBus, Mixer, TalkbackBus};
+use params::ParamStore;
+use phase::PhaseMeter;
+use profile::Profile;
+use realtime::Realtime;
+use recorder::Recorder;
+use resample::Quality;
+use rtp::{RtpReceiver, RtpSender};
+use scene::{Fades, Scenes};
+use service::Failure;
+use snapcast::SnapcastServer;
+use source::{ProcessedSource, RecordedSource, Source, SourceSettings, SourceTap, StreamFeed};
+use spectrogram::Spectrogram;
 use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+use supervisor::{Direction, Supervisor};
+use tempo::Tempo;
+use timing::{CallbackTimes, Stage};
+use tracing::{info, warn};
+use virtual_mic::VirtualMic;
+use xrun::XrunStats;
+
+/// Smallest buffer size `--low-latency` asks for.
+const LOW_LATENCY_FRAMES: u32 = 64;
+
+/// Picks the audio host named on the command line, or the default one,
+/// after listing the hosts available here.
+fn select_host(name: Option<&str>) -> Result<Host> {
+    let available = cpal::available_hosts();
+    let names: Vec<_> = available.iter().map(|id| id.name()).collect();
+    println!("Available hosts: {}", names.join(", "));
 
-fn select_io_devices() -> Result<(Device, Device)> {
-    // 1. Setup Host
-    let host = cpal::default_host();
-    println!("Default Host: {}\n", host.id().name());
+    let Some(name) = name else {
+        let host = cpal::default_host();
+        println!("Default Host: {}\n", host.id().name());
+        return Ok(host);
+    };
+    match available
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    {
+        Some(id) => {
+            let host = cpal::host_from_id(id)?;
+            println!("Host: {}\n", host.id().name());
+            Ok(host)
+        }
+        // Built in, but its server isn't running or its driver is missing
+        None if cpal::ALL_HOSTS
+            .iter()
+            .any(|id| id.name().eq_ignore_ascii_case(name)) =>
+        {
+            bail!("Host {} is not available right now", name)
+        }
+        None => bail!(
+            "Unknown host '{}', available: {}. JACK and ASIO need `--features jack` or `asio`",
+            name,
+            names.join(", ")
+        ),
+    }
+}
 
-    // 2. Query and Collect Input Devices
+/// `output` is a device already chosen, the virtual microphone's, which
+/// leaves only the inputs to pick.
+fn select_io_devices(host: &Host, output: Option<&Device>) -> Result<(Vec<Device>, Device)> {
+    // 1. Query and Collect Input Devices
     println!("--- Input Devices ---");
     let input_devices: Vec<_> = host.input_devices()?.collect();
 
@@ -23,36 +170,34 @@ fn select_io_devices() -> Result<(Device, Device)> {
         let description = device.description().unwrap();
         let config = device.default_input_config();
         match config {
-            Ok(c) => println!(
-                "[{}] {} (Default Rate: {} Hz)",
-                index,
-                description,
-                c.sample_rate()
-            ),
+            Ok(c) => println!("[{}] {} ({})", index, description, describe_config(&c)),
             Err(_) => println!("[{}] {} (Config unavailable)", index, description),
         }
     }
 
-    // 3. User Input Selection
-    println!("\nEnter the ID of the input device to use:");
+    // 2. User Input Selection
+    println!("\nEnter the ID(s) of the input device(s) to use, separated by commas:");
     let mut selection = String::new();
     io::stdin().read_line(&mut selection)?;
-    let selection: usize = selection
-        .trim()
-        .parse()
-        .context("Please enter a valid number")?;
-
-    if selection >= input_devices.len() {
-        anyhow::bail!("Invalid device index.");
+    let mut selected_inputs = Vec::new();
+    for id in selection.trim().split(',') {
+        let id: usize = id.trim().parse().context("Please enter a valid number")?;
+        if id >= input_devices.len() {
+            anyhow::bail!("Invalid device index.");
+        }
+        let input_device = input_devices[id].clone();
+        println!(
+            "Selected input device: (id {:?}) {}",
+            input_device.id(),
+            input_device.description()?
+        );
+        selected_inputs.push(input_device);
+    }
+    if let Some(output) = output {
+        return Ok((selected_inputs, output.clone()));
     }
-    let input_device = input_devices[selection].clone();
-    println!(
-        "Selected input device: (id {:?}) {}",
-        input_device.id(),
-        input_device.description()?
-    );
 
-    // 4. Query and Collect Output Devices
+    // 3. Query and Collect Output Devices
     println!("--- Output Devices ---");
     let output_devices: Vec<_> = host.output_devices()?.collect();
 
@@ -64,17 +209,12 @@ fn select_io_devices() -> Result<(Device, Device)> {
         let description = device.description().unwrap();
         let config = device.default_output_config();
         match config {
-            Ok(c) => println!(
-                "[{}] {} (Default Rate: {} Hz)",
-                index,
-                description,
-                c.sample_rate()
-            ),
+            Ok(c) => println!("[{}] {} ({})", index, description, describe_config(&c)),
             Err(_) => println!("[{}] {} (Config unavailable)", index, description),
         }
     }
 
-    // 5. User Output Selection
+    // 4. User Output Selection
     println!("\nEnter the ID of the output device to use:");
     let mut selection = String::new();
     io::stdin().read_line(&mut selection)?;
@@ -87,234 +227,1666 @@ fn select_io_devices() -> Result<(Device, Device)> {
         anyhow::bail!("Invalid device index.");
     }
     let output_device = output_devices[selection].clone();
-    println!(
+    info!(
         "Selected output device: (id {:?}) {}",
         output_device.id(),
         output_device.description()?
     );
 
-    Ok((input_device, output_device))
+    Ok((selected_inputs, output_device))
 }
 
-fn main() -> Result<()> {
-    let (input_device, output_device) = select_io_devices()?;
+/// The host's default input and output, for a daemon started without a
+/// profile naming its devices.
+fn default_devices(host: &Host, output: Option<&Device>) -> Result<(Vec<Device>, Device)> {
+    let input = host
+        .default_input_device()
+        .context("No default input device")?;
+    let output = match output {
+        Some(output) => output.clone(),
+        None => host
+            .default_output_device()
+            .context("No default output device")?,
+    };
+    info!("Selected input device: {}", input.description()?);
+    info!("Selected output device: {}", output.description()?);
+    Ok((vec![input], output))
+}
 
-    // Call this multiple times to have multiple vocals
-    run_loopback(&input_device, &output_device)?;
-    // jack_loopback(&input_device, &output_device)?;
+/// Reads an answer to a prompt. A daemon has no one to ask, so it gets an
+/// empty line and every prompt its default.
+fn read_answer(headless: bool) -> Result<String> {
+    let mut answer = String::new();
+    if !headless {
+        io::stdin().read_line(&mut answer)?;
+    }
+    Ok(answer)
+}
 
-    Ok(())
+/// Opens the devices a profile names instead of asking for them.
+fn profile_devices(
+    host: &Host,
+    profile: &Profile,
+    output: Option<&Device>,
+) -> Result<(Vec<Device>, Device)> {
+    let inputs = profile
+        .inputs
+        .iter()
+        .map(|name| supervisor::find_device(host, Direction::Input, name))
+        .collect::<Result<Vec<_>>>()?;
+    let output = match (output, &profile.output) {
+        (Some(output), _) => output.clone(),
+        (None, Some(name)) => supervisor::find_device(host, Direction::Output, name)?,
+        (None, None) => bail!("Profile {} names no output", profile.name),
+    };
+    for device in &inputs {
+        info!("Selected input device: {}", device.description()?);
+    }
+    info!("Selected output device: {}", output.description()?);
+    Ok((inputs, output))
 }
 
-fn run_loopback(input_device: &cpal::Device, output_device: &cpal::Device) -> Result<()> {
-    let default_input_config = input_device.default_input_config()?;
-    let default_output_config = output_device.default_output_config()?;
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            service::exit_code(&err)
+        }
+    }
+}
 
-    let (input_min_buf, input_max_buf) = match default_input_config.buffer_size() {
-        SupportedBufferSize::Range { min, max } => (*min, *max),
-        SupportedBufferSize::Unknown => (1024, 1024),
+fn run() -> Result<()> {
+    let args = Args::parse().context(Failure::Usage)?;
+    if args.mode == Mode::Control {
+        println!(
+            "{}",
+            ipc::send_line(&args.socket_path(), &args.ctl.join(" "))?
+        );
+        return Ok(());
+    }
+    logging::init(args.log.as_deref(), args.log_file.as_deref()).context(Failure::Usage)?;
+    let profiles = match &args.profiles {
+        Some(path) => profile::load(path).context(Failure::Config)?,
+        None => Vec::new(),
+    };
+    let startup = match &args.profile {
+        Some(name) => Some(
+            profile::find(&profiles, name)
+                .context(Failure::Config)?
+                .clone(),
+        ),
+        None => None,
+    };
+    if args.daemon {
+        // There are no prompts to interrupt, and a stop while waiting for
+        // the devices should end cleanly
+        shutdown::install();
+    }
+    // JACK and PipeWire get their audio through the graph, there are no
+    // devices to pick
+    match args.mode {
+        Mode::Jack => return run_jack(&args, profiles),
+        Mode::PipeWire => return run_pipewire(&args, profiles),
+        Mode::Loopback | Mode::MeasureLatency | Mode::Calibrate => {}
+        Mode::Control => unreachable!(),
+    }
+    // Removed again when this returns, however it does
+    let broadcast_mic = args.broadcast == Some(BroadcastTarget::VirtualMic);
+    let virtual_mic = if args.virtual_mic || broadcast_mic {
+        Some(VirtualMic::create().context(Failure::Devices)?)
+    } else {
+        None
     };
-    let (output_min_buf, output_max_buf) = match default_output_config.buffer_size() {
-        SupportedBufferSize::Range { min, max } => (*min, *max),
-        SupportedBufferSize::Unknown => (1024, 1024),
+    let host = select_host(args.host.as_deref()).context(Failure::Devices)?;
+    let virtual_output = match &virtual_mic {
+        Some(mic) => Some(mic.output_device(&host).context(Failure::Devices)?),
+        None => None,
+    };
+    // The microphone carries either the output or the broadcast bus
+    let (output, broadcast_output) = if broadcast_mic {
+        (None, virtual_output)
+    } else {
+        (virtual_output.as_ref(), None)
+    };
+    let open_devices = || match &startup {
+        Some(profile) if !profile.inputs.is_empty() => profile_devices(&host, profile, output),
+        _ if args.daemon => default_devices(&host, output),
+        _ => select_io_devices(&host, output),
+    };
+    let (input_devices, output_device) = if args.daemon {
+        // Started at boot the interfaces may not be there yet
+        match service::wait_for("the audio devices", args.device_wait(), open_devices)? {
+            Some(devices) => devices,
+            None => return Ok(()),
+        }
+    } else {
+        open_devices()?
     };
-    let min_buf = max(input_min_buf, output_min_buf);
-    let max_buf = max(input_max_buf, output_max_buf);
 
-    println!("\nEnter buffer size, min: {}, max: {}. Default is: 1024", min_buf, max_buf);
-    let mut selection = String::new();
-    io::stdin().read_line(&mut selection)?;
-    let buffer_size: u32 = selection
-        .trim()
-        .parse()
-        .unwrap_or(1024);
-
-    /* Check that sample formats match */
-    if default_input_config.sample_format() != default_output_config.sample_format() {
-        panic!(
-            "Input and output device sample format are different: {} vs {}",
-            default_input_config.sample_format(),
-            default_output_config.sample_format()
+    let broadcast_device = match &args.broadcast {
+        Some(BroadcastTarget::Device(name)) => Some(
+            supervisor::find_device(&host, Direction::Output, name).context(Failure::Devices)?,
+        ),
+        Some(BroadcastTarget::VirtualMic) => broadcast_output,
+        None => None,
+    };
+
+    match args.mode {
+        // Every input device is mixed into the one output, and into the
+        // broadcast device when there is one
+        Mode::Loopback => run_loopback(
+            host,
+            &input_devices,
+            &output_device,
+            broadcast_device.as_ref(),
+            &args,
+            profiles,
+        )?,
+        Mode::MeasureLatency => {
+            let default_output_config = output_device.default_output_config()?;
+            let (buffer_size, quality) = prompt_stream_settings(
+                &input_devices[..1],
+                &default_output_config,
+                args.low_latency,
+                false,
+            )?;
+            latency::measure(&input_devices[0], &output_device, buffer_size, quality)?;
+        }
+        Mode::Calibrate => {
+            let default_output_config = output_device.default_output_config()?;
+            let (buffer_size, quality) = prompt_stream_settings(
+                &input_devices[..1],
+                &default_output_config,
+                args.low_latency,
+                false,
+            )?;
+            calibrate::calibrate(&input_devices[0], &output_device, buffer_size, quality)?;
+        }
+        Mode::Jack | Mode::PipeWire | Mode::Control => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Rate, channels and buffer sizes of a device for the selector. Channels
+/// are counted from 1 as in `--map`, interfaces with many of them, as ASIO
+/// drivers list them, show the whole range.
+fn describe_config(config: &SupportedStreamConfig) -> String {
+    let channels = match config.channels() {
+        1 => "1 channel".to_string(),
+        2 => "2 channels".to_string(),
+        n => format!("{} channels, in1-in{}", n, n),
+    };
+    let buffer = match buffer_range(config.buffer_size()) {
+        // ASIO drivers run at the size set in their control panel
+        Some((min, max)) if min == max => format!(", buffer {} frames, fixed by the driver", min),
+        Some((min, max)) => format!(", buffer {}-{} frames", min, max),
+        None => String::new(),
+    };
+    format!(
+        "Default Rate: {} Hz, {}{}",
+        config.sample_rate(),
+        channels,
+        buffer
+    )
+}
+
+/// Asks for the buffer size and, when the inputs need resampling, the
+/// resampler quality.
+fn prompt_stream_settings(
+    input_devices: &[Device],
+    default_output_config: &SupportedStreamConfig,
+    low_latency: bool,
+    headless: bool,
+) -> Result<(u32, Quality)> {
+    // Every device has to accept the size, so only offer the range they
+    // all support
+    let mut range = buffer_range(default_output_config.buffer_size());
+    let mut input_rates = Vec::new();
+    for input_device in input_devices {
+        let default_input_config = input_device.default_input_config()?;
+        range = match (range, buffer_range(default_input_config.buffer_size())) {
+            (Some((min_a, max_a)), Some((min_b, max_b))) => {
+                Some((min_a.max(min_b), max_a.min(max_b)))
+            }
+            (range, None) | (None, range) => range,
+        };
+        input_rates.push(default_input_config.sample_rate());
+    }
+
+    let mut buffer_size: u32 = if let Some((min_buf, max_buf)) = range
+        && min_buf == max_buf
+    {
+        // Nothing to choose, change it in the driver's control panel
+        info!("Buffer size is fixed by the driver at {} frames", min_buf);
+        min_buf
+    } else if low_latency {
+        // The smallest size every device takes. Devices that don't report a
+        // range are asked for LOW_LATENCY_FRAMES and may refuse
+        let size = match range {
+            Some((min_buf, max_buf)) if min_buf <= max_buf => min_buf.max(LOW_LATENCY_FRAMES),
+            _ => LOW_LATENCY_FRAMES,
+        };
+        info!(
+            "Low latency: asking for {} frames ({:.1} ms)",
+            size,
+            size as f32 * 1000.0 / default_output_config.sample_rate() as f32
         );
+        size
+    } else {
+        match range {
+            Some((min_buf, max_buf)) if min_buf <= max_buf => println!(
+                "\nEnter buffer size, min: {}, max: {}. Default is: 1024",
+                min_buf, max_buf
+            ),
+            Some(_) => println!(
+                "\nThe devices share no buffer size, each will be clamped to its own range. \
+                 Enter buffer size. Default is: 1024"
+            ),
+            None => println!("\nEnter buffer size. Default is: 1024"),
+        }
+        read_answer(headless)?.trim().parse().unwrap_or(1024)
+    };
+    if let Some((min_buf, max_buf)) = range
+        && min_buf <= max_buf
+        && !(min_buf..=max_buf).contains(&buffer_size)
+    {
+        buffer_size = buffer_size.clamp(min_buf, max_buf);
+        warn!("Buffer size clamped to {}", buffer_size);
     }
 
-    /* Check that sample rates match */
-    if default_input_config.sample_rate() != default_output_config.sample_rate() {
-        panic!(
-            "Input and output device sample rate are different: {} vs {}",
-            default_input_config.sample_rate(),
-            default_output_config.sample_rate()
+    /* Resample the inputs if the sample rates don't match */
+    let output_rate = default_output_config.sample_rate();
+    let mut resample_quality = Quality::Medium;
+    if input_rates.iter().any(|&rate| rate != output_rate) {
+        println!(
+            "\nInput and output sample rates differ: {:?} vs {}, inputs will be resampled.",
+            input_rates, output_rate
         );
+        println!("Select resampling quality: [0] low, [1] medium, [2] high. Default is: 1");
+        resample_quality = match read_answer(headless)?.trim().parse().unwrap_or(1) {
+            0 => Quality::Low,
+            2 => Quality::High,
+            _ => Quality::Medium,
+        };
     }
 
-    let mut input_config: cpal::StreamConfig = default_input_config.into();
+    Ok((buffer_size, resample_quality))
+}
+
+/// Buffer sizes a device accepts, `None` if it doesn't say.
+fn buffer_range(supported: &SupportedBufferSize) -> Option<(u32, u32)> {
+    match *supported {
+        SupportedBufferSize::Range { min, max } => Some((min, max)),
+        SupportedBufferSize::Unknown => None,
+    }
+}
+
+fn run_loopback(
+    host: Host,
+    input_devices: &[Device],
+    output_device: &Device,
+    broadcast_device: Option<&Device>,
+    args: &Args,
+    profiles: Vec<Profile>,
+) -> Result<()> {
+    let default_output_config = output_device
+        .default_output_config()
+        .context(Failure::Devices)?;
+
+    let (buffer_size, resample_quality) = prompt_stream_settings(
+        input_devices,
+        &default_output_config,
+        args.low_latency,
+        args.daemon,
+    )?;
+
+    /* Formats may differ, both sides are converted to and from f32 */
+    let output_format = default_output_config.sample_format();
+
+    let output_rate = default_output_config.sample_rate();
+    let requested =
+        sample_convert::fixed_buffer_size(default_output_config.buffer_size(), buffer_size);
     let mut output_config: cpal::StreamConfig = default_output_config.into();
-    // TODO: Tole ga zjebe wtf
-    // input_config.buffer_size = cpal::BufferSize::Fixed(buffer_size);
-    // output_config.buffer_size = cpal::BufferSize::Fixed(buffer_size);
+    output_config.buffer_size = requested;
 
-    /* Check that buffer */
+    info!(
+        "Output: {} Hz, {} channels, {} samples, buffer size {:?}",
+        output_config.sample_rate, output_config.channels, output_format, output_config.buffer_size
+    );
 
-    println!("\nStream Config:");
+    // --- Build Input Streams ---
+    // Each input device gets its own stream and ring buffers and becomes a
+    // mixer channel
+    let output_channels = output_config.channels as usize;
+    let target_latency = match args.target_latency {
+        Some(ms) => ((ms * output_rate as f32 / 1000.0) as u32).max(1),
+        None => buffer_size,
+    };
+    // Audio threads never print, they queue their messages for this thread
+    let logger = rtlog::Logger::spawn()?;
+    // Each stream's thread promotes itself to real-time on its first callback
+    let realtime = Arc::new(Realtime::spawn(args.realtime, args.rt_priority)?);
+    // Streams are rebuilt after a disconnect without touching the mixer
+    let wasapi = host.id().name() == "WASAPI";
+    let mut supervisor = Supervisor::new(host, args.fallback_default);
+    let mut xruns = Vec::new();
+    let mut buffers = Vec::new();
+    let mut clips = Vec::new();
+    let mut levels = Vec::new();
+    let mut sources: Vec<(Box<dyn Source>, f32)> = Vec::new();
+    for (index, input_device) in input_devices.iter().enumerate() {
+        let mut gain_db = 0.0;
+        if input_devices.len() > 1 {
+            println!(
+                "\nEnter gain in dB for input {}. Default is: 0",
+                input_device.description()?
+            );
+            gain_db = read_answer(args.daemon)?.trim().parse().unwrap_or(0.0);
+        }
+
+        let settings = SourceSettings {
+            output_rate,
+            output_channels,
+            buffer_size,
+            target_latency,
+            drift_compensation: args.drift_compensation,
+            map: args.map.get(index).map(String::as_str),
+            quality: resample_quality,
+            crossfade: args.crossfade_length(),
+        };
+        let (mut feed, tap) = source::open_input(input_device, &settings, &logger, &realtime)?;
+        let stream = feed.start(input_device).context(Failure::Devices)?;
+        supervisor.watch(
+            Direction::Input,
+            input_device,
+            stream,
+            tap.xruns(),
+            tap.fade(),
+            Box::new(move |device| feed.start(device)),
+        );
+        xruns.push(tap.xruns());
+        buffers.push(tap.jitter());
+        clips.push(tap.clips());
+        levels.push(tap.levels());
+        sources.push((Box::new(tap), gain_db));
+    }
+    let settings = SourceSettings {
+        output_rate,
+        output_channels,
+        buffer_size,
+        target_latency,
+        drift_compensation: args.drift_compensation,
+        map: None,
+        quality: resample_quality,
+        crossfade: args.crossfade_length(),
+    };
+    let (receivers, taps) = start_rtp_inputs(args, &settings)?;
+    for tap in taps {
+        xruns.push(tap.xruns());
+        buffers.push(tap.jitter());
+        clips.push(tap.clips());
+        levels.push(tap.levels());
+        sources.push((Box::new(tap), 0.0));
+    }
+    let (broadcast_feed, broadcast) = match broadcast_device {
+        Some(device) => {
+            let (feed, output) =
+                broadcast::open(device, &settings, args.dither, &logger, &realtime)
+                    .context(Failure::Devices)?;
+            (Some(feed), Some(output))
+        }
+        None => (None, None),
+    };
+
+    let Engine {
+        mut render,
+        mut controls,
+        recorders,
+        rtp,
+        icecast,
+        snapcast,
+    } = build_engine(
+        sources,
+        broadcast_feed,
+        output_rate,
+        output_channels,
+        sample_convert::max_frames(output_channels),
+        args,
+    )?;
+    let output_xruns = Arc::new(XrunStats::new("output".to_string(), output_rate));
+    xruns.push(output_xruns.clone());
+    let callback_xruns = output_xruns.clone();
+    let output_log = Arc::new(Mutex::new(logger.channel("output")));
+
+    // --- Build Output Stream ---
+    // The whole processing graph lives outside the stream so a rebuilt one
+    // carries on with it. Only the running stream's callback ever takes it
+    let render = Arc::new(Mutex::new(move |data: &mut [f32]| {
+        callback_xruns.record_callback((data.len() / output_channels) as u64);
+        render(data);
+    }));
+    let err_xruns = output_xruns.clone();
+    let dither = args.dither;
+    let output_realtime = realtime.clone();
+    let start_output = move |device: &Device| -> Result<cpal::Stream> {
+        // A replacement device has to run at the rate and channel count
+        // the graph was set up for
+        let format = device.default_output_config()?.sample_format();
+        let mut config = output_config.clone();
+        let render = render.clone();
+        let (err_xruns, output_log) = (err_xruns.clone(), output_log.clone());
+        let mut promoter = output_realtime.promoter("output", config.sample_rate, device);
+        sample_convert::build_output_stream(
+            device,
+            &mut config,
+            format,
+            dither,
+            move |data: &mut [f32]| {
+                promoter.promote(data.len() / output_channels);
+                match render.try_lock() {
+                    Ok(mut render) => render(data),
+                    Err(_) => data.fill(0.0),
+                }
+            },
+            move |err| {
+                err_xruns.record_error(&err);
+                if let Ok(mut log) = output_log.try_lock() {
+                    log.log(rtlog::Event::StreamError(err));
+                }
+            },
+        )
+    };
+    let output_stream = start_output(output_device).context(Failure::Devices)?;
+    supervisor.watch(
+        Direction::Output,
+        output_device,
+        output_stream,
+        output_xruns,
+        controls.fade.clone(),
+        Box::new(start_output),
+    );
+    // Watched after the output, which stays the first output stream that
+    // profiles switch
+    if let (Some(device), Some(output)) = (broadcast_device, broadcast) {
+        let stream = output.start(device).context(Failure::Devices)?;
+        xruns.push(output.xruns());
+        xruns.push(output.ring_xruns());
+        buffers.push(output.jitter());
+        supervisor.watch(
+            Direction::Output,
+            device,
+            stream,
+            output.xruns(),
+            output.fade(),
+            Box::new(move |device| output.start(device)),
+        );
+    }
+
+    // Installed only now so Ctrl-C still kills the device prompts at once
+    shutdown::install();
+    println!("\nStreaming started... Type 'help' for mixer commands, 'quit' or Ctrl-C to exit.");
+    supervisor.play()?;
+
+    // A device may take a fixed size and still call back with another, so
+    // report what the streams actually settled on
+    std::thread::sleep(Duration::from_millis(500));
+    for stats in &xruns {
+        info!("{}", stats.describe_callbacks(buffer_size));
+    }
+    let largest = xruns
+        .iter()
+        .filter_map(|stats| stats.callback_range())
+        .map(|(_, max)| max)
+        .max();
+    if args.low_latency
+        && let Some(largest) = largest
+        && largest > buffer_size as u64
+    {
+        warn!(
+            "Low latency was not granted, callbacks run up to {} frames ({:.1} ms)",
+            largest,
+            largest as f32 * 1000.0 / output_rate as f32
+        );
+        if wasapi {
+            warn!(
+                "WASAPI shared mode can't go below the Windows audio engine period, \
+                 lower it in the device's driver settings or try `--host asio`"
+            );
+        }
+    }
+
+    // Keep the main thread alive while streaming, taking mixer commands
+    controls.xruns = xruns;
+    controls.realtime = realtime;
+    controls.buffers = buffers;
+    controls.params.clips().add_inputs(clips);
+    controls.params.headroom().add_inputs(levels);
+    start_profile(&mut controls, profiles, args)?;
+    run_controls(&mut controls, args, Some(&mut supervisor))?;
+
+    // Ctrl-C and SIGTERM end up here as well. Stop the streams first so
+    // the recorders see the end of their input
+    supervisor.stop();
+    receivers.into_iter().for_each(RtpReceiver::stop);
+    finish(controls, recorders, rtp, icecast, snapcast, logger)
+}
+
+/// Runs the mixer and effects as a JACK client, with ports in place of
+/// devices. JACK runs everything on its one clock, so there is nothing to
+/// resample or buffer.
+#[cfg(feature = "jack")]
+fn run_jack(args: &Args, profiles: Vec<Profile>) -> Result<()> {
+    let channels = args.jack_channels;
+    let connect = || jack_client::connect("live_dsp", args.jack_inputs, channels);
+    let (setup, inputs) = if args.daemon {
+        // The JACK server may start after the daemon at boot
+        match service::wait_for("the JACK server", args.device_wait(), connect)? {
+            Some(client) => client,
+            None => return Ok(()),
+        }
+    } else {
+        connect().context(Failure::Devices)?
+    };
+    info!(
+        "JACK: {} Hz, {} frames per period, {} mixer channels of {} ports",
+        setup.sample_rate,
+        setup.buffer_size,
+        inputs.len(),
+        channels
+    );
+
+    let logger = rtlog::Logger::spawn()?;
+    let mut sources: Vec<(Box<dyn Source>, f32)> = inputs
+        .into_iter()
+        .map(|input| (Box::new(input) as Box<dyn Source>, 0.0))
+        .collect();
+    // Network inputs run on their sender's clock, so unlike the ports
+    // they are resampled and buffered
+    let settings = network_source_settings(args, setup.sample_rate, channels, setup.buffer_size);
+    let (receivers, taps) = start_rtp_inputs(args, &settings)?;
+    let mut buffers = Vec::new();
+    let mut input_xruns = Vec::new();
+    let mut clips = Vec::new();
+    let mut levels = Vec::new();
+    for tap in taps {
+        input_xruns.push(tap.xruns());
+        buffers.push(tap.jitter());
+        clips.push(tap.clips());
+        levels.push(tap.levels());
+        sources.push((Box::new(tap), 0.0));
+    }
+    let Engine {
+        render,
+        mut controls,
+        recorders,
+        rtp,
+        icecast,
+        snapcast,
+    } = build_engine(
+        sources,
+        None,
+        setup.sample_rate,
+        channels,
+        jack_client::CHUNK_FRAMES,
+        args,
+    )?;
+    let xruns = Arc::new(XrunStats::new("jack".to_string(), setup.sample_rate));
+    let session = setup.activate(render, xruns.clone(), &logger)?;
+
+    shutdown::install();
     println!(
-        "Input:  {} Hz, {} channels, buffer size {:?}",
-        input_config.sample_rate, input_config.channels, input_config.buffer_size
+        "\nJACK client running, connect its ports in qjackctl or Carla. \
+         Type 'help' for mixer commands, 'quit' or Ctrl-C to exit."
     );
+    controls.xruns = vec![xruns];
+    controls.xruns.extend(input_xruns);
+    controls.buffers = buffers;
+    controls.params.clips().add_inputs(clips);
+    controls.params.headroom().add_inputs(levels);
+    start_profile(&mut controls, profiles, args)?;
+    run_controls(&mut controls, args, None)?;
+
+    session.stop();
+    receivers.into_iter().for_each(RtpReceiver::stop);
+    finish(controls, recorders, rtp, icecast, snapcast, logger)
+}
+
+#[cfg(not(feature = "jack"))]
+fn run_jack(_args: &Args, _profiles: Vec<Profile>) -> Result<()> {
+    bail!("JACK client mode needs `--features jack`")
+}
+
+/// Runs the mixer and effects as PipeWire nodes: the capture node becomes
+/// the one mixer channel, the playback node carries the output.
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+fn run_pipewire(args: &Args, profiles: Vec<Profile>) -> Result<()> {
+    let rate = pipewire_node::SAMPLE_RATE;
+    let channels = 2;
+    let target = match args.target_latency {
+        Some(ms) => ((ms * rate as f32 / 1000.0) as u32).max(1),
+        None => 1024,
+    };
+    let (setup, input, jitter) = pipewire_node::open(channels, target);
+
+    let logger = rtlog::Logger::spawn()?;
+    let mut sources: Vec<(Box<dyn Source>, f32)> = vec![(Box::new(input), 0.0)];
+    // Sized for PipeWire's default quantum
+    let settings = network_source_settings(args, rate, channels, 1024);
+    let (receivers, taps) = start_rtp_inputs(args, &settings)?;
+    let mut buffers = vec![jitter];
+    let mut input_xruns = Vec::new();
+    let mut clips = Vec::new();
+    let mut levels = Vec::new();
+    for tap in taps {
+        input_xruns.push(tap.xruns());
+        buffers.push(tap.jitter());
+        clips.push(tap.clips());
+        levels.push(tap.levels());
+        sources.push((Box::new(tap), 0.0));
+    }
+    let Engine {
+        render,
+        mut controls,
+        recorders,
+        rtp,
+        icecast,
+        snapcast,
+    } = build_engine(
+        sources,
+        None,
+        rate,
+        channels,
+        pipewire_node::CHUNK_FRAMES,
+        args,
+    )?;
+    let xruns = Arc::new(XrunStats::new("pipewire".to_string(), rate));
+    let session = setup.start(render, xruns.clone())?;
+
+    shutdown::install();
     println!(
-        "Output: {} Hz, {} channels, buffer size {:?}",
-        output_config.sample_rate, output_config.channels, output_config.buffer_size
+        "\nPipeWire nodes running, relink them in qpwgraph or Helvum. \
+         Type 'help' for mixer commands, 'quit' or Ctrl-C to exit."
     );
+    controls.xruns = vec![xruns];
+    controls.xruns.extend(input_xruns);
+    controls.buffers = buffers;
+    controls.params.clips().add_inputs(clips);
+    controls.params.headroom().add_inputs(levels);
+    start_profile(&mut controls, profiles, args)?;
+    run_controls(&mut controls, args, None)?;
 
-    // Create a Ring Buffer with a capacity of 2x the buffer size to prevent underruns/overruns
-    // We transfer f32 samples.
-    let L_ring_buffer = HeapRb::<f32>::new(buffer_size as usize * 2);
-    let R_ring_buffer = HeapRb::<f32>::new(buffer_size as usize * 2);
-    let (mut L_producer, mut L_consumer) = L_ring_buffer.split();
-    let (mut R_producer, mut R_consumer) = R_ring_buffer.split();
-
-    // --- Build Input Stream ---
-    // We assume the input might be Mono or Stereo, but we only want to extract 1 channel to send.
-    let input_channels = input_config.channels as usize;
-    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
-
-    let default_input_config = input_device.default_input_config()?;
-    match default_input_config.sample_format() {
-        cpal::SampleFormat::F32 => println!("Have F32"),
-        cpal::SampleFormat::I16 => println!("Have I16"),
-        other => println!("Have this {}", other),
-    }
-    let input_stream = match input_device.default_input_config()?.sample_format() {
-        cpal::SampleFormat::F32 => input_device.build_input_stream(
-            &input_config,
-            move |data: &[f32], _: &_| {
-                println!("Have f32 input data ({}), data len: {}", input_channels, data.len());
-                // If input is empty, nothing to do
-                if data.is_empty() {
-                    return;
-                }
+    session.stop();
+    receivers.into_iter().for_each(RtpReceiver::stop);
+    finish(controls, recorders, rtp, icecast, snapcast, logger)
+}
 
-                // data is interleaved [L, R, L, R...]
-                // We iterate by frames (chunks of channel count)
-                if input_channels == 2 {
-                    for frame in data.chunks(2) {
-                        if let Err(_) = L_producer.try_push(frame[0]) {
-                            eprintln!("L producer full");
-                        }
-                        if let Err(_) = R_producer.try_push(frame[1]) {
-                            eprintln!("R producer full");
-                        }
-                    }
-                } else if input_channels == 1 {
-                    for sample in data.iter() {
-                        if let Err(_) = L_producer.try_push(*sample) {
-                            eprintln!("L producer full");
-                        }
-                        if let Err(_) = R_producer.try_push(*sample) {
-                            eprintln!("R producer full");
-                        }
-                    }
-                } else {
-                    panic!("What the fuck are these input channels: {}", input_channels);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => input_device.build_input_stream(
-            &input_config,
-            move |data: &[i16], _: &_| {
-                panic!("Have i16 input data");
-                // if data.is_empty() {
-                //     return;
-                // }
-                // for frame in data.chunks(input_channels) {
-                //     // Convert i16 to f32 range [-1.0, 1.0]
-                //     let sample = (frame[0] as f32) / i16::MAX as f32;
-                //     let _ = L_producer.try_push(sample);
-                // }
-            },
-            err_fn,
-            None,
-        )?,
-        f => anyhow::bail!("Unsupported input format: {:?}", f),
+#[cfg(not(all(target_os = "linux", feature = "pipewire")))]
+fn run_pipewire(_args: &Args, _profiles: Vec<Profile>) -> Result<()> {
+    bail!("PipeWire mode needs Linux and `--features pipewire`")
+}
+
+/// Source settings for network inputs in `jack` and `pipewire` mode, where
+/// there is no device buffer size or resampler quality to go by.
+#[cfg(any(feature = "jack", all(target_os = "linux", feature = "pipewire")))]
+fn network_source_settings(
+    args: &Args,
+    output_rate: u32,
+    output_channels: usize,
+    buffer_size: u32,
+) -> SourceSettings<'static> {
+    let target_latency = match args.target_latency {
+        Some(ms) => ((ms * output_rate as f32 / 1000.0) as u32).max(1),
+        None => buffer_size,
     };
+    SourceSettings {
+        output_rate,
+        output_channels,
+        buffer_size,
+        target_latency,
+        drift_compensation: args.drift_compensation,
+        map: None,
+        quality: Quality::High,
+        crossfade: args.crossfade_length(),
+    }
+}
 
-    // --- Build Output Stream ---
-    let output_channels = output_config.channels as usize;
-    let output_stream = match output_device.default_output_config()?.sample_format() {
-        cpal::SampleFormat::F32 => output_device.build_output_stream(
-            &output_config,
-            move |data: &mut [f32], _: &_| {
-                println!("filling f32 output data ({}), data len: {}", output_channels, data.len());
-                // for frame in data.chunks_mut(output_channels) {
-                //     // Try to get a sample from the ringbuffer, otherwise silence
-                //     let sample = left_consumer.try_pop().unwrap_or(0.0);
-                //     println!("Have f32 sample: {}", sample);
-
-                //     // Copy that single sample to ALL output channels (e.g. Left and Right)
-                //     for out_sample in frame.iter_mut() {
-                //         *out_sample = sample;
-                //     }
-                // }
-
-                // data is interleaved [L, R, L, R...]
-                // We iterate by frames (chunks of channel count)
-                if output_channels == 2 {
-                    for frame in data.chunks_mut(2) {
-                        frame[0] = L_consumer.try_pop().unwrap_or_else(|| {
-                            eprintln!("L consumer empty");
-                            0.0
-                        });
-                        frame[1] = R_consumer.try_pop().unwrap_or_else(|| {
-                            eprintln!("R consumer empty");
-                            0.0
-                        });
-                    }
-                } else if output_channels == 1 {
-                    for sample in data.iter_mut() {
-                        *sample = L_consumer.try_pop().unwrap_or_else(|| {
-                            eprintln!("L consumer empty");
-                            0.0
-                        });
-                        R_consumer.try_pop().unwrap_or_else(|| {
-                            eprintln!("R consumer empty");
-                            0.0
-                        });
-                    }
-                } else {
-                    panic!("What the fuck are these input channels: {}", input_channels);
+/// Starts a receiver for every `--rtp-in`, returning the taps to mix.
+fn start_rtp_inputs(
+    args: &Args,
+    settings: &SourceSettings,
+) -> Result<(Vec<RtpReceiver>, Vec<SourceTap>)> {
+    let mut receivers = Vec::new();
+    let mut taps = Vec::new();
+    for input in &args.rtp_inputs {
+        let (receiver, tap) = RtpReceiver::start(input, settings)?;
+        receivers.push(receiver);
+        taps.push(tap);
+    }
+    Ok((receivers, taps))
+}
+
+/// Renders one interleaved buffer of output.
+pub type Render = Box<dyn FnMut(&mut [f32]) + Send>;
+
+/// Everything between the sources and the output: mixer, effects, meters,
+/// recorders and the network stream.
+struct Engine {
+    render: Render,
+    /// Handles for the console, still without stream statistics.
+    controls: Controls,
+    recorders: Vec<Recorder>,
+    rtp: Option<RtpSender>,
+    icecast: Option<IcecastStream>,
+    snapcast: Option<SnapcastServer>,
+}
+
+/// Builds the mixer and the output processing around `sources`, adding the
+/// file players and generator asked for. Prompts for the reverb. A
+/// `broadcast` feed gets the broadcast bus. `max_frames` is the most the
+/// backend renders in one call.
+fn build_engine(
+    mut sources: Vec<(Box<dyn Source>, f32)>,
+    broadcast: Option<StreamFeed>,
+    output_rate: u32,
+    output_channels: usize,
+    max_frames: usize,
+    args: &Args,
+) -> Result<Engine> {
+    let sample_rate = output_rate as f32;
+    // Every processor parameter lands in the store so it can be changed
+    // from the console while streaming
+    let params = Arc::new(ParamStore::new());
+    // Peaks around every processor bound from here on, for the summary
+    if args.headroom {
+        params.headroom().enable();
+    }
+    // Followed by the tempo-synced effects, set with `tempo` or `tap`
+    let tempo = Arc::new(Tempo::new(args.bpm, &params));
+
+    // --- Input Recording ---
+    // Every input raw, copied as the mixer reads it and before the
+    // conditioning
+    let mut input_recorders = Vec::new();
+    if args.record_input
+        && let Some(settings) = args.record_settings()
+    {
+        sources = sources
+            .into_iter()
+            .enumerate()
+            .map(|(index, (source, gain_db))| {
+                let input = recorder::RecordSettings {
+                    path: recorder::input_path(&settings, index + 1),
+                    ..settings.clone()
+                };
+                let (recorder, tap) = Recorder::start(&input, output_rate, output_channels)?;
+                info!(
+                    "Recording input {} raw to {}",
+                    index + 1,
+                    input.path.display()
+                );
+                input_recorders.push(recorder);
+                let source: Box<dyn Source> = Box::new(RecordedSource::new(source, tap));
+                Ok((source, gain_db))
+            })
+            .collect::<Result<_>>()?;
+    }
+
+    // --- Input Conditioning ---
+    // DC and rumble come off every input before anything else sees it,
+    // with the settings as `in1.highpass.frequency` and so on
+    if args.dc_block || args.highpass.is_some() {
+        sources = sources
+            .into_iter()
+            .enumerate()
+            .map(|(index, (source, gain_db))| {
+                let mut chain = DspChain::new();
+                chain.push(DcBlocker::new(sample_rate));
+                if let Some(frequency) = args.highpass {
+                    chain.push(HighPass::new(frequency, sample_rate));
                 }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => output_device.build_output_stream(
-            &output_config,
-            move |data: &mut [i16], _: &_| {
-                panic!("filling i16 output data");
-                // for frame in data.chunks_mut(output_channels) {
-                //     let sample_f32 = left_consumer.try_pop().unwrap_or(0.0);
-                //     let sample_i16 = (sample_f32 * i16::MAX as f32) as i16;
-                //     println!("Have i16 sample: {}", sample_i16);
-
-                //     for out_sample in frame.iter_mut() {
-                //         *out_sample = sample_i16;
-                //     }
-                // }
-            },
-            err_fn,
-            None,
-        )?,
-        f => anyhow::bail!("Unsupported output format: {:?}", f),
+                chain.bind_params(&format!("in{}.", index + 1), &params);
+                chain.prepare(output_channels);
+                let source: Box<dyn Source> = Box::new(ProcessedSource::new(source, chain));
+                (source, gain_db)
+            })
+            .collect();
+    }
+
+    // --- Echo Cancellation ---
+    // The mic channel has what the speakers play taken out, fed back from
+    // the end of the output processing
+    #[cfg(feature = "aec")]
+    let mut echo_reference = None;
+    #[cfg(feature = "aec")]
+    if let Some(channel) = args.aec {
+        if channel == 0 || channel > sources.len() {
+            bail!(
+                "--aec {} isn't an input channel, there are {}",
+                channel,
+                sources.len()
+            );
+        }
+        let (source, gain_db) = sources.remove(channel - 1);
+        let (canceller, reference) = echo::cancel(source, output_rate)?;
+        sources.insert(channel - 1, (Box::new(canceller), gain_db));
+        echo_reference = Some(reference);
+    }
+    #[cfg(not(feature = "aec"))]
+    if args.aec.is_some() {
+        bail!("Echo cancellation needs `--features aec` and libspeexdsp");
+    }
+
+    // --- File Players ---
+    // Backing tracks become mixer channels after the inputs
+    let mut players = Vec::new();
+    for path in &args.play {
+        let (player, transport) = player::open(path, output_rate, output_channels, Quality::High)?;
+        transport.set_looping(args.play_loop);
+        sources.push((Box::new(player), 0.0));
+        players.push(transport);
+    }
+
+    // --- Aux Buses ---
+    // Shared effect returns, fed by per-channel sends. The effects run
+    // fully wet, the return level sets how much is heard
+    let mut aux_buses = Vec::new();
+    for (index, &effect) in args.aux.iter().enumerate() {
+        let mut chain = DspChain::new();
+        match effect {
+            AuxEffect::Reverb => {
+                let mut reverb = Reverb::new(sample_rate);
+                reverb.set_mix(1.0);
+                chain.push(reverb);
+            }
+            AuxEffect::Delay => {
+                let mut delay = Delay::new(sample_rate, tempo.param());
+                delay.set_mix(1.0);
+                chain.push(delay);
+            }
+        }
+        chain.bind_params(&format!("aux{}.", index + 1), &params);
+        aux_buses.push((format!("aux{} {}", index + 1, effect.name()), chain));
+    }
+
+    // --- Signal Generator ---
+    // Test signal channel, starts at -18 dBFS with its controls in the
+    // parameter store
+    if let Some(waveform) = args.generator {
+        let generator = SignalGenerator::new(waveform, sample_rate, &params);
+        sources.push((Box::new(generator), 0.0));
+    }
+
+    // --- Metronome ---
+    // Click channel on the engine tempo, for the monitors and not the
+    // audience
+    let mut metronome_channel = None;
+    if let Some(signature) = args.metronome {
+        let metronome = Metronome::new(
+            signature,
+            args.metronome_sound,
+            sample_rate,
+            tempo.param(),
+            &params,
+        );
+        metronome_channel = Some(sources.len());
+        sources.push((Box::new(metronome), 0.0));
+    }
+
+    // --- Looper ---
+    // Records and plays back on its channel, ahead of the fader, worked
+    // with `looper rec`, `undo` and `clear`
+    if let Some(channel) = args.looper {
+        if channel > sources.len() {
+            bail!(
+                "--looper {} isn't a mixer channel, there are {}",
+                channel,
+                sources.len()
+            );
+        }
+        let (source, gain_db) = sources.remove(channel - 1);
+        let mut chain = DspChain::new();
+        chain.push(Looper::new(sample_rate));
+        chain.bind_params("", &params);
+        chain.prepare(output_channels);
+        let source: Box<dyn Source> = Box::new(ProcessedSource::new(source, chain));
+        sources.insert(channel - 1, (source, gain_db));
+        info!("Looper on channel {}", channel);
+    }
+
+    let (mut mixer, mixer_controls, aux_controls) = Mixer::new(
+        sources,
+        aux_buses,
+        args.pan_law,
+        sample_rate,
+        output_channels,
+    );
+    mixer.prepare(max_frames, output_channels);
+    if let Some(channel) = metronome_channel {
+        mixer_controls[channel].set_on_air(false);
+        info!(
+            "Metronome on channel {}, off the broadcast mix, `metronome` to start and stop",
+            channel + 1
+        );
+    }
+    let (mut master, master_controls) = MasterBus::new();
+
+    // --- Broadcast Bus ---
+    // A second mix of the same channels for the audience, with a chain of
+    // its own in place of the main one
+    let mut broadcast_bus = None;
+    let mut broadcast_controls = None;
+    if let Some(feed) = broadcast {
+        mixer.enable_broadcast(output_channels);
+        let mut chain = match &args.broadcast_chain {
+            Some(path) => {
+                let entries = chain::load(path).context(Failure::Config)?;
+                let feedback = Arc::new(FeedbackView::default());
+                chain::Builder::new(sample_rate, feedback, tempo.param(), mixer.keys())
+                    .build(&entries, output_channels)
+                    .context(Failure::Config)?
+            }
+            None => DspChain::new(),
+        };
+        chain.bind_params("broadcast.", &params);
+        chain.prepare(output_channels);
+        let limiter = Limiter::new(sample_rate, args.limiter_ceiling, args.limiter_lookahead);
+        let (bus, controls) = BroadcastBus::new(chain, limiter, feed, sample_rate, output_channels);
+        info!("Broadcast chain: {}", bus.names().join(" -> "));
+        broadcast_bus = Some(bus);
+        broadcast_controls = Some(controls);
+    }
+
+    // --- Ducking and Auto-mix ---
+    // Channels turned down by others, tuned with `duck.` and `automix.`
+    if let Some((key, ducked)) = args.duck.clone() {
+        info!(
+            "Ducking channels {} under channel {}",
+            ducked
+                .iter()
+                .map(|channel| (channel + 1).to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            key + 1
+        );
+        mixer.enable_ducking(key, ducked, &params)?;
+    }
+    if let Some(channels) = args.automix.clone() {
+        info!("Auto-mixing {} mics", channels.len());
+        mixer.enable_automix(channels, &params)?;
+    }
+
+    // --- Talkback ---
+    // Switched from the console, MIDI or the TUI through its parameters
+    if let Some(channel) = args.talkback {
+        mixer.enable_talkback(channel - 1, args.talkback_bus, &params)?;
+        info!(
+            "Talkback: channel {} to the {} bus, `talk` to talk",
+            channel,
+            match args.talkback_bus {
+                TalkbackBus::Monitor => "monitor",
+                TalkbackBus::Broadcast => "broadcast",
+            }
+        );
+    }
+
+    // --- Tuner ---
+    // Reads the channel it's set to on its own thread, moved between
+    // channels from the console, MIDI or the TUI
+    let (tuner_tap, tuner) = tuner::spawn(sample_rate, args.tuner_reference)?;
+    if let Some(channel) = args.tuner {
+        if channel > mixer_controls.len() {
+            bail!(
+                "--tuner {} isn't a mixer channel, there are {}",
+                channel,
+                mixer_controls.len()
+            );
+        }
+        tuner.set_channel(Some(channel - 1));
+        info!("Tuner on channel {}", channel);
+    }
+    mixer.enable_tuner(tuner_tap);
+
+    // --- Scope ---
+    // The waveform of the channel it's set to, for the TUI
+    let (scope_tap, scope) = scope::spawn(sample_rate)?;
+    if let Some(channel) = args.scope {
+        if channel > mixer_controls.len() {
+            bail!(
+                "--scope {} isn't a mixer channel, there are {}",
+                channel,
+                mixer_controls.len()
+            );
+        }
+        scope.set_channel(Some(channel - 1));
+        info!("Scope on channel {}", channel);
+    }
+    mixer.enable_scope(scope_tap);
+
+    // --- Transfer function ---
+    // Compares a reference channel with a measurement mic on its own
+    // thread, for tuning the PA
+    let (transfer_tap, transfer) = transfer::spawn(sample_rate)?;
+    if let Some((reference, channel)) = args.transfer {
+        if let Some(&outside) = [reference, channel]
+            .iter()
+            .find(|&&c| c > mixer_controls.len())
+        {
+            bail!(
+                "--transfer {} isn't a mixer channel, there are {}",
+                outside,
+                mixer_controls.len()
+            );
+        }
+        transfer.set_channels(Some((reference - 1, channel - 1)));
+        info!(
+            "Transfer function from channel {} to channel {}",
+            reference, channel
+        );
+    }
+    mixer.enable_transfer(transfer_tap);
+
+    // --- DSP Chain ---
+    // Runs in the output callback on the interleaved output buffer. A
+    // chain file is rebuilt on `reload` or when it changes and swapped in
+    // between callbacks
+    let feedback = Arc::new(FeedbackView::default());
+    let chain_settings = ChainSettings {
+        sample_rate,
+        params: &params,
+        tempo: tempo.param(),
+        keys: mixer.keys(),
+    };
+    let (mut chain, reloader): (Box<dyn Processor>, _) = match &args.chain {
+        Some(path) => {
+            if !args.plugins.is_empty()
+                || !args.lv2.is_empty()
+                || args.denoise
+                || args.feedback
+                || args.agc
+                || args.deesser
+                || args.midside
+            {
+                bail!(
+                    "--plugin, --lv2, --denoise, --feedback, --agc, --deesser and --midside can't be used with --chain, add their lines to the file"
+                );
+            }
+            let (reloader, chain) =
+                ChainReloader::load(path, "", output_channels, &chain_settings, feedback.clone())
+                    .context(Failure::Config)?;
+            info!("DSP chain: {}", chain.names().join(" -> "));
+            (Box::new(chain), Some(reloader))
+        }
+        None => {
+            let mut chain = prompt_chain(args, sample_rate, output_channels, &feedback)?;
+            info!("DSP chain: {}", chain.names().join(" -> "));
+            chain.bind_params("", &params);
+            (Box::new(chain), None)
+        }
     };
 
-    println!("\nStreaming started... Press Enter to exit.");
-    input_stream.play()?;
-    output_stream.play()?;
+    // A second chain file next to the first, for comparing the two on the
+    // same input. `ab` switches, its parameters are under `b.`
+    let mut reloader_b = None;
+    if let Some(path) = &args.chain_b {
+        let (reloader, chain_b) = ChainReloader::load(
+            path,
+            "b.",
+            output_channels,
+            &chain_settings,
+            Arc::new(FeedbackView::default()),
+        )
+        .context(Failure::Config)?;
+        info!(
+            "DSP chain B: {}, `ab` switches between the chains",
+            chain_b.names().join(" -> ")
+        );
+        let mut ab = AbChain::new(chain, Box::new(chain_b), sample_rate, &params);
+        ab.prepare(output_channels);
+        chain = Box::new(ab);
+        reloader_b = Some(reloader);
+    }
+
+    if chain.latency() > 0 {
+        info!(
+            "DSP chain latency: {} samples ({:.1} ms)",
+            chain.latency(),
+            chain.latency() as f32 * 1000.0 / sample_rate
+        );
+    }
+
+    // Graphic EQ and multiband compression on the master bus, after the
+    // fader so they see the level being mastered
+    let mut mastering = DspChain::new();
+    if args.geq {
+        mastering.push(GraphicEq::new(sample_rate));
+        info!("Master graphic EQ: 31 bands");
+    }
+    if let Some(bands) = args.multiband {
+        mastering.push(Multiband::new(bands, sample_rate));
+        info!("Master multiband compressor: {} bands", bands);
+    }
+    mastering.bind_params("master.", &params);
+    mastering.prepare(output_channels);
+
+    // Soft clipper ahead of the limiter, so overs it lets through are
+    // rounded rather than squared off
+    let mut softclip = DspChain::new();
+    if args.softclip {
+        softclip.push(Saturation::new(Curve::Cubic, sample_rate));
+        softclip.bind_params("output.", &params);
+        softclip.prepare(output_channels);
+        info!("Output soft clipper: cubic");
+    }
+
+    // Speaker management, splitting the bus onto the output channels ahead
+    // of the limiter so it protects every box
+    let mut speakers = DspChain::new();
+    if let Some(spec) = &args.speakers {
+        let layout = SpeakerOutput::parse_list(spec, output_channels).context(Failure::Config)?;
+        for output in &layout {
+            info!("Speaker output {}", output.describe());
+        }
+        speakers.push(Speakers::new(&layout, sample_rate));
+        speakers.bind_params("", &params);
+        speakers.prepare(output_channels);
+    }
 
-    // Keep the main thread alive while streaming
-    let mut _input = String::new();
-    io::stdin().read_line(&mut _input)?;
+    // Room correction, after the speaker split so a filter with a channel
+    // per output corrects each box
+    let mut correction = DspChain::new();
+    if let Some(path) = &args.fir {
+        correction.push(FirFilter::load(path, sample_rate).context(Failure::Config)?);
+        correction.bind_params("output.", &params);
+        correction.prepare(output_channels);
+    }
+
+    // Time alignment per output channel, ahead of the limiter as reading
+    // between samples can overshoot a peak
+    let mut alignment = DspChain::new();
+    if !args.align.is_empty() {
+        for (index, &(channel, ms)) in args.align.iter().enumerate() {
+            if args.align[..index]
+                .iter()
+                .any(|&(other, _)| other == channel)
+            {
+                bail!("--align is given twice for output {}", channel + 1);
+            }
+            if channel >= output_channels {
+                bail!(
+                    "--align uses output {} but the device has {} output channels",
+                    channel + 1,
+                    output_channels
+                );
+            }
+            info!("Output {} delayed {:.2} ms", channel + 1, ms);
+        }
+        alignment.push(Alignment::new(&args.align, sample_rate));
+        alignment.bind_params("output.", &params);
+        alignment.prepare(output_channels);
+    }
+
+    // Output stage safety limiter, after everything that can raise a peak,
+    // only the fade follows
+    let mut limiter = Limiter::new(sample_rate, args.limiter_ceiling, args.limiter_lookahead);
+    limiter.prepare(output_channels);
+    info!(
+        "Output limiter: {:.1} dBFS ceiling, {} samples lookahead",
+        args.limiter_ceiling,
+        limiter.latency()
+    );
+
+    // Closed while the output stream is rebuilt, so a switch fades
+    // through silence instead of clicking
+    let (mut fade, fade_control) = Fade::new(sample_rate, args.crossfade_length());
+
+    let (mut output_meter, output_levels) = Meter::new(sample_rate, output_channels);
+    let (mut loudness_meter, loudness) = LoudnessMeter::new(sample_rate, output_channels);
+    let (mut phase_meter, phase) = PhaseMeter::new(sample_rate, output_channels);
+    // Fed every analyzer frame, so it shares the spectrum's FFT settings
+    let spectrogram = args.spectrogram.as_ref().map(|path| {
+        Arc::new(Spectrogram::new(
+            path.clone(),
+            sample_rate,
+            args.spectrum.size,
+        ))
+    });
+    let (mut spectrum_tap, spectrum) =
+        spectrum::spawn(sample_rate, args.spectrum, spectrogram.clone())?;
+
+    // --- Recording ---
+    // Taps only copy into ring buffers, the files are written on their own
+    // threads
+    let mut recorders = input_recorders;
+    let mut record_tap = None;
+    let mut mix_record_tap = None;
+    if let Some(settings) = args.record_settings() {
+        let (recorder, tap) = Recorder::start(&settings, output_rate, output_channels)?;
+        info!(
+            "Recording output to {} ({})",
+            settings.path.display(),
+            settings.format.extension()
+        );
+        recorders.push(recorder);
+        record_tap = Some(tap);
+
+        if args.record_mix {
+            let mix = recorder::RecordSettings {
+                path: recorder::mix_path(&settings),
+                ..settings.clone()
+            };
+            let (recorder, mut tap) = Recorder::start(&mix, output_rate, output_channels)?;
+            // Held back by the chain, room correction, alignment and
+            // limiter latency so the mix and processed files line up
+            tap.delay(
+                chain.latency() + correction.latency() + alignment.latency() + limiter.latency(),
+            );
+            info!(
+                "Recording the mix before the chain to {}",
+                mix.path.display()
+            );
+            recorders.push(recorder);
+            mix_record_tap = Some(tap);
+        }
+    }
+
+    // --- Network Stream ---
+    // The processed output as RTP, packets are built and sent on their own
+    // thread
+    let mut rtp = None;
+    let mut rtp_tap = None;
+    if let Some(settings) = args.rtp_settings() {
+        let (sender, tap) = RtpSender::start(&settings, output_rate, output_channels)?;
+        info!(
+            "Streaming output over RTP to {} ({})",
+            settings.destination,
+            settings.codec.name()
+        );
+        rtp = Some(sender);
+        rtp_tap = Some(tap);
+    }
+    // And as an internet radio stream
+    let mut icecast = None;
+    let mut icecast_tap = None;
+    if let Some(settings) = args.icecast_settings() {
+        let (stream, tap) = IcecastStream::start(&settings, output_rate, output_channels)?;
+        info!(
+            "Streaming output to {} ({}, {} kbit/s)",
+            settings.server,
+            settings.codec.name(),
+            settings.bitrate_kbps
+        );
+        icecast = Some(stream);
+        icecast_tap = Some(tap);
+    }
+    // And to the players around the venue
+    let mut snapcast = None;
+    let mut snapcast_tap = None;
+    if let Some(settings) = args.snapcast_settings() {
+        let (server, tap) = SnapcastServer::start(&settings, output_rate, output_channels)?;
+        info!(
+            "Serving output to Snapcast clients on {} ({} ms buffer)",
+            settings.addr, settings.buffer_ms
+        );
+        snapcast = Some(server);
+        snapcast_tap = Some(tap);
+    }
+
+    // Time each callback takes against the audio it renders, and which
+    // stages it goes to
+    let load = Arc::new(CallbackLoad::new(output_rate));
+    let callback_load = load.clone();
+    let (mut timer, callback_times) = CallbackTimes::spawn(args.callback_profile.clone())?;
+    let render = move |data: &mut [f32]| {
+        timer.begin();
+        // data is interleaved [L, R, L, R...]
+        // Sum every source into the output buffer
+        mixer.process(data, output_channels);
+        if let Some(bus) = broadcast_bus.as_mut()
+            && let Some(mix) = mixer.broadcast(data.len())
+        {
+            bus.process(mix, output_channels);
+        }
+        if let Some(tap) = mix_record_tap.as_mut() {
+            tap.process(data);
+        }
+        timer.lap(Stage::Mix);
+
+        chain.process(data, output_channels);
+        timer.lap(Stage::Chain);
+        master.process(data, output_channels);
+        mastering.process(data, output_channels);
+        phase_meter.process(data, output_channels);
+        timer.lap(Stage::Master);
+        softclip.process(data, output_channels);
+        speakers.process(data, output_channels);
+        correction.process(data, output_channels);
+        alignment.process(data, output_channels);
+        limiter.process(data, output_channels);
+        fade.process(data, output_channels);
+        #[cfg(feature = "aec")]
+        if let Some(reference) = echo_reference.as_mut() {
+            reference.process(data, output_channels);
+        }
+        timer.lap(Stage::Output);
+        output_meter.process(data, output_channels);
+        loudness_meter.process(data, output_channels);
+        spectrum_tap.process(data, output_channels);
+        timer.lap(Stage::Meters);
+        if let Some(tap) = record_tap.as_mut() {
+            tap.process(data);
+        }
+        if let Some(tap) = rtp_tap.as_mut() {
+            tap.process(data);
+        }
+        if let Some(tap) = icecast_tap.as_mut() {
+            tap.process(data);
+        }
+        if let Some(tap) = snapcast_tap.as_mut() {
+            tap.process(data);
+        }
+        timer.lap(Stage::Taps);
+        callback_load.record(timer.end(), (data.len() / output_channels) as u64);
+    };
+
+    // Commands from MIDI, OSC and other threads, applied by the control
+    // loop
+    let (remote, requests) = mpsc::channel();
+    let controls = Controls {
+        channels: mixer_controls,
+        aux: aux_controls,
+        master: master_controls,
+        broadcast: broadcast_controls,
+        output: output_levels,
+        loudness,
+        phase,
+        spectrum,
+        spectrogram,
+        load,
+        callback_times,
+        realtime: Arc::new(Realtime::default()),
+        tuner,
+        scope,
+        transfer,
+        feedback,
+        params,
+        tempo,
+        players,
+        xruns: Vec::new(),
+        buffers: Vec::new(),
+        profiles: Vec::new(),
+        fade: fade_control,
+        remote,
+        requests,
+        active_profile: Arc::new(Mutex::new(None)),
+        scenes: Scenes::default(),
+        fades: Fades::default(),
+        cues: CueList::default(),
+        history: History::default(),
+        midi: None,
+        chain: reloader,
+        chain_b: reloader_b,
+    };
+    Ok(Engine {
+        render: Box::new(render),
+        controls,
+        recorders,
+        rtp,
+        icecast,
+        snapcast,
+    })
+}
+
+/// Asks for the default chain's reverb and adds the plugins from the
+/// command line, prepared for `output_channels`.
+fn prompt_chain(
+    args: &Args,
+    sample_rate: f32,
+    output_channels: usize,
+    feedback: &Arc<FeedbackView>,
+) -> Result<DspChain> {
+    let mut chain = DspChain::new();
+    if args.denoise {
+        chain.push(NoiseSuppressor::new(sample_rate));
+    }
+    if args.feedback {
+        chain.push(FeedbackSuppressor::new(sample_rate, feedback.clone()));
+    }
+    chain.push(NoiseGate::new(sample_rate));
+    if args.agc {
+        chain.push(AutoGain::new(sample_rate));
+    }
+    chain.push(Compressor::new(sample_rate));
+    if args.deesser {
+        chain.push(DeEsser::new(sample_rate));
+    }
+    if args.midside {
+        chain.push(MidSide::new(sample_rate));
+    }
+
+    println!(
+        "\nSelect reverb: [0] none, [1] convolution (impulse response WAV), [2] algorithmic. Default is: 0"
+    );
+    match read_answer(args.daemon)?.trim().parse().unwrap_or(0) {
+        1 => {
+            println!("Enter path to the impulse response WAV:");
+            let ir_path = read_answer(args.daemon)?;
+            chain.push(ConvolutionReverb::from_wav(
+                Path::new(ir_path.trim()),
+                sample_rate,
+            )?);
+        }
+        2 => chain.push(Reverb::new(sample_rate)),
+        _ => {}
+    }
+    #[cfg(feature = "clap")]
+    {
+        let mut host = dsp::ClapHost::new();
+        for (path, id) in &args.plugins {
+            chain.push(host.load(path, id.as_deref(), sample_rate)?);
+        }
+    }
+    #[cfg(not(feature = "clap"))]
+    if !args.plugins.is_empty() {
+        bail!("CLAP plugins need `--features clap`");
+    }
+    #[cfg(all(target_os = "linux", feature = "lv2"))]
+    if !args.lv2.is_empty() {
+        let host = dsp::Lv2Host::new()?;
+        for uri in &args.lv2 {
+            chain.push(host.load(uri, sample_rate, output_channels)?);
+        }
+    }
+    #[cfg(not(all(target_os = "linux", feature = "lv2")))]
+    if !args.lv2.is_empty() {
+        bail!("LV2 plugins need Linux and `--features lv2`");
+    }
+    chain.prepare(output_channels);
+    Ok(chain)
+}
+
+/// Hands the profiles, scenes and cues to the console and runs the
+/// commands of the profile started with, which can recall a scene.
+fn start_profile(controls: &mut Controls, profiles: Vec<Profile>, args: &Args) -> Result<()> {
+    controls.profiles = profiles;
+    if let Some(path) = &args.scenes {
+        controls.scenes = Scenes::load(path).context(Failure::Config)?;
+    }
+    if let Some(path) = &args.cues {
+        controls.cues = CueList::load(path).context(Failure::Config)?;
+    }
+    if let Some(name) = &args.profile {
+        let profile = profile::find(&controls.profiles, name)?;
+        println!("{}", profile::run_startup(profile, controls)?);
+    }
+    Ok(())
+}
+
+/// Takes mixer commands from the console, the TUI or a daemon's socket,
+/// and MIDI, OSC, HTTP, a script and chain file changes when asked for,
+/// until quit or a shutdown signal. Looks after the streams when there is
+/// a supervisor.
+fn run_controls(
+    controls: &mut Controls,
+    args: &Args,
+    supervisor: Option<&mut Supervisor>,
+) -> Result<()> {
+    #[cfg(feature = "midi")]
+    let midi = match &args.midi {
+        Some(port) => {
+            let session = midi::connect(port, args.midi_map.as_deref(), controls)?;
+            controls.midi = Some(session.learner());
+            Some(session)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "midi"))]
+    if args.midi.is_some() {
+        bail!("MIDI control needs `--features midi`");
+    }
+    let osc = match args.osc {
+        Some(port) => Some(osc::OscServer::spawn(port, controls.remote.clone())?),
+        None => None,
+    };
+    let http = match args.http {
+        Some(addr) => Some(http::HttpServer::spawn(
+            addr,
+            args.http_access.clone(),
+            http::View::new(controls),
+            controls.remote.clone(),
+        )?),
+        None => None,
+    };
+    // Saving the chain file reloads it like the `reload` command
+    let watcher = match (&args.chain, &controls.chain) {
+        (Some(path), Some(_)) => Some(chain::ChainWatcher::spawn(
+            path,
+            controls.remote.clone(),
+            !args.tui,
+        )?),
+        _ => None,
+    };
+    let watcher_b = match (&args.chain_b, &controls.chain_b) {
+        (Some(path), Some(_)) => Some(chain::ChainWatcher::spawn(
+            path,
+            controls.remote.clone(),
+            !args.tui,
+        )?),
+        _ => None,
+    };
+    #[cfg(feature = "script")]
+    let script = match &args.script {
+        Some(path) => Some(script::ScriptHost::spawn(path, controls)?),
+        None => None,
+    };
+    #[cfg(not(feature = "script"))]
+    if args.script.is_some() {
+        bail!("Scripts need `--features script`");
+    }
+    let controls = &*controls;
+
+    // Xruns are counted on the audio threads and printed from here. The
+    // TUI shows them itself, printing would tear its screen
+    let reporter = if args.xrun_report > 0 && !args.tui {
+        Some(xrun::Reporter::spawn(
+            controls.xruns.clone(),
+            Duration::from_secs(args.xrun_report as u64),
+        )?)
+    } else {
+        None
+    };
+
+    let ipc = if args.daemon {
+        Some(ipc::IpcServer::spawn(
+            &args.socket_path(),
+            controls.remote.clone(),
+        )?)
+    } else {
+        None
+    };
+
+    // Streams are running and the socket is up
+    service::notify("READY=1");
+    let result = if args.daemon {
+        control::run_headless(controls, supervisor)
+    } else if args.tui {
+        tui::run(controls, supervisor)
+    } else {
+        control::run_console(controls, supervisor)
+    };
+    service::notify("STOPPING=1");
+    if let Some(reporter) = reporter {
+        reporter.stop();
+    }
+    if let Some(ipc) = ipc {
+        ipc.stop();
+    }
+    if let Some(osc) = osc {
+        osc.stop();
+    }
+    if let Some(http) = http {
+        http.stop();
+    }
+    if let Some(watcher) = watcher {
+        watcher.stop();
+    }
+    if let Some(watcher) = watcher_b {
+        watcher.stop();
+    }
+    #[cfg(feature = "script")]
+    if let Some(script) = script {
+        script.stop();
+    }
+    #[cfg(feature = "midi")]
+    if let Some(midi) = midi {
+        midi.stop();
+    }
+    result
+}
+
+/// Finishes the recordings once the streams are stopped and prints the
+/// final statistics.
+fn finish(
+    controls: Controls,
+    recorders: Vec<Recorder>,
+    rtp: Option<RtpSender>,
+    icecast: Option<IcecastStream>,
+    snapcast: Option<SnapcastServer>,
+    logger: rtlog::Logger,
+) -> Result<()> {
+    for recorder in recorders {
+        recorder.finish()?;
+    }
+    if let Some(rtp) = rtp {
+        rtp.finish()?;
+    }
+    if let Some(icecast) = icecast {
+        icecast.finish()?;
+    }
+    if let Some(snapcast) = snapcast {
+        snapcast.finish()?;
+    }
+    logger.stop();
+    for stats in &controls.xruns {
+        println!("{}", stats.describe());
+    }
+    for stats in &controls.buffers {
+        println!("{}", stats.describe());
+    }
+    println!("{}", controls.load.describe());
+    controls.callback_times.finish()?;
+    if let Some(spectrogram) = &controls.spectrogram {
+        let length = spectrogram.write()?;
+        println!(
+            "Spectrogram of {:.0} s written to {}",
+            length.as_secs_f32(),
+            spectrogram.path().display()
+        );
+    }
+    if controls.params.headroom().is_enabled() {
+        println!("{}", controls.params.headroom().report());
+    }
 
     Ok(())
 }
@@ -0,0 +1,173 @@
+use crate::dsp::{gain_to_db, time_coefficient};
+use crate::params::AtomicF32;
+use std::sync::Arc;
+
+/// Bottom of the meter scale, anything quieter reads as this.
+pub const FLOOR_DB: f32 = -90.0;
+/// Peak fall-back rate once the signal drops.
+const PEAK_FALL_DB_PER_SECOND: f32 = 20.0;
+/// Integration time of the RMS detector.
+const RMS_WINDOW_MS: f32 = 300.0;
+/// How long the peak-hold marker stays up.
+const HOLD_SECONDS: f32 = 2.0;
+
+/// One channel reading in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    pub peak_db: f32,
+    pub rms_db: f32,
+    pub hold_db: f32,
+}
+
+#[derive(Debug)]
+struct ChannelLevels {
+    peak_db: AtomicF32,
+    rms_db: AtomicF32,
+    hold_db: AtomicF32,
+}
+
+/// Meter readings published by the audio thread, one per audio channel.
+/// Readers never block the writer, they just see the latest block.
+#[derive(Debug)]
+pub struct MeterLevels {
+    channels: Vec<ChannelLevels>,
+}
+
+impl MeterLevels {
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    pub fn read(&self, channel: usize) -> Level {
+        let levels = &self.channels[channel];
+        Level {
+            peak_db: levels.peak_db.get(),
+            rms_db: levels.rms_db.get(),
+            hold_db: levels.hold_db.get(),
+        }
+    }
+
+    pub fn levels(&self) -> impl Iterator<Item = Level> + '_ {
+        (0..self.channels.len()).map(|channel| self.read(channel))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MeterState {
+    peak_db: f32,
+    mean_square: f32,
+    hold_db: f32,
+    hold_left: f32,
+}
+
+/// Audio-thread side of a meter: peak with a linear fall-back, RMS over a
+/// 300 ms window and a peak hold.
+pub struct Meter {
+    levels: Arc<MeterLevels>,
+    sample_rate: f32,
+    rms_coeff: f32,
+    state: Vec<MeterState>,
+}
+
+impl Meter {
+    pub fn new(sample_rate: f32, channels: usize) -> (Self, Arc<MeterLevels>) {
+        let levels = Arc::new(MeterLevels {
+            channels: (0..channels)
+                .map(|_| ChannelLevels {
+                    peak_db: AtomicF32::new(FLOOR_DB),
+                    rms_db: AtomicF32::new(FLOOR_DB),
+                    hold_db: AtomicF32::new(FLOOR_DB),
+                })
+                .collect(),
+        });
+        let meter = Meter {
+            levels: levels.clone(),
+            sample_rate,
+            rms_coeff: time_coefficient(RMS_WINDOW_MS, sample_rate),
+            state: vec![
+                MeterState {
+                    peak_db: FLOOR_DB,
+                    mean_square: 0.0,
+                    hold_db: FLOOR_DB,
+                    hold_left: 0.0,
+                };
+                channels
+            ],
+        };
+        (meter, levels)
+    }
+
+    /// Measures one interleaved block and publishes the result.
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        let frames = buffer.len() / channels.max(1);
+        if frames == 0 {
+            return;
+        }
+        let seconds = frames as f32 / self.sample_rate;
+
+        for (channel, (state, published)) in self
+            .state
+            .iter_mut()
+            .zip(self.levels.channels.iter())
+            .enumerate()
+            .take(channels)
+        {
+            let mut block_peak = 0.0f32;
+            for sample in buffer[channel..].iter().step_by(channels) {
+                block_peak = block_peak.max(sample.abs());
+                state.mean_square =
+                    self.rms_coeff * state.mean_square + (1.0 - self.rms_coeff) * sample * sample;
+            }
+
+            let fallen = state.peak_db - PEAK_FALL_DB_PER_SECOND * seconds;
+            state.peak_db = level_db(block_peak).max(fallen).max(FLOOR_DB);
+
+            if state.peak_db >= state.hold_db {
+                state.hold_db = state.peak_db;
+                state.hold_left = HOLD_SECONDS;
+            } else {
+                state.hold_left -= seconds;
+                if state.hold_left <= 0.0 {
+                    state.hold_db = state.peak_db;
+                }
+            }
+
+            published.peak_db.set(state.peak_db);
+            published.rms_db.set(level_db(state.mean_square.sqrt()));
+            published.hold_db.set(state.hold_db);
+        }
+    }
+}
+
+fn level_db(gain: f32) -> f32 {
+    gain_to_db(gain).max(FLOOR_DB)
+}
+
+/// Renders a level as a text bar of `width` cells covering `floor_db..0`:
+/// RMS as `#`, peak as `=`, the hold as `|` and anything over 0 dBFS as a
+/// trailing `!`.
+pub fn bar(level: Level, floor_db: f32, width: usize) -> String {
+    let cells = |db: f32| {
+        let ratio = ((db - floor_db) / -floor_db).clamp(0.0, 1.0);
+        (ratio * width as f32).round() as usize
+    };
+    let rms = cells(level.rms_db);
+    let peak = cells(level.peak_db);
+    let hold = cells(level.hold_db);
+
+    let mut bar: String = (0..width)
+        .map(|cell| {
+            if cell < rms {
+                '#'
+            } else if cell < peak {
+                '='
+            } else if hold > 0 && cell == hold - 1 {
+                '|'
+            } else {
+                ' '
+            }
+        })
+        .collect();
+    bar.push(if level.hold_db >= 0.0 { '!' } else { ' ' });
+    bar
+}
@@ -0,0 +1,60 @@
+use std::fmt::Write;
+
+/// Prefix of every metric name.
+const NAMESPACE: &str = "live_dsp";
+
+/// Builds a page in the Prometheus text exposition format, as scraped from
+/// `GET /metrics`. Each family is declared once, then its samples follow.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the family `name`, a `counter`, `gauge` or `histogram`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {}_{} {}", NAMESPACE, name, help);
+        let _ = writeln!(self.text, "# TYPE {}_{} {}", NAMESPACE, name, kind);
+    }
+
+    /// Adds one sample of `name`, which is the family's name or, for a
+    /// histogram, one of its `_bucket`, `_sum` and `_count` series.
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.text, "{}_{}", NAMESPACE, name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", format_value(value));
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+/// Label values are quoted, so backslashes, quotes and line breaks in
+/// device names are escaped.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
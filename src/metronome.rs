@@ -0,0 +1,224 @@
+use crate::dsp::db_to_gain;
+use crate::params::{Param, ParamInfo, ParamStore};
+use crate::source::Source;
+use anyhow::{Context, Result, bail};
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+/// Longest a click rings for.
+const CLICK_SECONDS: f32 = 0.06;
+/// Beep pitches, the first beat of the bar higher.
+const BEEP_HZ: f32 = 1000.0;
+const ACCENT_BEEP_HZ: f32 = 1500.0;
+/// Decay time constants of the beep and the noise click.
+const BEEP_DECAY: f32 = 0.012;
+const CLICK_DECAY: f32 = 0.003;
+/// Attack ramp, long enough not to pop.
+const ATTACK_SECONDS: f32 = 0.001;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    /// Decaying sine, higher on the accent.
+    Beep,
+    /// Short noise burst, brighter on the accent.
+    Click,
+}
+
+impl Sound {
+    const ALL: [Sound; 2] = [Sound::Beep, Sound::Click];
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let sound = match text.trim() {
+            "beep" => Sound::Beep,
+            "click" => Sound::Click,
+            other => bail!(
+                "Unknown metronome sound '{}', expected beep or click",
+                other
+            ),
+        };
+        Ok(sound)
+    }
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|&s| s == self).unwrap_or(0)
+    }
+}
+
+/// Time signature: beats to the bar and the note value of a beat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats: u32,
+    pub unit: u32,
+}
+
+impl TimeSignature {
+    /// As written, e.g. 4/4 or 6/8.
+    pub fn parse(text: &str) -> Result<Self> {
+        let (beats, unit) = text
+            .trim()
+            .split_once('/')
+            .with_context(|| format!("Invalid time signature '{}', expected e.g. 4/4", text))?;
+        let beats: u32 = beats
+            .parse()
+            .ok()
+            .filter(|beats| (1..=16).contains(beats))
+            .with_context(|| format!("Beats to the bar must be 1 to 16, got {}", beats))?;
+        let unit: u32 = unit
+            .parse()
+            .ok()
+            .filter(|unit: &u32| unit.is_power_of_two() && *unit <= 16)
+            .with_context(|| format!("Beat unit must be 1, 2, 4, 8 or 16, got {}", unit))?;
+        Ok(TimeSignature { beats, unit })
+    }
+}
+
+/// Click track mixer channel following the engine tempo, so `tempo` and
+/// `tap` set its speed. The tempo counts quarter notes, a beat of 6/8 is
+/// an eighth. The first beat of the bar is accented by `accent` dB and
+/// sounds higher. Controls are `metronome.*` parameters: `on`, `beats`
+/// and `unit` of the time signature, `sound`, `level` and `accent`.
+pub struct Metronome {
+    sample_rate: f32,
+    tempo: Arc<Param>,
+    on: Arc<Param>,
+    beats: Arc<Param>,
+    unit: Arc<Param>,
+    sound: Arc<Param>,
+    level_db: Arc<Param>,
+    accent_db: Arc<Param>,
+
+    /// Beats since it was started, the fraction into the current one.
+    position: f64,
+    running: bool,
+    /// Samples into the click sounding, past its end when quiet.
+    elapsed: usize,
+    accented: bool,
+    rng: u32,
+    /// Last noise sample, the click's high-pass.
+    last_noise: f32,
+}
+
+impl Metronome {
+    /// Registers the metronome's controls in `store` as `metronome.*`.
+    pub fn new(
+        signature: TimeSignature,
+        sound: Sound,
+        sample_rate: f32,
+        tempo: Arc<Param>,
+        store: &ParamStore,
+    ) -> Self {
+        let param = |name: &'static str, value: f32, min: f32, max: f32| {
+            let info = ParamInfo::new(name, value, min, max);
+            let param = Arc::new(Param::new(format!("metronome.{}", name), &info));
+            store.add(param.clone());
+            param
+        };
+        let last = (Sound::ALL.len() - 1) as f32;
+        Metronome {
+            sample_rate,
+            tempo,
+            on: param("on", 1.0, 0.0, 1.0),
+            beats: param("beats", signature.beats as f32, 1.0, 16.0),
+            unit: param("unit", signature.unit as f32, 1.0, 16.0),
+            sound: param("sound", sound.index() as f32, 0.0, last),
+            level_db: param("level", -12.0, -60.0, 0.0),
+            accent_db: param("accent", 6.0, 0.0, 12.0),
+            position: 0.0,
+            running: false,
+            elapsed: usize::MAX,
+            accented: false,
+            rng: 0x9e37_79b9,
+            last_noise: 0.0,
+        }
+    }
+
+    fn sound(&self) -> Sound {
+        let index = self.sound.get().round() as usize;
+        Sound::ALL[index.min(Sound::ALL.len() - 1)]
+    }
+
+    /// Beat unit snapped to a power of two.
+    fn unit(&self) -> f64 {
+        self.unit.get().clamp(1.0, 16.0).log2().round().exp2() as f64
+    }
+
+    /// Uniform in `[-1, 1)`.
+    fn white(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+
+    /// The sounding click at `elapsed`, before the level.
+    fn click(&mut self, sound: Sound) -> f32 {
+        let t = self.elapsed as f32 / self.sample_rate;
+        let attack = (t / ATTACK_SECONDS).min(1.0);
+        match sound {
+            Sound::Beep => {
+                let frequency = if self.accented {
+                    ACCENT_BEEP_HZ
+                } else {
+                    BEEP_HZ
+                };
+                (TAU * frequency * t).sin() * (-t / BEEP_DECAY).exp() * attack
+            }
+            Sound::Click => {
+                // First difference of white noise, tilted up
+                let noise = self.white();
+                let bright = noise - self.last_noise;
+                self.last_noise = noise;
+                let sample = if self.accented {
+                    bright
+                } else {
+                    0.5 * (noise + bright)
+                };
+                sample * (-t / CLICK_DECAY).exp() * attack
+            }
+        }
+    }
+}
+
+impl Source for Metronome {
+    fn name(&self) -> &str {
+        "metronome"
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        // Started from the top of the bar, a stopped one lets its last
+        // click ring out
+        let on = self.on.get() >= 0.5;
+        if on && !self.running {
+            self.position = -1e-9;
+        }
+        self.running = on;
+
+        let sound = self.sound();
+        let beats = self.beats.get().round().clamp(1.0, 16.0) as u64;
+        let step = self.tempo.get() as f64 / 60.0 * self.unit() / 4.0 / self.sample_rate as f64;
+        let gain = db_to_gain(self.level_db.get());
+        let accent = db_to_gain(self.accent_db.get());
+        let length = (CLICK_SECONDS * self.sample_rate) as usize;
+
+        for frame in data.chunks_mut(channels) {
+            if self.running {
+                let next = self.position + step;
+                if next.floor() > self.position.floor() {
+                    self.elapsed = 0;
+                    self.accented = (next.floor() as u64).is_multiple_of(beats);
+                }
+                self.position = next;
+            }
+            let sample = if self.elapsed < length {
+                let level = if self.accented { gain * accent } else { gain };
+                let sample = self.click(sound) * level;
+                self.elapsed += 1;
+                sample
+            } else {
+                0.0
+            };
+            frame.iter_mut().for_each(|s| *s = sample);
+        }
+    }
+}
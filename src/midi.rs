@@ -0,0 +1,416 @@
+use crate::control::{Command, Controls, Learn, Request};
+use crate::mixer::SEND_OFF_DB;
+use crate::params::Param;
+use anyhow::{Context, Result, anyhow, bail};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// Fader range of a controller, the bottom of its travel is off.
+const FADER_MIN_DB: f32 = -60.0;
+const FADER_MAX_DB: f32 = 6.0;
+
+/// Controller message a mapping listens to. Channels count from 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Message {
+    Cc { channel: u8, number: u8 },
+    Note { channel: u8, number: u8 },
+    Program { channel: u8, number: u8 },
+}
+
+impl Message {
+    fn parse(text: &str) -> Result<Self> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let [kind, channel, number] = words.as_slice() else {
+            bail!("Expected '<cc|note|pc> <channel> <number>', got '{}'", text);
+        };
+        let channel: u8 = channel
+            .parse()
+            .with_context(|| format!("Invalid MIDI channel '{}'", channel))?;
+        if !(1..=16).contains(&channel) {
+            bail!("MIDI channels go from 1 to 16");
+        }
+        let number: u8 = number
+            .parse()
+            .with_context(|| format!("Invalid MIDI number '{}'", number))?;
+        if number > 127 {
+            bail!("MIDI numbers go from 0 to 127");
+        }
+        let message = match *kind {
+            "cc" => Message::Cc { channel, number },
+            "note" => Message::Note { channel, number },
+            "pc" => Message::Program { channel, number },
+            other => bail!("Unknown MIDI message '{}', expected cc, note or pc", other),
+        };
+        Ok(message)
+    }
+
+    /// Splits a raw message into what it is and its value, 0 for a note
+    /// off. Everything else a mapping can't use is `None`.
+    fn decode(bytes: &[u8]) -> Option<(Message, u8)> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = (status & 0x0F) + 1;
+        match (status & 0xF0, data) {
+            (0xB0, &[number, value]) => Some((Message::Cc { channel, number }, value)),
+            (0x90, &[number, velocity]) => Some((Message::Note { channel, number }, velocity)),
+            (0x80, &[number, _]) => Some((Message::Note { channel, number }, 0)),
+            (0xC0, &[number, ..]) => Some((Message::Program { channel, number }, 127)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Cc { channel, number } => write!(f, "cc {} {}", channel, number),
+            Message::Note { channel, number } => write!(f, "note {} {}", channel, number),
+            Message::Program { channel, number } => write!(f, "pc {} {}", channel, number),
+        }
+    }
+}
+
+/// What a mapped control does: a console command, either with its value
+/// taken from the control or fired as is.
+#[derive(Clone, Debug)]
+enum Target {
+    /// Gain, send or return level in dB, scaled as a fader.
+    Fader(Command),
+    Pan(Command),
+    /// Effect parameter, scaled over its range.
+    Param(Command, f32, f32),
+    /// Fired when a note is hit or a knob passes half way up.
+    Trigger(Command),
+    /// On while a note or button is held down, push-to-talk for `talk`.
+    Hold(Command),
+}
+
+impl Target {
+    /// `text` is a console command, leaving out the value for the ones
+    /// that take one: `gain 1`, `set reverb.mix`, `mute 2`, `profile live`.
+    fn parse(text: &str, params: &[Arc<Param>]) -> Result<Self> {
+        if let Ok(command) = Command::parse(text) {
+            return match command {
+                Command::Mute(..)
+                | Command::Solo(..)
+                | Command::Dim(_)
+                | Command::Mono(_)
+                | Command::ResetLoudness
+                | Command::ResetClips
+                | Command::ResetHeadroom
+                | Command::ResetCpu
+                | Command::Play(_)
+                | Command::Pause(_)
+                | Command::Stop(_)
+                | Command::Loop(_)
+                | Command::Looper(_)
+                | Command::Tap
+                | Command::SetTuner(_)
+                | Command::SetScope(_)
+                | Command::SetTransfer(_)
+                | Command::FindDelay
+                | Command::Metronome(_)
+                | Command::Scene(..)
+                | Command::Ab(_)
+                | Command::Undo
+                | Command::Redo
+                | Command::CueGo
+                | Command::Cue(_)
+                | Command::CueStart(_)
+                | Command::CueStop
+                | Command::CueReset
+                | Command::Profile(_) => Ok(Target::Trigger(command)),
+                Command::Talk(_) => Ok(Target::Hold(command)),
+                _ => bail!("'{}' can't be mapped to a MIDI control", text),
+            };
+        }
+        // Everything else takes its value from the control
+        let command = Command::parse(&format!("{} 0", text))
+            .with_context(|| format!("Invalid MIDI target '{}'", text))?;
+        match command {
+            Command::Gain(..) | Command::Send(..) | Command::Return(..) | Command::Master(_) => {
+                Ok(Target::Fader(command))
+            }
+            Command::Pan(..) => Ok(Target::Pan(command)),
+            Command::Set(ref key, _) => {
+                let param = params
+                    .iter()
+                    .find(|p| &p.key == key)
+                    .with_context(|| format!("No parameter '{}', see 'params'", key))?;
+                let (min, max) = (param.min, param.max);
+                Ok(Target::Param(command, min, max))
+            }
+            _ => bail!("'{}' can't be mapped to a MIDI control", text),
+        }
+    }
+
+    /// Command for a control at `value`, 0 to 127.
+    fn command(&self, value: u8) -> Command {
+        let position = value as f32 / 127.0;
+        match self {
+            Target::Fader(command) => {
+                let db = if value == 0 {
+                    SEND_OFF_DB
+                } else {
+                    FADER_MIN_DB + position * (FADER_MAX_DB - FADER_MIN_DB)
+                };
+                with_value(command, db)
+            }
+            Target::Pan(command) => with_value(command, position * 2.0 - 1.0),
+            Target::Param(command, min, max) => with_value(command, min + position * (max - min)),
+            Target::Trigger(command) => command.clone(),
+            Target::Hold(Command::Talk(_)) => Command::Talk(Some(value >= 64)),
+            Target::Hold(command) => command.clone(),
+        }
+    }
+}
+
+fn with_value(command: &Command, value: f32) -> Command {
+    match *command {
+        Command::Gain(channel, _) => Command::Gain(channel, value),
+        Command::Pan(channel, _) => Command::Pan(channel, value),
+        Command::Send(channel, aux, _) => Command::Send(channel, aux, value),
+        Command::Return(aux, _) => Command::Return(aux, value),
+        Command::Master(_) => Command::Master(value),
+        Command::Set(ref key, _) => Command::Set(key.clone(), value),
+        ref command => command.clone(),
+    }
+}
+
+struct Mapping {
+    message: Message,
+    /// As written in the map, for listing and saving.
+    text: String,
+    target: Target,
+}
+
+/// Reads a mapping table. Each line maps one control, a later line for the
+/// same control replaces an earlier one. `#` starts a comment:
+///
+/// ```text
+/// cc 1 7 = gain 1
+/// cc 1 10 = pan 1
+/// cc 1 21 = set reverb.mix
+/// note 1 36 = mute 1
+/// note 1 37 = talk
+/// note 1 38 = looper rec
+/// pc 1 0 = profile streaming
+/// note 1 40 = scene 2 5
+/// ```
+///
+/// A program change no line maps recalls the scene one above its number,
+/// program 0 is scene 1.
+fn load(path: &Path, params: &[Arc<Param>]) -> Result<Vec<Mapping>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the MIDI map {}", path.display()))?;
+    let mut mappings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mapping = parse_mapping(line, params)
+            .with_context(|| format!("{} line {}", path.display(), number + 1))?;
+        mappings.retain(|m: &Mapping| m.message != mapping.message);
+        mappings.push(mapping);
+    }
+    Ok(mappings)
+}
+
+fn parse_mapping(line: &str, params: &[Arc<Param>]) -> Result<Mapping> {
+    let (message, target) = line
+        .split_once('=')
+        .context("Expected '<message> = <target>'")?;
+    Ok(Mapping {
+        message: Message::parse(message)?,
+        text: target.trim().to_string(),
+        target: Target::parse(target.trim(), params)?,
+    })
+}
+
+/// What the MIDI callback works on, shared with the console for learning.
+struct State {
+    mappings: Vec<Mapping>,
+    /// Target waiting for the next control moved.
+    learning: Option<(String, Target)>,
+    /// Notes and knobs held past half way, so a trigger fires once.
+    held: Vec<bool>,
+    remote: Sender<Request>,
+}
+
+impl State {
+    fn handle(&mut self, bytes: &[u8], learned: &mut Option<String>) {
+        let Some((message, value)) = Message::decode(bytes) else {
+            return;
+        };
+        if let Some((text, target)) = self.learning.take() {
+            self.mappings.retain(|m| m.message != message);
+            *learned = Some(format!("{} = {}", message, text));
+            self.mappings.push(Mapping {
+                message,
+                text,
+                target,
+            });
+        }
+
+        if let Message::Program { number, .. } = message
+            && !self.mappings.iter().any(|m| m.message == message)
+        {
+            let _ = self.remote.send(Request {
+                command: Command::Scene(number as usize + 1, None),
+                reply: None,
+            });
+            return;
+        }
+
+        for mapping in self.mappings.iter().filter(|m| m.message == message) {
+            let value = match (&mapping.target, message) {
+                // A held note holds whatever its velocity, its note off
+                // lets go. Nothing else uses note offs
+                (Target::Hold(_), Message::Note { .. }) => value.min(1) * 127,
+                (_, Message::Note { .. }) if value == 0 => continue,
+                (Target::Trigger(_) | Target::Hold(_), Message::Cc { channel, number }) => {
+                    // A knob or button on a CC fires on the way up only,
+                    // a hold on the way down too
+                    let held = &mut self.held[(channel as usize - 1) * 128 + number as usize];
+                    let was_held = *held;
+                    *held = value >= 64;
+                    let trigger = matches!(mapping.target, Target::Trigger(_));
+                    if was_held == *held || trigger && !*held {
+                        continue;
+                    }
+                    value
+                }
+                _ => value,
+            };
+            let _ = self.remote.send(Request {
+                command: mapping.target.command(value),
+                reply: None,
+            });
+        }
+    }
+}
+
+/// Console side of the MIDI input.
+struct Learner {
+    state: Arc<Mutex<State>>,
+    params: Vec<Arc<Param>>,
+}
+
+impl Learn for Learner {
+    fn learn(&self, target: &str) -> Result<String> {
+        let parsed = Target::parse(target, &self.params)?;
+        self.state.lock().unwrap().learning = Some((target.to_string(), parsed));
+        Ok(format!("Move a MIDI control to map it to '{}'", target))
+    }
+
+    fn describe(&self) -> String {
+        let state = self.state.lock().unwrap();
+        if state.mappings.is_empty() {
+            return "No MIDI mappings, use 'learn <target>' or --midi-map".to_string();
+        }
+        state
+            .mappings
+            .iter()
+            .map(|m| format!("{} = {}", m.message, m.text))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Open MIDI input port.
+pub struct MidiSession {
+    connection: MidiInputConnection<()>,
+    learner: Arc<Learner>,
+}
+
+impl MidiSession {
+    pub fn learner(&self) -> Arc<dyn Learn> {
+        self.learner.clone()
+    }
+
+    pub fn stop(self) {
+        self.connection.close();
+    }
+}
+
+/// Opens the first MIDI input whose name contains `port` and maps its
+/// controls with the table in `map`. Mapped controls send their commands
+/// to the control loop. Learned mappings are appended to `map`.
+pub fn connect(port: &str, map: Option<&Path>, controls: &Controls) -> Result<MidiSession> {
+    let params: Vec<Arc<Param>> = controls.params.all();
+    // A map that isn't there yet is created by the first `learn`
+    let mappings = match map {
+        Some(path) if path.exists() => load(path, &params)?,
+        _ => Vec::new(),
+    };
+
+    let mut input = MidiInput::new("live_dsp").map_err(|err| anyhow!("MIDI: {}", err))?;
+    input.ignore(Ignore::All);
+    let ports = input.ports();
+    let names: Vec<String> = ports
+        .iter()
+        .map(|p| input.port_name(p).unwrap_or_default())
+        .collect();
+    let wanted = port.to_lowercase();
+    let Some(index) = names
+        .iter()
+        .position(|name| name.to_lowercase().contains(&wanted))
+    else {
+        bail!(
+            "No MIDI input matching '{}', available: {}",
+            port,
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        );
+    };
+    info!("MIDI input: {}, {} mappings", names[index], mappings.len());
+
+    let state = Arc::new(Mutex::new(State {
+        mappings,
+        learning: None,
+        held: vec![false; 16 * 128],
+        remote: controls.remote.clone(),
+    }));
+    let callback_state = state.clone();
+    let map: Option<PathBuf> = map.map(Path::to_path_buf);
+    let connection = input
+        .connect(
+            &ports[index],
+            "live_dsp input",
+            move |_, bytes, _| {
+                let mut learned = None;
+                callback_state.lock().unwrap().handle(bytes, &mut learned);
+                if let Some(line) = learned {
+                    info!("MIDI learn: {}", line);
+                    if let Some(path) = &map
+                        && let Err(err) = append(path, &line)
+                    {
+                        warn!("Failed to save to {}: {}", path.display(), err);
+                    }
+                }
+            },
+            (),
+        )
+        .map_err(|err| anyhow!("Failed to open MIDI input {}: {}", names[index], err))?;
+
+    Ok(MidiSession {
+        connection,
+        learner: Arc::new(Learner { state, params }),
+    })
+}
+
+/// Later lines replace earlier ones, so learned mappings are just appended.
+fn append(path: &Path, line: &str) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
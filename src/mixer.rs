@@ -0,0 +1,803 @@
+use crate::automix::{AutoMix, Ducker};
+use crate::dsp::{DelayCompensation, DspChain, Processor, db_to_gain};
+use crate::meter::{Meter, MeterLevels};
+use crate::params::{AtomicF32, Param, ParamInfo, ParamStore};
+use crate::scope::ScopeTap;
+use crate::source::Source;
+use crate::transfer::TransferTap;
+use crate::tuner::TunerTap;
+use anyhow::{Result, bail};
+use std::f32::consts::FRAC_PI_4;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// How a centred channel is attenuated when panning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanLaw {
+    /// Sin/cos law, -3 dB in the centre, constant loudness across the field.
+    ConstantPower,
+    /// -6 dB in the centre, sums to unity in mono.
+    Linear,
+    /// 0 dB in the centre, panning only turns the opposite side down.
+    Balance,
+}
+
+impl PanLaw {
+    pub fn parse(text: &str) -> Result<Self> {
+        let law = match text.trim() {
+            "constant-power" => PanLaw::ConstantPower,
+            "linear" => PanLaw::Linear,
+            "balance" => PanLaw::Balance,
+            other => bail!(
+                "Unknown pan law '{}', expected constant-power, linear or balance",
+                other
+            ),
+        };
+        Ok(law)
+    }
+
+    /// Left/right gains for `pan` in `[-1.0, 1.0]`.
+    pub fn gains(self, pan: f32) -> (f32, f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        match self {
+            PanLaw::ConstantPower => {
+                let angle = (pan + 1.0) * FRAC_PI_4;
+                (angle.cos(), angle.sin())
+            }
+            PanLaw::Linear => ((1.0 - pan) / 2.0, (1.0 + pan) / 2.0),
+            PanLaw::Balance => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+        }
+    }
+}
+
+/// Bus the talkback mic goes to while talking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TalkbackBus {
+    /// The local output, e.g. the performer's in-ears.
+    Monitor,
+    Broadcast,
+}
+
+impl TalkbackBus {
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim() {
+            "monitor" => Ok(TalkbackBus::Monitor),
+            "broadcast" => Ok(TalkbackBus::Broadcast),
+            other => bail!(
+                "Unknown talkback bus '{}', expected monitor or broadcast",
+                other
+            ),
+        }
+    }
+}
+
+/// Send levels at or below this are treated as fully off.
+pub const SEND_OFF_DB: f32 = -96.0;
+
+fn send_gain(level_db: f32) -> f32 {
+    if level_db <= SEND_OFF_DB {
+        0.0
+    } else {
+        db_to_gain(level_db)
+    }
+}
+
+/// Level of one channel feeding one aux bus.
+#[derive(Debug)]
+pub struct AuxSend {
+    level_db: AtomicF32,
+    pre_fader: AtomicBool,
+}
+
+impl AuxSend {
+    fn new() -> Self {
+        AuxSend {
+            level_db: AtomicF32::new(SEND_OFF_DB),
+            pre_fader: AtomicBool::new(false),
+        }
+    }
+
+    pub fn level_db(&self) -> f32 {
+        self.level_db.get()
+    }
+
+    pub fn set_level_db(&self, level_db: f32) {
+        self.level_db.set(level_db.clamp(SEND_OFF_DB, 12.0));
+    }
+
+    /// Pre-fader sends ignore the channel gain and pan, post-fader sends
+    /// follow them. Mute and solo silence both.
+    pub fn pre_fader(&self) -> bool {
+        self.pre_fader.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pre_fader(&self, pre_fader: bool) {
+        self.pre_fader.store(pre_fader, Ordering::Relaxed);
+    }
+}
+
+/// Runtime controls of one mixer channel, shared with the control thread.
+#[derive(Debug)]
+pub struct ChannelControls {
+    pub name: String,
+    gain_db: AtomicF32,
+    pan: AtomicF32,
+    mute: AtomicBool,
+    solo: AtomicBool,
+    on_air: AtomicBool,
+    /// Pre-fader input levels.
+    pub meter: Arc<MeterLevels>,
+    /// One send per aux bus, in bus order.
+    pub sends: Vec<AuxSend>,
+}
+
+impl ChannelControls {
+    pub fn new(name: String, gain_db: f32, aux_buses: usize, meter: Arc<MeterLevels>) -> Self {
+        ChannelControls {
+            name,
+            gain_db: AtomicF32::new(gain_db),
+            pan: AtomicF32::new(0.0),
+            mute: AtomicBool::new(false),
+            solo: AtomicBool::new(false),
+            on_air: AtomicBool::new(true),
+            meter,
+            sends: (0..aux_buses).map(|_| AuxSend::new()).collect(),
+        }
+    }
+
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db.get()
+    }
+
+    pub fn set_gain_db(&self, gain_db: f32) {
+        self.gain_db.set(gain_db.clamp(-96.0, 24.0));
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.pan.get()
+    }
+
+    pub fn set_pan(&self, pan: f32) {
+        self.pan.set(pan.clamp(-1.0, 1.0));
+    }
+
+    pub fn muted(&self) -> bool {
+        self.mute.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mute(&self, mute: bool) {
+        self.mute.store(mute, Ordering::Relaxed);
+    }
+
+    pub fn soloed(&self) -> bool {
+        self.solo.load(Ordering::Relaxed)
+    }
+
+    pub fn set_solo(&self, solo: bool) {
+        self.solo.store(solo, Ordering::Relaxed);
+    }
+
+    /// Whether the channel is in the broadcast mix. Solo only changes the
+    /// monitor mix, so checking a channel doesn't take the others off air.
+    pub fn on_air(&self) -> bool {
+        self.on_air.load(Ordering::Relaxed)
+    }
+
+    pub fn set_on_air(&self, on_air: bool) {
+        self.on_air.store(on_air, Ordering::Relaxed);
+    }
+}
+
+/// Runtime controls of one aux bus return.
+#[derive(Debug)]
+pub struct AuxControls {
+    pub name: String,
+    return_db: AtomicF32,
+    on_air: AtomicBool,
+}
+
+impl AuxControls {
+    pub fn return_db(&self) -> f32 {
+        self.return_db.get()
+    }
+
+    pub fn set_return_db(&self, return_db: f32) {
+        self.return_db.set(return_db.clamp(SEND_OFF_DB, 12.0));
+    }
+
+    /// Whether the return is in the broadcast mix as well, e.g. a reverb
+    /// only the performer hears is taken off air.
+    pub fn on_air(&self) -> bool {
+        self.on_air.load(Ordering::Relaxed)
+    }
+
+    pub fn set_on_air(&self, on_air: bool) {
+        self.on_air.store(on_air, Ordering::Relaxed);
+    }
+}
+
+/// Pre-fader peak of every mixer channel's last block, the sidechain keys
+/// of compressors in the chains. Set while mixing, so the chains run on
+/// the mix read the peaks of the block they're processing.
+#[derive(Debug)]
+pub struct ChannelKeys {
+    peaks: Vec<Arc<AtomicF32>>,
+}
+
+impl ChannelKeys {
+    pub fn channels(&self) -> usize {
+        self.peaks.len()
+    }
+
+    /// The key of `channel`, counted from 0.
+    pub fn get(&self, channel: usize) -> Option<Arc<AtomicF32>> {
+        self.peaks.get(channel).cloned()
+    }
+}
+
+/// A shared effect return. Channels send into `buffer`, the chain runs on
+/// the sum and the result is added back into the mix.
+struct AuxBus {
+    controls: Arc<AuxControls>,
+    chain: DspChain,
+    /// Makes up the difference to the bus with the most latency.
+    align: DelayCompensation,
+    buffer: Vec<f32>,
+    current: f32,
+    /// Return gain into the broadcast mix at the end of the previous block.
+    broadcast_current: f32,
+}
+
+struct MixerChannel {
+    controls: Arc<ChannelControls>,
+    tap: Box<dyn Source>,
+    meter: Meter,
+    /// Left, right and unpanned gains applied at the end of the previous
+    /// block, ramped towards the new target so fader moves don't click.
+    current: [f32; 3],
+    /// Same for every aux send.
+    send_current: Vec<[f32; 3]>,
+    /// Same for the broadcast mix.
+    broadcast_current: [f32; 3],
+}
+
+/// Talkback on one mixer channel, switched through the parameter store.
+/// While `talkback.on` is set the channel goes to its bus alone, at
+/// `talkback.level` whatever its fader and mute, and the rest of that bus
+/// is dimmed by `talkback.dim`. Off, the channel is mixed as usual.
+struct Talkback {
+    channel: usize,
+    bus: TalkbackBus,
+    on: Arc<Param>,
+    level_db: Arc<Param>,
+    dim_db: Arc<Param>,
+}
+
+/// Talkback as it stands for one block, gains rather than dB.
+#[derive(Clone, Copy)]
+struct Talk {
+    channel: usize,
+    bus: TalkbackBus,
+    level: f32,
+    dim: f32,
+}
+
+/// Second mix of the same channels for the audience, with its own
+/// on-air switches in place of solo.
+struct BroadcastMix {
+    buffer: Vec<f32>,
+    /// Holds it back by the aux latency like the direct mix.
+    direct: DelayCompensation,
+}
+
+/// Sums every input and file source into the output buffer with gain, pan,
+/// mute and solo. Pan applies to the first two output channels.
+pub struct Mixer {
+    channels: Vec<MixerChannel>,
+    aux_buses: Vec<AuxBus>,
+    /// Holds the direct mix back by the aux buses' latency so the returns
+    /// stay in phase with it.
+    direct: DelayCompensation,
+    broadcast: Option<BroadcastMix>,
+    talkback: Option<Talkback>,
+    ducker: Option<Ducker>,
+    automix: Option<AutoMix>,
+    /// Fed the selected channel, pre-fader so it can be tuned muted.
+    tuner: Option<TunerTap>,
+    /// Fed the channel on the scope, pre-fader too.
+    scope: Option<ScopeTap>,
+    /// Fed the reference and measurement channels, pre-fader too.
+    transfer: Option<TransferTap>,
+    /// Mean square of every channel's last block, pre-fader, and the gains
+    /// the ducker and auto-mix set from it for the next.
+    powers: Vec<f32>,
+    auto_gains: Vec<f32>,
+    keys: Arc<ChannelKeys>,
+    pan_law: PanLaw,
+    sample_rate: f32,
+    scratch: Vec<f32>,
+}
+
+impl Mixer {
+    /// Returns the mixer and the control handles of its channels and aux
+    /// buses, in the same order as `sources` and `aux_buses`. `sample_rate`
+    /// and `output_channels` size the channel meters and aux effects.
+    pub fn new(
+        sources: Vec<(Box<dyn Source>, f32)>,
+        aux_buses: Vec<(String, DspChain)>,
+        pan_law: PanLaw,
+        sample_rate: f32,
+        output_channels: usize,
+    ) -> (Self, Vec<Arc<ChannelControls>>, Vec<Arc<AuxControls>>) {
+        let aux_count = aux_buses.len();
+        let channels: Vec<MixerChannel> = sources
+            .into_iter()
+            .map(|(tap, gain_db)| {
+                let (meter, levels) = Meter::new(sample_rate, output_channels);
+                let controls =
+                    ChannelControls::new(tap.name().to_string(), gain_db, aux_count, levels);
+                MixerChannel {
+                    controls: Arc::new(controls),
+                    tap,
+                    meter,
+                    current: [0.0; 3],
+                    send_current: vec![[0.0; 3]; aux_count],
+                    broadcast_current: [0.0; 3],
+                }
+            })
+            .collect();
+        let controls = channels.iter().map(|c| c.controls.clone()).collect();
+
+        let latency = aux_buses
+            .iter()
+            .map(|(_, chain)| chain.latency())
+            .max()
+            .unwrap_or(0);
+        let mut direct = DelayCompensation::new(latency);
+        direct.prepare(output_channels);
+        let aux_buses: Vec<AuxBus> = aux_buses
+            .into_iter()
+            .map(|(name, mut chain)| {
+                chain.prepare(output_channels);
+                let mut align = DelayCompensation::new(latency - chain.latency());
+                align.prepare(output_channels);
+                AuxBus {
+                    controls: Arc::new(AuxControls {
+                        name,
+                        return_db: AtomicF32::new(0.0),
+                        on_air: AtomicBool::new(true),
+                    }),
+                    chain,
+                    align,
+                    buffer: Vec::new(),
+                    current: 1.0,
+                    broadcast_current: 1.0,
+                }
+            })
+            .collect();
+        let aux_controls = aux_buses.iter().map(|a| a.controls.clone()).collect();
+
+        let count = channels.len();
+        let mixer = Mixer {
+            channels,
+            aux_buses,
+            direct,
+            broadcast: None,
+            talkback: None,
+            ducker: None,
+            automix: None,
+            tuner: None,
+            scope: None,
+            transfer: None,
+            powers: vec![0.0; count],
+            auto_gains: vec![1.0; count],
+            keys: Arc::new(ChannelKeys {
+                peaks: (0..count).map(|_| Arc::new(AtomicF32::new(0.0))).collect(),
+            }),
+            pan_law,
+            sample_rate,
+            scratch: Vec::new(),
+        };
+        (mixer, controls, aux_controls)
+    }
+
+    /// Sizes the mix buffers for callbacks of up to `max_frames`, so
+    /// `process` never allocates. Call before streaming.
+    pub fn prepare(&mut self, max_frames: usize, channels: usize) {
+        let len = max_frames * channels;
+        self.scratch = vec![0.0; len];
+        for aux in self.aux_buses.iter_mut() {
+            aux.buffer = vec![0.0; len];
+        }
+        if let Some(broadcast) = self.broadcast.as_mut() {
+            broadcast.buffer = vec![0.0; len];
+        }
+    }
+
+    /// Also mixes a broadcast bus, read back with `broadcast` after every
+    /// `process`.
+    pub fn enable_broadcast(&mut self, output_channels: usize) {
+        let mut direct = DelayCompensation::new(self.direct.latency());
+        direct.prepare(output_channels);
+        self.broadcast = Some(BroadcastMix {
+            buffer: vec![0.0; self.scratch.len()],
+            direct,
+        });
+    }
+
+    /// Makes `channel`, counted from 0, a talkback mic for `bus`, with its
+    /// switch and levels as `talkback.` parameters. A broadcast talkback
+    /// needs `enable_broadcast` first.
+    pub fn enable_talkback(
+        &mut self,
+        channel: usize,
+        bus: TalkbackBus,
+        params: &ParamStore,
+    ) -> Result<()> {
+        if channel >= self.channels.len() {
+            bail!(
+                "Talkback channel {} isn't a mixer channel, there are {}",
+                channel + 1,
+                self.channels.len()
+            );
+        }
+        if bus == TalkbackBus::Broadcast && self.broadcast.is_none() {
+            bail!("Talkback to the broadcast bus needs --broadcast");
+        }
+        let param = |name, value, min, max| {
+            params.bind(
+                format!("talkback.{}", name),
+                &ParamInfo::new(name, value, min, max),
+            )
+        };
+        self.talkback = Some(Talkback {
+            channel,
+            bus,
+            on: param("on", 0.0, 0.0, 1.0),
+            level_db: param("level", 0.0, -60.0, 12.0),
+            dim_db: param("dim", -20.0, -96.0, 0.0),
+        });
+        Ok(())
+    }
+
+    /// Ducks the `ducked` channels while `key` is above `duck.threshold`,
+    /// channels counted from 0.
+    pub fn enable_ducking(
+        &mut self,
+        key: usize,
+        ducked: Vec<usize>,
+        params: &ParamStore,
+    ) -> Result<()> {
+        self.ducker = Some(Ducker::new(key, ducked, self.channels.len(), params)?);
+        Ok(())
+    }
+
+    /// Shares the gain between the mics in `channels`, counted from 0.
+    pub fn enable_automix(&mut self, channels: Vec<usize>, params: &ParamStore) -> Result<()> {
+        self.automix = Some(AutoMix::new(channels, self.channels.len(), params)?);
+        Ok(())
+    }
+
+    /// Hands the channel the tuner is set to to `tap`.
+    pub fn enable_tuner(&mut self, tap: TunerTap) {
+        self.tuner = Some(tap);
+    }
+
+    /// Hands the channel on the scope to `tap`.
+    pub fn enable_scope(&mut self, tap: ScopeTap) {
+        self.scope = Some(tap);
+    }
+
+    /// Hands the channels the transfer function is measured on to `tap`.
+    pub fn enable_transfer(&mut self, tap: TransferTap) {
+        self.transfer = Some(tap);
+    }
+
+    /// Sidechain keys of the channels, for `chain::Builder`.
+    pub fn keys(&self) -> Arc<ChannelKeys> {
+        self.keys.clone()
+    }
+
+    /// The broadcast mix of the last `process`, as long as its output.
+    pub fn broadcast(&mut self, len: usize) -> Option<&mut [f32]> {
+        self.broadcast
+            .as_mut()
+            .map(|broadcast| &mut broadcast.buffer[..len])
+    }
+
+    /// Mixes into `output`, which holds no more than the frames given to
+    /// `prepare`.
+    pub fn process(&mut self, output: &mut [f32], channels: usize) {
+        output.iter_mut().for_each(|s| *s = 0.0);
+        for aux in self.aux_buses.iter_mut() {
+            aux.buffer[..output.len()].iter_mut().for_each(|s| *s = 0.0);
+        }
+        if let Some(broadcast) = self.broadcast.as_mut() {
+            broadcast.buffer[..output.len()]
+                .iter_mut()
+                .for_each(|s| *s = 0.0);
+        }
+        let scratch = &mut self.scratch[..output.len()];
+        let any_solo = self.channels.iter().any(|c| c.controls.soloed());
+        let talk = self
+            .talkback
+            .as_ref()
+            .filter(|talkback| talkback.on.get() >= 0.5)
+            .map(|talkback| Talk {
+                channel: talkback.channel,
+                bus: talkback.bus,
+                level: db_to_gain(talkback.level_db.get()),
+                dim: db_to_gain(talkback.dim_db.get()),
+            });
+        let (monitor_dim, broadcast_dim) = match talk {
+            Some(talk) if talk.bus == TalkbackBus::Monitor => (talk.dim, 1.0),
+            Some(talk) => (1.0, talk.dim),
+            None => (1.0, 1.0),
+        };
+
+        // Ducking and auto-mix follow the levels of the block before, a
+        // few milliseconds late, so every channel is read only once
+        self.auto_gains.fill(1.0);
+        let frames = output.len() / channels.max(1);
+        if frames > 0 {
+            let blocks = self.sample_rate / frames as f32;
+            if let Some(ducker) = self.ducker.as_mut() {
+                ducker.process(&self.powers, &mut self.auto_gains, blocks);
+            }
+            if let Some(automix) = self.automix.as_mut() {
+                automix.process(&self.powers, &mut self.auto_gains, blocks);
+            }
+        }
+
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            // Always drain the source so it stays in sync while muted
+            channel.tap.read_into(scratch, channels);
+
+            let controls = &channel.controls;
+            channel.meter.process(scratch, channels);
+            if let Some(tuner) = self.tuner.as_mut()
+                && tuner.channel() == Some(index)
+            {
+                tuner.process(scratch, channels);
+            }
+            if let Some(scope) = self.scope.as_mut()
+                && scope.channel() == Some(index)
+            {
+                scope.process(scratch, channels);
+            }
+            if let Some(transfer) = self.transfer.as_mut() {
+                transfer.capture(index, scratch, channels);
+            }
+            let (sum, peak) = scratch.iter().fold((0.0f32, 0.0f32), |(sum, peak), x| {
+                (sum + x * x, peak.max(x.abs()))
+            });
+            self.powers[index] = sum / scratch.len().max(1) as f32;
+            self.keys.peaks[index].set(peak);
+            let pan_law = self.pan_law;
+            let panned = |gain: f32| {
+                if channels >= 2 {
+                    let (left, right) = pan_law.gains(controls.pan());
+                    [gain * left, gain * right, gain]
+                } else {
+                    [gain; 3]
+                }
+            };
+            let talking = talk.filter(|talk| talk.channel == index);
+            let audible =
+                talking.is_none() && !controls.muted() && (!any_solo || controls.soloed());
+            let fader = if controls.muted() {
+                [0.0; 3]
+            } else {
+                panned(db_to_gain(controls.gain_db()) * self.auto_gains[index])
+            };
+            let target = if audible { fader } else { [0.0; 3] };
+            let (monitor, broadcast) = match talking {
+                Some(talk) if talk.bus == TalkbackBus::Monitor => (panned(talk.level), [0.0; 3]),
+                Some(talk) => ([0.0; 3], panned(talk.level)),
+                None => (
+                    target.map(|gain| gain * monitor_dim),
+                    if controls.on_air() {
+                        fader.map(|gain| gain * broadcast_dim)
+                    } else {
+                        [0.0; 3]
+                    },
+                ),
+            };
+            mix_ramped(output, scratch, channels, channel.current, monitor);
+            channel.current = monitor;
+
+            if let Some(mix) = self.broadcast.as_mut() {
+                let buffer = &mut mix.buffer[..output.len()];
+                mix_ramped(
+                    buffer,
+                    scratch,
+                    channels,
+                    channel.broadcast_current,
+                    broadcast,
+                );
+                channel.broadcast_current = broadcast;
+            }
+
+            for ((aux, send), current) in self
+                .aux_buses
+                .iter_mut()
+                .zip(controls.sends.iter())
+                .zip(channel.send_current.iter_mut())
+            {
+                let level = if audible {
+                    send_gain(send.level_db())
+                } else {
+                    0.0
+                };
+                let send_target = if send.pre_fader() {
+                    [level; 3]
+                } else {
+                    target.map(|gain| gain * level)
+                };
+                if send_target == [0.0; 3] && *current == [0.0; 3] {
+                    continue;
+                }
+                let buffer = &mut aux.buffer[..output.len()];
+                mix_ramped(buffer, scratch, channels, *current, send_target);
+                *current = send_target;
+            }
+        }
+        if let Some(transfer) = self.transfer.as_mut() {
+            transfer.flush(frames);
+        }
+
+        self.direct.process(output, channels);
+        if let Some(broadcast) = self.broadcast.as_mut() {
+            let buffer = &mut broadcast.buffer[..output.len()];
+            broadcast.direct.process(buffer, channels);
+        }
+        for aux in self.aux_buses.iter_mut() {
+            let buffer = &mut aux.buffer[..output.len()];
+            aux.chain.process(buffer, channels);
+            aux.align.process(buffer, channels);
+
+            let target = send_gain(aux.controls.return_db());
+            let monitor = target * monitor_dim;
+            let start = aux.current;
+            mix_ramped(output, buffer, channels, [start; 3], [monitor; 3]);
+            aux.current = monitor;
+
+            if let Some(broadcast) = self.broadcast.as_mut() {
+                let target = if aux.controls.on_air() {
+                    target * broadcast_dim
+                } else {
+                    0.0
+                };
+                let start = aux.broadcast_current;
+                let mix = &mut broadcast.buffer[..output.len()];
+                mix_ramped(mix, buffer, channels, [start; 3], [target; 3]);
+                aux.broadcast_current = target;
+            }
+        }
+    }
+}
+
+/// Adds `input` into `output`, ramping the left, right and unpanned gains
+/// from `start` to `target` over the block. Channels past the stereo pair
+/// follow the unpanned gain.
+fn mix_ramped(
+    output: &mut [f32],
+    input: &[f32],
+    channels: usize,
+    start: [f32; 3],
+    target: [f32; 3],
+) {
+    let frames = (output.len() / channels.max(1)).max(1);
+    for (i, (out, input)) in output
+        .chunks_mut(channels)
+        .zip(input.chunks(channels))
+        .enumerate()
+    {
+        let t = (i + 1) as f32 / frames as f32;
+        let ramp = |k: usize| start[k] + (target[k] - start[k]) * t;
+        let (left, right, centre) = (ramp(0), ramp(1), ramp(2));
+
+        for (c, (o, x)) in out.iter_mut().zip(input).enumerate() {
+            let gain = match c {
+                0 => left,
+                1 => right,
+                _ => centre,
+            };
+            *o += x * gain;
+        }
+    }
+}
+
+/// Runtime controls of the master bus.
+#[derive(Debug)]
+pub struct MasterControls {
+    gain_db: AtomicF32,
+    dim: AtomicBool,
+    dim_db: AtomicF32,
+    mono: AtomicBool,
+}
+
+impl Default for MasterControls {
+    fn default() -> Self {
+        MasterControls {
+            gain_db: AtomicF32::new(0.0),
+            dim: AtomicBool::new(false),
+            dim_db: AtomicF32::new(-20.0),
+            mono: AtomicBool::new(false),
+        }
+    }
+}
+
+impl MasterControls {
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db.get()
+    }
+
+    pub fn set_gain_db(&self, gain_db: f32) {
+        self.gain_db.set(gain_db.clamp(-96.0, 12.0));
+    }
+
+    pub fn dimmed(&self) -> bool {
+        self.dim.load(Ordering::Relaxed)
+    }
+
+    pub fn set_dim(&self, dim: bool) {
+        self.dim.store(dim, Ordering::Relaxed);
+    }
+
+    /// Attenuation applied while dimmed, in dB.
+    pub fn dim_db(&self) -> f32 {
+        self.dim_db.get()
+    }
+
+    pub fn set_dim_db(&self, dim_db: f32) {
+        self.dim_db.set(dim_db.clamp(-96.0, 0.0));
+    }
+
+    pub fn mono(&self) -> bool {
+        self.mono.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mono(&self, mono: bool) {
+        self.mono.store(mono, Ordering::Relaxed);
+    }
+}
+
+/// Final gain stage before the output limiter: master fader, dim and a
+/// mono sum for checking mono compatibility.
+pub struct MasterBus {
+    controls: Arc<MasterControls>,
+    current: f32,
+}
+
+impl MasterBus {
+    pub fn new() -> (Self, Arc<MasterControls>) {
+        let controls = Arc::new(MasterControls::default());
+        let bus = MasterBus {
+            controls: controls.clone(),
+            current: db_to_gain(controls.gain_db()),
+        };
+        (bus, controls)
+    }
+
+    pub fn process(&mut self, buffer: &mut [f32], channels: usize) {
+        let mut gain_db = self.controls.gain_db();
+        if self.controls.dimmed() {
+            gain_db += self.controls.dim_db();
+        }
+        let target = db_to_gain(gain_db);
+        let mono = self.controls.mono() && channels > 1;
+
+        let start = self.current;
+        let frames = (buffer.len() / channels.max(1)).max(1);
+        for (i, frame) in buffer.chunks_mut(channels).enumerate() {
+            let gain = start + (target - start) * (i + 1) as f32 / frames as f32;
+            if mono {
+                let sum = frame.iter().sum::<f32>() / channels as f32;
+                frame.iter_mut().for_each(|s| *s = sum);
+            }
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        self.current = target;
+    }
+}
@@ -0,0 +1,216 @@
+use crate::control::{Command, Request};
+use anyhow::{Context, Result, bail};
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::info;
+
+/// Every address starts with this.
+const PREFIX: &str = "/live_dsp/";
+/// Largest packet read, OSC over UDP stays well below.
+const MAX_PACKET: usize = 8192;
+
+/// OSC server taking mixer and effect commands over UDP, for TouchOSC and
+/// the like. Values are in the console's units, dB for levels and -1 to 1
+/// for pan, so set the range of a control to match:
+///
+/// ```text
+/// /live_dsp/channel/1/gain -6       /live_dsp/master/gain -3
+/// /live_dsp/channel/1/pan 0.5       /live_dsp/master/dim 1
+/// /live_dsp/channel/1/mute 1        /live_dsp/master/mono 0
+/// /live_dsp/channel/1/solo 0        /live_dsp/aux/1/return -12
+/// /live_dsp/channel/1/send/1 -10    /live_dsp/fx/reverb/mix 0.3
+/// /live_dsp/player/1/play           /live_dsp/profile "podcast"
+/// /live_dsp/scene 3                 /live_dsp/scene/3
+/// /live_dsp/cue                     /live_dsp/cue/intro
+/// /live_dsp/cues/start              /live_dsp/cues/stop
+/// ```
+///
+/// `fx` takes a parameter key with its dots as slashes, `aux1.reverb.mix`
+/// is `/live_dsp/fx/aux1/reverb/mix`. Buttons fire when pressed, an
+/// argument below 0.5 is the release and does nothing. Mute, solo, dim and
+/// mono follow their argument and toggle without one. `scene` recalls the
+/// scene its argument numbers, `scene/<n>` is a button for one. `cue`
+/// fires the cue on standby, `cue/<name>` a named one.
+pub struct OscServer {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl OscServer {
+    /// Listens on UDP `port` on every interface and sends what arrives to
+    /// the control loop. Packets that don't map to a command are dropped.
+    pub fn spawn(port: u16, remote: Sender<Request>) -> Result<OscServer> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("Failed to listen for OSC on UDP port {}", port))?;
+        // Wakes up now and then to see whether to stop
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let thread = thread::Builder::new()
+            .name("osc".to_string())
+            .spawn(move || {
+                let mut packet = [0u8; MAX_PACKET];
+                while flag.load(Ordering::Relaxed) {
+                    let Ok((len, _)) = socket.recv_from(&mut packet) else {
+                        continue;
+                    };
+                    let mut messages = Vec::new();
+                    if decode_packet(&packet[..len], &mut messages).is_err() {
+                        continue;
+                    }
+                    for (address, args) in messages {
+                        if let Ok(Some(command)) = command(&address, &args) {
+                            let _ = remote.send(Request {
+                                command,
+                                reply: None,
+                            });
+                        }
+                    }
+                }
+            })?;
+        info!("OSC: listening on UDP port {}", port);
+        Ok(OscServer { running, thread })
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Arg {
+    Number(f32),
+    Text(String),
+}
+
+/// Maps a message to the console command it stands for. `None` for a
+/// button being released.
+fn command(address: &str, args: &[Arg]) -> Result<Option<Command>> {
+    let Some(path) = address.strip_prefix(PREFIX) else {
+        bail!("Not a live_dsp address: {}", address);
+    };
+    let parts: Vec<&str> = path.split('/').collect();
+    let number = args.iter().find_map(|arg| match arg {
+        Arg::Number(value) => Some(*value),
+        Arg::Text(_) => None,
+    });
+    let value = || number.with_context(|| format!("{} needs a value", address));
+    let state = || match number {
+        Some(value) if value >= 0.5 => " on",
+        Some(_) => " off",
+        None => "",
+    };
+    let pressed = number.is_none_or(|value| value >= 0.5);
+
+    // Built as console lines, so the console checks them
+    let line = match parts.as_slice() {
+        ["channel", ch, "gain"] => format!("gain {} {}", ch, value()?),
+        ["channel", ch, "pan"] => format!("pan {} {}", ch, value()?),
+        ["channel", ch, "mute"] => format!("mute {}{}", ch, state()),
+        ["channel", ch, "solo"] => format!("solo {}{}", ch, state()),
+        ["channel", ch, "send", aux] => format!("send {} {} {}", ch, aux, value()?),
+        ["aux", aux, "return"] => format!("return {} {}", aux, value()?),
+        ["master", "gain"] => format!("master {}", value()?),
+        ["master", "dim"] => format!("dim{}", state()),
+        ["master", "mono"] => format!("mono{}", state()),
+        ["fx", key @ ..] if !key.is_empty() => format!("set {} {}", key.join("."), value()?),
+        ["loudness", "reset"] if pressed => "loudness reset".to_string(),
+        ["player", p, action @ ("play" | "pause" | "stop" | "loop")] if pressed => {
+            format!("{} {}", action, p)
+        }
+        ["profile"] => match args.first() {
+            Some(Arg::Text(name)) => format!("profile {}", name),
+            _ => bail!("{} needs a profile name", address),
+        },
+        ["profile", name] if pressed => format!("profile {}", name),
+        ["scene"] => format!("scene {}", value()? as usize),
+        ["scene", n] if pressed => format!("scene {}", n),
+        ["cue"] if pressed => "cue".to_string(),
+        ["cue", name] if pressed => format!("cue {}", name),
+        ["cues", action @ ("start" | "stop" | "reset")] if pressed => format!("cues {}", action),
+        // Released buttons
+        ["loudness", "reset"]
+        | ["player", _, "play" | "pause" | "stop" | "loop"]
+        | ["profile", _]
+        | ["scene", _]
+        | ["cue"]
+        | ["cue", _]
+        | ["cues", "start" | "stop" | "reset"] => return Ok(None),
+        _ => bail!("Unknown OSC address {}", address),
+    };
+    Command::parse(&line).map(Some)
+}
+
+/// Decodes a message or a bundle, bundles in bundles included.
+fn decode_packet(packet: &[u8], messages: &mut Vec<(String, Vec<Arg>)>) -> Result<()> {
+    let mut reader = Reader { data: packet };
+    if packet.starts_with(b"#bundle\0") {
+        reader.take(16)?; // Tag and time tag, everything runs right away
+        while !reader.data.is_empty() {
+            let size = reader.int()? as usize;
+            decode_packet(reader.take(size)?, messages)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    let tags = if reader.data.is_empty() {
+        String::new()
+    } else {
+        reader.string()?
+    };
+    let mut args = Vec::new();
+    for tag in tags.strip_prefix(',').unwrap_or("").chars() {
+        let arg = match tag {
+            'f' => Arg::Number(f32::from_bits(reader.int()? as u32)),
+            'i' => Arg::Number(reader.int()? as f32),
+            'd' => {
+                let bytes: [u8; 8] = reader.take(8)?.try_into()?;
+                Arg::Number(f64::from_be_bytes(bytes) as f32)
+            }
+            's' => Arg::Text(reader.string()?),
+            'T' => Arg::Number(1.0),
+            'F' => Arg::Number(0.0),
+            other => bail!("Unsupported OSC argument type '{}'", other),
+        };
+        args.push(arg);
+    }
+    messages.push((address, args));
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.data.len() {
+            bail!("Truncated OSC packet");
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn int(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Null terminated and padded to four bytes.
+    fn string(&mut self) -> Result<String> {
+        let len = self
+            .data
+            .iter()
+            .position(|&b| b == 0)
+            .context("Unterminated OSC string")?;
+        let text = String::from_utf8(self.data[..len].to_vec())?;
+        self.take((len + 4) & !3)?;
+        Ok(text)
+    }
+}
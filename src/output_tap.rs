@@ -0,0 +1,87 @@
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Creates a tap on the output for a recorder or a network stream, a ring
+/// holding `seconds` of audio between the audio thread and the one
+/// writing or sending it.
+pub fn output_tap(sample_rate: u32, channels: usize, seconds: usize) -> (OutputTap, TapReader) {
+    let channels = channels.max(1);
+    let capacity = sample_rate as usize * channels * seconds;
+    let (producer, consumer) = HeapRb::<f32>::new(capacity.max(channels)).split();
+    let dropped = Arc::new(AtomicU64::new(0));
+    let tap = OutputTap {
+        producer,
+        channels,
+        dropped: dropped.clone(),
+    };
+    let reader = TapReader {
+        consumer,
+        channels,
+        dropped,
+    };
+    (tap, reader)
+}
+
+/// Audio-thread side of a tap. Never blocks: when the reader falls behind,
+/// whole frames are dropped and their samples counted, so the channels
+/// never shift against each other.
+pub struct OutputTap {
+    producer: HeapProd<f32>,
+    channels: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+impl OutputTap {
+    /// Starts the tap with `frames` of silence, lining it up with one taken
+    /// after processors that add latency. Call before streaming.
+    pub fn delay(&mut self, frames: usize) {
+        let room = self.producer.vacant_len() / self.channels;
+        self.producer
+            .push_iter(std::iter::repeat_n(0.0, frames.min(room) * self.channels));
+    }
+
+    pub fn process(&mut self, buffer: &[f32]) {
+        let room = self.producer.vacant_len() / self.channels * self.channels;
+        let pushed = self.producer.push_slice(&buffer[..buffer.len().min(room)]);
+        if pushed < buffer.len() {
+            self.dropped
+                .fetch_add((buffer.len() - pushed) as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The side drained on the writer's or sender's own thread.
+pub struct TapReader {
+    consumer: HeapCons<f32>,
+    channels: usize,
+    dropped: Arc<AtomicU64>,
+}
+
+impl TapReader {
+    /// Samples the tap has dropped so far, for the control side to report
+    /// once the reader is gone.
+    pub fn dropped(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
+
+    /// Waits for audio, checking every `poll`, and reads whole frames into
+    /// `block`, which must hold at least one. Returns the samples read, or
+    /// `None` once the tap is dropped and everything it pushed was read.
+    pub fn read(&mut self, block: &mut [f32], poll: Duration) -> Option<usize> {
+        let room = block.len() / self.channels * self.channels;
+        loop {
+            let count = self.consumer.pop_slice(&mut block[..room]);
+            if count > 0 {
+                return Some(count);
+            }
+            if !self.consumer.write_is_held() && self.consumer.is_empty() {
+                return None;
+            }
+            thread::sleep(poll);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::clip::ClipMeters;
+use crate::headroom::HeadroomMeters;
+use crate::load::ProcessorLoads;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// An `f32` that can be shared between the control and audio threads.
+#[derive(Debug, Default)]
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub fn new(value: f32) -> Self {
+        AtomicF32(AtomicU32::new(value.to_bits()))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// A processor parameter as reported by `Processor::params`, with its
+/// current value and allowed range.
+#[derive(Debug, Clone, Copy)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl ParamInfo {
+    pub fn new(name: &'static str, value: f32, min: f32, max: f32) -> Self {
+        ParamInfo {
+            name,
+            value,
+            min,
+            max,
+        }
+    }
+}
+
+/// One runtime-adjustable value. The control thread writes the value and
+/// bumps `version`; the audio thread compares versions once per block and
+/// only touches the processor when something changed.
+#[derive(Debug)]
+pub struct Param {
+    pub key: String,
+    pub min: f32,
+    pub max: f32,
+    value: AtomicF32,
+    version: AtomicU32,
+}
+
+impl Param {
+    pub fn new(key: String, info: &ParamInfo) -> Self {
+        Param {
+            key,
+            min: info.min,
+            max: info.max,
+            value: AtomicF32::new(info.value),
+            version: AtomicU32::new(0),
+        }
+    }
+
+    pub fn get(&self) -> f32 {
+        self.value.get()
+    }
+
+    /// Stores `value` clamped to the parameter range and returns it.
+    pub fn set(&self, value: f32) -> f32 {
+        let value = value.clamp(self.min, self.max);
+        self.value.set(value);
+        self.version.fetch_add(1, Ordering::Release);
+        value
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version.load(Ordering::Acquire)
+    }
+}
+
+/// Every runtime parameter, keyed `<processor>.<param>` with an optional
+/// bus prefix such as `aux1.reverb.mix`. Shared, so the DSP chain can be
+/// rebound when it's reloaded while the console holds the store.
+#[derive(Debug, Default)]
+pub struct ParamStore {
+    params: Mutex<Vec<Arc<Param>>>,
+    /// Clip counters after each bound processor, named like its keys.
+    clips: ClipMeters,
+    /// Peaks around each bound processor, with `--headroom`.
+    headroom: HeadroomMeters,
+    /// Time each bound processor takes per callback.
+    loads: ProcessorLoads,
+}
+
+impl ParamStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, param: Arc<Param>) {
+        self.params.lock().unwrap().push(param);
+    }
+
+    /// The parameter stored under `key` with `info`'s value, or a new one.
+    /// Keeping the old one lets controllers and scripts holding it carry
+    /// on after a reload.
+    pub fn bind(&self, key: String, info: &ParamInfo) -> Arc<Param> {
+        let mut params = self.params.lock().unwrap();
+        if let Some(param) = params
+            .iter()
+            .find(|p| p.key == key && p.min == info.min && p.max == info.max)
+        {
+            param.set(info.value);
+            return param.clone();
+        }
+        params.retain(|p| p.key != key);
+        let param = Arc::new(Param::new(key, info));
+        params.push(param.clone());
+        param
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.params.lock().unwrap().retain(|p| p.key != key);
+    }
+
+    pub fn get(&self, key: &str) -> Option<Arc<Param>> {
+        self.params
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|p| p.key == key)
+            .cloned()
+    }
+
+    /// Every parameter in the order added.
+    pub fn all(&self) -> Vec<Arc<Param>> {
+        self.params.lock().unwrap().clone()
+    }
+
+    pub fn clips(&self) -> &ClipMeters {
+        &self.clips
+    }
+
+    pub fn headroom(&self) -> &HeadroomMeters {
+        &self.headroom
+    }
+
+    pub fn loads(&self) -> &ProcessorLoads {
+        &self.loads
+    }
+}
@@ -0,0 +1,150 @@
+use crate::dsp::time_coefficient;
+use crate::params::AtomicF32;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Integration time of the correlation, about a hardware meter's needle.
+const WINDOW_MS: f32 = 300.0;
+/// How long the lowest reading stays up.
+const HOLD_SECONDS: f32 = 2.0;
+/// Goniometer points kept, spread over the newest `TRACE_MS`.
+const POINTS: usize = 512;
+const TRACE_MS: f32 = 50.0;
+/// Mean square under which, about -70 dBFS, the correlation holds still
+/// instead of reading the noise floor.
+const SILENCE: f32 = 1e-7;
+
+/// Phase readings of the first two output channels published by the audio
+/// thread.
+#[derive(Debug)]
+pub struct PhaseLevels {
+    stereo: bool,
+    /// -1 out of phase, 0 unrelated, +1 mono.
+    correlation: AtomicF32,
+    lowest: AtomicF32,
+    /// Side and mid of the newest points, a ring written at `write`.
+    side: Vec<AtomicF32>,
+    mid: Vec<AtomicF32>,
+    write: AtomicUsize,
+}
+
+impl PhaseLevels {
+    /// False on a mono output, which has no phase to read.
+    pub fn stereo(&self) -> bool {
+        self.stereo
+    }
+
+    pub fn correlation(&self) -> f32 {
+        self.correlation.get()
+    }
+
+    /// Lowest correlation of the last two seconds, where a cancellation
+    /// that came and went shows.
+    pub fn lowest(&self) -> f32 {
+        self.lowest.get()
+    }
+
+    /// The goniometer's points as `(side, mid)`, oldest first: a vertical
+    /// line is mono, a horizontal one is out of phase.
+    pub fn points(&self) -> Vec<(f32, f32)> {
+        let write = self.write.load(Ordering::Relaxed);
+        (0..POINTS)
+            .map(|index| (write + index) % POINTS)
+            .map(|index| (self.side[index].get(), self.mid[index].get()))
+            .collect()
+    }
+}
+
+/// Audio-thread side of the phase meter: the correlation between left and
+/// right over a 300 ms window with a hold of its lowest, and every few
+/// frames a goniometer point.
+pub struct PhaseMeter {
+    levels: Arc<PhaseLevels>,
+    sample_rate: f32,
+    coeff: f32,
+    /// Averaged left times right, left squared and right squared.
+    product: f32,
+    left_power: f32,
+    right_power: f32,
+    lowest: f32,
+    hold_left: f32,
+    /// Frames between goniometer points, and until the next one.
+    step: usize,
+    countdown: usize,
+    write: usize,
+}
+
+impl PhaseMeter {
+    pub fn new(sample_rate: f32, channels: usize) -> (Self, Arc<PhaseLevels>) {
+        let points = || (0..POINTS).map(|_| AtomicF32::new(0.0)).collect();
+        let levels = Arc::new(PhaseLevels {
+            stereo: channels >= 2,
+            correlation: AtomicF32::new(1.0),
+            lowest: AtomicF32::new(1.0),
+            side: points(),
+            mid: points(),
+            write: AtomicUsize::new(0),
+        });
+        let meter = PhaseMeter {
+            levels: levels.clone(),
+            sample_rate,
+            coeff: time_coefficient(WINDOW_MS, sample_rate),
+            product: 0.0,
+            left_power: 0.0,
+            right_power: 0.0,
+            lowest: 1.0,
+            hold_left: 0.0,
+            step: ((TRACE_MS * 0.001 * sample_rate) as usize / POINTS).max(1),
+            countdown: 0,
+            write: 0,
+        };
+        (meter, levels)
+    }
+
+    /// Measures one interleaved block and publishes the result.
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        if channels < 2 || !self.levels.stereo {
+            return;
+        }
+        let frames = buffer.len() / channels;
+        if frames == 0 {
+            return;
+        }
+
+        for frame in buffer.chunks(channels) {
+            let (left, right) = (frame[0], frame[1]);
+            let keep = self.coeff;
+            self.product = keep * self.product + (1.0 - keep) * left * right;
+            self.left_power = keep * self.left_power + (1.0 - keep) * left * left;
+            self.right_power = keep * self.right_power + (1.0 - keep) * right * right;
+
+            if self.countdown == 0 {
+                self.countdown = self.step;
+                let scale = std::f32::consts::FRAC_1_SQRT_2;
+                self.levels.side[self.write].set((right - left) * scale);
+                self.levels.mid[self.write].set((left + right) * scale);
+                self.write = (self.write + 1) % POINTS;
+            }
+            self.countdown -= 1;
+        }
+        self.levels.write.store(self.write, Ordering::Relaxed);
+
+        if self.left_power < SILENCE || self.right_power < SILENCE {
+            return;
+        }
+        let correlation =
+            (self.product / (self.left_power * self.right_power).sqrt()).clamp(-1.0, 1.0);
+        let seconds = frames as f32 / self.sample_rate;
+        if correlation <= self.lowest {
+            self.lowest = correlation;
+            self.hold_left = HOLD_SECONDS;
+        } else {
+            self.hold_left -= seconds;
+            if self.hold_left <= 0.0 {
+                self.lowest = correlation;
+            }
+        }
+        self.levels.correlation.set(correlation);
+        self.levels.lowest.set(self.lowest);
+    }
+}
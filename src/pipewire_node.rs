@@ -0,0 +1,264 @@
+use crate::Render;
+use crate::frame_ring::{self, FrameProducer};
+use crate::jitter::{JitterBuffer, JitterStats};
+use crate::source::Source;
+use crate::xrun::XrunStats;
+use anyhow::{Context, Result, anyhow};
+use pipewire as pw;
+use pw::spa::param::audio::{AudioFormat, AudioInfoRaw};
+use pw::spa::pod::serialize::PodSerializer;
+use pw::spa::pod::{Object, Pod, Value};
+use pw::stream::{Stream, StreamFlags};
+use std::io::Cursor;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+/// Rate asked of the graph. PipeWire converts whatever it runs at.
+pub const SAMPLE_RATE: u32 = 48000;
+/// Frames rendered per pass, the scratch buffer is allocated up front.
+pub const CHUNK_FRAMES: usize = 1024;
+/// Ring between the capture and the playback node. PipeWire runs the two
+/// in either order within a cycle, so the jitter buffer sits in between.
+const RING_FRAMES: usize = 16384;
+
+/// Mixer channel fed by the capture node.
+pub struct PipeWireInput {
+    buffer: JitterBuffer,
+}
+
+impl Source for PipeWireInput {
+    fn name(&self) -> &str {
+        "pipewire in"
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        self.buffer.begin(data.len() / channels);
+        for frame in data.chunks_mut(channels) {
+            self.buffer.pop_frame(frame);
+        }
+    }
+}
+
+/// Capture side waiting for the engine to be built.
+pub struct PipeWireSetup {
+    channels: usize,
+    producer: FrameProducer,
+}
+
+/// Sets up the ring between a capture and a playback node of `channels`
+/// channels, holding `target` frames. Nothing is registered before `start`.
+pub fn open(channels: usize, target: u32) -> (PipeWireSetup, PipeWireInput, Arc<JitterStats>) {
+    let (producer, consumer) = frame_ring::frame_ring(RING_FRAMES, channels);
+    let jitter = Arc::new(JitterStats::new(
+        "pipewire in".to_string(),
+        SAMPLE_RATE,
+        target as u64,
+        (RING_FRAMES - CHUNK_FRAMES) as u64,
+    ));
+    let buffer = JitterBuffer::new(consumer, channels, CHUNK_FRAMES, false, jitter.clone());
+    (
+        PipeWireSetup { channels, producer },
+        PipeWireInput { buffer },
+        jitter,
+    )
+}
+
+impl PipeWireSetup {
+    /// Registers the `live_dsp` capture and playback nodes on a thread
+    /// running the PipeWire loop. The session manager links them to the
+    /// default source and sink, they can be relinked to anything in the
+    /// graph from there.
+    pub fn start(self, render: Render, xruns: Arc<XrunStats>) -> Result<PipeWireSession> {
+        let (quit, quit_receiver) = pw::channel::channel();
+        let (ready, started) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("pipewire".to_string())
+            .spawn(move || {
+                if let Err(err) = run(self, render, xruns, quit_receiver, &ready) {
+                    let _ = ready.send(Err(err));
+                }
+            })?;
+        started
+            .recv()
+            .map_err(|_| anyhow!("The PipeWire thread exited"))??;
+        Ok(PipeWireSession { quit, thread })
+    }
+}
+
+/// Running PipeWire nodes.
+pub struct PipeWireSession {
+    quit: pw::channel::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl PipeWireSession {
+    /// Removes the nodes from the graph and stops the loop.
+    pub fn stop(self) {
+        let _ = self.quit.send(());
+        let _ = self.thread.join();
+    }
+}
+
+struct Capture {
+    producer: FrameProducer,
+    frame: Vec<f32>,
+    xruns: Arc<XrunStats>,
+}
+
+struct Playback {
+    render: Render,
+    scratch: Vec<f32>,
+    channels: usize,
+    xruns: Arc<XrunStats>,
+}
+
+/// Builds the loop and both streams, then runs the loop until `quit`.
+fn run(
+    setup: PipeWireSetup,
+    render: Render,
+    xruns: Arc<XrunStats>,
+    quit: pw::channel::Receiver<()>,
+    ready: &mpsc::Sender<Result<()>>,
+) -> Result<()> {
+    pw::init();
+    let mainloop = pw::main_loop::MainLoop::new(None)?;
+    let context = pw::context::Context::new(&mainloop)?;
+    let core = context
+        .connect(None)
+        .context("Failed to connect to PipeWire, is it running?")?;
+    let _quit = quit.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |()| mainloop.quit()
+    });
+    let channels = setup.channels;
+
+    let capture = Stream::new(
+        &core,
+        "live_dsp input",
+        pw::properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CATEGORY => "Capture",
+            *pw::keys::MEDIA_ROLE => "DSP",
+            *pw::keys::NODE_NAME => "live_dsp.input",
+            *pw::keys::NODE_DESCRIPTION => "live_dsp input",
+        },
+    )?;
+    let _capture_listener = capture
+        .add_local_listener_with_user_data(Capture {
+            producer: setup.producer,
+            frame: vec![0.0; channels],
+            xruns: xruns.clone(),
+        })
+        .process(move |stream, capture| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let data = &mut buffer.datas_mut()[0];
+            let offset = data.chunk().offset() as usize;
+            let size = data.chunk().size() as usize;
+            let Some(bytes) = data.data() else {
+                return;
+            };
+            let end = (offset + size).min(bytes.len());
+            let bytes = &bytes[offset.min(end)..end];
+
+            let mut dropped = 0;
+            for frame in bytes.chunks_exact(channels * 4) {
+                for (sample, raw) in capture.frame.iter_mut().zip(frame.chunks_exact(4)) {
+                    *sample = f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+                }
+                if !capture.producer.push_frame(&capture.frame) {
+                    dropped += channels as u64;
+                }
+            }
+            capture.xruns.record_overrun(dropped);
+        })
+        .register()?;
+
+    let playback = Stream::new(
+        &core,
+        "live_dsp output",
+        pw::properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CATEGORY => "Playback",
+            *pw::keys::MEDIA_ROLE => "DSP",
+            *pw::keys::NODE_NAME => "live_dsp.output",
+            *pw::keys::NODE_DESCRIPTION => "live_dsp output",
+        },
+    )?;
+    let _playback_listener = playback
+        .add_local_listener_with_user_data(Playback {
+            render,
+            scratch: vec![0.0; CHUNK_FRAMES * channels],
+            channels,
+            xruns,
+        })
+        .process(|stream, playback| {
+            let Some(mut buffer) = stream.dequeue_buffer() else {
+                return;
+            };
+            let data = &mut buffer.datas_mut()[0];
+            let stride = playback.channels * 4;
+            let Some(bytes) = data.data() else {
+                return;
+            };
+            let frames = bytes.len() / stride;
+            playback.xruns.record_callback(frames as u64);
+
+            // Rendered in chunks of the scratch buffer, then written out as
+            // little endian f32
+            for out in bytes[..frames * stride].chunks_mut(CHUNK_FRAMES * stride) {
+                let scratch = &mut playback.scratch[..out.len() / 4];
+                (playback.render)(scratch);
+                for (raw, sample) in out.chunks_exact_mut(4).zip(scratch.iter()) {
+                    raw.copy_from_slice(&sample.to_le_bytes());
+                }
+            }
+            let chunk = data.chunk_mut();
+            *chunk.offset_mut() = 0;
+            *chunk.stride_mut() = stride as i32;
+            *chunk.size_mut() = (frames * stride) as u32;
+        })
+        .register()?;
+
+    // Both nodes carry interleaved f32 at the engine's rate
+    let format = format_pod(channels)?;
+    let flags = StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | StreamFlags::RT_PROCESS;
+    capture.connect(
+        pw::spa::utils::Direction::Input,
+        None,
+        flags,
+        &mut [pod(&format)?],
+    )?;
+    playback.connect(
+        pw::spa::utils::Direction::Output,
+        None,
+        flags,
+        &mut [pod(&format)?],
+    )?;
+
+    let _ = ready.send(Ok(()));
+    mainloop.run();
+    Ok(())
+}
+
+/// Serialized `EnumFormat` parameter for interleaved f32 audio.
+fn format_pod(channels: usize) -> Result<Vec<u8>> {
+    let mut info = AudioInfoRaw::new();
+    info.set_format(AudioFormat::F32LE);
+    info.set_rate(SAMPLE_RATE);
+    info.set_channels(channels as u32);
+    let object = Value::Object(Object {
+        type_: pw::spa::utils::SpaTypes::ObjectParamFormat.as_raw(),
+        id: pw::spa::param::ParamType::EnumFormat.as_raw(),
+        properties: info.into(),
+    });
+    let (cursor, _) = PodSerializer::serialize(Cursor::new(Vec::new()), &object)
+        .map_err(|err| anyhow!("Failed to build the PipeWire format: {}", err))?;
+    Ok(cursor.into_inner())
+}
+
+fn pod(bytes: &[u8]) -> Result<&Pod> {
+    Pod::from_bytes(bytes).ok_or_else(|| anyhow!("Invalid PipeWire format"))
+}
@@ -0,0 +1,288 @@
+use crate::resample::{Quality, Resampler};
+use crate::routing::ChannelMap;
+use crate::source::Source;
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tracing::info;
+
+/// `seek_to` value meaning no seek is pending.
+const NO_SEEK: u64 = u64::MAX;
+
+/// Transport of one file player, shared with the control thread.
+#[derive(Debug)]
+pub struct Transport {
+    pub name: String,
+    pub sample_rate: u32,
+    /// Track length in output frames.
+    pub length: u64,
+    playing: AtomicBool,
+    looping: AtomicBool,
+    position: AtomicU64,
+    seek_to: AtomicU64,
+}
+
+impl Transport {
+    pub fn playing(&self) -> bool {
+        self.playing.load(Ordering::Relaxed)
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+
+    /// Pauses and rewinds to the start.
+    pub fn stop(&self) {
+        self.pause();
+        self.seek(0.0);
+    }
+
+    pub fn looping(&self) -> bool {
+        self.looping.load(Ordering::Relaxed)
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.looping.store(looping, Ordering::Relaxed);
+    }
+
+    /// Jumps to `seconds` from the start, clamped to the track. Takes
+    /// effect on the next audio callback.
+    pub fn seek(&self, seconds: f32) {
+        let frame = (seconds.max(0.0) as f64 * self.sample_rate as f64) as u64;
+        self.seek_to
+            .store(frame.min(self.length), Ordering::Relaxed);
+    }
+
+    pub fn position_seconds(&self) -> f32 {
+        self.position.load(Ordering::Relaxed) as f32 / self.sample_rate as f32
+    }
+
+    pub fn length_seconds(&self) -> f32 {
+        self.length as f32 / self.sample_rate as f32
+    }
+}
+
+/// Audio side of a backing track. The whole file is decoded, resampled and
+/// routed up front so the callback only copies samples and can seek
+/// anywhere without touching the disk.
+pub struct FilePlayer {
+    name: String,
+    transport: Arc<Transport>,
+    channels: usize,
+    /// Interleaved at the output rate and channel count.
+    samples: Vec<f32>,
+    position: usize,
+}
+
+impl Source for FilePlayer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        let seek_to = self.transport.seek_to.swap(NO_SEEK, Ordering::Relaxed);
+        if seek_to != NO_SEEK {
+            self.position = seek_to as usize;
+        }
+
+        let frames = self.samples.len() / self.channels;
+        for frame in data.chunks_mut(channels) {
+            if self.position >= frames && self.transport.playing() {
+                if self.transport.looping() {
+                    self.position = 0;
+                } else {
+                    // Rewind at the end so `play` starts the track again
+                    self.transport.pause();
+                    self.position = 0;
+                }
+            }
+
+            if !self.transport.playing() || channels != self.channels {
+                frame.iter_mut().for_each(|s| *s = 0.0);
+                continue;
+            }
+            let start = self.position * self.channels;
+            frame.copy_from_slice(&self.samples[start..start + self.channels]);
+            self.position += 1;
+        }
+        self.transport
+            .position
+            .store(self.position as u64, Ordering::Relaxed);
+    }
+}
+
+/// Decodes `path` into a player for the output format. The player starts
+/// out playing.
+pub fn open(
+    path: &Path,
+    output_rate: u32,
+    output_channels: usize,
+    quality: Quality,
+) -> Result<(FilePlayer, Arc<Transport>)> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let decoded = match extension.as_deref() {
+        Some("wav") => decode_wav(path)?,
+        #[cfg(feature = "symphonia")]
+        _ => decode_symphonia(path)?,
+        #[cfg(not(feature = "symphonia"))]
+        _ => bail!(
+            "Cannot play {}: only WAV is built in, mp3, flac and ogg need `--features symphonia`",
+            path.display()
+        ),
+    };
+    if decoded.channels == 0 || decoded.samples.is_empty() {
+        bail!("{} has no audio", path.display());
+    }
+
+    // Resample the whole track once, then flush the filter tail
+    let mut resampler = Resampler::new(decoded.sample_rate, output_rate, decoded.channels, quality);
+    let input_frames = decoded.samples.len() / decoded.channels;
+    let output_frames =
+        (input_frames as u64 * output_rate as u64 / decoded.sample_rate as u64) as usize;
+    let channel_map = ChannelMap::default_for(decoded.channels, output_channels);
+    let mut samples = Vec::with_capacity((output_frames + 1) * output_channels);
+    let mut routed = vec![0.0f32; output_channels];
+    let mut route = |frame: &[f32]| {
+        channel_map.apply(frame, &mut routed);
+        samples.extend_from_slice(&routed);
+    };
+    resampler.process(&decoded.samples, &mut route);
+    resampler.process(&vec![0.0; 64 * decoded.channels], &mut route);
+    samples.truncate(output_frames * output_channels);
+
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("file")
+        .to_string();
+    info!(
+        "Player {}: {} Hz, {} channels, {:.1} s, channel map {}",
+        name,
+        decoded.sample_rate,
+        decoded.channels,
+        output_frames as f32 / output_rate as f32,
+        channel_map.describe()
+    );
+
+    let transport = Arc::new(Transport {
+        name: name.clone(),
+        sample_rate: output_rate,
+        length: output_frames as u64,
+        playing: AtomicBool::new(true),
+        looping: AtomicBool::new(false),
+        position: AtomicU64::new(0),
+        seek_to: AtomicU64::new(NO_SEEK),
+    });
+    let player = FilePlayer {
+        name,
+        transport: transport.clone(),
+        channels: output_channels,
+        samples,
+        position: 0,
+    };
+    Ok((player, transport))
+}
+
+/// Interleaved samples at the file's own rate and channel count.
+struct Decoded {
+    sample_rate: u32,
+    channels: usize,
+    samples: Vec<f32>,
+}
+
+fn decode_wav(path: &Path) -> Result<Decoded> {
+    let reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let spec = reader.spec();
+    let samples = match spec.sample_format {
+        hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    Ok(Decoded {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels as usize,
+        samples,
+    })
+}
+
+/// Any format symphonia can probe: mp3, flac, ogg/vorbis, aac...
+#[cfg(feature = "symphonia")]
+fn decode_symphonia(path: &Path) -> Result<Decoded> {
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::errors::Error;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .with_context(|| format!("Unsupported audio file {}", path.display()))?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .with_context(|| format!("{} has no audio track", path.display()))?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut channels = 0;
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is skipped rather than ending playback
+            Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        let spec = *decoded.spec();
+        sample_rate = spec.rate;
+        channels = spec.channels.count();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    Ok(Decoded {
+        sample_rate,
+        channels,
+        samples,
+    })
+}
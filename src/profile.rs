@@ -0,0 +1,165 @@
+use crate::control::{Command, Controls};
+use crate::supervisor::{Direction, Supervisor};
+use anyhow::{Context, Result, bail};
+use cpal::traits::DeviceTrait;
+use std::fs;
+use std::path::Path;
+
+/// A named set of devices and console commands, switched to with
+/// `profile <name>`.
+///
+/// Profiles live in a plain text file. A `[name]` line starts one, `input =`
+/// and `output =` lines pick devices by part of their name, and every other
+/// line is a console command run on the switch:
+///
+/// ```text
+/// [podcast]
+/// input = MV7
+/// output = Scarlett
+/// gain 1 -3
+/// set compressor.ratio 4
+/// ```
+///
+/// `input` lines are taken in order, one per open input. Blank lines and
+/// lines starting with `#` are skipped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub inputs: Vec<String>,
+    pub output: Option<String>,
+    pub commands: Vec<String>,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Profile>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read profiles from {}", path.display()))?;
+    parse(&text).with_context(|| format!("Invalid profiles in {}", path.display()))
+}
+
+pub fn parse(text: &str) -> Result<Vec<Profile>> {
+    let mut profiles: Vec<Profile> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                bail!(
+                    "Line {}: profile names can't be empty or hold spaces",
+                    number + 1
+                );
+            }
+            if profiles.iter().any(|p| p.name == name) {
+                bail!("Line {}: profile {} is defined twice", number + 1, name);
+            }
+            profiles.push(Profile {
+                name: name.to_string(),
+                inputs: Vec::new(),
+                output: None,
+                commands: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(profile) = profiles.last_mut() else {
+            bail!("Line {}: expected a [profile] line first", number + 1);
+        };
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("input", device)) => profile.inputs.push(device.to_string()),
+            Some(("output", device)) => profile.output = Some(device.to_string()),
+            _ => {
+                // Checked now so a typo shows up at startup, not mid-show
+                match Command::parse(line).with_context(|| format!("Line {}", number + 1))? {
+                    Command::Profile(_) | Command::Quit => {
+                        bail!("Line {}: '{}' can't be used in a profile", number + 1, line)
+                    }
+                    _ => profile.commands.push(line.to_string()),
+                }
+            }
+        }
+    }
+    Ok(profiles)
+}
+
+pub fn find<'a>(profiles: &'a [Profile], name: &str) -> Result<&'a Profile> {
+    profiles.iter().find(|p| p.name == name).with_context(|| {
+        let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+        if names.is_empty() {
+            "No profiles loaded, pass --profiles <FILE>".to_string()
+        } else {
+            format!("No profile '{}', there are: {}", name, names.join(", "))
+        }
+    })
+}
+
+/// Moves the streams to the profile's devices and runs its commands. Each
+/// stream moved crossfades, see `Supervisor::switch`. Without a supervisor,
+/// as in JACK and PipeWire mode, only the commands are run.
+pub fn switch(
+    profile: &Profile,
+    controls: &Controls,
+    supervisor: Option<&mut Supervisor>,
+) -> Result<String> {
+    let mut lines = Vec::new();
+    let switches_devices = !profile.inputs.is_empty() || profile.output.is_some();
+    match supervisor {
+        Some(supervisor) if switches_devices => {
+            // Every device is found before anything is touched, so a
+            // missing one leaves the streams as they were
+            let inputs = profile
+                .inputs
+                .iter()
+                .map(|name| supervisor.find_device(Direction::Input, name))
+                .collect::<Result<Vec<_>>>()?;
+            let output = profile
+                .output
+                .as_ref()
+                .map(|name| supervisor.find_device(Direction::Output, name))
+                .transpose()?;
+
+            let mut result: Result<()> = Ok(());
+            let moves = inputs
+                .iter()
+                .enumerate()
+                .map(|(index, device)| (Direction::Input, index, device))
+                .chain(output.iter().map(|device| (Direction::Output, 0, device)));
+            for (direction, index, device) in moves {
+                result = result.and_then(|()| {
+                    if supervisor.switch(direction, index, device)? {
+                        lines.push(format!("Switched to {}", device.description()?));
+                    }
+                    Ok(())
+                });
+            }
+            result?;
+            run_commands(profile, controls, &mut lines)?;
+        }
+        _ => {
+            if switches_devices {
+                lines.push("Devices are left to the audio graph".to_string());
+            }
+            run_commands(profile, controls, &mut lines)?;
+        }
+    }
+    *controls.active_profile.lock().unwrap() = Some(profile.name.clone());
+    lines.push(format!("Profile {} active", profile.name));
+    Ok(lines.join("\n"))
+}
+
+/// Runs only the profile's commands, for the profile started with.
+pub fn run_startup(profile: &Profile, controls: &Controls) -> Result<String> {
+    let mut lines = Vec::new();
+    run_commands(profile, controls, &mut lines)?;
+    *controls.active_profile.lock().unwrap() = Some(profile.name.clone());
+    lines.push(format!("Profile {} active", profile.name));
+    Ok(lines.join("\n"))
+}
+
+fn run_commands(profile: &Profile, controls: &Controls, lines: &mut Vec<String>) -> Result<()> {
+    for command in &profile.commands {
+        lines.push(Command::parse(command)?.apply(controls)?);
+    }
+    Ok(())
+}
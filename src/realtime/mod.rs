@@ -0,0 +1,460 @@
+#[cfg(target_os = "linux")]
+mod rtkit;
+#[cfg(target_os = "macos")]
+mod workgroup;
+
+use cpal::Device;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// SCHED_FIFO priority of the audio threads without `--rt-priority`, under
+/// JACK's and PipeWire's own threads but over anything desktop.
+pub const DEFAULT_PRIORITY: u8 = 70;
+/// Promotions queued for the reporting thread, one per stream started.
+const CAPACITY: usize = 32;
+
+/// How an audio thread ended up scheduled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheduling {
+    /// The stream hasn't called back yet.
+    Pending,
+    /// Left alone with `--no-realtime`.
+    Off,
+    /// SCHED_FIFO at this priority, set by the thread itself.
+    #[cfg(unix)]
+    Fifo(u8),
+    /// SCHED_FIFO at this priority, granted by rtkit.
+    #[cfg(target_os = "linux")]
+    RtKit(u8),
+    /// Joined the MMCSS "Pro Audio" task.
+    #[cfg(windows)]
+    Mmcss,
+    /// Time-constraint policy set by the thread itself.
+    #[cfg(target_os = "macos")]
+    TimeConstraint,
+    /// Already time-constrained, as CoreAudio's I/O threads are.
+    #[cfg(target_os = "macos")]
+    AlreadyRealtime,
+    /// Left at normal priority, with the OS error of the last attempt.
+    #[cfg(any(unix, windows))]
+    Normal(i32),
+    /// The platform has no way to ask.
+    #[cfg(not(any(unix, windows)))]
+    Unsupported,
+}
+
+impl Scheduling {
+    pub fn is_realtime(self) -> bool {
+        match self {
+            #[cfg(unix)]
+            Scheduling::Fifo(_) => true,
+            #[cfg(target_os = "linux")]
+            Scheduling::RtKit(_) => true,
+            #[cfg(windows)]
+            Scheduling::Mmcss => true,
+            #[cfg(target_os = "macos")]
+            Scheduling::TimeConstraint | Scheduling::AlreadyRealtime => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Scheduling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheduling::Pending => write!(f, "not called back yet"),
+            Scheduling::Off => write!(f, "normal priority, --no-realtime"),
+            #[cfg(unix)]
+            Scheduling::Fifo(priority) => write!(f, "SCHED_FIFO priority {}", priority),
+            #[cfg(target_os = "linux")]
+            Scheduling::RtKit(priority) => {
+                write!(f, "SCHED_FIFO priority {} through rtkit", priority)
+            }
+            #[cfg(windows)]
+            Scheduling::Mmcss => write!(f, "MMCSS Pro Audio task"),
+            #[cfg(target_os = "macos")]
+            Scheduling::TimeConstraint => write!(f, "time-constraint policy"),
+            #[cfg(target_os = "macos")]
+            Scheduling::AlreadyRealtime => {
+                write!(f, "time-constrained by CoreAudio")
+            }
+            #[cfg(any(unix, windows))]
+            Scheduling::Normal(code) => write!(
+                f,
+                "normal priority, {}",
+                std::io::Error::from_raw_os_error(*code)
+            ),
+            #[cfg(not(any(unix, windows)))]
+            Scheduling::Unsupported => {
+                write!(
+                    f,
+                    "normal priority, no real-time scheduling on this platform"
+                )
+            }
+        }
+    }
+}
+
+/// One audio thread as reported, named after its stream. A stream rebuilt
+/// on another device reports its new thread under the same name.
+#[derive(Debug)]
+struct AudioThread {
+    name: String,
+    scheduling: Mutex<Scheduling>,
+}
+
+/// What a thread got when it asked, sent to the reporting thread.
+struct Attempt {
+    thread: Arc<AudioThread>,
+    /// Kernel thread ID, for rtkit to promote from outside.
+    #[cfg(target_os = "linux")]
+    tid: i32,
+    scheduling: Scheduling,
+    /// How joining the device's audio workgroup went, 0 or the error.
+    /// None when the device has none.
+    #[cfg(target_os = "macos")]
+    workgroup: Option<i32>,
+}
+
+/// Promotes the audio threads of the device streams to real-time
+/// scheduling: SCHED_FIFO on Linux, asking rtkit when the process has no
+/// rtprio limit or CAP_SYS_NICE, MMCSS's "Pro Audio" task on Windows, and
+/// a time-constraint policy on macOS for threads CoreAudio didn't already
+/// set one for. On macOS the thread also joins its device's audio
+/// workgroup. A thread that can't be promoted keeps running at normal
+/// priority, and the reason is logged.
+///
+/// JACK and PipeWire schedule their own threads, so only the cpal streams
+/// ask.
+#[derive(Debug, Default)]
+pub struct Realtime {
+    /// Unset with `--no-realtime`, and for JACK and PipeWire.
+    requests: Option<SyncSender<Attempt>>,
+    priority: u8,
+    threads: Mutex<Vec<Arc<AudioThread>>>,
+}
+
+impl Realtime {
+    /// Starts the thread that reports each promotion, and asks rtkit on
+    /// Linux for the threads that couldn't promote themselves.
+    pub fn spawn(enabled: bool, priority: u8) -> std::io::Result<Realtime> {
+        if !enabled {
+            return Ok(Realtime::default());
+        }
+        let (requests, attempts) = mpsc::sync_channel(CAPACITY);
+        thread::Builder::new()
+            .name("realtime".to_string())
+            .spawn(move || run(attempts, priority))?;
+        Ok(Realtime {
+            requests: Some(requests),
+            priority,
+            threads: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Hands a stream's callback what it needs to promote its thread, at
+    /// the start of its first call. `sample_rate` turns the first call's
+    /// length into the period of the time constraint on macOS, where the
+    /// `device`'s audio workgroup is looked up here, off the audio thread.
+    pub fn promoter(
+        &self,
+        name: &str,
+        sample_rate: u32,
+        #[cfg_attr(not(target_os = "macos"), allow(unused_variables))] device: &Device,
+    ) -> Promoter {
+        let mut threads = self.threads.lock().unwrap();
+        let thread = match threads.iter().find(|t| t.name == name) {
+            Some(thread) => thread.clone(),
+            None => {
+                let thread = Arc::new(AudioThread {
+                    name: name.to_string(),
+                    scheduling: Mutex::new(Scheduling::Pending),
+                });
+                threads.push(thread.clone());
+                thread
+            }
+        };
+        *thread.scheduling.lock().unwrap() = if self.requests.is_some() {
+            Scheduling::Pending
+        } else {
+            Scheduling::Off
+        };
+        Promoter {
+            thread,
+            requests: self.requests.clone(),
+            priority: self.priority,
+            sample_rate,
+            #[cfg(target_os = "macos")]
+            workgroup: self
+                .requests
+                .as_ref()
+                .and_then(|_| workgroup::Workgroup::of(device)),
+            done: false,
+        }
+    }
+
+    /// How each stream's thread is scheduled.
+    pub fn report(&self) -> String {
+        let threads = self.threads.lock().unwrap();
+        if threads.is_empty() {
+            return "No device streams, JACK and PipeWire schedule their own threads".to_string();
+        }
+        threads
+            .iter()
+            .map(|t| format!("{}: {}", t.name, t.scheduling.lock().unwrap()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Audio thread side: promotes the thread it's first called on. After
+/// that, calls return at once.
+pub struct Promoter {
+    thread: Arc<AudioThread>,
+    requests: Option<SyncSender<Attempt>>,
+    priority: u8,
+    sample_rate: u32,
+    #[cfg(target_os = "macos")]
+    workgroup: Option<workgroup::Workgroup>,
+    done: bool,
+}
+
+impl Promoter {
+    /// Call at the start of every callback with the frames it got. The
+    /// first call makes a system call or two, nothing that blocks, and
+    /// leaves the reporting and any rtkit request to another thread.
+    pub fn promote(&mut self, frames: usize) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        let Some(requests) = &self.requests else {
+            return;
+        };
+        let period = Duration::from_secs_f64(frames as f64 / self.sample_rate.max(1) as f64);
+        let attempt = Attempt {
+            thread: self.thread.clone(),
+            #[cfg(target_os = "linux")]
+            tid: rtkit::current_tid(),
+            scheduling: promote_current(self.priority, period),
+            #[cfg(target_os = "macos")]
+            workgroup: self.workgroup.as_ref().map(|workgroup| workgroup.join()),
+        };
+        let _ = requests.try_send(attempt);
+    }
+}
+
+/// Runs until every promoter and the `Realtime` are dropped.
+fn run(attempts: Receiver<Attempt>, priority: u8) {
+    for attempt in attempts {
+        let scheduling = fall_back(&attempt, priority);
+        let name = &attempt.thread.name;
+        if scheduling.is_realtime() {
+            info!(thread = %name, "Audio thread runs at {}", scheduling);
+        } else {
+            warn!(
+                thread = %name,
+                "Audio thread runs at {}, expect dropouts at small buffer sizes",
+                scheduling
+            );
+        }
+        #[cfg(target_os = "macos")]
+        report_workgroup(&attempt);
+        *attempt.thread.scheduling.lock().unwrap() = scheduling;
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn report_workgroup(attempt: &Attempt) {
+    let name = &attempt.thread.name;
+    match attempt.workgroup {
+        None => {}
+        Some(0) => info!(thread = %name, "Audio thread joined the device's audio workgroup"),
+        Some(workgroup::EALREADY) => {
+            info!(thread = %name, "Audio thread is already in the device's audio workgroup")
+        }
+        Some(err) => warn!(
+            thread = %name,
+            "Audio thread couldn't join the device's audio workgroup: {}",
+            std::io::Error::from_raw_os_error(err)
+        ),
+    }
+}
+
+/// Asks rtkit for a thread that wasn't allowed to promote itself.
+#[cfg(target_os = "linux")]
+fn fall_back(attempt: &Attempt, priority: u8) -> Scheduling {
+    if !matches!(attempt.scheduling, Scheduling::Normal(_)) {
+        return attempt.scheduling;
+    }
+    let priority = priority.min(rtkit::MAX_PRIORITY);
+    match rtkit::make_realtime(attempt.tid, priority) {
+        Ok(()) => Scheduling::RtKit(priority),
+        Err(err) => {
+            warn!(
+                thread = %attempt.thread.name,
+                "rtkit refused real-time scheduling: {:#}. Give the user an rtprio limit, \
+                 e.g. through the audio group, or start rtkit-daemon",
+                err
+            );
+            attempt.scheduling
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fall_back(attempt: &Attempt, _priority: u8) -> Scheduling {
+    attempt.scheduling
+}
+
+#[cfg(target_os = "linux")]
+fn promote_current(priority: u8, _period: Duration) -> Scheduling {
+    let param = libc::sched_param {
+        sched_priority: priority as libc::c_int,
+    };
+    // Reset on fork so nothing started from an audio thread inherits it,
+    // rtkit asks the same
+    let policy = libc::SCHED_FIFO | libc::SCHED_RESET_ON_FORK;
+    // SAFETY: `param` outlives the call, 0 is the calling thread
+    if unsafe { libc::sched_setscheduler(0, policy, &param) } == 0 {
+        Scheduling::Fifo(priority)
+    } else {
+        last_error()
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+fn promote_current(priority: u8, _period: Duration) -> Scheduling {
+    let param = libc::sched_param {
+        sched_priority: priority as libc::c_int,
+    };
+    // SAFETY: `param` outlives the call, the thread is the calling one
+    let err =
+        unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    if err == 0 {
+        Scheduling::Fifo(priority)
+    } else {
+        Scheduling::Normal(err)
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn promote_current(_priority: u8, period: Duration) -> Scheduling {
+    use mach::*;
+
+    // SAFETY: the calling thread's own port, and policy structs sized
+    // as the count says
+    unsafe {
+        let thread = libc::pthread_mach_thread_np(libc::pthread_self());
+        let mut policy = TimeConstraintPolicy::default();
+        let mut count = POLICY_COUNT;
+        let mut default = 0;
+        let err = thread_policy_get(
+            thread,
+            THREAD_TIME_CONSTRAINT_POLICY,
+            &mut policy,
+            &mut count,
+            &mut default,
+        );
+        if err == 0 && default == 0 {
+            return Scheduling::AlreadyRealtime;
+        }
+
+        // The callback must finish within its period and gets about half
+        // of it to compute in, as CoreAudio's own threads do
+        let mut timebase = Timebase::default();
+        mach_timebase_info(&mut timebase);
+        let ticks = |duration: Duration| {
+            (duration.as_nanos() as u64 * timebase.denom as u64 / timebase.numer.max(1) as u64)
+                as u32
+        };
+        let policy = TimeConstraintPolicy {
+            period: ticks(period),
+            computation: ticks(period / 2),
+            constraint: ticks(period),
+            preemptible: 1,
+        };
+        match thread_policy_set(thread, THREAD_TIME_CONSTRAINT_POLICY, &policy, POLICY_COUNT) {
+            0 => Scheduling::TimeConstraint,
+            err => Scheduling::Normal(err),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod mach {
+    pub const THREAD_TIME_CONSTRAINT_POLICY: u32 = 2;
+    /// Fields of `TimeConstraintPolicy`, in 32-bit words.
+    pub const POLICY_COUNT: u32 = 4;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct TimeConstraintPolicy {
+        pub period: u32,
+        pub computation: u32,
+        pub constraint: u32,
+        pub preemptible: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Timebase {
+        pub numer: u32,
+        pub denom: u32,
+    }
+
+    unsafe extern "C" {
+        pub fn thread_policy_get(
+            thread: u32,
+            flavor: u32,
+            policy: *mut TimeConstraintPolicy,
+            count: *mut u32,
+            get_default: *mut i32,
+        ) -> i32;
+        pub fn thread_policy_set(
+            thread: u32,
+            flavor: u32,
+            policy: *const TimeConstraintPolicy,
+            count: u32,
+        ) -> i32;
+        pub fn mach_timebase_info(timebase: *mut Timebase) -> i32;
+    }
+}
+
+#[cfg(windows)]
+fn promote_current(_priority: u8, _period: Duration) -> Scheduling {
+    // "Pro Audio" as a nul-terminated UTF-16 string, built on the stack
+    let mut task = [0u16; 10];
+    for (unit, c) in task.iter_mut().zip("Pro Audio".encode_utf16()) {
+        *unit = c;
+    }
+    let mut index = 0u32;
+    // SAFETY: both pointers outlive the call. The task is left when the
+    // thread ends with its stream
+    let handle = unsafe { AvSetMmThreadCharacteristicsW(task.as_ptr(), &mut index) };
+    if handle.is_null() {
+        last_error()
+    } else {
+        Scheduling::Mmcss
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "avrt")]
+unsafe extern "system" {
+    fn AvSetMmThreadCharacteristicsW(task: *const u16, index: *mut u32) -> *mut std::ffi::c_void;
+}
+
+#[cfg(not(any(unix, windows)))]
+fn promote_current(_priority: u8, _period: Duration) -> Scheduling {
+    Scheduling::Unsupported
+}
+
+#[cfg(any(target_os = "linux", windows))]
+fn last_error() -> Scheduling {
+    Scheduling::Normal(std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+}
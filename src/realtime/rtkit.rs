@@ -0,0 +1,307 @@
+use anyhow::{Context, Result, bail};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// rtkit's default `MaxRealtimePriority`, it refuses anything higher.
+pub const MAX_PRIORITY: u8 = 20;
+/// CPU time a real-time thread may use without blocking, in microseconds.
+/// rtkit only serves processes whose RLIMIT_RTTIME is at most its own
+/// limit, 200 ms by default.
+const RTTIME_US: u64 = 200_000;
+const SYSTEM_BUS: &str = "/var/run/dbus/system_bus_socket";
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Message types in the D-Bus header.
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+/// Header field codes.
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+pub fn current_tid() -> i32 {
+    // SAFETY: gettid takes no arguments and can't fail
+    unsafe { libc::syscall(libc::SYS_gettid) as i32 }
+}
+
+/// Asks rtkit for SCHED_FIFO at `priority` for thread `tid` of this
+/// process. Blocks on the system bus, so never call it from the thread
+/// being promoted.
+pub fn make_realtime(tid: i32, priority: u8) -> Result<()> {
+    limit_rttime()?;
+    let mut bus = Bus::connect()?;
+    bus.call(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        "org.freedesktop.DBus",
+        "Hello",
+        "",
+        &[],
+    )?;
+    let mut body = Vec::new();
+    body.extend_from_slice(&(tid as u64).to_le_bytes());
+    body.extend_from_slice(&(priority as u32).to_le_bytes());
+    bus.call(
+        "org.freedesktop.RealtimeKit1",
+        "/org/freedesktop/RealtimeKit1",
+        "org.freedesktop.RealtimeKit1",
+        "MakeThreadRealtime",
+        "tu",
+        &body,
+    )
+}
+
+/// Lowers RLIMIT_RTTIME to rtkit's limit. A thread over it gets SIGXCPU,
+/// which only a callback spinning for 200 ms straight would reach.
+fn limit_rttime() -> Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` outlives both calls
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_RTTIME, &mut limit) != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to read RLIMIT_RTTIME");
+        }
+        let wanted = (RTTIME_US as libc::rlim_t).min(limit.rlim_max);
+        limit.rlim_cur = wanted;
+        limit.rlim_max = wanted;
+        if libc::setrlimit(libc::RLIMIT_RTTIME, &limit) != 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set RLIMIT_RTTIME");
+        }
+    }
+    Ok(())
+}
+
+/// Just enough of a D-Bus client to make method calls without arguments
+/// coming back: little-endian messages, EXTERNAL authentication.
+struct Bus {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+    serial: u32,
+}
+
+impl Bus {
+    fn connect() -> Result<Bus> {
+        let path = system_bus_path();
+        let mut stream = UnixStream::connect(&path)
+            .with_context(|| format!("Failed to connect to the system bus at {}", path))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // The bus knows the user from the socket, the name only has to match
+        // SAFETY: getuid can't fail
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        stream.write_all(b"\0")?;
+        write!(stream, "AUTH EXTERNAL {}\r\n", hex)?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            bail!("The system bus refused authentication: {}", line.trim());
+        }
+        stream.write_all(b"BEGIN\r\n")?;
+        Ok(Bus {
+            stream,
+            reader,
+            serial: 0,
+        })
+    }
+
+    /// Calls `member` and waits for its reply, failing on an error reply.
+    fn call(
+        &mut self,
+        destination: &str,
+        path: &str,
+        interface: &str,
+        member: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<()> {
+        self.serial += 1;
+        let mut fields = Vec::new();
+        put_field(&mut fields, FIELD_PATH, b'o', path);
+        put_field(&mut fields, FIELD_DESTINATION, b's', destination);
+        put_field(&mut fields, FIELD_INTERFACE, b's', interface);
+        put_field(&mut fields, FIELD_MEMBER, b's', member);
+        if !signature.is_empty() {
+            put_field(&mut fields, FIELD_SIGNATURE, b'g', signature);
+        }
+
+        let mut message = vec![b'l', METHOD_CALL, 0, 1];
+        message.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        message.extend_from_slice(&self.serial.to_le_bytes());
+        message.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        message.extend_from_slice(&fields);
+        pad(&mut message, 8);
+        message.extend_from_slice(body);
+        self.stream.write_all(&message)?;
+        self.reply(self.serial)
+            .with_context(|| format!("{} failed", member))
+    }
+
+    /// Reads messages until the reply to `serial`, skipping signals such
+    /// as `NameAcquired` on the way.
+    fn reply(&mut self, serial: u32) -> Result<()> {
+        loop {
+            let mut fixed = [0u8; 16];
+            self.reader.read_exact(&mut fixed)?;
+            let little = fixed[0] == b'l';
+            let kind = fixed[1];
+            let body_len = Fields::u32_from(&fixed[4..8], little) as usize;
+            let fields_len = Fields::u32_from(&fixed[12..16], little) as usize;
+            // The header is padded to 8 bytes before the body
+            let header_len = (16 + fields_len).next_multiple_of(8);
+            let mut rest = vec![0u8; header_len - 16 + body_len];
+            self.reader.read_exact(&mut rest)?;
+
+            let mut fields = Fields {
+                bytes: &rest[..fields_len],
+                at: 0,
+                little,
+            };
+            let (reply_serial, error) = fields.parse()?;
+            if reply_serial != Some(serial) {
+                continue;
+            }
+            match kind {
+                METHOD_RETURN => return Ok(()),
+                ERROR => {
+                    let mut body = Fields {
+                        bytes: &rest[header_len - 16..],
+                        at: 0,
+                        little,
+                    };
+                    let message = body.string().unwrap_or_default();
+                    bail!("{}: {}", error.unwrap_or_default(), message);
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// A header field, a code and a variant holding one string-like value.
+/// Each starts on an 8-byte boundary, the fields start on one too.
+fn put_field(fields: &mut Vec<u8>, code: u8, kind: u8, value: &str) {
+    pad(fields, 8);
+    fields.extend_from_slice(&[code, 1, kind, 0]);
+    if kind == b'g' {
+        fields.push(value.len() as u8);
+    } else {
+        fields.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    }
+    fields.extend_from_slice(value.as_bytes());
+    fields.push(0);
+}
+
+fn pad(bytes: &mut Vec<u8>, alignment: usize) {
+    bytes.resize(bytes.len().next_multiple_of(alignment), 0);
+}
+
+/// Reads the header fields of a reply, or the start of its body, which
+/// are both 8-byte aligned from the start of the message.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    little: bool,
+}
+
+impl Fields<'_> {
+    /// The reply serial and error name, when there are.
+    fn parse(&mut self) -> Result<(Option<u32>, Option<String>)> {
+        let (mut reply_serial, mut error) = (None, None);
+        while self.at < self.bytes.len() {
+            self.align(8);
+            let code = self.byte()?;
+            let signature = self.signature()?;
+            match signature.as_str() {
+                "u" => {
+                    let value = self.u32()?;
+                    if code == FIELD_REPLY_SERIAL {
+                        reply_serial = Some(value);
+                    }
+                }
+                "s" | "o" => {
+                    let value = self.string()?;
+                    if code == FIELD_ERROR_NAME {
+                        error = Some(value);
+                    }
+                }
+                "g" => {
+                    self.signature()?;
+                }
+                other => bail!("Unexpected header field type '{}'", other),
+            }
+        }
+        Ok((reply_serial, error))
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.at = self.at.next_multiple_of(alignment);
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let bytes = self
+            .bytes
+            .get(self.at..self.at + len)
+            .context("Truncated D-Bus message")?;
+        self.at += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.align(4);
+        let little = self.little;
+        Ok(Self::u32_from(self.take(4)?, little))
+    }
+
+    fn u32_from(bytes: &[u8], little: bool) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    /// A string or object path: its length, the bytes and a nul.
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        let text = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(text)
+    }
+
+    /// A signature: a one-byte length, the bytes and a nul.
+    fn signature(&mut self) -> Result<String> {
+        let len = self.byte()? as usize;
+        let text = String::from_utf8_lossy(self.take(len)?).into_owned();
+        self.take(1)?;
+        Ok(text)
+    }
+}
+
+/// `DBUS_SYSTEM_BUS_ADDRESS` when it names a socket path, the standard
+/// socket otherwise.
+fn system_bus_path() -> String {
+    std::env::var("DBUS_SYSTEM_BUS_ADDRESS")
+        .ok()
+        .and_then(|address| {
+            address.split(';').find_map(|entry| {
+                let path = entry.strip_prefix("unix:path=")?;
+                Some(path.split(',').next().unwrap_or(path).to_string())
+            })
+        })
+        .unwrap_or_else(|| SYSTEM_BUS.to_string())
+}
@@ -0,0 +1,226 @@
+//! The audio workgroup of a CoreAudio device, which macOS 11 and later
+//! schedule the device's I/O threads in on Apple silicon. Threads that
+//! join it run on the performance cores with the device's deadline.
+
+use cpal::traits::DeviceTrait;
+use cpal::{Device, HostId};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void};
+use std::mem;
+use std::ptr;
+
+/// Already a member, as CoreAudio's own I/O threads may be.
+pub const EALREADY: i32 = libc::EALREADY;
+
+/// `kAudioHardwarePropertyTranslateUIDToDevice`
+const TRANSLATE_UID_TO_DEVICE: u32 = u32::from_be_bytes(*b"uidd");
+/// `kAudioDevicePropertyIOThreadOSWorkgroup`
+const IO_THREAD_OS_WORKGROUP: u32 = u32::from_be_bytes(*b"oswg");
+/// `kAudioObjectPropertyScopeGlobal`
+const SCOPE_GLOBAL: u32 = u32::from_be_bytes(*b"glob");
+/// `kAudioObjectSystemObject`
+const SYSTEM_OBJECT: u32 = 1;
+/// `kCFStringEncodingUTF8`
+const UTF8: u32 = 0x0800_0100;
+
+type JoinFn = unsafe extern "C" fn(*mut c_void, *mut JoinToken) -> i32;
+type LeaveFn = unsafe extern "C" fn(*mut c_void, *mut JoinToken);
+
+/// `os_workgroup_join_token_s`, opaque but for its size and alignment.
+type JoinToken = [u32; 10];
+
+/// A retained `os_workgroup_t`, with the functions to join and leave it.
+pub struct Workgroup {
+    handle: *mut c_void,
+    join: JoinFn,
+    leave: LeaveFn,
+}
+
+// SAFETY: workgroups are reference counted and may be joined from any
+// thread, the handle is only read
+unsafe impl Send for Workgroup {}
+
+impl Workgroup {
+    /// The workgroup of a CoreAudio device, looked up before its stream
+    /// starts. None on other hosts, before macOS 11, and for devices
+    /// that have none.
+    pub fn of(device: &Device) -> Option<Workgroup> {
+        let id = device.id().ok()?;
+        if id.0 != HostId::CoreAudio {
+            return None;
+        }
+        let (join, leave) = functions()?;
+        let object = device_object(&id.1)?;
+        let address = PropertyAddress::global(IO_THREAD_OS_WORKGROUP);
+        let mut handle: *mut c_void = ptr::null_mut();
+        let mut size = mem::size_of::<*mut c_void>() as u32;
+        // SAFETY: the property is an os_workgroup_t, retained for the
+        // caller, and `handle` is sized as `size` says
+        let status = unsafe {
+            AudioObjectGetPropertyData(
+                object,
+                &address,
+                0,
+                ptr::null(),
+                &mut size,
+                (&mut handle as *mut *mut c_void).cast(),
+            )
+        };
+        if status != 0 || handle.is_null() {
+            return None;
+        }
+        Some(Workgroup {
+            handle,
+            join,
+            leave,
+        })
+    }
+
+    /// Joins the calling thread to the workgroup until the thread ends,
+    /// leaving any it joined before. Returns 0 or the error of the join.
+    pub fn join(&self) -> i32 {
+        let mut token: JoinToken = [0; 10];
+        // SAFETY: the handle is a live workgroup and the token outlives
+        // the call
+        let err = unsafe { (self.join)(self.handle, &mut token) };
+        if err == 0 {
+            // SAFETY: the membership holds a retain of its own, so the
+            // stream can drop this one first
+            unsafe { os_retain(self.handle) };
+            JOINED.with(|joined| {
+                *joined.borrow_mut() = Some(Membership {
+                    handle: self.handle,
+                    token,
+                    leave: self.leave,
+                })
+            });
+        }
+        err
+    }
+}
+
+impl Drop for Workgroup {
+    fn drop(&mut self) {
+        // SAFETY: releases the retain the property lookup handed over
+        unsafe { os_release(self.handle) };
+    }
+}
+
+/// A thread's place in a workgroup, left from the same thread, as the
+/// token requires, when the thread ends or joins another.
+struct Membership {
+    handle: *mut c_void,
+    token: JoinToken,
+    leave: LeaveFn,
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        // SAFETY: on the thread that joined, with the token the join
+        // filled in, then releases the retain taken for it
+        unsafe {
+            (self.leave)(self.handle, &mut self.token);
+            os_release(self.handle);
+        }
+    }
+}
+
+thread_local! {
+    static JOINED: RefCell<Option<Membership>> = const { RefCell::new(None) };
+}
+
+/// `os_workgroup_join` and `os_workgroup_leave`, looked up at run time
+/// as they're missing before macOS 11.
+fn functions() -> Option<(JoinFn, LeaveFn)> {
+    let lookup = |name: &[u8]| {
+        // SAFETY: a nul-terminated name, looked up in every loaded image
+        let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const c_char) };
+        (!symbol.is_null()).then_some(symbol)
+    };
+    let join = lookup(b"os_workgroup_join\0")?;
+    let leave = lookup(b"os_workgroup_leave\0")?;
+    // SAFETY: both symbols have these signatures in <os/workgroup_object.h>
+    unsafe {
+        Some((
+            mem::transmute::<*mut c_void, JoinFn>(join),
+            mem::transmute::<*mut c_void, LeaveFn>(leave),
+        ))
+    }
+}
+
+/// The AudioObjectID of the device with this UID, which cpal gives as
+/// its device ID.
+fn device_object(uid: &str) -> Option<u32> {
+    // SAFETY: the bytes outlive the call, which copies them
+    let uid =
+        unsafe { CFStringCreateWithBytes(ptr::null(), uid.as_ptr(), uid.len() as isize, UTF8, 0) };
+    if uid.is_null() {
+        return None;
+    }
+    let address = PropertyAddress::global(TRANSLATE_UID_TO_DEVICE);
+    let mut object = 0u32;
+    let mut size = mem::size_of::<u32>() as u32;
+    // SAFETY: the qualifier is the CFStringRef, the result an
+    // AudioObjectID sized as `size` says. The string is released after
+    let status = unsafe {
+        let status = AudioObjectGetPropertyData(
+            SYSTEM_OBJECT,
+            &address,
+            mem::size_of::<*const c_void>() as u32,
+            (&uid as *const *const c_void).cast(),
+            &mut size,
+            (&mut object as *mut u32).cast(),
+        );
+        CFRelease(uid);
+        status
+    };
+    // kAudioObjectUnknown when no device has the UID
+    (status == 0 && object != 0).then_some(object)
+}
+
+/// `AudioObjectPropertyAddress`
+#[repr(C)]
+struct PropertyAddress {
+    selector: u32,
+    scope: u32,
+    element: u32,
+}
+
+impl PropertyAddress {
+    fn global(selector: u32) -> PropertyAddress {
+        PropertyAddress {
+            selector,
+            scope: SCOPE_GLOBAL,
+            element: 0,
+        }
+    }
+}
+
+#[link(name = "CoreAudio", kind = "framework")]
+unsafe extern "C" {
+    fn AudioObjectGetPropertyData(
+        object: u32,
+        address: *const PropertyAddress,
+        qualifier_size: u32,
+        qualifier: *const c_void,
+        size: *mut u32,
+        data: *mut c_void,
+    ) -> i32;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+unsafe extern "C" {
+    fn CFStringCreateWithBytes(
+        allocator: *const c_void,
+        bytes: *const u8,
+        len: isize,
+        encoding: u32,
+        external: u8,
+    ) -> *const c_void;
+    fn CFRelease(object: *const c_void);
+}
+
+unsafe extern "C" {
+    fn os_retain(object: *mut c_void) -> *mut c_void;
+    fn os_release(object: *mut c_void);
+}
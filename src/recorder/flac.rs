@@ -0,0 +1,618 @@
+use super::{BitDepth, Encoder};
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Frames per FLAC block, the reference encoder's default.
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 6;
+/// Rice parameters above this need the 5-bit parameter coding.
+const MAX_RICE_PARAM: u32 = 14;
+const MAX_RICE2_PARAM: u32 = 30;
+
+/// Minimal streaming FLAC encoder: fixed linear predictors with
+/// partitioned Rice residuals, independent channels. Compresses less than
+/// libFLAC but is lossless and needs no native library.
+pub struct FlacEncoder {
+    writer: BufWriter<File>,
+    sample_rate: u32,
+    channels: usize,
+    bits: u32,
+    /// Interleaved samples waiting for a full block.
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_bytes: u32,
+    max_frame_bytes: u32,
+    channel: Vec<i64>,
+    residual: Vec<i64>,
+    out: BitWriter,
+}
+
+impl FlacEncoder {
+    pub fn create(
+        path: &Path,
+        bit_depth: BitDepth,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Self> {
+        if bit_depth == BitDepth::Float32 {
+            bail!("FLAC records 16 or 24 bit, not float");
+        }
+        if !(1..=8).contains(&channels) {
+            bail!("FLAC supports 1 to 8 channels, got {}", channels);
+        }
+
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        let mut encoder = FlacEncoder {
+            writer: BufWriter::new(file),
+            sample_rate,
+            channels,
+            bits: bit_depth.bits(),
+            pending: Vec::with_capacity(BLOCK_SIZE * channels * 2),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_bytes: u32::MAX,
+            max_frame_bytes: 0,
+            channel: Vec::with_capacity(BLOCK_SIZE),
+            residual: Vec::with_capacity(BLOCK_SIZE),
+            out: BitWriter::default(),
+        };
+        encoder.writer.write_all(b"fLaC")?;
+        let header = encoder.stream_info();
+        encoder.writer.write_all(&header)?;
+        Ok(encoder)
+    }
+
+    /// The STREAMINFO metadata block, rewritten with the final totals when
+    /// the file is finished.
+    fn stream_info(&self) -> Vec<u8> {
+        let mut w = BitWriter::default();
+        // Last metadata block, type 0, 34 bytes
+        w.write(1, 1);
+        w.write(0, 7);
+        w.write(34, 24);
+
+        w.write(BLOCK_SIZE as u64, 16);
+        w.write(BLOCK_SIZE as u64, 16);
+        let min_frame = if self.min_frame_bytes == u32::MAX {
+            0
+        } else {
+            self.min_frame_bytes
+        };
+        w.write(min_frame as u64, 24);
+        w.write(self.max_frame_bytes as u64, 24);
+        w.write(self.sample_rate as u64, 20);
+        w.write(self.channels as u64 - 1, 3);
+        w.write(self.bits as u64 - 1, 5);
+        w.write(self.total_frames, 36);
+        // MD5 of the audio, all zero means not computed
+        for _ in 0..4 {
+            w.write(0, 32);
+        }
+        w.bytes
+    }
+
+    fn encode_block(&mut self, frames: usize) -> Result<()> {
+        let mut w = std::mem::take(&mut self.out);
+        w.bytes.clear();
+
+        // Frame header: sync code, fixed block size, 16-bit block size at
+        // the end, sample rate and depth from STREAMINFO
+        w.write(0b11111111111110, 14);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(0b0111, 4);
+        w.write(0b0000, 4);
+        w.write(self.channels as u64 - 1, 4);
+        w.write(if self.bits == 16 { 0b100 } else { 0b110 }, 3);
+        w.write(0, 1);
+        write_utf8(&mut w, self.frame_number);
+        w.write(frames as u64 - 1, 16);
+        let crc = crc8(&w.bytes);
+        w.write(crc as u64, 8);
+
+        for channel in 0..self.channels {
+            self.channel.clear();
+            self.channel.extend(
+                self.pending[..frames * self.channels]
+                    .iter()
+                    .skip(channel)
+                    .step_by(self.channels)
+                    .map(|&s| s as i64),
+            );
+            encode_subframe(&mut w, &self.channel, &mut self.residual, self.bits);
+        }
+
+        w.pad_to_byte();
+        let crc = crc16(&w.bytes);
+        w.write(crc as u64, 16);
+
+        self.writer.write_all(&w.bytes)?;
+        self.min_frame_bytes = self.min_frame_bytes.min(w.bytes.len() as u32);
+        self.max_frame_bytes = self.max_frame_bytes.max(w.bytes.len() as u32);
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        self.pending.drain(..frames * self.channels);
+        self.out = w;
+        Ok(())
+    }
+}
+
+impl Encoder for FlacEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let scale = ((1i64 << (self.bits - 1)) - 1) as f32;
+        self.pending.extend(
+            samples
+                .iter()
+                .map(|&s| (s.clamp(-1.0, 1.0) * scale).round() as i32),
+        );
+        while self.pending.len() >= BLOCK_SIZE * self.channels {
+            self.encode_block(BLOCK_SIZE)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let frames = self.pending.len() / self.channels;
+        if frames > 0 {
+            self.encode_block(frames)?;
+        }
+
+        let header = self.stream_info();
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&header)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Picks the cheapest of a constant, verbatim or fixed-predictor subframe.
+fn encode_subframe(w: &mut BitWriter, samples: &[i64], residual: &mut Vec<i64>, bits: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        w.write(0, 8);
+        w.write_signed(samples[0], bits);
+        return;
+    }
+
+    let mut best: Option<(usize, u64, Partitioning)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+        fixed_residual(samples, order, residual);
+        let partitioning = best_partitioning(residual, samples.len(), order);
+        let cost = order as u64 * bits as u64 + partitioning.cost;
+        if best
+            .as_ref()
+            .is_none_or(|(_, best_cost, _)| cost < *best_cost)
+        {
+            best = Some((order, cost, partitioning));
+        }
+    }
+
+    let verbatim_cost = samples.len() as u64 * bits as u64;
+    match best {
+        Some((order, cost, partitioning)) if cost < verbatim_cost => {
+            w.write((0b001000 | order as u64) << 1, 8);
+            for &sample in &samples[..order] {
+                w.write_signed(sample, bits);
+            }
+            fixed_residual(samples, order, residual);
+            write_residual(w, residual, order, &partitioning);
+        }
+        _ => {
+            w.write(0b000001 << 1, 8);
+            for &sample in samples {
+                w.write_signed(sample, bits);
+            }
+        }
+    }
+}
+
+/// Residual of the fixed polynomial predictor of `order`, skipping the
+/// warm-up samples.
+fn fixed_residual(x: &[i64], order: usize, residual: &mut Vec<i64>) {
+    residual.clear();
+    residual.extend((order..x.len()).map(|i| match order {
+        0 => x[i],
+        1 => x[i] - x[i - 1],
+        2 => x[i] - 2 * x[i - 1] + x[i - 2],
+        3 => x[i] - 3 * x[i - 1] + 3 * x[i - 2] - x[i - 3],
+        _ => x[i] - 4 * x[i - 1] + 6 * x[i - 2] - 4 * x[i - 3] + x[i - 4],
+    }));
+}
+
+struct Partitioning {
+    order: u32,
+    params: Vec<u32>,
+    /// Estimated size of the residual section in bits.
+    cost: u64,
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Tries every Rice partition order the block size allows and keeps the
+/// cheapest, using the usual `sum >> k` size estimate.
+fn best_partitioning(residual: &[i64], block: usize, predictor_order: usize) -> Partitioning {
+    let mut best: Option<Partitioning> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << order;
+        if !block.is_multiple_of(partitions) || block / partitions <= predictor_order {
+            break;
+        }
+
+        let mut params = Vec::with_capacity(partitions);
+        let mut cost = 2 + 4;
+        let mut start = 0;
+        for partition in 0..partitions {
+            let mut len = block / partitions;
+            if partition == 0 {
+                len -= predictor_order;
+            }
+            let sum: u64 = residual[start..start + len]
+                .iter()
+                .map(|&r| zigzag(r))
+                .sum();
+            start += len;
+
+            let (param, bits) = (0..=MAX_RICE2_PARAM)
+                .map(|k| (k, len as u64 * (k as u64 + 1) + (sum >> k)))
+                .min_by_key(|&(_, bits)| bits)
+                .unwrap_or((0, 0));
+            params.push(param);
+            cost += bits;
+        }
+        let param_bits = if params.iter().any(|&k| k > MAX_RICE_PARAM) {
+            5
+        } else {
+            4
+        };
+        cost += param_bits * partitions as u64;
+
+        if best.as_ref().is_none_or(|b| cost < b.cost) {
+            best = Some(Partitioning {
+                order,
+                params,
+                cost,
+            });
+        }
+    }
+    best.expect("partition order 0 is always valid")
+}
+
+fn write_residual(
+    w: &mut BitWriter,
+    residual: &[i64],
+    predictor_order: usize,
+    partitioning: &Partitioning,
+) {
+    let rice2 = partitioning.params.iter().any(|&k| k > MAX_RICE_PARAM);
+    let (method, param_bits) = if rice2 { (1, 5) } else { (0, 4) };
+    w.write(method, 2);
+    w.write(partitioning.order as u64, 4);
+
+    let per_partition = (residual.len() + predictor_order) >> partitioning.order;
+    let mut start = 0;
+    for (partition, &k) in partitioning.params.iter().enumerate() {
+        let len = if partition == 0 {
+            per_partition - predictor_order
+        } else {
+            per_partition
+        };
+        w.write(k as u64, param_bits);
+        for &r in &residual[start..start + len] {
+            let u = zigzag(r);
+            w.write_zeros(u >> k);
+            w.write(1, 1);
+            w.write(u, k);
+        }
+        start += len;
+    }
+}
+
+/// FLAC's UTF-8 style variable length frame number.
+fn write_utf8(w: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        w.write(value, 8);
+        return;
+    }
+    let mut bytes = 2;
+    while value >= 1 << (5 * bytes + 1) {
+        bytes += 1;
+    }
+    let prefix = (0xFF00u64 >> bytes) & 0xFF;
+    w.write(prefix | (value >> (6 * (bytes - 1))), 8);
+    for i in (0..bytes - 1).rev() {
+        w.write(0x80 | ((value >> (6 * i)) & 0x3F), 8);
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// MSB-first bit packer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `count` bits of `value`. Up to 7 bits are held back
+    /// between calls, so `count` is at most 56.
+    fn write(&mut self, value: u64, count: u32) {
+        debug_assert!(count <= 56);
+        if count == 0 {
+            return;
+        }
+        self.acc = (self.acc << count) | (value & ((1u64 << count) - 1));
+        self.bits += count;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64, count);
+    }
+
+    fn write_zeros(&mut self, mut count: u64) {
+        while count > 0 {
+            let chunk = count.min(32);
+            self.write(0, chunk as u32);
+            count -= chunk;
+        }
+    }
+
+    fn pad_to_byte(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Reads back what `BitWriter` packs, MSB first.
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        at: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..count {
+                let bit = (self.bytes[self.at / 8] >> (7 - self.at % 8)) & 1;
+                value = (value << 1) | bit as u64;
+                self.at += 1;
+            }
+            value
+        }
+
+        fn read_signed(&mut self, count: u32) -> i64 {
+            let value = self.read(count);
+            ((value << (64 - count)) as i64) >> (64 - count)
+        }
+
+        fn read_unary(&mut self) -> u64 {
+            let mut zeros = 0;
+            while self.read(1) == 0 {
+                zeros += 1;
+            }
+            zeros
+        }
+
+        fn align(&mut self) {
+            self.at = self.at.next_multiple_of(8);
+        }
+    }
+
+    /// Decodes a subframe of `block` samples, returns its type code and
+    /// the samples.
+    fn decode_subframe(r: &mut BitReader, block: usize, bits: u32) -> (u64, Vec<i64>) {
+        let header = r.read(8);
+        assert_eq!(header & 1, 0, "no wasted bits are written");
+        let kind = header >> 1;
+        let samples = match kind {
+            0 => vec![r.read_signed(bits); block],
+            1 => (0..block).map(|_| r.read_signed(bits)).collect(),
+            8..=12 => {
+                let order = (kind - 8) as usize;
+                let mut x: Vec<i64> = (0..order).map(|_| r.read_signed(bits)).collect();
+                let param_bits = if r.read(2) == 1 { 5 } else { 4 };
+                let partitions = 1usize << r.read(4);
+                for partition in 0..partitions {
+                    let k = r.read(param_bits) as u32;
+                    let len = block / partitions - if partition == 0 { order } else { 0 };
+                    for _ in 0..len {
+                        let u = (r.read_unary() << k) | r.read(k);
+                        let residual = (u >> 1) as i64 ^ -((u & 1) as i64);
+                        let i = x.len();
+                        let predicted = match order {
+                            0 => 0,
+                            1 => x[i - 1],
+                            2 => 2 * x[i - 1] - x[i - 2],
+                            3 => 3 * x[i - 1] - 3 * x[i - 2] + x[i - 3],
+                            _ => 4 * x[i - 1] - 6 * x[i - 2] + 4 * x[i - 3] - x[i - 4],
+                        };
+                        x.push(predicted + residual);
+                    }
+                }
+                x
+            }
+            other => panic!("unexpected subframe type {}", other),
+        };
+        (kind, samples)
+    }
+
+    /// Encodes `samples` as one subframe, decodes it and returns the type
+    /// the encoder picked.
+    fn roundtrip(samples: &[i64], bits: u32) -> u64 {
+        let mut w = BitWriter::default();
+        let mut residual = Vec::new();
+        encode_subframe(&mut w, samples, &mut residual, bits);
+        w.pad_to_byte();
+
+        let mut r = BitReader {
+            bytes: &w.bytes,
+            at: 0,
+        };
+        let (kind, decoded) = decode_subframe(&mut r, samples.len(), bits);
+        assert_eq!(decoded, samples);
+        r.align();
+        assert_eq!(r.at / 8, w.bytes.len(), "trailing bytes after the subframe");
+        kind
+    }
+
+    /// Full-scale white noise at `bits`, which no predictor helps with.
+    fn noise(len: usize, bits: u32) -> Vec<i64> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state as i32 >> (32 - bits)) as i64
+            })
+            .collect()
+    }
+
+    fn sine(len: usize, amplitude: f64) -> Vec<i64> {
+        (0..len)
+            .map(|i| ((i as f64 * 0.03).sin() * amplitude).round() as i64)
+            .collect()
+    }
+
+    #[test]
+    fn crcs_match_check_values() {
+        // CRC-8 with polynomial 0x07 and CRC-16/UMTS, as the FLAC spec has
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn constant_block_roundtrips() {
+        assert_eq!(roundtrip(&[-1234; 256], 16), 0);
+        assert_eq!(roundtrip(&[0; BLOCK_SIZE], 24), 0);
+    }
+
+    #[test]
+    fn noise_roundtrips_verbatim() {
+        assert_eq!(roundtrip(&noise(BLOCK_SIZE, 16), 16), 1);
+        assert_eq!(roundtrip(&noise(100, 24), 24), 1);
+    }
+
+    #[test]
+    fn smooth_signal_roundtrips_fixed() {
+        for (samples, bits) in [
+            (sine(BLOCK_SIZE, 20000.0), 16),
+            (sine(BLOCK_SIZE, 4_000_000.0), 24),
+            (sine(37, 12000.0), 16),
+        ] {
+            let kind = roundtrip(&samples, bits);
+            assert!((9..=12).contains(&kind), "picked type {}", kind);
+        }
+    }
+
+    #[test]
+    fn file_decodes_with_valid_frame_crcs() {
+        let path = std::env::temp_dir().join(format!("live_dsp-flac-{}.flac", std::process::id()));
+        let frames = BLOCK_SIZE + 100;
+        let left = sine(frames, 20000.0);
+        // Constant for the first block, noise for the short last one. The
+        // encoder scales by 32767, -32768 would clip
+        let tail: Vec<i64> = noise(100, 16).iter().map(|&s| s.max(-32767)).collect();
+        let right: Vec<i64> = [vec![-8000; BLOCK_SIZE], tail].concat();
+        let samples: Vec<f32> = left
+            .iter()
+            .zip(&right)
+            .flat_map(|(&l, &r)| [l as f32 / 32767.0, r as f32 / 32767.0])
+            .collect();
+
+        let mut encoder = Box::new(FlacEncoder::create(&path, BitDepth::Int16, 48000, 2).unwrap());
+        encoder.write(&samples).unwrap();
+        encoder.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&bytes[..4], b"fLaC");
+        let mut info = BitReader {
+            bytes: &bytes[8..42],
+            at: 108,
+        };
+        assert_eq!(info.read(36), frames as u64);
+
+        let mut r = BitReader {
+            bytes: &bytes,
+            at: 42 * 8,
+        };
+        let mut decoded = [Vec::new(), Vec::new()];
+        let mut kinds = Vec::new();
+        while r.at / 8 < bytes.len() {
+            let start = r.at / 8;
+            assert_eq!(r.read(14), 0b11111111111110);
+            r.read(2);
+            assert_eq!(r.read(4), 0b0111);
+            r.read(4);
+            assert_eq!(r.read(4), 1);
+            r.read(4);
+            let first = r.read(8);
+            let extra = if first < 0xC0 {
+                0
+            } else {
+                (first as u8).leading_ones() - 1
+            };
+            r.read(8 * extra);
+            let block = r.read(16) as usize + 1;
+            let header_end = r.at / 8;
+            assert_eq!(r.read(8) as u8, crc8(&bytes[start..header_end]));
+
+            for channel in decoded.iter_mut() {
+                let (kind, samples) = decode_subframe(&mut r, block, 16);
+                kinds.push(kind);
+                channel.extend(samples);
+            }
+            r.align();
+            let frame_end = r.at / 8;
+            assert_eq!(r.read(16) as u16, crc16(&bytes[start..frame_end]));
+        }
+
+        assert_eq!(decoded, [left, right]);
+        assert_eq!(kinds[1], 0);
+        assert_eq!(kinds[3], 1);
+        assert!((9..=12).contains(&kinds[0]));
+    }
+}
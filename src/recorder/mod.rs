@@ -0,0 +1,297 @@
+mod flac;
+#[cfg(feature = "opus")]
+pub mod opus;
+mod wav;
+
+use crate::output_tap::{self, OutputTap, TapReader};
+use anyhow::{Result, bail};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Seconds of audio the ring buffer holds while the writer catches up.
+const RING_SECONDS: usize = 2;
+/// How often the writer looks for new audio.
+const POLL: Duration = Duration::from_millis(20);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl BitDepth {
+    pub fn parse(text: &str) -> Result<Self> {
+        let depth = match text.trim() {
+            "16" => BitDepth::Int16,
+            "24" => BitDepth::Int24,
+            "32" | "32f" | "float" => BitDepth::Float32,
+            other => bail!("Unsupported bit depth '{}', expected 16, 24 or 32", other),
+        };
+        Ok(depth)
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            BitDepth::Int16 => 16,
+            BitDepth::Int24 => 24,
+            BitDepth::Float32 => 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Wav,
+    Flac,
+    Opus,
+}
+
+impl RecordFormat {
+    pub fn parse(text: &str) -> Result<Self> {
+        let format = match text.trim() {
+            "wav" => RecordFormat::Wav,
+            "flac" => RecordFormat::Flac,
+            "opus" => RecordFormat::Opus,
+            other => bail!(
+                "Unknown record format '{}', expected wav, flac or opus",
+                other
+            ),
+        };
+        Ok(format)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Wav => "wav",
+            RecordFormat::Flac => "flac",
+            RecordFormat::Opus => "opus",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordSettings {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// Sample depth for WAV and FLAC. FLAC has no float mode.
+    pub bit_depth: BitDepth,
+    /// Opus target bitrate.
+    #[cfg(feature = "opus")]
+    pub bitrate_kbps: u32,
+    /// Start a new numbered file every this many minutes.
+    pub split_minutes: Option<u32>,
+}
+
+/// A file format backend, also used for network streams. Encoders run on
+/// their own thread, never on the audio thread, so they are free to block
+/// and allocate.
+pub trait Encoder: Send {
+    /// Encodes interleaved frames.
+    fn write(&mut self, samples: &[f32]) -> Result<()>;
+
+    /// Flushes buffered audio and completes the file headers.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+fn create_encoder(
+    settings: &RecordSettings,
+    path: &Path,
+    sample_rate: u32,
+    channels: usize,
+) -> Result<Box<dyn Encoder>> {
+    let encoder: Box<dyn Encoder> = match settings.format {
+        RecordFormat::Wav => Box::new(wav::WavEncoder::create(
+            path,
+            settings.bit_depth,
+            sample_rate,
+            channels,
+        )?),
+        RecordFormat::Flac => Box::new(flac::FlacEncoder::create(
+            path,
+            settings.bit_depth,
+            sample_rate,
+            channels,
+        )?),
+        #[cfg(feature = "opus")]
+        RecordFormat::Opus => Box::new(opus::OpusEncoder::create(
+            path,
+            settings.bitrate_kbps,
+            sample_rate,
+            channels,
+        )?),
+        #[cfg(not(feature = "opus"))]
+        RecordFormat::Opus => bail!("Opus recording needs a build with `--features opus`"),
+    };
+    Ok(encoder)
+}
+
+/// Control side of a recording, used to wait for the writer to flush.
+pub struct Recorder {
+    name: String,
+    dropped: Arc<AtomicU64>,
+    writer: JoinHandle<Result<u64>>,
+}
+
+impl Recorder {
+    /// Starts the writer thread. Returns the tap to feed from the audio
+    /// callback; the recording ends once the tap is dropped.
+    pub fn start(
+        settings: &RecordSettings,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<(Recorder, OutputTap)> {
+        let (tap, reader) = output_tap::output_tap(sample_rate, channels, RING_SECONDS);
+        let dropped = reader.dropped();
+
+        let writer = SplitWriter::new(settings, sample_rate, channels)?;
+        let name = settings.path.display().to_string();
+        let writer = thread::Builder::new()
+            .name(format!("recorder {}", name))
+            .spawn(move || writer.run(reader))?;
+
+        let recorder = Recorder {
+            name,
+            dropped,
+            writer,
+        };
+        Ok((recorder, tap))
+    }
+
+    /// Waits for the writer to drain the ring and close the file. Call
+    /// after the stream feeding the tap has been dropped.
+    pub fn finish(self) -> Result<()> {
+        let frames = self
+            .writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Recorder thread for {} panicked", self.name))??;
+        info!(file = %self.name, frames, "recording finished");
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(
+                file = %self.name,
+                dropped,
+                "recorder dropped samples, the disk could not keep up"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Encoder wrapper that rolls over to `<stem>-002.<ext>`,
+/// `<stem>-003.<ext>`... every `split_frames` frames.
+struct SplitWriter {
+    settings: RecordSettings,
+    sample_rate: u32,
+    channels: usize,
+    split_frames: Option<u64>,
+    part: u32,
+    frames_in_part: u64,
+    total_frames: u64,
+    encoder: Box<dyn Encoder>,
+}
+
+impl SplitWriter {
+    fn new(settings: &RecordSettings, sample_rate: u32, channels: usize) -> Result<Self> {
+        let split_frames = settings
+            .split_minutes
+            .map(|minutes| minutes as u64 * 60 * sample_rate as u64);
+        let path = part_path(settings, 1, split_frames.is_some());
+        let encoder = create_encoder(settings, &path, sample_rate, channels)?;
+
+        Ok(SplitWriter {
+            settings: settings.clone(),
+            sample_rate,
+            channels,
+            split_frames,
+            part: 1,
+            frames_in_part: 0,
+            total_frames: 0,
+            encoder,
+        })
+    }
+
+    /// Drains the ring until the tap is dropped, returns the frames written.
+    fn run(mut self, mut reader: TapReader) -> Result<u64> {
+        let mut block = vec![0.0f32; 4096 * self.channels];
+        while let Some(count) = reader.read(&mut block, POLL) {
+            self.write(&block[..count])?;
+        }
+
+        self.encoder.finish()?;
+        Ok(self.total_frames)
+    }
+
+    fn write(&mut self, mut samples: &[f32]) -> Result<()> {
+        while !samples.is_empty() {
+            let mut frames = samples.len() / self.channels;
+            if let Some(split_frames) = self.split_frames {
+                if self.frames_in_part >= split_frames {
+                    self.part += 1;
+                    let path = part_path(&self.settings, self.part, true);
+                    let next =
+                        create_encoder(&self.settings, &path, self.sample_rate, self.channels)?;
+                    std::mem::replace(&mut self.encoder, next).finish()?;
+                    self.frames_in_part = 0;
+                }
+                frames = frames.min((split_frames - self.frames_in_part) as usize);
+            }
+
+            let (now, rest) = samples.split_at(frames * self.channels);
+            self.encoder.write(now)?;
+            self.frames_in_part += frames as u64;
+            self.total_frames += frames as u64;
+            samples = rest;
+        }
+        Ok(())
+    }
+}
+
+/// `take.flac` becomes `take-001.flac` when splitting is enabled.
+fn part_path(settings: &RecordSettings, part: u32, numbered: bool) -> PathBuf {
+    if !numbered {
+        return settings.path.clone();
+    }
+    let path = &settings.path;
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("recording");
+    path.with_file_name(format!(
+        "{}-{:03}.{}",
+        stem,
+        part,
+        settings.format.extension()
+    ))
+}
+
+/// Path of the recording of the mix before the chain, next to the main
+/// one, `take.wav` -> `take-mix.wav`.
+pub fn mix_path(settings: &RecordSettings) -> PathBuf {
+    suffixed_path(settings, "mix")
+}
+
+/// Path of the raw recording of input `input`, counted from 1, next to the
+/// main one, `take.wav` -> `take-in1.wav`.
+pub fn input_path(settings: &RecordSettings, input: usize) -> PathBuf {
+    suffixed_path(settings, &format!("in{}", input))
+}
+
+fn suffixed_path(settings: &RecordSettings, suffix: &str) -> PathBuf {
+    let path = &settings.path;
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("recording");
+    path.with_file_name(format!(
+        "{}-{}.{}",
+        stem,
+        suffix,
+        settings.format.extension()
+    ))
+}
@@ -0,0 +1,162 @@
+use super::Encoder;
+use crate::resample::{Quality, Resampler};
+use anyhow::{Context, Result, bail};
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Opus always runs at 48 kHz internally; Ogg granule positions count
+/// 48 kHz samples regardless of the input rate.
+const OPUS_RATE: u32 = 48_000;
+/// 20 ms frames.
+const FRAME_SIZE: usize = 960;
+/// Largest packet libopus will produce for one frame.
+const MAX_PACKET: usize = 4000;
+/// Packets per Ogg page, one second. Short pages let a listener of a live
+/// stream start without waiting for a full one.
+const PAGE_PACKETS: u64 = 50;
+
+/// Ogg/Opus written to a file or, for Icecast, to a connection.
+pub struct OpusEncoder<W: Write> {
+    encoder: opus::Encoder,
+    writer: PacketWriter<'static, W>,
+    serial: u32,
+    channels: usize,
+    resampler: Resampler,
+    /// Interleaved 48 kHz samples waiting for a full frame.
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+    /// Encoder lookahead the decoder must discard.
+    pre_skip: u64,
+    /// 48 kHz frames of real audio received so far.
+    input_frames: u64,
+    /// 48 kHz frames handed to the encoder, padding included.
+    encoded_frames: u64,
+}
+
+impl OpusEncoder<BufWriter<File>> {
+    pub fn create(
+        path: &Path,
+        bitrate_kbps: u32,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        OpusEncoder::new(BufWriter::new(file), bitrate_kbps, sample_rate, channels)
+    }
+}
+
+impl<W: Write> OpusEncoder<W> {
+    /// Writes the Ogg headers to `writer` and returns the encoder.
+    pub fn new(writer: W, bitrate_kbps: u32, sample_rate: u32, channels: usize) -> Result<Self> {
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            _ => bail!("Ogg/Opus supports 1 or 2 channels, got {}", channels),
+        };
+
+        let mut encoder = opus::Encoder::new(OPUS_RATE, opus_channels, Application::Audio)?;
+        encoder.set_bitrate(Bitrate::Bits(bitrate_kbps as i32 * 1000))?;
+        let pre_skip = encoder.get_lookahead()? as u64;
+
+        let mut writer = PacketWriter::new(writer);
+        let serial = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0x4c44_5350);
+
+        // RFC 7845 identification and comment headers, each on its own page
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1);
+        head.push(channels as u8);
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        writer.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("live_dsp ", env!("CARGO_PKG_VERSION"));
+        let mut tags = Vec::new();
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        writer.write_packet(tags, serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(OpusEncoder {
+            encoder,
+            writer,
+            serial,
+            channels,
+            resampler: Resampler::new(sample_rate, OPUS_RATE, channels, Quality::High),
+            pending: Vec::with_capacity(FRAME_SIZE * channels * 2),
+            packet: vec![0; MAX_PACKET],
+            pre_skip,
+            input_frames: 0,
+            encoded_frames: 0,
+        })
+    }
+
+    /// Encodes the first frame of `pending`. The last packet closes the
+    /// stream with a granule position that trims the padding.
+    fn encode_frame(&mut self, last: bool) -> Result<()> {
+        let samples = FRAME_SIZE * self.channels;
+        let size = self
+            .encoder
+            .encode_float(&self.pending[..samples], &mut self.packet)?;
+        self.pending.drain(..samples);
+        self.encoded_frames += FRAME_SIZE as u64;
+
+        let (end, granule) = if last {
+            (
+                PacketWriteEndInfo::EndStream,
+                self.pre_skip + self.input_frames,
+            )
+        } else if (self.encoded_frames / FRAME_SIZE as u64).is_multiple_of(PAGE_PACKETS) {
+            (PacketWriteEndInfo::EndPage, self.encoded_frames)
+        } else {
+            (PacketWriteEndInfo::NormalPacket, self.encoded_frames)
+        };
+        self.writer
+            .write_packet(self.packet[..size].to_vec(), self.serial, end, granule)?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Encoder for OpusEncoder<W> {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let pending = &mut self.pending;
+        let mut frames = 0;
+        self.resampler.process(samples, |frame| {
+            pending.extend_from_slice(frame);
+            frames += 1;
+        });
+        self.input_frames += frames;
+
+        while self.pending.len() >= FRAME_SIZE * self.channels {
+            self.encode_frame(false)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // Pad with silence until the lookahead has been flushed through
+        let target = self.pre_skip + self.input_frames;
+        loop {
+            self.pending.resize(FRAME_SIZE * self.channels, 0.0);
+            let last = self.encoded_frames + FRAME_SIZE as u64 >= target;
+            self.encode_frame(last)?;
+            if last {
+                break;
+            }
+        }
+
+        self.writer.into_inner().flush()?;
+        Ok(())
+    }
+}
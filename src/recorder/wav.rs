@@ -0,0 +1,56 @@
+use super::{BitDepth, Encoder};
+use anyhow::{Context, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+pub struct WavEncoder {
+    bit_depth: BitDepth,
+    writer: WavWriter<BufWriter<File>>,
+}
+
+impl WavEncoder {
+    pub fn create(
+        path: &Path,
+        bit_depth: BitDepth,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<Self> {
+        let sample_format = match bit_depth {
+            BitDepth::Float32 => SampleFormat::Float,
+            _ => SampleFormat::Int,
+        };
+        let spec = WavSpec {
+            channels: channels as u16,
+            sample_rate,
+            bits_per_sample: bit_depth.bits() as u16,
+            sample_format,
+        };
+        let writer = WavWriter::create(path, spec)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(WavEncoder { bit_depth, writer })
+    }
+}
+
+impl Encoder for WavEncoder {
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        for &sample in samples {
+            match self.bit_depth {
+                BitDepth::Int16 => self
+                    .writer
+                    .write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?,
+                BitDepth::Int24 => self
+                    .writer
+                    .write_sample((sample.clamp(-1.0, 1.0) * 8_388_607.0) as i32)?,
+                BitDepth::Float32 => self.writer.write_sample(sample)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.writer.finalize()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use std::f64::consts::PI;
+
+/// Sub-sample positions the interpolation filter is tabulated at.
+const PHASES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    /// Zero crossings of the windowed sinc on each side of the centre.
+    fn half_taps(self) -> usize {
+        match self {
+            Quality::Low => 4,
+            Quality::Medium => 16,
+            Quality::High => 32,
+        }
+    }
+}
+
+/// Streaming polyphase windowed-sinc resampler for interleaved audio.
+/// Output lags the input by `half_taps` input frames.
+pub struct Resampler {
+    channels: usize,
+    /// Input frames advanced per output frame.
+    step: f64,
+    /// `step` before any drift correction.
+    nominal_step: f64,
+    passthrough: bool,
+    half_taps: usize,
+    /// `PHASES + 1` rows of `2 * half_taps` coefficients.
+    table: Vec<f32>,
+
+    /// Pending input frames, interleaved.
+    history: Vec<f32>,
+    /// Read position in frames relative to the start of `history`.
+    position: f64,
+    frame: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: usize, quality: Quality) -> Self {
+        let step = input_rate as f64 / output_rate as f64;
+        let half_taps = quality.half_taps();
+        let taps = half_taps * 2;
+
+        // Lower the cutoff when downsampling so nothing aliases
+        let cutoff = 0.95 * (1.0 / step).min(1.0);
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            for k in 0..taps {
+                let x = k as f64 - (half_taps as f64 - 1.0) - frac;
+                table.push((cutoff * sinc(cutoff * x) * blackman(x / half_taps as f64)) as f32);
+            }
+        }
+
+        // Start with `half_taps - 1` frames of silence so the first output
+        // frame has its full left context
+        let history = vec![0.0; (half_taps - 1) * channels];
+
+        Resampler {
+            channels,
+            step,
+            nominal_step: step,
+            passthrough: step == 1.0,
+            half_taps,
+            table,
+            history,
+            position: (half_taps - 1) as f64,
+            frame: vec![0.0; channels],
+        }
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.passthrough
+    }
+
+    /// Speeds up the input by `correction`, a small fraction, to follow a
+    /// clock that drifts against the output. Once called the resampler
+    /// always interpolates, so call it from the first callback on.
+    pub fn set_drift(&mut self, correction: f64) {
+        self.step = self.nominal_step * (1.0 + correction);
+        self.passthrough = false;
+    }
+
+    /// Pre-allocate room for callbacks of up to `frames` input frames.
+    pub fn reserve(&mut self, frames: usize) {
+        self.history
+            .reserve((frames + self.half_taps * 2) * self.channels);
+    }
+
+    /// Feeds interleaved input and calls `emit` with every output frame
+    /// that can be produced so far.
+    pub fn process(&mut self, input: &[f32], mut emit: impl FnMut(&[f32])) {
+        if self.is_passthrough() {
+            input.chunks(self.channels).for_each(emit);
+            return;
+        }
+
+        self.history.extend_from_slice(input);
+        let available = self.history.len() / self.channels;
+        let taps = self.half_taps * 2;
+
+        while (self.position as usize) + self.half_taps < available {
+            let index = self.position as usize;
+            let frac = self.position - index as f64;
+
+            // Linear interpolation between the two nearest tabulated phases
+            let phase = frac * PHASES as f64;
+            let row = phase as usize;
+            let blend = (phase - row as f64) as f32;
+            let a = &self.table[row * taps..(row + 1) * taps];
+            let b = &self.table[(row + 1) * taps..(row + 2) * taps];
+
+            let start = index + 1 - self.half_taps;
+            self.frame.iter_mut().for_each(|s| *s = 0.0);
+            for k in 0..taps {
+                let coeff = a[k] + (b[k] - a[k]) * blend;
+                let offset = (start + k) * self.channels;
+                for (out, &x) in self.frame.iter_mut().zip(&self.history[offset..]) {
+                    *out += x * coeff;
+                }
+            }
+            emit(&self.frame);
+
+            self.position += self.step;
+        }
+
+        // Drop frames that are no longer needed as left context
+        let keep_from = (self.position as usize + 1).saturating_sub(self.half_taps);
+        if keep_from > 0 {
+            self.history.drain(..keep_from * self.channels);
+            self.position -= keep_from as f64;
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Blackman window over `x` in `[-1, 1]`.
+fn blackman(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        return 0.0;
+    }
+    let t = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * t).cos() + 0.08 * (4.0 * PI * t).cos()
+}
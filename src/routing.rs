@@ -0,0 +1,116 @@
+use anyhow::{Context, Result, bail};
+
+/// One input channel feeding one output channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Route {
+    pub input: usize,
+    pub output: usize,
+    pub gain: f32,
+}
+
+/// Sparse N×M routing matrix from device input channels to output channels.
+/// Several routes into the same output are summed.
+#[derive(Clone, Debug)]
+pub struct ChannelMap {
+    routes: Vec<Route>,
+}
+
+impl ChannelMap {
+    /// Straight-through routing: input N goes to output N, a mono input is
+    /// copied to every output.
+    pub fn default_for(inputs: usize, outputs: usize) -> Self {
+        let routes = if inputs == 1 {
+            (0..outputs)
+                .map(|output| Route {
+                    input: 0,
+                    output,
+                    gain: 1.0,
+                })
+                .collect()
+        } else {
+            (0..inputs.min(outputs))
+                .map(|channel| Route {
+                    input: channel,
+                    output: channel,
+                    gain: 1.0,
+                })
+                .collect()
+        };
+        ChannelMap { routes }
+    }
+
+    /// Parses `in3:outL,in4:outR`. Channels are numbered from 1, `L` and `R`
+    /// are aliases for output 1 and 2.
+    pub fn parse(spec: &str, inputs: usize, outputs: usize) -> Result<Self> {
+        let mut routes = Vec::new();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (input, output) = entry
+                .split_once(':')
+                .with_context(|| format!("Invalid route '{}', expected inN:outM", entry))?;
+            let input = parse_channel(input, "in")?;
+            let output = parse_channel(output, "out")?;
+
+            if input >= inputs {
+                bail!(
+                    "Route '{}' uses input {} but the device has {} input channels",
+                    entry,
+                    input + 1,
+                    inputs
+                );
+            }
+            if output >= outputs {
+                bail!(
+                    "Route '{}' uses output {} but the device has {} output channels",
+                    entry,
+                    output + 1,
+                    outputs
+                );
+            }
+            routes.push(Route {
+                input,
+                output,
+                gain: 1.0,
+            });
+        }
+
+        if routes.is_empty() {
+            bail!("Channel map '{}' has no routes", spec);
+        }
+        Ok(ChannelMap { routes })
+    }
+
+    /// Mixes one input frame into one output frame.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) {
+        output.iter_mut().for_each(|s| *s = 0.0);
+        for route in self.routes.iter() {
+            output[route.output] += input[route.input] * route.gain;
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        self.routes
+            .iter()
+            .map(|r| format!("in{}:out{}", r.input + 1, r.output + 1))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn parse_channel(text: &str, prefix: &str) -> Result<usize> {
+    let text = text.trim();
+    let name = text
+        .strip_prefix(prefix)
+        .with_context(|| format!("Invalid channel '{}', expected {}N", text, prefix))?;
+
+    let number = match name {
+        "L" | "l" if prefix == "out" => 1,
+        "R" | "r" if prefix == "out" => 2,
+        _ => name
+            .parse::<usize>()
+            .with_context(|| format!("Invalid channel number in '{}'", text))?,
+    };
+    if number == 0 {
+        bail!("Channel numbers start at 1: '{}'", text);
+    }
+    Ok(number - 1)
+}
@@ -0,0 +1,136 @@
+use ringbuf::traits::{Consumer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::warn;
+
+/// Events queued per audio thread before new ones are dropped.
+const CAPACITY: usize = 64;
+
+/// Something an audio thread has to say. Events only carry what the audio
+/// thread already owns, so logging one never allocates.
+#[derive(Debug)]
+pub enum Event {
+    /// Error handed to a stream's error callback.
+    StreamError(cpal::StreamError),
+    /// The JACK period changed to this many frames.
+    #[cfg(feature = "jack")]
+    BufferSize(u32),
+    /// The JACK sample rate changed away from the one the engine runs at.
+    #[cfg(feature = "jack")]
+    SampleRate { rate: u32, engine: u32 },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::StreamError(err) => write!(f, "an error occurred on the stream: {}", err),
+            #[cfg(feature = "jack")]
+            Event::BufferSize(frames) => write!(f, "buffer size changed to {} frames", frames),
+            #[cfg(feature = "jack")]
+            Event::SampleRate { rate, engine } => write!(
+                f,
+                "sample rate changed to {} Hz, the effects keep running at {} Hz until restarted",
+                rate, engine
+            ),
+        }
+    }
+}
+
+/// Audio thread end of a log channel. Logging is a push into a lock-free
+/// ring, a full ring drops the event and counts it.
+pub struct RtLogger {
+    producer: HeapProd<Event>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl RtLogger {
+    pub fn log(&mut self, event: Event) {
+        if self.producer.try_push(event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    consumer: HeapCons<Event>,
+    dropped: Arc<AtomicU64>,
+    reported: u64,
+}
+
+impl Channel {
+    fn drain(&mut self) {
+        while let Some(event) = self.consumer.try_pop() {
+            warn!(thread = %self.name, "{}", event);
+        }
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > self.reported {
+            warn!(
+                thread = %self.name,
+                dropped = dropped - self.reported,
+                "log events dropped"
+            );
+            self.reported = dropped;
+        }
+    }
+}
+
+/// Background thread printing what the audio threads log. Each audio
+/// thread gets its own channel, so every ring has a single producer.
+pub struct Logger {
+    channels: Arc<Mutex<Vec<Channel>>>,
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Logger {
+    pub fn spawn() -> std::io::Result<Logger> {
+        let channels: Arc<Mutex<Vec<Channel>>> = Arc::new(Mutex::new(Vec::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let (drained, flag) = (channels.clone(), running.clone());
+        let thread = thread::Builder::new()
+            .name("rt log".to_string())
+            .spawn(move || {
+                while flag.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(50));
+                    if let Ok(mut channels) = drained.lock() {
+                        channels.iter_mut().for_each(Channel::drain);
+                    }
+                }
+            })?;
+        Ok(Logger {
+            channels,
+            running,
+            thread,
+        })
+    }
+
+    /// Opens a channel for one audio thread, printed with `name`.
+    pub fn channel(&self, name: impl Into<String>) -> RtLogger {
+        let (producer, consumer) = HeapRb::new(CAPACITY).split();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let channel = Channel {
+            name: name.into(),
+            consumer,
+            dropped: dropped.clone(),
+            reported: 0,
+        };
+        if let Ok(mut channels) = self.channels.lock() {
+            channels.push(channel);
+        }
+        RtLogger { producer, dropped }
+    }
+
+    /// Stops the thread after printing whatever is still queued.
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+        if let Ok(mut channels) = self.channels.lock() {
+            channels.iter_mut().for_each(Channel::drain);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+mod ptp;
+mod receiver;
+mod sap;
+mod sender;
+
+pub use receiver::RtpReceiver;
+pub use sender::RtpSender;
+
+use crate::dither::DitherMode;
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Dynamic payload type every stream is sent as, the SDP says what it is.
+const PAYLOAD_TYPE: u8 = 96;
+/// RTP header without CSRCs or extensions.
+const HEADER_LEN: usize = 12;
+/// AES67 streams run at 48 kHz, the one rate every device must take.
+const AES67_RATE: u32 = 48_000;
+/// Hops multicast streams and announcements travel, enough for a routed
+/// studio network without leaving the site.
+const MULTICAST_TTL: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtpCodec {
+    /// 16-bit big-endian PCM, RFC 3551.
+    L16,
+    /// 24-bit big-endian PCM, RFC 3190.
+    L24,
+    /// RFC 7587, 20 ms frames at 48 kHz.
+    Opus,
+}
+
+impl RtpCodec {
+    pub fn parse(text: &str) -> Result<Self> {
+        let codec = match text.trim().to_ascii_lowercase().as_str() {
+            "l16" => RtpCodec::L16,
+            "l24" => RtpCodec::L24,
+            "opus" => RtpCodec::Opus,
+            other => bail!("Unknown RTP codec '{}', expected l16, l24 or opus", other),
+        };
+        Ok(codec)
+    }
+
+    /// Encoding name in the SDP.
+    pub fn name(self) -> &'static str {
+        match self {
+            RtpCodec::L16 => "L16",
+            RtpCodec::L24 => "L24",
+            RtpCodec::Opus => "opus",
+        }
+    }
+}
+
+/// Encoding, clock rate and channels of a received stream, written as in
+/// an SDP `rtpmap`: `l24/48000/2`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtpFormat {
+    pub codec: RtpCodec,
+    pub rate: u32,
+    pub channels: usize,
+}
+
+impl RtpFormat {
+    /// Rate and channels can be left out, they default to 48 kHz stereo.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parts = text.split('/').map(str::trim);
+        let codec = RtpCodec::parse(parts.next().unwrap_or(""))?;
+        let rate = match parts.next() {
+            Some(rate) => rate
+                .parse()
+                .with_context(|| format!("Invalid rate '{}' in '{}'", rate, text))?,
+            None => 48000,
+        };
+        let channels = match parts.next() {
+            Some(channels) => channels
+                .parse()
+                .with_context(|| format!("Invalid channels '{}' in '{}'", channels, text))?,
+            None => 2,
+        };
+        if parts.next().is_some() {
+            bail!("Invalid format '{}', expected e.g. l24/48000/2", text);
+        }
+        if !(8000..=192000).contains(&rate) {
+            bail!("RTP rate must be 8000 to 192000 Hz, got {}", rate);
+        }
+        if !(1..=64).contains(&channels) {
+            bail!("RTP streams carry 1 to 64 channels, got {}", channels);
+        }
+        if codec == RtpCodec::Opus && (rate != 48000 || channels > 2) {
+            bail!("Opus over RTP is always 48000 Hz with 1 or 2 channels");
+        }
+        Ok(RtpFormat {
+            codec,
+            rate,
+            channels,
+        })
+    }
+}
+
+/// A stream received as a mixer channel.
+#[derive(Clone, Debug)]
+pub struct RtpInputSettings {
+    pub port: u16,
+    pub format: RtpFormat,
+    /// Multicast group to join, for AES67 and other multicast senders.
+    pub group: Option<IpAddr>,
+}
+
+impl RtpInputSettings {
+    /// Parses `PORT`, `PORT:l16/44100/2`, a multicast group in front of
+    /// either, `239.69.1.10:5004:l24/48000/8`, or the path of an SDP file
+    /// such as the one `--rtp-sdp` writes or an AES67 device exports. A
+    /// bare port expects what another live_dsp sends by default, L24 at
+    /// 48 kHz in stereo.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        if text.to_ascii_lowercase().ends_with(".sdp") {
+            return read_sdp(Path::new(text));
+        }
+        let mut rest = text;
+        let mut group = None;
+        if let Some((address, tail)) = text.split_once(':')
+            && let Ok(address) = address.trim().parse::<Ipv4Addr>()
+        {
+            if !address.is_multicast() {
+                bail!("{} is not a multicast group", address);
+            }
+            group = Some(IpAddr::V4(address));
+            rest = tail;
+        }
+        let (port, format) = match rest.split_once(':') {
+            Some((port, format)) => (port, RtpFormat::parse(format)?),
+            None => (rest, RtpFormat::parse("l24")?),
+        };
+        let port = port.trim().parse().with_context(|| {
+            format!(
+                "Invalid RTP input '{}', expected [GROUP:]PORT[:FORMAT]",
+                text
+            )
+        })?;
+        Ok(RtpInputSettings {
+            port,
+            format,
+            group,
+        })
+    }
+}
+
+/// Takes the port and format of the first audio stream an SDP describes,
+/// and its multicast group if it's sent to one.
+fn read_sdp(path: &Path) -> Result<RtpInputSettings> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut media = None;
+    let mut group = None;
+    for line in text.lines().map(str::trim) {
+        // `c=IN IP4 239.69.1.10/32`, the TTL after the slash
+        if let Some(rest) = line.strip_prefix("c=IN ")
+            && let Some(address) = rest.split_whitespace().nth(1)
+            && let Ok(address) = address.split('/').next().unwrap_or("").parse::<IpAddr>()
+        {
+            group = address.is_multicast().then_some(address);
+        } else if let Some(rest) = line.strip_prefix("m=audio ") {
+            let mut fields = rest.split_whitespace();
+            let port: u16 = fields
+                .next()
+                .and_then(|port| port.parse().ok())
+                .with_context(|| format!("Invalid media line '{}'", line))?;
+            let payload_type = fields.nth(1).unwrap_or("").to_string();
+            media = Some((port, payload_type));
+        } else if let Some(rest) = line.strip_prefix("a=rtpmap:")
+            && let Some((port, payload_type)) = &media
+            && let Some((number, format)) = rest.split_once(' ')
+            && number == payload_type
+        {
+            return Ok(RtpInputSettings {
+                port: *port,
+                format: RtpFormat::parse(format)?,
+                group,
+            });
+        }
+    }
+    bail!("{} describes no RTP audio stream", path.display())
+}
+
+#[derive(Clone, Debug)]
+pub struct RtpSettings {
+    pub destination: SocketAddr,
+    pub codec: RtpCodec,
+    /// Opus target bitrate.
+    #[cfg(feature = "opus")]
+    pub bitrate_kbps: u32,
+    /// Dither before L16 and L24.
+    pub dither: DitherMode,
+    /// Where the SDP receivers open is written, printed when not given.
+    pub sdp: Option<PathBuf>,
+    /// 48 kHz L24 in 1 ms packets, timestamped from PTP and announced
+    /// over SAP, for AES67 and Dante receivers.
+    pub aes67: bool,
+    pub ptp_domain: u8,
+}
+
+/// Resolves `HOST:PORT`, the first address a host name resolves to.
+pub fn parse_destination(text: &str) -> Result<SocketAddr> {
+    text.to_socket_addrs()
+        .with_context(|| format!("Invalid RTP destination '{}', expected HOST:PORT", text))?
+        .next()
+        .with_context(|| format!("{} doesn't resolve to an address", text))
+}
+
+/// Starting sequence number, timestamp and SSRC. RFC 3550 wants them
+/// random so a restarted sender isn't taken for the old one.
+fn random_u32(salt: u32) -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0x4c44_5350);
+    // xorshift over the clock, the process and the salt
+    let mut x = nanos ^ std::process::id().rotate_left(16) ^ salt.wrapping_mul(0x9e37_79b9);
+    for _ in 0..4 {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+    }
+    x
+}
+
+/// Writes the 12-byte header of one packet.
+fn write_header(packet: &mut Vec<u8>, marker: bool, sequence: u16, timestamp: u32, ssrc: u32) {
+    packet.push(0x80);
+    packet.push(PAYLOAD_TYPE | if marker { 0x80 } else { 0 });
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(&timestamp.to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+}
+
+/// What the receiver uses of a packet.
+struct Packet<'a> {
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+    payload: &'a [u8],
+}
+
+/// Parses a packet, skipping CSRCs, a header extension and padding.
+/// Anything that isn't RTP version 2 is `None`.
+fn parse_packet(data: &[u8]) -> Option<Packet<'_>> {
+    if data.len() < HEADER_LEN || data[0] >> 6 != 2 {
+        return None;
+    }
+    let mut start = HEADER_LEN + (data[0] & 0x0f) as usize * 4;
+    if data[0] & 0x10 != 0 {
+        let words = data.get(start + 2..start + 4)?;
+        start += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+    }
+    let mut end = data.len();
+    if data[0] & 0x20 != 0 {
+        end = end.checked_sub(*data.last()? as usize)?;
+    }
+    Some(Packet {
+        sequence: u16::from_be_bytes([data[2], data[3]]),
+        timestamp: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ssrc: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        payload: data.get(start..end)?,
+    })
+}
+
+/// What a sender's session description is made from.
+struct Description {
+    destination: SocketAddr,
+    codec: RtpCodec,
+    origin: IpAddr,
+    clock_rate: u32,
+    channels: usize,
+    packet_ms: f32,
+    /// Stays the same across SAP announcements of the stream.
+    session: u32,
+}
+
+impl Description {
+    /// Session description for `ffplay`, VLC or GStreamer's `sdpdemux` to
+    /// receive the stream with. `reference` is the PTP clock of an AES67
+    /// stream, its `ts-refclk`.
+    fn sdp(&self, reference: Option<&str>) -> String {
+        let family = |ip: IpAddr| if ip.is_ipv4() { "IP4" } else { "IP6" };
+        let session = self.session;
+        let destination = self.destination.ip();
+        // IPv4 multicast carries its TTL
+        let connection = match destination {
+            IpAddr::V4(ip) if ip.is_multicast() => format!("{}/{}", ip, MULTICAST_TTL),
+            ip => ip.to_string(),
+        };
+        let mut text = format!(
+            "v=0\r\n\
+             o=- {session} {session} IN {} {}\r\n\
+             s=live_dsp\r\n\
+             c=IN {} {}\r\n\
+             t=0 0\r\n\
+             m=audio {} RTP/AVP {PAYLOAD_TYPE}\r\n\
+             a=rtpmap:{PAYLOAD_TYPE} {}/{}/{}\r\n",
+            family(self.origin),
+            self.origin,
+            family(destination),
+            connection,
+            self.destination.port(),
+            self.codec.name(),
+            self.clock_rate,
+            self.channels,
+        );
+        if self.codec == RtpCodec::Opus && self.channels == 2 {
+            text += &format!("a=fmtp:{PAYLOAD_TYPE} sprop-stereo=1\r\n");
+        }
+        text += &format!("a=ptime:{}\r\n", self.packet_ms);
+        if let Some(reference) = reference {
+            text += &format!("a=ts-refclk:{}\r\na=mediaclk:direct=0\r\n", reference);
+        }
+        text += "a=sendonly\r\n";
+        text
+    }
+}
@@ -0,0 +1,307 @@
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Sync messages arrive on the event port, their follow-ups and the
+/// announces on the general one.
+const EVENT_PORT: u16 = 319;
+const GENERAL_PORT: u16 = 320;
+/// Where PTPv2 masters send over IPv4.
+const PTP_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 1, 129);
+/// How long a receive waits before checking whether to stop.
+const POLL: Duration = Duration::from_millis(100);
+/// A master that hasn't sent a Sync in this long is given up for another.
+const MASTER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Offsets further than this from the filtered one are outliers, or the
+/// master stepped its clock when several come in a row.
+const STEP_NS: i64 = 1_000_000;
+/// Outliers in a row taken as a step.
+const STEP_COUNT: u32 = 3;
+
+const SYNC: u8 = 0x0;
+const FOLLOW_UP: u8 = 0x8;
+const ANNOUNCE: u8 = 0xb;
+/// Common header length.
+const HEADER_LEN: usize = 34;
+
+/// Local wall clock in nanoseconds.
+fn local_ns() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0)
+}
+
+/// The fields of a PTPv2 message the clock uses.
+struct Message {
+    kind: u8,
+    domain: u8,
+    two_step: bool,
+    correction_ns: i64,
+    /// Clock identity and port of the sender.
+    source: [u8; 10],
+    sequence: u16,
+    /// Origin timestamp of a Sync or the precise one of a Follow_Up, in
+    /// nanoseconds.
+    timestamp_ns: i64,
+}
+
+fn parse_message(data: &[u8]) -> Option<Message> {
+    if data.len() < HEADER_LEN + 10 || data[1] & 0x0f != 2 {
+        return None;
+    }
+    let seconds = data[HEADER_LEN..HEADER_LEN + 6]
+        .iter()
+        .fold(0i64, |acc, &byte| acc << 8 | byte as i64);
+    let nanos = u32::from_be_bytes(data[HEADER_LEN + 6..HEADER_LEN + 10].try_into().ok()?);
+    let correction = i64::from_be_bytes(data[8..16].try_into().ok()?);
+    Some(Message {
+        kind: data[0] & 0x0f,
+        domain: data[4],
+        two_step: data[6] & 0x02 != 0,
+        // Scaled by 2^16
+        correction_ns: correction >> 16,
+        source: data[20..30].try_into().ok()?,
+        sequence: u16::from_be_bytes([data[30], data[31]]),
+        timestamp_ns: seconds * 1_000_000_000 + nanos as i64,
+    })
+}
+
+/// A Sync waiting for its Follow_Up.
+struct PendingSync {
+    source: [u8; 10],
+    sequence: u16,
+    received_ns: i64,
+    correction_ns: i64,
+}
+
+/// The master followed and the last Sync from it.
+struct Follow {
+    master: Option<([u8; 10], Instant)>,
+    pending: Option<PendingSync>,
+    outliers: u32,
+}
+
+struct Shared {
+    domain: u8,
+    /// PTP time minus the local clock in nanoseconds.
+    offset_ns: AtomicI64,
+    locked: AtomicBool,
+    grandmaster: AtomicU64,
+    running: AtomicBool,
+    follow: Mutex<Follow>,
+}
+
+impl Shared {
+    /// Takes one offset measurement, ignoring the path delay, which on a
+    /// LAN is microseconds, well under a sample.
+    fn measure(&self, follow: &mut Follow, master_ns: i64, received_ns: i64) {
+        let sample = master_ns - received_ns;
+        if !self.locked.load(Ordering::Relaxed) {
+            self.offset_ns.store(sample, Ordering::Relaxed);
+            self.locked.store(true, Ordering::Relaxed);
+            return;
+        }
+        let offset = self.offset_ns.load(Ordering::Relaxed);
+        if (sample - offset).abs() > STEP_NS {
+            follow.outliers += 1;
+            if follow.outliers < STEP_COUNT {
+                return;
+            }
+            self.offset_ns.store(sample, Ordering::Relaxed);
+        } else {
+            // Smoothed over about eight syncs against the receive jitter
+            self.offset_ns
+                .store(offset + (sample - offset) / 8, Ordering::Relaxed);
+        }
+        follow.outliers = 0;
+    }
+
+    /// Whether to take time from `source`, which becomes the followed
+    /// master when there is none or the last one went quiet.
+    fn follows(&self, follow: &mut Follow, source: [u8; 10]) -> bool {
+        let now = Instant::now();
+        match follow.master {
+            Some((master, _)) if master == source => {}
+            Some((_, last)) if now.duration_since(last) < MASTER_TIMEOUT => return false,
+            _ => {
+                info!("PTP: following master {}", clock_identity(&source[..8]));
+                self.grandmaster.store(
+                    u64::from_be_bytes(source[..8].try_into().unwrap()),
+                    Ordering::Relaxed,
+                );
+                self.locked.store(false, Ordering::Relaxed);
+            }
+        }
+        follow.master = Some((source, now));
+        true
+    }
+
+    fn event(&self, message: Message, received_ns: i64) {
+        let mut follow = self.follow.lock().unwrap();
+        if message.kind != SYNC || !self.follows(&mut follow, message.source) {
+            return;
+        }
+        if message.two_step {
+            follow.pending = Some(PendingSync {
+                source: message.source,
+                sequence: message.sequence,
+                received_ns,
+                correction_ns: message.correction_ns,
+            });
+        } else {
+            let master_ns = message.timestamp_ns + message.correction_ns;
+            self.measure(&mut follow, master_ns, received_ns);
+        }
+    }
+
+    fn general(&self, message: Message, data: &[u8]) {
+        let mut follow = self.follow.lock().unwrap();
+        match message.kind {
+            FOLLOW_UP => {
+                let Some(sync) = follow.pending.take_if(|sync| {
+                    sync.source == message.source && sync.sequence == message.sequence
+                }) else {
+                    return;
+                };
+                let master_ns = message.timestamp_ns + sync.correction_ns + message.correction_ns;
+                self.measure(&mut follow, master_ns, sync.received_ns);
+            }
+            // The grandmaster behind a boundary clock, for the SDP
+            ANNOUNCE
+                if follow
+                    .master
+                    .is_some_and(|(master, _)| master == message.source) =>
+            {
+                if let Some(identity) = data.get(53..61) {
+                    self.grandmaster.store(
+                        u64::from_be_bytes(identity.try_into().unwrap()),
+                        Ordering::Relaxed,
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `00-1D-C1-FF-FE-12-34-56`, the way AES67 SDPs write an identity.
+fn clock_identity(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Follows a PTPv2 master in `domain` to give AES67 streams their media
+/// clock. Only listens: the offset comes from Sync and Follow_Up alone,
+/// without delay requests. Until a master is heard the local clock stands
+/// in. The PTP ports are privileged, so live_dsp needs root or
+/// `CAP_NET_BIND_SERVICE`, and can't share them with a running ptp4l.
+pub struct PtpClock {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl PtpClock {
+    pub fn spawn(domain: u8) -> Result<PtpClock> {
+        let shared = Arc::new(Shared {
+            domain,
+            offset_ns: AtomicI64::new(0),
+            locked: AtomicBool::new(false),
+            grandmaster: AtomicU64::new(0),
+            running: AtomicBool::new(true),
+            follow: Mutex::new(Follow {
+                master: None,
+                pending: None,
+                outliers: 0,
+            }),
+        });
+        let mut threads = Vec::new();
+        for port in [EVENT_PORT, GENERAL_PORT] {
+            let socket =
+                UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port))).with_context(|| {
+                    format!(
+                        "Failed to listen for PTP on port {}, it needs root or \
+                         CAP_NET_BIND_SERVICE and no other PTP daemon on it",
+                        port
+                    )
+                })?;
+            socket.join_multicast_v4(&PTP_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+            socket.set_read_timeout(Some(POLL))?;
+            let shared = shared.clone();
+            threads.push(
+                thread::Builder::new()
+                    .name(format!("ptp {}", port))
+                    .spawn(move || receive(&shared, &socket, port == EVENT_PORT))?,
+            );
+        }
+        Ok(PtpClock { shared, threads })
+    }
+
+    /// Waits up to `timeout` for a master, returns whether one was heard.
+    pub fn wait_for_master(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while !self.locked() && start.elapsed() < timeout {
+            thread::sleep(Duration::from_millis(50));
+        }
+        self.locked()
+    }
+
+    pub fn locked(&self) -> bool {
+        self.shared.locked.load(Ordering::Relaxed)
+    }
+
+    /// PTP time in nanoseconds.
+    pub fn now_ns(&self) -> i64 {
+        local_ns() + self.shared.offset_ns.load(Ordering::Relaxed)
+    }
+
+    /// The `ts-refclk` of an SDP: the grandmaster and domain, or just
+    /// that the clock is PTP while none is heard.
+    pub fn reference(&self) -> String {
+        if !self.locked() {
+            return "ptp=IEEE1588-2008:traceable".to_string();
+        }
+        let grandmaster = self.shared.grandmaster.load(Ordering::Relaxed);
+        format!(
+            "ptp=IEEE1588-2008:{}:{}",
+            clock_identity(&grandmaster.to_be_bytes()),
+            self.shared.domain
+        )
+    }
+
+    pub fn stop(self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn receive(shared: &Shared, socket: &UdpSocket, event: bool) {
+    let mut data = [0u8; 1500];
+    while shared.running.load(Ordering::Relaxed) {
+        let Ok(size) = socket.recv(&mut data) else {
+            continue;
+        };
+        // Taken first thing, it's the receive time of a Sync
+        let received_ns = local_ns();
+        let Some(message) = parse_message(&data[..size]) else {
+            continue;
+        };
+        if message.domain != shared.domain {
+            continue;
+        }
+        if event {
+            shared.event(message, received_ns);
+        } else {
+            shared.general(message, &data[..size]);
+        }
+    }
+}
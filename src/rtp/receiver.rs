@@ -0,0 +1,235 @@
+use super::{RtpCodec, RtpFormat, RtpInputSettings, parse_packet};
+use crate::source::{self, SourceSettings, SourceTap, StreamFeed};
+use anyhow::{Context, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::info;
+
+/// How long a receive waits before checking whether to stop.
+const POLL: Duration = Duration::from_millis(100);
+/// Largest UDP datagram.
+const MAX_DATAGRAM: usize = 65536;
+
+/// Turns RTP payloads back into interleaved samples.
+trait Depacketizer: Send {
+    /// Decodes one payload into `samples` and returns its frames.
+    fn decode(&mut self, payload: &[u8], samples: &mut Vec<f32>) -> usize;
+}
+
+/// L16 and L24, big-endian integers.
+struct PcmDepacketizer {
+    bytes: usize,
+    channels: usize,
+}
+
+impl Depacketizer for PcmDepacketizer {
+    fn decode(&mut self, payload: &[u8], samples: &mut Vec<f32>) -> usize {
+        let scale = 1.0 / (1u32 << (self.bytes * 8 - 1)) as f32;
+        samples.clear();
+        for raw in payload.chunks_exact(self.bytes) {
+            let mut word = [0u8; 4];
+            word[..self.bytes].copy_from_slice(raw);
+            // Shifted down from the top so the sign carries
+            let value = i32::from_be_bytes(word) >> ((4 - self.bytes) * 8);
+            samples.push(value as f32 * scale);
+        }
+        samples.truncate(samples.len() / self.channels * self.channels);
+        samples.len() / self.channels
+    }
+}
+
+#[cfg(feature = "opus")]
+mod opus_depacketizer {
+    use super::Depacketizer;
+    use anyhow::Result;
+    use opus::Channels;
+
+    /// Longest Opus packet, 120 ms at 48 kHz.
+    const MAX_FRAMES: usize = 5760;
+
+    pub struct OpusDepacketizer {
+        decoder: opus::Decoder,
+        channels: usize,
+    }
+
+    impl OpusDepacketizer {
+        pub fn new(channels: usize) -> Result<Self> {
+            let opus_channels = if channels == 1 {
+                Channels::Mono
+            } else {
+                Channels::Stereo
+            };
+            Ok(OpusDepacketizer {
+                decoder: opus::Decoder::new(48000, opus_channels)?,
+                channels,
+            })
+        }
+    }
+
+    impl Depacketizer for OpusDepacketizer {
+        fn decode(&mut self, payload: &[u8], samples: &mut Vec<f32>) -> usize {
+            samples.resize(MAX_FRAMES * self.channels, 0.0);
+            let frames = self
+                .decoder
+                .decode_float(payload, samples, false)
+                .unwrap_or(0);
+            samples.truncate(frames * self.channels);
+            frames
+        }
+    }
+}
+
+/// Packets seen by a receiver, reported when it stops.
+#[derive(Default)]
+struct ReceiveStats {
+    packets: u64,
+    /// Gaps in the sequence numbers.
+    lost: u64,
+    /// Arrived after a later packet had already been played.
+    late: u64,
+}
+
+/// An RTP stream received on a UDP port as a mixer channel. Packets are
+/// decoded on their own thread and go through the same resampler and
+/// jitter buffer as a device input, so the sender's clock drift is
+/// followed the same way. Lost packets play as silence, late ones are
+/// dropped.
+pub struct RtpReceiver {
+    name: String,
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<ReceiveStats>,
+}
+
+impl RtpReceiver {
+    /// Binds the port and starts receiving. Returns the tap to add to the
+    /// mixer.
+    pub fn start(
+        settings: &RtpInputSettings,
+        source: &SourceSettings,
+    ) -> Result<(RtpReceiver, SourceTap)> {
+        let format = settings.format;
+        let depacketizer: Box<dyn Depacketizer> = match format.codec {
+            RtpCodec::L16 | RtpCodec::L24 => Box::new(PcmDepacketizer {
+                bytes: if format.codec == RtpCodec::L16 { 2 } else { 3 },
+                channels: format.channels,
+            }),
+            #[cfg(feature = "opus")]
+            RtpCodec::Opus => Box::new(opus_depacketizer::OpusDepacketizer::new(format.channels)?),
+            #[cfg(not(feature = "opus"))]
+            RtpCodec::Opus => anyhow::bail!("Opus over RTP needs a build with `--features opus`"),
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], settings.port)))
+            .with_context(|| format!("Failed to listen for RTP on port {}", settings.port))?;
+        socket.set_read_timeout(Some(POLL))?;
+        match settings.group {
+            Some(IpAddr::V4(group)) => socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED),
+            Some(IpAddr::V6(group)) => socket.join_multicast_v6(&group, 0),
+            None => Ok(()),
+        }
+        .with_context(|| format!("Failed to join multicast group {:?}", settings.group))?;
+
+        let address = match settings.group {
+            Some(group) => format!("{}:{}", group, settings.port),
+            None => format!(":{}", settings.port),
+        };
+        let name = format!("rtp {}", address);
+        let (feed, tap) = source::open_stream(name.clone(), format.rate, format.channels, source)?;
+        info!(
+            "Receiving RTP on {}: {} {} Hz, {} channels",
+            address,
+            format.codec.name(),
+            format.rate,
+            format.channels
+        );
+
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let thread_name = name.clone();
+        let thread = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || receive(&thread_name, socket, format, depacketizer, feed, &flag))?;
+        Ok((
+            RtpReceiver {
+                name,
+                running,
+                thread,
+            },
+            tap,
+        ))
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Ok(stats) = self.thread.join() {
+            info!(
+                stream = %self.name,
+                packets = stats.packets,
+                lost = stats.lost,
+                late = stats.late,
+                "RTP packets received"
+            );
+        }
+    }
+}
+
+/// The sender being received and where its next packet should start.
+struct Stream {
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+}
+
+fn receive(
+    name: &str,
+    socket: UdpSocket,
+    format: RtpFormat,
+    mut depacketizer: Box<dyn Depacketizer>,
+    mut feed: StreamFeed,
+    running: &AtomicBool,
+) -> ReceiveStats {
+    let mut stats = ReceiveStats::default();
+    let mut datagram = vec![0u8; MAX_DATAGRAM];
+    let mut samples = Vec::new();
+    let mut silence = Vec::new();
+    let mut stream: Option<Stream> = None;
+    while running.load(Ordering::Relaxed) {
+        let Ok((size, from)) = socket.recv_from(&mut datagram) else {
+            continue;
+        };
+        let Some(packet) = parse_packet(&datagram[..size]) else {
+            continue;
+        };
+        match &stream {
+            Some(current) if current.ssrc == packet.ssrc => {
+                let ahead = packet.sequence.wrapping_sub(current.sequence) as i16;
+                if ahead < 0 {
+                    stats.late += 1;
+                    continue;
+                }
+                stats.lost += ahead as u64;
+                // Silence in place of what went missing, a second at most
+                let missing = packet.timestamp.wrapping_sub(current.timestamp) as i32;
+                if missing > 0 {
+                    let frames = (missing as usize).min(format.rate as usize);
+                    silence.resize(frames * format.channels, 0.0);
+                    feed.push(&silence);
+                }
+            }
+            _ => info!("{}: receiving from {}", name, from),
+        }
+
+        let frames = depacketizer.decode(packet.payload, &mut samples);
+        feed.push(&samples);
+        stats.packets += 1;
+        stream = Some(Stream {
+            ssrc: packet.ssrc,
+            sequence: packet.sequence.wrapping_add(1),
+            timestamp: packet.timestamp.wrapping_add(frames as u32),
+        });
+    }
+    stats
+}
@@ -0,0 +1,73 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Where SAP announcements for administratively scoped sessions go, and
+/// where Dante Controller and other AES67 gear listen for them.
+const SAP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 255);
+const SAP_PORT: u16 = 9875;
+/// RFC 2974 suggests 300 s for the whole scope, AES67 devices repeat far
+/// more often so a stream shows up quickly.
+const INTERVAL: Duration = Duration::from_secs(30);
+const MIME_TYPE: &[u8] = b"application/sdp\0";
+
+/// Announces a stream's SDP with the Session Announcement Protocol, so
+/// AES67 receivers list it without being given the SDP by hand.
+pub struct SapAnnouncer {
+    socket: UdpSocket,
+    origin: Ipv4Addr,
+    /// Identifies the session across announcements, changes with its SDP.
+    hash: u16,
+    last: Option<Instant>,
+}
+
+impl SapAnnouncer {
+    /// `origin` is the sender's address, SAP carries IPv4 only here.
+    pub fn new(origin: IpAddr, ttl: u32) -> Option<Self> {
+        let IpAddr::V4(origin) = origin else {
+            return None;
+        };
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).ok()?;
+        socket.set_multicast_ttl_v4(ttl).ok()?;
+        Some(SapAnnouncer {
+            socket,
+            origin,
+            hash: 0,
+            last: None,
+        })
+    }
+
+    /// Announces `sdp` when it's time to. The SDP is built on demand, it
+    /// may have changed with the PTP master.
+    pub fn poll(&mut self, sdp: impl FnOnce() -> String) {
+        if self.last.is_some_and(|last| last.elapsed() < INTERVAL) {
+            return;
+        }
+        let sdp = sdp();
+        self.hash = sdp
+            .bytes()
+            .fold(0u16, |hash, byte| hash.rotate_left(5) ^ byte as u16);
+        self.send(false, &sdp);
+        self.last = Some(Instant::now());
+    }
+
+    /// Withdraws the session so receivers drop it at once instead of when
+    /// it times out.
+    pub fn delete(&mut self, sdp: &str) {
+        if self.last.is_some() {
+            self.send(true, sdp);
+        }
+    }
+
+    fn send(&self, delete: bool, sdp: &str) {
+        let mut packet = Vec::with_capacity(24 + MIME_TYPE.len() + sdp.len());
+        // Version 1, IPv4, announce or delete, no encryption or compression
+        packet.push(0x20 | if delete { 0x04 } else { 0 });
+        // No authentication data
+        packet.push(0);
+        packet.extend_from_slice(&self.hash.to_be_bytes());
+        packet.extend_from_slice(&self.origin.octets());
+        packet.extend_from_slice(MIME_TYPE);
+        packet.extend_from_slice(sdp.as_bytes());
+        let _ = self.socket.send_to(&packet, (SAP_GROUP, SAP_PORT));
+    }
+}
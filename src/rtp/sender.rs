@@ -0,0 +1,466 @@
+use super::ptp::PtpClock;
+use super::sap::SapAnnouncer;
+use super::{
+    AES67_RATE, Description, HEADER_LEN, MULTICAST_TTL, RtpCodec, RtpSettings, random_u32,
+    write_header,
+};
+use crate::dither::Dither;
+use crate::drift::DriftEstimator;
+use crate::output_tap::{self, OutputTap, TapReader};
+use crate::resample::{Quality, Resampler};
+use anyhow::{Context, Result};
+use std::fs;
+use std::net::{IpAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Audio the ring buffer holds while the sender catches up, in seconds.
+const RING_SECONDS: usize = 1;
+/// Largest payload that still fits a 1500-byte Ethernet frame after the
+/// IP, UDP and RTP headers, so nothing gets fragmented.
+const MAX_PAYLOAD: usize = 1440;
+/// Packet time of L16 and L24.
+const PCM_PACKET_MS: u32 = 5;
+/// Packet time of an AES67 stream, the one every receiver must take.
+const AES67_PACKET_MS: u32 = 1;
+/// How long to listen for a PTP master before starting on the local clock.
+const PTP_WAIT: Duration = Duration::from_secs(3);
+/// How often the media clock's drift correction is updated.
+const CLOCK_UPDATE: Duration = Duration::from_secs(1);
+/// Timestamps further than this from PTP time are stepped rather than
+/// slewed, after the master changed or the output stalled.
+const MAX_CLOCK_ERROR: u32 = AES67_RATE / 10;
+/// How often the sender looks for new audio, well under a packet.
+const POLL: Duration = Duration::from_millis(1);
+
+/// Turns interleaved samples into RTP payloads.
+trait Packetizer: Send {
+    /// Takes interleaved frames and calls `send` with every finished
+    /// payload and the RTP clock ticks it covers.
+    fn push(&mut self, samples: &[f32], send: &mut dyn FnMut(&[u8], u32));
+
+    /// RTP clock rate.
+    fn clock_rate(&self) -> u32;
+
+    fn packet_ms(&self) -> f32;
+}
+
+/// L16 and L24: big-endian integers, `frames` per packet.
+struct PcmPacketizer {
+    bytes: usize,
+    sample_rate: u32,
+    channels: usize,
+    frames: usize,
+    dither: Dither,
+    pending: Vec<f32>,
+    payload: Vec<u8>,
+}
+
+impl PcmPacketizer {
+    fn new(settings: &RtpSettings, sample_rate: u32, channels: usize) -> Self {
+        let bytes = if settings.codec == RtpCodec::L16 {
+            2
+        } else {
+            3
+        };
+        let packet_ms = if settings.aes67 {
+            AES67_PACKET_MS
+        } else {
+            PCM_PACKET_MS
+        };
+        // Fewer frames when many channels wouldn't fit a packet
+        let frames =
+            (sample_rate * packet_ms / 1000).min((MAX_PAYLOAD / (bytes * channels)) as u32);
+        PcmPacketizer {
+            bytes,
+            sample_rate,
+            channels,
+            frames: frames.max(1) as usize,
+            dither: Dither::new(settings.dither, bytes as u32 * 8, channels),
+            pending: Vec::new(),
+            payload: Vec::with_capacity(MAX_PAYLOAD),
+        }
+    }
+}
+
+impl Packetizer for PcmPacketizer {
+    fn push(&mut self, samples: &[f32], send: &mut dyn FnMut(&[u8], u32)) {
+        self.pending.extend_from_slice(samples);
+        let packet = self.frames * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= packet {
+            let block = &mut self.pending[start..start + packet];
+            self.dither.process(block, self.channels);
+            self.payload.clear();
+            for &sample in block.iter() {
+                let value = (sample * (1 << (self.bytes * 8 - 1)) as f32) as i32;
+                self.payload
+                    .extend_from_slice(&value.to_be_bytes()[4 - self.bytes..]);
+            }
+            send(&self.payload, self.frames as u32);
+            start += packet;
+        }
+        self.pending.drain(..start);
+    }
+
+    fn clock_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn packet_ms(&self) -> f32 {
+        self.frames as f32 * 1000.0 / self.sample_rate as f32
+    }
+}
+
+#[cfg(feature = "opus")]
+mod opus_packetizer {
+    use super::Packetizer;
+    use crate::resample::{Quality, Resampler};
+    use anyhow::{Result, bail};
+    use opus::{Application, Bitrate, Channels};
+
+    /// Opus always runs at 48 kHz, which is also its RTP clock.
+    const OPUS_RATE: u32 = 48_000;
+    /// 20 ms frames.
+    const FRAME_SIZE: usize = 960;
+    /// Largest packet libopus will produce for one frame.
+    const MAX_PACKET: usize = 4000;
+
+    pub struct OpusPacketizer {
+        encoder: opus::Encoder,
+        resampler: Resampler,
+        channels: usize,
+        /// Interleaved 48 kHz samples waiting for a full frame.
+        pending: Vec<f32>,
+        packet: Vec<u8>,
+    }
+
+    impl OpusPacketizer {
+        pub fn new(bitrate_kbps: u32, sample_rate: u32, channels: usize) -> Result<Self> {
+            let opus_channels = match channels {
+                1 => Channels::Mono,
+                2 => Channels::Stereo,
+                _ => bail!("Opus over RTP supports 1 or 2 channels, got {}", channels),
+            };
+            // Low delay rather than the best quality per bit, it's live
+            let mut encoder = opus::Encoder::new(OPUS_RATE, opus_channels, Application::LowDelay)?;
+            encoder.set_bitrate(Bitrate::Bits(bitrate_kbps as i32 * 1000))?;
+            Ok(OpusPacketizer {
+                encoder,
+                resampler: Resampler::new(sample_rate, OPUS_RATE, channels, Quality::High),
+                channels,
+                pending: Vec::with_capacity(FRAME_SIZE * channels * 2),
+                packet: vec![0; MAX_PACKET],
+            })
+        }
+    }
+
+    impl Packetizer for OpusPacketizer {
+        fn push(&mut self, samples: &[f32], send: &mut dyn FnMut(&[u8], u32)) {
+            let pending = &mut self.pending;
+            self.resampler
+                .process(samples, |frame| pending.extend_from_slice(frame));
+            let frame = FRAME_SIZE * self.channels;
+            while self.pending.len() >= frame {
+                if let Ok(size) = self
+                    .encoder
+                    .encode_float(&self.pending[..frame], &mut self.packet)
+                {
+                    send(&self.packet[..size], FRAME_SIZE as u32);
+                }
+                self.pending.drain(..frame);
+            }
+        }
+
+        fn clock_rate(&self) -> u32 {
+            OPUS_RATE
+        }
+
+        fn packet_ms(&self) -> f32 {
+            20.0
+        }
+    }
+}
+
+/// Ties an AES67 stream to PTP time. The output is resampled to 48 kHz
+/// and the resampler follows the sound card's drift against the PTP
+/// clock, so the RTP timestamps stay where receivers expect them.
+struct MediaClock {
+    ptp: PtpClock,
+    resampler: Resampler,
+    estimator: DriftEstimator,
+    /// Timestamp error in frames summed since the last update.
+    error: f64,
+    count: u32,
+    last_update: Instant,
+    resampled: Vec<f32>,
+}
+
+impl MediaClock {
+    fn new(ptp: PtpClock, sample_rate: u32, channels: usize) -> Self {
+        let mut resampler = Resampler::new(sample_rate, AES67_RATE, channels, Quality::High);
+        resampler.set_drift(0.0);
+        MediaClock {
+            ptp,
+            resampler,
+            estimator: DriftEstimator::new(AES67_RATE, CLOCK_UPDATE.as_secs_f64()),
+            error: 0.0,
+            count: 0,
+            last_update: Instant::now(),
+            resampled: Vec::new(),
+        }
+    }
+
+    /// RTP timestamp of the current PTP time, counted from the PTP epoch
+    /// the way AES67 has it with a media clock offset of 0.
+    fn now(&self) -> u32 {
+        (self.ptp.now_ns() as i128 * AES67_RATE as i128 / 1_000_000_000) as u32
+    }
+
+    /// Compares the timestamp of the audio about to be sent against PTP
+    /// time. Returns the timestamp to continue from when it's too far off
+    /// to slew back.
+    fn measure(&mut self, timestamp: u32) -> Option<u32> {
+        let now = self.now();
+        let error = timestamp.wrapping_sub(now) as i32;
+        if error.unsigned_abs() > MAX_CLOCK_ERROR {
+            self.estimator = DriftEstimator::new(AES67_RATE, CLOCK_UPDATE.as_secs_f64());
+            self.resampler.set_drift(0.0);
+            self.error = 0.0;
+            self.count = 0;
+            return Some(now);
+        }
+        self.error += error as f64;
+        self.count += 1;
+        if self.last_update.elapsed() >= CLOCK_UPDATE {
+            let correction = self.estimator.update(self.error / self.count as f64);
+            self.resampler.set_drift(correction);
+            self.error = 0.0;
+            self.count = 0;
+            self.last_update = Instant::now();
+        }
+        None
+    }
+
+    fn resample(&mut self, samples: &[f32]) -> &[f32] {
+        let resampled = &mut self.resampled;
+        resampled.clear();
+        self.resampler
+            .process(samples, |frame| resampled.extend_from_slice(frame));
+        &self.resampled
+    }
+}
+
+/// Control side of an RTP stream: packets are built and sent on their own
+/// thread, which ends once the tap is dropped.
+pub struct RtpSender {
+    name: String,
+    dropped: Arc<AtomicU64>,
+    sender: JoinHandle<u64>,
+}
+
+impl RtpSender {
+    /// Opens the socket, writes or prints the SDP and starts the sender
+    /// thread. Returns the tap to feed from the audio callback.
+    pub fn start(
+        settings: &RtpSettings,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<(RtpSender, OutputTap)> {
+        let packetizer: Box<dyn Packetizer> = match settings.codec {
+            // AES67 is resampled to 48 kHz by the media clock first
+            RtpCodec::L16 | RtpCodec::L24 if settings.aes67 => {
+                Box::new(PcmPacketizer::new(settings, AES67_RATE, channels))
+            }
+            RtpCodec::L16 | RtpCodec::L24 => {
+                Box::new(PcmPacketizer::new(settings, sample_rate, channels))
+            }
+            #[cfg(feature = "opus")]
+            RtpCodec::Opus => Box::new(opus_packetizer::OpusPacketizer::new(
+                settings.bitrate_kbps,
+                sample_rate,
+                channels,
+            )?),
+            #[cfg(not(feature = "opus"))]
+            RtpCodec::Opus => anyhow::bail!("Opus over RTP needs a build with `--features opus`"),
+        };
+
+        let destination = settings.destination;
+        let bind = if destination.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind).context("Failed to open the RTP socket")?;
+        if destination.ip().is_multicast() {
+            match destination.ip() {
+                IpAddr::V4(_) => socket.set_multicast_ttl_v4(MULTICAST_TTL)?,
+                IpAddr::V6(_) => {}
+            }
+        }
+        socket
+            .connect(destination)
+            .with_context(|| format!("Failed to reach {}", destination))?;
+
+        let clock = if settings.aes67 {
+            let ptp = PtpClock::spawn(settings.ptp_domain)?;
+            if ptp.wait_for_master(PTP_WAIT) {
+                info!("PTP: locked in domain {}", settings.ptp_domain);
+            } else {
+                warn!(
+                    "No PTP master in domain {} yet, timestamps follow the local clock \
+                     until one is heard",
+                    settings.ptp_domain
+                );
+            }
+            Some(MediaClock::new(ptp, sample_rate, channels))
+        } else {
+            None
+        };
+
+        let origin = socket.local_addr()?.ip();
+        let description = Description {
+            destination,
+            codec: settings.codec,
+            origin,
+            clock_rate: packetizer.clock_rate(),
+            channels,
+            packet_ms: packetizer.packet_ms(),
+            session: random_u32(3),
+        };
+        let reference = clock.as_ref().map(|clock| clock.ptp.reference());
+        let sdp = description.sdp(reference.as_deref());
+        match &settings.sdp {
+            Some(path) => {
+                fs::write(path, &sdp)
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                info!("RTP session description written to {}", path.display());
+            }
+            None => print!("RTP session description:\n{}", sdp.replace('\r', "")),
+        }
+        // AES67 receivers find the stream themselves
+        let announcer = settings
+            .aes67
+            .then(|| SapAnnouncer::new(origin, MULTICAST_TTL))
+            .flatten()
+            .map(|announcer| (announcer, description));
+
+        let (tap, reader) = output_tap::output_tap(sample_rate, channels, RING_SECONDS);
+        let dropped = reader.dropped();
+        let name = destination.to_string();
+        let stream = Stream {
+            socket,
+            ssrc: random_u32(0),
+            sequence: random_u32(1) as u16,
+            timestamp: random_u32(2),
+            marker: true,
+            packets: 0,
+            packet: Vec::with_capacity(HEADER_LEN + MAX_PAYLOAD),
+        };
+        let sender = thread::Builder::new()
+            .name(format!("rtp {}", name))
+            .spawn(move || send(stream, packetizer, reader, clock, announcer))?;
+
+        let rtp = RtpSender {
+            name,
+            dropped,
+            sender,
+        };
+        Ok((rtp, tap))
+    }
+
+    /// Waits for the sender to drain the ring. Call after the stream
+    /// feeding the tap has been dropped.
+    pub fn finish(self) -> Result<()> {
+        let packets = self
+            .sender
+            .join()
+            .map_err(|_| anyhow::anyhow!("RTP sender for {} panicked", self.name))?;
+        info!("Sent {} RTP packets to {}", packets, self.name);
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(
+                "RTP sender {} dropped {} samples, it could not keep up",
+                self.name, dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Header state of the packets sent so far.
+struct Stream {
+    socket: UdpSocket,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    marker: bool,
+    packets: u64,
+    packet: Vec<u8>,
+}
+
+impl Stream {
+    /// A receiver that isn't up yet makes sends fail, the stream just
+    /// carries on so it picks up once it is.
+    fn send(&mut self, payload: &[u8], ticks: u32) {
+        self.packet.clear();
+        write_header(
+            &mut self.packet,
+            self.marker,
+            self.sequence,
+            self.timestamp,
+            self.ssrc,
+        );
+        self.packet.extend_from_slice(payload);
+        if self.socket.send(&self.packet).is_ok() {
+            self.packets += 1;
+        }
+        self.marker = false;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(ticks);
+    }
+}
+
+/// Packetizes the ring until the tap is dropped, returns the packets sent.
+fn send(
+    mut stream: Stream,
+    mut packetizer: Box<dyn Packetizer>,
+    mut reader: TapReader,
+    mut clock: Option<MediaClock>,
+    mut announcer: Option<(SapAnnouncer, Description)>,
+) -> u64 {
+    let reference = |clock: &Option<MediaClock>| clock.as_ref().map(|clock| clock.ptp.reference());
+    let mut block = vec![0.0f32; 4096];
+    loop {
+        if let Some((sap, description)) = &mut announcer {
+            sap.poll(|| description.sdp(reference(&clock).as_deref()));
+        }
+        let Some(count) = reader.read(&mut block, POLL) else {
+            break;
+        };
+        let samples = match &mut clock {
+            Some(clock) => {
+                if let Some(timestamp) = clock.measure(stream.timestamp) {
+                    // A discontinuity, marked as at the start
+                    stream.timestamp = timestamp;
+                    stream.marker = true;
+                }
+                clock.resample(&block[..count])
+            }
+            None => &block[..count],
+        };
+        packetizer.push(samples, &mut |payload, ticks| stream.send(payload, ticks));
+    }
+
+    if let Some((mut sap, description)) = announcer {
+        sap.delete(&description.sdp(reference(&clock).as_deref()));
+    }
+    if let Some(clock) = clock {
+        clock.ptp.stop();
+    }
+    stream.packets
+}
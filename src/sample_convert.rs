@@ -0,0 +1,290 @@
+use crate::dither::{Dither, DitherMode};
+use anyhow::{Result, bail};
+use cpal::traits::DeviceTrait;
+use cpal::{
+    BufferSize, Device, FromSample, Sample, SampleFormat, SizedSample, Stream, StreamConfig,
+    StreamError, SupportedBufferSize,
+};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Scratch space allocated up front. Bigger callbacks are handed on in
+/// pieces of this size so the audio thread never allocates.
+const SCRATCH_SAMPLES: usize = 8192;
+
+/// Builds an input stream in the device's native sample format and hands
+/// the callback interleaved f32 samples in `[-1.0, 1.0]`.
+///
+/// A fixed buffer size the device refuses falls back to the default size,
+/// `config` is left holding the one in use.
+pub fn build_input_stream<D, E>(
+    device: &Device,
+    config: &mut StreamConfig,
+    format: SampleFormat,
+    callback: D,
+    error_callback: E,
+) -> Result<Stream>
+where
+    D: FnMut(&[f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    if matches!(config.buffer_size, BufferSize::Default) {
+        return build_input_in_format(device, config, format, callback, error_callback);
+    }
+
+    let (callback, error_callback) = (shared(callback), shared(error_callback));
+    with_fallback(config, |config| {
+        let callback = callback.clone();
+        let error_callback = error_callback.clone();
+        build_input_in_format(
+            device,
+            config,
+            format,
+            move |data: &[f32]| {
+                if let Ok(mut callback) = callback.try_lock() {
+                    callback(data)
+                }
+            },
+            move |err| {
+                if let Ok(mut error_callback) = error_callback.try_lock() {
+                    error_callback(err)
+                }
+            },
+        )
+    })
+}
+
+fn build_input_in_format<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    callback: D,
+    error_callback: E,
+) -> Result<Stream>
+where
+    D: FnMut(&[f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let stream = match format {
+        SampleFormat::I8 => build_input::<i8, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I16 => build_input::<i16, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I32 => build_input::<i32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I64 => build_input::<i64, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U8 => build_input::<u8, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U16 => build_input::<u16, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U32 => build_input::<u32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U64 => build_input::<u64, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::F32 => build_input::<f32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::F64 => build_input::<f64, _, _>(device, config, callback, error_callback)?,
+        f => bail!("Unsupported input format: {:?}", f),
+    };
+    Ok(stream)
+}
+
+/// Builds an output stream in the device's native sample format. The
+/// callback fills an interleaved f32 buffer which is then converted,
+/// through `dither` for integer formats.
+///
+/// Falls back to the default buffer size like `build_input_stream`.
+pub fn build_output_stream<D, E>(
+    device: &Device,
+    config: &mut StreamConfig,
+    format: SampleFormat,
+    dither: DitherMode,
+    callback: D,
+    error_callback: E,
+) -> Result<Stream>
+where
+    D: FnMut(&mut [f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    if matches!(config.buffer_size, BufferSize::Default) {
+        return build_output_in_format(device, config, format, dither, callback, error_callback);
+    }
+
+    let (callback, error_callback) = (shared(callback), shared(error_callback));
+    with_fallback(config, |config| {
+        let callback = callback.clone();
+        let error_callback = error_callback.clone();
+        build_output_in_format(
+            device,
+            config,
+            format,
+            dither,
+            move |data: &mut [f32]| {
+                if let Ok(mut callback) = callback.try_lock() {
+                    callback(data)
+                }
+            },
+            move |err| {
+                if let Ok(mut error_callback) = error_callback.try_lock() {
+                    error_callback(err)
+                }
+            },
+        )
+    })
+}
+
+fn build_output_in_format<D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    format: SampleFormat,
+    dither: DitherMode,
+    mut callback: D,
+    error_callback: E,
+) -> Result<Stream>
+where
+    D: FnMut(&mut [f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    // 32-bit integer devices are taken as 24-bit converters, the most any
+    // delivers
+    let bits = match format {
+        SampleFormat::I8 | SampleFormat::U8 => Some(8),
+        SampleFormat::I16 | SampleFormat::U16 => Some(16),
+        SampleFormat::I32 | SampleFormat::U32 => Some(24),
+        _ => None,
+    };
+    let channels = (config.channels as usize).max(1);
+    let mut dither = bits.map(|bits| Dither::new(dither, bits, channels));
+    let callback = move |data: &mut [f32]| {
+        callback(data);
+        if let Some(dither) = dither.as_mut() {
+            dither.process(data, channels);
+        }
+    };
+    let stream = match format {
+        SampleFormat::I8 => build_output::<i8, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I16 => build_output::<i16, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I32 => build_output::<i32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::I64 => build_output::<i64, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U8 => build_output::<u8, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U16 => build_output::<u16, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U32 => build_output::<u32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::U64 => build_output::<u64, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::F32 => build_output::<f32, _, _>(device, config, callback, error_callback)?,
+        SampleFormat::F64 => build_output::<f64, _, _>(device, config, callback, error_callback)?,
+        f => bail!("Unsupported output format: {:?}", f),
+    };
+    Ok(stream)
+}
+
+/// Asks for `frames` per callback, clamped to what the device reports it
+/// supports.
+pub fn fixed_buffer_size(supported: &SupportedBufferSize, frames: u32) -> BufferSize {
+    match *supported {
+        SupportedBufferSize::Range { min, max } if min <= max => {
+            BufferSize::Fixed(frames.clamp(min, max))
+        }
+        _ => BufferSize::Fixed(frames),
+    }
+}
+
+/// Callbacks survive a failed build only if the attempt doesn't own them.
+/// Once a stream runs only its audio thread takes them, with `try_lock`,
+/// which never blocks and never finds them taken.
+fn shared<T>(callback: T) -> Arc<Mutex<T>> {
+    Arc::new(Mutex::new(callback))
+}
+
+/// Builds with `config`, and if that fails, once more with the device's
+/// default buffer size.
+fn with_fallback(
+    config: &mut StreamConfig,
+    build: impl Fn(&StreamConfig) -> Result<Stream>,
+) -> Result<Stream> {
+    build(config).or_else(|err| {
+        warn!(
+            "Buffer size {:?} was refused ({}), falling back to the device default",
+            config.buffer_size, err
+        );
+        config.buffer_size = BufferSize::Default;
+        build(config)
+    })
+}
+
+pub fn to_f32<T>(input: &[T], output: &mut [f32])
+where
+    T: Sample,
+    f32: FromSample<T>,
+{
+    for (out, &sample) in output.iter_mut().zip(input) {
+        *out = f32::from_sample(sample);
+    }
+}
+
+pub fn from_f32<T>(input: &[f32], output: &mut [T])
+where
+    T: Sample + FromSample<f32>,
+{
+    for (out, &sample) in output.iter_mut().zip(input) {
+        *out = T::from_sample(sample.clamp(-1.0, 1.0));
+    }
+}
+
+/// Most frames a callback hands on at once with `channels` channels, what
+/// the processing after it has to be sized for.
+pub fn max_frames(channels: usize) -> usize {
+    SCRATCH_SAMPLES / channels.max(1)
+}
+
+/// Largest whole number of frames that fits the scratch, in samples.
+fn block_len(config: &StreamConfig) -> usize {
+    let channels = config.channels as usize;
+    max_frames(channels) * channels.max(1)
+}
+
+fn build_input<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: D,
+    error_callback: E,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    D: FnMut(&[f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let mut converted = vec![0.0f32; SCRATCH_SAMPLES];
+    let block = block_len(config);
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &_| {
+            for chunk in data.chunks(block) {
+                let converted = &mut converted[..chunk.len()];
+                to_f32(chunk, converted);
+                callback(converted);
+            }
+        },
+        error_callback,
+        None,
+    )
+}
+
+fn build_output<T, D, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut callback: D,
+    error_callback: E,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    D: FnMut(&mut [f32]) + Send + 'static,
+    E: FnMut(StreamError) + Send + 'static,
+{
+    let mut converted = vec![0.0f32; SCRATCH_SAMPLES];
+    let block = block_len(config);
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &_| {
+            for chunk in data.chunks_mut(block) {
+                let converted = &mut converted[..chunk.len()];
+                callback(converted);
+                from_f32(converted, chunk);
+            }
+        },
+        error_callback,
+        None,
+    )
+}
@@ -0,0 +1,580 @@
+use crate::control::Controls;
+use crate::mixer::{AuxControls, AuxSend, ChannelControls, MasterControls};
+use crate::params::Param;
+use anyhow::{Context, Result, bail};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Highest scene number, a program change reaches 1 to 128.
+pub const MAX_SCENE: usize = 128;
+/// Parameters that fire something rather than hold a setting, never
+/// stored. Matched on the end of the key so bus copies count too.
+const MOMENTARY: [&str; 4] = [
+    "talkback.on",
+    "looper.record",
+    "looper.undo",
+    "looper.clear",
+];
+/// Last part of the key of an on/off setting. These switch at the start of
+/// a fade instead of passing through values in between.
+const SWITCHES: [&str; 7] = ["mute", "solo", "onair", "dim", "mono", "bypass", "on"];
+
+/// One stored value, with a fade time of its own when it shouldn't take
+/// the scene's.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value {
+    pub key: String,
+    pub value: f32,
+    pub fade: Option<f32>,
+}
+
+/// A snapshot of the mixer and every effect parameter, recalled by number
+/// with `scene <n>`, an OSC message or a MIDI program change.
+///
+/// Scenes live in a plain text file, written back whenever one is saved
+/// with `scene save <n>`. A `[number]` or `[number name]` line starts one,
+/// `fade = <seconds>` sets its fade and every other line is a setting,
+/// optionally with a fade of its own:
+///
+/// ```text
+/// [1 intro]
+/// fade = 2
+/// channel.1.gain = -3
+/// channel.1.mute = 0
+/// channel.2.send.1 = -12
+/// master.gain = 0
+/// reverb.mix = 0.4 fade 6
+/// ```
+///
+/// Mixer keys are `channel.<n>.gain`, `pan`, `mute`, `solo`, `onair`,
+/// `send.<aux>` and `prefader.<aux>`, `aux.<n>.return` and `onair`,
+/// `master.gain`, `dim` and `mono` and `broadcast.gain`, the rest are
+/// effect parameters as `params` lists them. A recall only sets the keys
+/// its scene holds. Comments, lines starting with `#`, are lost when the
+/// file is written back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scene {
+    pub number: usize,
+    pub name: Option<String>,
+    /// Seconds, 0 recalls at once.
+    pub fade: f32,
+    pub values: Vec<Value>,
+}
+
+/// A setting on its way to a scene's value.
+struct Ramp {
+    key: String,
+    from: f32,
+    to: f32,
+    start: Instant,
+    length: Duration,
+    /// Last value written, anything else means it was moved by hand and
+    /// the fade lets go of it.
+    written: f32,
+}
+
+/// Settings on their way to new values, from scene recalls, `fade` and
+/// cues. Moved along by the control loop.
+#[derive(Default)]
+pub struct Fades {
+    ramps: Mutex<Vec<Ramp>>,
+}
+
+impl Fades {
+    /// Takes the setting under `key` to `value` over `seconds`, at once
+    /// for 0 or a switch. A fade already running on it is dropped. Returns
+    /// whether it's fading.
+    pub fn start(&self, controls: &Controls, key: &str, value: f32, seconds: f32) -> Result<bool> {
+        check_fade(seconds)?;
+        let setting = Setting::find(controls, key)
+            .with_context(|| format!("No setting '{}', see scene.rs for the keys", key))?;
+        let mut ramps = self.ramps.lock().unwrap();
+        ramps.retain(|ramp| ramp.key != key);
+        let from = setting.get();
+        // Unchanged ones are left be, a parameter only costs the audio
+        // thread when its version moves
+        if from == value {
+            return Ok(false);
+        }
+        if seconds == 0.0 || is_switch(key) {
+            setting.set(value);
+            return Ok(false);
+        }
+        ramps.push(Ramp {
+            key: key.to_string(),
+            from,
+            to: value,
+            start: Instant::now(),
+            length: Duration::from_secs_f32(seconds),
+            written: from,
+        });
+        Ok(true)
+    }
+
+    /// Drops the fade running on `key`, leaving it where it got to.
+    pub fn cancel(&self, key: &str) {
+        self.ramps.lock().unwrap().retain(|ramp| ramp.key != key);
+    }
+
+    /// Moves the running fades along.
+    pub fn step(&self, controls: &Controls) {
+        let mut ramps = self.ramps.lock().unwrap();
+        if ramps.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        ramps.retain_mut(|ramp| {
+            let Some(setting) = Setting::find(controls, &ramp.key) else {
+                return false;
+            };
+            // Moved since the last step, whoever did it wins
+            if (setting.get() - ramp.written).abs() > 1e-4 {
+                return false;
+            }
+            let position = now.duration_since(ramp.start).as_secs_f32() / ramp.length.as_secs_f32();
+            let position = position.min(1.0);
+            ramp.written = setting.set(ramp.from + (ramp.to - ramp.from) * position);
+            position < 1.0
+        });
+    }
+}
+
+/// The scenes, kept by the control loop.
+#[derive(Default)]
+pub struct Scenes {
+    /// Written back on save, when scenes came from a file.
+    path: Option<PathBuf>,
+    scenes: Mutex<Vec<Scene>>,
+    /// Number of the scene last recalled.
+    current: Mutex<Option<usize>>,
+}
+
+impl Scenes {
+    /// Scenes from `path`, none when it isn't there yet. The first save
+    /// creates it.
+    pub fn load(path: &Path) -> Result<Self> {
+        let scenes = if path.exists() {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read scenes from {}", path.display()))?;
+            parse(&text).with_context(|| format!("Invalid scenes in {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+        Ok(Scenes {
+            path: Some(path.to_path_buf()),
+            scenes: Mutex::new(scenes),
+            ..Scenes::default()
+        })
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        *self.current.lock().unwrap()
+    }
+
+    /// One line per scene.
+    pub fn describe(&self) -> String {
+        let scenes = self.scenes.lock().unwrap();
+        if scenes.is_empty() {
+            return "No scenes, store one with 'scene save <n>'".to_string();
+        }
+        let current = self.current();
+        scenes
+            .iter()
+            .map(|scene| {
+                format!(
+                    "[{}] {}: {} settings, fade {:.1} s{}",
+                    scene.number,
+                    scene.name.as_deref().unwrap_or("unnamed"),
+                    scene.values.len(),
+                    scene.fade,
+                    if current == Some(scene.number) {
+                        ", current"
+                    } else {
+                        ""
+                    }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Stores every setting as scene `number`. Fades of a scene already
+    /// there are kept for the keys it had.
+    pub fn save(&self, number: usize, name: Option<&str>, controls: &Controls) -> Result<String> {
+        check_number(number)?;
+        let mut scenes = self.scenes.lock().unwrap();
+        let old = scenes.iter().position(|s| s.number == number);
+        let old = old.map(|index| scenes.remove(index));
+        let fade_of = |key: &str| {
+            old.as_ref()
+                .and_then(|s| s.values.iter().find(|v| v.key == key))
+                .and_then(|v| v.fade)
+        };
+        let values: Vec<Value> = snapshot(controls)
+            .into_iter()
+            .map(|(key, value)| {
+                let fade = fade_of(&key);
+                Value { key, value, fade }
+            })
+            .collect();
+        let scene = Scene {
+            number,
+            name: name
+                .map(str::to_string)
+                .or_else(|| old.as_ref().and_then(|s| s.name.clone())),
+            fade: old.as_ref().map_or(0.0, |s| s.fade),
+            values,
+        };
+        let line = format!("Scene {} saved, {} settings", number, scene.values.len());
+        let index = scenes.partition_point(|s| s.number < number);
+        scenes.insert(index, scene);
+        *self.current.lock().unwrap() = Some(number);
+        self.write(&scenes)?;
+        Ok(line)
+    }
+
+    /// Sets the fade of scene `number`, or of one of its settings.
+    pub fn set_fade(&self, number: usize, key: Option<&str>, seconds: f32) -> Result<String> {
+        check_fade(seconds)?;
+        let mut scenes = self.scenes.lock().unwrap();
+        let scene = find(&mut scenes, number)?;
+        let line = match key {
+            Some(key) => {
+                let value = scene
+                    .values
+                    .iter_mut()
+                    .find(|v| v.key == key)
+                    .with_context(|| format!("Scene {} doesn't hold '{}'", number, key))?;
+                value.fade = Some(seconds);
+                format!("Scene {}: {} fades in {:.1} s", number, key, seconds)
+            }
+            None => {
+                scene.fade = seconds;
+                format!("Scene {} fades in {:.1} s", number, seconds)
+            }
+        };
+        self.write(&scenes)?;
+        Ok(line)
+    }
+
+    /// Recalls scene `number`, over `fade` seconds when given instead of
+    /// the scene's own. Settings with a fade of their own keep it.
+    pub fn recall(&self, number: usize, fade: Option<f32>, controls: &Controls) -> Result<String> {
+        if let Some(seconds) = fade {
+            check_fade(seconds)?;
+        }
+        let mut scenes = self.scenes.lock().unwrap();
+        let scene = find(&mut scenes, number)?;
+        let default = fade.unwrap_or(scene.fade);
+
+        let mut missing = Vec::new();
+        let mut fading = 0;
+        for value in &scene.values {
+            let seconds = value.fade.unwrap_or(default);
+            match controls
+                .fades
+                .start(controls, &value.key, value.value, seconds)
+            {
+                Ok(true) => fading += 1,
+                Ok(false) => {}
+                Err(_) => missing.push(value.key.as_str()),
+            }
+        }
+        *self.current.lock().unwrap() = Some(number);
+
+        let mut line = format!(
+            "Scene {}{}",
+            number,
+            scene
+                .name
+                .as_ref()
+                .map_or(String::new(), |name| format!(" ({})", name))
+        );
+        if fading > 0 {
+            let _ = write!(line, ", fading {} settings", fading);
+        }
+        if !missing.is_empty() {
+            let _ = write!(line, ", skipped {}", missing.join(", "));
+        }
+        Ok(line)
+    }
+
+    fn write(&self, scenes: &[Scene]) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        fs::write(path, format(scenes))
+            .with_context(|| format!("Failed to write scenes to {}", path.display()))
+    }
+}
+
+fn check_number(number: usize) -> Result<()> {
+    if !(1..=MAX_SCENE).contains(&number) {
+        bail!("Scenes are numbered 1 to {}", MAX_SCENE);
+    }
+    Ok(())
+}
+
+fn check_fade(seconds: f32) -> Result<()> {
+    if !seconds.is_finite() || seconds < 0.0 {
+        bail!("Fade times are seconds from 0 up");
+    }
+    Ok(())
+}
+
+fn find(scenes: &mut [Scene], number: usize) -> Result<&mut Scene> {
+    check_number(number)?;
+    scenes
+        .iter_mut()
+        .find(|s| s.number == number)
+        .with_context(|| {
+            format!(
+                "No scene {}, store one with 'scene save {}'",
+                number, number
+            )
+        })
+}
+
+pub fn parse(text: &str) -> Result<Vec<Scene>> {
+    let mut scenes: Vec<Scene> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let (scene, name) = match header.trim().split_once(char::is_whitespace) {
+                Some((scene, name)) => (scene, Some(name.trim().to_string())),
+                None => (header.trim(), None),
+            };
+            let scene: usize = scene.parse().with_context(|| {
+                format!("Line {}: invalid scene number '{}'", number + 1, scene)
+            })?;
+            check_number(scene).with_context(|| format!("Line {}", number + 1))?;
+            if scenes.iter().any(|s| s.number == scene) {
+                bail!("Line {}: scene {} is defined twice", number + 1, scene);
+            }
+            scenes.push(Scene {
+                number: scene,
+                name,
+                fade: 0.0,
+                values: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some(scene) = scenes.last_mut() else {
+            bail!("Line {}: expected a [scene] line first", number + 1);
+        };
+        let Some((key, rest)) = line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) else {
+            bail!("Line {}: expected '<key> = <value>'", number + 1);
+        };
+        let seconds = |text: &str| {
+            text.parse::<f32>()
+                .ok()
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .with_context(|| format!("Line {}: invalid fade '{}'", number + 1, text))
+        };
+        if key == "fade" {
+            scene.fade = seconds(rest)?;
+            continue;
+        }
+        let words: Vec<&str> = rest.split_whitespace().collect();
+        let (value, fade) = match words.as_slice() {
+            [value] => (*value, None),
+            [value, "fade", fade] => (*value, Some(seconds(fade)?)),
+            _ => bail!(
+                "Line {}: expected '<key> = <value> [fade <seconds>]'",
+                number + 1
+            ),
+        };
+        let value: f32 = value
+            .parse()
+            .with_context(|| format!("Line {}: invalid value '{}'", number + 1, value))?;
+        scene.values.retain(|v| v.key != key);
+        scene.values.push(Value {
+            key: key.to_string(),
+            value,
+            fade,
+        });
+    }
+    scenes.sort_by_key(|s| s.number);
+    Ok(scenes)
+}
+
+fn format(scenes: &[Scene]) -> String {
+    let mut text = String::new();
+    for scene in scenes {
+        match &scene.name {
+            Some(name) => writeln!(text, "[{} {}]", scene.number, name),
+            None => writeln!(text, "[{}]", scene.number),
+        }
+        .unwrap();
+        writeln!(text, "fade = {}", scene.fade).unwrap();
+        for value in &scene.values {
+            match value.fade {
+                Some(fade) => writeln!(text, "{} = {} fade {}", value.key, value.value, fade),
+                None => writeln!(text, "{} = {}", value.key, value.value),
+            }
+            .unwrap();
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn is_switch(key: &str) -> bool {
+    let mut parts = key.rsplit('.');
+    let last = parts.next().unwrap_or("");
+    SWITCHES.contains(&last) || parts.next() == Some("prefader")
+}
+
+fn is_momentary(key: &str) -> bool {
+    MOMENTARY
+        .iter()
+        .any(|m| key == *m || key.strip_suffix(m).is_some_and(|bus| bus.ends_with('.')))
+}
+
+/// Every setting a scene stores with its value, mixer first.
+pub fn snapshot(controls: &Controls) -> Vec<(String, f32)> {
+    keys(controls)
+        .into_iter()
+        .filter_map(|key| {
+            let value = Setting::find(controls, &key)?.get();
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Sets the setting under `key` at once, stopping a fade on it. `false`
+/// when there's no such setting.
+pub fn set(controls: &Controls, key: &str, value: f32) -> bool {
+    controls.fades.cancel(key);
+    Setting::find(controls, key)
+        .map(|setting| setting.set(value))
+        .is_some()
+}
+
+/// Every setting a scene stores, mixer first.
+fn keys(controls: &Controls) -> Vec<String> {
+    let mut keys = Vec::new();
+    for (index, channel) in controls.channels.iter().enumerate() {
+        let prefix = format!("channel.{}", index + 1);
+        for name in ["gain", "pan", "mute", "solo", "onair"] {
+            keys.push(format!("{}.{}", prefix, name));
+        }
+        for bus in 1..=channel.sends.len() {
+            keys.push(format!("{}.send.{}", prefix, bus));
+            keys.push(format!("{}.prefader.{}", prefix, bus));
+        }
+    }
+    for bus in 1..=controls.aux.len() {
+        keys.push(format!("aux.{}.return", bus));
+        keys.push(format!("aux.{}.onair", bus));
+    }
+    keys.extend(["master.gain", "master.dim", "master.mono"].map(str::to_string));
+    if controls.broadcast.is_some() {
+        keys.push("broadcast.gain".to_string());
+    }
+    keys.extend(
+        controls
+            .params
+            .all()
+            .into_iter()
+            .map(|p| p.key.clone())
+            .filter(|key| !is_momentary(key)),
+    );
+    keys
+}
+
+/// A setting a key names, with its value as a number, switches 0 or 1.
+enum Setting<'a> {
+    Gain(&'a ChannelControls),
+    Pan(&'a ChannelControls),
+    Mute(&'a ChannelControls),
+    Solo(&'a ChannelControls),
+    OnAir(&'a ChannelControls),
+    Send(&'a AuxSend),
+    PreFader(&'a AuxSend),
+    Return(&'a AuxControls),
+    ReturnOnAir(&'a AuxControls),
+    Master(&'a MasterControls),
+    Dim(&'a MasterControls),
+    Mono(&'a MasterControls),
+    Param(Arc<Param>),
+}
+
+impl<'a> Setting<'a> {
+    /// `None` when there's no such setting, e.g. a channel that's gone.
+    fn find(controls: &'a Controls, key: &str) -> Option<Self> {
+        let channel = |ch: &str| {
+            controls
+                .channels
+                .get(ch.parse::<usize>().ok()?.checked_sub(1)?)
+        };
+        let aux = |bus: &str| bus.parse::<usize>().ok()?.checked_sub(1);
+        let send = |ch: &str, bus: &str| channel(ch)?.sends.get(aux(bus)?);
+        let aux_bus = |bus: &str| controls.aux.get(aux(bus)?);
+        let master = &*controls.master;
+        let setting = match key.split('.').collect::<Vec<_>>().as_slice() {
+            ["channel", ch, "gain"] => Setting::Gain(channel(ch)?),
+            ["channel", ch, "pan"] => Setting::Pan(channel(ch)?),
+            ["channel", ch, "mute"] => Setting::Mute(channel(ch)?),
+            ["channel", ch, "solo"] => Setting::Solo(channel(ch)?),
+            ["channel", ch, "onair"] => Setting::OnAir(channel(ch)?),
+            ["channel", ch, "send", bus] => Setting::Send(send(ch, bus)?),
+            ["channel", ch, "prefader", bus] => Setting::PreFader(send(ch, bus)?),
+            ["aux", bus, "return"] => Setting::Return(aux_bus(bus)?),
+            ["aux", bus, "onair"] => Setting::ReturnOnAir(aux_bus(bus)?),
+            ["master", "gain"] => Setting::Master(master),
+            ["master", "dim"] => Setting::Dim(master),
+            ["master", "mono"] => Setting::Mono(master),
+            ["broadcast", "gain"] => Setting::Master(&controls.broadcast.as_ref()?.master),
+            _ => Setting::Param(controls.params.get(key)?),
+        };
+        Some(setting)
+    }
+
+    fn get(&self) -> f32 {
+        let flag = |on: bool| if on { 1.0 } else { 0.0 };
+        match self {
+            Setting::Gain(c) => c.gain_db(),
+            Setting::Pan(c) => c.pan(),
+            Setting::Mute(c) => flag(c.muted()),
+            Setting::Solo(c) => flag(c.soloed()),
+            Setting::OnAir(c) => flag(c.on_air()),
+            Setting::Send(s) => s.level_db(),
+            Setting::PreFader(s) => flag(s.pre_fader()),
+            Setting::Return(a) => a.return_db(),
+            Setting::ReturnOnAir(a) => flag(a.on_air()),
+            Setting::Master(m) => m.gain_db(),
+            Setting::Dim(m) => flag(m.dimmed()),
+            Setting::Mono(m) => flag(m.mono()),
+            Setting::Param(p) => p.get(),
+        }
+    }
+
+    /// Returns the value taken, clamped to the setting's range.
+    fn set(&self, value: f32) -> f32 {
+        let on = value >= 0.5;
+        match self {
+            Setting::Gain(c) => c.set_gain_db(value),
+            Setting::Pan(c) => c.set_pan(value),
+            Setting::Mute(c) => c.set_mute(on),
+            Setting::Solo(c) => c.set_solo(on),
+            Setting::OnAir(c) => c.set_on_air(on),
+            Setting::Send(s) => s.set_level_db(value),
+            Setting::PreFader(s) => s.set_pre_fader(on),
+            Setting::Return(a) => a.set_return_db(value),
+            Setting::ReturnOnAir(a) => a.set_on_air(on),
+            Setting::Master(m) => m.set_gain_db(value),
+            Setting::Dim(m) => m.set_dim(on),
+            Setting::Mono(m) => m.set_mono(on),
+            Setting::Param(p) => return p.set(value),
+        }
+        self.get()
+    }
+}
@@ -0,0 +1,227 @@
+use crate::dsp::gain_to_db;
+use crate::params::AtomicF32;
+use anyhow::Result;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Points a sweep is cut down to, more than a terminal is wide in braille.
+const POINTS: usize = 1024;
+/// Range of the time across the screen.
+pub const MIN_TIME_MS: f32 = 1.0;
+pub const MAX_TIME_MS: f32 = 1000.0;
+const DEFAULT_TIME_MS: f32 = 20.0;
+/// Time between sweeps, about the TUI's frame rate. A sweep longer than
+/// this scrolls.
+const REFRESH_MS: f32 = 33.0;
+/// Samples this close to full scale count as clipped.
+const CLIP_LEVEL: f32 = 0.999;
+
+/// Levels over the last sweep.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    pub peak_db: f32,
+    /// Mean of both sides, as a share of full scale.
+    pub dc: f32,
+    pub clipped: usize,
+    /// Whether it started on the trigger rather than running free.
+    pub triggered: bool,
+}
+
+/// The scope's channel, settings and latest sweep, readable from any
+/// thread.
+#[derive(Debug)]
+pub struct ScopeView {
+    /// Mixer channel from 1, 0 while the scope is off.
+    channel: AtomicUsize,
+    time_ms: AtomicF32,
+    /// Level the left side rising through starts a sweep, NaN to run free.
+    trigger: AtomicF32,
+    /// The first two output channels of the sweep, `len` points of each.
+    left: Vec<AtomicF32>,
+    right: Vec<AtomicF32>,
+    len: AtomicUsize,
+    peak: AtomicF32,
+    dc: AtomicF32,
+    clipped: AtomicUsize,
+    triggered: AtomicBool,
+}
+
+impl ScopeView {
+    /// Mixer channel shown, counted from 0.
+    pub fn channel(&self) -> Option<usize> {
+        self.channel.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    /// Shows mixer `channel`, counted from 0, or turns the scope off.
+    pub fn set_channel(&self, channel: Option<usize>) {
+        self.len.store(0, Ordering::Relaxed);
+        self.channel
+            .store(channel.map_or(0, |channel| channel + 1), Ordering::Relaxed);
+    }
+
+    /// Time across the screen.
+    pub fn time_ms(&self) -> f32 {
+        self.time_ms.get()
+    }
+
+    pub fn set_time_ms(&self, ms: f32) -> f32 {
+        let ms = ms.clamp(MIN_TIME_MS, MAX_TIME_MS);
+        self.time_ms.set(ms);
+        ms
+    }
+
+    /// Trigger level on the left side, rising, `None` while running free.
+    pub fn trigger(&self) -> Option<f32> {
+        let level = self.trigger.get();
+        (!level.is_nan()).then_some(level)
+    }
+
+    pub fn set_trigger(&self, level: Option<f32>) {
+        self.trigger
+            .set(level.map_or(f32::NAN, |level| level.clamp(-1.0, 1.0)));
+    }
+
+    /// Left and right of the last sweep, empty until there is one.
+    pub fn trace(&self) -> (Vec<f32>, Vec<f32>) {
+        let len = self.len.load(Ordering::Relaxed);
+        let read = |side: &[AtomicF32]| side[..len].iter().map(|p| p.get()).collect();
+        (read(&self.left), read(&self.right))
+    }
+
+    pub fn sweep(&self) -> Option<Sweep> {
+        if self.channel().is_none() || self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(Sweep {
+            peak_db: gain_to_db(self.peak.get()),
+            dc: self.dc.get(),
+            clipped: self.clipped.load(Ordering::Relaxed),
+            triggered: self.triggered.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Audio-thread side: hands the shown channel's first two output channels
+/// to the scope thread. Samples are dropped when it falls behind.
+pub struct ScopeTap {
+    producer: HeapProd<(f32, f32)>,
+    view: Arc<ScopeView>,
+}
+
+impl ScopeTap {
+    /// Mixer channel to pass to `process`, counted from 0.
+    pub fn channel(&self) -> Option<usize> {
+        self.view.channel()
+    }
+
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        for frame in buffer.chunks(channels) {
+            let pair = (frame[0], frame[1.min(frame.len() - 1)]);
+            if self.producer.try_push(pair).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Starts the scope thread, off until a channel is set, and returns the
+/// tap for the mixer plus the shared view of the sweep.
+pub fn spawn(sample_rate: f32) -> Result<(ScopeTap, Arc<ScopeView>)> {
+    let points = || (0..POINTS).map(|_| AtomicF32::new(0.0)).collect();
+    let view = Arc::new(ScopeView {
+        channel: AtomicUsize::new(0),
+        time_ms: AtomicF32::new(DEFAULT_TIME_MS),
+        trigger: AtomicF32::new(0.0),
+        left: points(),
+        right: points(),
+        len: AtomicUsize::new(0),
+        peak: AtomicF32::new(0.0),
+        dc: AtomicF32::new(0.0),
+        clipped: AtomicUsize::new(0),
+        triggered: AtomicBool::new(false),
+    });
+    // Room for a sweep and as long again to look for the trigger in
+    let history = 2 * (MAX_TIME_MS * 0.001 * sample_rate) as usize;
+    let (producer, consumer) = HeapRb::<(f32, f32)>::new(history).split();
+
+    let shared = view.clone();
+    thread::Builder::new()
+        .name("scope".to_string())
+        .spawn(move || run(consumer, &shared, sample_rate, history))?;
+
+    let tap = ScopeTap {
+        producer,
+        view: view.clone(),
+    };
+    Ok((tap, view))
+}
+
+/// Publishes a sweep once per refresh of new input until the tap is
+/// dropped.
+fn run(mut consumer: HeapCons<(f32, f32)>, view: &ScopeView, sample_rate: f32, history: usize) {
+    let hop = ((REFRESH_MS * 0.001 * sample_rate) as usize).clamp(1, history);
+    let mut samples = vec![(0.0f32, 0.0f32); history];
+    loop {
+        if consumer.occupied_len() < hop {
+            if !consumer.write_is_held() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+
+        samples.copy_within(hop.., 0);
+        consumer.pop_slice(&mut samples[history - hop..]);
+        if view.channel().is_none() {
+            continue;
+        }
+
+        let width = ((view.time_ms() * 0.001 * sample_rate) as usize).clamp(1, history / 2);
+        // The latest rising edge leaving a whole sweep after it, or the
+        // newest samples when there's none or no trigger
+        let latest = history - width;
+        let start = view.trigger().and_then(|level| {
+            (latest - width + 1..=latest)
+                .rev()
+                .find(|&i| samples[i - 1].0 < level && samples[i].0 >= level)
+        });
+        publish(view, &samples[start.unwrap_or(latest)..][..width]);
+        view.triggered.store(start.is_some(), Ordering::Relaxed);
+    }
+}
+
+/// Cuts `sweep` down to at most `POINTS`, keeping each span's furthest
+/// sample from zero so peaks and clipping still show.
+fn publish(view: &ScopeView, sweep: &[(f32, f32)]) {
+    let points = sweep.len().min(POINTS);
+    let furthest = |a: f32, b: f32| if b.abs() > a.abs() { b } else { a };
+    for point in 0..points {
+        let span = &sweep[point * sweep.len() / points..(point + 1) * sweep.len() / points];
+        let (left, right) = span
+            .iter()
+            .fold((0.0f32, 0.0f32), |(l, r), &(left, right)| {
+                (furthest(l, left), furthest(r, right))
+            });
+        view.left[point].set(left);
+        view.right[point].set(right);
+    }
+    view.len.store(points, Ordering::Relaxed);
+
+    let peak = sweep
+        .iter()
+        .map(|&(left, right)| left.abs().max(right.abs()))
+        .fold(0.0, f32::max);
+    let dc =
+        sweep.iter().map(|&(left, right)| left + right).sum::<f32>() / (2 * sweep.len()) as f32;
+    let clipped = sweep
+        .iter()
+        .filter(|&&(left, right)| left.abs().max(right.abs()) >= CLIP_LEVEL)
+        .count();
+    view.peak.set(peak);
+    view.dc.set(dc);
+    view.clipped.store(clipped, Ordering::Relaxed);
+}
@@ -0,0 +1,333 @@
+use crate::control::{Command, Controls, Request};
+use crate::meter::MeterLevels;
+use crate::params::ParamStore;
+use crate::xrun::XrunStats;
+use anyhow::{Context, Result, anyhow, bail};
+use rhai::{AST, Dynamic, Engine, EvalAltResult, FLOAT, INT, Scope};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Time between rounds of hooks.
+const TICK: Duration = Duration::from_millis(50);
+/// A watched level has to stay this far below its threshold...
+const RELEASE_DB: f32 = 3.0;
+/// ...for this long before it counts as quiet again, so speech doesn't
+/// flip it between words.
+const RELEASE_HOLD: Duration = Duration::from_millis(500);
+/// Time the control loop gets to apply a script's command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A level watched with `threshold(channel, db)`.
+struct Watch {
+    channel: usize,
+    threshold_db: f32,
+    active: bool,
+    quiet_since: Option<Instant>,
+}
+
+/// Rhai script reacting to what happens in the engine. The top level runs
+/// once at start, and these functions are called when the script has them:
+///
+/// ```text
+/// on_tick()                 every 50 ms
+/// on_level(channel, active) a channel watched with threshold() got loud or quiet
+/// on_xrun(stream)           a stream had overruns, underruns or errors
+/// on_profile(name)          a profile was switched to
+/// ```
+///
+/// Scripts act through `command("gain 2 -12")`, any console command, and
+/// read with `level(ch)` and `peak(ch)` in dBFS and `param("reverb.mix")`.
+/// Ducking a backing track on channel 2 while the vocal on 1 is active:
+///
+/// ```text
+/// threshold(1, -35.0);
+///
+/// fn on_level(channel, active) {
+///     if channel == 1 {
+///         command(if active { "gain 2 -12" } else { "gain 2 0" });
+///     }
+/// }
+/// ```
+pub struct ScriptHost {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+/// What the script thread reads, cloned out of the `Controls`.
+struct Sources {
+    meters: Vec<Arc<MeterLevels>>,
+    params: Arc<ParamStore>,
+    xruns: Vec<Arc<XrunStats>>,
+    profile: Arc<Mutex<Option<String>>>,
+    remote: Sender<Request>,
+}
+
+impl ScriptHost {
+    /// Compiles the script at `path` and runs it on its own thread.
+    /// Compile errors and errors in the top level fail here; errors in a
+    /// hook are printed and the script carries on.
+    pub fn spawn(path: &Path, controls: &Controls) -> Result<ScriptHost> {
+        let sources = Sources {
+            meters: controls.channels.iter().map(|c| c.meter.clone()).collect(),
+            params: controls.params.clone(),
+            xruns: controls.xruns.clone(),
+            profile: controls.active_profile.clone(),
+            remote: controls.remote.clone(),
+        };
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let path = path.to_path_buf();
+        let name = path.display().to_string();
+        // The engine isn't Send, so it's built on the thread and the
+        // result of loading the script comes back on a channel
+        let (loaded, result) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("script".to_string())
+            .spawn(move || {
+                let script = match Script::load(path, &sources) {
+                    Ok(script) => {
+                        let _ = loaded.send(Ok(()));
+                        script
+                    }
+                    Err(err) => {
+                        let _ = loaded.send(Err(err));
+                        return;
+                    }
+                };
+                script.run(&sources, &flag);
+            })?;
+        result
+            .recv()
+            .map_err(|_| anyhow!("The script thread stopped"))?
+            .with_context(|| format!("Failed to load the script {}", name))?;
+        info!("Script: {}", name);
+        Ok(ScriptHost { running, thread })
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    watches: Rc<RefCell<Vec<Watch>>>,
+    /// Hooks the script defines, the others aren't called.
+    hooks: Vec<String>,
+}
+
+impl Script {
+    fn load(path: PathBuf, sources: &Sources) -> Result<Script> {
+        let mut engine = Engine::new();
+        let watches = Rc::new(RefCell::new(Vec::new()));
+        let started = Rc::new(Cell::new(false));
+        register(&mut engine, sources, &watches, &started);
+        engine.on_print(|text| info!("script: {}", text));
+
+        let ast = engine.compile_file(path).map_err(script_error)?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(script_error)?;
+        started.set(true);
+        let hooks = ast.iter_functions().map(|f| f.name.to_string()).collect();
+        Ok(Script {
+            engine,
+            ast,
+            scope,
+            watches,
+            hooks,
+        })
+    }
+
+    fn run(mut self, sources: &Sources, running: &AtomicBool) {
+        let mut xruns: Vec<u64> = sources
+            .xruns
+            .iter()
+            .map(|x| x.snapshot().events())
+            .collect();
+        let mut profile = sources.profile.lock().unwrap().clone();
+
+        while running.load(Ordering::Relaxed) {
+            thread::sleep(TICK);
+
+            let now = Instant::now();
+            let mut changed = Vec::new();
+            for watch in self.watches.borrow_mut().iter_mut() {
+                let level = rms_db(&sources.meters[watch.channel]);
+                if level >= watch.threshold_db {
+                    watch.quiet_since = None;
+                    if !watch.active {
+                        watch.active = true;
+                        changed.push((watch.channel, true));
+                    }
+                } else if watch.active && level < watch.threshold_db - RELEASE_DB {
+                    let since = *watch.quiet_since.get_or_insert(now);
+                    if now - since >= RELEASE_HOLD {
+                        watch.active = false;
+                        changed.push((watch.channel, false));
+                    }
+                } else {
+                    watch.quiet_since = None;
+                }
+            }
+            for (channel, active) in changed {
+                self.call("on_level", (channel as INT + 1, active));
+            }
+
+            for (stats, reported) in sources.xruns.iter().zip(xruns.iter_mut()) {
+                let events = stats.snapshot().events();
+                if events > *reported {
+                    self.call("on_xrun", (stats.name.clone(),));
+                }
+                *reported = events;
+            }
+
+            let current = sources.profile.lock().unwrap().clone();
+            if current != profile {
+                if let Some(name) = &current {
+                    self.call("on_profile", (name.clone(),));
+                }
+                profile = current;
+            }
+
+            self.call("on_tick", ());
+        }
+    }
+
+    fn call(&mut self, hook: &str, args: impl rhai::FuncArgs) {
+        if !self.hooks.iter().any(|h| h == hook) {
+            return;
+        }
+        if let Err(err) = self
+            .engine
+            .call_fn::<Dynamic>(&mut self.scope, &self.ast, hook, args)
+        {
+            warn!("script: {} failed: {}", hook, err);
+        }
+    }
+}
+
+/// Functions the script can call.
+fn register(
+    engine: &mut Engine,
+    sources: &Sources,
+    watches: &Rc<RefCell<Vec<Watch>>>,
+    started: &Rc<Cell<bool>>,
+) {
+    let count = sources.meters.len();
+    let watch_list = watches.clone();
+    engine.register_fn(
+        "threshold",
+        move |channel: INT, db: FLOAT| -> Result<(), Box<EvalAltResult>> {
+            let channel = channel_index(channel, count)?;
+            let mut watches = watch_list.borrow_mut();
+            watches.retain(|w| w.channel != channel);
+            watches.push(Watch {
+                channel,
+                threshold_db: db as f32,
+                active: false,
+                quiet_since: None,
+            });
+            Ok(())
+        },
+    );
+
+    let meters = sources.meters.clone();
+    engine.register_fn(
+        "level",
+        move |channel: INT| -> Result<FLOAT, Box<EvalAltResult>> {
+            Ok(rms_db(&meters[channel_index(channel, count)?]) as FLOAT)
+        },
+    );
+    let meters = sources.meters.clone();
+    engine.register_fn(
+        "peak",
+        move |channel: INT| -> Result<FLOAT, Box<EvalAltResult>> {
+            let levels = &meters[channel_index(channel, count)?];
+            let peak = levels
+                .levels()
+                .map(|l| l.peak_db)
+                .fold(f32::NEG_INFINITY, f32::max);
+            Ok(peak as FLOAT)
+        },
+    );
+
+    let params = sources.params.clone();
+    engine.register_fn(
+        "param",
+        move |key: &str| -> Result<FLOAT, Box<EvalAltResult>> {
+            params
+                .get(key)
+                .map(|p| p.get() as FLOAT)
+                .ok_or_else(|| format!("No parameter '{}', see 'params'", key).into())
+        },
+    );
+
+    let remote = sources.remote.clone();
+    let started = started.clone();
+    engine.register_fn(
+        "command",
+        move |line: &str| -> Result<String, Box<EvalAltResult>> {
+            send(&remote, line, started.get()).map_err(|err| format!("{:#}", err).into())
+        },
+    );
+}
+
+/// Loudest RMS across the channel's meters, minus infinity in silence.
+fn rms_db(levels: &MeterLevels) -> f32 {
+    levels
+        .levels()
+        .map(|l| l.rms_db)
+        .fold(f32::NEG_INFINITY, f32::max)
+}
+
+fn channel_index(channel: INT, count: usize) -> Result<usize, Box<EvalAltResult>> {
+    if channel < 1 || channel as usize > count {
+        return Err(format!("Channel {} out of range 1-{}", channel, count).into());
+    }
+    Ok(channel as usize - 1)
+}
+
+/// Has the control loop apply the console command `line` and waits for
+/// the result. The top level runs before the control loop does, so its
+/// commands are queued without waiting.
+fn send(remote: &Sender<Request>, line: &str, wait: bool) -> Result<String> {
+    let command = Command::parse(line)?;
+    if command == Command::Quit {
+        bail!("Scripts can't quit");
+    }
+    if !wait {
+        remote
+            .send(Request {
+                command,
+                reply: None,
+            })
+            .map_err(|_| anyhow!("The control loop has stopped"))?;
+        return Ok(String::new());
+    }
+    let (reply, result) = mpsc::channel();
+    remote
+        .send(Request {
+            command,
+            reply: Some(reply),
+        })
+        .map_err(|_| anyhow!("The control loop has stopped"))?;
+    result
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| anyhow!("The control loop didn't answer"))?
+}
+
+fn script_error(err: Box<EvalAltResult>) -> anyhow::Error {
+    anyhow!("{}", err)
+}
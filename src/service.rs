@@ -0,0 +1,204 @@
+use crate::load::CallbackLoad;
+use crate::shutdown;
+use anyhow::Result;
+use std::fmt;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Time between attempts to open devices that aren't there yet.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Why the program gave up, passed on to the service manager as the exit
+/// code so it can tell a setup that restarting won't fix from devices that
+/// may still turn up. Anything else exits with 1. A systemd unit for a
+/// daemon:
+///
+/// ```text
+/// [Service]
+/// Type=notify
+/// ExecStart=/usr/local/bin/live_dsp daemon --profiles /etc/live_dsp.txt --profile stage
+/// WatchdogSec=10
+/// Restart=on-failure
+/// RestartSec=5
+/// RestartPreventExitStatus=2 3
+/// ```
+///
+/// On Windows the daemon takes commands on a named pipe and WinSW stops
+/// it with Ctrl-C. WinSW has no readiness or watchdog, `<onfailure
+/// action="restart" delay="5 sec"/>` in the service XML restarts it on
+/// any failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Invalid command line, exits with 2.
+    Usage,
+    /// A profile, chain or speaker layout that can't be used, 3.
+    Config,
+    /// The devices or the audio server never showed up or failed to open, 4.
+    Devices,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Usage => 2,
+            Failure::Config => 3,
+            Failure::Devices => 4,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Failure::Usage => "Invalid command line",
+            Failure::Config => "Invalid configuration",
+            Failure::Devices => "Audio devices unavailable",
+        })
+    }
+}
+
+/// Exit code for the error that ended the program, from the `Failure` it
+/// was tagged with.
+pub fn exit_code(err: &anyhow::Error) -> ExitCode {
+    ExitCode::from(
+        err.downcast_ref::<Failure>()
+            .map_or(1, |failure| failure.code()),
+    )
+}
+
+/// Tells systemd about the service, `READY=1` once the streams run,
+/// `STOPPING=1` or a watchdog ping, on the socket in `$NOTIFY_SOCKET`.
+/// Does nothing when not started by a `Type=notify` unit.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    let _ = match path.as_encoded_bytes().strip_prefix(b"@") {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), &path).map(|_| ()),
+    };
+}
+
+/// A socket name starting with `@` is in Linux's abstract namespace.
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    socket: &std::os::unix::net::UnixDatagram,
+    name: &[u8],
+    state: &str,
+) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name)?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _socket: &std::os::unix::net::UnixDatagram,
+    _name: &[u8],
+    _state: &str,
+) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// No systemd elsewhere.
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
+
+/// Pings systemd's watchdog at half its `WatchdogSec=`, from the loop
+/// that looks after the streams, so a daemon that hangs gets restarted.
+/// Only pings while the output callback keeps running, a stalled stream
+/// gets the daemon restarted too.
+pub struct Watchdog {
+    interval: Option<Duration>,
+    last: Instant,
+    load: Arc<CallbackLoad>,
+    /// Output callbacks at the last ping.
+    callbacks: u64,
+}
+
+impl Watchdog {
+    pub fn from_env(load: Arc<CallbackLoad>) -> Self {
+        // A watchdog set up for another process, e.g. the shell of a
+        // wrapper script, isn't ours to ping
+        let ours = std::env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            .is_none_or(|pid| pid == std::process::id());
+        let interval = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0 && ours)
+            .map(|usec| Duration::from_micros(usec / 2));
+        let callbacks = load.snapshot().callbacks;
+        Watchdog {
+            interval,
+            last: Instant::now(),
+            load,
+            callbacks,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let callbacks = self.load.snapshot().callbacks;
+        if self.last.elapsed() >= interval && callbacks > self.callbacks {
+            notify("WATCHDOG=1");
+            self.last = Instant::now();
+            self.callbacks = callbacks;
+        }
+    }
+}
+
+/// Retries `open` until it succeeds, for a daemon started at boot before
+/// its interface is enumerated or the audio server is up. The last error
+/// is returned as `Failure::Devices` once `timeout` has passed, `None`
+/// waits for good. Returns `None` when asked to shut down meanwhile.
+pub fn wait_for<T>(
+    what: &str,
+    timeout: Option<Duration>,
+    mut open: impl FnMut() -> Result<T>,
+) -> Result<Option<T>> {
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        match open() {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) if timeout.is_some_and(|timeout| start.elapsed() >= timeout) => {
+                return Err(err.context(Failure::Devices));
+            }
+            Err(err) => {
+                if !waiting {
+                    info!("Waiting for {}: {}", what, err);
+                    waiting = true;
+                }
+                // Keeps systemd from timing out the start meanwhile
+                notify(&format!(
+                    "STATUS=Waiting for {}\nEXTEND_TIMEOUT_USEC={}",
+                    what,
+                    (RETRY_INTERVAL * 2).as_micros()
+                ));
+            }
+        }
+        let retry_at = Instant::now() + RETRY_INTERVAL;
+        while Instant::now() < retry_at {
+            if shutdown::requested() {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
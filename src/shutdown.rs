@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Turns Ctrl-C, SIGTERM and SIGHUP into a shutdown request, so the
+/// streams stop in order and recordings get finished instead of the
+/// process dying mid-write. A second signal exits at once.
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_signal: libc::c_int) {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            // Only async-signal-safe calls in here
+            unsafe { libc::_exit(130) };
+        }
+    }
+
+    let handler: extern "C" fn(libc::c_int) = handle;
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        // SAFETY: the handler only touches an atomic and calls `_exit`
+        unsafe { libc::signal(signal, handler as libc::sighandler_t) };
+    }
+}
+
+/// Turns Ctrl-C and Ctrl-Break into a shutdown request. A second one is
+/// left to the default handler, which exits at once.
+#[cfg(windows)]
+pub fn install() {
+    const CTRL_C_EVENT: u32 = 0;
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    // Called on a thread of its own, returning TRUE marks the event handled
+    unsafe extern "system" fn handle(event: u32) -> i32 {
+        match event {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => !REQUESTED.swap(true, Ordering::SeqCst) as i32,
+            _ => 0,
+        }
+    }
+
+    // SAFETY: the handler only touches an atomic and lives as long as the
+    // process
+    if unsafe { SetConsoleCtrlHandler(Some(handle), 1) } == 0 {
+        tracing::warn!(
+            "Failed to install the Ctrl-C handler: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+unsafe extern "system" {
+    fn SetConsoleCtrlHandler(
+        handler: Option<unsafe extern "system" fn(u32) -> i32>,
+        add: i32,
+    ) -> i32;
+}
+
+/// Other platforms keep their default Ctrl-C handling.
+#[cfg(not(any(unix, windows)))]
+pub fn install() {}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Asks for a shutdown as a signal would, for `quit` sent to a daemon.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,436 @@
+use crate::dither::{Dither, DitherMode};
+use crate::json::Json;
+use crate::output_tap::{self, OutputTap, TapReader};
+use anyhow::{Context, Result};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Audio the ring buffer holds while the streamer catches up, in seconds.
+const RING_SECONDS: usize = 1;
+/// Audio per wire chunk.
+const CHUNK_MS: u32 = 20;
+/// How often the streamer looks for new audio.
+const POLL: Duration = Duration::from_millis(5);
+/// A client that can't take a chunk in this long is dropped, so it can't
+/// hold up the others.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+/// Chunks over which the stream's start time estimate is smoothed, about
+/// ten seconds, so the timestamps follow the sound card's clock without
+/// the jitter of its buffers.
+const ANCHOR_SMOOTHING: f64 = 500.0;
+/// An estimate this far from the smoothed one means the output stalled or
+/// was rebuilt, the timestamps jump to it.
+const ANCHOR_STEP: Duration = Duration::from_millis(100);
+
+/// Message types of the Snapcast protocol.
+const CODEC_HEADER: u16 = 1;
+const WIRE_CHUNK: u16 = 2;
+const SERVER_SETTINGS: u16 = 3;
+const TIME: u16 = 4;
+const HELLO: u16 = 5;
+/// Base header: type, id, refers to, sent and received times, size.
+const HEADER_LEN: usize = 26;
+/// Largest message a client sends, its Hello JSON.
+const MAX_MESSAGE: usize = 100_000;
+
+/// `PORT` listens on every interface, clients are other machines on the
+/// venue network. `ADDR:PORT` on that address only.
+pub fn parse_addr(text: &str) -> Result<SocketAddr> {
+    if let Ok(port) = text.parse::<u16>() {
+        return Ok(SocketAddr::from(([0, 0, 0, 0], port)));
+    }
+    text.parse()
+        .with_context(|| format!("Invalid address '{}', expected PORT or ADDR:PORT", text))
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapcastSettings {
+    pub addr: SocketAddr,
+    /// How far behind the live mix every client plays, which is what lets
+    /// them all play the same sample at the same time.
+    pub buffer_ms: u32,
+    /// Dither before the 16-bit PCM sent to clients.
+    pub dither: DitherMode,
+}
+
+/// A Snapcast time value: seconds and microseconds.
+#[derive(Clone, Copy, Default)]
+struct Tv {
+    sec: i32,
+    usec: i32,
+}
+
+impl Tv {
+    fn from_duration(time: Duration) -> Tv {
+        Tv {
+            sec: time.as_secs() as i32,
+            usec: time.subsec_micros() as i32,
+        }
+    }
+
+    fn micros(self) -> i64 {
+        self.sec as i64 * 1_000_000 + self.usec as i64
+    }
+
+    fn from_micros(micros: i64) -> Tv {
+        Tv {
+            sec: micros.div_euclid(1_000_000) as i32,
+            usec: micros.rem_euclid(1_000_000) as i32,
+        }
+    }
+
+    fn write(self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.sec.to_le_bytes());
+        out.extend_from_slice(&self.usec.to_le_bytes());
+    }
+
+    fn read(data: &[u8]) -> Tv {
+        Tv {
+            sec: i32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            usec: i32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+        }
+    }
+}
+
+/// A connected client. The reader thread answers its time requests, the
+/// streamer sends it chunks once it said hello.
+struct Client {
+    address: SocketAddr,
+    stream: Mutex<TcpStream>,
+    ready: AtomicBool,
+    gone: AtomicBool,
+}
+
+struct Shared {
+    /// Server time is counted from here, clients only ever see it through
+    /// the offset they measure against their own clocks.
+    epoch: Instant,
+    running: AtomicBool,
+    clients: Mutex<Vec<Arc<Client>>>,
+    buffer_ms: u32,
+    /// RIFF header telling clients the PCM format.
+    codec_header: Vec<u8>,
+}
+
+impl Shared {
+    fn now(&self) -> Tv {
+        Tv::from_duration(self.epoch.elapsed())
+    }
+
+    /// Frames a message: base header stamped with the send time, then the
+    /// payload.
+    fn message(&self, kind: u16, refers_to: u16, received: Tv, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HEADER_LEN + payload.len());
+        message.extend_from_slice(&kind.to_le_bytes());
+        message.extend_from_slice(&0u16.to_le_bytes());
+        message.extend_from_slice(&refers_to.to_le_bytes());
+        self.now().write(&mut message);
+        received.write(&mut message);
+        message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        message.extend_from_slice(payload);
+        message
+    }
+}
+
+/// Strings and JSON go on the wire after their length.
+fn sized(text: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 + text.len());
+    payload.extend_from_slice(&(text.len() as u32).to_le_bytes());
+    payload.extend_from_slice(text);
+    payload
+}
+
+/// The codec header of 16-bit PCM: the name, then a WAV header.
+fn codec_header(sample_rate: u32, channels: usize) -> Vec<u8> {
+    let block_align = channels as u16 * 2;
+    let mut wav = Vec::with_capacity(44);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&36u32.to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&(channels as u16).to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&0u32.to_le_bytes());
+
+    let mut header = sized(b"pcm");
+    header.extend_from_slice(&sized(&wav));
+    header
+}
+
+/// Serves the output to Snapcast clients for multi-room playback: every
+/// `snapclient` in the venue connects here, as it would to `snapserver`,
+/// and plays the stream in sync with the others. Chunks carry the time
+/// their first sample was rendered, clients measure their clock against
+/// this server's and play each chunk the buffer time after it. The audio
+/// goes out as 16-bit PCM; accepting, answering clients and streaming
+/// each run on their own thread, which end once the tap is dropped.
+pub struct SnapcastServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    dropped: Arc<AtomicU64>,
+    acceptor: JoinHandle<u64>,
+    streamer: JoinHandle<()>,
+}
+
+impl SnapcastServer {
+    /// Binds the port and starts serving. Returns the tap to feed from the
+    /// audio callback.
+    pub fn start(
+        settings: &SnapcastSettings,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Result<(SnapcastServer, OutputTap)> {
+        let listener = TcpListener::bind(settings.addr)
+            .with_context(|| format!("Failed to listen for Snapcast on {}", settings.addr))?;
+        // Polled so the thread can notice it should stop
+        listener.set_nonblocking(true)?;
+
+        let shared = Arc::new(Shared {
+            epoch: Instant::now(),
+            running: AtomicBool::new(true),
+            clients: Mutex::new(Vec::new()),
+            buffer_ms: settings.buffer_ms,
+            codec_header: codec_header(sample_rate, channels),
+        });
+        let (tap, reader) = output_tap::output_tap(sample_rate, channels, RING_SECONDS);
+        let dropped = reader.dropped();
+
+        let accepting = shared.clone();
+        let acceptor = thread::Builder::new()
+            .name("snapcast".to_string())
+            .spawn(move || accept(&accepting, listener))?;
+        let streaming = shared.clone();
+        let dither = Dither::new(settings.dither, 16, channels);
+        let streamer = thread::Builder::new()
+            .name("snapcast stream".to_string())
+            .spawn(move || stream(&streaming, sample_rate, channels, dither, reader))?;
+
+        let server = SnapcastServer {
+            addr: settings.addr,
+            shared,
+            dropped,
+            acceptor,
+            streamer,
+        };
+        Ok((server, tap))
+    }
+
+    /// Waits for the streamer to send what's left, then disconnects the
+    /// clients. Call after the stream feeding the tap has been dropped.
+    pub fn finish(self) -> Result<()> {
+        self.streamer
+            .join()
+            .map_err(|_| anyhow::anyhow!("Snapcast streamer panicked"))?;
+        self.shared.running.store(false, Ordering::Relaxed);
+        let served = self
+            .acceptor
+            .join()
+            .map_err(|_| anyhow::anyhow!("Snapcast server panicked"))?;
+        info!("Snapcast: served {} clients on {}", served, self.addr);
+
+        let dropped = self.dropped.load(Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(
+                "Snapcast dropped {} samples, the clients could not keep up",
+                dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Accepts clients until the server stops, returns how many connected.
+fn accept(shared: &Arc<Shared>, listener: TcpListener) -> u64 {
+    let mut readers: Vec<JoinHandle<()>> = Vec::new();
+    let mut served = 0;
+    while shared.running.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, address)) => {
+                let Ok(reader) = stream.try_clone() else {
+                    continue;
+                };
+                let _ = stream.set_nodelay(true);
+                let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                let client = Arc::new(Client {
+                    address,
+                    stream: Mutex::new(stream),
+                    ready: AtomicBool::new(false),
+                    gone: AtomicBool::new(false),
+                });
+                shared.clients.lock().unwrap().push(client.clone());
+                let shared = shared.clone();
+                let thread = thread::Builder::new()
+                    .name("snapcast client".to_string())
+                    .spawn(move || serve(&shared, &client, reader));
+                readers.extend(thread);
+                readers.retain(|reader| !reader.is_finished());
+                served += 1;
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(_) => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+    // Unblocks the readers
+    for client in shared.clients.lock().unwrap().iter() {
+        let _ = client.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+    for reader in readers {
+        let _ = reader.join();
+    }
+    served
+}
+
+/// Reads a client's messages until it disconnects: settings and the codec
+/// header in answer to its hello, the server time to its time requests.
+fn serve(shared: &Shared, client: &Client, mut reader: TcpStream) {
+    let mut header = [0u8; HEADER_LEN];
+    let mut payload = Vec::new();
+    loop {
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        let received = shared.now();
+        let kind = u16::from_le_bytes([header[0], header[1]]);
+        let id = u16::from_le_bytes([header[2], header[3]]);
+        let sent = Tv::read(&header[6..14]);
+        let size = u32::from_le_bytes([header[22], header[23], header[24], header[25]]) as usize;
+        if size > MAX_MESSAGE {
+            break;
+        }
+        payload.resize(size, 0);
+        if reader.read_exact(&mut payload).is_err() {
+            break;
+        }
+
+        let reply = match kind {
+            HELLO => {
+                let name = payload
+                    .get(4..)
+                    .and_then(|json| Json::parse(&String::from_utf8_lossy(json)).ok())
+                    .and_then(|hello| hello.get("HostName")?.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                info!("Snapcast: client {} {} connected", name, client.address);
+                let settings = Json::object([
+                    ("bufferMs", (shared.buffer_ms as u64).into()),
+                    ("latency", 0u64.into()),
+                    ("muted", false.into()),
+                    ("volume", 100u64.into()),
+                ]);
+                let mut reply = shared.message(
+                    SERVER_SETTINGS,
+                    id,
+                    received,
+                    &sized(settings.to_string().as_bytes()),
+                );
+                reply.extend(shared.message(CODEC_HEADER, id, received, &shared.codec_header));
+                reply
+            }
+            // The client works out the clock offset from this and the
+            // times in the header
+            TIME => {
+                let mut latency = Vec::with_capacity(8);
+                Tv::from_micros(received.micros() - sent.micros()).write(&mut latency);
+                shared.message(TIME, id, received, &latency)
+            }
+            _ => continue,
+        };
+        if client.stream.lock().unwrap().write_all(&reply).is_err() {
+            break;
+        }
+        if kind == HELLO {
+            client.ready.store(true, Ordering::Relaxed);
+        }
+    }
+    client.gone.store(true, Ordering::Relaxed);
+    if shared.running.load(Ordering::Relaxed) {
+        info!("Snapcast: client {} disconnected", client.address);
+    }
+}
+
+/// Where the stream's first sample sits on the server clock, following the
+/// sound card's clock as the audio arrives.
+struct Anchor {
+    start_us: Option<f64>,
+}
+
+impl Anchor {
+    /// Takes the arrival of audio ending `frames` into the stream and
+    /// returns the smoothed start time in microseconds.
+    fn update(&mut self, now: Tv, frames: u64, sample_rate: u32) -> f64 {
+        let estimate = now.micros() as f64 - frames as f64 * 1e6 / sample_rate as f64;
+        let start = match self.start_us {
+            Some(start) if (estimate - start).abs() < ANCHOR_STEP.as_micros() as f64 => {
+                start + (estimate - start) / ANCHOR_SMOOTHING
+            }
+            _ => estimate,
+        };
+        self.start_us = Some(start);
+        start
+    }
+}
+
+/// Cuts the ring into timestamped chunks and sends them to every ready
+/// client, until the tap is dropped.
+fn stream(
+    shared: &Shared,
+    sample_rate: u32,
+    channels: usize,
+    mut dither: Dither,
+    mut reader: TapReader,
+) {
+    let chunk = (sample_rate * CHUNK_MS / 1000) as usize * channels;
+    let mut block = vec![0.0f32; chunk];
+    let mut filled = 0;
+    let mut frames: u64 = 0;
+    let mut anchor = Anchor { start_us: None };
+    let mut payload = Vec::with_capacity(12 + chunk * 2);
+    while let Some(count) = reader.read(&mut block[filled..], POLL) {
+        filled += count;
+        if filled < chunk {
+            continue;
+        }
+        filled = 0;
+
+        let start = anchor.update(
+            shared.now(),
+            frames + (chunk / channels) as u64,
+            sample_rate,
+        );
+        let timestamp = start + frames as f64 * 1e6 / sample_rate as f64;
+        frames += (chunk / channels) as u64;
+
+        dither.process(&mut block, channels);
+        payload.clear();
+        Tv::from_micros(timestamp as i64).write(&mut payload);
+        payload.extend_from_slice(&((chunk * 2) as u32).to_le_bytes());
+        for &sample in &block {
+            let value = (sample * 32768.0).clamp(-32768.0, 32767.0) as i16;
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+        let message = shared.message(WIRE_CHUNK, 0, Tv::default(), &payload);
+
+        let mut clients = shared.clients.lock().unwrap();
+        for client in clients.iter() {
+            if client.ready.load(Ordering::Relaxed)
+                && client.stream.lock().unwrap().write_all(&message).is_err()
+            {
+                // Too slow or gone, its reader sees the connection close
+                let _ = client.stream.lock().unwrap().shutdown(Shutdown::Both);
+                client.gone.store(true, Ordering::Relaxed);
+            }
+        }
+        clients.retain(|client| !client.gone.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,425 @@
+use crate::clip::ClipStats;
+use crate::dsp::{DspChain, Processor};
+use crate::fade::{Fade, FadeControl};
+use crate::frame_ring::{self, FrameProducer};
+use crate::headroom::StageLevels;
+use crate::jitter::{JitterBuffer, JitterStats};
+use crate::output_tap::OutputTap;
+use crate::realtime::Realtime;
+use crate::resample::{Quality, Resampler};
+use crate::routing::ChannelMap;
+use crate::rtlog::{Event, Logger, RtLogger};
+use crate::sample_convert;
+use crate::xrun::XrunStats;
+use anyhow::Result;
+use cpal::traits::DeviceTrait;
+use cpal::{Device, Stream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+/// Extra ring space in frames for devices that call back with more than
+/// the buffer size, as when they fall back from a refused fixed size.
+const CALLBACK_HEADROOM: usize = 8192;
+
+/// Anything the mixer can pull output-rate frames from: a live input or a
+/// file player.
+pub trait Source: Send {
+    fn name(&self) -> &str;
+
+    /// Fills `data` with one buffer worth of this source.
+    fn read_into(&mut self, data: &mut [f32], channels: usize);
+}
+
+/// Output side of one input device. The input callback resamples and routes
+/// its frames into a frame ring, the output callback reads them back out
+/// through a jitter buffer for the mixer.
+pub struct SourceTap {
+    pub name: String,
+    buffer: JitterBuffer,
+    jitter: Arc<JitterStats>,
+    xruns: Arc<XrunStats>,
+    clips: Arc<ClipStats>,
+    levels: Arc<StageLevels>,
+    /// Frames missing in a row so far.
+    gap: u64,
+    /// Ramps the input back in after a gap or a rebuilt stream.
+    fade: Fade,
+    fade_control: Arc<FadeControl>,
+}
+
+impl SourceTap {
+    pub fn xruns(&self) -> Arc<XrunStats> {
+        self.xruns.clone()
+    }
+
+    pub fn jitter(&self) -> Arc<JitterStats> {
+        self.jitter.clone()
+    }
+
+    /// Clipping in the input as converted, before the resampler.
+    pub fn clips(&self) -> Arc<ClipStats> {
+        self.clips.clone()
+    }
+
+    /// Peaks of the input as converted, for the headroom analysis.
+    pub fn levels(&self) -> Arc<StageLevels> {
+        self.levels.clone()
+    }
+
+    pub fn fade(&self) -> Arc<FadeControl> {
+        self.fade_control.clone()
+    }
+}
+
+impl Source for SourceTap {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        // Missing samples play as silence and are only counted here, the
+        // xrun reporter prints them off the audio thread
+        let mut missing = 0;
+        self.buffer.begin(data.len() / channels);
+        self.fade.begin();
+        for frame in data.chunks_mut(channels) {
+            // Audio after a gap fades in rather than starting mid-waveform
+            if !self.buffer.pop_frame(frame) {
+                missing += channels as u64;
+                self.gap += 1;
+                self.fade.silence();
+                continue;
+            }
+            if self.gap > 0 {
+                self.xruns.record_gap(self.gap);
+                self.gap = 0;
+            }
+            if self.buffer.prefilling() {
+                self.fade.silence();
+            } else {
+                self.fade.apply(frame);
+            }
+        }
+        if self.gap > 0 {
+            self.xruns.record_gap(self.gap);
+        }
+        self.xruns.record_underrun(missing);
+    }
+}
+
+pub struct SourceSettings<'a> {
+    pub output_rate: u32,
+    pub output_channels: usize,
+    pub buffer_size: u32,
+    /// Frames the jitter buffer aims to keep queued.
+    pub target_latency: u32,
+    /// Follow the input's clock drift by resampling instead of dropping
+    /// and repeating frames.
+    pub drift_compensation: bool,
+    pub map: Option<&'a str>,
+    pub quality: Quality,
+    /// Length of the fades when the input is switched or comes back.
+    pub crossfade: Duration,
+}
+
+/// Input side of one input device: the resampler, channel map and ring
+/// producer its callback feeds. It outlives the stream, so a stream rebuilt
+/// after a disconnect picks up where the old one left off.
+pub struct InputFeed {
+    pub name: String,
+    output_rate: u32,
+    output_channels: usize,
+    buffer_size: u32,
+    quality: Quality,
+    map: Option<String>,
+    state: Arc<Mutex<FeedState>>,
+    log: Arc<Mutex<RtLogger>>,
+    realtime: Arc<Realtime>,
+}
+
+/// What the input callback works on, taken with `try_lock`. Only the one
+/// running stream ever takes it.
+struct FeedState {
+    resampler: Resampler,
+    channel_map: ChannelMap,
+    producer: FrameProducer,
+    routed: Vec<f32>,
+    /// Rate and channel count the resampler and map are set up for.
+    input_rate: u32,
+    input_channels: usize,
+    xruns: Arc<XrunStats>,
+    jitter: Arc<JitterStats>,
+    clips: Arc<ClipStats>,
+    levels: Arc<StageLevels>,
+    drift_compensation: bool,
+}
+
+impl FeedState {
+    fn process(&mut self, data: &[f32]) {
+        // If input is empty, nothing to do
+        if data.is_empty() {
+            return;
+        }
+        self.xruns
+            .record_callback((data.len() / self.input_channels) as u64);
+        self.clips.record(data, self.input_channels);
+        self.levels.record_output(data);
+
+        // The output side measures the drift, the resampler follows it
+        if self.drift_compensation {
+            self.resampler.set_drift(self.jitter.correction() as f64);
+        }
+
+        // data is interleaved [L, R, L, R...]
+        // The resampler hands back frames at the output rate, which are
+        // then routed to the output channels
+        let mut dropped = 0;
+        let (channel_map, routed, producer) =
+            (&self.channel_map, &mut self.routed, &mut self.producer);
+        self.resampler.process(data, |frame| {
+            channel_map.apply(frame, routed);
+            if !producer.push_frame(routed) {
+                dropped += routed.len() as u64;
+            }
+        });
+        self.xruns.record_overrun(dropped);
+    }
+}
+
+impl InputFeed {
+    /// Builds a paused stream on `device` with its default config. A device
+    /// with another rate or channel count than the last one gets a new
+    /// resampler and channel map, the ring and jitter buffer stay.
+    pub fn start(&mut self, device: &Device) -> Result<Stream> {
+        let default_config = device.default_input_config()?;
+        let format = default_config.sample_format();
+        let input_rate = default_config.sample_rate();
+        let requested =
+            sample_convert::fixed_buffer_size(default_config.buffer_size(), self.buffer_size);
+        let mut config: cpal::StreamConfig = default_config.into();
+        config.buffer_size = requested;
+        let input_channels = config.channels as usize;
+
+        let mut state = self.state.lock().unwrap();
+        if state.input_rate != input_rate || state.input_channels != input_channels {
+            state.channel_map = match &self.map {
+                Some(spec) => ChannelMap::parse(spec, input_channels, self.output_channels)?,
+                None => ChannelMap::default_for(input_channels, self.output_channels),
+            };
+            state.resampler =
+                Resampler::new(input_rate, self.output_rate, input_channels, self.quality);
+            state
+                .resampler
+                .reserve((self.buffer_size as usize * 4).max(CALLBACK_HEADROOM));
+            state.input_rate = input_rate;
+            state.input_channels = input_channels;
+        }
+        info!(
+            "Input {}: {} Hz, {} channels, {} samples, buffer size {:?}, channel map {}",
+            self.name,
+            config.sample_rate,
+            config.channels,
+            format,
+            config.buffer_size,
+            state.channel_map.describe()
+        );
+        let err_xruns = state.xruns.clone();
+        drop(state);
+
+        let (state, log) = (self.state.clone(), self.log.clone());
+        let mut promoter = self.realtime.promoter(&self.name, input_rate, device);
+        sample_convert::build_input_stream(
+            device,
+            &mut config,
+            format,
+            move |data: &[f32]| {
+                promoter.promote(data.len() / input_channels);
+                if let Ok(mut state) = state.try_lock() {
+                    state.process(data);
+                }
+            },
+            move |err| {
+                err_xruns.record_error(&err);
+                if let Ok(mut log) = log.try_lock() {
+                    log.log(Event::StreamError(err));
+                }
+            },
+        )
+    }
+}
+
+/// Sets up the input side of `device` and the `SourceTap` reading it. Call
+/// `InputFeed::start` for the stream, its errors go to `logger` and its
+/// thread is promoted through `realtime`.
+pub fn open_input(
+    device: &Device,
+    settings: &SourceSettings,
+    logger: &Logger,
+    realtime: &Arc<Realtime>,
+) -> Result<(InputFeed, SourceTap)> {
+    let name = device.description()?.to_string();
+    let default_config = device.default_input_config()?;
+    let input_rate = default_config.sample_rate();
+    let input_channels = default_config.channels() as usize;
+    let (state, tap) = open_feed(name.clone(), input_rate, input_channels, settings)?;
+    let feed = InputFeed {
+        name: name.clone(),
+        output_rate: settings.output_rate,
+        output_channels: settings.output_channels,
+        buffer_size: settings.buffer_size,
+        quality: settings.quality,
+        map: settings.map.map(str::to_string),
+        state: Arc::new(Mutex::new(state)),
+        log: Arc::new(Mutex::new(logger.channel(name))),
+        realtime: realtime.clone(),
+    };
+    Ok((feed, tap))
+}
+
+/// Input that arrives on a thread of its own instead of a device
+/// callback, such as audio received from the network. Pushing never
+/// blocks, frames the ring has no room for are counted as overruns.
+pub struct StreamFeed {
+    state: FeedState,
+}
+
+impl StreamFeed {
+    /// Takes interleaved frames at the input rate.
+    pub fn push(&mut self, data: &[f32]) {
+        self.state.process(data);
+    }
+}
+
+/// Sets up a feed of `input_channels` at `input_rate` pushed from outside
+/// a device, and the `SourceTap` reading it.
+pub fn open_stream(
+    name: String,
+    input_rate: u32,
+    input_channels: usize,
+    settings: &SourceSettings,
+) -> Result<(StreamFeed, SourceTap)> {
+    let (state, tap) = open_feed(name, input_rate, input_channels, settings)?;
+    Ok((StreamFeed { state }, tap))
+}
+
+/// The resampler, channel map and ring from an input to the mixer, and the
+/// tap on the mixer's side of the ring.
+fn open_feed(
+    name: String,
+    input_rate: u32,
+    input_channels: usize,
+    settings: &SourceSettings,
+) -> Result<(FeedState, SourceTap)> {
+    let output_channels = settings.output_channels;
+    let channel_map = match settings.map {
+        Some(spec) => ChannelMap::parse(spec, input_channels, output_channels)?,
+        None => ChannelMap::default_for(input_channels, output_channels),
+    };
+
+    // Create a Ring Buffer with room for twice the target latency plus two buffers to prevent overruns
+    // It moves whole interleaved frames so the channels can't slip apart.
+    // The jitter buffer sets the latency, spare room only costs memory
+    let buffer_size = settings.buffer_size as usize;
+    let capacity = (settings.target_latency as usize + buffer_size) * 2 + CALLBACK_HEADROOM;
+    let (producer, consumer) = frame_ring::frame_ring(capacity, output_channels);
+    let jitter = Arc::new(JitterStats::new(
+        name.clone(),
+        settings.output_rate,
+        settings.target_latency as u64,
+        (capacity - buffer_size) as u64,
+    ));
+
+    let mut resampler = Resampler::new(
+        input_rate,
+        settings.output_rate,
+        input_channels,
+        settings.quality,
+    );
+    resampler.reserve((buffer_size * 4).max(CALLBACK_HEADROOM));
+    let xruns = Arc::new(XrunStats::new(name.clone(), settings.output_rate));
+    let clips = Arc::new(ClipStats::new(format!("{} input", name)));
+    let levels = Arc::new(StageLevels::input(format!("{} input", name)));
+
+    let state = FeedState {
+        resampler,
+        channel_map,
+        producer,
+        routed: vec![0.0f32; output_channels],
+        input_rate,
+        input_channels,
+        xruns: xruns.clone(),
+        jitter: jitter.clone(),
+        clips: clips.clone(),
+        levels: levels.clone(),
+        drift_compensation: settings.drift_compensation,
+    };
+    let (fade, fade_control) = Fade::new(settings.output_rate as f32, settings.crossfade);
+    let tap = SourceTap {
+        name,
+        buffer: JitterBuffer::new(
+            consumer,
+            output_channels,
+            buffer_size,
+            settings.drift_compensation,
+            jitter.clone(),
+        ),
+        jitter,
+        xruns,
+        clips,
+        levels,
+        gap: 0,
+        fade,
+        fade_control,
+    };
+    Ok((state, tap))
+}
+
+/// A source run through its own DSP chain before the mixer, such as the
+/// input conditioning.
+pub struct ProcessedSource {
+    source: Box<dyn Source>,
+    chain: DspChain,
+}
+
+impl ProcessedSource {
+    /// `chain` should already be prepared for the output channels.
+    pub fn new(source: Box<dyn Source>, chain: DspChain) -> Self {
+        ProcessedSource { source, chain }
+    }
+}
+
+impl Source for ProcessedSource {
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        self.source.read_into(data, channels);
+        self.chain.process(data, channels);
+    }
+}
+
+/// A source copied to a recorder as it's read, before anything else
+/// processes it.
+pub struct RecordedSource {
+    source: Box<dyn Source>,
+    tap: OutputTap,
+}
+
+impl RecordedSource {
+    pub fn new(source: Box<dyn Source>, tap: OutputTap) -> Self {
+        RecordedSource { source, tap }
+    }
+}
+
+impl Source for RecordedSource {
+    fn name(&self) -> &str {
+        self.source.name()
+    }
+
+    fn read_into(&mut self, data: &mut [f32], channels: usize) {
+        self.source.read_into(data, channels);
+        self.tap.process(data);
+    }
+}
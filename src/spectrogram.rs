@@ -0,0 +1,209 @@
+use crate::spectrum::FLOOR_DB;
+use anyhow::{Context, Result};
+use std::array;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Rows of the image, log-spaced from `MIN_HZ` to Nyquist.
+const ROWS: usize = 256;
+const MIN_HZ: f32 = 20.0;
+/// Time a column covers when the session starts. Long sessions merge
+/// columns in pairs to stay under `MAX_COLUMNS`.
+const COLUMN_SECONDS: f32 = 0.5;
+const MAX_COLUMNS: usize = 4096;
+/// Levels at or below this are black, 0 dBFS is white.
+const RANGE_DB: f32 = 100.0;
+/// Deflate stored blocks hold at most this many bytes.
+const STORED_BLOCK: usize = 65535;
+
+/// The output spectrum over the whole session, one column per stretch of
+/// analyzer frames holding the loudest level each row reached, written as
+/// a PNG once streaming stops.
+#[derive(Debug)]
+pub struct Spectrogram {
+    path: PathBuf,
+    sample_rate: f32,
+    /// FFT length of the frames added.
+    size: usize,
+    /// Analyzer frames per second, one per half FFT length.
+    frame_rate: f32,
+    columns: Mutex<Columns>,
+}
+
+#[derive(Debug)]
+struct Columns {
+    done: Vec<[f32; ROWS]>,
+    current: [f32; ROWS],
+    frames: usize,
+    frames_per_column: usize,
+}
+
+impl Spectrogram {
+    pub fn new(path: PathBuf, sample_rate: f32, size: usize) -> Self {
+        let frame_rate = sample_rate / (size / 2) as f32;
+        Spectrogram {
+            path,
+            sample_rate,
+            size,
+            frame_rate,
+            columns: Mutex::new(Columns {
+                done: Vec::new(),
+                current: [FLOOR_DB; ROWS],
+                frames: 0,
+                frames_per_column: ((COLUMN_SECONDS * frame_rate).round() as usize).max(1),
+            }),
+        }
+    }
+
+    /// Adds one analyzer frame, `size / 2 + 1` bins in dBFS. Runs on the
+    /// analyzer thread, never the audio one.
+    pub fn add_frame(&self, bins_db: &[f32]) {
+        let mut columns = self.columns.lock().unwrap();
+        for (row, level) in columns.current.iter_mut().enumerate() {
+            let (start, end) = self.row_bins(row, bins_db.len());
+            let loudest = bins_db[start..end].iter().copied().fold(FLOOR_DB, f32::max);
+            *level = level.max(loudest);
+        }
+        columns.frames += 1;
+        if columns.frames < columns.frames_per_column {
+            return;
+        }
+        let column = columns.current;
+        columns.done.push(column);
+        columns.current = [FLOOR_DB; ROWS];
+        columns.frames = 0;
+        if columns.done.len() >= MAX_COLUMNS {
+            columns.done = columns
+                .done
+                .chunks(2)
+                .map(|pair| match pair {
+                    [first, second] => array::from_fn(|row| first[row].max(second[row])),
+                    _ => pair[0],
+                })
+                .collect();
+            columns.frames_per_column *= 2;
+        }
+    }
+
+    /// Bins from `start` to `end` that fall in `row`, at least one.
+    fn row_bins(&self, row: usize, bins: usize) -> (usize, usize) {
+        let nyquist = self.sample_rate / 2.0;
+        let ratio = (nyquist / MIN_HZ).max(1.0);
+        let bin_of = |hz: f32| ((hz * self.size as f32 / self.sample_rate) as usize).min(bins - 1);
+        let low = MIN_HZ * ratio.powf(row as f32 / ROWS as f32);
+        let high = MIN_HZ * ratio.powf((row + 1) as f32 / ROWS as f32);
+        let start = bin_of(low);
+        (start, bin_of(high).max(start + 1).min(bins))
+    }
+
+    /// Writes the image, time left to right and low frequencies at the
+    /// bottom. Returns the time it covers.
+    pub fn write(&self) -> Result<Duration> {
+        let columns = self.columns.lock().unwrap();
+        let width = columns.done.len().max(1);
+        let mut pixels = vec![0u8; width * ROWS * 3];
+        for (x, column) in columns.done.iter().enumerate() {
+            for (row, &level) in column.iter().enumerate() {
+                let y = ROWS - 1 - row;
+                let offset = (y * width + x) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&heat((level + RANGE_DB) / RANGE_DB));
+            }
+        }
+        let frames = columns.done.len() * columns.frames_per_column + columns.frames;
+        let png = encode_png(width as u32, ROWS as u32, &pixels);
+        fs::write(&self.path, png)
+            .with_context(|| format!("Failed to write the spectrogram {}", self.path.display()))?;
+        Ok(Duration::from_secs_f32(frames as f32 / self.frame_rate))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+/// Black through blue, red and yellow to white as `level` goes from 0 to 1.
+fn heat(level: f32) -> [u8; 3] {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 160.0],
+        [200.0, 0.0, 60.0],
+        [255.0, 200.0, 0.0],
+        [255.0, 255.0, 255.0],
+    ];
+    let position = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let index = (position as usize).min(STOPS.len() - 2);
+    let t = position - index as f32;
+    let (from, to) = (STOPS[index], STOPS[index + 1]);
+    [0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * t) as u8)
+}
+
+/// An 8-bit RGB PNG. The image data goes in uncompressed deflate blocks,
+/// larger than it could be but needing no compressor.
+fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    let stride = width as usize * 3;
+    // Every scanline starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for line in rgb.chunks(stride) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(STORED_BLOCK).count().max(1);
+    for (index, block) in raw.chunks(STORED_BLOCK).enumerate() {
+        zlib.push(u8::from(index + 1 == blocks));
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 with the reflected 0xEDB88320 polynomial, as PNG chunks use.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
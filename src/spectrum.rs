@@ -0,0 +1,256 @@
+use crate::dsp::fft::{Complex, Fft};
+use crate::dsp::gain_to_db;
+use crate::params::AtomicF32;
+use crate::spectrogram::Spectrogram;
+use anyhow::{Result, bail};
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Lowest value a bin can report.
+pub const FLOOR_DB: f32 = -120.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    pub fn parse(text: &str) -> Result<Self> {
+        let window = match text.trim() {
+            "rect" | "rectangular" => Window::Rectangular,
+            "hann" => Window::Hann,
+            "hamming" => Window::Hamming,
+            "blackman" => Window::Blackman,
+            other => bail!(
+                "Unknown window '{}', expected rect, hann, hamming or blackman",
+                other
+            ),
+        };
+        Ok(window)
+    }
+
+    fn coefficients(self, size: usize) -> Vec<f32> {
+        (0..size)
+            .map(|n| {
+                let x = 2.0 * PI * n as f32 / size as f32;
+                match self {
+                    Window::Rectangular => 1.0,
+                    Window::Hann => 0.5 - 0.5 * x.cos(),
+                    Window::Hamming => 0.54 - 0.46 * x.cos(),
+                    Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpectrumSettings {
+    /// FFT length in samples, a power of two.
+    pub size: usize,
+    pub window: Window,
+    /// Exponential averaging between frames, 0 is none, close to 1 is slow.
+    pub averaging: f32,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> Self {
+        SpectrumSettings {
+            size: 4096,
+            window: Window::Hann,
+            averaging: 0.7,
+        }
+    }
+}
+
+/// Audio-thread side: sums each frame to mono and hands it to the analyzer
+/// thread. Samples are dropped when the analyzer falls behind.
+pub struct SpectrumTap {
+    producer: HeapProd<f32>,
+}
+
+impl SpectrumTap {
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        for frame in buffer.chunks(channels) {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            if self.producer.try_push(mono).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Latest averaged magnitude spectrum, readable from any thread.
+#[derive(Debug)]
+pub struct SpectrumView {
+    pub sample_rate: f32,
+    pub size: usize,
+    /// `size / 2 + 1` bins in dBFS.
+    bins: Vec<AtomicF32>,
+}
+
+impl SpectrumView {
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.sample_rate / self.size as f32
+    }
+
+    pub fn magnitudes_db(&self) -> Vec<f32> {
+        self.bins.iter().map(|b| b.get()).collect()
+    }
+
+    /// Collapses the spectrum into `bands` log-spaced bands between
+    /// `min_hz` and Nyquist, taking the loudest bin of each band.
+    pub fn bands(&self, bands: usize, min_hz: f32) -> Vec<f32> {
+        let nyquist = self.sample_rate / 2.0;
+        let ratio = (nyquist / min_hz).max(1.0);
+        let bin_of = |hz: f32| {
+            ((hz * self.size as f32 / self.sample_rate) as usize).min(self.bins.len() - 1)
+        };
+
+        (0..bands)
+            .map(|band| {
+                let low = min_hz * ratio.powf(band as f32 / bands as f32);
+                let high = min_hz * ratio.powf((band + 1) as f32 / bands as f32);
+                let (start, end) = (bin_of(low), bin_of(high).max(bin_of(low) + 1));
+                self.bins[start..end.min(self.bins.len())]
+                    .iter()
+                    .map(|b| b.get())
+                    .fold(FLOOR_DB, f32::max)
+            })
+            .collect()
+    }
+
+    /// The `count` strongest local maxima as `(frequency, dB)`, loudest
+    /// first. Handy for spotting feedback.
+    pub fn peaks(&self, count: usize) -> Vec<(f32, f32)> {
+        let magnitudes = self.magnitudes_db();
+        let mut peaks: Vec<(f32, f32)> = (1..magnitudes.len().saturating_sub(1))
+            .filter(|&i| magnitudes[i] > magnitudes[i - 1] && magnitudes[i] >= magnitudes[i + 1])
+            .map(|i| (self.bin_frequency(i), magnitudes[i]))
+            .collect();
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peaks.truncate(count);
+        peaks
+    }
+}
+
+/// Starts the analyzer thread and returns the tap for the audio callback
+/// plus the shared view of the result. A `spectrogram` gets every frame
+/// before averaging.
+pub fn spawn(
+    sample_rate: f32,
+    settings: SpectrumSettings,
+    spectrogram: Option<Arc<Spectrogram>>,
+) -> Result<(SpectrumTap, Arc<SpectrumView>)> {
+    if !settings.size.is_power_of_two() || settings.size < 64 {
+        bail!(
+            "FFT size must be a power of two of at least 64, got {}",
+            settings.size
+        );
+    }
+
+    let (producer, consumer) = HeapRb::<f32>::new(settings.size * 4).split();
+    let view = Arc::new(SpectrumView {
+        sample_rate,
+        size: settings.size,
+        bins: (0..settings.size / 2 + 1)
+            .map(|_| AtomicF32::new(FLOOR_DB))
+            .collect(),
+    });
+
+    let analyzer = Analyzer::new(settings, view.clone(), spectrogram);
+    thread::Builder::new()
+        .name("spectrum".to_string())
+        .spawn(move || analyzer.run(consumer))?;
+
+    Ok((SpectrumTap { producer }, view))
+}
+
+struct Analyzer {
+    settings: SpectrumSettings,
+    view: Arc<SpectrumView>,
+    fft: Fft,
+    window: Vec<f32>,
+    /// Normalises a full-scale sine to 0 dBFS for the chosen window.
+    scale: f32,
+    /// The newest `size` samples.
+    frame: Vec<f32>,
+    spectrum: Vec<Complex>,
+    average: Vec<f32>,
+    spectrogram: Option<Arc<Spectrogram>>,
+    /// The last frame in dBFS, unaveraged, for the spectrogram.
+    frame_db: Vec<f32>,
+}
+
+impl Analyzer {
+    fn new(
+        settings: SpectrumSettings,
+        view: Arc<SpectrumView>,
+        spectrogram: Option<Arc<Spectrogram>>,
+    ) -> Self {
+        let window = settings.window.coefficients(settings.size);
+        let scale = 2.0 / window.iter().sum::<f32>();
+        Analyzer {
+            fft: Fft::new(settings.size),
+            window,
+            scale,
+            frame: vec![0.0; settings.size],
+            spectrum: vec![Complex::ZERO; settings.size],
+            average: vec![0.0; settings.size / 2 + 1],
+            spectrogram,
+            frame_db: vec![FLOOR_DB; settings.size / 2 + 1],
+            settings,
+            view,
+        }
+    }
+
+    /// Runs one FFT per half frame of new input until the tap is dropped.
+    fn run(mut self, mut consumer: HeapCons<f32>) {
+        let hop = self.settings.size / 2;
+        loop {
+            if consumer.occupied_len() < hop {
+                if !consumer.write_is_held() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            self.frame.copy_within(hop.., 0);
+            let size = self.frame.len();
+            consumer.pop_slice(&mut self.frame[size - hop..]);
+            self.analyze();
+        }
+    }
+
+    fn analyze(&mut self) {
+        for ((bin, &sample), &w) in self.spectrum.iter_mut().zip(&self.frame).zip(&self.window) {
+            *bin = Complex::new(sample * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        let smoothing = self.settings.averaging.clamp(0.0, 0.99);
+        for (((average, bin), published), frame_db) in self
+            .average
+            .iter_mut()
+            .zip(&self.spectrum)
+            .zip(&self.view.bins)
+            .zip(self.frame_db.iter_mut())
+        {
+            let magnitude = bin.norm() * self.scale;
+            *average = smoothing * *average + (1.0 - smoothing) * magnitude;
+            published.set(gain_to_db(*average).max(FLOOR_DB));
+            *frame_db = gain_to_db(magnitude).max(FLOOR_DB);
+        }
+        if let Some(spectrogram) = &self.spectrogram {
+            spectrogram.add_frame(&self.frame_db);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::fade::FadeControl;
+use crate::xrun::XrunStats;
+use anyhow::{Result, anyhow};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Device, DeviceId, Host, Stream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Time a stream may go without a callback before it counts as stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time between attempts to bring a lost stream back.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Input => "input",
+            Direction::Output => "output",
+        }
+    }
+}
+
+/// Builds a paused stream on a device. The state the callbacks work on has
+/// to live outside the stream, so a rebuilt stream carries on with it.
+pub type Builder = Box<dyn FnMut(&Device) -> Result<Stream>>;
+
+struct Watched {
+    name: String,
+    direction: Direction,
+    /// Device the stream was opened on, found again by id once it's back.
+    id: Option<DeviceId>,
+    stream: Option<Stream>,
+    xruns: Arc<XrunStats>,
+    /// Fade of the path the stream feeds, closed while it's rebuilt.
+    fade: Arc<FadeControl>,
+    build: Builder,
+    /// Callback count at the last poll and when it last moved.
+    callbacks: u64,
+    moved: Instant,
+    retry_at: Instant,
+    /// Failed attempts to reconnect since the stream was lost.
+    attempts: u32,
+}
+
+/// Keeps the streams running across disconnects.
+///
+/// A stream counts as lost when its backend reports the device gone, or
+/// when its callbacks stop for `STALL_TIMEOUT`, which is all some hosts do
+/// on unplug. A lost stream is dropped and rebuilt once its device shows up
+/// again. The mixer, effects and rings are left alone, the other streams
+/// carry on meanwhile with silence where the lost one was.
+///
+/// Streams can't leave the thread they were built on, so this is polled
+/// from the main thread's control loop.
+pub struct Supervisor {
+    host: Host,
+    /// Take the default device when the lost one isn't there.
+    fallback: bool,
+    streams: Vec<Watched>,
+}
+
+impl Supervisor {
+    pub fn new(host: Host, fallback: bool) -> Self {
+        Supervisor {
+            host,
+            fallback,
+            streams: Vec::new(),
+        }
+    }
+
+    /// Watches `stream`, running on `device`. `fade` silences what the
+    /// stream feeds while it's rebuilt. Streams are started in the order
+    /// they were added and stopped in reverse.
+    pub fn watch(
+        &mut self,
+        direction: Direction,
+        device: &Device,
+        stream: Stream,
+        xruns: Arc<XrunStats>,
+        fade: Arc<FadeControl>,
+        build: Builder,
+    ) {
+        let now = Instant::now();
+        self.streams.push(Watched {
+            name: xruns.name.clone(),
+            direction,
+            id: device.id().ok(),
+            stream: Some(stream),
+            callbacks: xruns.callbacks(),
+            xruns,
+            fade,
+            build,
+            moved: now,
+            retry_at: now,
+            attempts: 0,
+        });
+    }
+
+    pub fn play(&mut self) -> Result<()> {
+        let now = Instant::now();
+        for watched in &mut self.streams {
+            if let Some(stream) = &watched.stream {
+                stream.play()?;
+            }
+            watched.moved = now;
+        }
+        Ok(())
+    }
+
+    /// Checks every stream and tries to bring lost ones back.
+    pub fn poll(&mut self) {
+        let now = Instant::now();
+        for watched in &mut self.streams {
+            if watched.stream.is_some() {
+                let callbacks = watched.xruns.callbacks();
+                if callbacks != watched.callbacks {
+                    watched.callbacks = callbacks;
+                    watched.moved = now;
+                }
+                let lost = watched.xruns.take_device_lost();
+                if lost || now.duration_since(watched.moved) > STALL_TIMEOUT {
+                    warn!(
+                        stream = %watched.name,
+                        reason = if lost { "device lost" } else { "stream stalled" },
+                        "reconnecting"
+                    );
+                    watched.stream = None;
+                    watched.fade.mute();
+                    watched.retry_at = now;
+                    watched.attempts = 0;
+                }
+            }
+
+            if watched.stream.is_none() && now >= watched.retry_at {
+                match reconnect(&self.host, self.fallback, watched) {
+                    Ok((stream, device)) => {
+                        info!(
+                            stream = %watched.name,
+                            device = %device
+                                .description()
+                                .map_or_else(|_| "device".to_string(), |d| d.to_string()),
+                            "reconnected"
+                        );
+                        watched.stream = Some(stream);
+                        watched.fade.fade_in();
+                        watched.callbacks = watched.xruns.callbacks();
+                        watched.moved = now;
+                    }
+                    Err(err) => {
+                        // Only the first failure is worth a line, the
+                        // device is usually just not back yet
+                        if watched.attempts == 0 {
+                            warn!(stream = %watched.name, error = %err, "can't reconnect yet, retrying");
+                        }
+                        watched.attempts += 1;
+                        watched.retry_at = now + RETRY_INTERVAL;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds a device of this host by part of its name, see `find_device`.
+    pub fn find_device(&self, direction: Direction, name: &str) -> Result<Device> {
+        find_device(&self.host, direction, name)
+    }
+
+    /// Moves the `index`th stream of `direction` to `device`. The old
+    /// stream fades out and is dropped first, as most hosts won't open a
+    /// device twice, then the new one fades in. When the new one can't be
+    /// built the stream counts as lost and is retried on `device` by
+    /// `poll`. Returns whether anything changed.
+    pub fn switch(&mut self, direction: Direction, index: usize, device: &Device) -> Result<bool> {
+        let count = self
+            .streams
+            .iter()
+            .filter(|w| w.direction == direction)
+            .count();
+        let watched = self
+            .streams
+            .iter_mut()
+            .filter(|w| w.direction == direction)
+            .nth(index)
+            .ok_or_else(|| anyhow!("Only {} {} streams are open", count, direction.name()))?;
+        let id = device.id().ok();
+        if id.is_some() && id == watched.id && watched.stream.is_some() {
+            return Ok(false);
+        }
+
+        if watched.stream.is_some() {
+            watched.fade.fade_out();
+        }
+        let now = Instant::now();
+        watched.stream = None;
+        watched.id = id;
+        watched.retry_at = now + RETRY_INTERVAL;
+        watched.attempts = 1;
+        let stream = (watched.build)(device)?;
+        stream.play()?;
+        watched.stream = Some(stream);
+        watched.fade.fade_in();
+        watched.callbacks = watched.xruns.callbacks();
+        watched.moved = now;
+        watched.attempts = 0;
+        Ok(true)
+    }
+
+    /// Stops every stream, the last one added first.
+    pub fn stop(self) {
+        for watched in self.streams.into_iter().rev() {
+            drop(watched.stream);
+        }
+    }
+}
+
+/// Finds the first device whose name contains `name`, ignoring case.
+pub fn find_device(host: &Host, direction: Direction, name: &str) -> Result<Device> {
+    let devices = match direction {
+        Direction::Input => host.input_devices()?,
+        Direction::Output => host.output_devices()?,
+    };
+    let wanted = name.to_lowercase();
+    for device in devices {
+        if let Ok(description) = device.description()
+            && description.to_string().to_lowercase().contains(&wanted)
+        {
+            return Ok(device);
+        }
+    }
+    Err(anyhow!(
+        "No {} device matching '{}'",
+        direction.name(),
+        name
+    ))
+}
+
+/// Finds the lost stream's device again, or the default device with
+/// `fallback`, and builds and starts a new stream on it.
+fn reconnect(host: &Host, fallback: bool, watched: &mut Watched) -> Result<(Stream, Device)> {
+    let mut devices = match watched.direction {
+        Direction::Input => host.input_devices()?,
+        Direction::Output => host.output_devices()?,
+    };
+    let found = watched
+        .id
+        .as_ref()
+        .and_then(|id| devices.find(|device| device.id().is_ok_and(|other| &other == id)));
+    let device = match found {
+        Some(device) => device,
+        None if fallback => match watched.direction {
+            Direction::Input => host.default_input_device(),
+            Direction::Output => host.default_output_device(),
+        }
+        .ok_or_else(|| anyhow!("no default device"))?,
+        None => return Err(anyhow!("device not found")),
+    };
+
+    let stream = (watched.build)(&device)?;
+    stream.play()?;
+    Ok((stream, device))
+}
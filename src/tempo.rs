@@ -0,0 +1,66 @@
+use crate::params::{Param, ParamInfo, ParamStore};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const MIN_BPM: f32 = 20.0;
+pub const MAX_BPM: f32 = 400.0;
+/// A longer gap between taps starts a new count.
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Taps averaged into the tempo, the last four beats.
+const TAP_HISTORY: usize = 5;
+
+/// Engine tempo the tempo-synced effects follow, the `tempo.bpm`
+/// parameter. Set from the console or tapped in: from the second tap the
+/// tempo is the average of the last few beats.
+pub struct Tempo {
+    bpm: Arc<Param>,
+    taps: Mutex<Vec<Instant>>,
+}
+
+impl Tempo {
+    pub fn new(bpm: f32, params: &ParamStore) -> Self {
+        Tempo {
+            bpm: params.bind(
+                "tempo.bpm".to_string(),
+                &ParamInfo::new("bpm", bpm.clamp(MIN_BPM, MAX_BPM), MIN_BPM, MAX_BPM),
+            ),
+            taps: Mutex::new(Vec::with_capacity(TAP_HISTORY)),
+        }
+    }
+
+    /// The parameter itself, read by processors every block.
+    pub fn param(&self) -> Arc<Param> {
+        self.bpm.clone()
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm.get()
+    }
+
+    /// Returns the tempo set, clamped to its range.
+    pub fn set(&self, bpm: f32) -> f32 {
+        self.bpm.set(bpm)
+    }
+
+    /// Counts one beat, returning the new tempo from the second tap on.
+    pub fn tap(&self) -> Option<f32> {
+        let now = Instant::now();
+        let mut taps = self.taps.lock().unwrap();
+        if taps
+            .last()
+            .is_some_and(|&last| now.duration_since(last) > TAP_TIMEOUT)
+        {
+            taps.clear();
+        }
+        if taps.len() == TAP_HISTORY {
+            taps.remove(0);
+        }
+        taps.push(now);
+        if taps.len() < 2 {
+            return None;
+        }
+        let span = now.duration_since(taps[0]).as_secs_f32();
+        let beat = span / (taps.len() - 1) as f32;
+        Some(self.set(60.0 / beat))
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Callbacks queued before new timings are dropped, about a second at
+/// small buffer sizes.
+const CAPACITY: usize = 1024;
+/// Time between summaries logged at debug level.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Parts of the output callback timed separately, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Mixer, broadcast bus and the dry recording.
+    Mix,
+    Chain,
+    /// Master fader, graphic EQ and multiband, the phase meter.
+    Master,
+    /// Soft clipper, speakers, correction, limiter, alignment and fade.
+    Output,
+    /// Meters, loudness and the spectrum analyzer.
+    Meters,
+    /// Recorders and network streams.
+    Taps,
+}
+
+const STAGES: usize = 6;
+
+impl Stage {
+    const ALL: [Stage; STAGES] = [
+        Stage::Mix,
+        Stage::Chain,
+        Stage::Master,
+        Stage::Output,
+        Stage::Meters,
+        Stage::Taps,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Mix => "mix",
+            Stage::Chain => "chain",
+            Stage::Master => "master",
+            Stage::Output => "output",
+            Stage::Meters => "meters",
+            Stage::Taps => "taps",
+        }
+    }
+}
+
+/// Nanoseconds per stage of one callback, then the whole callback.
+type Laps = [u32; STAGES + 1];
+
+/// Audio thread side: times the stages of each callback and queues them in
+/// a lock-free ring, so timing never blocks or allocates.
+pub struct CallbackTimer {
+    producer: HeapProd<Laps>,
+    dropped: Arc<AtomicU64>,
+    started: Instant,
+    last: Instant,
+    laps: Laps,
+}
+
+impl CallbackTimer {
+    pub fn begin(&mut self) {
+        self.started = Instant::now();
+        self.last = self.started;
+        self.laps = [0; STAGES + 1];
+    }
+
+    /// Adds the time since the previous lap to `stage`.
+    pub fn lap(&mut self, stage: Stage) {
+        let now = Instant::now();
+        self.laps[stage as usize] += nanos(now - self.last);
+        self.last = now;
+    }
+
+    /// Queues the callback's timings and returns how long it took.
+    pub fn end(&mut self) -> Duration {
+        let busy = self.started.elapsed();
+        self.laps[STAGES] = nanos(busy);
+        if self.producer.try_push(self.laps).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        busy
+    }
+}
+
+fn nanos(duration: Duration) -> u32 {
+    u32::try_from(duration.as_nanos()).unwrap_or(u32::MAX)
+}
+
+/// Where callback time went over the session, summed from the timings the
+/// audio thread queues.
+#[derive(Debug)]
+pub struct CallbackTimes {
+    totals: Mutex<Totals>,
+    dropped: Arc<AtomicU64>,
+    /// Folded stacks are written here on exit, set with `--callback-profile`.
+    report: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    callbacks: u64,
+    /// Nanoseconds per stage, then the whole callbacks.
+    stages: [u64; STAGES + 1],
+    longest: u64,
+}
+
+impl CallbackTimes {
+    /// The ring for each callback's timings is drained on its own thread,
+    /// which logs a summary at debug level every second.
+    pub fn spawn(report: Option<PathBuf>) -> std::io::Result<(CallbackTimer, Arc<CallbackTimes>)> {
+        let (producer, consumer) = HeapRb::<Laps>::new(CAPACITY).split();
+        let times = Arc::new(CallbackTimes {
+            totals: Mutex::new(Totals::default()),
+            dropped: Arc::new(AtomicU64::new(0)),
+            report,
+        });
+        let timer = CallbackTimer {
+            producer,
+            dropped: times.dropped.clone(),
+            started: Instant::now(),
+            last: Instant::now(),
+            laps: [0; STAGES + 1],
+        };
+        let shared = times.clone();
+        thread::Builder::new()
+            .name("callback timing".to_string())
+            .spawn(move || shared.run(consumer))?;
+        Ok((timer, times))
+    }
+
+    /// Sums timings until the timer is dropped with the engine.
+    fn run(&self, mut consumer: HeapCons<Laps>) {
+        let mut summary = Totals::default();
+        let mut last_summary = Instant::now();
+        loop {
+            if consumer.is_empty() {
+                if !consumer.write_is_held() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            while let Some(laps) = consumer.try_pop() {
+                summary.add(&laps);
+                self.totals.lock().unwrap().add(&laps);
+            }
+            if last_summary.elapsed() >= SUMMARY_INTERVAL && summary.callbacks > 0 {
+                let average = |stage: usize| summary.stages[stage] / summary.callbacks / 1000;
+                debug!(
+                    callbacks = summary.callbacks,
+                    average_us = average(STAGES),
+                    longest_us = summary.longest / 1000,
+                    mix_us = average(Stage::Mix as usize),
+                    chain_us = average(Stage::Chain as usize),
+                    master_us = average(Stage::Master as usize),
+                    output_us = average(Stage::Output as usize),
+                    meters_us = average(Stage::Meters as usize),
+                    taps_us = average(Stage::Taps as usize),
+                    "callback timing"
+                );
+                summary = Totals::default();
+                last_summary = Instant::now();
+            }
+        }
+    }
+
+    /// Total time per stage as folded stacks, `render;chain 1234` in
+    /// microseconds, the input flamegraph.pl and inferno take. Time between
+    /// the stages is left on `render` itself.
+    pub fn folded(&self) -> String {
+        let totals = *self.totals.lock().unwrap();
+        let mut text = String::new();
+        let staged: u64 = totals.stages[..STAGES].iter().sum();
+        let _ = writeln!(
+            text,
+            "render {}",
+            totals.stages[STAGES].saturating_sub(staged) / 1000
+        );
+        for stage in Stage::ALL {
+            let _ = writeln!(
+                text,
+                "render;{} {}",
+                stage.name(),
+                totals.stages[stage as usize] / 1000
+            );
+        }
+        text
+    }
+
+    /// Share of the callback time per stage, the largest first.
+    pub fn describe(&self) -> String {
+        let totals = *self.totals.lock().unwrap();
+        if totals.callbacks == 0 {
+            return "Callback timing: no callbacks yet".to_string();
+        }
+        let total = totals.stages[STAGES].max(1);
+        let mut stages: Vec<(Stage, u64)> = Stage::ALL
+            .iter()
+            .map(|&stage| (stage, totals.stages[stage as usize]))
+            .collect();
+        stages.sort_by_key(|s| std::cmp::Reverse(s.1));
+        let mut lines = vec![format!(
+            "Callback timing over {} callbacks: {:.1} us average, {:.1} us longest, {} dropped",
+            totals.callbacks,
+            total as f64 / totals.callbacks as f64 / 1000.0,
+            totals.longest as f64 / 1000.0,
+            self.dropped.load(Ordering::Relaxed)
+        )];
+        for (stage, nanos) in stages {
+            lines.push(format!(
+                "  {:<7} {:5.1}%  {:.1} us average",
+                stage.name(),
+                nanos as f64 * 100.0 / total as f64,
+                nanos as f64 / totals.callbacks as f64 / 1000.0
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Writes the folded stacks and prints the shares when a report was
+    /// asked for.
+    pub fn finish(&self) -> Result<()> {
+        let Some(path) = &self.report else {
+            return Ok(());
+        };
+        fs::write(path, self.folded())
+            .with_context(|| format!("Failed to write the callback profile {}", path.display()))?;
+        println!("{}", self.describe());
+        info!(path = %path.display(), "callback profile written");
+        Ok(())
+    }
+}
+
+impl Totals {
+    fn add(&mut self, laps: &Laps) {
+        self.callbacks += 1;
+        for (total, &lap) in self.stages.iter_mut().zip(laps) {
+            *total += lap as u64;
+        }
+        self.longest = self.longest.max(laps[STAGES] as u64);
+    }
+}
@@ -0,0 +1,401 @@
+use crate::dsp::fft::{Complex, Fft};
+use crate::dsp::gain_to_db;
+use crate::dsp::graphic_eq::BANDS;
+use crate::params::AtomicF32;
+use anyhow::Result;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// FFT length, 2.9 Hz bins at 48 kHz, fine enough for the low third
+/// octaves.
+const FFT_SIZE: usize = 16384;
+/// History the delay finder correlates, 2.7 s at 48 kHz.
+const HISTORY: usize = 131072;
+/// Longest delay searched for, the system's latency plus a mic 100 m out.
+const MAX_DELAY_SECONDS: f32 = 0.5;
+/// Share of the average kept per frame, two seconds or so to settle on
+/// programme material.
+const AVERAGING: f32 = 0.9;
+/// Correlation peak over the search window's RMS needed to trust a delay.
+const MIN_CONFIDENCE: f32 = 8.0;
+/// Coherence under which a band's reading is mostly noise or reflections.
+pub const MIN_COHERENCE: f32 = 0.5;
+
+/// Transfer function in a band: how the measurement differs from the
+/// reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub magnitude_db: f32,
+    /// -180 to 180 degrees.
+    pub phase: f32,
+    /// 0 to 1, how much of the measurement the reference accounts for.
+    pub coherence: f32,
+}
+
+/// The channels compared and the averaged spectra, readable from any
+/// thread.
+#[derive(Debug)]
+pub struct TransferView {
+    sample_rate: f32,
+    /// Mixer channels from 1, 0 while the measurement is off.
+    reference: AtomicUsize,
+    measurement: AtomicUsize,
+    /// Bumped on every change of channels or search for the delay, so the
+    /// analyzer starts afresh.
+    generation: AtomicUsize,
+    /// Set to look for the delay again.
+    find: AtomicBool,
+    /// Samples the measurement lags the reference by, plus 1, 0 while it
+    /// hasn't been found.
+    delay: AtomicUsize,
+    /// Per bin, the reference's and the measurement's power and their
+    /// cross spectrum.
+    reference_power: Vec<AtomicF32>,
+    measurement_power: Vec<AtomicF32>,
+    cross_re: Vec<AtomicF32>,
+    cross_im: Vec<AtomicF32>,
+}
+
+impl TransferView {
+    /// Reference and measurement channels, counted from 0.
+    pub fn channels(&self) -> Option<(usize, usize)> {
+        let reference = self.reference.load(Ordering::Relaxed).checked_sub(1)?;
+        let measurement = self.measurement.load(Ordering::Relaxed).checked_sub(1)?;
+        Some((reference, measurement))
+    }
+
+    /// Compares mixer channels `(reference, measurement)`, counted from 0,
+    /// or turns the measurement off. The delay is looked for anew.
+    pub fn set_channels(&self, channels: Option<(usize, usize)>) {
+        let (reference, measurement) = channels.map_or((0, 0), |(r, m)| (r + 1, m + 1));
+        self.reference.store(reference, Ordering::Relaxed);
+        self.measurement.store(measurement, Ordering::Relaxed);
+        self.find_delay();
+    }
+
+    /// Looks for the delay again, after the mic was moved, and starts the
+    /// averages afresh.
+    pub fn find_delay(&self) {
+        self.delay.store(0, Ordering::Relaxed);
+        self.find.store(true, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Delay the reference is held back by to line up with the
+    /// measurement, in ms, `None` until it's found.
+    pub fn delay_ms(&self) -> Option<f32> {
+        let delay = self.delay.load(Ordering::Relaxed).checked_sub(1)?;
+        Some(delay as f32 * 1000.0 / self.sample_rate)
+    }
+
+    /// The bins from `low_hz` to `high_hz` together, `None` while nothing
+    /// has been measured there.
+    pub fn band(&self, low_hz: f32, high_hz: f32) -> Option<Reading> {
+        let bin = |hz: f32| hz * FFT_SIZE as f32 / self.sample_rate;
+        let last = self.reference_power.len() - 1;
+        let low = (bin(low_hz).ceil() as usize).min(last);
+        let high = (bin(high_hz).floor() as usize).clamp(low, last);
+        let (mut xx, mut yy, mut cross) = (0.0, 0.0, Complex::ZERO);
+        for index in low..=high {
+            xx += self.reference_power[index].get();
+            yy += self.measurement_power[index].get();
+            cross += Complex::new(self.cross_re[index].get(), self.cross_im[index].get());
+        }
+        if self.delay_ms().is_none() || xx <= 0.0 || yy <= 0.0 {
+            return None;
+        }
+        Some(Reading {
+            magnitude_db: gain_to_db(cross.norm() / xx),
+            phase: cross.arg().to_degrees(),
+            coherence: (cross.norm_sqr() / (xx * yy)).min(1.0),
+        })
+    }
+
+    /// Every ISO third octave by its name.
+    pub fn third_octaves(&self) -> Vec<(&'static str, Option<Reading>)> {
+        let edge = 2.0f32.powf(1.0 / 6.0);
+        BANDS
+            .iter()
+            .filter(|&&(centre, _)| centre * edge < self.sample_rate / 2.0)
+            .map(|&(centre, name)| (name, self.band(centre / edge, centre * edge)))
+            .collect()
+    }
+
+    /// `bands` log-spaced bands between `min_hz` and Nyquist.
+    pub fn bands(&self, bands: usize, min_hz: f32) -> Vec<Option<Reading>> {
+        let ratio = (self.sample_rate / 2.0 / min_hz).max(1.0);
+        (0..bands)
+            .map(|band| {
+                let low = min_hz * ratio.powf(band as f32 / bands as f32);
+                let high = min_hz * ratio.powf((band + 1) as f32 / bands as f32);
+                self.band(low, high)
+            })
+            .collect()
+    }
+}
+
+/// Audio-thread side: takes the two channels' blocks from the mixer, each
+/// summed to mono, and hands them to the analyzer thread in pairs so they
+/// stay lined up. Pairs are dropped when the analyzer falls behind.
+pub struct TransferTap {
+    producer: HeapProd<(f32, f32)>,
+    view: Arc<TransferView>,
+    reference: Vec<f32>,
+    measurement: Vec<f32>,
+}
+
+impl TransferTap {
+    /// Takes mixer channel `index`'s block if it's one of the two.
+    pub fn capture(&mut self, index: usize, buffer: &[f32], channels: usize) {
+        let Some((reference, measurement)) = self.view.channels() else {
+            return;
+        };
+        let mono = if index == reference {
+            &mut self.reference
+        } else if index == measurement {
+            &mut self.measurement
+        } else {
+            return;
+        };
+        let frames = buffer.len() / channels;
+        if mono.len() < frames {
+            mono.resize(frames, 0.0);
+        }
+        for (sample, frame) in mono.iter_mut().zip(buffer.chunks(channels)) {
+            *sample = frame.iter().sum::<f32>() / channels as f32;
+        }
+    }
+
+    /// Sends the block's `frames` once both channels are captured.
+    pub fn flush(&mut self, frames: usize) {
+        if self.view.channels().is_none() {
+            return;
+        }
+        let frames = frames.min(self.reference.len()).min(self.measurement.len());
+        for pair in self.reference[..frames]
+            .iter()
+            .zip(&self.measurement[..frames])
+        {
+            if self.producer.try_push((*pair.0, *pair.1)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Starts the analyzer thread, off until channels are set, and returns the
+/// tap for the mixer plus the shared view of the result.
+pub fn spawn(sample_rate: f32) -> Result<(TransferTap, Arc<TransferView>)> {
+    let bins = || (0..FFT_SIZE / 2 + 1).map(|_| AtomicF32::new(0.0)).collect();
+    let view = Arc::new(TransferView {
+        sample_rate,
+        reference: AtomicUsize::new(0),
+        measurement: AtomicUsize::new(0),
+        generation: AtomicUsize::new(0),
+        find: AtomicBool::new(false),
+        delay: AtomicUsize::new(0),
+        reference_power: bins(),
+        measurement_power: bins(),
+        cross_re: bins(),
+        cross_im: bins(),
+    });
+    let (producer, consumer) = HeapRb::<(f32, f32)>::new(FFT_SIZE * 4).split();
+
+    let analyzer = Analyzer::new(sample_rate, view.clone());
+    thread::Builder::new()
+        .name("transfer".to_string())
+        .spawn(move || analyzer.run(consumer))?;
+
+    let tap = TransferTap {
+        producer,
+        view: view.clone(),
+        reference: vec![0.0; 8192],
+        measurement: vec![0.0; 8192],
+    };
+    Ok((tap, view))
+}
+
+/// Dual-FFT analyzer: averages the reference's and the measurement's
+/// spectra and their cross spectrum, with the reference held back by the
+/// delay found between them.
+struct Analyzer {
+    view: Arc<TransferView>,
+    max_delay: usize,
+    fft: Fft,
+    window: Vec<f32>,
+    /// The newest `HISTORY` samples of each channel.
+    reference: Vec<f32>,
+    measurement: Vec<f32>,
+    /// New samples since the channels or the delay changed.
+    filled: usize,
+    generation: usize,
+    x: Vec<Complex>,
+    y: Vec<Complex>,
+    reference_power: Vec<f32>,
+    measurement_power: Vec<f32>,
+    cross: Vec<Complex>,
+}
+
+impl Analyzer {
+    fn new(sample_rate: f32, view: Arc<TransferView>) -> Self {
+        let bins = FFT_SIZE / 2 + 1;
+        Analyzer {
+            view,
+            max_delay: ((MAX_DELAY_SECONDS * sample_rate) as usize).min(HISTORY - FFT_SIZE),
+            fft: Fft::new(FFT_SIZE),
+            window: (0..FFT_SIZE)
+                .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / FFT_SIZE as f32).cos())
+                .collect(),
+            reference: vec![0.0; HISTORY],
+            measurement: vec![0.0; HISTORY],
+            filled: 0,
+            generation: 0,
+            x: vec![Complex::ZERO; FFT_SIZE],
+            y: vec![Complex::ZERO; FFT_SIZE],
+            reference_power: vec![0.0; bins],
+            measurement_power: vec![0.0; bins],
+            cross: vec![Complex::ZERO; bins],
+        }
+    }
+
+    /// Runs one FFT per half frame of new input until the tap is dropped.
+    fn run(mut self, mut consumer: HeapCons<(f32, f32)>) {
+        let hop = FFT_SIZE / 2;
+        let mut pairs = vec![(0.0, 0.0); hop];
+        loop {
+            if consumer.occupied_len() < hop {
+                if !consumer.write_is_held() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            consumer.pop_slice(&mut pairs);
+            let generation = self.view.generation.load(Ordering::Relaxed);
+            if generation != self.generation {
+                self.generation = generation;
+                self.clear();
+            }
+            if self.view.channels().is_none() {
+                continue;
+            }
+            self.reference.copy_within(hop.., 0);
+            self.measurement.copy_within(hop.., 0);
+            for (index, &(reference, measurement)) in pairs.iter().enumerate() {
+                self.reference[HISTORY - hop + index] = reference;
+                self.measurement[HISTORY - hop + index] = measurement;
+            }
+            self.filled += hop;
+
+            if self.view.find.load(Ordering::Relaxed) {
+                if self.filled < HISTORY {
+                    continue;
+                }
+                match self.find_delay() {
+                    Some(delay) => {
+                        self.view.find.store(false, Ordering::Relaxed);
+                        self.view.delay.store(delay + 1, Ordering::Relaxed);
+                        self.clear();
+                        self.filled = HISTORY;
+                    }
+                    // Tried again on the next half of the history, there
+                    // may be nothing playing yet
+                    None => self.filled = HISTORY / 2,
+                }
+                continue;
+            }
+            if let Some(delay) = self.view.delay.load(Ordering::Relaxed).checked_sub(1) {
+                self.analyze(delay);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.filled = 0;
+        self.reference_power.fill(0.0);
+        self.measurement_power.fill(0.0);
+        self.cross.fill(Complex::ZERO);
+        for bins in [
+            &self.view.reference_power,
+            &self.view.measurement_power,
+            &self.view.cross_re,
+            &self.view.cross_im,
+        ] {
+            bins.iter().for_each(|bin| bin.set(0.0));
+        }
+    }
+
+    /// Lag of the measurement behind the reference by cross-correlating
+    /// the history, phase-transformed so music with strong low notes still
+    /// gives a single sharp peak.
+    fn find_delay(&self) -> Option<usize> {
+        let size = HISTORY * 2;
+        let fft = Fft::new(size);
+        let mut x = vec![Complex::ZERO; size];
+        let mut y = vec![Complex::ZERO; size];
+        for (value, &s) in x.iter_mut().zip(&self.reference) {
+            *value = Complex::new(s, 0.0);
+        }
+        for (value, &s) in y.iter_mut().zip(&self.measurement) {
+            *value = Complex::new(s, 0.0);
+        }
+        fft.forward(&mut x);
+        fft.forward(&mut y);
+        for (y, x) in y.iter_mut().zip(&x) {
+            let cross = *y * x.conj();
+            let norm = cross.norm();
+            *y = if norm > 1e-12 {
+                cross.scale(1.0 / norm)
+            } else {
+                Complex::ZERO
+            };
+        }
+        fft.inverse(&mut y);
+
+        let window = &y[..=self.max_delay];
+        let (lag, peak) = window
+            .iter()
+            .map(|c| c.re)
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let rms = (window.iter().map(|c| c.re * c.re).sum::<f32>() / window.len() as f32).sqrt();
+        (rms > 0.0 && peak / rms >= MIN_CONFIDENCE).then_some(lag)
+    }
+
+    fn analyze(&mut self, delay: usize) {
+        if self.filled < FFT_SIZE + delay {
+            return;
+        }
+        let measurement = &self.measurement[HISTORY - FFT_SIZE..];
+        let reference = &self.reference[HISTORY - FFT_SIZE - delay..HISTORY - delay];
+        for (index, &w) in self.window.iter().enumerate() {
+            self.x[index] = Complex::new(reference[index] * w, 0.0);
+            self.y[index] = Complex::new(measurement[index] * w, 0.0);
+        }
+        self.fft.forward(&mut self.x);
+        self.fft.forward(&mut self.y);
+
+        for index in 0..self.cross.len() {
+            let (x, y) = (self.x[index], self.y[index]);
+            let keep = |average: f32, value: f32| AVERAGING * average + (1.0 - AVERAGING) * value;
+            self.reference_power[index] = keep(self.reference_power[index], x.norm_sqr());
+            self.measurement_power[index] = keep(self.measurement_power[index], y.norm_sqr());
+            let cross = y * x.conj();
+            self.cross[index] = Complex::new(
+                keep(self.cross[index].re, cross.re),
+                keep(self.cross[index].im, cross.im),
+            );
+
+            self.view.reference_power[index].set(self.reference_power[index]);
+            self.view.measurement_power[index].set(self.measurement_power[index]);
+            self.view.cross_re[index].set(self.cross[index].re);
+            self.view.cross_im[index].set(self.cross[index].im);
+        }
+    }
+}
@@ -0,0 +1,733 @@
+use crate::control::{self, Controls};
+use crate::meter::{self, Level, MeterLevels};
+use crate::shutdown;
+use crate::supervisor::Supervisor;
+use crate::transfer;
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::{Axis, Block, Chart, Dataset, GraphType, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::Duration;
+
+/// Bottom of the meter scale.
+const METER_FLOOR_DB: f32 = -60.0;
+/// Range and lowest frequency of the spectrum display.
+const SPECTRUM_FLOOR_DB: f32 = -90.0;
+const SPECTRUM_MIN_HZ: f32 = 20.0;
+/// Columns of the goniometer next to the spectrum, borders included.
+const GONIOMETER_WIDTH: u16 = 24;
+/// Rows of the scope, borders included.
+const SCOPE_HEIGHT: u16 = 12;
+/// Magnitude either side of 0 dB on the transfer function display.
+const TRANSFER_RANGE_DB: f32 = 24.0;
+/// Columns of the clip indicator in the header.
+const CLIP_WIDTH: u16 = 32;
+/// Most rows of the processor timings under the parameters, borders
+/// included.
+const CPU_HEIGHT: u16 = 10;
+/// Share of the callback budget one processor may take before its timing
+/// turns yellow, then red.
+const CPU_WARN: f32 = 0.25;
+const CPU_ALERT: f32 = 0.5;
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+/// Cents either side of the tuner needle's scale, and close enough to
+/// show as in tune.
+const TUNER_RANGE_CENTS: f32 = 50.0;
+const IN_TUNE_CENTS: f32 = 3.0;
+const GAIN_STEP_DB: f32 = 0.5;
+const PAN_STEP: f32 = 0.05;
+
+const HELP: &str = "Tab pane | Up/Down select | Left/Right adjust | [ ] pan | m mute | s solo | d dim | o mono | b bypass | t talk | k u x looper rec, undo, clear | p tap tempo | c metronome | n tuner | w scope | r reset loudness | f reset feedback | e reset clips | g reset cpu | z y undo, redo | Space play | Home rewind | l loop | q quit";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Mixer,
+    Params,
+}
+
+struct App<'a> {
+    controls: &'a Controls,
+    pane: Pane,
+    /// Selected mixer row, the master bus is the last one.
+    channel: usize,
+    param: usize,
+}
+
+/// Runs the terminal UI until `q` or Esc is pressed.
+pub fn run(controls: &Controls, supervisor: Option<&mut Supervisor>) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(controls).run(&mut terminal, supervisor);
+    ratatui::restore();
+    result
+}
+
+impl<'a> App<'a> {
+    fn new(controls: &'a Controls) -> Self {
+        App {
+            controls,
+            pane: Pane::Mixer,
+            channel: 0,
+            param: 0,
+        }
+    }
+
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        mut supervisor: Option<&mut Supervisor>,
+    ) -> Result<()> {
+        while !shutdown::requested() {
+            if let Some(supervisor) = supervisor.as_mut() {
+                supervisor.poll();
+            }
+            control::serve_requests(self.controls, supervisor.as_deref_mut());
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(FRAME_INTERVAL)? {
+                continue;
+            }
+            // Raw mode turns Ctrl-C into a key instead of a signal
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+                    || !self.handle_key(key.code))
+            {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns false when the UI should exit.
+    fn handle_key(&mut self, code: KeyCode) -> bool {
+        let rows = self.controls.channels.len() + 1;
+        let params = self.controls.params.all().len();
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Tab => {
+                self.pane = match self.pane {
+                    Pane::Mixer => Pane::Params,
+                    Pane::Params => Pane::Mixer,
+                }
+            }
+            KeyCode::Up => match self.pane {
+                Pane::Mixer => self.channel = self.channel.saturating_sub(1),
+                Pane::Params => self.param = self.param.saturating_sub(1),
+            },
+            KeyCode::Down => match self.pane {
+                Pane::Mixer => self.channel = (self.channel + 1).min(rows - 1),
+                Pane::Params => self.param = (self.param + 1).min(params.saturating_sub(1)),
+            },
+            KeyCode::Left => self.adjust(-1.0),
+            KeyCode::Right => self.adjust(1.0),
+            KeyCode::Char('[') => self.pan(-PAN_STEP),
+            KeyCode::Char(']') => self.pan(PAN_STEP),
+            KeyCode::Char('m') => {
+                if let Some(channel) = self.controls.channels.get(self.channel) {
+                    channel.set_mute(!channel.muted());
+                }
+            }
+            KeyCode::Char('s') => {
+                if let Some(channel) = self.controls.channels.get(self.channel) {
+                    channel.set_solo(!channel.soloed());
+                }
+            }
+            KeyCode::Char('d') => {
+                let master = &self.controls.master;
+                master.set_dim(!master.dimmed());
+            }
+            KeyCode::Char('o') => {
+                let master = &self.controls.master;
+                master.set_mono(!master.mono());
+            }
+            KeyCode::Char('b') => self.toggle_bypass(),
+            KeyCode::Char('t') => {
+                if let Ok(on) = control::talkback(self.controls) {
+                    on.set(if on.get() < 0.5 { 1.0 } else { 0.0 });
+                }
+            }
+            KeyCode::Char('k') => self.fire_looper("record"),
+            KeyCode::Char('u') => self.fire_looper("undo"),
+            KeyCode::Char('x') => self.fire_looper("clear"),
+            KeyCode::Char('p') => {
+                self.controls.tempo.tap();
+            }
+            KeyCode::Char('c') => {
+                if let Ok(on) = control::metronome(self.controls) {
+                    on.set(if on.get() < 0.5 { 1.0 } else { 0.0 });
+                }
+            }
+            // Tunes the selected channel, or turns the tuner off on it
+            KeyCode::Char('n') => {
+                let tuner = &self.controls.tuner;
+                if tuner.channel() == Some(self.channel) {
+                    tuner.set_channel(None);
+                } else if self.channel < self.controls.channels.len() {
+                    tuner.set_channel(Some(self.channel));
+                }
+            }
+            // Shows the selected channel on the scope, or turns it off
+            KeyCode::Char('w') => {
+                let scope = &self.controls.scope;
+                if scope.channel() == Some(self.channel) {
+                    scope.set_channel(None);
+                } else if self.channel < self.controls.channels.len() {
+                    scope.set_channel(Some(self.channel));
+                }
+            }
+            KeyCode::Char('z') => {
+                let _ = self.controls.history.undo(self.controls);
+            }
+            KeyCode::Char('y') => {
+                let _ = self.controls.history.redo(self.controls);
+            }
+            KeyCode::Char('r') => self.controls.loudness.reset(),
+            KeyCode::Char('f') => self.controls.feedback.reset(),
+            KeyCode::Char('e') => self.controls.params.clips().reset(),
+            KeyCode::Char('g') => self.controls.params.loads().reset(),
+            // Transport keys drive every file player together
+            KeyCode::Char(' ') => {
+                let playing = self.controls.players.iter().any(|p| p.playing());
+                for player in &self.controls.players {
+                    if playing {
+                        player.pause();
+                    } else {
+                        player.play();
+                    }
+                }
+            }
+            KeyCode::Home => self.controls.players.iter().for_each(|p| p.seek(0.0)),
+            KeyCode::Char('l') => {
+                for player in &self.controls.players {
+                    player.set_looping(!player.looping());
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn fire_looper(&self, action: &str) {
+        if let Ok(trigger) = control::looper(self.controls, action) {
+            trigger.set(1.0);
+        }
+    }
+
+    /// Nudges the selected fader or parameter up or down one step.
+    fn adjust(&mut self, direction: f32) {
+        match self.pane {
+            Pane::Mixer => match self.controls.channels.get(self.channel) {
+                Some(channel) => channel.set_gain_db(channel.gain_db() + direction * GAIN_STEP_DB),
+                None => {
+                    let master = &self.controls.master;
+                    master.set_gain_db(master.gain_db() + direction * GAIN_STEP_DB);
+                }
+            },
+            Pane::Params => {
+                if let Some(param) = self.controls.params.all().get(self.param) {
+                    let step = (param.max - param.min) / 100.0;
+                    param.set(param.get() + direction * step);
+                }
+            }
+        }
+    }
+
+    fn pan(&mut self, amount: f32) {
+        if self.pane == Pane::Mixer
+            && let Some(channel) = self.controls.channels.get(self.channel)
+        {
+            channel.set_pan(channel.pan() + amount);
+        }
+    }
+
+    /// Toggles the bypass switch of the processor owning the selected
+    /// parameter.
+    fn toggle_bypass(&mut self) {
+        let params = self.controls.params.all();
+        let Some(param) = params.get(self.param) else {
+            return;
+        };
+        let Some((processor, _)) = param.key.rsplit_once('.') else {
+            return;
+        };
+        if let Some(bypass) = self.controls.params.get(&format!("{}.bypass", processor)) {
+            bypass.set(if bypass.get() >= 0.5 { 0.0 } else { 1.0 });
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let tuning = self.controls.tuner.channel().is_some();
+        let scoping = self.controls.scope.channel().is_some();
+        let [header, body, tuner, scope, analyzer, transport, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(if tuning { 3 } else { 0 }),
+            Constraint::Length(if scoping { SCOPE_HEIGHT } else { 0 }),
+            Constraint::Length(8),
+            Constraint::Length(self.controls.players.len() as u16),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [mixer, params] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(body);
+        let processors = self.controls.params.loads().all().len() as u16;
+        let [params, cpu] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(if processors > 0 {
+                (processors + 2).min(CPU_HEIGHT)
+            } else {
+                0
+            }),
+        ])
+        .areas(params);
+
+        let [title, loudness, clips, xruns] = Layout::horizontal([
+            Constraint::Length(10),
+            Constraint::Min(0),
+            Constraint::Length(CLIP_WIDTH),
+            Constraint::Length(14),
+        ])
+        .areas(header);
+        frame.render_widget(
+            Paragraph::new(
+                Line::from("live_dsp").style(Style::default().add_modifier(Modifier::BOLD)),
+            ),
+            title,
+        );
+        frame.render_widget(
+            Paragraph::new(control::describe_loudness(&self.controls.loudness)),
+            loudness,
+        );
+        let events: u64 = self
+            .controls
+            .xruns
+            .iter()
+            .map(|x| x.snapshot().events())
+            .sum();
+        let style = if events > 0 {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        frame.render_widget(
+            Paragraph::new(format!("xruns {}", events)).style(style),
+            xruns,
+        );
+        // The earliest stage holding a clip, `clips` lists them all
+        let held = self.controls.params.clips().held();
+        let (text, style) = match held.first() {
+            Some(stats) if held.len() > 1 => (
+                format!("CLIP {} +{}", stats.name, held.len() - 1),
+                Style::default().fg(Color::Red),
+            ),
+            Some(stats) => (
+                format!("CLIP {}", stats.name),
+                Style::default().fg(Color::Red),
+            ),
+            None => ("no clips".to_string(), Style::default().fg(Color::DarkGray)),
+        };
+        frame.render_widget(Paragraph::new(text).style(style), clips);
+        self.draw_mixer(frame, mixer);
+        self.draw_params(frame, params);
+        if processors > 0 {
+            self.draw_cpu(frame, cpu);
+        }
+        if tuning {
+            self.draw_tuner(frame, tuner);
+        }
+        if scoping {
+            self.draw_scope(frame, scope);
+        }
+        let [analyzer, goniometer] = Layout::horizontal([
+            Constraint::Min(0),
+            Constraint::Length(if self.controls.phase.stereo() {
+                GONIOMETER_WIDTH
+            } else {
+                0
+            }),
+        ])
+        .areas(analyzer);
+        if self.controls.transfer.channels().is_some() {
+            self.draw_transfer(frame, analyzer);
+        } else {
+            self.draw_spectrum(frame, analyzer);
+        }
+        if self.controls.phase.stereo() {
+            self.draw_goniometer(frame, goniometer);
+        }
+        let players: Vec<Line> = self
+            .controls
+            .players
+            .iter()
+            .enumerate()
+            .map(|(index, player)| Line::from(control::describe_player(index, player)))
+            .collect();
+        frame.render_widget(Paragraph::new(players), transport);
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::default().fg(Color::DarkGray)),
+            footer,
+        );
+    }
+
+    fn draw_mixer(&self, frame: &mut Frame, area: Rect) {
+        let block = pane_block(" Mixer ", self.pane == Pane::Mixer);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        // One strip per mixer channel plus the master, one line per
+        // audio channel
+        let controls = self.controls;
+        let strips: Vec<&MeterLevels> = controls
+            .channels
+            .iter()
+            .map(|c| c.meter.as_ref())
+            .chain(std::iter::once(controls.output.as_ref()))
+            .collect();
+        let heights = strips
+            .iter()
+            .map(|levels| Constraint::Length(levels.channels().max(1) as u16));
+        let rows = Layout::vertical(heights).split(inner);
+
+        for (index, (row, levels)) in rows.iter().zip(&strips).enumerate() {
+            let label = match controls.channels.get(index) {
+                Some(channel) => format!(
+                    "{:<16.16} {:+6.1} dB {:+5.2} {}{}",
+                    channel.name,
+                    channel.gain_db(),
+                    channel.pan(),
+                    if channel.muted() { 'M' } else { ' ' },
+                    if channel.soloed() { 'S' } else { ' ' },
+                ),
+                None => {
+                    let master = &controls.master;
+                    format!(
+                        "{:<16.16} {:+6.1} dB       {}{}",
+                        "Master",
+                        master.gain_db(),
+                        if master.dimmed() { 'D' } else { ' ' },
+                        if master.mono() { 'O' } else { ' ' },
+                    )
+                }
+            };
+
+            let [text, bars] =
+                Layout::horizontal([Constraint::Length(38), Constraint::Min(8)]).areas(*row);
+            let mut style = Style::default();
+            if index == self.channel {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            frame.render_widget(Paragraph::new(label).style(style), text);
+
+            let width = (bars.width as usize).saturating_sub(10).max(1);
+            let lines: Vec<Line> = levels
+                .levels()
+                .map(|level| level_line(level, width))
+                .collect();
+            frame.render_widget(Paragraph::new(lines), bars);
+        }
+    }
+
+    fn draw_params(&self, frame: &mut Frame, area: Rect) {
+        let block = pane_block(" Parameters ", self.pane == Pane::Params);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let lines: Vec<Line> = self
+            .controls
+            .params
+            .all()
+            .iter()
+            .enumerate()
+            .map(|(index, param)| {
+                let line = Line::from(format!("{:<28} {:>8.2}", param.key, param.get()));
+                if index == self.param {
+                    line.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        // Keep the selection in view
+        let height = inner.height.max(1) as usize;
+        let offset = self.param.saturating_sub(height - 1);
+        frame.render_widget(Paragraph::new(lines).scroll((offset as u16, 0)), inner);
+    }
+
+    /// Average and longest time per callback of each processor, coloured
+    /// by its share of the budget.
+    fn draw_cpu(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered().title(" Processor load ");
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let sample_rate = self.controls.load.sample_rate;
+        let lines: Vec<Line> = self
+            .controls
+            .params
+            .loads()
+            .all()
+            .iter()
+            .map(|load| {
+                let s = load.snapshot();
+                let share = s.load(sample_rate);
+                let line = Line::from(format!(
+                    "{:<18.18} {:>6.0} {:>6.0} us {:>5.1}%",
+                    load.name,
+                    s.average().as_secs_f32() * 1e6,
+                    s.longest.as_secs_f32() * 1e6,
+                    share * 100.0
+                ));
+                if share >= CPU_ALERT {
+                    line.style(Style::default().fg(Color::Red))
+                } else if share >= CPU_WARN {
+                    line.style(Style::default().fg(Color::Yellow))
+                } else {
+                    line
+                }
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), inner);
+    }
+
+    /// A needle over -50 to +50 cents, green once in tune.
+    fn draw_tuner(&self, frame: &mut Frame, area: Rect) {
+        let block =
+            Block::bordered().title(format!(" {} ", control::describe_tuner(self.controls)));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let width = (inner.width as usize).max(3);
+        let centre = width / 2;
+        let mut scale: Vec<char> = (0..width)
+            .map(|column| if column == centre { '|' } else { '-' })
+            .collect();
+        let style = match self.controls.tuner.reading() {
+            Some(reading) => {
+                let offset = (reading.cents / TUNER_RANGE_CENTS).clamp(-1.0, 1.0);
+                let column = centre as f32 + offset * (centre as f32 - 1.0).max(0.0);
+                scale[(column.round() as usize).min(width - 1)] = '█';
+                let cents = reading.cents.abs();
+                Style::default().fg(if cents <= IN_TUNE_CENTS {
+                    Color::Green
+                } else if cents <= 15.0 {
+                    Color::Yellow
+                } else {
+                    Color::Red
+                })
+            }
+            None => Style::default().fg(Color::DarkGray),
+        };
+        frame.render_widget(
+            Paragraph::new(scale.into_iter().collect::<String>()).style(style),
+            inner,
+        );
+    }
+
+    /// Left and right over the sweep, full scale top to bottom.
+    fn draw_scope(&self, frame: &mut Frame, area: Rect) {
+        let view = &self.controls.scope;
+        let (left, right) = view.trace();
+        let time_ms = view.time_ms() as f64;
+        let points = |side: &[f32]| -> Vec<(f64, f64)> {
+            let step = time_ms / side.len().max(1) as f64;
+            side.iter()
+                .enumerate()
+                .map(|(index, &value)| (index as f64 * step, value as f64))
+                .collect()
+        };
+        let (left, right) = (points(&left), points(&right));
+        let datasets = [(&right, Color::Magenta), (&left, Color::Cyan)]
+            .into_iter()
+            .map(|(data, color)| {
+                Dataset::default()
+                    .marker(Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(color))
+                    .data(data)
+            })
+            .collect();
+        let chart = Chart::new(datasets)
+            .block(Block::bordered().title(format!(" {} ", control::describe_scope(self.controls))))
+            .x_axis(Axis::default().bounds([0.0, time_ms]))
+            .y_axis(Axis::default().bounds([-1.0, 1.0]));
+        frame.render_widget(chart, area);
+    }
+
+    /// Side across, mid up, scaled to the loudest point so a quiet mix
+    /// keeps its shape, with the correlation on top: red once the two
+    /// sides work against each other.
+    fn draw_goniometer(&self, frame: &mut Frame, area: Rect) {
+        let phase = &self.controls.phase;
+        let points = phase.points();
+        let loudest = points
+            .iter()
+            .map(|&(side, mid)| side.abs().max(mid.abs()))
+            .fold(0.0, f32::max);
+        let scale = if loudest > 0.0 { 1.0 / loudest } else { 0.0 };
+        let data: Vec<(f64, f64)> = points
+            .iter()
+            .map(|&(side, mid)| ((side * scale) as f64, (mid * scale) as f64))
+            .collect();
+        let color = |correlation: f32| {
+            if correlation < 0.0 {
+                Color::Red
+            } else if correlation < 0.3 {
+                Color::Yellow
+            } else {
+                Color::Green
+            }
+        };
+        let block = Block::bordered()
+            .title(
+                Line::from(format!(" Phase {:+.2} ", phase.correlation()))
+                    .style(Style::default().fg(color(phase.correlation()))),
+            )
+            .title_bottom(
+                Line::from(format!(" lowest {:+.2} ", phase.lowest()))
+                    .style(Style::default().fg(color(phase.lowest()))),
+            );
+        let chart = Chart::new(vec![
+            Dataset::default()
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Scatter)
+                .style(Style::default().fg(Color::Cyan))
+                .data(&data),
+        ])
+        .block(block)
+        .x_axis(Axis::default().bounds([-1.0, 1.0]))
+        .y_axis(Axis::default().bounds([-1.0, 1.0]));
+        frame.render_widget(chart, area);
+    }
+
+    fn draw_spectrum(&self, frame: &mut Frame, area: Rect) {
+        let view = &self.controls.spectrum;
+        let title = match view.peaks(1).first() {
+            Some((frequency, level_db)) => {
+                format!(" Spectrum, peak {:.0} Hz {:+.1} dB ", frequency, level_db)
+            }
+            None => " Spectrum ".to_string(),
+        };
+        let mut block = Block::bordered().title(title);
+        // Feedback notches along the bottom edge
+        let feedback = &self.controls.feedback;
+        if feedback.is_attached() {
+            let notches: Vec<String> = feedback
+                .notches()
+                .iter()
+                .map(|(frequency, depth_db)| format!("{:.0} Hz {:+.0} dB", frequency, depth_db))
+                .collect();
+            block = block.title_bottom(if notches.is_empty() {
+                " No feedback notches ".to_string()
+            } else {
+                format!(" Notches: {} ", notches.join(", "))
+            });
+        }
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        // One log-spaced band per column, scaled to 0..100
+        let data: Vec<u64> = view
+            .bands(inner.width.max(1) as usize, SPECTRUM_MIN_HZ)
+            .iter()
+            .map(|&db| {
+                let ratio = ((db - SPECTRUM_FLOOR_DB) / -SPECTRUM_FLOOR_DB).clamp(0.0, 1.0);
+                (ratio * 100.0) as u64
+            })
+            .collect();
+        frame.render_widget(
+            Sparkline::default()
+                .data(&data)
+                .max(100)
+                .style(Style::default().fg(Color::Magenta)),
+            inner,
+        );
+    }
+
+    /// Magnitude, phase and coherence, one log-spaced band per column.
+    /// Bands the reference barely accounts for are blanked on the first
+    /// two.
+    fn draw_transfer(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered()
+            .title(format!(" {} ", control::describe_transfer(self.controls)))
+            .title_bottom(format!(
+                " Magnitude ±{:.0} dB, phase ±180 deg, coherence ",
+                TRANSFER_RANGE_DB
+            ));
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        let [magnitude, phase, coherence] = Layout::vertical([
+            Constraint::Fill(3),
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+        ])
+        .areas(inner);
+
+        let bands = self
+            .controls
+            .transfer
+            .bands(inner.width.max(1) as usize, SPECTRUM_MIN_HZ);
+        let trace = |value: &dyn Fn(&transfer::Reading) -> f32, blank: bool| -> Vec<u64> {
+            bands
+                .iter()
+                .map(|reading| match reading {
+                    Some(r) if !blank || r.coherence >= transfer::MIN_COHERENCE => {
+                        (value(r).clamp(0.0, 1.0) * 100.0) as u64
+                    }
+                    _ => 0,
+                })
+                .collect()
+        };
+        let magnitude_data = trace(
+            &|r| (r.magnitude_db + TRANSFER_RANGE_DB) / (2.0 * TRANSFER_RANGE_DB),
+            true,
+        );
+        let phase_data = trace(&|r| (r.phase + 180.0) / 360.0, true);
+        let coherence_data = trace(&|r| r.coherence, false);
+        for (data, area, color) in [
+            (&magnitude_data, magnitude, Color::Cyan),
+            (&phase_data, phase, Color::Yellow),
+            (&coherence_data, coherence, Color::Green),
+        ] {
+            frame.render_widget(
+                Sparkline::default()
+                    .data(data)
+                    .max(100)
+                    .style(Style::default().fg(color)),
+                area,
+            );
+        }
+    }
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'_> {
+    let style = if focused {
+        Style::default().fg(Color::Cyan)
+    } else {
+        Style::default()
+    };
+    Block::bordered().title(title).border_style(style)
+}
+
+/// A meter bar followed by the peak reading, coloured by the peak hold.
+fn level_line(level: Level, width: usize) -> Line<'static> {
+    let color = if level.hold_db > -3.0 {
+        Color::Red
+    } else if level.hold_db > -18.0 {
+        Color::Yellow
+    } else {
+        Color::Green
+    };
+    Line::from(format!(
+        "{}{:+6.1}",
+        meter::bar(level, METER_FLOOR_DB, width),
+        level.peak_db.max(METER_FLOOR_DB)
+    ))
+    .style(Style::default().fg(color))
+}
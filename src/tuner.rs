@@ -0,0 +1,266 @@
+use crate::params::AtomicF32;
+use anyhow::Result;
+use ringbuf::traits::{Consumer, Observer, Producer, Split};
+use ringbuf::{HeapCons, HeapProd, HeapRb};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Range of the tuner, a five-string bass's low B to past the top of a
+/// guitar's neck.
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 1500.0;
+/// Dip of YIN's normalised difference a period has to reach to count.
+const YIN_THRESHOLD: f32 = 0.15;
+/// Mean square below which nothing is read, -60 dBFS.
+const SILENCE: f32 = 1e-6;
+/// Time between readings.
+const HOP_MS: f32 = 50.0;
+/// Share of a new reading taken into the shown one while the note holds,
+/// so the needle settles instead of jittering.
+const SMOOTHING: f32 = 0.5;
+/// A jump of more than this many cents starts the needle afresh.
+const SMOOTHING_CENTS: f32 = 50.0;
+pub const MIN_REFERENCE: f32 = 400.0;
+pub const MAX_REFERENCE: f32 = 480.0;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// A pitch read by the tuner, against the nearest equal-tempered note.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    pub frequency: f32,
+    /// MIDI note number, 69 is A4.
+    pub note: i32,
+    /// Off the note, -50 to +50.
+    pub cents: f32,
+}
+
+impl Reading {
+    /// Note name with its octave, e.g. E2.
+    pub fn name(&self) -> String {
+        format!(
+            "{}{}",
+            NOTE_NAMES[self.note.rem_euclid(12) as usize],
+            self.note.div_euclid(12) - 1
+        )
+    }
+}
+
+/// The tuner's channel and latest reading, readable from any thread.
+#[derive(Debug)]
+pub struct TunerView {
+    /// Mixer channel from 1, 0 while the tuner is off.
+    channel: AtomicUsize,
+    /// Frequency of A4, in Hz.
+    reference: f32,
+    /// Smoothed pitch, 0 while nothing is heard.
+    frequency: AtomicF32,
+}
+
+impl TunerView {
+    /// Mixer channel listened to, counted from 0.
+    pub fn channel(&self) -> Option<usize> {
+        self.channel.load(Ordering::Relaxed).checked_sub(1)
+    }
+
+    /// Listens to mixer `channel`, counted from 0, or turns the tuner off.
+    pub fn set_channel(&self, channel: Option<usize>) {
+        self.frequency.set(0.0);
+        self.channel
+            .store(channel.map_or(0, |channel| channel + 1), Ordering::Relaxed);
+    }
+
+    pub fn reference(&self) -> f32 {
+        self.reference
+    }
+
+    /// The pitch on the channel, `None` while the tuner is off or hears
+    /// nothing it can read.
+    pub fn reading(&self) -> Option<Reading> {
+        let frequency = self.frequency.get();
+        if self.channel().is_none() || frequency <= 0.0 {
+            return None;
+        }
+        let exact = 69.0 + 12.0 * (frequency / self.reference()).log2();
+        let note = exact.round();
+        Some(Reading {
+            frequency,
+            note: note as i32,
+            cents: (exact - note) * 100.0,
+        })
+    }
+}
+
+/// Audio-thread side: sums the tuned channel to mono and hands it to the
+/// tuner thread. Samples are dropped when the tuner falls behind.
+pub struct TunerTap {
+    producer: HeapProd<f32>,
+    view: Arc<TunerView>,
+}
+
+impl TunerTap {
+    /// Mixer channel to pass to `process`, counted from 0.
+    pub fn channel(&self) -> Option<usize> {
+        self.view.channel()
+    }
+
+    pub fn process(&mut self, buffer: &[f32], channels: usize) {
+        for frame in buffer.chunks(channels) {
+            let mono = frame.iter().sum::<f32>() / channels as f32;
+            if self.producer.try_push(mono).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Starts the tuner thread, off until a channel is set, and returns the
+/// tap for the mixer plus the shared view of the reading.
+pub fn spawn(sample_rate: f32, reference: f32) -> Result<(TunerTap, Arc<TunerView>)> {
+    let detector = Detector::new(sample_rate);
+    let (producer, consumer) = HeapRb::<f32>::new(detector.frame.len() * 2).split();
+    let view = Arc::new(TunerView {
+        channel: AtomicUsize::new(0),
+        reference,
+        frequency: AtomicF32::new(0.0),
+    });
+
+    let shared = view.clone();
+    thread::Builder::new()
+        .name("tuner".to_string())
+        .spawn(move || detector.run(consumer, &shared))?;
+
+    let tap = TunerTap {
+        producer,
+        view: view.clone(),
+    };
+    Ok((tap, view))
+}
+
+/// YIN pitch detector over the newest `2 * max_period` samples.
+struct Detector {
+    sample_rate: f32,
+    min_period: usize,
+    max_period: usize,
+    hop: usize,
+    frame: Vec<f32>,
+    /// Squared difference of the frame against itself at each lag, and
+    /// the same normalised by its running mean.
+    raw: Vec<f32>,
+    difference: Vec<f32>,
+}
+
+impl Detector {
+    fn new(sample_rate: f32) -> Self {
+        let max_period = (sample_rate / MIN_FREQUENCY).ceil() as usize;
+        Detector {
+            sample_rate,
+            min_period: ((sample_rate / MAX_FREQUENCY) as usize).max(2),
+            max_period,
+            hop: (HOP_MS * 0.001 * sample_rate) as usize,
+            frame: vec![0.0; 2 * max_period + 1],
+            raw: vec![0.0; max_period + 2],
+            difference: vec![1.0; max_period + 2],
+        }
+    }
+
+    /// Reads the pitch once per hop of new input until the tap is dropped.
+    fn run(mut self, mut consumer: HeapCons<f32>, view: &TunerView) {
+        let hop = self.hop.min(self.frame.len());
+        loop {
+            if consumer.occupied_len() < hop {
+                if !consumer.write_is_held() {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            self.frame.copy_within(hop.., 0);
+            let size = self.frame.len();
+            consumer.pop_slice(&mut self.frame[size - hop..]);
+            if view.channel().is_none() {
+                continue;
+            }
+
+            let frequency = match self.period() {
+                Some(period) => {
+                    let frequency = self.sample_rate / period;
+                    let shown = view.frequency.get();
+                    let jump = 1200.0 * (frequency / shown).log2().abs();
+                    if shown > 0.0 && jump < SMOOTHING_CENTS {
+                        shown * (frequency / shown).powf(SMOOTHING)
+                    } else {
+                        frequency
+                    }
+                }
+                None => 0.0,
+            };
+            view.frequency.set(frequency);
+        }
+    }
+
+    /// Period of the frame in samples, from the first dip of the
+    /// cumulative mean normalised difference under the threshold.
+    fn period(&mut self) -> Option<f32> {
+        let (min, max) = (self.min_period, self.max_period);
+        let window = &self.frame[..max];
+        let power = window.iter().map(|x| x * x).sum::<f32>() / max as f32;
+        if power < SILENCE {
+            return None;
+        }
+
+        let mut running = 0.0;
+        for tau in 1..=max {
+            let lagged = &self.frame[tau..tau + max];
+            let d: f32 = window
+                .iter()
+                .zip(lagged)
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum();
+            running += d;
+            self.raw[tau] = d;
+            self.difference[tau] = if running > 0.0 {
+                d * tau as f32 / running
+            } else {
+                1.0
+            };
+        }
+
+        // First dip under the threshold, followed down to its bottom
+        let d = &self.difference;
+        let mut tau = (min..=max).find(|&tau| d[tau] < YIN_THRESHOLD)?;
+        while tau < max && d[tau + 1] < d[tau] {
+            tau += 1;
+        }
+        let period = interpolate(d, tau);
+
+        // The dip at the longest multiple of the period in the window
+        // pins it down that many times finer
+        let multiple = ((max - 1) as f32 / period).floor().max(1.0);
+        let guess = (period * multiple).round() as usize;
+        // Within a third of a period, clear of the dips either side
+        let around = ((period / 3.0) as usize).max(1);
+        let lowest = (guess.saturating_sub(around).max(1)..=(guess + around).min(max))
+            .min_by(|&a, &b| self.raw[a].total_cmp(&self.raw[b]))?;
+        Some(interpolate(&self.raw, lowest) / multiple)
+    }
+}
+
+/// Lag of the bottom of the dip at `tau`, between samples from a
+/// parabola through it and its neighbours.
+fn interpolate(d: &[f32], tau: usize) -> f32 {
+    let mut lag = tau as f32;
+    if tau > 0 && tau + 1 < d.len() {
+        let (a, b, c) = (d[tau - 1], d[tau], d[tau + 1]);
+        let curve = a - 2.0 * b + c;
+        if curve > 1e-9 {
+            lag += 0.5 * (a - c) / curve;
+        }
+    }
+    lag
+}
@@ -0,0 +1,175 @@
+use crate::supervisor::{self, Direction};
+use anyhow::Result;
+use cpal::{Device, Host};
+use tracing::{info, warn};
+
+/// Sink the processed output plays into. Its monitor becomes the
+/// microphone.
+#[cfg(target_os = "linux")]
+const SINK: &str = "live_dsp_output";
+#[cfg(target_os = "linux")]
+const SOURCE: &str = "live_dsp_mic";
+
+/// A microphone other programs can pick, carrying the processed output:
+/// select it in Discord, OBS or a browser instead of the real mic.
+///
+/// On Linux it's created on the sound server, PulseAudio or PipeWire's
+/// Pulse server, through `pactl`: a null sink live_dsp plays into and a
+/// source remapped from its monitor, so it lists as a microphone rather
+/// than a monitor that some programs hide. Both go away again when
+/// live_dsp exits. Windows and macOS can't create devices from a program,
+/// there the output goes to VB-Audio Virtual Cable or BlackHole, which
+/// have to be installed once.
+pub struct VirtualMic {
+    /// Output device that feeds the microphone, matched by name.
+    outputs: &'static [&'static str],
+    /// `pactl` modules to unload on exit, last loaded first.
+    #[cfg(target_os = "linux")]
+    modules: Vec<u32>,
+}
+
+#[cfg(target_os = "linux")]
+impl VirtualMic {
+    /// Creates the sink and source and points the Pulse and PipeWire ALSA
+    /// plugins at the sink. Call before any threads are started.
+    pub fn create() -> Result<VirtualMic> {
+        // Left behind by a run that didn't get to clean up
+        unload(&stale_modules()?);
+
+        let mut mic = VirtualMic {
+            outputs: &["pipewire", "pulse", "default"],
+            modules: Vec::new(),
+        };
+        mic.modules.push(pactl::load(&[
+            "module-null-sink",
+            &format!("sink_name={}", SINK),
+            "sink_properties=device.description=\"live_dsp Output\"",
+        ])?);
+        mic.modules.push(pactl::load(&[
+            "module-remap-source",
+            &format!("master={}.monitor", SINK),
+            &format!("source_name={}", SOURCE),
+            "source_properties=device.description=\"live_dsp Microphone\"",
+        ])?);
+
+        // SAFETY: called while the process is still single threaded, the
+        // plugins read these when the output is opened
+        unsafe {
+            std::env::set_var("PULSE_SINK", SINK);
+            std::env::set_var("PIPEWIRE_NODE", SINK);
+        }
+        info!("Virtual microphone created, select \"live_dsp Microphone\" as the input");
+        Ok(mic)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for VirtualMic {
+    fn drop(&mut self) {
+        unload(&self.modules);
+        if !self.modules.is_empty() {
+            info!("Virtual microphone removed");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn unload(modules: &[u32]) {
+    for &module in modules.iter().rev() {
+        if let Err(err) = pactl::run(&["unload-module", &module.to_string()]) {
+            warn!(
+                "Failed to remove virtual device module {}: {:?}",
+                module, err
+            );
+        }
+    }
+}
+
+/// Modules a previous run loaded, found by the names it gave them.
+#[cfg(target_os = "linux")]
+fn stale_modules() -> Result<Vec<u32>> {
+    let list = pactl::run(&["list", "short", "modules"])?;
+    let ours = [
+        format!("sink_name={}", SINK),
+        format!("source_name={}", SOURCE),
+    ];
+    Ok(list
+        .lines()
+        .filter(|line| ours.iter().any(|name| line.contains(name.as_str())))
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect())
+}
+
+#[cfg(target_os = "linux")]
+mod pactl {
+    use anyhow::{Context, Result, bail};
+    use std::process::Command;
+
+    /// Runs `pactl` and returns what it printed.
+    pub fn run(args: &[&str]) -> Result<String> {
+        let output = Command::new("pactl")
+            .args(args)
+            .output()
+            .context("Failed to run pactl, install pulseaudio-utils")?;
+        if !output.status.success() {
+            bail!(
+                "pactl {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Loads a module, returns its index for unloading.
+    pub fn load(args: &[&str]) -> Result<u32> {
+        let mut command = vec!["load-module"];
+        command.extend_from_slice(args);
+        let index = run(&command)?;
+        index
+            .trim()
+            .parse()
+            .with_context(|| format!("pactl gave no module index: '{}'", index.trim()))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl VirtualMic {
+    /// Nothing to create, the cable driver provides both ends.
+    pub fn create() -> Result<VirtualMic> {
+        let (outputs, microphone): (&'static [&'static str], _) = if cfg!(windows) {
+            (&["CABLE Input"], "CABLE Output")
+        } else {
+            (&["BlackHole"], "BlackHole")
+        };
+        info!(
+            "Playing into the virtual cable, select \"{}\" as the input",
+            microphone
+        );
+        Ok(VirtualMic { outputs })
+    }
+}
+
+impl VirtualMic {
+    /// The output device feeding the microphone, in place of one picked by
+    /// hand.
+    pub fn output_device(&self, host: &Host) -> Result<Device> {
+        for name in self.outputs {
+            if let Ok(device) = supervisor::find_device(host, Direction::Output, name) {
+                return Ok(device);
+            }
+        }
+        if cfg!(target_os = "linux") {
+            anyhow::bail!("No PipeWire or Pulse ALSA device to play into the virtual microphone")
+        }
+        anyhow::bail!(
+            "No {} device, install {}",
+            self.outputs[0],
+            if cfg!(windows) {
+                "VB-Audio Virtual Cable from vb-audio.com"
+            } else {
+                "BlackHole from existential.audio"
+            }
+        )
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{Result, bail};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Appended to the client's key to prove the server speaks WebSocket.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest frame taken from a client, they only send pings and closes.
+const MAX_INCOMING: usize = 4096;
+/// Time a client gets to take a frame.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Server side of a WebSocket connection, after the HTTP upgrade.
+pub struct WebSocket {
+    stream: TcpStream,
+    /// Client bytes not yet making up a whole frame.
+    pending: Vec<u8>,
+}
+
+impl WebSocket {
+    /// Answers the upgrade request carrying `key` and takes over `stream`.
+    pub fn accept(mut stream: TcpStream, key: &str) -> Result<WebSocket> {
+        let accept = base64(&sha1(
+            format!("{}{}", key.trim(), HANDSHAKE_GUID).as_bytes(),
+        ));
+        write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        )?;
+        stream.flush()?;
+        // Polled between messages, so reading never holds up sending
+        stream.set_nonblocking(true)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(WebSocket {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<()> {
+        self.send(OP_TEXT, text.as_bytes())
+    }
+
+    /// Handles whatever the client sent since the last call: answers pings
+    /// and closes. `false` once the connection is closed.
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut buffer = [0u8; 1024];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(len) => self.pending.extend_from_slice(&buffer[..len]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err.into()),
+            }
+            if self.pending.len() > MAX_INCOMING {
+                bail!("WebSocket frame over {} bytes", MAX_INCOMING);
+            }
+        }
+        while let Some((opcode, payload, len)) = decode(&self.pending)? {
+            self.pending.drain(..len);
+            match opcode {
+                OP_CLOSE => {
+                    let _ = self.send(OP_CLOSE, &payload);
+                    return Ok(false);
+                }
+                OP_PING => self.send(OP_PONG, &payload)?,
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        // Servers send whole, unmasked frames
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        // Written blocking, a client stalled past the timeout is dropped
+        self.stream.set_nonblocking(false)?;
+        let result = self.stream.write_all(&frame);
+        self.stream.set_nonblocking(true)?;
+        Ok(result?)
+    }
+}
+
+/// Opcode, unmasked payload and length of the first whole frame in
+/// `data`, `None` until one has arrived.
+fn decode(data: &[u8]) -> Result<Option<(u8, Vec<u8>, usize)>> {
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let opcode = data[0] & 0x0F;
+    if data[1] & 0x80 == 0 {
+        bail!("Unmasked WebSocket frame from the client");
+    }
+    let (len, mut at) = match data[1] & 0x7F {
+        126 if data.len() >= 4 => (u16::from_be_bytes([data[2], data[3]]) as usize, 4),
+        127 if data.len() >= 10 => (u64::from_be_bytes(data[2..10].try_into()?) as usize, 10),
+        126 | 127 => return Ok(None),
+        len => (len as usize, 2),
+    };
+    if len > MAX_INCOMING {
+        bail!("WebSocket frame over {} bytes", MAX_INCOMING);
+    }
+    if data.len() < at + 4 + len {
+        return Ok(None);
+    }
+    let mask = [data[at], data[at + 1], data[at + 2], data[at + 3]];
+    at += 4;
+    let payload = data[at..at + len]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+    Ok(Some((opcode, payload, at + len)))
+}
+
+/// SHA-1, which the handshake needs and nothing else.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (chunk, word) in digest.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
@@ -0,0 +1,219 @@
+use cpal::StreamError;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Overrun and underrun counters of one stream. The audio callbacks add to
+/// them once per callback at most, the control side reads snapshots.
+#[derive(Debug)]
+pub struct XrunStats {
+    pub name: String,
+    /// Rate the gap lengths are counted at.
+    pub sample_rate: u32,
+    overruns: AtomicU64,
+    overrun_samples: AtomicU64,
+    underruns: AtomicU64,
+    underrun_samples: AtomicU64,
+    longest_gap: AtomicU64,
+    errors: AtomicU64,
+    /// Smallest and largest callback seen, in frames.
+    min_callback: AtomicU64,
+    max_callback: AtomicU64,
+    callbacks: AtomicU64,
+    /// Set when the backend reports the device gone, cleared by the
+    /// supervisor once it has noticed.
+    device_lost: AtomicBool,
+}
+
+/// Totals at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XrunSnapshot {
+    /// Callbacks that found the ring full and dropped samples.
+    pub overruns: u64,
+    pub overrun_samples: u64,
+    /// Callbacks that found the ring empty and played silence.
+    pub underruns: u64,
+    pub underrun_samples: u64,
+    /// Longest run of consecutive missing frames.
+    pub longest_gap: u64,
+    /// Errors reported by the audio backend, which include device xruns on
+    /// some hosts.
+    pub errors: u64,
+}
+
+impl XrunSnapshot {
+    pub fn events(&self) -> u64 {
+        self.overruns + self.underruns + self.errors
+    }
+}
+
+impl XrunStats {
+    pub fn new(name: String, sample_rate: u32) -> Self {
+        XrunStats {
+            name,
+            sample_rate,
+            overruns: AtomicU64::new(0),
+            overrun_samples: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            underrun_samples: AtomicU64::new(0),
+            longest_gap: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            min_callback: AtomicU64::new(u64::MAX),
+            max_callback: AtomicU64::new(0),
+            callbacks: AtomicU64::new(0),
+            device_lost: AtomicBool::new(false),
+        }
+    }
+
+    /// Records one callback that dropped `samples` because the ring was full.
+    pub fn record_overrun(&self, samples: u64) {
+        if samples > 0 {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+            self.overrun_samples.fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+
+    /// Records one callback that was short of `samples`.
+    pub fn record_underrun(&self, samples: u64) {
+        if samples > 0 {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+            self.underrun_samples.fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a finished run of `frames` consecutive missing frames.
+    pub fn record_gap(&self, frames: u64) {
+        self.longest_gap.fetch_max(frames, Ordering::Relaxed);
+    }
+
+    /// Records the size of one callback in frames.
+    pub fn record_callback(&self, frames: u64) {
+        self.min_callback.fetch_min(frames, Ordering::Relaxed);
+        self.max_callback.fetch_max(frames, Ordering::Relaxed);
+        self.callbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Callbacks so far. A stream whose count stops moving has stalled.
+    pub fn callbacks(&self) -> u64 {
+        self.callbacks.load(Ordering::Relaxed)
+    }
+
+    /// Smallest and largest callback so far, `None` before the first one.
+    pub fn callback_range(&self) -> Option<(u64, u64)> {
+        let max = self.max_callback.load(Ordering::Relaxed);
+        (max > 0).then(|| (self.min_callback.load(Ordering::Relaxed), max))
+    }
+
+    /// Compares the callback sizes seen with the `requested` size.
+    pub fn describe_callbacks(&self, requested: u32) -> String {
+        let requested = requested as u64;
+        match self.callback_range() {
+            None => format!("{}: no callbacks yet", self.name),
+            Some((min, max)) if min == requested && max == requested => {
+                format!("{}: callbacks of {} frames", self.name, requested)
+            }
+            Some((min, max)) if min == max => format!(
+                "{}: callbacks of {} frames instead of {}",
+                self.name, min, requested
+            ),
+            Some((min, max)) => format!(
+                "{}: callbacks of {} to {} frames instead of {}",
+                self.name, min, max, requested
+            ),
+        }
+    }
+
+    pub fn record_error(&self, err: &StreamError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        if matches!(err, StreamError::DeviceNotAvailable) {
+            self.device_lost.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Records an xrun the backend noticed itself, as JACK reports them.
+    #[cfg(feature = "jack")]
+    pub fn record_xrun(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the device went away since the last call.
+    pub fn take_device_lost(&self) -> bool {
+        self.device_lost.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn snapshot(&self) -> XrunSnapshot {
+        XrunSnapshot {
+            overruns: self.overruns.load(Ordering::Relaxed),
+            overrun_samples: self.overrun_samples.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            underrun_samples: self.underrun_samples.load(Ordering::Relaxed),
+            longest_gap: self.longest_gap.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let s = self.snapshot();
+        format!(
+            "{}: {} overruns ({} samples), {} underruns ({} samples), longest gap {} frames ({:.1} ms), {} stream errors",
+            self.name,
+            s.overruns,
+            s.overrun_samples,
+            s.underruns,
+            s.underrun_samples,
+            s.longest_gap,
+            s.longest_gap as f32 * 1000.0 / self.sample_rate.max(1) as f32,
+            s.errors
+        )
+    }
+}
+
+/// Background thread printing the stats of every stream that had new
+/// xruns since the previous report.
+pub struct Reporter {
+    running: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Reporter {
+    pub fn spawn(stats: Vec<Arc<XrunStats>>, interval: Duration) -> std::io::Result<Reporter> {
+        let running = Arc::new(AtomicBool::new(true));
+        let flag = running.clone();
+        let thread = thread::Builder::new()
+            .name("xrun report".to_string())
+            .spawn(move || {
+                let mut reported: Vec<XrunSnapshot> = stats.iter().map(|s| s.snapshot()).collect();
+                let mut last = Instant::now();
+                while flag.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(100));
+                    if last.elapsed() < interval {
+                        continue;
+                    }
+                    last = Instant::now();
+
+                    for (stats, reported) in stats.iter().zip(reported.iter_mut()) {
+                        let snapshot = stats.snapshot();
+                        if snapshot != *reported {
+                            warn!(
+                                stream = %stats.name,
+                                overruns = snapshot.overruns,
+                                underruns = snapshot.underruns,
+                                errors = snapshot.errors,
+                                "xrun {}",
+                                stats.describe()
+                            );
+                            *reported = snapshot;
+                        }
+                    }
+                }
+            })?;
+        Ok(Reporter { running, thread })
+    }
+
+    pub fn stop(self) {
+        self.running.store(false, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}